@@ -1,10 +1,38 @@
 use anyhow::Result;
 use async_trait::async_trait;
+use serde_json::Value;
+use crate::agents::events::AgentEventSink;
+use crate::clients::TokenUsage;
 use crate::orchestrator::message::{ChatMessage, Message};
+use crate::orchestrator::types::{SessionFiles, UserMailbox};
 
 #[async_trait]
 pub trait Agent: Send + Sync {
     fn name(&self) -> &str;
 
+    /// orchestrator 在分发每个步骤前设置事件出口，不产生中间事件的代理可以忽略
+    fn set_event_sink(&mut self, _sink: Option<AgentEventSink>) {}
+
+    /// 步骤执行期间读取新用户消息的句柄，多轮工具调用的代理在每轮之间轮询
+    fn set_mailbox(&mut self, _mailbox: Option<UserMailbox>) {}
+
+    /// 本次运行可以使用的会话文件，CLI 的运行为 None；不上传文件的代理可以忽略
+    fn set_session_files(&mut self, _files: Option<SessionFiles>) {}
+
+    /// 上次读取之后累计的模型用量，orchestrator 在每个步骤结束后读取并计入统计
+    fn take_usage(&mut self) -> TokenUsage {
+        TokenUsage::default()
+    }
+
+    /// 写检查点时保存的状态（如浏览器的 cookies 和打开的页面），没有需要保存的状态时返回 None
+    async fn save_state(&mut self) -> Result<Option<Value>> {
+        Ok(None)
+    }
+
+    /// 从检查点恢复运行时，在执行剩余步骤之前交还 save_state 保存的状态
+    async fn restore_state(&mut self, _state: Value) -> Result<()> {
+        Ok(())
+    }
+
     async fn on_message_stream(&mut self, message: Message) -> Result<ChatMessage>;
 }
\ No newline at end of file
@@ -0,0 +1,313 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::Local;
+use tempfile::TempDir;
+
+use crate::agents::coder_agent::config::CoderAgentConfig;
+use crate::agents::coder_agent::prompt::coder_system_message;
+use crate::agents::coder_agent::sandbox::{execute_code, extract_code_blocks, CodeBlock};
+use crate::agents::{Agent, AgentEvent, AgentEventSink};
+use crate::clients::{ChatCompletionClient, TokenUsage};
+use crate::orchestrator::message::{
+    chat_message_to_llm_message, AssistantContent, AssistantMessage, ChatMessage, LLMMessage, Message, MessageRole,
+    SystemMessage, UserContent, UserMessage,
+};
+use crate::tools::approval_guard::ActionGuard;
+
+/* 通过写代码并执行来完成步骤的代理：让模型根据指令写出 python 或 sh 代码块，
+在本次运行的工作目录中依次执行，把退出码和输出交回模型，模型据此修正代码或给出总结，
+最多执行 max_iterations 轮。第一次执行前经 ActionGuard 审批，没有设置 guard 时直接执行 */
+pub struct CoderAgent {
+    config: CoderAgentConfig,
+    model_client: Arc<dyn ChatCompletionClient>,
+    action_guard: Option<Arc<dyn ActionGuard>>,
+    event_sink: Option<AgentEventSink>,
+    // 第一次执行代码时创建，代理销毁时删除
+    work_dir: Option<TempDir>,
+    approved: bool,
+    executions: usize,
+    usage: TokenUsage,
+}
+
+impl CoderAgent {
+    pub fn new(config: CoderAgentConfig, model_client: Arc<dyn ChatCompletionClient>) -> Self {
+        Self {
+            config,
+            model_client,
+            action_guard: None,
+            event_sink: None,
+            work_dir: None,
+            approved: false,
+            executions: 0,
+            usage: TokenUsage::default(),
+        }
+    }
+
+    pub fn set_action_guard(&mut self, guard: Arc<dyn ActionGuard>) {
+        self.action_guard = Some(guard);
+    }
+
+    pub fn description(&self) -> &str {
+        &self.config.description
+    }
+
+    /// 本次运行的工作目录，还没有执行过代码时为 None
+    pub fn work_dir(&self) -> Option<&Path> {
+        self.work_dir.as_ref().map(|dir| dir.path())
+    }
+
+    fn ensure_work_dir(&mut self) -> Result<PathBuf> {
+        if self.work_dir.is_none() {
+            let mut builder = tempfile::Builder::new();
+            builder.prefix("coder_agent_");
+            let dir = match &self.config.work_dir {
+                Some(parent) => {
+                    std::fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent))?;
+                    builder.tempdir_in(parent)
+                }
+                None => builder.tempdir(),
+            }
+            .context("Failed to create the coder_agent working directory")?;
+            self.work_dir = Some(dir);
+        }
+        Ok(self.work_dir.as_ref().map(|dir| dir.path().to_path_buf()).unwrap_or_default())
+    }
+
+    // 第一次执行前请用户确认，批准一次之后本次运行不再询问
+    async fn approve(&mut self, blocks: &[CodeBlock]) -> bool {
+        if self.approved || !self.config.require_approval {
+            return true;
+        }
+        let guard = match &self.action_guard {
+            Some(guard) => guard.clone(),
+            None => return true,
+        };
+        let code = blocks
+            .iter()
+            .map(|block| format!("```{}\n{}\n```", block.language.name(), block.code))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let network = if self.config.allow_network { "with" } else { "without" };
+        let request = ChatMessage::new_text(
+            MessageRole::User,
+            self.config.name.clone(),
+            format!("{} wants to execute the following code {} network access:\n\n{}\n\nDo you approve?", self.config.name, network, code),
+        );
+        self.approved = guard.get_approval(request).await;
+        self.approved
+    }
+
+    fn emit(&self, event: AgentEvent) {
+        if let Some(sink) = &self.event_sink {
+            sink.emit(event);
+        }
+    }
+
+    fn reply(&self, content: String) -> ChatMessage {
+        ChatMessage::new_text(MessageRole::Assistant, self.config.name.clone(), content)
+    }
+}
+
+#[async_trait]
+impl Agent for CoderAgent {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn set_event_sink(&mut self, sink: Option<AgentEventSink>) {
+        self.event_sink = sink;
+    }
+
+    fn take_usage(&mut self) -> TokenUsage {
+        std::mem::take(&mut self.usage)
+    }
+
+    async fn on_message_stream(&mut self, message: Message) -> Result<ChatMessage> {
+        let date_today = Local::now().format("%Y-%m-%d").to_string();
+        let mut context = vec![LLMMessage::System(SystemMessage::new(coder_system_message(&date_today, self.config.allow_network)))];
+        for chat_message in &message.chat_history {
+            context.push(chat_message_to_llm_message(chat_message)?);
+        }
+
+        let mut rounds = 0;
+        let mut last_output: Option<(String, bool)> = None;
+        loop {
+            let result = self.model_client.create(&context).await?;
+            self.usage.add(&result.usage);
+            let reply = result.content;
+            let blocks = extract_code_blocks(&reply);
+            // 没有代码块时模型给出的是总结，附上最后一次执行的输出供 orchestrator 判断
+            if blocks.is_empty() {
+                let content = match &last_output {
+                    Some((output, _)) => format!("{}\n\nLast execution output:\n{}", reply.trim(), output),
+                    None => reply.trim().to_string(),
+                };
+                return Ok(self.reply(content));
+            }
+            if rounds >= self.config.max_iterations.max(1) {
+                break;
+            }
+            if !self.approve(&blocks).await {
+                return Ok(self.reply("The user did not approve executing the code, so nothing was run.".to_string()));
+            }
+
+            let dir = self.ensure_work_dir()?;
+            let mut outputs = Vec::new();
+            let mut success = true;
+            for block in &blocks {
+                self.executions += 1;
+                let action = format!("execute_{}", block.language.name());
+                self.emit(AgentEvent::ActionProposed { action: action.clone(), explanation: block.code.clone() });
+                let result = execute_code(block, &dir, &format!("code_{}", self.executions), &self.config).await?;
+                let output = result.describe(self.config.timeout_secs);
+                self.emit(AgentEvent::ActionResult { action, result: output.clone() });
+                outputs.push(output);
+                // 后面的代码块通常依赖前面的结果，失败后不再继续
+                if !result.success() {
+                    success = false;
+                    break;
+                }
+            }
+            rounds += 1;
+
+            let output = outputs.join("\n\n");
+            let next = if success {
+                "If the task is done, reply with a short summary of the results without any code block. Otherwise write the next code."
+            } else {
+                "The code failed. Fix it and output the complete corrected code."
+            };
+            context.push(LLMMessage::Assistant(AssistantMessage::new(AssistantContent::String(reply), Some(self.config.name.clone()))));
+            context.push(LLMMessage::User(UserMessage::new(
+                UserContent::String(format!("Execution results:\n{}\n\n{}", output, next)),
+                message.from.clone(),
+            )));
+            last_output = Some((output, success));
+        }
+
+        // 轮数用完模型仍在写代码：最后一次执行失败时标记为失败，交给 orchestrator 的重试策略
+        let (output, success) = last_output.unwrap_or_default();
+        let summary = self.reply(format!(
+            "Stopped after {} round(s) of code execution without a final summary. Last execution output:\n{}",
+            rounds, output
+        ));
+        Ok(if success { summary } else { summary.with_error("the code still fails after the last correction") })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use crate::testing::MockProvider;
+
+    #[derive(Debug, Default)]
+    struct RecordingGuard {
+        approve: bool,
+        requests: Mutex<Vec<ChatMessage>>,
+    }
+
+    #[async_trait]
+    impl ActionGuard for RecordingGuard {
+        async fn get_approval(&self, request: ChatMessage) -> bool {
+            self.requests.lock().unwrap().push(request);
+            self.approve
+        }
+    }
+
+    fn test_config() -> CoderAgentConfig {
+        // 测试环境不一定允许创建网络命名空间
+        CoderAgentConfig { allow_network: true, timeout_secs: 10, ..CoderAgentConfig::default() }
+    }
+
+    fn execute(instruction: &str) -> Message {
+        Message::execute(
+            "orchestrator",
+            "coder_agent",
+            "Say hello",
+            ChatMessage::new_text(MessageRole::User, "orchestrator".to_string(), instruction.to_string()),
+        )
+    }
+
+    fn text(message: &ChatMessage) -> &str {
+        match message {
+            ChatMessage::Text { content, .. } => content,
+            ChatMessage::MultiModal { .. } => "",
+        }
+    }
+
+    #[tokio::test]
+    async fn test_print_hello_step() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond("I will print it.\n```python\nprint('hello')\n```")
+            .respond("The script printed hello."));
+        let guard = Arc::new(RecordingGuard { approve: true, ..RecordingGuard::default() });
+        let mut agent = CoderAgent::new(test_config(), provider.clone());
+        agent.set_action_guard(guard.clone());
+
+        let response = agent.on_message_stream(execute("Print hello with Python")).await?;
+
+        assert_eq!(response.error(), None);
+        assert!(text(&response).starts_with("The script printed hello."));
+        assert!(text(&response).contains("exit code 0\nstdout:\nhello"));
+        assert_eq!(guard.requests.lock().unwrap().len(), 1);
+        assert!(agent.work_dir().unwrap().join("code_1.py").exists());
+
+        // 第二次请求模型时带上了执行结果
+        let requests = provider.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(serde_json::to_string(&requests[1])?.contains("Execution results:\\nexit code 0"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_code_is_corrected() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond("```python\nprint(undefined_name)\n```")
+            .respond("```python\nprint('fixed')\n```")
+            .respond("Fixed the NameError, the script printed fixed."));
+        let mut agent = CoderAgent::new(test_config(), provider.clone());
+
+        let response = agent.on_message_stream(execute("Print something")).await?;
+
+        assert!(text(&response).contains("stdout:\nfixed"));
+        let correction = serde_json::to_string(&provider.requests()[1])?;
+        assert!(correction.contains("NameError"));
+        assert!(correction.contains("The code failed. Fix it"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_iterations_are_capped() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond("```sh\nexit 1\n```")
+            .respond("```sh\nexit 2\n```")
+            .respond("```sh\nexit 3\n```"));
+        let config = CoderAgentConfig { max_iterations: 2, ..test_config() };
+        let mut agent = CoderAgent::new(config, provider.clone());
+
+        let response = agent.on_message_stream(execute("Exit")).await?;
+
+        assert!(text(&response).starts_with("Stopped after 2 round(s)"));
+        assert!(text(&response).contains("exit code 2"));
+        assert!(response.error().is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejected_code_is_not_executed() -> Result<()> {
+        let provider = Arc::new(MockProvider::new().respond("```sh\ntouch ran.txt\n```"));
+        let guard = Arc::new(RecordingGuard::default());
+        let mut agent = CoderAgent::new(test_config(), provider);
+        agent.set_action_guard(guard.clone());
+
+        let response = agent.on_message_stream(execute("Create a file")).await?;
+
+        assert!(text(&response).contains("did not approve"));
+        assert!(text(guard.requests.lock().unwrap().first().unwrap()).contains("touch ran.txt"));
+        assert!(agent.work_dir().is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+/// CoderAgent 的配置，默认值偏保守：独立的临时目录、不允许联网、第一次执行前需要审批
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CoderAgentConfig {
+    pub name: String,
+    pub description: String,
+    /// 工作目录的父目录，每次运行在其中新建一个子目录；未设置时使用系统临时目录
+    pub work_dir: Option<String>,
+    /// 单段代码执行的墙钟超时（秒），超时后结束进程
+    pub timeout_secs: u64,
+    /// stdout 和 stderr 各自保留的最大字节数，超出部分丢弃
+    pub max_output_bytes: usize,
+    /// 执行失败后让模型修正代码的最大轮数，包含第一次
+    pub max_iterations: usize,
+    /// 允许代码访问网络；关闭时通过 network_sandbox 在独立的网络命名空间中执行
+    pub allow_network: bool,
+    /// 关闭网络时放在解释器前面的命令
+    pub network_sandbox: Vec<String>,
+    /// 进程的虚拟内存上限（MB）
+    pub max_memory_mb: u64,
+    /// 进程的 CPU 时间上限（秒）
+    pub max_cpu_secs: u64,
+    pub python_command: String,
+    pub shell_command: String,
+    /// 第一次执行代码之前通过 ActionGuard 请用户确认
+    pub require_approval: bool,
+}
+
+impl Default for CoderAgentConfig {
+    fn default() -> Self {
+        Self {
+            name: "coder_agent".to_string(),
+            description: "Writes and executes Python or shell code to process data, run scripts and compute results.".to_string(),
+            work_dir: None,
+            timeout_secs: 60,
+            max_output_bytes: 16 * 1024,
+            max_iterations: 3,
+            allow_network: false,
+            network_sandbox: vec!["unshare".to_string(), "--net".to_string(), "--map-root-user".to_string()],
+            max_memory_mb: 1024,
+            max_cpu_secs: 60,
+            python_command: "python3".to_string(),
+            shell_command: "sh".to_string(),
+            require_approval: true,
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub mod agent;
+pub mod config;
+pub mod prompt;
+pub mod sandbox;
+
+pub use agent::CoderAgent;
+pub use config::CoderAgentConfig;
@@ -0,0 +1,242 @@
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+
+use crate::agents::coder_agent::config::CoderAgentConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeLanguage {
+    Python,
+    Shell,
+}
+
+impl CodeLanguage {
+    // 代码块的语言标记，不支持的语言返回 None
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag.to_ascii_lowercase().as_str() {
+            "python" | "python3" | "py" => Some(CodeLanguage::Python),
+            "sh" | "bash" | "shell" => Some(CodeLanguage::Shell),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CodeLanguage::Python => "python",
+            CodeLanguage::Shell => "sh",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            CodeLanguage::Python => "py",
+            CodeLanguage::Shell => "sh",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeBlock {
+    pub language: CodeLanguage,
+    pub code: String,
+}
+
+/* 按顺序提取 ``` 围起来的代码块。没有语言标记或语言不支持的代码块不执行，
+没有结束标记的代码块（回复被截断）也丢弃 */
+pub fn extract_code_blocks(text: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<(Option<CodeLanguage>, Vec<&str>)> = None;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        match current.take() {
+            None => {
+                if let Some(tag) = trimmed.strip_prefix("```") {
+                    let tag = tag.split_whitespace().next().unwrap_or_default();
+                    current = Some((CodeLanguage::from_tag(tag), Vec::new()));
+                }
+            }
+            Some((language, lines)) if trimmed == "```" => {
+                if let Some(language) = language {
+                    blocks.push(CodeBlock { language, code: lines.join("\n") });
+                }
+            }
+            Some((language, mut lines)) => {
+                lines.push(line);
+                current = Some((language, lines));
+            }
+        }
+    }
+    blocks
+}
+
+/// 一段代码的执行结果，输出已经按 max_output_bytes 截断
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionResult {
+    /// 超时或被信号结束时为 None
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+    pub truncated: bool,
+}
+
+impl ExecutionResult {
+    pub fn success(&self) -> bool {
+        !self.timed_out && self.exit_code == Some(0)
+    }
+
+    /// 交给模型和展示给用户的文字
+    pub fn describe(&self, timeout_secs: u64) -> String {
+        let status = match (self.timed_out, self.exit_code) {
+            (true, _) => format!("timed out after {}s", timeout_secs),
+            (false, Some(code)) => format!("exit code {}", code),
+            (false, None) => "terminated by a signal".to_string(),
+        };
+        let mut text = format!("{}\nstdout:\n{}\nstderr:\n{}", status, self.stdout.trim_end(), self.stderr.trim_end());
+        if self.truncated {
+            text.push_str("\n(output truncated)");
+        }
+        text
+    }
+}
+
+// 读取子进程输出，只保留前 cap 个字节，其余读出后丢弃，避免子进程因管道写满而阻塞
+async fn read_capped(mut reader: impl AsyncRead + Unpin, cap: usize) -> (Vec<u8>, bool) {
+    let mut kept = Vec::new();
+    let mut truncated = false;
+    let mut buf = [0u8; 8192];
+    loop {
+        match reader.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                let room = cap.saturating_sub(kept.len());
+                if n > room {
+                    truncated = true;
+                }
+                kept.extend_from_slice(&buf[..n.min(room)]);
+            }
+        }
+    }
+    (kept, truncated)
+}
+
+/* 把代码写入 dir/file_stem.<扩展名> 并在 dir 中执行：清空环境变量（只保留 PATH，HOME 和 TMPDIR 指向 dir），
+通过 sh 的 ulimit 限制内存和 CPU 时间，关闭网络时在 network_sandbox 中执行，超过 timeout_secs 结束进程 */
+pub async fn execute_code(block: &CodeBlock, dir: &Path, file_stem: &str, config: &CoderAgentConfig) -> Result<ExecutionResult> {
+    let file_name = format!("{}.{}", file_stem, block.language.extension());
+    std::fs::write(dir.join(&file_name), &block.code)
+        .with_context(|| format!("Failed to write {} in {}", file_name, dir.display()))?;
+
+    let interpreter = match block.language {
+        CodeLanguage::Python => &config.python_command,
+        CodeLanguage::Shell => &config.shell_command,
+    };
+    let limits = format!(
+        "ulimit -v {}; ulimit -t {}; exec \"$0\" \"$@\"",
+        config.max_memory_mb * 1024,
+        config.max_cpu_secs
+    );
+    let mut argv: Vec<String> = if config.allow_network { Vec::new() } else { config.network_sandbox.clone() };
+    argv.extend(["sh".to_string(), "-c".to_string(), limits, interpreter.clone(), file_name]);
+
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .current_dir(dir)
+        .env_clear()
+        .env("PATH", std::env::var("PATH").unwrap_or_else(|_| "/usr/local/bin:/usr/bin:/bin".to_string()))
+        .env("HOME", dir)
+        .env("TMPDIR", dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to start {}", argv[0]))?;
+
+    let cap = config.max_output_bytes;
+    let stdout = tokio::spawn(read_capped(child.stdout.take().context("stdout is not captured")?, cap));
+    let stderr = tokio::spawn(read_capped(child.stderr.take().context("stderr is not captured")?, cap));
+
+    let (exit_code, timed_out) = match tokio::time::timeout(Duration::from_secs(config.timeout_secs), child.wait()).await {
+        Ok(status) => (status?.code(), false),
+        Err(_) => {
+            let _ = child.kill().await;
+            (None, true)
+        }
+    };
+
+    // 子进程自己启动的后台进程可能一直占着管道，最多再等一秒
+    let collect = |handle: tokio::task::JoinHandle<(Vec<u8>, bool)>| async move {
+        match tokio::time::timeout(Duration::from_secs(1), handle).await {
+            Ok(Ok((bytes, truncated))) => (String::from_utf8_lossy(&bytes).to_string(), truncated),
+            _ => (String::new(), false),
+        }
+    };
+    let (stdout, stdout_truncated) = collect(stdout).await;
+    let (stderr, stderr_truncated) = collect(stderr).await;
+
+    Ok(ExecutionResult {
+        exit_code,
+        stdout,
+        stderr,
+        timed_out,
+        truncated: stdout_truncated || stderr_truncated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local_config() -> CoderAgentConfig {
+        // 测试环境不一定允许创建网络命名空间
+        CoderAgentConfig { allow_network: true, timeout_secs: 2, max_output_bytes: 64, ..CoderAgentConfig::default() }
+    }
+
+    #[test]
+    fn test_extract_code_blocks() {
+        let reply = "First list the files:\n```sh\nls -la\n```\nthen count them:\n```Python\nimport os\nprint(len(os.listdir('.')))\n```\n```\nplain text\n```\n```js\nconsole.log(1)\n```\n```python\nprint('truncated'";
+        let blocks = extract_code_blocks(reply);
+        assert_eq!(blocks, vec![
+            CodeBlock { language: CodeLanguage::Shell, code: "ls -la".to_string() },
+            CodeBlock { language: CodeLanguage::Python, code: "import os\nprint(len(os.listdir('.')))".to_string() },
+        ]);
+        assert!(extract_code_blocks("The answer is 42.").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_execute_captures_output_and_exit_code() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let block = CodeBlock { language: CodeLanguage::Shell, code: "echo out; echo err >&2; exit 3".to_string() };
+
+        let result = execute_code(&block, dir.path(), "step_1", &local_config()).await?;
+        assert_eq!(result.exit_code, Some(3));
+        assert_eq!(result.stdout, "out\n");
+        assert_eq!(result.stderr, "err\n");
+        assert!(!result.success());
+        assert!(dir.path().join("step_1.sh").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_execute_timeout_and_output_cap() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = local_config();
+
+        let slow = CodeBlock { language: CodeLanguage::Shell, code: "sleep 30".to_string() };
+        let result = execute_code(&slow, dir.path(), "slow", &config).await?;
+        assert!(result.timed_out);
+        assert!(result.describe(config.timeout_secs).starts_with("timed out after 2s"));
+
+        let noisy = CodeBlock { language: CodeLanguage::Shell, code: "yes | head -c 10000".to_string() };
+        let result = execute_code(&noisy, dir.path(), "noisy", &config).await?;
+        assert!(result.success());
+        assert_eq!(result.stdout.len(), 64);
+        assert!(result.truncated);
+        Ok(())
+    }
+}
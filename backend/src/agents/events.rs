@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::orchestrator::events::OrchestratorEvent;
+
+/// 代理执行过程中产生的中间事件
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentEvent {
+    /// 模型的思考或文字回复
+    Thought { text: String },
+    /// 将要执行的动作
+    ActionProposed { action: String, explanation: String },
+    /// 动作执行的结果
+    ActionResult { action: String, result: String },
+}
+
+/* orchestrator 在分发步骤前交给代理的事件出口，已经带上代理名称和步骤下标，
+事件直接写入 orchestrator 的广播通道，因此和 StepStarted / LedgerEvaluated 的顺序一致 */
+#[derive(Debug, Clone)]
+pub struct AgentEventSink {
+    agent_name: String,
+    step_index: usize,
+    tx: broadcast::Sender<OrchestratorEvent>,
+}
+
+impl AgentEventSink {
+    pub fn new(agent_name: &str, step_index: usize, tx: broadcast::Sender<OrchestratorEvent>) -> Self {
+        Self {
+            agent_name: agent_name.to_string(),
+            step_index,
+            tx,
+        }
+    }
+
+    // 没有订阅者时发送会失败，直接忽略；落后的订阅者由广播通道丢弃最旧的事件
+    pub fn emit(&self, event: AgentEvent) {
+        let _ = self.tx.send(OrchestratorEvent::AgentEvent {
+            agent_name: self.agent_name.clone(),
+            step_index: self.step_index,
+            event,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::Agent;
+    use crate::orchestrator::config::RetryPolicy;
+    use crate::orchestrator::message::{ChatMessage, Message, MessageRole};
+    use crate::orchestrator::retry::dispatch_with_retry;
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use futures::lock::Mutex;
+    use tokio_util::sync::CancellationToken;
+
+    #[derive(Default)]
+    struct ChattyAgent {
+        sink: Option<AgentEventSink>,
+    }
+
+    #[async_trait]
+    impl Agent for ChattyAgent {
+        fn name(&self) -> &str {
+            "web_surfer"
+        }
+
+        fn set_event_sink(&mut self, sink: Option<AgentEventSink>) {
+            self.sink = sink;
+        }
+
+        async fn on_message_stream(&mut self, message: Message) -> Result<ChatMessage> {
+            if let Some(sink) = &self.sink {
+                sink.emit(AgentEvent::Thought { text: "Looking for the search box".to_string() });
+                sink.emit(AgentEvent::ActionProposed {
+                    action: "input_text".to_string(),
+                    explanation: "Type the query".to_string(),
+                });
+                sink.emit(AgentEvent::ActionResult {
+                    action: "input_text".to_string(),
+                    result: "Typed 'flights to Tokyo'".to_string(),
+                });
+            }
+            Ok(ChatMessage::new_text(MessageRole::Assistant, message.to, "done".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_events_are_forwarded_in_order() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let agent: Mutex<Box<dyn Agent>> = Mutex::new(Box::new(ChattyAgent::default()));
+
+        // 和 orchestrator 分发步骤时的顺序一致
+        let _ = tx.send(OrchestratorEvent::StepStarted {
+            step_index: 1,
+            agent_name: "web_surfer".to_string(),
+            instruction: "Search flights".to_string(),
+        });
+        agent.lock().await.set_event_sink(Some(AgentEventSink::new("web_surfer", 1, tx.clone())));
+        let message = Message::execute(
+            "orchestrator",
+            "web_surfer",
+            "Book a flight",
+            ChatMessage::new_text(MessageRole::User, "orchestrator".to_string(), "Search flights".to_string()),
+        );
+        let outcome = dispatch_with_retry(&agent, message, &RetryPolicy::no_retry(), "orchestrator", &CancellationToken::new()).await;
+        assert!(!outcome.exhausted);
+        let _ = tx.send(OrchestratorEvent::LedgerEvaluated {
+            step_index: 1,
+            step_complete: true,
+            need_to_replan: false,
+            next_speaker: "web_surfer".to_string(),
+        });
+
+        assert!(matches!(rx.recv().await.unwrap(), OrchestratorEvent::StepStarted { step_index: 1, .. }));
+        let mut forwarded = Vec::new();
+        for _ in 0..3 {
+            match rx.recv().await.unwrap() {
+                OrchestratorEvent::AgentEvent { agent_name, step_index, event } => {
+                    assert_eq!(agent_name, "web_surfer");
+                    assert_eq!(step_index, 1);
+                    forwarded.push(event);
+                }
+                other => panic!("Expected agent event, got {:?}", other),
+            }
+        }
+        assert!(matches!(forwarded[0], AgentEvent::Thought { .. }));
+        assert!(matches!(forwarded[1], AgentEvent::ActionProposed { .. }));
+        assert!(matches!(forwarded[2], AgentEvent::ActionResult { .. }));
+        assert!(matches!(rx.recv().await.unwrap(), OrchestratorEvent::LedgerEvaluated { .. }));
+    }
+}
\ No newline at end of file
@@ -1,5 +1,13 @@
 pub mod web_agent;
 pub mod agent;
+pub mod events;
+pub mod coder_agent;
+pub mod simulated_agent;
+pub mod user_proxy;
 
 pub use agent::Agent;
-// pub use web_agent::WebAgent;
\ No newline at end of file
+pub use events::{AgentEvent, AgentEventSink};
+pub use coder_agent::CoderAgent;
+pub use simulated_agent::{DryRunLog, DryRunReport, SimulatedAgent};
+pub use user_proxy::UserProxy;
+pub use web_agent::WebAgent;
\ No newline at end of file
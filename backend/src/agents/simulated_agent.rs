@@ -0,0 +1,351 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::agents::{Agent, AgentEvent, AgentEventSink};
+use crate::clients::{ChatCompletionClient, TokenUsage};
+use crate::common::json_repair::parse_json_lenient;
+use crate::orchestrator::estimate::EstimatedCost;
+use crate::orchestrator::message::{
+    chat_message_to_llm_message, ChatMessage, LLMMessage, Message, MessageRole, MessageType, SystemMessage,
+};
+use crate::tools::url_status_manager::UrlStatusManager;
+
+/// 模拟输出的前缀，避免和真实执行的结果混淆
+pub const SIMULATED_TAG: &str = "[SIMULATED]";
+
+/// URL 策略对预测访问的网址给出的结论
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UrlVerdict {
+    Allowed,
+    Blocked,
+    NeedsApproval,
+}
+
+impl UrlVerdict {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UrlVerdict::Allowed => "allowed",
+            UrlVerdict::Blocked => "blocked",
+            UrlVerdict::NeedsApproval => "needs approval",
+        }
+    }
+}
+
+/// 模型预测的一个动作
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PredictedAction {
+    pub tool: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub url: Option<String>,
+    /// 有 url 时 URL 策略的结论
+    #[serde(default)]
+    pub verdict: Option<UrlVerdict>,
+}
+
+/// 一次模拟执行的步骤
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimulatedStep {
+    pub agent_name: String,
+    pub instruction: String,
+    pub actions: Vec<PredictedAction>,
+    pub summary: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Prediction {
+    #[serde(default)]
+    actions: Vec<PredictedAction>,
+    #[serde(default)]
+    summary: String,
+}
+
+/// 所有模拟代理共享的记录，演练结束后用来生成报告
+#[derive(Debug, Clone, Default)]
+pub struct DryRunLog(Arc<Mutex<Vec<SimulatedStep>>>);
+
+impl DryRunLog {
+    pub fn steps(&self) -> Vec<SimulatedStep> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn push(&self, step: SimulatedStep) {
+        self.0.lock().unwrap().push(step);
+    }
+}
+
+/* 演练运行中代替真实代理的代理：不操作浏览器也不执行代码，而是让模型预测
+完成指令需要的动作和访问的网址，网址仍然经过 URL 策略检查，让用户看到哪些地方需要审批。
+返回给 orchestrator 的结果都带有 [SIMULATED] 前缀 */
+pub struct SimulatedAgent {
+    name: String,
+    description: String,
+    model_client: Arc<dyn ChatCompletionClient>,
+    url_policy: Option<Arc<UrlStatusManager>>,
+    log: DryRunLog,
+    event_sink: Option<AgentEventSink>,
+    usage: TokenUsage,
+}
+
+impl SimulatedAgent {
+    /// description 是被代替的代理的描述，模型据此判断它能使用哪些工具
+    pub fn new(name: &str, description: &str, model_client: Arc<dyn ChatCompletionClient>, log: DryRunLog) -> Self {
+        Self {
+            name: name.to_string(),
+            description: description.to_string(),
+            model_client,
+            url_policy: None,
+            log,
+            event_sink: None,
+            usage: TokenUsage::default(),
+        }
+    }
+
+    pub fn with_url_policy(mut self, policy: Arc<UrlStatusManager>) -> Self {
+        self.url_policy = Some(policy);
+        self
+    }
+
+    // 没有 URL 策略时不限制网站
+    fn verdict(&self, url: &str) -> UrlVerdict {
+        match &self.url_policy {
+            None => UrlVerdict::Allowed,
+            Some(policy) if policy.is_url_blocked(url) || policy.is_url_rejected(url) => UrlVerdict::Blocked,
+            Some(policy) if policy.is_url_allowed(url) => UrlVerdict::Allowed,
+            Some(_) => UrlVerdict::NeedsApproval,
+        }
+    }
+
+    fn system_message(&self) -> String {
+        format!(
+            r#"You are simulating the agent "{name}" for a dry run. The agent is described as: {description}
+Do not pretend that anything was executed. Predict the actions the agent would most likely take to complete the instruction, in order, including every URL it would visit.
+Respond with only a JSON object of the form:
+{{"actions": [{{"tool": "tool name", "description": "what the action does", "url": "https://... or null"}}], "summary": "one sentence describing the likely result"}}"#,
+            name = self.name,
+            description = self.description,
+        )
+    }
+
+    fn emit(&self, event: AgentEvent) {
+        if let Some(sink) = &self.event_sink {
+            sink.emit(event);
+        }
+    }
+
+    fn reply(&self, content: String) -> ChatMessage {
+        ChatMessage::new_text(MessageRole::Assistant, self.name.clone(), content)
+    }
+}
+
+#[async_trait]
+impl Agent for SimulatedAgent {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_event_sink(&mut self, sink: Option<AgentEventSink>) {
+        self.event_sink = sink;
+    }
+
+    fn take_usage(&mut self) -> TokenUsage {
+        std::mem::take(&mut self.usage)
+    }
+
+    async fn on_message_stream(&mut self, message: Message) -> Result<ChatMessage> {
+        if matches!(message.msg_type, MessageType::Notify) {
+            return Ok(self.reply(String::new()));
+        }
+        let instruction = match message.chat_history.last() {
+            Some(ChatMessage::Text { content, .. }) => content.clone(),
+            _ => String::new(),
+        };
+
+        let mut context = vec![LLMMessage::System(SystemMessage::new(self.system_message()))];
+        for chat_message in &message.chat_history {
+            context.push(chat_message_to_llm_message(chat_message)?);
+        }
+        let result = self.model_client.create(&context).await?;
+        self.usage.add(&result.usage);
+        let reply = result.content;
+        // 预测不是 JSON 时把原文作为总结，不记录动作
+        let prediction = parse_json_lenient(&reply)
+            .and_then(|repaired| serde_json::from_value::<Prediction>(repaired.value).ok())
+            .unwrap_or_else(|| Prediction { actions: Vec::new(), summary: reply.trim().to_string() });
+
+        let mut lines = vec![format!("{} {}", SIMULATED_TAG, prediction.summary.trim())];
+        let mut actions = Vec::new();
+        for mut action in prediction.actions {
+            action.url = action.url.filter(|url| !url.trim().is_empty());
+            action.verdict = action.url.as_deref().map(|url| self.verdict(url));
+            let mut line = format!("- {}: {}", action.tool, action.description);
+            if let (Some(url), Some(verdict)) = (&action.url, action.verdict) {
+                line.push_str(&format!(" ({}, {})", url, verdict.name()));
+            }
+            self.emit(AgentEvent::ActionProposed {
+                action: action.tool.clone(),
+                explanation: format!("{} {}", SIMULATED_TAG, action.description),
+            });
+            lines.push(line);
+            actions.push(action);
+        }
+
+        self.log.push(SimulatedStep {
+            agent_name: self.name.clone(),
+            instruction,
+            actions,
+            summary: prediction.summary.trim().to_string(),
+        });
+        Ok(self.reply(lines.join("\n")))
+    }
+}
+
+/// 演练报告：每个步骤预测的动作、需要的审批和开销估算
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DryRunReport {
+    pub steps: Vec<SimulatedStep>,
+    pub estimate: Option<EstimatedCost>,
+}
+
+impl DryRunReport {
+    pub fn new(log: &DryRunLog, estimate: Option<EstimatedCost>) -> Self {
+        Self { steps: log.steps(), estimate }
+    }
+
+    /// 需要用户审批的网址，按出现顺序去重
+    pub fn required_approvals(&self) -> Vec<String> {
+        let mut urls: Vec<String> = Vec::new();
+        for action in self.steps.iter().flat_map(|step| &step.actions) {
+            if let (Some(url), Some(UrlVerdict::NeedsApproval)) = (&action.url, action.verdict) {
+                if !urls.contains(url) {
+                    urls.push(url.clone());
+                }
+            }
+        }
+        urls
+    }
+
+    pub fn render(&self) -> String {
+        let mut lines = vec![format!("{} Dry-run report, nothing was executed.", SIMULATED_TAG)];
+        for (i, step) in self.steps.iter().enumerate() {
+            lines.push(format!("{}. {}: {}", i + 1, step.agent_name, step.instruction));
+            for action in &step.actions {
+                let mut line = format!("   - {}: {}", action.tool, action.description);
+                if let (Some(url), Some(verdict)) = (&action.url, action.verdict) {
+                    line.push_str(&format!(" [{}: {}]", verdict.name(), url));
+                }
+                lines.push(line);
+            }
+            if !step.summary.is_empty() {
+                lines.push(format!("   Likely result: {}", step.summary));
+            }
+        }
+        let approvals = self.required_approvals();
+        if approvals.is_empty() {
+            lines.push("Required approvals: none".to_string());
+        } else {
+            lines.push(format!("Required approvals: {}", approvals.join(", ")));
+        }
+        if let Some(estimate) = &self.estimate {
+            lines.push(estimate.summary());
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::orchestrator::types::RunOptions;
+    use crate::testing::{ledger_json, plan_json, MockProvider, OrchestratorBuilder};
+    use crate::tools::url_status_manager::UrlStatus;
+
+    fn prediction(url: &str) -> String {
+        serde_json::json!({
+            "actions": [
+                { "tool": "visit_url", "description": "Open the restaurant site", "url": url },
+                { "tool": "click", "description": "Open the menu page" },
+            ],
+            "summary": "The menu page would be open",
+        })
+        .to_string()
+    }
+
+    fn policy() -> Arc<UrlStatusManager> {
+        let statuses = HashMap::from([("wikipedia.org".to_string(), UrlStatus::Allowed)]);
+        Arc::new(UrlStatusManager::new(Some(statuses), Some(vec!["ads.example.com".to_string()])))
+    }
+
+    #[tokio::test]
+    async fn test_simulated_step_checks_predicted_urls() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond(prediction("https://wikipedia.org/wiki/Menu"))
+            .respond(prediction("https://roma.example.org"))
+            .respond(prediction("https://ads.example.com/promo")));
+        let log = DryRunLog::default();
+        let mut agent = SimulatedAgent::new("web_surfer", "Browses the web", provider, log.clone()).with_url_policy(policy());
+
+        let execute = |text: &str| Message::execute(
+            "orchestrator",
+            "web_surfer",
+            "Find the menu",
+            ChatMessage::new_text(MessageRole::User, "orchestrator".to_string(), text.to_string()),
+        );
+        let reply = agent.on_message_stream(execute("Open the menu")).await?;
+        match &reply {
+            ChatMessage::Text { content, .. } => {
+                assert!(content.starts_with("[SIMULATED] The menu page would be open"));
+                assert!(content.contains("- visit_url: Open the restaurant site (https://wikipedia.org/wiki/Menu, allowed)"));
+            }
+            _ => panic!("Expected text message"),
+        }
+        agent.on_message_stream(execute("Open the menu")).await?;
+        agent.on_message_stream(execute("Open the menu")).await?;
+
+        let verdicts: Vec<Option<UrlVerdict>> = log.steps().iter().map(|step| step.actions[0].verdict).collect();
+        assert_eq!(verdicts, vec![Some(UrlVerdict::Allowed), Some(UrlVerdict::NeedsApproval), Some(UrlVerdict::Blocked)]);
+        assert_eq!(log.steps()[0].actions[1].verdict, None);
+        assert_eq!(log.steps()[0].instruction, "Open the menu");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_report_from_orchestrator_run() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Find the menu", &[("Find the menu", "Open the restaurant menu", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Open the restaurant menu"))
+            .respond(prediction("https://roma.example.org/menu"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("The menu would be on roma.example.org."));
+        let log = DryRunLog::default();
+        let dir = tempfile::tempdir()?;
+        let session_dir = dir.path().join("session");
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", SimulatedAgent::new("web_surfer", "Browses the web", provider, log.clone()).with_url_policy(policy()))
+            .configure(|c| c.session_dir = Some(session_dir.display().to_string()))
+            .build()
+            .await?;
+
+        let outcome = orchestrator
+            .run_task("Find the menu".to_string(), RunOptions { dry_run: true, ..RunOptions::default() })
+            .await?;
+        assert_eq!(outcome.final_answer, "The menu would be on roma.example.org.");
+        // 演练不写会话检查点
+        assert!(!session_dir.exists());
+
+        let report = DryRunReport::new(&log, orchestrator.plan_estimate().cloned());
+        assert_eq!(report.required_approvals(), vec!["https://roma.example.org/menu".to_string()]);
+        let rendered = report.render();
+        assert!(rendered.starts_with("[SIMULATED] Dry-run report"));
+        assert!(rendered.contains("[needs approval: https://roma.example.org/menu]"));
+        assert!(rendered.contains("Approximate estimate:"));
+        Ok(())
+    }
+}
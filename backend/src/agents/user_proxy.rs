@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::agents::Agent;
+use crate::orchestrator::message::{ChatMessage, Message, MessageRole, MessageType};
+use crate::tools::approval_guard::ActionGuard;
+
+pub const USER_PROXY_DESCRIPTION: &str = "The user at the terminal, who can answer clarification questions";
+
+/* 代表终端前的用户：orchestrator 分发给 user_proxy 的提问（如规划前的追问）交给 guard 的 get_answer，
+回答作为用户的消息交还 orchestrator。用户没有回答时告诉模型自行做出合理的假设 */
+#[derive(Debug)]
+pub struct UserProxy {
+    name: String,
+    guard: Arc<dyn ActionGuard>,
+}
+
+impl UserProxy {
+    pub fn new(guard: Arc<dyn ActionGuard>) -> Self {
+        Self { name: "user_proxy".to_string(), guard }
+    }
+
+    fn reply(&self, content: String) -> ChatMessage {
+        ChatMessage::new_text(MessageRole::User, self.name.clone(), content)
+    }
+}
+
+#[async_trait]
+impl Agent for UserProxy {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn on_message_stream(&mut self, message: Message) -> Result<ChatMessage> {
+        if !matches!(message.msg_type, MessageType::Execute) {
+            return Ok(self.reply(String::new()));
+        }
+        // 最后一条是这次的提问，前面是任务背景
+        let question = match message.chat_history.last() {
+            Some(question) => question,
+            None => return Ok(self.reply(String::new())),
+        };
+        Ok(match self.guard.get_answer(question).await {
+            Some(answer) => self.reply(answer),
+            None => self.reply("The user did not answer. Make reasonable assumptions and state them.".to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockGuard;
+
+    fn question(text: &str) -> Message {
+        let question = ChatMessage::new_text(MessageRole::Assistant, "orchestrator".to_string(), text.to_string());
+        Message::execute("orchestrator", "user_proxy", "Book a table", question)
+    }
+
+    fn text(message: &ChatMessage) -> &str {
+        match message {
+            ChatMessage::Text { content, .. } => content,
+            ChatMessage::MultiModal { .. } => "",
+        }
+    }
+
+    #[tokio::test]
+    async fn test_questions_are_answered_by_the_user() -> Result<()> {
+        let guard = Arc::new(MockGuard::deny_all().with_answer("Tomorrow, two people"));
+        let mut proxy = UserProxy::new(guard.clone());
+        let reply = proxy.on_message_stream(question("For which day and how many people?")).await?;
+        assert_eq!(text(&reply), "Tomorrow, two people");
+        // 只交出提问，不含任务背景
+        assert_eq!(guard.requests(), ["For which day and how many people?"]);
+
+        // 没有回答时让模型自行假设
+        let mut proxy = UserProxy::new(Arc::new(MockGuard::deny_all()));
+        let reply = proxy.on_message_stream(question("Which restaurant?")).await?;
+        assert!(text(&reply).starts_with("The user did not answer"), "{}", text(&reply));
+        Ok(())
+    }
+}
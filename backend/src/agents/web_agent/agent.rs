@@ -3,6 +3,8 @@ use std::fmt::Debug;
 use async_trait::async_trait;
 use urlencoding::encode;
 use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use regex::Regex;
 use chrono::Utc;
 use anyhow::{anyhow, Result};
@@ -11,26 +13,44 @@ use serde_json::json;
 use tldextract::{TldExtractor, TldOption};
 use image::{imageops::FilterType};
 use crate::agents::agent::Agent;
+use crate::agents::events::{AgentEvent, AgentEventSink};
 use crate::agents::web_agent::prompt::WEB_SURFER_SYSTEM_MESSAGE;
+use crate::agents::web_agent::limits::{within, RunEnd, StepLimits, TimeoutKind};
+use crate::agents::web_agent::response::plan_response;
+use crate::agents::web_agent::notify::{acknowledgement, notification_history};
 use crate::agents::web_agent::set_of_mark::{PageState, add_set_of_mark};
-use crate::agents::web_agent::tool_define::DefaultTools;
-use crate::clients::{call_llm, LLMResponse};
+use crate::agents::web_agent::types::{FunctionCall, LLMOutput, LLMReply};
+use crate::agents::web_agent::tool_define::{
+    hold_seconds, sleep_seconds, step_control, DefaultTools, StepControl, DEFAULT_MAX_SLEEP_SECS, DEFAULT_STOP_ANSWER,
+};
+use crate::clients::llm::DEFAULT_MAX_IMAGE_BYTES;
+use crate::clients::{ChatCompletionClient, HttpChatClient, LlmRetryPolicy, ModelProfile, ModelRegistry, ModelRole, RetryingClient, TokenUsage, ToolSpec};
+use crate::common::template::render_template;
+use crate::tools::utils::webpage_text_utils::limit_tokens;
 use crate::orchestrator::message::MessageRole;
 use crate::orchestrator::message::MessageType;
 use crate::orchestrator::message::AssistantContent;
 use crate::orchestrator::message::AssistantMessage;
 use crate::orchestrator::message::ChatMessage;
-use crate::orchestrator::message::FunctionCall;
 use crate::orchestrator::message::Message;
 use crate::orchestrator::message::MultiModalContent;
 use crate::orchestrator::message::UserContent;
 use crate::orchestrator::message::LLMMessage;
 use crate::orchestrator::message::SystemMessage;
 use crate::orchestrator::message::UserMessage;
-use crate::tools::chrome::chrome_ctrl::Chrome;
+use crate::orchestrator::message::PAGE_UNCHANGED_KEY;
+use crate::orchestrator::message::USER_INTERRUPT_KEY;
+use crate::orchestrator::message::TOKEN_USAGE_KEY;
+use crate::orchestrator::types::{as_new_user_message, resolve_upload_path, SessionFiles, UserMailbox};
+use crate::tools::chrome::chrome_ctrl::{Chrome, ChromeConfig, DEFAULT_MAX_VISIBLE_TEXT_CHARS};
+use crate::tools::chrome::downloads::VisitOutcome;
 use crate::tools::chrome::types::InteractiveRegion;
+use crate::tools::approval_guard::{describe_action, gate_action, gate_domain, is_irreversible_action, ActionGuard};
 use crate::tools::tool_metadata::ToolSchema;
-use crate::tools::url_status_manager::UrlStatusManager;
+use crate::tools::url_status_manager::{SitePolicy, UrlStatusManager};
+
+// answer_question 和 summarize_page 的提示词中页面正文默认最多的 token 数，给问题、截图和回答留出空间
+const DEFAULT_PAGE_MAX_TOKENS: usize = 8000;
 
 #[derive(Debug, Clone)]
 pub enum ContentItem {
@@ -38,14 +58,39 @@ pub enum ContentItem {
     Image(Vec<u8>),
 }
 
-#[derive(Debug)]
 pub struct WebAgent {
-    chrome_ctrl: Option<Chrome>,
+    // 自己启动的浏览器，或者从 BrowserPool 借来、运行期间共享的实例
+    chrome_ctrl: Option<Arc<Chrome>>,
     chat_history: Option<Vec<LLMMessage>>,
     prior_metadata_hash: Option<String>,
     url_status_manager: UrlStatusManager,
     last_rejected_url: Option<String>,
     name: String,
+    event_sink: Option<AgentEventSink>,
+    mailbox: Option<UserMailbox>,
+    session_files: Option<SessionFiles>,
+    uploads_dir: Option<PathBuf>,
+    page_max_tokens: usize,
+    // sleep 工具最长等待的秒数
+    max_sleep_secs: f64,
+    // 一轮执行的步骤数和时间限制
+    limits: StepLimits,
+    // 除了标注了编号的截图，是否再附上原始截图
+    include_raw_screenshot: bool,
+    // 选择动作使用的模型，对应 [models.web_agent]
+    model_client: Arc<dyn ChatCompletionClient>,
+    // answer_question 和 summarize_page 使用的模型，对应 [models.summarizer]
+    summarizer_client: Arc<dyn ChatCompletionClient>,
+    // 上次 take_usage 之后累计的模型用量；&self 的方法也会调用模型，所以放在 Mutex 中
+    usage: std::sync::Mutex<TokenUsage>,
+    // 访问新域名和执行无法撤销的动作之前询问，没有设置时新域名一律拒绝
+    action_guard: Option<Arc<dyn ActionGuard>>,
+}
+
+// 没有调用 set_models 时使用 [llm] 的默认模型
+fn default_model_client() -> Arc<dyn ChatCompletionClient> {
+    let client = HttpChatClient::new(ModelProfile::default(), DEFAULT_MAX_IMAGE_BYTES);
+    Arc::new(RetryingClient::new(Arc::new(client), LlmRetryPolicy::default()))
 }
 
 impl Default for WebAgent {
@@ -58,6 +103,18 @@ impl Default for WebAgent {
             url_status_manager: UrlStatusManager::new(None, None),
             last_rejected_url: None,
             name: "WebAgent".to_string(),
+            event_sink: None,
+            mailbox: None,
+            session_files: None,
+            uploads_dir: None,
+            page_max_tokens: DEFAULT_PAGE_MAX_TOKENS,
+            max_sleep_secs: DEFAULT_MAX_SLEEP_SECS,
+            limits: StepLimits::default(),
+            include_raw_screenshot: false,
+            model_client: default_model_client(),
+            summarizer_client: default_model_client(),
+            usage: std::sync::Mutex::new(TokenUsage::default()),
+            action_guard: None,
         }
     }
 
@@ -69,6 +126,22 @@ impl Agent for WebAgent {
     fn name(&self) -> &str {
         &self.name
     }
+
+    fn set_event_sink(&mut self, sink: Option<AgentEventSink>) {
+        self.event_sink = sink;
+    }
+
+    fn set_session_files(&mut self, files: Option<SessionFiles>) {
+        self.session_files = files;
+    }
+
+    fn set_mailbox(&mut self, mailbox: Option<UserMailbox>) {
+        self.mailbox = mailbox;
+    }
+
+    fn take_usage(&mut self) -> TokenUsage {
+        std::mem::take(self.usage.get_mut().unwrap())
+    }
     // web_agent的核心，接收用户或者orchestrator的消息，驱动浏览器进行一系列的操作，并将操作以流的形式（AsyncGenerator）逐步返回
     async fn on_message_stream(
         &mut self,
@@ -76,17 +149,22 @@ impl Agent for WebAgent {
     ) -> Result<ChatMessage> {
 
         match messages.msg_type {
+            // 只记录通知，不操作浏览器
             MessageType::Notify => {
-                unimplemented!()
+                let notifications = notification_history(&messages);
+                self.chat_history.as_mut().unwrap().extend(notifications);
+                Ok(acknowledgement(&self.name))
             }
 
             MessageType::Execute => {
+                // 之前尚未被读取的用量先取出，结束时再加回去，中间累计的就是这一轮的用量
+                let carried_usage = self.take_usage();
                 // 1. 依据消息的类型，将消息添加到聊天历史中
                 // （多模态消息全部保留，文本消息只保留最后一条，为了避免历史消息进行影响）
                 let total = messages.chat_history.len();
                 for (i, chat_message) in messages.chat_history.into_iter().enumerate() {
                     match chat_message {
-                        ChatMessage::Text { role, source, content, metadata } => {
+                        ChatMessage::Text { source, content, .. } => {
                             if i == total - 1 {
                                 self.chat_history.as_mut().unwrap().push(
                                     LLMMessage::User(
@@ -98,7 +176,7 @@ impl Agent for WebAgent {
                                 );
                             }
                         },
-                        ChatMessage::MultiModal { role, source, content, .. } => {
+                        ChatMessage::MultiModal { source, content, .. } => {
                             self.chat_history.as_mut().unwrap().push(
                                 LLMMessage::User(
                                     UserMessage::new(
@@ -117,128 +195,212 @@ impl Agent for WebAgent {
                 let mut actions_proposed = Vec::<String>::new();
                 let mut action_results = Vec::<String>::new();
                 let mut all_screenshots = Vec::<Vec<u8>>::new();
-
-                let non_action_tools: HashSet<&str> = 
-                    vec!["stop_action", "answer_question"].into_iter().collect();
-                
-                let max_steps = 10; // 最大步骤数
+                // 执行失败的原因，会以 error 标记返回给 orchestrator 以便重试
+                let mut failure: Option<String> = None;
+                // 步骤执行期间是否收到了新的用户消息
+                let mut interrupted = false;
+                // stop_action 的回答或 answer_question / summarize_page 的结果，有值时结束主循环
+                let mut final_answer: Option<String> = None;
+                // 模型给出了文本回复
+                let mut replied = false;
+                // 因为步骤或时间用完而结束的原因
+                let mut run_end: Option<RunEnd> = None;
+                let mut steps_taken = 0;
+
+                let limits = self.limits;
+                let run_started = tokio::time::Instant::now();
                 
                 // 3. 主循环：从第0步到最大步骤之间的执行
-                for _step in 0..max_steps {
+                'steps: for step in 0..limits.max_steps {
+                    if failure.is_some() {
+                        break;
+                    }
+                    steps_taken = step + 1;
+                    // 调用模型和执行工具共用这个截止时间
+                    let deadline = limits.step_deadline(run_started, tokio::time::Instant::now());
+
+                    // 3.0) 上一次工具调用之后用户追加的消息，在调用 LLM 之前放进对话历史
+                    interrupted |= self.poll_user_messages();
                     
                     // 3.1) 调用LLM，获取下一步要执行的动作
-                    let (llm_responses, rects, tools, element_id_mapping, _need_execute_tool) = 
-                        self.get_llm_response().await?;
+                    let (outputs, rects, tools, element_id_mapping, _need_execute_tool) =
+                        match within(deadline, self.get_llm_response()).await {
+                            Ok(Ok(response)) => response,
+                            Ok(Err(e)) => {
+                                tracing::warn!("LLM error: {:#}", e);
+                                failure = Some(format!("LLM error: {:#}", e));
+                                break 'steps;
+                            }
+                            Err(kind) => {
+                                let observation = self.record_timeout(kind, step, "waiting for the model");
+                                observations.push(observation);
+                                run_end = limits.run_end(kind);
+                                if run_end.is_some() {
+                                    break 'steps;
+                                }
+                                continue 'steps;
+                            }
+                        };
                     
                     // 3.2) 如果不需要工具（思考或总结），输出文本响应并继续
                     let title = self.chrome_ctrl.as_ref().unwrap().get_title().await?;
                     let url = self.chrome_ctrl.as_ref().unwrap().get_url().await?;
                     
-                    // 处理第一个 LLM 响应
-                    if let Some(first_response) = llm_responses.first() {
-                        match first_response {
-                            LLMResponse::Text(text) => {
-                                let summary = format!(
-                                    "On the webpage '{}', we propose the following action: {}",
-                                    title, text
-                                );
-
-                                // 将LLM的思考添加到历史中
-                                self.chat_history.as_mut().unwrap().push(
-                                    LLMMessage::Assistant(AssistantMessage::new(
-                                        AssistantContent::String(summary.clone()),
-                                        Some(self.name.clone()),
-                                    ))
-                                );
+                    // 按顺序处理响应的所有部分：文本合并为想法，函数调用逐个执行
+                    let plan = plan_response(&outputs);
+
+                    if let Some(text) = &plan.thought {
+                        let summary = format!(
+                            "On the webpage '{}', we propose the following action: {}",
+                            title, text
+                        );
+
+                        // 将LLM的思考添加到历史中
+                        self.chat_history.as_mut().unwrap().push(
+                            LLMMessage::Assistant(AssistantMessage::new(
+                                AssistantContent::String(summary.clone()),
+                                Some(self.name.clone()),
+                            ))
+                        );
+
+                        emited_responses.push(text.clone());
+                        if plan.is_text_only() {
+                            actions_proposed.push(summary);
+                        }
 
-                                emited_responses.push(text.clone());
-                                actions_proposed.push(summary);
+                        // 进行response
+                        self.emit_event(AgentEvent::Thought { text: text.clone() });
+                    }
 
-                                // 进行response
+                    // 只有文本时是给用户的回复，终止循环
+                    if plan.is_text_only() {
+                        replied = true;
+                        break;
+                    }
 
-                                break; // 终止循环
+                    let mut calls = plan.calls.into_iter();
+                    while let Some(action) = calls.next() {
+                        let tool_call_name = action.name.clone();
+                        // 模型给出的参数不是合法的 JSON 时不执行，错误作为这个动作的结果，下一步的模型可以看到并改正
+                        let arguments = match serde_json::from_str::<Value>(&action.arguments) {
+                            Ok(arguments) => arguments,
+                            Err(e) => {
+                                let error = format!("Action {} failed: the arguments are not valid JSON ({}): {}", tool_call_name, e, action.arguments);
+                                actions_proposed.push(format!("'{} ({})'", tool_call_name, action.arguments));
+                                action_results.push(error.clone());
+                                observations.push(error.clone());
+                                self.chat_history.as_mut().unwrap().push(LLMMessage::User(UserMessage::new(
+                                    UserContent::String(format!("Observation: {}", error)),
+                                    self.name.clone(),
+                                )));
+                                continue;
                             }
-                            LLMResponse::FunctionCalls(function_calls) => {
-                                for action in function_calls {
-                                    let tool_call_name = action.name.clone();
-                                    let tool_call_msg = format!("'{} ({})'", action.name, 
-                                        serde_json::to_string(&serde_json::from_str::<Value>(&action.arguments).unwrap()).unwrap());
-                                    
-                                    let tool_call_explanation = serde_json::from_str::<serde_json::Value>(&action.arguments)
-                                        .ok()
-                                        .and_then(|v| v.get("explanation").and_then(|e| e.as_str()).map(|s| s.to_string()))
-                                        .unwrap_or_default();
-
-                                    actions_proposed.push(tool_call_msg.clone());
-                                    let action_context = format!("'{}' (at '{}')", title, url);
-                                    
-                                    self.chat_history.as_mut().unwrap().push(
-                                        LLMMessage::Assistant(AssistantMessage::new(
-                                            AssistantContent::String(format!("On the webpage {}, we propose the following action: {}", action_context, tool_call_msg)),
-                                            Some(self.name.clone())
-                                        ))
-                                    );
-
-                                    // 终止操作
-                                    if tool_call_name == "stop_action" {
-                                        let tool_call_answer = serde_json::from_str::<serde_json::Value>(&action.arguments)
-                                            .ok()
-                                            .and_then(|v| v.get("answer").and_then(|a| a.as_str()).map(|s| s.to_string()))
-                                            .unwrap_or_default();
-
-                                        observations.push(tool_call_answer.clone());
-                                        action_results.push(tool_call_answer.clone());
-                                        emited_responses.push(tool_call_answer);
-                                        // 返回response
-                                    }
-
-                                    // 普通操作
-                                    emited_responses.push(tool_call_explanation);
-                                    // 返回response
-
-                                    let action_result = self.execute_tool(vec![action.clone()], rects.clone(), tools.clone(), element_id_mapping.clone()).await?;
-                            
-                                    let new_screenshot = self.chrome_ctrl.as_ref().unwrap().get_screenshot(None).await?;
-                                    all_screenshots.push(new_screenshot.clone());
-
-                                    let _content_item = vec![
-                                        ContentItem::Text(action_result.clone()),
-                                        ContentItem::Image(new_screenshot.clone()),
-                                    ];
-
-                                    emited_responses.push(action_result.clone());
-
-                                    // response
-
-                                    let(message_content, _, _metadata_hash) = self
-                                        .chrome_ctrl.as_ref().unwrap().describe_page(false).await?;
-                                    
-                                    observations.push(format!("'{}' \n\n '{}'", action_result, message_content));
-                                    action_results.push(action_result.clone());
-
-                                    let observation_text = format!("Observation: {}\n\n{}", action_result, message_content);
-
-                                    let content = UserContent::MultiModal(vec![
-                                        MultiModalContent::Text(observation_text),
-                                        MultiModalContent::Image(new_screenshot.clone()),
-                                    ]);
-
-                                    self.chat_history.as_mut().unwrap().push(
-                                        LLMMessage::User(UserMessage::new(
-                                            content,
-                                            self.name.clone()
-                                        ))
-                                    );
-
-                                    if non_action_tools.contains(tool_call_name.as_str()) {
-                                        break;
-                                    }
+                        };
+                        let tool_call_msg = format!("'{} ({})'", action.name, arguments);
+
+                        let tool_call_explanation = arguments
+                            .get("explanation")
+                            .and_then(|e| e.as_str())
+                            .map(|s| s.to_string())
+                            .unwrap_or_default();
+
+                        actions_proposed.push(tool_call_msg.clone());
+                        let action_context = format!("'{}' (at '{}')", title, url);
+                        
+                        self.chat_history.as_mut().unwrap().push(
+                            LLMMessage::Assistant(AssistantMessage::new(
+                                AssistantContent::String(format!("On the webpage {}, we propose the following action: {}", action_context, tool_call_msg)),
+                                Some(self.name.clone())
+                            ))
+                        );
+
+                        let control = step_control(&tool_call_name, &arguments);
+
+                        // 终止操作：不执行工具，answer 就是最终回答
+                        if let StepControl::Stop { answer } = control {
+                            observations.push(answer.clone());
+                            action_results.push(answer.clone());
+                            emited_responses.push(answer.clone());
+                            self.emit_event(AgentEvent::Thought { text: answer.clone() });
+                            final_answer = Some(answer);
+                            break 'steps;
+                        }
+
+                        // 普通操作
+                        emited_responses.push(tool_call_explanation.clone());
+                        // 返回response
+                        self.emit_event(AgentEvent::ActionProposed {
+                            action: tool_call_msg.clone(),
+                            explanation: tool_call_explanation,
+                        });
+
+                        let execution = self.execute_tool(vec![action.clone()], rects.clone(), tools.clone(), element_id_mapping.clone());
+                        let action_result = match within(deadline, execution).await {
+                            Ok(Ok(result)) => result,
+                            Err(kind) => {
+                                let observation = self.record_timeout(kind, step, &format!("running {}", tool_call_name));
+                                action_results.push(observation.clone());
+                                observations.push(observation);
+                                run_end = limits.run_end(kind);
+                                if run_end.is_some() {
+                                    break 'steps;
+                                }
+                                // 本步骤的时间已经用完，同一回复中剩下的调用不再执行；记录下来，由下一步的模型重新决定
+                                for skipped in calls.by_ref() {
+                                    let skipped_msg = format!("'{} ({})'", skipped.name, skipped.arguments);
+                                    let note = format!("Action {} was not performed because step {} ran out of time.", skipped_msg, step + 1);
+                                    actions_proposed.push(skipped_msg);
+                                    action_results.push(note.clone());
+                                    observations.push(note.clone());
+                                    self.chat_history.as_mut().unwrap().push(LLMMessage::User(UserMessage::new(
+                                        UserContent::String(format!("Observation: {}", note)),
+                                        self.name.clone(),
+                                    )));
                                 }
+                                continue 'steps;
                             }
-                            LLMResponse::Error(err) => {
-                                eprintln!("LLM Error: {}", err);
+                            Ok(Err(e)) => {
+                                let error = format!("Action {} failed: {:#}", tool_call_name, e);
+                                action_results.push(error.clone());
+                                failure = Some(error);
                                 break;
                             }
+                        };
+                    
+                        let new_screenshot = self.chrome_ctrl.as_ref().unwrap().get_screenshot(None).await?;
+                        all_screenshots.push(new_screenshot.clone());
+
+                        emited_responses.push(action_result.clone());
+
+                        // response
+                        self.emit_event(AgentEvent::ActionResult {
+                            action: tool_call_msg.clone(),
+                            result: action_result.clone(),
+                        });
+
+                        let(message_content, _, _metadata_hash) = self
+                            .chrome_ctrl.as_ref().unwrap().describe_page(false).await?;
+                        
+                        observations.push(format!("'{}' \n\n '{}'", action_result, message_content));
+                        action_results.push(action_result.clone());
+
+                        let observation_text = format!("Observation: {}\n\n{}", action_result, message_content);
+
+                        let content = UserContent::MultiModal(vec![
+                            MultiModalContent::Text(observation_text),
+                            MultiModalContent::Image(new_screenshot.clone()),
+                        ]);
+
+                        self.chat_history.as_mut().unwrap().push(
+                            LLMMessage::User(UserMessage::new(
+                                content,
+                                self.name.clone()
+                            ))
+                        );
+
+                        if control == StepControl::FinishAfter {
+                            final_answer = Some(action_result);
+                            break 'steps;
                         }
                     }
                 }
@@ -256,25 +418,46 @@ impl Agent for WebAgent {
                 let (message_content, maybe_new_screenshot, metadata_hash) = self
                     .chrome_ctrl.as_ref().unwrap().describe_page(true).await?;
 
+                let page_unchanged = self.prior_metadata_hash.as_deref() == Some(metadata_hash.as_str());
                 self.prior_metadata_hash = Some(metadata_hash);
 
-                let message_content_final = format!("\n\n{}\n\n{}", all_responses, message_content);
+                let run_usage = *self.usage.get_mut().unwrap();
+                self.usage.get_mut().unwrap().add(&carried_usage);
+
+                if run_end.is_none() && final_answer.is_none() && failure.is_none() && !replied && steps_taken == limits.max_steps {
+                    run_end = Some(RunEnd::StepsExhausted(limits.max_steps));
+                }
+
+                // 有最终回答时直接交给 orchestrator，否则交出动作记录和当前页面
+                let mut message_content_final = match final_answer {
+                    Some(answer) => answer,
+                    None => format!("\n\n{}\n\n{}", all_responses, message_content),
+                };
+                if let Some(end) = run_end {
+                    message_content_final.push_str(&format!("\n\n{}", end.describe(&limits)));
+                }
 
                 let new_screenshot = maybe_new_screenshot.unwrap_or_else(Vec::new);
 
-                // 构造最终的响应消息
+                // 构造最终的响应消息：每个动作之后的截图在前，结束时的页面截图在最后
+                let mut content = vec![MultiModalContent::Text(message_content_final)];
+                content.extend(all_screenshots.into_iter().map(MultiModalContent::Image));
+                content.push(MultiModalContent::Image(new_screenshot));
                 let final_message = ChatMessage::MultiModal {
                     role: MessageRole::Assistant,
                     source: self.name.clone(),
-                    content: vec![
-                        MultiModalContent::Text(message_content_final),
-                        MultiModalContent::Image(new_screenshot),
-                    ],
-                    metadata: HashMap::new(),
+                    content,
+                    metadata: HashMap::from([
+                        (PAGE_UNCHANGED_KEY.to_string(), page_unchanged.to_string()),
+                        (USER_INTERRUPT_KEY.to_string(), interrupted.to_string()),
+                        (TOKEN_USAGE_KEY.to_string(), serde_json::to_string(&run_usage)?),
+                    ]),
                 };
 
-                
-                Ok(final_message)
+                match failure {
+                    Some(error) => Ok(final_message.with_error(error)),
+                    None => Ok(final_message),
+                }
             }
         
         }
@@ -289,14 +472,119 @@ impl WebAgent {
         Self::default()
     }
 
-    pub async fn initialize(&mut self) -> Result<()> {
-        self.chrome_ctrl = Some(Chrome::new().await?);
+    /// 没有会话文件时（CLI）upload_file 可以上传的本地目录，对应 WebAgentConfig.uploads_dir
+    pub fn set_uploads_dir(&mut self, dir: Option<PathBuf>) {
+        self.uploads_dir = dir;
+    }
+
+    pub fn uploads_dir(&self) -> Option<&Path> {
+        self.uploads_dir.as_deref()
+    }
+
+    pub fn set_action_guard(&mut self, guard: Option<Arc<dyn ActionGuard>>) {
+        self.action_guard = guard;
+    }
+
+    /// 回答问题和总结页面时，提示词中页面正文最多的 token 数，0 为不截断
+    pub fn set_page_max_tokens(&mut self, max_tokens: usize) {
+        self.page_max_tokens = max_tokens;
+    }
+
+    pub fn page_max_tokens(&self) -> usize {
+        self.page_max_tokens
+    }
+
+    /// sleep 工具最长等待的秒数，更长的请求被截断
+    pub fn set_max_sleep_secs(&mut self, max_secs: f64) {
+        self.max_sleep_secs = max_secs;
+    }
+
+    pub fn max_sleep_secs(&self) -> f64 {
+        self.max_sleep_secs
+    }
+
+    /// 一轮执行的步骤数和时间限制，对应 WebAgentConfig::step_limits
+    pub fn set_step_limits(&mut self, limits: StepLimits) {
+        self.limits = limits;
+    }
+
+    pub fn step_limits(&self) -> StepLimits {
+        self.limits
+    }
+
+    /// 对应 WebAgentConfig.include_raw_screenshot
+    pub fn set_include_raw_screenshot(&mut self, include: bool) {
+        self.include_raw_screenshot = include;
+    }
+
+    pub fn include_raw_screenshot(&self) -> bool {
+        self.include_raw_screenshot
+    }
+
+    /// 由 ModelRegistry 按角色取得客户端，未单独配置的角色使用 [llm] 的模型
+    pub fn set_models(&mut self, registry: &ModelRegistry) {
+        self.set_model_clients(registry.client(ModelRole::WebAgent), registry.client(ModelRole::Summarizer));
+    }
+
+    pub fn set_model_clients(&mut self, model: Arc<dyn ChatCompletionClient>, summarizer: Arc<dyn ChatCompletionClient>) {
+        self.model_client = model;
+        self.summarizer_client = summarizer;
+    }
+
+    fn record_usage(&self, usage: &TokenUsage) {
+        self.usage.lock().unwrap().add(usage);
+    }
+
+    // 把超时的说明追加到对话历史，下一步的模型可以看到
+    fn record_timeout(&mut self, kind: TimeoutKind, step: usize, waiting_for: &str) -> String {
+        let observation = self.limits.timeout_observation(kind, step, waiting_for);
+        self.chat_history.as_mut().unwrap().push(LLMMessage::User(UserMessage::new(
+            UserContent::String(format!("Observation: {}", observation)),
+            self.name.clone(),
+        )));
+        observation
+    }
+
+    fn emit_event(&self, event: AgentEvent) {
+        if let Some(sink) = &self.event_sink {
+            sink.emit(event);
+        }
+    }
+
+    // 取出步骤执行期间到达的用户消息并追加到对话历史，返回是否有新消息
+    fn poll_user_messages(&mut self) -> bool {
+        let new_messages = match self.mailbox.as_mut() {
+            Some(mailbox) => mailbox.take_new(),
+            None => return false,
+        };
+        let has_new = !new_messages.is_empty();
+        for message in new_messages {
+            let message = match as_new_user_message(message) {
+                ChatMessage::Text { source, content, .. } => UserMessage::new(UserContent::String(content), source),
+                ChatMessage::MultiModal { source, content, .. } => UserMessage::new(UserContent::MultiModal(content), source),
+            };
+            self.chat_history.as_mut().unwrap().push(LLMMessage::User(message));
+        }
+        has_new
+    }
+
+    /// 按 browser 启动浏览器；sites 中预先允许和屏蔽的网站决定之后哪些网址需要审批（见 WebAgentConfig::site_policy）
+    pub async fn initialize(&mut self, sites: &SitePolicy, browser: ChromeConfig) -> Result<()> {
+        let chrome = Chrome::with_config(browser).await?;
+        self.attach_browser(sites, Arc::new(chrome))
+    }
+
+    /// 使用已经启动的浏览器，例如后端从 BrowserPool 借出的实例，归还由借出方负责
+    pub fn attach_browser(&mut self, sites: &SitePolicy, chrome: Arc<Chrome>) -> Result<()> {
+        sites.validate()?;
+        self.url_status_manager = sites.to_url_status_manager();
+        self.chrome_ctrl = Some(chrome);
         self.chat_history = Some(Vec::new());
         Ok(())
     }
 
-    pub async fn chrome_mut(&mut self) -> Result<&mut Chrome> {
-        self.chrome_ctrl.as_mut()
+    pub fn chrome(&self) -> Result<&Chrome> {
+        self.chrome_ctrl.as_deref()
             .ok_or_else(|| anyhow!("Chrome context is not initialized. Call initialize() first."))
     }
 
@@ -304,7 +592,7 @@ impl WebAgent {
     pub async fn get_llm_response(
         &self,
     ) -> Result<(
-        Vec<LLMResponse>,
+        Vec<LLMOutput>,
         HashMap<String,InteractiveRegion>,
         Vec<ToolSchema>,
         HashMap<String,String>,
@@ -318,7 +606,7 @@ impl WebAgent {
         let date_today = Utc::now().format("%Y-%m-%d").to_string();
         let mut history = self.chat_history.as_ref().unwrap().clone();
 
-        let system_content = WEB_SURFER_SYSTEM_MESSAGE.replace("{date_today}", &date_today);
+        let system_content = render_template(WEB_SURFER_SYSTEM_MESSAGE, &[("date_today", &date_today)]);
         history.push(LLMMessage::System(
             SystemMessage::new(system_content)
         ));
@@ -347,7 +635,10 @@ impl WebAgent {
             .collect();
 
         let (num_tabs, tab_info) = self.get_tabs_info().await?;
-        let tabs_info_str = format!("There are {} tabs open. The tabs are as follows:\n{}", num_tabs, tab_info);
+        let tabs_info_str = format!(
+            "There are {} tabs open. Your actions apply to the tab marked [CONTROLLED]. The tabs are as follows:\n{}",
+            num_tabs, tab_info
+        );
         // 4. 准备工具和上下文信息
         let mut tools = Vec::new();
 
@@ -358,7 +649,8 @@ impl WebAgent {
             &default_tools.web_search,
             &default_tools.click,
             &default_tools.input_text,
-            // &default_tools.answer_question,
+            &default_tools.answer_question,
+            &default_tools.summarize_page,
             &default_tools.sleep,
             &default_tools.hover,
             &default_tools.history_back,
@@ -453,7 +745,7 @@ impl WebAgent {
             String::new()
         };
 
-        let webpage_text = self.chrome_ctrl.as_ref().unwrap().get_visible_text().await?;
+        let webpage_text = self.chrome_ctrl.as_ref().unwrap().get_visible_text(DEFAULT_MAX_VISIBLE_TEXT_CHARS).await?;
         let url = self.chrome_ctrl.as_ref().unwrap().get_url().await?;
         
         let last_outside_message = "".to_string();
@@ -496,34 +788,39 @@ impl WebAgent {
         )?;
         
         
-        // 6.2 添加用户消息（文本提示 + 两张图片）
+        // 6.2 添加用户消息（文本提示 + 标注了编号的截图，设置了 include_raw_screenshot 时再加原始截图）
+        // 模型不支持图片时由客户端（request_messages）跳过图片
+        let mut content = vec![
+            MultiModalContent::Text(text_prompt),
+            MultiModalContent::Image(som_bytes),
+        ];
+        if self.include_raw_screenshot {
+            content.push(MultiModalContent::Image(screenshot_bytes));
+        }
         history.push(LLMMessage::User(UserMessage::new(
-            UserContent::MultiModal(vec![
-                MultiModalContent::Text(text_prompt),
-                // MultiModalContent::Image(screenshot_bytes),
-                // MultiModalContent::Image(som_bytes),
-            ]), 
+            UserContent::MultiModal(content),
             self.name.clone(),
         )));
 
         // println!("history: {:?}", history);
 
         // 7. 获取模型响应
-        let llm_responses = call_llm(&history, &tools).await?;
-        
+        let outputs = self.request_actions(&history, &tools).await?;
+
         // 8. 解析响应，判断是否需要执行工具
-        let need_execute_tool = llm_responses.iter().any(|resp| {
-            matches!(resp, LLMResponse::FunctionCalls(_))
+        let need_execute_tool = outputs.iter().any(|output| {
+            matches!(output, LLMOutput::FunctionCalls(_))
         });
-        
-        // 检查是否有错误
-        for resp in &llm_responses {
-            if let LLMResponse::Error(err) = resp {
-                return Err(anyhow!("LLM Error: {}", err));
-            }
-        }
 
-        Ok((llm_responses, rects, tools, page_state.element_id_mapping, need_execute_tool))
+        Ok((outputs, rects, tools, page_state.element_id_mapping, need_execute_tool))
+    }
+
+    // 调用一次选择动作的模型，文字在前、函数调用在后
+    async fn request_actions(&self, history: &[LLMMessage], tools: &[ToolSchema]) -> Result<Vec<LLMOutput>> {
+        let tools: Vec<ToolSpec> = tools.iter().map(ToolSpec::from).collect();
+        let reply = LLMReply::from(self.model_client.create_with_tools(history, &tools).await?);
+        self.record_usage(&reply.usage);
+        Ok(reply.outputs)
     }
 
     async fn get_page_state_and_elements(&self) -> Result<(PageState, HashMap<String, InteractiveRegion>)> {
@@ -560,9 +857,9 @@ impl WebAgent {
                 let domain = {
                     // 使用临时 extractor（或可缓存到 WebAgent）
                     let extractor = TldExtractor::new(TldOption::default());
-                    let extracted = extractor.extract(&url).unwrap_or_else(|_| {
+                    let extracted = extractor.extract(&url).unwrap_or(
                         tldextract::TldResult { domain: None, subdomain: None, suffix: None }
-                    });
+                    );
                     match (&extracted.domain, &extracted.suffix) {
                         (Some(domain), Some(suffix)) => format!("{}.{}", domain, suffix),
                         (Some(domain), None) => domain.clone(),
@@ -571,28 +868,17 @@ impl WebAgent {
                 };
                 let domain = if domain.is_empty() { url.clone() } else { domain };
 
-                /*
-                let approved = if let Some(guard) = &self.action_guard {
-                    let request_msg = ChatMessage::new_text(
-                        MessageRole::User,
-                        self.name.clone(),
-                        format!(
-                            "The website {} is not allowed. Would you like to allow the domain {} for this session?",
-                            url, domain
-                        ),
-                    );
-                    guard.get_approval(request_msg).await
-                } else {
-                    false
-                };
-
+                // 批准或拒绝都记录在 url_status_manager 中，本次会话不再重复询问
+                let approved = gate_domain(
+                    self.action_guard.as_deref(),
+                    &mut self.url_status_manager,
+                    &self.name,
+                    &url,
+                    &domain,
+                ).await;
                 if approved {
-                    self.url_status_manager.set_url_status(&domain, UrlStatus::Allowed);
                     return Ok(("".to_string(), true));
-                } else {
-                    self.url_status_manager.set_url_status(&domain, UrlStatus::Rejected);
                 }
-                */
             }
 
             // 记录最后被拒绝的 URL
@@ -725,13 +1011,13 @@ impl WebAgent {
         // 3. 从 function call 中获取参数(工具的名称[name] 和 参数[arguments])
         let function_call = &messages[0];
         let name = &function_call.name;
-        let args = serde_json::from_str(&function_call.arguments)
+        let args: serde_json::Value = serde_json::from_str(&function_call.arguments)
             .map_err(|e| anyhow::anyhow!("Failed to parse function arguments: {}", e))?;
 
         // 4. 记录工具调用
         let tool_call_msg = format!("{}({})", name, serde_json::to_string(&args)?);
         
-        println!("🔧 工具调用: {}", tool_call_msg);
+        tracing::info!("Tool call: {}", tool_call_msg);
 
         // 5. 验证工具是否存在
         let available_tools: Vec<String> = tools.iter()
@@ -747,15 +1033,37 @@ impl WebAgent {
             ));
         }
 
-        // 6. 根据工具名称执行对应的工具函数
+        // 6. 无法撤销的动作（按回车提交、点击购买/删除等）先经过 action_guard 审批，
+        // 审批请求中说明目标控件、当前页面和要输入的文字
+        let target_name = ["target_id", "input_field_id"]
+            .iter()
+            .find_map(|key| args.get(*key))
+            .map(|id| match id {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            })
+            .and_then(|id| element_id_mapping.get(&id))
+            .and_then(|mapping_id| self.target_name(mapping_id, &rects));
+        if is_irreversible_action(name, &args, target_name.as_deref()) {
+            let page_url = match &self.chrome_ctrl {
+                Some(chrome) => chrome.get_url().await.ok(),
+                None => None,
+            };
+            let description = describe_action(name, &args, target_name.as_deref(), page_url.as_deref());
+            if !gate_action(self.action_guard.as_deref(), &self.name, &description).await {
+                return Ok(format!("The user declined the action {}, so I did not perform it.", tool_call_msg));
+            }
+        }
+
+        // 7. 根据工具名称执行对应的工具函数
         let action_description = match name.as_str() {
             "click" => self.execute_tool_click(args, &rects, &element_id_mapping).await?,
             "input_text" => self.execute_tool_input_text(args, &rects, &element_id_mapping).await?,
             "hover" => self.execute_tool_hover(args, &rects, &element_id_mapping).await?,
             "select_option" => self.execute_tool_select_option().await?,    // TODO
-            "upload_file" => self.execute_tool_upload_file().await?,        // TODO
+            "upload_file" => self.execute_tool_upload_file(args, &rects, &element_id_mapping).await?,
             "click_full" => self.execute_tool_click_full(args, &rects, &element_id_mapping).await?,
-            "answer_question" => self.execute_tool_answer_question().await?,    // TODO
+            "answer_question" => self.execute_tool_answer_question(args).await?,
             "visit_url" => self.execute_tool_visit_url(args).await?,
             "web_search" => self.execute_tool_web_search(args).await?,
             "history_back" => self.execute_tool_history_back().await?,
@@ -766,7 +1074,7 @@ impl WebAgent {
             "scroll_up" => self.execute_tool_scroll_up(args).await?,
             "sleep" => self.execute_tool_sleep(args).await?,
             "stop_action" => self.execute_tool_stop_action(args).await?,
-            "summarize_page" => self.execute_tool_summarize_page().await?,  // TODO
+            "summarize_page" => self.execute_tool_summarize_page().await?,
             "create_tab" => self.execute_tool_create_tab(args).await?,
             "switch_tab" => self.execute_tool_switch_tab(args).await?,
             "close_tab" => self.execute_tool_close_tab(args).await?,
@@ -775,9 +1083,10 @@ impl WebAgent {
             }
         };
 
-        // 7. TODO: 清理动画（如果实现了动画功能）
+        // 8. TODO: 清理动画（如果实现了动画功能）
         // self.chrome_ctrl.as_ref().unwrap().cleanup_animations().await?;
 
+        tracing::debug!("Tool result of {}: {}", name, action_description);
         Ok(action_description)
     }
 
@@ -786,7 +1095,7 @@ impl WebAgent {
         let ans = args
             .get("answer")
             .and_then(|v|v.as_str())
-            .unwrap_or("I stopped the action.");
+            .unwrap_or(DEFAULT_STOP_ANSWER);
         Ok(ans.to_string())
     }
 
@@ -810,7 +1119,7 @@ impl WebAgent {
 
         let action_description = format!("I type '{}' into the browser address bar.", url);
 
-        let reset_prior_metadata = 
+        let outcome =
             if url.starts_with("https://") 
                 || url.starts_with("http://") 
                 || url.starts_with("file://") 
@@ -830,22 +1139,28 @@ impl WebAgent {
                 self.chrome_ctrl.as_ref().unwrap().visit_page(&full_url).await?
             };
 
-        // 4. 更新状态
-        if reset_prior_metadata {
-            self.prior_metadata_hash = None;
+        // 4. 更新状态；导航触发了下载时报告下载的文件，当前页面没有变化
+        match outcome {
+            VisitOutcome::Page => {
+                self.prior_metadata_hash = None;
+                Ok(action_description)
+            }
+            VisitOutcome::Download(file) => Ok(format!(
+                "I downloaded the file '{}' to {}.",
+                file.file_name,
+                file.path.display()
+            )),
         }
-
-        Ok(action_description)
     }
 
     async fn execute_tool_history_back(&self) -> Result<String> {
         self.chrome_ctrl.as_ref().unwrap().wait_for_page_ready().await?;
         match self.chrome_ctrl.as_ref().unwrap().go_back().await {
             Ok(()) => {
-                return Ok("I clicked the browser back button.".to_string())
+                Ok("I clicked the browser back button.".to_string())
             }
             Err(_) => {
-                return Ok("No previous page in the browser history or couldn't navigate back.".to_string())
+                Ok("No previous page in the browser history or couldn't navigate back.".to_string())
             }
         }
     }
@@ -876,9 +1191,9 @@ impl WebAgent {
         let chrome = self.chrome_ctrl.as_ref().ok_or_else(|| anyhow!("Chrome controller not initialized"))?;
         chrome.wait_for_page_ready().await?;
 
-        let reset_prior_metadata = chrome.visit_page(&search_url).await?;
+        let outcome = chrome.visit_page(&search_url).await?;
 
-        if reset_prior_metadata {
+        if outcome.resets_metadata() {
             self.prior_metadata_hash = None;
         }
 
@@ -942,7 +1257,7 @@ impl WebAgent {
             "I clicked the control.".to_string()
         };
 
-        let chrome_ctrl = self.chrome_ctrl.as_mut()
+        let chrome_ctrl = self.chrome_ctrl.as_ref()
             .ok_or_else(|| anyhow!("Chrome controller not initialized"))?;
 
         // 新旧页面判断
@@ -983,12 +1298,13 @@ impl WebAgent {
             .get(&target_id_str)
             .ok_or_else(|| anyhow!("Target ID '{}' not found in mapping", target_id_str))?;
         
-        let target_name = self.target_name(mapping_id, &rects);
+        let target_name = self.target_name(mapping_id, rects);
 
         let button = args
             .get("button")
             .and_then(|v| v.as_str())
             .unwrap_or("left");
+        let hold = hold_seconds(&args)?;
 
         let action_description = if let Some(name) = target_name {
             format!(
@@ -1001,12 +1317,17 @@ impl WebAgent {
                 button
             )
         };
+        let action_description = if hold > 0.0 {
+            format!("{}. I held the button down for {:.1} seconds.", action_description.trim_end_matches('.'), hold)
+        } else {
+            action_description
+        };
 
-        let chrome_ctrl = self.chrome_ctrl.as_mut()
+        let chrome_ctrl = self.chrome_ctrl.as_ref()
             .ok_or_else(|| anyhow!("Chrome controller not initialized"))?;
 
         let new_page = chrome_ctrl
-            .click_id(mapping_id, 0.0, button)
+            .click_id(mapping_id, hold, button)
             .await?;
 
         if new_page {
@@ -1062,25 +1383,29 @@ impl WebAgent {
         };
 
         self.chrome_ctrl
-            .as_mut()
+            .as_ref()
             .ok_or_else(|| anyhow!("Chrome controller not initialized"))?
             .fill_id(mapping_id, text_value, press_enter, delete_existing_text)
             .await?;
         Ok(action_description)
     }
 
+    // 回答关于当前页面的问题，回答作为动作描述返回，之后进入观察和最终的消息
     async fn execute_tool_answer_question(
         &self,
+        args: serde_json::Value,
     ) -> Result<String> {
-        // TODO
-        Ok("Answer question action executed".to_string())
+        let question = args
+            .get("question")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("Missing required argument 'question'"))?;
+        self.ask_about_page(Some(question)).await
     }
 
     async fn execute_tool_summarize_page(
-        &mut self,
-    ) -> Result<String> { 
-        // TODO
-        Ok("Summarize page action executed".to_string())
+        &self,
+    ) -> Result<String> {
+        self.summarize_page().await
     }
 
     async fn execute_tool_hover(
@@ -1109,11 +1434,11 @@ impl WebAgent {
         let action_description = if let Some(name) = target_name {
             format!("I hovered over '{}'.", name)
         } else {
-            format!("I hovered over the control.")
+            "I hovered over the control.".to_string()
         };
 
         self.chrome_ctrl
-            .as_mut()
+            .as_ref()
             .ok_or_else(|| anyhow!("Chrome controller not initialized"))?
             .hover_id(mapping_id)
             .await?;
@@ -1123,9 +1448,10 @@ impl WebAgent {
 
 
     async fn execute_tool_sleep(&mut self, args: serde_json::Value) -> Result<String> {
-        let duration = args.get("duration").and_then(|v|v.as_i64()).unwrap_or(1000) as u64;
-        self.chrome_ctrl.as_mut().unwrap().sleep(duration).await?;
-        Ok(format!("I waited {} seconds.", duration))
+        let seconds = sleep_seconds(&args, self.max_sleep_secs)?;
+        let duration = std::time::Duration::from_secs_f64(seconds);
+        self.chrome_ctrl.as_ref().unwrap().sleep(duration.as_millis() as u64).await?;
+        Ok(format!("I waited {} seconds.", seconds))
     }
 
     async fn execute_tool_select_option(
@@ -1148,7 +1474,7 @@ impl WebAgent {
         }
 
         let action_description = format!("I created a new tab and navigated to '{}'.", url);
-        let _ = self.chrome_ctrl.as_ref().ok_or_else(|| anyhow!("Chrome controller not initialized"))?.new_tab(url).await?;
+        let _ = self.chrome_ctrl.as_ref().ok_or_else(|| anyhow!("Chrome controller not initialized"))?.new_tab(url, true).await?;
 
         self.prior_metadata_hash = None;
         Ok(action_description)
@@ -1185,11 +1511,45 @@ impl WebAgent {
         Ok(action_description)
     }
 
+    /* 后端的运行只能上传会话文件（session://<id>），交给文件输入框的是运行前下载的本地副本；
+    CLI 只能上传 uploads_dir 中的文件。目标不是文件输入框时返回错误 */
     async fn execute_tool_upload_file(
-        &self,
+        &mut self,
+        args: serde_json::Value,
+        rects: &HashMap<String, InteractiveRegion>,
+        element_id_mapping: &HashMap<String, String>,
     ) -> Result<String> {
-        // TODO: 实现文件上传功能
-        Ok("Upload file action executed".to_string())
+        let target_id = match args.get("target_id") {
+            Some(Value::String(id)) => id.clone(),
+            Some(Value::Number(id)) => id.to_string(),
+            _ => return Err(anyhow!("'target_id' is required")),
+        };
+        let file_path = args
+            .get("file_path")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("'file_path' is required"))?;
+        let local_path = resolve_upload_path(self.session_files.as_ref(), self.uploads_dir.as_deref(), file_path)?;
+        let file_name = local_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| file_path.to_string());
+
+        let target_name = self.target_name(&target_id, rects);
+        let mapping_id = element_id_mapping
+            .get(&target_id)
+            .ok_or_else(|| anyhow!("Target ID '{}' not found in mapping", target_id))?;
+
+        self.chrome_ctrl
+            .as_ref()
+            .ok_or_else(|| anyhow!("Chrome controller not initialized"))?
+            .upload_file(mapping_id, &local_path)
+            .await?;
+
+        self.prior_metadata_hash = None;
+        Ok(match target_name {
+            Some(name) => format!("I uploaded '{}' to '{}'.", file_name, name),
+            None => format!("I uploaded '{}'.", file_name),
+        })
     }
 
     fn target_name(&self, target: &str, rects: &HashMap<String, InteractiveRegion>) -> Option<String> {
@@ -1200,12 +1560,62 @@ impl WebAgent {
             .filter(|name| !name.is_empty())
     }
 
-    // 总结当前的页面
+    // 用一到两段话总结当前的页面
     pub async fn summarize_page(
-        &mut self, 
+        &self,
     ) -> Result<String> {
-        // TODO
-        Ok("".to_string())
+        self.ask_about_page(None).await
+    }
+
+    // 页面问答的提示词：页面标题、视口中的文字和正文，正文超过 page_max_tokens 时截断
+    fn page_question_prompt(&self, title: &str, question: Option<&str>, visible_text: &str, page_markdown: &str) -> Result<String> {
+        Ok(format!(
+            "{}Text in the current viewport:\n{}\n\nFull page content:\n{}",
+            Self::web_surfer_qa_prompt(title, question),
+            visible_text.trim(),
+            limit_tokens(page_markdown, self.page_max_tokens)?,
+        ))
+    }
+
+    /* 根据当前页面单独调用一次 LLM：question 为 None 时总结页面，否则回答问题。
+    提示词是页面标题、视口中的文字和正文（Markdown，按 page_max_tokens 截断），并附上当前截图 */
+    async fn ask_about_page(&self, question: Option<&str>) -> Result<String> {
+        let chrome = self.chrome_ctrl
+            .as_ref()
+            .ok_or_else(|| anyhow!("Chrome controller not initialized"))?;
+
+        let title = chrome.get_title().await?;
+        let visible_text = chrome.get_visible_text(DEFAULT_MAX_VISIBLE_TEXT_CHARS).await?;
+        // 正文在 page_question_prompt 中统一按 page_max_tokens 截断
+        let page_markdown = chrome.get_page_markdown(0).await?;
+        let prompt = self.page_question_prompt(&title, question, &visible_text, &page_markdown)?;
+
+        let mut content = vec![MultiModalContent::Text(prompt)];
+        // 截图失败时只根据页面文字回答
+        match chrome.get_screenshot(None).await {
+            Ok(screenshot) => content.push(MultiModalContent::Image(screenshot)),
+            Err(e) => tracing::warn!("Screenshot failed, answering from the page text only: {:#}", e),
+        }
+        let messages = vec![LLMMessage::User(UserMessage::new(
+            UserContent::MultiModal(content),
+            self.name.clone(),
+        ))];
+
+        let reply = LLMReply::from(self.summarizer_client.create(&messages).await?);
+        self.record_usage(&reply.usage);
+        let answer: Vec<String> = reply
+            .outputs
+            .into_iter()
+            .filter_map(|output| match output {
+                LLMOutput::Text(text) => Some(text),
+                LLMOutput::FunctionCalls(_) => None,
+            })
+            .collect();
+        let answer = answer.join("\n").trim().to_string();
+        if answer.is_empty() {
+            return Err(anyhow!("The model returned no text about the page '{}'", title));
+        }
+        Ok(answer)
     }
 
     fn web_surfer_qa_prompt(title: &str, question: Option<&str>) -> String {
@@ -1225,9 +1635,7 @@ impl WebAgent {
 #[cfg(test)]
 mod tests {
     use super::*;
-    
-    /// 测试基本的 LLM 响应
-    
+
     /// 测试 Google 搜索 "grok"
     /// 运行方式：cargo test test_google_search_grok -- --ignored --nocapture
     #[tokio::test]
@@ -1238,14 +1646,14 @@ mod tests {
 
         // 1. 创建并初始化 WebAgent
         let mut agent = WebAgent::new().await;
-        agent.initialize().await?;
+        agent.initialize(&SitePolicy::default(), ChromeConfig::default()).await?;
         
         println!("✅ WebAgent 初始化成功");
         
         // 2. 访问 Google 首页（在单独的作用域中完成，避免借用冲突）
         {
             println!("\n📍 正在访问 Google...");
-            let chrome = agent.chrome_mut().await?;
+            let chrome = agent.chrome()?;
             chrome.visit_page("https://www.google.com").await?;
             chrome.sleep(2000).await?;
             println!("✅ 已访问 Google");
@@ -1270,10 +1678,10 @@ mod tests {
         for (idx, response) in responses.iter().enumerate() {
             println!("\n响应 [{}]:", idx + 1);
             match response {
-                LLMResponse::Text(text) => {
+                LLMOutput::Text(text) => {
                     println!("💬 文本响应：\n{}", text);
                 }
-                LLMResponse::FunctionCalls(calls) => {
+                LLMOutput::FunctionCalls(calls) => {
                     println!("🔧 工具调用（共 {} 个）：", calls.len());
                     for (i, call) in calls.iter().enumerate() {
                         println!("\n  [{}] 工具名称: {}", i + 1, call.name);
@@ -1281,9 +1689,6 @@ mod tests {
                         println!("      参数: {}", call.arguments);
                     }
                 }
-                LLMResponse::Error(err) => {
-                    println!("❌ 错误: {}", err);
-                }
             }
         }
         
@@ -1294,7 +1699,7 @@ mod tests {
         
         // 6. 如果需要执行工具，展示第一个工具的详细信息
         if need_execute_tool {
-            if let Some(LLMResponse::FunctionCalls(calls)) = responses.first() {
+            if let Some(LLMOutput::FunctionCalls(calls)) = responses.first() {
                 if let Some(first_call) = calls.first() {
                     println!("\n{}", "=".repeat(60));
                     println!("🎯 第一个工具调用详情");
@@ -1318,7 +1723,7 @@ mod tests {
         
         // 7. 等待一下再关闭浏览器，方便查看
         {
-            let chrome = agent.chrome_mut().await?;
+            let chrome = agent.chrome()?;
             chrome.sleep(3000).await?;
         }
         
@@ -1334,7 +1739,7 @@ mod tests {
 
         // 1. 创建并初始化 WebAgent
         let mut agent = WebAgent::new().await;
-        agent.initialize().await?;
+        agent.initialize(&SitePolicy::default(), ChromeConfig::default()).await?;
         
         println!("✅ WebAgent 初始化成功");
         
@@ -1363,5 +1768,133 @@ mod tests {
         
         Ok(())
     }
-}
 
+    #[test]
+    fn test_page_prompt_respects_page_token_budget() -> Result<()> {
+        let mut agent = WebAgent::default();
+        agent.set_page_max_tokens(50);
+        let page = "The opening hours are listed below. ".repeat(200);
+        let prompt = agent.page_question_prompt("Menu", Some("When does it open?"), "  Opening hours  ", &page)?;
+
+        let (head, body) = prompt.split_once("Full page content:\n").expect("page content section");
+        assert!(head.contains("'Menu'") && head.contains("When does it open?"));
+        assert!(head.contains("Text in the current viewport:\nOpening hours\n"));
+        // 正文只保留前 50 个 token
+        assert!(body.len() < page.len() && page.starts_with(body));
+        assert_eq!(limit_tokens(body, 50)?, body);
+
+        // 0 表示不限制
+        agent.set_page_max_tokens(0);
+        let prompt = agent.page_question_prompt("Menu", None, "", &page)?;
+        assert!(prompt.ends_with(&page));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stop_action_reply_is_one_model_call_and_the_answer() -> Result<()> {
+        use crate::testing::MockProvider;
+
+        let provider = Arc::new(MockProvider::new().respond_tool_call("stop_action", json!({ "answer": "The menu lists soup." })));
+        let mut agent = WebAgent::default();
+        agent.set_model_clients(provider.clone(), provider.clone());
+        let tools = DefaultTools::new().map_err(|e| anyhow!("{}", e))?;
+
+        let history = vec![LLMMessage::User(UserMessage::new(UserContent::String("What is on the menu?".to_string()), "user".to_string()))];
+        let outputs = agent.request_actions(&history, &[tools.click, tools.stop_action]).await?;
+
+        assert_eq!(provider.requests().len(), 1);
+        assert_eq!(provider.offered_tools(), vec![vec!["click".to_string(), "stop_action".to_string()]]);
+        let plan = plan_response(&outputs);
+        assert_eq!(plan.calls.len(), 1);
+        let arguments: Value = serde_json::from_str(&plan.calls[0].arguments)?;
+        assert_eq!(
+            step_control(&plan.calls[0].name, &arguments),
+            StepControl::Stop { answer: "The menu lists soup.".to_string() }
+        );
+        Ok(())
+    }
+
+    /// 第一步就返回 stop_action 时只调用一次模型，回答就是最终消息
+    /// 运行方式：cargo test test_stop_action_ends_the_run -- --ignored
+    #[tokio::test]
+    #[ignore] // 需要浏览器，使用 cargo test -- --ignored 运行
+    async fn test_stop_action_ends_the_run() -> Result<()> {
+        use crate::testing::MockProvider;
+
+        let provider = Arc::new(MockProvider::new().respond_tool_call("stop_action", json!({ "answer": "The menu lists soup." })));
+        let mut agent = WebAgent::new().await;
+        agent.initialize(&SitePolicy::default(), ChromeConfig::default()).await?;
+        agent.set_model_clients(provider.clone(), provider.clone());
+
+        let reply = agent.on_message_stream(Message {
+            from: "User".to_string(),
+            to: "WebAgent".to_string(),
+            chat_history: vec![ChatMessage::new_text(MessageRole::User, "User".to_string(), "What is on the menu?".to_string())],
+            msg_type: MessageType::Execute,
+        }).await?;
+
+        assert_eq!(provider.requests().len(), 1);
+        match reply {
+            ChatMessage::MultiModal { content, .. } => {
+                assert!(matches!(&content[0], MultiModalContent::Text(text) if text == "The menu lists soup."));
+            }
+            ChatMessage::Text { .. } => panic!("expected a multimodal reply"),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_registry_model_client_retries_rate_limits() -> Result<()> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use axum::http::{header, StatusCode};
+        use axum::response::IntoResponse;
+        use axum::routing::post;
+        use crate::config::AppConfig;
+
+        // 第一次返回 429，之后返回一个 stop_action 调用
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let app = axum::Router::new().route(
+            "/chat/completions",
+            post(move || {
+                let first = counter.fetch_add(1, Ordering::SeqCst) == 0;
+                async move {
+                    if first {
+                        return (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, "0")], "slow down").into_response();
+                    }
+                    axum::Json(json!({
+                        "choices": [{
+                            "message": {
+                                "role": "assistant",
+                                "content": null,
+                                "tool_calls": [{
+                                    "id": "call_1",
+                                    "type": "function",
+                                    "function": { "name": "stop_action", "arguments": "{\"answer\": \"Done.\"}" }
+                                }]
+                            },
+                            "finish_reason": "tool_calls"
+                        }],
+                        "usage": { "prompt_tokens": 5, "completion_tokens": 1 }
+                    }))
+                    .into_response()
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base_url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut config = AppConfig::default();
+        config.llm.base_url = Some(base_url);
+        let mut agent = WebAgent::default();
+        agent.set_models(&ModelRegistry::from_config(&config));
+        let tools = DefaultTools::new().map_err(|e| anyhow!("{}", e))?;
+
+        let history = vec![LLMMessage::User(UserMessage::new(UserContent::String("Stop".to_string()), "user".to_string()))];
+        let outputs = agent.request_actions(&history, &[tools.stop_action]).await?;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(plan_response(&outputs).calls[0].name, "stop_action");
+        Ok(())
+    }
+}
@@ -1,5 +1,10 @@
+use std::time::Duration;
+
 use serde::{Serialize, Deserialize};
 
+use crate::agents::web_agent::limits::{StepLimits, StepTimeoutPolicy, DEFAULT_MAX_STEPS};
+use crate::tools::url_status_manager::SitePolicy;
+
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WebAgentConfig {
@@ -8,6 +13,8 @@ pub struct WebAgentConfig {
     // browser: ComponentModel | Dict[str, Any]
     pub model_context_token_limit: Option<usize>,
     pub downloads_folder: Option<String>,
+    // upload_file 只能上传这个目录中的文件，未设置时不能上传本地文件
+    pub uploads_dir: Option<String>,
     pub description: Option<String>,
     pub debug_dir: Option<String>,
     pub start_page: Option<String>,
@@ -16,11 +23,50 @@ pub struct WebAgentConfig {
     pub max_actions_per_step: usize,
     pub to_resize_viewport: bool,
     // pub url_statuses: Option<HashMap<String, UrlStatus>>,
-    pub url_block_list: Option<Vec<String>>,
+    // 预先允许和屏蔽的网站，见 SitePolicy
+    pub allowed_websites: Option<Vec<String>>,
+    pub blocked_websites: Option<Vec<String>>,
     pub single_tab_mode: bool,
     pub json_model_output: bool,
     pub multiple_tools_per_call: bool,
     pub viewport_height: usize,
     pub viewport_width: usize,
     pub use_action_guard: bool,
-}
\ No newline at end of file
+    // 一轮执行最多的步骤数
+    #[serde(default = "default_max_steps")]
+    pub max_steps: usize,
+    // 一个步骤（调用模型和执行工具）最长的秒数，None 时不限制
+    #[serde(default)]
+    pub per_step_timeout_secs: Option<u64>,
+    // 整轮执行最长的秒数，None 时不限制
+    #[serde(default)]
+    pub overall_deadline_secs: Option<u64>,
+    // 步骤超时之后继续下一步还是结束本轮
+    #[serde(default)]
+    pub on_step_timeout: StepTimeoutPolicy,
+    // 除了标注了编号的截图，再附上原始截图
+    #[serde(default)]
+    pub include_raw_screenshot: bool,
+}
+
+fn default_max_steps() -> usize {
+    DEFAULT_MAX_STEPS
+}
+
+impl WebAgentConfig {
+    pub fn site_policy(&self) -> SitePolicy {
+        SitePolicy {
+            allowed_sites: self.allowed_websites.clone().unwrap_or_default(),
+            blocked_sites: self.blocked_websites.clone().unwrap_or_default(),
+        }
+    }
+
+    pub fn step_limits(&self) -> StepLimits {
+        StepLimits {
+            max_steps: self.max_steps,
+            per_step_timeout: self.per_step_timeout_secs.map(Duration::from_secs),
+            overall_deadline: self.overall_deadline_secs.map(Duration::from_secs),
+            on_step_timeout: self.on_step_timeout,
+        }
+    }
+}
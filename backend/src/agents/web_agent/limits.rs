@@ -0,0 +1,196 @@
+use std::future::Future;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+/// 一轮执行默认最多的步骤数
+pub const DEFAULT_MAX_STEPS: usize = 10;
+
+/// 单个步骤超时之后的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepTimeoutPolicy {
+    /// 记录超时的观察，继续下一步
+    #[default]
+    Continue,
+    /// 结束本轮
+    Abort,
+}
+
+/// WebAgent 一轮执行的限制，见 WebAgentConfig::step_limits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepLimits {
+    pub max_steps: usize,
+    // 一个步骤（调用模型和执行工具）最长的时间，None 时不限制
+    pub per_step_timeout: Option<Duration>,
+    // 整轮执行的最长时间，None 时不限制
+    pub overall_deadline: Option<Duration>,
+    pub on_step_timeout: StepTimeoutPolicy,
+}
+
+impl Default for StepLimits {
+    fn default() -> Self {
+        Self {
+            max_steps: DEFAULT_MAX_STEPS,
+            per_step_timeout: None,
+            overall_deadline: None,
+            on_step_timeout: StepTimeoutPolicy::Continue,
+        }
+    }
+}
+
+/// 触发的是哪一个时间限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutKind {
+    Step,
+    Deadline,
+}
+
+/// 一个步骤的截止时间
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepDeadline {
+    pub at: Instant,
+    pub kind: TimeoutKind,
+}
+
+/// 一轮执行没有正常结束的原因，写进最终的消息
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunEnd {
+    /// 用完了 max_steps 个步骤
+    StepsExhausted(usize),
+    /// 步骤超时并且策略为 Abort
+    StepTimedOut,
+    /// 超过了整体的截止时间
+    DeadlineExceeded,
+}
+
+impl RunEnd {
+    pub fn describe(&self, limits: &StepLimits) -> String {
+        match self {
+            RunEnd::StepsExhausted(steps) => {
+                format!("The run ended because it used all {} steps before the task was finished.", steps)
+            }
+            RunEnd::StepTimedOut => format!(
+                "The run ended because a step took longer than {} seconds.",
+                limits.per_step_timeout.unwrap_or_default().as_secs_f64()
+            ),
+            RunEnd::DeadlineExceeded => format!(
+                "The run ended because it reached its deadline of {} seconds.",
+                limits.overall_deadline.unwrap_or_default().as_secs_f64()
+            ),
+        }
+    }
+}
+
+impl StepLimits {
+    /* 从 step_started 开始的步骤的截止时间：per_step_timeout 和整体截止时间中较早的一个，
+    都没有设置时为 None */
+    pub fn step_deadline(&self, run_started: Instant, step_started: Instant) -> Option<StepDeadline> {
+        let step = self.per_step_timeout.map(|timeout| StepDeadline { at: step_started + timeout, kind: TimeoutKind::Step });
+        let overall = self
+            .overall_deadline
+            .map(|deadline| StepDeadline { at: run_started + deadline, kind: TimeoutKind::Deadline });
+        match (step, overall) {
+            (Some(step), Some(overall)) if overall.at <= step.at => Some(overall),
+            (Some(step), _) => Some(step),
+            (None, overall) => overall,
+        }
+    }
+
+    /// 超时之后是否结束本轮：整体截止时间一定结束，步骤超时按策略
+    pub fn run_end(&self, kind: TimeoutKind) -> Option<RunEnd> {
+        match (kind, self.on_step_timeout) {
+            (TimeoutKind::Deadline, _) => Some(RunEnd::DeadlineExceeded),
+            (TimeoutKind::Step, StepTimeoutPolicy::Abort) => Some(RunEnd::StepTimedOut),
+            (TimeoutKind::Step, StepTimeoutPolicy::Continue) => None,
+        }
+    }
+
+    /// 追加到对话中的超时说明，让模型知道上一步没有完成
+    pub fn timeout_observation(&self, kind: TimeoutKind, step: usize, waiting_for: &str) -> String {
+        match kind {
+            TimeoutKind::Step => format!(
+                "Step {} timed out after {} seconds while {}. The action may not have completed.",
+                step + 1,
+                self.per_step_timeout.unwrap_or_default().as_secs_f64(),
+                waiting_for
+            ),
+            TimeoutKind::Deadline => format!(
+                "The run reached its deadline of {} seconds while {}.",
+                self.overall_deadline.unwrap_or_default().as_secs_f64(),
+                waiting_for
+            ),
+        }
+    }
+}
+
+/// 在截止时间之前完成 future，没有截止时间时一直等待
+pub async fn within<F: Future>(deadline: Option<StepDeadline>, future: F) -> Result<F::Output, TimeoutKind> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.at, future).await.map_err(|_| deadline.kind),
+        None => Ok(future.await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::sleep;
+
+    fn limits(step_ms: Option<u64>, overall_ms: Option<u64>, policy: StepTimeoutPolicy) -> StepLimits {
+        StepLimits {
+            max_steps: DEFAULT_MAX_STEPS,
+            per_step_timeout: step_ms.map(Duration::from_millis),
+            overall_deadline: overall_ms.map(Duration::from_millis),
+            on_step_timeout: policy,
+        }
+    }
+
+    // 模拟一个耗时的模型调用或页面操作
+    async fn slow(ms: u64) -> &'static str {
+        sleep(Duration::from_millis(ms)).await;
+        "done"
+    }
+
+    #[tokio::test]
+    async fn test_step_timeout() {
+        let limits = limits(Some(100), None, StepTimeoutPolicy::Continue);
+        let start = Instant::now();
+        let deadline = limits.step_deadline(start, start);
+        assert_eq!(within(deadline, slow(10)).await, Ok("done"));
+        assert_eq!(within(deadline, slow(1000)).await, Err(TimeoutKind::Step));
+        // 默认策略下步骤超时不结束本轮
+        assert_eq!(limits.run_end(TimeoutKind::Step), None);
+        let observation = limits.timeout_observation(TimeoutKind::Step, 0, "waiting for the model");
+        assert!(observation.starts_with("Step 1 timed out after 0.1 seconds"), "{}", observation);
+
+        let abort = StepLimits { on_step_timeout: StepTimeoutPolicy::Abort, ..limits };
+        assert_eq!(abort.run_end(TimeoutKind::Step), Some(RunEnd::StepTimedOut));
+    }
+
+    #[tokio::test]
+    async fn test_overall_deadline() {
+        let limits = limits(Some(200), Some(300), StepTimeoutPolicy::Continue);
+        let start = Instant::now();
+        // 第一个步骤仍然受 per_step_timeout 限制
+        assert_eq!(limits.step_deadline(start, start).map(|d| d.kind), Some(TimeoutKind::Step));
+        assert_eq!(within(limits.step_deadline(start, start), slow(1000)).await, Err(TimeoutKind::Step));
+
+        // 第二个步骤只剩约 100ms，先到达整体截止时间
+        let deadline = limits.step_deadline(start, Instant::now());
+        assert_eq!(deadline.map(|d| d.kind), Some(TimeoutKind::Deadline));
+        assert_eq!(within(deadline, slow(1000)).await, Err(TimeoutKind::Deadline));
+        assert_eq!(limits.run_end(TimeoutKind::Deadline), Some(RunEnd::DeadlineExceeded));
+        assert!(RunEnd::DeadlineExceeded.describe(&limits).contains("deadline of 0.3 seconds"));
+    }
+
+    #[tokio::test]
+    async fn test_no_limits() {
+        let limits = StepLimits::default();
+        let start = Instant::now();
+        assert_eq!(limits.step_deadline(start, start), None);
+        assert_eq!(within(None, async { 1 }).await, Ok(1));
+        assert!(RunEnd::StepsExhausted(10).describe(&limits).contains("all 10 steps"));
+    }
+}
@@ -1,8 +1,11 @@
 pub mod types;
-// pub mod agent;
+pub mod agent;
 pub mod prompt;
 pub mod config;
 pub mod set_of_mark;
 pub mod tool_define;
+pub mod response;
+pub mod notify;
+pub mod limits;
 
-// pub use agent::WebAgent;
\ No newline at end of file
+pub use agent::WebAgent;
\ No newline at end of file
@@ -0,0 +1,61 @@
+use crate::orchestrator::message::{ChatMessage, LLMMessage, Message, MessageRole, UserContent, UserMessage};
+
+/// 收到通知后的回复，orchestrator 只关心通知是否送达
+pub const NOTIFY_ACK: &str = "noted";
+
+/* 通知（重规划、最终答案等广播）中的消息原样放进对话历史，供之后的 Execute 参考：
+文字和多模态消息都保留，来源记为发出通知的一方（orchestrator），不驱动浏览器 */
+pub fn notification_history(message: &Message) -> Vec<LLMMessage> {
+    message
+        .chat_history
+        .iter()
+        .map(|chat_message| {
+            let content = match chat_message {
+                ChatMessage::Text { content, .. } => UserContent::String(content.clone()),
+                ChatMessage::MultiModal { content, .. } => UserContent::MultiModal(content.clone()),
+            };
+            LLMMessage::User(UserMessage::new(content, message.from.clone()))
+        })
+        .collect()
+}
+
+pub fn acknowledgement(agent_name: &str) -> ChatMessage {
+    ChatMessage::new_text(MessageRole::Assistant, agent_name.to_string(), NOTIFY_ACK.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::llm::{request_messages, DEFAULT_MAX_IMAGE_BYTES};
+    use crate::clients::ModelInfo;
+    use crate::orchestrator::message::{MessageType, MultiModalContent};
+
+    #[test]
+    fn test_notifications_reach_the_llm_context() {
+        let notify = Message {
+            from: "orchestrator".to_string(),
+            to: "all".to_string(),
+            chat_history: vec![
+                ChatMessage::new_text(MessageRole::Assistant, "orchestrator".to_string(), "New plan: search the menu first".to_string()),
+                ChatMessage::new_multimodal(
+                    MessageRole::User,
+                    "user".to_string(),
+                    vec![MultiModalContent::Text("Use this photo".to_string()), MultiModalContent::Image(vec![1, 2, 3])],
+                ),
+            ],
+            msg_type: MessageType::Notify,
+        };
+        // 之后 Execute 在同一份历史后面追加指令，get_llm_response 再把整份历史发给模型
+        let mut history = notification_history(&notify);
+        history.push(LLMMessage::User(UserMessage::new(UserContent::String("Open the menu".to_string()), "orchestrator".to_string())));
+
+        assert!(history.iter().take(2).all(|message| matches!(message, LLMMessage::User(user) if user.source == "orchestrator")));
+        assert!(matches!(&history[1], LLMMessage::User(user) if matches!(&user.content, UserContent::MultiModal(parts) if parts.len() == 2)));
+        let context = serde_json::to_string(&request_messages(&history, &ModelInfo { vision: false, ..ModelInfo::default() }, DEFAULT_MAX_IMAGE_BYTES)).unwrap();
+        assert!(context.contains("New plan: search the menu first"));
+        assert!(context.contains("Use this photo"));
+        assert!(context.contains("Open the menu"));
+
+        assert_eq!(acknowledgement("web_surfer"), ChatMessage::new_text(MessageRole::Assistant, "web_surfer".to_string(), "noted".to_string()));
+    }
+}
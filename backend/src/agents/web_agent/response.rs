@@ -0,0 +1,122 @@
+use serde_json::Value;
+
+use crate::agents::web_agent::types::{FunctionCall, LLMOutput};
+
+/// 一次 LLM 响应整理成的执行计划
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ResponsePlan {
+    /// 所有文本部分按顺序合并成的想法，没有文本时为 None
+    pub thought: Option<String>,
+    /// 按顺序执行的函数调用，stop_action 之后的调用被丢弃
+    pub calls: Vec<FunctionCall>,
+}
+
+impl ResponsePlan {
+    /// 只有文本没有函数调用：模型给出的是回复而不是动作
+    pub fn is_text_only(&self) -> bool {
+        self.calls.is_empty()
+    }
+}
+
+/* 按顺序处理一次响应中的所有部分（DashScope 会先给出一段想法再给出函数调用）：
+文本合并为 thought，各个 FunctionCalls 展开成一个调用列表，到第一个 stop_action 为止。
+stop_action 没有给出 answer 时用前面的文本作为回答 */
+pub fn plan_response(outputs: &[LLMOutput]) -> ResponsePlan {
+    let texts: Vec<&str> = outputs
+        .iter()
+        .filter_map(|output| match output {
+            LLMOutput::Text(text) => Some(text.trim()),
+            LLMOutput::FunctionCalls(_) => None,
+        })
+        .filter(|text| !text.is_empty())
+        .collect();
+    let thought = Some(texts.join("\n")).filter(|thought| !thought.is_empty());
+
+    let mut calls = Vec::new();
+    for call in outputs.iter().flat_map(|output| match output {
+        LLMOutput::FunctionCalls(calls) => calls.as_slice(),
+        LLMOutput::Text(_) => &[],
+    }) {
+        if call.name != "stop_action" {
+            calls.push(call.clone());
+            continue;
+        }
+        let mut call = call.clone();
+        if let Some(thought) = &thought {
+            call.arguments = fill_stop_answer(&call.arguments, thought);
+        }
+        calls.push(call);
+        break;
+    }
+    ResponsePlan { thought, calls }
+}
+
+// stop_action 的参数中 answer 缺失或为空时填入 answer
+fn fill_stop_answer(arguments: &str, answer: &str) -> String {
+    let mut args = match serde_json::from_str::<Value>(arguments) {
+        Ok(Value::Object(args)) => args,
+        _ => serde_json::Map::new(),
+    };
+    let has_answer = args.get("answer").and_then(Value::as_str).is_some_and(|answer| !answer.trim().is_empty());
+    if !has_answer {
+        args.insert("answer".to_string(), Value::String(answer.to_string()));
+    }
+    Value::Object(args).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn call(name: &str, args: Value) -> FunctionCall {
+        FunctionCall { id: format!("call_{}", name), arguments: args.to_string(), name: name.to_string() }
+    }
+
+    fn answer(call: &FunctionCall) -> String {
+        let args: Value = serde_json::from_str(&call.arguments).unwrap();
+        args["answer"].as_str().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn test_text_followed_by_calls_runs_every_call() {
+        let plan = plan_response(&[
+            LLMOutput::Text("I will search first.".to_string()),
+            LLMOutput::FunctionCalls(vec![call("input_text", json!({ "input_field_id": 3, "text_value": "rust" }))]),
+            LLMOutput::FunctionCalls(vec![call("click", json!({ "target_id": 7 })), call("scroll_down", json!({}))]),
+        ]);
+        assert_eq!(plan.thought.as_deref(), Some("I will search first."));
+        let names: Vec<&str> = plan.calls.iter().map(|call| call.name.as_str()).collect();
+        assert_eq!(names, ["input_text", "click", "scroll_down"]);
+        assert!(!plan.is_text_only());
+    }
+
+    #[test]
+    fn test_text_only_response() {
+        let plan = plan_response(&[LLMOutput::Text("Which city?".to_string()), LLMOutput::Text(" ".to_string())]);
+        assert_eq!(plan.thought.as_deref(), Some("Which city?"));
+        assert!(plan.is_text_only());
+        assert_eq!(plan_response(&[]), ResponsePlan::default());
+    }
+
+    #[test]
+    fn test_text_before_stop_action() {
+        // stop_action 没有 answer 时用前面的文本
+        let plan = plan_response(&[
+            LLMOutput::Text("The cheapest flight is $120.".to_string()),
+            LLMOutput::FunctionCalls(vec![call("stop_action", json!({ "explanation": "done" })), call("click", json!({ "target_id": 1 }))]),
+        ]);
+        assert_eq!(plan.calls.len(), 1);
+        assert_eq!(answer(&plan.calls[0]), "The cheapest flight is $120.");
+
+        // 给出了 answer 时保持不变，之后的调用被丢弃
+        let plan = plan_response(&[
+            LLMOutput::Text("Done.".to_string()),
+            LLMOutput::FunctionCalls(vec![call("click", json!({ "target_id": 1 })), call("stop_action", json!({ "answer": "Booked." }))]),
+            LLMOutput::FunctionCalls(vec![call("scroll_down", json!({}))]),
+        ]);
+        let names: Vec<&str> = plan.calls.iter().map(|call| call.name.as_str()).collect();
+        assert_eq!(names, ["click", "stop_action"]);
+        assert_eq!(answer(&plan.calls[1]), "Booked.");
+    }
+}
@@ -93,7 +93,7 @@ pub fn add_set_of_mark(
         let new_visible = visible_rects.clone();
         let new_above = rects_above.clone();
         let new_below = rects_below.clone();
-        for list in vec![&visible_rects, &rects_above, &rects_below] {
+        for list in [&visible_rects, &rects_above, &rects_below] {
             for id in list {
                 id_mapping.insert(id.clone(), id.clone());
                 original_to_new.insert(id.clone(), id.clone());
@@ -185,11 +185,7 @@ fn _draw_roi(
     let text_height = (v_metrics.ascent - v_metrics.descent).ceil() as i32;
 
     // Adjust for anchor
-    let text_x = if anchor_rb {
-        label_x - text_width - 3  // Align right
-    } else {
-        label_x - text_width - 3
-    };
+    let text_x = label_x - text_width - 3;  // Align right
     let text_y = if anchor_rb {
         label_y - text_height - 3  // Baseline above bottom
     } else {
@@ -1,3 +1,7 @@
+use anyhow::anyhow;
+use serde_json::Value;
+
+use crate::tools::chrome::chrome_ctrl::clamp_hold;
 use crate::tools::tool_metadata::{load_tool, make_approval_prompt, ToolSchema};
 
 // --- Approval Prompt (used elsewhere) ---
@@ -162,10 +166,10 @@ const TOOL_CLICK_FULL_JSON: &str = r#"{
             "properties": {
                 "explanation": { "type": "string", "description": "Explain to the user the action to be performed and reason for doing so. Phrase as if you are directly talking to the user." },
                 "target_id": { "type": "integer", "description": "The numeric id of the target to click." },
-                "hold": { "type": "number", "description": "Seconds to hold the mouse button down before releasing. Default: 0.0.", "default": 0.0 },
+                "hold_seconds": { "type": "number", "description": "Seconds to hold the left mouse button down before releasing, for long-press controls. At most 5. Default: 0.0.", "default": 0.0 },
                 "button": { "type": "string", "enum": ["left", "right"], "description": "Mouse button to use. Default: 'left'.", "default": "left" }
             },
-            "required": ["explanation", "target_id", "hold", "button"]
+            "required": ["explanation", "target_id"]
         }
     },
     "metadata": { "requires_approval": "maybe" }
@@ -273,12 +277,12 @@ const TOOL_SUMMARIZE_PAGE_JSON: &str = r#"{
 const TOOL_SLEEP_JSON: &str = r#"{
     "function": {
         "name": "sleep",
-        "description": "Sleeps for a given number of seconds. Use this tool when the user asks to sleep for a given number of seconds.",
+        "description": "Sleeps for a given number of seconds, at most 60. Use this tool when the user asks to sleep for a given number of seconds, or to wait for a page to finish loading.",
         "parameters": {
             "type": "object",
             "properties": {
                 "explanation": { "type": "string", "description": "Explain to the user the action to be performed and reason for doing so. Phrase as if you are directly talking to the user." },
-                "seconds": { "type": "integer", "description": "The number of seconds to sleep." }
+                "seconds": { "type": "number", "description": "The number of seconds to sleep, for example 3. Longer waits are cut to 60 seconds." }
             },
             "required": ["explanation", "seconds"]
         }
@@ -373,7 +377,7 @@ const TOOL_UPLOAD_FILE_JSON: &str = r#"{
             "properties": {
                 "explanation": { "type": "string", "description": "The explanation of the action to be performed." },
                 "target_id": { "type": "string", "description": "The ID of the target input element." },
-                "file_path": { "type": "string", "description": "The path to the file to be uploaded." }
+                "file_path": { "type": "string", "description": "The path to the file to be uploaded. Files attached to the task are referred to as session://<file-id>." }
             },
             "required": ["explanation", "target_id", "file_path"]
         }
@@ -439,4 +443,132 @@ impl DefaultTools {
             upload_file: load_tool(TOOL_UPLOAD_FILE_JSON)?,
         })
     }
-}
\ No newline at end of file
+}
+
+/// click_full 的 hold_seconds 参数（旧的名字 hold 也接受），可以是数字或数字字符串，没有时为 0，超过上限时截断
+pub fn hold_seconds(args: &Value) -> anyhow::Result<f64> {
+    let hold = match args.get("hold_seconds").or_else(|| args.get("hold")) {
+        None | Some(Value::Null) => 0.0,
+        Some(Value::Number(n)) => n.as_f64().unwrap_or(0.0),
+        Some(Value::String(s)) => s
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| anyhow!("'hold_seconds' must be a number of seconds, got '{}'", s))?,
+        Some(other) => return Err(anyhow!("'hold_seconds' must be a number of seconds, got {}", other)),
+    };
+    Ok(clamp_hold(hold))
+}
+
+/// 一次工具调用之后 WebAgent 主循环如何继续
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepControl {
+    /// 执行工具，然后继续下一步
+    Continue,
+    /// 执行工具，工具的结果作为最终回答，结束本轮（answer_question、summarize_page）
+    FinishAfter,
+    /// 不执行工具直接结束本轮，answer 作为最终回答（stop_action）
+    Stop { answer: String },
+}
+
+/// stop_action 没有给出 answer 时的最终回答
+pub const DEFAULT_STOP_ANSWER: &str = "I stopped the action.";
+
+pub fn step_control(tool_name: &str, args: &Value) -> StepControl {
+    match tool_name {
+        "stop_action" => StepControl::Stop {
+            answer: args
+                .get("answer")
+                .and_then(|v| v.as_str())
+                .filter(|answer| !answer.trim().is_empty())
+                .unwrap_or(DEFAULT_STOP_ANSWER)
+                .to_string(),
+        },
+        "answer_question" | "summarize_page" => StepControl::FinishAfter,
+        _ => StepControl::Continue,
+    }
+}
+
+/// sleep 没有给出时长时等待的秒数
+pub const DEFAULT_SLEEP_SECS: f64 = 1.0;
+/// sleep 默认的最长秒数，见 WebAgent::set_max_sleep_secs
+pub const DEFAULT_MAX_SLEEP_SECS: f64 = 60.0;
+
+/// sleep 的 seconds 参数（旧的名字 duration 也接受，单位同样是秒），没有时为 DEFAULT_SLEEP_SECS，
+/// 负数和 NaN 视为 0，超过 max_secs 时截断
+pub fn sleep_seconds(args: &Value, max_secs: f64) -> anyhow::Result<f64> {
+    let seconds = match args.get("seconds").or_else(|| args.get("duration")) {
+        None | Some(Value::Null) => DEFAULT_SLEEP_SECS,
+        Some(Value::Number(n)) => n.as_f64().unwrap_or(DEFAULT_SLEEP_SECS),
+        Some(Value::String(s)) => s
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| anyhow!("'seconds' must be a number of seconds, got '{}'", s))?,
+        Some(other) => return Err(anyhow!("'seconds' must be a number of seconds, got {}", other)),
+    };
+    if seconds.is_nan() {
+        return Ok(0.0);
+    }
+    Ok(seconds.clamp(0.0, max_secs.max(0.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_hold_seconds() -> anyhow::Result<()> {
+        assert_eq!(hold_seconds(&json!({ "target_id": 3 }))?, 0.0);
+        assert_eq!(hold_seconds(&json!({ "hold_seconds": 1.5 }))?, 1.5);
+        assert_eq!(hold_seconds(&json!({ "hold_seconds": "2" }))?, 2.0);
+        assert_eq!(hold_seconds(&json!({ "hold": 0.5 }))?, 0.5);
+        // 超过上限和负数都被截断
+        assert_eq!(hold_seconds(&json!({ "hold_seconds": 60 }))?, 5.0);
+        assert_eq!(hold_seconds(&json!({ "hold_seconds": -2 }))?, 0.0);
+        assert!(hold_seconds(&json!({ "hold_seconds": "long" })).is_err());
+        assert!(hold_seconds(&json!({ "hold_seconds": true })).is_err());
+
+        let tools = DefaultTools::new().map_err(|e| anyhow!("{}", e))?;
+        let schema = serde_json::to_value(&tools.click_full)?;
+        assert!(schema.to_string().contains("hold_seconds"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_step_control() {
+        assert_eq!(
+            step_control("stop_action", &json!({ "answer": "The price is $12." })),
+            StepControl::Stop { answer: "The price is $12.".to_string() }
+        );
+        assert_eq!(
+            step_control("stop_action", &json!({ "answer": "" })),
+            StepControl::Stop { answer: DEFAULT_STOP_ANSWER.to_string() }
+        );
+        assert_eq!(step_control("answer_question", &json!({ "question": "?" })), StepControl::FinishAfter);
+        assert_eq!(step_control("summarize_page", &json!({})), StepControl::FinishAfter);
+        assert_eq!(step_control("click", &json!({ "target_id": 1 })), StepControl::Continue);
+    }
+
+    #[test]
+    fn test_sleep_seconds() -> anyhow::Result<()> {
+        assert_eq!(sleep_seconds(&json!({}), DEFAULT_MAX_SLEEP_SECS)?, DEFAULT_SLEEP_SECS);
+        assert_eq!(sleep_seconds(&json!({ "seconds": 2 }), DEFAULT_MAX_SLEEP_SECS)?, 2.0);
+        assert_eq!(sleep_seconds(&json!({ "seconds": "0.5" }), DEFAULT_MAX_SLEEP_SECS)?, 0.5);
+        // 旧的 duration 参数同样按秒计算
+        assert_eq!(sleep_seconds(&json!({ "duration": 3 }), DEFAULT_MAX_SLEEP_SECS)?, 3.0);
+        // 超过上限和负数都被截断
+        assert_eq!(sleep_seconds(&json!({ "seconds": 3600 }), DEFAULT_MAX_SLEEP_SECS)?, 60.0);
+        assert_eq!(sleep_seconds(&json!({ "seconds": 30 }), 10.0)?, 10.0);
+        assert_eq!(sleep_seconds(&json!({ "seconds": -1 }), DEFAULT_MAX_SLEEP_SECS)?, 0.0);
+        assert!(sleep_seconds(&json!({ "seconds": "soon" }), DEFAULT_MAX_SLEEP_SECS).is_err());
+
+        // Chrome::sleep 的单位是毫秒
+        let duration = std::time::Duration::from_secs_f64(sleep_seconds(&json!({ "seconds": 2 }), DEFAULT_MAX_SLEEP_SECS)?);
+        assert_eq!(duration.as_millis(), 2000);
+
+        let tools = DefaultTools::new().map_err(|e| anyhow!("{}", e))?;
+        let schema = serde_json::to_value(&tools.sleep)?.to_string();
+        assert!(schema.contains("seconds") && schema.contains("at most 60"));
+        Ok(())
+    }
+}
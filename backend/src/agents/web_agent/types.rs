@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::clients::{CreateResult, TokenUsage};
 use crate::tools::chrome::types::InteractiveRegion;
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
@@ -40,6 +41,33 @@ pub enum LLMOutput {
     FunctionCalls(Vec<FunctionCall>)
 }
 
+/// 一次模型调用的输出和这次调用的 token 用量
+#[derive(Clone, Debug)]
+pub struct LLMReply {
+    pub outputs: Vec<LLMOutput>,
+    pub usage: TokenUsage,
+}
+
+impl From<CreateResult> for LLMReply {
+    // 文字在前，函数调用在后，与 DashScope 先给出想法再调用工具的顺序一致
+    fn from(result: CreateResult) -> Self {
+        let mut outputs = Vec::new();
+        if !result.content.trim().is_empty() {
+            outputs.push(LLMOutput::Text(result.content));
+        }
+        if !result.tool_calls.is_empty() {
+            outputs.push(LLMOutput::FunctionCalls(
+                result
+                    .tool_calls
+                    .into_iter()
+                    .map(|call| FunctionCall { id: call.id, arguments: call.arguments, name: call.name })
+                    .collect(),
+            ));
+        }
+        Self { outputs, usage: result.usage }
+    }
+}
+
 
 #[derive(Debug)]
 pub struct LLMResponse {
@@ -48,4 +76,26 @@ pub struct LLMResponse {
     pub tools: Vec<ToolSchema>,
     pub element_id: HashMap<String, String>,
     pub need_execute_tool: bool,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::ToolCall;
+
+    #[test]
+    fn test_reply_from_create_result() {
+        let reply = LLMReply::from(CreateResult {
+            content: "I will open the menu.".to_string(),
+            usage: TokenUsage { prompt_tokens: 900, completion_tokens: 25 },
+            tool_calls: vec![ToolCall { id: "call_1".to_string(), name: "click".to_string(), arguments: "{\"target_id\": 4}".to_string() }],
+            finish_reason: None,
+        });
+        assert_eq!(reply.usage.total(), 925);
+        assert!(matches!(&reply.outputs[0], LLMOutput::Text(text) if text == "I will open the menu."));
+        assert!(matches!(&reply.outputs[1], LLMOutput::FunctionCalls(calls) if calls[0].name == "click" && calls[0].id == "call_1"));
+
+        let reply = LLMReply::from(CreateResult { content: " ".to_string(), ..CreateResult::default() });
+        assert!(reply.outputs.is_empty());
+    }
+}
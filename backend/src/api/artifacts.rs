@@ -0,0 +1,38 @@
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+
+use crate::api::auth::AuthUser;
+use crate::api::error::ApiError;
+use crate::api::server::AppState;
+use crate::storage::is_valid_key;
+
+/* GET /api/artifacts/:key：返回会话消息中引用的截图等产物，Content-Type 为保存时的类型。
+只有引用了它的会话的所有者可以读取，其他情况一律 404，不区分产物是否存在 */
+pub async fn get_artifact(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(key): Path<String>,
+) -> Result<Response, ApiError> {
+    if !is_valid_key(&key) {
+        return Err(artifact_not_found(&key));
+    }
+    let Some(record) = state.artifacts.find_for_user(&key, &user.user_id).await? else {
+        return Err(artifact_not_found(&key));
+    };
+    let Some(bytes) = state.blobs.get(&key).await? else {
+        tracing::warn!("Artifact {} is indexed but missing from the {} store", key, record.store);
+        return Err(artifact_not_found(&key));
+    };
+    let headers = [
+        (header::CONTENT_TYPE, record.mime),
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+    ];
+    Ok((headers, Body::from(bytes)).into_response())
+}
+
+fn artifact_not_found(key: &str) -> ApiError {
+    ApiError::not_found(format!("Artifact {} not found", key))
+}
@@ -0,0 +1,47 @@
+use axum::extract::{Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::api::error::ApiError;
+use crate::api::server::AppState;
+use crate::database::KeyCheck;
+
+/// 通过认证的调用方，由中间件放入请求扩展，处理函数用 Extension<AuthUser> 取出
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthUser {
+    pub user_id: String,
+    pub key_id: String,
+}
+
+/* 所有 /api 接口（包括之后的 WebSocket 和 SSE）共用的认证：校验 Authorization: Bearer <key>，
+缺少或无效的 key 返回 401，已撤销的 key 返回 403 */
+pub async fn require_api_key(State(state): State<AppState>, mut request: Request, next: Next) -> Result<Response, ApiError> {
+    let header = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| ApiError::unauthorized("Missing Authorization header"))?;
+    let key = header
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .ok_or_else(|| ApiError::unauthorized("Authorization must be a Bearer API key"))?;
+
+    let record = match state.api_keys.verify(key).await? {
+        KeyCheck::Valid(record) => record,
+        KeyCheck::Revoked => return Err(ApiError::forbidden("The API key has been revoked")),
+        KeyCheck::Invalid => return Err(ApiError::unauthorized("Invalid API key")),
+    };
+    request.extensions_mut().insert(AuthUser { user_id: record.user_id, key_id: record.id });
+    Ok(next.run(request).await)
+}
+
+/// 管理接口的检查：只有 server.admin_users 中的用户可以调用，其他用户返回 403
+#[allow(clippy::result_large_err)]
+pub fn require_admin(state: &AppState, user: &AuthUser) -> Result<(), ApiError> {
+    if !state.config.server.admin_users.contains(&user.user_id) {
+        return Err(ApiError::forbidden("This endpoint requires an admin user"));
+    }
+    Ok(())
+}
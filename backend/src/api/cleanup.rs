@@ -0,0 +1,315 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::extract::State;
+use axum::{Extension, Json};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+use crate::api::auth::{require_admin, AuthUser};
+use crate::api::error::ApiError;
+use crate::api::server::AppState;
+use crate::database::{CleanupSummary, RetentionPolicy, RetentionStore};
+use crate::storage::BlobStore;
+
+/* 按保留策略清理过期数据：每类数据按时间从旧到新分批删除，每批一个事务；
+之后删除不再被引用的产物内容，最后把结果写入 cleanup_runs。同一时间只有一次清理在执行 */
+#[derive(Clone)]
+pub struct RetentionJob {
+    store: RetentionStore,
+    blobs: Arc<dyn BlobStore>,
+    policy: RetentionPolicy,
+    running: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl RetentionJob {
+    pub fn new(store: RetentionStore, blobs: Arc<dyn BlobStore>, policy: RetentionPolicy) -> Self {
+        Self { store, blobs, policy, running: Arc::new(tokio::sync::Mutex::new(())) }
+    }
+
+    pub fn policy(&self) -> &RetentionPolicy {
+        &self.policy
+    }
+
+    /// 清理一次，正在清理时等它结束再开始
+    pub async fn run_once(&self, trigger: &str) -> Result<CleanupSummary> {
+        let _running = self.running.lock().await;
+        self.pass(trigger).await
+    }
+
+    /// 清理一次，正在清理时返回 None
+    pub async fn try_run(&self, trigger: &str) -> Result<Option<CleanupSummary>> {
+        let Ok(_running) = self.running.try_lock() else {
+            return Ok(None);
+        };
+        self.pass(trigger).await.map(Some)
+    }
+
+    // 启动后立即清理一次，之后每隔 interval 清理一次
+    pub fn spawn(&self, interval: Duration) -> JoinHandle<()> {
+        let job = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval.max(Duration::from_secs(1)));
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                match job.try_run("schedule").await {
+                    Ok(Some(_)) => {}
+                    Ok(None) => tracing::debug!("Skipped a scheduled cleanup while another one is running"),
+                    Err(e) => tracing::warn!("Scheduled cleanup failed: {:#}", e),
+                }
+            }
+        })
+    }
+
+    async fn pass(&self, trigger: &str) -> Result<CleanupSummary> {
+        let policy = self.policy;
+        let store = &self.store;
+        let mut summary =
+            CleanupSummary { trigger: trigger.to_string(), started_at: chrono::Utc::now().timestamp(), ..Default::default() };
+        summary.sessions_deleted =
+            self.drain(policy.session_days, move |days, limit| store.delete_expired_sessions(days, limit)).await?;
+        summary.runs_deleted = self.drain(policy.session_days, move |days, limit| store.delete_expired_runs(days, limit)).await?;
+        summary.run_events_deleted =
+            self.drain(policy.run_event_days, move |days, limit| store.delete_expired_run_events(days, limit)).await?;
+        summary.artifacts_deleted =
+            self.drain(policy.artifact_days, move |days, limit| store.delete_expired_artifacts(days, limit)).await?;
+        (summary.blobs_deleted, summary.blob_errors) = self.delete_orphaned_blobs().await?;
+
+        let summary = self.store.record_cleanup(&summary).await?;
+        tracing::info!(
+            "Cleanup {} removed {} sessions, {} runs, {} run events, {} artifacts and {} blobs ({} blob errors)",
+            summary.id,
+            summary.sessions_deleted,
+            summary.runs_deleted,
+            summary.run_events_deleted,
+            summary.artifacts_deleted,
+            summary.blobs_deleted,
+            summary.blob_errors
+        );
+        Ok(summary)
+    }
+
+    // 一批不满 batch_size 时说明已经删完，最多 max_batches 批，剩下的留给下一次清理
+    async fn drain<F, Fut>(&self, days: u32, delete: F) -> Result<i64>
+    where
+        F: Fn(u32, u32) -> Fut,
+        Fut: Future<Output = Result<u64>>,
+    {
+        if days == 0 {
+            return Ok(0);
+        }
+        let batch = self.policy.batch_size.max(1);
+        let mut total = 0;
+        for _ in 0..self.policy.max_batches.max(1) {
+            let deleted = delete(days, batch).await?;
+            total += deleted as i64;
+            if deleted < batch as u64 {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    /* 删除失败的内容留在队列中，下次清理时重试。内容按哈希命名，
+    删除前刚好有新的运行保存了相同的内容时，这份内容会丢失，读取时按不存在处理 */
+    async fn delete_orphaned_blobs(&self) -> Result<(i64, i64)> {
+        let batch = self.policy.batch_size.max(1);
+        let (mut deleted, mut errors) = (0, 0);
+        for _ in 0..self.policy.max_batches.max(1) {
+            let keys = self.store.orphaned_blobs(batch).await?;
+            let mut failed = false;
+            for key in &keys {
+                match self.blobs.delete(key).await {
+                    Ok(()) => {
+                        self.store.forget_blob(key).await?;
+                        deleted += 1;
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to delete blob {} from the {} store: {:#}", key, self.blobs.name(), e);
+                        errors += 1;
+                        failed = true;
+                    }
+                }
+            }
+            if failed || keys.len() < batch as usize {
+                break;
+            }
+        }
+        Ok((deleted, errors))
+    }
+}
+
+// POST /api/admin/cleanup：立即清理一次，返回这次清理的结果；已经在清理时返回 409
+pub async fn run_cleanup(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<CleanupSummary>, ApiError> {
+    require_admin(&state, &user)?;
+    match state.retention.try_run("manual").await? {
+        Some(summary) => Ok(Json(summary)),
+        None => Err(ApiError::conflict("A cleanup is already running")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::SessionStore;
+    use crate::storage::LocalDirStore;
+    use sqlx::PgPool;
+
+    const DAY: i64 = 24 * 3600;
+
+    async fn seed_session(pool: &PgPool, id: &str, age_days: i64, pinned: bool) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, user_id, title, status, pinned, created_at, updated_at)
+            VALUES ($1, 'retention-user', 'retention', 'idle', $2, $3, $3)
+            "#,
+        )
+        .bind(id)
+        .bind(pinned)
+        .bind(chrono::Utc::now().timestamp() - age_days * DAY)
+        .execute(pool)
+        .await?;
+        sqlx::query(r#"INSERT INTO messages (id, session_id, role, source, content_json) VALUES ($1, $2, 'user', 'user', '{}')"#)
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn seed_run(pool: &PgPool, id: &str, session_id: Option<&str>, age_days: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO runs (id, user_id, task, status, session_id, created_at, updated_at)
+            VALUES ($1, 'retention-user', 'retention', 'completed', $2, $3, $3)
+            "#,
+        )
+        .bind(id)
+        .bind(session_id)
+        .bind(chrono::Utc::now().timestamp() - age_days * DAY)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn seed_event(pool: &PgPool, run_id: &str, seq: i64, age_days: i64) -> Result<()> {
+        sqlx::query(r#"INSERT INTO run_events (run_id, seq, event_json, created_at) VALUES ($1, $2, '{}', $3)"#)
+            .bind(run_id)
+            .bind(seq)
+            .bind(chrono::Utc::now().timestamp() - age_days * DAY)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn seed_artifact(pool: &PgPool, blobs: &LocalDirStore, session_id: &str, bytes: &[u8], age_days: i64) -> Result<String> {
+        let blob = blobs.put(bytes, "image/png").await?;
+        sqlx::query(
+            r#"
+            INSERT INTO artifacts (key, session_id, store, mime, size, created_at)
+            VALUES ($1, $2, 'local', 'image/png', $3, $4)
+            "#,
+        )
+        .bind(&blob.key)
+        .bind(session_id)
+        .bind(bytes.len() as i64)
+        .bind(chrono::Utc::now().timestamp() - age_days * DAY)
+        .execute(pool)
+        .await?;
+        Ok(blob.key)
+    }
+
+    async fn count(pool: &PgPool, sql: &str, id: &str) -> Result<i64> {
+        Ok(sqlx::query_scalar(sql).bind(id).fetch_one(pool).await?)
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_cleanup_removes_only_expired_data() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let pool = PgPool::connect(&database_url).await?;
+        SessionStore::new(pool.clone()).migrate().await?;
+        let dir = tempfile::tempdir()?;
+        let blobs = LocalDirStore::new(dir.path())?;
+        let suffix = uuid::Uuid::new_v4().simple().to_string();
+        let id = |name: &str| format!("{}-{}", name, suffix);
+
+        // 过期的会话：连同消息、运行、事件和产物一起删除；与新会话共用的内容保留
+        seed_session(&pool, &id("expired"), 40, false).await?;
+        seed_run(&pool, &id("expired-run"), Some(&id("expired")), 40).await?;
+        seed_event(&pool, &id("expired-run"), 1, 40).await?;
+        let expired_key = seed_artifact(&pool, &blobs, &id("expired"), format!("expired {}", suffix).as_bytes(), 40).await?;
+        let shared = format!("shared {}", suffix);
+        let shared_key = seed_artifact(&pool, &blobs, &id("expired"), shared.as_bytes(), 40).await?;
+
+        // 置顶的过期会话全部保留
+        seed_session(&pool, &id("pinned"), 40, true).await?;
+        seed_run(&pool, &id("pinned-run"), Some(&id("pinned")), 40).await?;
+        seed_event(&pool, &id("pinned-run"), 1, 40).await?;
+        let pinned_key = seed_artifact(&pool, &blobs, &id("pinned"), format!("pinned {}", suffix).as_bytes(), 40).await?;
+
+        // 新会话中过期的事件和产物删除，新的保留
+        seed_session(&pool, &id("fresh"), 0, false).await?;
+        seed_run(&pool, &id("fresh-run"), Some(&id("fresh")), 10).await?;
+        seed_event(&pool, &id("fresh-run"), 1, 10).await?;
+        seed_event(&pool, &id("fresh-run"), 2, 0).await?;
+        let old_key = seed_artifact(&pool, &blobs, &id("fresh"), format!("old {}", suffix).as_bytes(), 40).await?;
+        let fresh_key = seed_artifact(&pool, &blobs, &id("fresh"), format!("fresh {}", suffix).as_bytes(), 0).await?;
+        seed_artifact(&pool, &blobs, &id("fresh"), shared.as_bytes(), 0).await?;
+
+        // 没有会话的运行按创建时间
+        seed_run(&pool, &id("cli-old"), None, 40).await?;
+        seed_run(&pool, &id("cli-new"), None, 0).await?;
+
+        // 每批两行，检查分批删除
+        let policy = RetentionPolicy { batch_size: 2, ..RetentionPolicy::default() };
+        let blob_store: Arc<dyn BlobStore> = Arc::new(blobs.clone());
+        let job = RetentionJob::new(RetentionStore::new(pool.clone()), blob_store, policy);
+        let summary = job.run_once("manual").await?;
+        assert!(summary.id > 0);
+        assert!(summary.sessions_deleted >= 1);
+        assert!(summary.runs_deleted >= 1);
+        assert!(summary.run_events_deleted >= 1);
+        assert!(summary.artifacts_deleted >= 1);
+        assert!(summary.blobs_deleted >= 2);
+        assert_eq!(RetentionStore::new(pool.clone()).recent_cleanups(1).await?[0].id, summary.id);
+
+        let sessions = r#"SELECT COUNT(*) FROM sessions WHERE id = $1"#;
+        let messages = r#"SELECT COUNT(*) FROM messages WHERE session_id = $1"#;
+        let runs = r#"SELECT COUNT(*) FROM runs WHERE id = $1"#;
+        let events = r#"SELECT COUNT(*) FROM run_events WHERE run_id = $1"#;
+        let artifacts = r#"SELECT COUNT(*) FROM artifacts WHERE session_id = $1"#;
+        assert_eq!(count(&pool, sessions, &id("expired")).await?, 0);
+        assert_eq!(count(&pool, messages, &id("expired")).await?, 0);
+        assert_eq!(count(&pool, runs, &id("expired-run")).await?, 0);
+        assert_eq!(count(&pool, artifacts, &id("expired")).await?, 0);
+        assert_eq!(count(&pool, sessions, &id("pinned")).await?, 1);
+        assert_eq!(count(&pool, messages, &id("pinned")).await?, 1);
+        assert_eq!(count(&pool, events, &id("pinned-run")).await?, 1);
+        assert_eq!(count(&pool, artifacts, &id("pinned")).await?, 1);
+        assert_eq!(count(&pool, sessions, &id("fresh")).await?, 1);
+        assert_eq!(count(&pool, events, &id("fresh-run")).await?, 1);
+        assert_eq!(count(&pool, artifacts, &id("fresh")).await?, 2);
+        assert_eq!(count(&pool, runs, &id("cli-old")).await?, 0);
+        assert_eq!(count(&pool, runs, &id("cli-new")).await?, 1);
+
+        assert!(blobs.get(&expired_key).await?.is_none());
+        assert!(blobs.get(&old_key).await?.is_none());
+        assert!(blobs.get(&shared_key).await?.is_some());
+        assert!(blobs.get(&pinned_key).await?.is_some());
+        assert!(blobs.get(&fresh_key).await?.is_some());
+
+        for session in [id("pinned"), id("fresh")] {
+            sqlx::query(r#"DELETE FROM sessions WHERE id = $1"#).bind(session).execute(&pool).await?;
+        }
+        sqlx::query(r#"DELETE FROM runs WHERE id = $1"#).bind(id("cli-new")).execute(&pool).await?;
+        Ok(())
+    }
+}
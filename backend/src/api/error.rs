@@ -0,0 +1,146 @@
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::{json, Map, Value};
+
+use crate::api::executor::QueueError;
+use crate::api::quotas::QuotaExceeded;
+
+/* HTTP 接口统一的错误：状态码 + 机器可读的 code + 说明，
+响应体为 {"error": {"code": "...", "message": "..."}}，details 中的字段一并放进 error；
+retry_after 给出时带 Retry-After 头（秒） */
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    pub details: Map<String, Value>,
+    pub retry_after: Option<u64>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self { status, code, message: message.into(), details: Map::new(), retry_after: None }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, "forbidden", message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, "conflict", message)
+    }
+
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::SERVICE_UNAVAILABLE, "unavailable", message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut error = self.details;
+        error.insert("code".to_string(), json!(self.code));
+        error.insert("message".to_string(), json!(self.message));
+        let mut response = (self.status, Json(json!({ "error": error }))).into_response();
+        if let Some(seconds) = self.retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, seconds.into());
+        }
+        response
+    }
+}
+
+// 超出配额：429，error 中给出是哪一项配额和它的上限
+impl From<QuotaExceeded> for ApiError {
+    fn from(exceeded: QuotaExceeded) -> Self {
+        let mut error = Self::new(StatusCode::TOO_MANY_REQUESTS, "quota_exceeded", exceeded.to_string());
+        error.details.insert("quota".to_string(), json!(exceeded.quota));
+        error.details.insert("limit".to_string(), json!(exceeded.limit));
+        error.retry_after = Some(exceeded.retry_after.as_secs().max(1));
+        error
+    }
+}
+
+/// 提交运行失败：超出配额时 429，队列满或执行器已停止时 503，其他为内部错误
+pub fn submission_error(error: anyhow::Error) -> ApiError {
+    if let Some(exceeded) = error.downcast_ref::<QuotaExceeded>() {
+        return exceeded.clone().into();
+    }
+    match error.downcast_ref::<QueueError>() {
+        Some(queue_error) => ApiError::unavailable(format!("{}, try again later", queue_error)),
+        None => ApiError::from(error),
+    }
+}
+
+// 数据库等内部错误只记录日志，不把细节返回给调用方
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        tracing::error!("Internal error while handling a request: {:?}", error);
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal", "Internal server error")
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::new(rejection.status(), "invalid_json", rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::bad_request(rejection.body_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_error_body() -> anyhow::Result<()> {
+        let response = ApiError::not_found("Session abc not found").into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+        let value: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(value, json!({ "error": { "code": "not_found", "message": "Session abc not found" } }));
+
+        let response = ApiError::from(anyhow::anyhow!("connection refused")).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+        assert!(!String::from_utf8_lossy(&body).contains("connection refused"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_quota_exceeded_response() -> anyhow::Result<()> {
+        let exceeded = QuotaExceeded {
+            quota: "max_runs_per_day",
+            limit: 50,
+            retry_after: std::time::Duration::from_millis(2500),
+        };
+        let response = submission_error(exceeded.into()).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+        let value: Value = serde_json::from_slice(&body)?;
+        assert_eq!(value["error"]["code"], "quota_exceeded");
+        assert_eq!(value["error"]["quota"], "max_runs_per_day");
+        assert_eq!(value["error"]["limit"], 50);
+
+        let response = submission_error(QueueError::Full.into()).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        Ok(())
+    }
+}
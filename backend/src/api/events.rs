@@ -0,0 +1,257 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::extract::{Path, State};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::Extension;
+use futures::Stream;
+use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::api::auth::AuthUser;
+use crate::api::error::ApiError;
+use crate::api::executor::RunEvent;
+use crate::api::server::AppState;
+use crate::api::sessions::find_session;
+use crate::orchestrator::event_log::encode_event;
+use crate::orchestrator::events::OrchestratorEvent;
+
+// 代理和反向代理通常在一段时间没有数据后断开连接
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+// 等待发给一个客户端的事件数，超过时先丢弃可以丢弃的事件
+const MAX_BUFFERED: usize = 256;
+const REPLAY_PAGE_SIZE: i64 = 500;
+
+/* 运行事件推给客户端时的形式，SSE 和之后的 WebSocket 共用：
+id 为 <run_id>:<seq>，和 run_events 表中的记录一一对应，data 为清理过的 OrchestratorEvent JSON */
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutgoingEvent {
+    pub run_id: String,
+    pub seq: i64,
+    pub data: Value,
+}
+
+impl OutgoingEvent {
+    pub fn new(run_id: &str, seq: i64, event: &OrchestratorEvent) -> serde_json::Result<Self> {
+        Ok(Self { run_id: run_id.to_string(), seq, data: encode_event(event)? })
+    }
+
+    pub fn id(&self) -> String {
+        format!("{}:{}", self.run_id, self.seq)
+    }
+
+    // 客户端跟不上时可以丢弃的事件：代理的中间过程和流式输出的计划步骤，完整的计划、步骤进度和结果总是保留
+    pub fn droppable(&self) -> bool {
+        matches!(self.data.get("type").and_then(Value::as_str), Some("agent_event" | "plan_step_streamed"))
+    }
+
+    fn to_sse(&self) -> Event {
+        Event::default().id(self.id()).data(self.data.to_string())
+    }
+}
+
+/// 解析 OutgoingEvent::id 的形式，运行 id 中不含冒号
+pub fn parse_event_id(id: &str) -> Option<(String, i64)> {
+    let (run_id, seq) = id.trim().rsplit_once(':')?;
+    let seq = seq.parse().ok()?;
+    (!run_id.is_empty() && !run_id.contains(':')).then(|| (run_id.to_string(), seq))
+}
+
+/// 等待发给一个慢客户端的事件，超过 capacity 时从最旧的开始丢弃可以丢弃的事件
+#[derive(Debug)]
+pub struct EventBuffer {
+    queue: VecDeque<OutgoingEvent>,
+    capacity: usize,
+    dropped: u64,
+}
+
+impl EventBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self { queue: VecDeque::new(), capacity: capacity.max(1), dropped: 0 }
+    }
+
+    // 没有可以丢弃的事件时暂时超出 capacity，调用方在 is_full 时停止接收新的事件
+    pub fn push(&mut self, event: OutgoingEvent) {
+        self.queue.push_back(event);
+        while self.queue.len() > self.capacity {
+            let Some(index) = self.queue.iter().position(OutgoingEvent::droppable) else {
+                break;
+            };
+            self.queue.remove(index);
+            self.dropped += 1;
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<OutgoingEvent> {
+        self.queue.pop_front()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.queue.len() >= self.capacity
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/* GET /api/sessions/:id/events：会话中运行事件的 SSE 流（text/event-stream），给不能使用 WebSocket 的客户端。
+每 15 秒一条注释作为心跳。带 Last-Event-ID 重新连接时，先从 run_events 表中补上该事件之后的事件，
+再继续推送新的事件；没有时只推送连接之后的事件。客户端跟不上时丢弃代理的中间事件，
+落后太多错过的事件从表中补上 */
+pub async fn session_events(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let cursor = match headers.get("last-event-id") {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(parse_event_id)
+                .ok_or_else(|| ApiError::bad_request("Last-Event-ID must look like <run_id>:<seq>"))?,
+        ),
+        None => None,
+    };
+    find_session(&state, &user, &id).await?;
+
+    // 先订阅再补历史，两者之间的事件按 seq 去重
+    let live = state.runs.subscribe_events();
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(pump_events(state, id, cursor, live, tx));
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|event| (Ok(event), rx))
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(HEARTBEAT_INTERVAL)))
+}
+
+// 客户端断开后 tx 关闭，任务随之结束
+async fn pump_events(
+    state: AppState,
+    session_id: String,
+    mut cursor: Option<(String, i64)>,
+    mut live: broadcast::Receiver<RunEvent>,
+    tx: mpsc::Sender<Event>,
+) {
+    let metrics = state.runs.metrics();
+    metrics.event_ws_clients.inc();
+    let mut buffer = EventBuffer::new(MAX_BUFFERED);
+    // 每个运行已经放进 buffer 的最大 seq
+    let mut sent: HashMap<String, i64> = HashMap::new();
+    let mut replaying = cursor.is_some();
+
+    loop {
+        if replaying && !buffer.is_full() {
+            let after = cursor.as_ref().map(|(run_id, seq)| (run_id.as_str(), *seq));
+            let page = match state.run_store.session_events_after(&session_id, after, REPLAY_PAGE_SIZE).await {
+                Ok(page) => page,
+                Err(e) => {
+                    tracing::warn!("Failed to replay events of session {}: {:?}", session_id, e);
+                    break;
+                }
+            };
+            replaying = page.len() as i64 == REPLAY_PAGE_SIZE;
+            for record in page {
+                sent.insert(record.run_id.clone(), record.seq);
+                cursor = Some((record.run_id.clone(), record.seq));
+                buffer.push(OutgoingEvent { run_id: record.run_id, seq: record.seq, data: record.event_json });
+            }
+            continue;
+        }
+
+        tokio::select! {
+            permit = tx.reserve(), if !buffer.is_empty() => {
+                let Ok(permit) = permit else { break };
+                if let Some(event) = buffer.pop() {
+                    permit.send(event.to_sse());
+                }
+            }
+            received = live.recv(), if !buffer.is_full() && !replaying => match received {
+                Ok(event) if event.session_id == session_id => {
+                    if sent.get(&event.run_id).is_some_and(|seq| event.seq <= *seq) {
+                        continue;
+                    }
+                    sent.insert(event.run_id.clone(), event.seq);
+                    cursor = Some((event.run_id.clone(), event.seq));
+                    buffer.push(OutgoingEvent { run_id: event.run_id, seq: event.seq, data: event.data });
+                }
+                Ok(_) => {}
+                // 错过的事件已经写入 run_events；还没有推送过事件时无从补起
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::debug!("Event stream of session {} lagged by {} events", session_id, skipped);
+                    replaying = cursor.is_some();
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = tx.closed() => break,
+        }
+    }
+    if buffer.dropped() > 0 {
+        tracing::debug!("Dropped {} agent events for a slow client of session {}", buffer.dropped(), session_id);
+    }
+    metrics.event_ws_clients.dec();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::events::AgentEvent;
+
+    fn agent_event(seq: i64) -> OutgoingEvent {
+        let event = OrchestratorEvent::AgentEvent {
+            agent_name: "web_surfer".to_string(),
+            step_index: 0,
+            event: AgentEvent::Thought { text: format!("thought {}", seq) },
+        };
+        OutgoingEvent::new("run-1", seq, &event).unwrap()
+    }
+
+    fn step_event(seq: i64) -> OutgoingEvent {
+        let event = OrchestratorEvent::StepStarted {
+            step_index: seq as usize,
+            agent_name: "web_surfer".to_string(),
+            instruction: "Open the menu page".to_string(),
+        };
+        OutgoingEvent::new("run-1", seq, &event).unwrap()
+    }
+
+    #[test]
+    fn test_slow_clients_lose_agent_events_first() {
+        let mut buffer = EventBuffer::new(3);
+        buffer.push(step_event(1));
+        buffer.push(agent_event(2));
+        buffer.push(agent_event(3));
+        buffer.push(step_event(4));
+        assert!(buffer.is_full());
+        buffer.push(step_event(5));
+        // 超出时丢弃最旧的代理事件，步骤事件保留
+        assert_eq!(buffer.dropped(), 2);
+        let seqs: Vec<i64> = std::iter::from_fn(|| buffer.pop()).map(|event| event.seq).collect();
+        assert_eq!(seqs, vec![1, 4, 5]);
+
+        // 没有可以丢弃的事件时不丢弃
+        let mut buffer = EventBuffer::new(1);
+        buffer.push(step_event(1));
+        buffer.push(step_event(2));
+        assert_eq!(buffer.dropped(), 0);
+        assert_eq!(buffer.pop().map(|event| event.id()), Some("run-1:1".to_string()));
+    }
+
+    #[test]
+    fn test_event_ids() {
+        assert_eq!(step_event(7).id(), "run-1:7");
+        assert_eq!(parse_event_id(" 3f1c-42:12 "), Some(("3f1c-42".to_string(), 12)));
+        for invalid in ["", "run-1", ":3", "run-1:x", "a:b:3"] {
+            assert_eq!(parse_event_id(invalid), None, "{:?}", invalid);
+        }
+    }
+}
@@ -0,0 +1,1112 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::{JoinError, JoinHandle};
+
+use crate::api::quotas::QuotaGate;
+use crate::api::server::QueuedRun;
+use crate::clients::{MeteredClient, RateLimitedClient, TokenUsage};
+use crate::common::metrics::Metrics;
+use crate::database::runs::{
+    RUN_STATUS_CANCELLED, RUN_STATUS_COMPLETED, RUN_STATUS_FAILED, RUN_STATUS_INTERRUPTED, RUN_STATUS_RUNNING,
+};
+use crate::database::sessions::{SESSION_STATUS_IDLE, SESSION_STATUS_QUEUED, SESSION_STATUS_RUNNING};
+use crate::database::{with_tx, ArtifactIndex, RunRecord, RunStore, SessionFileStore, SessionStore, StaleRuns};
+use crate::orchestrator::event_log::{artifact_ref, inline_images, sanitize_event};
+use crate::orchestrator::events::OrchestratorEvent;
+use crate::orchestrator::message::{ChatMessage, MessageRole, MultiModalContent, FINAL_ANSWER_MESSAGE_KIND, MESSAGE_KIND_KEY};
+use crate::orchestrator::orchestrator::Orchestrator;
+use crate::orchestrator::plan::Plan;
+use crate::orchestrator::session::SessionCheckpoint;
+use crate::orchestrator::types::{RunOptions, RunOutcome, SessionFile, SessionFiles, UserMessageQueue};
+use crate::storage::{sniff_mime, BlobRef, BlobStore};
+use crate::tools::chrome::Lease;
+
+/// 运行没能放入队列的原因，接口层据此返回 503
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum QueueError {
+    #[error("The run queue is full")]
+    Full,
+    #[error("The run executor has stopped")]
+    Stopped,
+}
+
+/// 为每次运行组装 Orchestrator，模型、代理和计划库由实现决定；运行记录由执行器设置
+#[async_trait]
+pub trait OrchestratorFactory: Send + Sync {
+    async fn build(&self, run: &QueuedRun) -> Result<BuiltRun>;
+
+    /// 按组装出的团队检查上传的计划，接口据此拒绝不合法的计划；默认不检查，执行时 set_plan 仍会校验
+    fn check_plan(&self, _plan: &Plan) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// factory 组装好的一次运行，browser 为代理使用的浏览器租约，运行结束（包括取消和出错）后由执行器归还
+pub struct BuiltRun {
+    pub orchestrator: Orchestrator,
+    pub browser: Option<Box<dyn Lease>>,
+}
+
+impl From<Orchestrator> for BuiltRun {
+    fn from(orchestrator: Orchestrator) -> Self {
+        Self { orchestrator, browser: None }
+    }
+}
+
+/// 执行器转发的一条运行事件，seq 和 run_events 表中的一致；data 为清理过的 JSON，和表中保存的相同
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunEvent {
+    pub run_id: String,
+    pub session_id: String,
+    pub seq: i64,
+    pub event: OrchestratorEvent,
+    pub data: Value,
+}
+
+/// 正在执行的一次运行，供取消接口和 WebSocket 层使用
+#[derive(Clone)]
+pub struct RunHandle {
+    pub run_id: String,
+    pub session_id: String,
+    events: broadcast::Sender<OrchestratorEvent>,
+    messages: UserMessageQueue,
+    cancelled: Arc<AtomicBool>,
+    // 运行借用的浏览器，orchestrator 停止后归还
+    browser: Arc<Mutex<Option<Box<dyn Lease>>>>,
+}
+
+impl RunHandle {
+    /// 订阅此刻之后的事件，之前的事件在 run_events 表中
+    pub fn subscribe(&self) -> broadcast::Receiver<OrchestratorEvent> {
+        self.events.subscribe()
+    }
+
+    /// 交给正在执行的 orchestrator，下一次评估进度时看到；停止指令会取消运行
+    pub fn send_message(&self, message: ChatMessage) {
+        self.messages.push(message);
+    }
+
+    /// 停止运行，orchestrator 会给出目前为止的总结
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.messages.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    // 归还借用的浏览器，只有第一次调用生效
+    async fn release_browser(&self) {
+        let lease = self.browser.lock().unwrap().take();
+        if let Some(lease) = lease {
+            lease.release().await;
+        }
+    }
+
+    // run_task 开始时会换一个新的取消 token，在那之前的取消在收到事件时补上
+    fn reapply_cancel(&self) {
+        if self.is_cancelled() && !self.messages.is_cancelled() {
+            self.messages.cancel();
+        }
+    }
+}
+
+// 一个会话在执行器中的状态，排队和执行中的运行都结束后移除
+#[derive(Default)]
+struct SessionSlot {
+    // 同一会话的运行依次执行
+    lock: Arc<tokio::sync::Mutex<()>>,
+    pending: usize,
+    active: Option<RunHandle>,
+    // 会话所属的用户，用于统计每个用户排队和执行中的运行数
+    user_id: Option<String>,
+}
+
+struct Shared {
+    factory: Arc<dyn OrchestratorFactory>,
+    runs: RunStore,
+    sessions: SessionStore,
+    blobs: Arc<dyn BlobStore>,
+    files: SessionFileStore,
+    slots: Mutex<HashMap<String, SessionSlot>>,
+    stopping: AtomicBool,
+    // 未设置时不限制
+    quotas: OnceLock<QuotaGate>,
+    metrics: Arc<Metrics>,
+    // 所有运行的事件，SSE 等按会话过滤
+    events: broadcast::Sender<RunEvent>,
+    // 模型调用指标的 provider 和 model 标签
+    model_labels: OnceLock<(String, String)>,
+}
+
+/* 后台执行用户消息触发的运行：有界队列 + 固定数量的 worker。每次运行由 factory 组装
+一个新的 Orchestrator，结果和事件写入 runs / run_events，完整的消息记录和回答写入会话消息，
+其中的截图等二进制内容存入 BlobStore，消息里只保存引用，并维护会话状态；
+会话中上传的文件在运行期间写到临时目录，代理通过 session://<file-id> 使用；
+同时写入的多行（运行记录和会话状态、消息和产物、回答和运行结束状态）各自在一个事务中。
+设置了配额时，提交按用户的配额放行，执行时按用户限速模型调用并累计用量。
+运行出错或 panic 时记为 failed 并保存原因，不会一直停留在 running；
+每个步骤完成后写入 run_checkpoints，进程中途停止的运行可以用 resume 从检查点继续 */
+#[derive(Clone)]
+pub struct RunExecutor {
+    sender: async_channel::Sender<QueuedRun>,
+    receiver: async_channel::Receiver<QueuedRun>,
+    shared: Arc<Shared>,
+    workers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl RunExecutor {
+    /// 创建执行器，调用 start 之前只排队不执行
+    pub fn new(
+        factory: Arc<dyn OrchestratorFactory>,
+        runs: RunStore,
+        sessions: SessionStore,
+        blobs: Arc<dyn BlobStore>,
+        queue_size: usize,
+    ) -> Self {
+        let (sender, receiver) = async_channel::bounded(queue_size.max(1));
+        Self {
+            sender,
+            receiver,
+            shared: Arc::new(Shared {
+                factory,
+                runs,
+                files: SessionFileStore::new(sessions.pool().clone()),
+                sessions,
+                blobs,
+                slots: Mutex::new(HashMap::new()),
+                stopping: AtomicBool::new(false),
+                quotas: OnceLock::new(),
+                metrics: Arc::new(Metrics::new()),
+                events: broadcast::channel(1024).0,
+                model_labels: OnceLock::new(),
+            }),
+            workers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// 启用用户配额，在 start 之前调用；重复调用时保留第一次的设置
+    pub fn set_quotas(&self, quotas: QuotaGate) {
+        if self.shared.quotas.set(quotas).is_err() {
+            tracing::warn!("Quotas are already configured for the run executor");
+        }
+    }
+
+    pub fn quotas(&self) -> Option<&QuotaGate> {
+        self.shared.quotas.get()
+    }
+
+    /// 运行和模型调用的指标，GET /metrics 导出
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.shared.metrics.clone()
+    }
+
+    /// 模型调用指标的标签，未设置时为 unknown；重复调用时保留第一次的设置
+    pub fn set_model_labels(&self, provider: &str, model: &str) {
+        if self.shared.model_labels.set((provider.to_string(), model.to_string())).is_err() {
+            tracing::warn!("Model labels are already configured for the run executor");
+        }
+    }
+
+    /// 上传的计划能否由 factory 组装的团队执行，见 OrchestratorFactory::check_plan
+    pub fn check_plan(&self, plan: &Plan) -> Result<()> {
+        self.shared.factory.check_plan(plan)
+    }
+
+    /// 排队等待执行的运行数
+    pub fn queue_depth(&self) -> usize {
+        self.sender.len()
+    }
+
+    /// 启动时调用：上次进程遗留的 running 运行标记为 interrupted 或失败，排队和运行中的会话恢复为 idle
+    pub async fn recover(&self) -> Result<StaleRuns> {
+        let stale = self.shared.runs.sweep_stale_runs().await?;
+        for id in &stale.interrupted {
+            tracing::warn!("Marked stale run {} as interrupted", id);
+        }
+        for id in &stale.failed {
+            tracing::warn!("Marked stale run {} as failed", id);
+        }
+        self.shared.sessions.reset_active_sessions().await?;
+        Ok(stale)
+    }
+
+    /* 把被中断的运行放回队列，从它最新的检查点继续；user_id 给出时只能恢复该用户的运行。
+    运行不存在或不是 interrupted 时返回 None，超出并发配额或队列已满时恢复为 interrupted 并返回错误 */
+    pub async fn resume(&self, run_id: &str, user_id: Option<&str>) -> Result<Option<RunRecord>> {
+        let claimed = with_tx(self.shared.runs.pool(), |conn| {
+            let (run_id, user_id) = (run_id.to_string(), user_id.map(str::to_string));
+            Box::pin(async move {
+                let Some(record) = RunStore::claim_interrupted_run_in(&mut *conn, &run_id, user_id.as_deref()).await? else {
+                    return Ok(None);
+                };
+                let session_id = record.session_id.clone().unwrap_or_default();
+                SessionStore::set_status_in(conn, &session_id, SESSION_STATUS_QUEUED).await?;
+                Ok(Some(record))
+            })
+        })
+        .await?;
+        let Some(record) = claimed else {
+            return Ok(None);
+        };
+        let session_id = record.session_id.clone().unwrap_or_default();
+        let queued = QueuedRun {
+            session_id: session_id.clone(),
+            message_id: String::new(),
+            user_id: record.user_id.clone(),
+            task: record.task.clone(),
+            resume_run_id: Some(record.id.clone()),
+            plan: None,
+            attachments: Vec::new(),
+        };
+        if let Err(e) = self.submit(queued).await {
+            self.shared.runs.end_run(&record.id, RUN_STATUS_INTERRUPTED, None).await?;
+            if self.active_run(&session_id).is_none() {
+                self.shared.sessions.set_status(&session_id, SESSION_STATUS_IDLE).await?;
+            }
+            return Err(e);
+        }
+        Ok(Some(record))
+    }
+
+    pub fn start(&self, concurrency: usize) {
+        let mut workers = self.workers.lock().unwrap();
+        for _ in 0..concurrency.max(1) {
+            let receiver = self.receiver.clone();
+            let shared = self.shared.clone();
+            workers.push(tokio::spawn(async move {
+                while let Ok(run) = receiver.recv().await {
+                    shared.execute(run).await;
+                }
+            }));
+        }
+    }
+
+    /* 放入队列，超出用户配额（QuotaExceeded）、队列已满或执行器已经停止（QueueError）时返回错误。
+    并发数在登记排队时检查，同一用户同时提交的运行不会一起越过上限 */
+    pub async fn submit(&self, run: QueuedRun) -> Result<()> {
+        let admission = match (self.quotas(), &run.user_id) {
+            (Some(quotas), Some(user_id)) => Some(quotas.admission(user_id).await?),
+            _ => None,
+        };
+        let session_id = run.session_id.clone();
+        {
+            let mut slots = self.shared.slots.lock().unwrap();
+            if let (Some(admission), Some(user_id)) = (&admission, &run.user_id) {
+                let in_flight: usize = slots
+                    .values()
+                    .filter(|slot| slot.user_id.as_ref() == Some(user_id))
+                    .map(|slot| slot.pending)
+                    .sum();
+                admission.check(in_flight as u64, run.resume_run_id.is_some())?;
+            }
+            let slot = slots.entry(session_id.clone()).or_default();
+            slot.pending += 1;
+            slot.user_id = run.user_id.clone();
+        }
+        if let Err(e) = self.sender.try_send(run) {
+            self.shared.release(&session_id);
+            return Err(match e {
+                async_channel::TrySendError::Full(_) => QueueError::Full.into(),
+                async_channel::TrySendError::Closed(_) => QueueError::Stopped.into(),
+            });
+        }
+        Ok(())
+    }
+
+    /// 会话当前正在执行的运行
+    pub fn active_run(&self, session_id: &str) -> Option<RunHandle> {
+        self.shared.slots.lock().unwrap().get(session_id).and_then(|slot| slot.active.clone())
+    }
+
+    /// 订阅会话当前运行的事件，没有运行时返回 None
+    pub fn attach(&self, session_id: &str) -> Option<broadcast::Receiver<OrchestratorEvent>> {
+        self.active_run(session_id).map(|handle| handle.subscribe())
+    }
+
+    /// 订阅此刻之后所有运行的事件，落后太多的订阅者会收到 Lagged，错过的事件在 run_events 表中
+    pub fn subscribe_events(&self) -> broadcast::Receiver<RunEvent> {
+        self.shared.events.subscribe()
+    }
+
+    /// 取消会话当前的运行，返回是否有运行被取消；排队中的运行不受影响
+    pub fn cancel(&self, session_id: &str) -> bool {
+        match self.active_run(session_id) {
+            Some(handle) => {
+                handle.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /* 按运行 id 取消正在执行的运行，返回是否找到。取消沿 orchestrator 的停止流程传到正在执行的代理，
+    运行以目前为止的总结结束并记为 cancelled，借用的浏览器在 orchestrator 停止后归还池中；
+    重复取消没有额外的影响 */
+    pub fn cancel_run(&self, run_id: &str) -> bool {
+        let handle = self
+            .shared
+            .slots
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|slot| slot.active.clone())
+            .find(|handle| handle.run_id == run_id);
+        match handle {
+            Some(handle) => {
+                handle.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 不再接收新的运行，等排队的运行全部执行完
+    pub async fn drain(&self) {
+        self.sender.close();
+        let workers = std::mem::take(&mut *self.workers.lock().unwrap());
+        for worker in workers {
+            if let Err(e) = worker.await {
+                tracing::warn!("Run worker exited abnormally: {}", e);
+            }
+        }
+    }
+
+    /// 停止服务时调用：取消正在执行的运行，排队的运行不再开始，下次启动时由 recover 处理会话状态
+    pub async fn shutdown(&self) {
+        self.shared.stopping.store(true, Ordering::SeqCst);
+        let active: Vec<RunHandle> = self
+            .shared
+            .slots
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|slot| slot.active.clone())
+            .collect();
+        for handle in active {
+            handle.cancel();
+        }
+        self.drain().await;
+    }
+
+    #[cfg(test)]
+    pub(crate) async fn next_queued(&self) -> Option<QueuedRun> {
+        self.receiver.recv().await.ok()
+    }
+}
+
+impl Shared {
+    async fn execute(&self, run: QueuedRun) {
+        let lock = self.slots.lock().unwrap().entry(run.session_id.clone()).or_default().lock.clone();
+        let guard = lock.lock().await;
+        if !self.stopping.load(Ordering::SeqCst) {
+            if let Err(e) = self.execute_locked(&run).await {
+                tracing::warn!("Failed to record the run for session {}: {:#}", run.session_id, e);
+            }
+        }
+        drop(guard);
+
+        let status = if self.release(&run.session_id) { SESSION_STATUS_QUEUED } else { SESSION_STATUS_IDLE };
+        if let Err(e) = self.sessions.set_status(&run.session_id, status).await {
+            tracing::warn!("Failed to update session {}: {:?}", run.session_id, e);
+        }
+    }
+
+    async fn execute_locked(&self, run: &QueuedRun) -> Result<()> {
+        if let Some(run_id) = &run.resume_run_id {
+            // 排队等待恢复期间被取消的运行不再执行
+            let status = self.runs.run_detail(run_id).await?.map(|detail| detail.run.status);
+            if status.as_deref() != Some(RUN_STATUS_RUNNING) {
+                tracing::info!("Run {} is no longer waiting to resume, skipping it", run_id);
+                return Ok(());
+            }
+        }
+        let run_id = self.start_run(run).await?;
+        let started = tokio::time::Instant::now();
+        self.metrics.runs_started.inc();
+        if let (Some(quotas), Some(user_id), None) = (self.quotas.get(), &run.user_id, &run.resume_run_id) {
+            if let Err(e) = quotas.record_run(user_id).await {
+                tracing::warn!("Failed to record the run count of user {}: {:?}", user_id, e);
+            }
+        }
+
+        let (result, transcript) = match self.load_checkpoint(run).await {
+            Ok(checkpoint) => self.run_orchestrator(run, &run_id, checkpoint).await,
+            Err(e) => (Err(e), Vec::new()),
+        };
+        // 出错的运行也保存已有的消息记录，便于事后检查
+        let attachments = match self.save_transcript(run, &run_id, &transcript).await {
+            Ok(attachments) => attachments,
+            Err(e) => {
+                tracing::warn!("Failed to save the transcript of run {}: {:?}", run_id, e);
+                Vec::new()
+            }
+        };
+        let (status, error, answer) = match result {
+            Ok((outcome, cancelled)) => {
+                self.record_agent_usage(run, &outcome).await;
+                let answer = json!({ "text": outcome.final_answer, "run_id": run_id, "attachments": attachments });
+                (if cancelled { RUN_STATUS_CANCELLED } else { RUN_STATUS_COMPLETED }, None, Some(answer))
+            }
+            Err(e) => {
+                tracing::warn!("Run {} failed: {:#}", run_id, e);
+                (RUN_STATUS_FAILED, Some(format!("{:#}", e)), None)
+            }
+        };
+        let saved = self.end_run(run, &run_id, status, error.as_deref(), answer, &attachments).await;
+        self.metrics.run_finished(status == RUN_STATUS_FAILED || saved.is_err(), started.elapsed());
+        match saved {
+            Ok(()) => Ok(()),
+            // 回答没能写入时运行记为失败，避免停在 running
+            Err(e) => {
+                tracing::warn!("Failed to save the result of run {}: {:?}", run_id, e);
+                let error = format!("Failed to save the result of the run: {:#}", e);
+                self.runs.end_run(&run_id, RUN_STATUS_FAILED, Some(&error)).await
+            }
+        }
+    }
+
+    // 新的运行创建记录并把会话标记为 running，两者在同一个事务中；恢复的运行沿用原来的记录
+    async fn start_run(&self, run: &QueuedRun) -> Result<String> {
+        if let Some(run_id) = &run.resume_run_id {
+            self.sessions.set_status(&run.session_id, SESSION_STATUS_RUNNING).await?;
+            return Ok(run_id.clone());
+        }
+        let record = with_tx(self.runs.pool(), |conn| {
+            let run = run.clone();
+            Box::pin(async move {
+                let record =
+                    RunStore::start_session_run_in(&mut *conn, &run.session_id, run.user_id.as_deref(), &run.task).await?;
+                SessionStore::set_status_in(conn, &run.session_id, SESSION_STATUS_RUNNING).await?;
+                Ok(record)
+            })
+        })
+        .await?;
+        Ok(record.id)
+    }
+
+    // 最终答案、它引用的产物和运行的结束状态在同一个事务中写入
+    async fn end_run(
+        &self,
+        run: &QueuedRun,
+        run_id: &str,
+        status: &str,
+        error: Option<&str>,
+        answer: Option<serde_json::Value>,
+        attachments: &[BlobRef],
+    ) -> Result<()> {
+        with_tx(self.runs.pool(), |conn| {
+            let (session_id, run_id, status) = (run.session_id.clone(), run_id.to_string(), status.to_string());
+            let (error, answer, attachments) = (error.map(str::to_string), answer.clone(), attachments.to_vec());
+            Box::pin(async move {
+                if let Some(answer) = answer {
+                    for blob in &attachments {
+                        ArtifactIndex::record_in(&mut *conn, &session_id, Some(&run_id), blob).await?;
+                    }
+                    SessionStore::add_message_in(&mut *conn, &session_id, "assistant", "orchestrator", answer).await?;
+                }
+                RunStore::end_run_in(conn, &run_id, &status, error.as_deref()).await
+            })
+        })
+        .await
+    }
+
+    // 恢复的运行读取它最新的检查点，新的运行没有
+    async fn load_checkpoint(&self, run: &QueuedRun) -> Result<Option<SessionCheckpoint>> {
+        let Some(run_id) = &run.resume_run_id else {
+            return Ok(None);
+        };
+        match self.runs.run_checkpoint(run_id).await? {
+            Some(checkpoint) => Ok(Some(checkpoint)),
+            None => Err(anyhow!("Run {} has no checkpoint to resume from", run_id)),
+        }
+    }
+
+    /* 返回运行结果、是否被取消，以及运行的完整消息记录；有检查点时从检查点继续。
+    orchestrator 在单独的任务中执行，panic 也会作为错误返回，此时没有消息记录 */
+    async fn run_orchestrator(
+        &self,
+        run: &QueuedRun,
+        run_id: &str,
+        checkpoint: Option<SessionCheckpoint>,
+    ) -> (Result<(RunOutcome, bool)>, Vec<ChatMessage>) {
+        // 下面提前返回时租约随之丢弃，浏览器在后台归还
+        let BuiltRun { mut orchestrator, browser } = match self.factory.build(run).await {
+            Ok(built) => built,
+            Err(e) => return (Err(e), Vec::new()),
+        };
+        orchestrator.set_run_store(self.runs.clone(), run.user_id.clone());
+        // 上传的计划代替模型规划，按这次组装的团队重新校验
+        if let Some(plan) = run.plan.clone() {
+            if let Err(e) = orchestrator.set_plan(plan) {
+                return (Err(e), Vec::new());
+            }
+        }
+        // 临时目录在运行结束后删除
+        let (files, _files_dir) = match self.materialize_files(&run.session_id).await {
+            Ok(materialized) => materialized,
+            Err(e) => return (Err(e), Vec::new()),
+        };
+        self.meter_model_client(&mut orchestrator);
+        if let Err(e) = self.limit_model_client(&mut orchestrator, run).await {
+            return (Err(e), Vec::new());
+        }
+
+        let handle = RunHandle {
+            run_id: run_id.to_string(),
+            session_id: run.session_id.clone(),
+            events: broadcast::channel(256).0,
+            messages: orchestrator.user_message_queue(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            browser: Arc::new(Mutex::new(browser)),
+        };
+        let log = EventLog {
+            runs: self.runs.clone(),
+            blobs: self.blobs.clone(),
+            artifacts: ArtifactIndex::new(self.sessions.pool().clone()),
+        };
+        let forwarder = tokio::spawn(forward_events(orchestrator.subscribe_events(), handle.clone(), log, self.events.clone()));
+        self.set_active(&run.session_id, Some(handle.clone()));
+
+        let task = run.task.clone();
+        let opts = RunOptions {
+            user_id: run.user_id.clone(),
+            run_id: Some(run_id.to_string()),
+            files,
+            attachments: run.attachments.clone(),
+            ..RunOptions::default()
+        };
+        let result = tokio::spawn(async move {
+            let result = match checkpoint {
+                Some(checkpoint) => orchestrator.resume_checkpoint(checkpoint, opts).await,
+                None => orchestrator.run_task(task, opts).await,
+            };
+            (result, orchestrator.transcript())
+        })
+        .await;
+
+        // orchestrator 已经停止（包括取消和 panic），浏览器不再被使用
+        handle.release_browser().await;
+        self.set_active(&run.session_id, None);
+        // orchestrator 已经释放，事件通道关闭后转发任务结束
+        if let Err(e) = forwarder.await {
+            tracing::warn!("Event forwarding for run {} exited abnormally: {}", run_id, e);
+        }
+        match result {
+            Ok((run_result, transcript)) => (run_result.map(|outcome| (outcome, handle.is_cancelled())), transcript),
+            Err(e) => (Err(join_error(e)), Vec::new()),
+        }
+    }
+
+    /* 会话的上传文件从 BlobStore 写到临时目录的 <file-id>/<name>，保留原来的文件名，
+    网页上看到的是用户上传时的名字；没有上传文件时不创建目录 */
+    async fn materialize_files(&self, session_id: &str) -> Result<(Option<SessionFiles>, Option<tempfile::TempDir>)> {
+        let records = self.files.list_files(session_id).await?;
+        if records.is_empty() {
+            return Ok((None, None));
+        }
+        let dir = tempfile::Builder::new().prefix("magentic-files-").tempdir()?;
+        let mut files = Vec::with_capacity(records.len());
+        for record in records {
+            let bytes = self
+                .blobs
+                .get(&record.key)
+                .await?
+                .ok_or_else(|| anyhow!("File {} is missing from the {} store", record.id, record.store))?;
+            let path = dir.path().join(&record.id).join(&record.name);
+            tokio::fs::create_dir_all(dir.path().join(&record.id)).await?;
+            tokio::fs::write(&path, &bytes).await?;
+            files.push(SessionFile { id: record.id, name: record.name, size: bytes.len() as u64, path });
+        }
+        Ok((Some(SessionFiles::new(files)), Some(dir)))
+    }
+
+    // 计量在限速之内，耗时不包括等待限速的时间
+    fn meter_model_client(&self, orchestrator: &mut Orchestrator) {
+        let (provider, model) = match self.model_labels.get() {
+            Some((provider, model)) => (provider.as_str(), model.as_str()),
+            None => ("unknown", "unknown"),
+        };
+        let client = MeteredClient::new(orchestrator.model_client(), self.metrics.clone(), provider, model);
+        orchestrator.set_model_client(Arc::new(client));
+    }
+
+    // orchestrator 的模型调用按用户限速，并在调用前后检查和累计当天的 token
+    async fn limit_model_client(&self, orchestrator: &mut Orchestrator, run: &QueuedRun) -> Result<()> {
+        let (Some(quotas), Some(user_id)) = (self.quotas.get(), &run.user_id) else {
+            return Ok(());
+        };
+        let quota = quotas.quota_for(user_id).await?;
+        let client = RateLimitedClient::new(orchestrator.model_client(), quotas.limiter(), user_id.clone())
+            .with_meter(quotas.meter(user_id, quota));
+        orchestrator.set_model_client(Arc::new(client));
+        Ok(())
+    }
+
+    // 代理自己的模型调用不经过限速客户端，运行结束后按统计一并计入当天的 token
+    async fn record_agent_usage(&self, run: &QueuedRun, outcome: &RunOutcome) {
+        let (Some(quotas), Some(user_id)) = (self.quotas.get(), &run.user_id) else {
+            return;
+        };
+        let mut usage = TokenUsage::default();
+        for agent_usage in outcome.metrics.agent_usage.values() {
+            usage.add(agent_usage);
+        }
+        if let Err(e) = quotas.record_tokens(user_id, &usage).await {
+            tracing::warn!("Failed to record the token usage of user {}: {:?}", user_id, e);
+        }
+    }
+
+    /* 把运行过程中的消息依次写入会话，第一条是任务本身，提交时已经写入。
+    图片先存入 BlobStore，消息和它引用的 artifacts 记录再在同一个事务中写入，消息中只保存引用；
+    最终答案由调用方写入，这里返回它的附件 */
+    async fn save_transcript(&self, run: &QueuedRun, run_id: &str, transcript: &[ChatMessage]) -> Result<Vec<BlobRef>> {
+        let mut final_attachments = Vec::new();
+        for message in transcript.iter().skip(1) {
+            let (text, attachments) = self.store_content(message).await?;
+            if message.metadata().get(MESSAGE_KIND_KEY).map(String::as_str) == Some(FINAL_ANSWER_MESSAGE_KIND) {
+                final_attachments = attachments;
+                continue;
+            }
+            let content = json!({
+                "text": text,
+                "run_id": run_id,
+                "attachments": attachments,
+                "metadata": message.metadata(),
+            });
+            let role = role_name(message);
+            with_tx(self.sessions.pool(), |conn| {
+                let (session_id, run_id, source) = (run.session_id.clone(), run_id.to_string(), message.source().to_string());
+                let (content, attachments) = (content.clone(), attachments.clone());
+                Box::pin(async move {
+                    for blob in &attachments {
+                        ArtifactIndex::record_in(&mut *conn, &session_id, Some(&run_id), blob).await?;
+                    }
+                    SessionStore::add_message_in(conn, &session_id, role, &source, content).await?;
+                    Ok(())
+                })
+            })
+            .await?;
+        }
+        Ok(final_attachments)
+    }
+
+    // 消息的文本和图片引用，key 由内容决定，同样的截图只保存一份
+    async fn store_content(&self, message: &ChatMessage) -> Result<(String, Vec<BlobRef>)> {
+        let parts = match message {
+            ChatMessage::Text { content, .. } => return Ok((content.clone(), Vec::new())),
+            ChatMessage::MultiModal { content, .. } => content,
+        };
+        let mut texts = Vec::new();
+        let mut attachments = Vec::new();
+        for part in parts {
+            match part {
+                MultiModalContent::Text(text) => texts.push(text.as_str()),
+                MultiModalContent::Image(bytes) => {
+                    attachments.push(self.blobs.put(bytes, sniff_mime(bytes)).await?);
+                }
+            }
+        }
+        Ok((texts.join("\n"), attachments))
+    }
+
+    fn set_active(&self, session_id: &str, handle: Option<RunHandle>) {
+        if let Some(slot) = self.slots.lock().unwrap().get_mut(session_id) {
+            slot.active = handle;
+        }
+    }
+
+    // 一个运行结束（或没能入队），返回该会话是否还有排队的运行
+    fn release(&self, session_id: &str) -> bool {
+        let mut slots = self.slots.lock().unwrap();
+        let Some(slot) = slots.get_mut(session_id) else {
+            return false;
+        };
+        slot.pending = slot.pending.saturating_sub(1);
+        if slot.pending == 0 {
+            slots.remove(session_id);
+            return false;
+        }
+        true
+    }
+}
+
+// 把 orchestrator 的事件按顺序写入 run_events，再转发给订阅了这次运行的连接，订阅者错过的事件总能从表中补上
+async fn forward_events(
+    mut source: broadcast::Receiver<OrchestratorEvent>,
+    handle: RunHandle,
+    log: EventLog,
+    all: broadcast::Sender<RunEvent>,
+) {
+    let mut seq = 0i64;
+    loop {
+        match source.recv().await {
+            Ok(event) => {
+                handle.reapply_cancel();
+                seq += 1;
+                let emitted_at_ms = chrono::Utc::now().timestamp_millis();
+                let data = log.sanitize(&handle, &event).await;
+                if let Err(e) = log.runs.add_event(&handle.run_id, seq, &data, emitted_at_ms).await {
+                    tracing::warn!("Failed to persist event of run {}: {:?}", handle.run_id, e);
+                }
+                let _ = handle.events.send(event.clone());
+                let _ = all.send(RunEvent {
+                    run_id: handle.run_id.clone(),
+                    session_id: handle.session_id.clone(),
+                    seq,
+                    event,
+                    data,
+                });
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!("Run {} skipped {} events", handle.run_id, skipped);
+                seq += skipped as i64;
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+// 运行事件的日志：写入之前清理凭据，内嵌的图片存入 BlobStore 并记为这次运行的产物，日志中只保存引用
+struct EventLog {
+    runs: RunStore,
+    blobs: Arc<dyn BlobStore>,
+    artifacts: ArtifactIndex,
+}
+
+impl EventLog {
+    // 图片保存失败时只是在日志中省略它
+    async fn sanitize(&self, handle: &RunHandle, event: &OrchestratorEvent) -> Value {
+        let mut data = match serde_json::to_value(event) {
+            Ok(data) => data,
+            Err(e) => return json!({ "type": "unserializable", "error": e.to_string() }),
+        };
+        let mut refs = HashMap::new();
+        for image in inline_images(&data) {
+            match self.store_image(handle, &image.bytes, &image.mime).await {
+                Ok(key) => {
+                    refs.insert(image.data_url, artifact_ref(&key));
+                }
+                Err(e) => tracing::warn!("Failed to store an image from run {}: {:?}", handle.run_id, e),
+            }
+        }
+        sanitize_event(&mut data, &refs);
+        data
+    }
+
+    async fn store_image(&self, handle: &RunHandle, bytes: &[u8], mime: &str) -> Result<String> {
+        let blob = self.blobs.put(bytes, mime).await?;
+        self.artifacts.record(&handle.session_id, Some(&handle.run_id), &blob).await?;
+        Ok(blob.key)
+    }
+}
+
+fn role_name(message: &ChatMessage) -> &'static str {
+    let role = match message {
+        ChatMessage::Text { role, .. } => role,
+        ChatMessage::MultiModal { role, .. } => role,
+    };
+    match role {
+        MessageRole::User => "user",
+        MessageRole::Assistant => "assistant",
+        MessageRole::System => "system",
+        MessageRole::Tool => "tool",
+    }
+}
+
+fn join_error(error: JoinError) -> anyhow::Error {
+    if !error.is_panic() {
+        return anyhow!("The run was aborted: {}", error);
+    }
+    let panic = error.into_panic();
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    anyhow!("The run panicked: {}", message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::quotas::QuotaExceeded;
+    use crate::config::QuotaSettings;
+    use crate::database::{Quota, QuotaOverride, QuotaStore};
+    use crate::testing::{direct_answer_json, ledger_json, plan_json, MessageLog, MockAgent, MockProvider, OrchestratorBuilder};
+    use crate::storage::LocalDirStore;
+    use chrono::Utc;
+    use sqlx::PgPool;
+
+    // 按任务内容给出直接回答；任务为 "explode" 时组装失败
+    struct ScriptedFactory {
+        built: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl OrchestratorFactory for ScriptedFactory {
+        async fn build(&self, run: &QueuedRun) -> Result<BuiltRun> {
+            self.built.lock().unwrap().push(run.task.clone());
+            if run.task == "explode" {
+                return Err(anyhow!("No model client is configured"));
+            }
+            let answer = format!("Answer to {}", run.task);
+            OrchestratorBuilder::new()
+                .provider(Arc::new(MockProvider::new().respond_json(direct_answer_json(&run.task, &answer))))
+                .agent("Browses the web", MockAgent::new("web_surfer"))
+                .build()
+                .await
+                .map(Into::into)
+        }
+    }
+
+    /* 两步计划：web_surfer 搜索，coder_agent 汇总。第一次运行在第一步完成后模型调用失败；
+    恢复的运行只有第二步的脚本，记录每次组装的代理收到的消息和 web_surfer 的状态 */
+    #[derive(Default)]
+    struct InterruptedFactory {
+        resumed: AtomicBool,
+        logs: Mutex<Vec<(MessageLog, MessageLog)>>,
+        web_states: Mutex<Vec<Arc<Mutex<Option<serde_json::Value>>>>>,
+    }
+
+    #[async_trait]
+    impl OrchestratorFactory for InterruptedFactory {
+        async fn build(&self, run: &QueuedRun) -> Result<BuiltRun> {
+            let (provider, web_surfer) = if self.resumed.load(Ordering::SeqCst) {
+                let provider = MockProvider::new()
+                    .respond_json(ledger_json(false, false, "coder_agent", "Summarize the menu"))
+                    .respond_json(ledger_json(true, false, "coder_agent", "Nothing left to do"))
+                    .respond("The menu has three dishes.");
+                (provider, MockAgent::new("web_surfer"))
+            } else {
+                let provider = MockProvider::new()
+                    .respond_json(plan_json(&run.task, &[
+                        ("Search", "Search for the menu", "web_surfer"),
+                        ("Summarize", "Summarize the menu", "coder_agent"),
+                    ]))
+                    .respond_json(ledger_json(false, false, "web_surfer", "Search for the menu"))
+                    .respond_json(ledger_json(true, false, "coder_agent", "Summarize the menu"));
+                let web_surfer = MockAgent::new("web_surfer")
+                    .reply("Found the menu")
+                    .with_state(json!({ "cookies": ["sid=1"] }));
+                (provider, web_surfer)
+            };
+            let coder = MockAgent::new("coder_agent").reply("Three dishes");
+            self.logs.lock().unwrap().push((web_surfer.log(), coder.log()));
+            self.web_states.lock().unwrap().push(web_surfer.state());
+            OrchestratorBuilder::new()
+                .provider(Arc::new(provider))
+                .agent("Browses the web", web_surfer)
+                .agent("Writes code", coder)
+                .build()
+                .await
+                .map(Into::into)
+        }
+    }
+
+    fn queued(session_id: &str, task: &str) -> QueuedRun {
+        QueuedRun {
+            session_id: session_id.to_string(),
+            message_id: uuid::Uuid::new_v4().to_string(),
+            user_id: Some("test-user".to_string()),
+            task: task.to_string(),
+            resume_run_id: None,
+            plan: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_queued_runs_execute_in_order() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let pool = PgPool::connect(&database_url).await?;
+        let runs = RunStore::new(pool.clone());
+        let sessions = SessionStore::new(pool.clone());
+        runs.migrate().await?;
+        let session = sessions.create_session(Some("test-user"), "Executor").await?;
+
+        let factory = Arc::new(ScriptedFactory { built: Mutex::new(Vec::new()) });
+        let blob_dir = tempfile::tempdir()?;
+        let blobs = Arc::new(LocalDirStore::new(blob_dir.path())?);
+        let executor = RunExecutor::new(factory.clone(), runs.clone(), sessions.clone(), blobs, 8);
+        for task in ["first", "second", "explode"] {
+            executor.submit(queued(&session.id, task)).await?;
+        }
+        executor.start(1);
+        executor.drain().await;
+        assert!(executor.submit(queued(&session.id, "late")).await.is_err());
+
+        assert_eq!(*factory.built.lock().unwrap(), vec!["first", "second", "explode"]);
+        let answers: Vec<String> = sessions
+            .list_messages(&session.id, 0, 10)
+            .await?
+            .messages
+            .iter()
+            .filter(|m| m.role == "assistant")
+            .map(|m| m.content_json["text"].as_str().unwrap_or_default().to_string())
+            .collect();
+        assert_eq!(answers, vec!["Answer to first", "Answer to second"]);
+
+        let statuses: Vec<(String, String, String)> =
+            sqlx::query_as(r#"SELECT id, task, status FROM runs WHERE session_id = $1"#)
+                .bind(&session.id)
+                .fetch_all(&pool)
+                .await?;
+        assert_eq!(statuses.len(), 3);
+        for (id, task, status) in &statuses {
+            let detail = runs.run_detail(id).await?.expect("run should exist");
+            match task.as_str() {
+                "explode" => {
+                    assert_eq!(status, RUN_STATUS_FAILED);
+                    let error = detail.facts.iter().find(|f| f.kind == "error").expect("error fact");
+                    assert!(error.content.contains("No model client is configured"));
+                }
+                _ => {
+                    assert_eq!(status, RUN_STATUS_COMPLETED);
+                    assert_eq!(detail.run.final_answer.as_deref(), Some(format!("Answer to {}", task).as_str()));
+                    let events: i64 = sqlx::query_scalar(r#"SELECT COUNT(*) FROM run_events WHERE run_id = $1"#)
+                        .bind(id)
+                        .fetch_one(&pool)
+                        .await?;
+                    assert!(events > 0);
+                }
+            }
+        }
+        let session = sessions.get_session(&session.id, "test-user").await?.expect("session should exist");
+        assert_eq!(session.status, SESSION_STATUS_IDLE);
+        sessions.delete_session(&session.id, "test-user").await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_interrupted_run_resumes_from_checkpoint() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let pool = PgPool::connect(&database_url).await?;
+        let runs = RunStore::new(pool.clone());
+        let sessions = SessionStore::new(pool.clone());
+        runs.migrate().await?;
+        let session = sessions.create_session(Some("test-user"), "Resume").await?;
+        let blob_dir = tempfile::tempdir()?;
+        let blobs: Arc<dyn BlobStore> = Arc::new(LocalDirStore::new(blob_dir.path())?);
+        let factory = Arc::new(InterruptedFactory::default());
+
+        let executor = RunExecutor::new(factory.clone(), runs.clone(), sessions.clone(), blobs.clone(), 8);
+        executor.submit(queued(&session.id, "Summarize the menu")).await?;
+        executor.start(1);
+        executor.drain().await;
+        let run_id: String = sqlx::query_scalar(r#"SELECT id FROM runs WHERE session_id = $1"#)
+            .bind(&session.id)
+            .fetch_one(&pool)
+            .await?;
+        let checkpoint = runs.run_checkpoint(&run_id).await?.expect("checkpoint should exist");
+        assert_eq!(checkpoint.current_step_idx, 1);
+        assert_eq!(checkpoint.agent_states["web_surfer"]["cookies"][0], "sid=1");
+        // 模拟进程在运行中途退出：运行记录停在 running
+        sqlx::query(r#"UPDATE runs SET status = $2 WHERE id = $1"#)
+            .bind(&run_id)
+            .bind(RUN_STATUS_RUNNING)
+            .execute(&pool)
+            .await?;
+
+        // 重新创建执行器，相当于重启后的进程
+        factory.resumed.store(true, Ordering::SeqCst);
+        let executor = RunExecutor::new(factory.clone(), runs.clone(), sessions.clone(), blobs, 8);
+        let stale = executor.recover().await?;
+        assert!(stale.interrupted.contains(&run_id));
+        assert_eq!(runs.list_runs("test-user", Some(RUN_STATUS_INTERRUPTED), 50).await?.iter().filter(|r| r.id == run_id).count(), 1);
+        assert!(executor.resume(&run_id, Some("someone-else")).await?.is_none());
+        let resumed = executor.resume(&run_id, Some("test-user")).await?.expect("run should be resumable");
+        assert_eq!(resumed.status, RUN_STATUS_RUNNING);
+        assert!(executor.resume(&run_id, Some("test-user")).await?.is_none());
+        executor.start(1);
+        executor.drain().await;
+
+        let detail = runs.run_detail(&run_id).await?.expect("run should exist");
+        assert_eq!(detail.run.status, RUN_STATUS_COMPLETED);
+        assert_eq!(detail.run.final_answer.as_deref(), Some("The menu has three dishes."));
+        let outcomes: Vec<crate::database::StepOutcome> = serde_json::from_str(&detail.run.step_outcomes_json)?;
+        assert_eq!(outcomes.len(), 2);
+        // 恢复的运行只执行了第二步，并拿回了浏览器状态
+        let (web_log, coder_log) = factory.logs.lock().unwrap()[1].clone();
+        assert!(web_log.executes().is_empty());
+        assert_eq!(coder_log.executes().len(), 1);
+        let web_state = factory.web_states.lock().unwrap()[1].lock().unwrap().clone();
+        assert_eq!(web_state, Some(json!({ "cookies": ["sid=1"] })));
+        let runs_in_session: i64 = sqlx::query_scalar(r#"SELECT COUNT(*) FROM runs WHERE session_id = $1"#)
+            .bind(&session.id)
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(runs_in_session, 1);
+        sessions.delete_session(&session.id, "test-user").await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_submit_enforces_user_quotas() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let pool = PgPool::connect(&database_url).await?;
+        let runs = RunStore::new(pool.clone());
+        let sessions = SessionStore::new(pool.clone());
+        runs.migrate().await?;
+        let user_id = format!("quota-user-{}", uuid::Uuid::new_v4());
+        let session = sessions.create_session(Some(&user_id), "Quotas").await?;
+        let blob_dir = tempfile::tempdir()?;
+        let blobs: Arc<dyn BlobStore> = Arc::new(LocalDirStore::new(blob_dir.path())?);
+        let factory = Arc::new(ScriptedFactory { built: Mutex::new(Vec::new()) });
+        let store = QuotaStore::new(pool.clone());
+        let settings = QuotaSettings {
+            defaults: Quota { max_concurrent_runs: 1, max_runs_per_day: 2, max_tokens_per_day: 1000 },
+            llm_requests_per_minute: 0,
+        };
+        // 每次重新创建执行器，当天的用量只能从数据库中读出
+        let executor = || {
+            let executor = RunExecutor::new(factory.clone(), runs.clone(), sessions.clone(), blobs.clone(), 8);
+            executor.set_quotas(QuotaGate::new(store.clone(), settings.clone()));
+            executor
+        };
+        let run = |task: &str| QueuedRun { user_id: Some(user_id.clone()), ..queued(&session.id, task) };
+        let rejected = |result: Result<()>| result.unwrap_err().downcast::<QuotaExceeded>().expect("quota error").quota;
+
+        let first = executor();
+        first.submit(run("first")).await?;
+        assert_eq!(rejected(first.submit(run("second")).await), "max_concurrent_runs");
+        first.start(1);
+        first.drain().await;
+
+        let second = executor();
+        second.submit(run("second")).await?;
+        second.start(1);
+        second.drain().await;
+        assert_eq!(store.usage(&user_id, Utc::now().date_naive()).await?.runs, 2);
+
+        let third = executor();
+        assert_eq!(rejected(third.submit(run("third")).await), "max_runs_per_day");
+        store.set_override(&user_id, &QuotaOverride { max_runs_per_day: Some(5), ..QuotaOverride::default() }).await?;
+        third.submit(run("third")).await?;
+        third.start(1);
+        third.drain().await;
+
+        store.add_usage(&user_id, Utc::now().date_naive(), 0, 1000).await?;
+        assert_eq!(rejected(executor().submit(run("fourth")).await), "max_tokens_per_day");
+        assert_eq!(*factory.built.lock().unwrap(), vec!["first", "second", "third"]);
+
+        sessions.delete_session(&session.id, &user_id).await?;
+        sqlx::query(r#"DELETE FROM user_usage WHERE user_id = $1"#).bind(&user_id).execute(&pool).await?;
+        sqlx::query(r#"DELETE FROM user_quotas WHERE user_id = $1"#).bind(&user_id).execute(&pool).await?;
+        Ok(())
+    }
+}
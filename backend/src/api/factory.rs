@@ -0,0 +1,592 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+
+use crate::agents::coder_agent::config::CoderAgentConfig;
+use crate::agents::web_agent::WebAgent;
+use crate::agents::user_proxy::USER_PROXY_DESCRIPTION;
+use crate::agents::{Agent, CoderAgent, DryRunLog, SimulatedAgent, UserProxy};
+use crate::api::executor::{BuiltRun, OrchestratorFactory};
+use crate::api::server::QueuedRun;
+use crate::clients::{ModelRegistry, ModelRole};
+use crate::config::AppConfig;
+use crate::orchestrator::agent_names::remap_plan_steps;
+use crate::orchestrator::config::OrchestratorConfig;
+use crate::orchestrator::message::{ChatMessage, MessageRole};
+use crate::orchestrator::metrics::CostSummaryConfig;
+use crate::orchestrator::orchestrator::Orchestrator;
+use crate::orchestrator::plan::Plan;
+use crate::orchestrator::plan_library::PlanLibrary;
+use crate::orchestrator::validation::{format_validation_errors, validate_plan_steps};
+use crate::tools::approval_guard::{ActionGuard, PolicyGuard};
+use crate::tools::chrome::chrome_ctrl::Chrome;
+use crate::tools::chrome::{BrowserPool, Lease, PooledBrowser};
+use crate::tools::url_status_manager::SitePolicy;
+
+const WEB_SURFER_DESCRIPTION: &str =
+    "A web browsing agent that can open pages, search, click, type, scroll and read the content of websites";
+
+/// 用一次运行借出的浏览器创建 web_surfer
+pub type WebSurferBuilder<B> = Arc<dyn Fn(Arc<B>) -> Result<Box<dyn Agent>> + Send + Sync>;
+
+/* 后端使用的 OrchestratorFactory：规划和进度账本的模型按 [llm] / [models.orchestrator] 创建，
+每次运行从 BrowserPool 借一个浏览器交给 web_surfer，租约随 BuiltRun 交给执行器，运行结束后归还；
+池借满时等待其他运行归还。设置了计划库时规划参考库中相似的成功计划，运行记录由执行器设置；
+设置了 action_guard 时计划、步骤和恢复会话都先交给它审批；设置了 coder_agent 时每次运行另外注册一个新的 CoderAgent；
+设置了 user_proxy 时注册 UserProxy，模型的追问交给终端中的用户回答。
+演练（with_dry_run）时不借浏览器，代理都换成按 sites 检查网址的 SimulatedAgent，预测记录在 DryRunLog 中 */
+pub struct ServerFactory<B: PooledBrowser> {
+    config: OrchestratorConfig,
+    models: ModelRegistry,
+    browsers: BrowserPool<B>,
+    web_surfer: WebSurferBuilder<B>,
+    plan_library: Option<Arc<dyn PlanLibrary>>,
+    action_guard: Option<Arc<dyn ActionGuard>>,
+    coder_agent: Option<(CoderAgentConfig, Option<Arc<dyn ActionGuard>>)>,
+    dry_run: Option<(DryRunLog, SitePolicy)>,
+    user_proxy: Option<Arc<dyn ActionGuard>>,
+}
+
+impl<B: PooledBrowser> ServerFactory<B> {
+    pub fn new(config: OrchestratorConfig, models: ModelRegistry, browsers: BrowserPool<B>, web_surfer: WebSurferBuilder<B>) -> Self {
+        Self { config, models, browsers, web_surfer, plan_library: None, action_guard: None, coder_agent: None, dry_run: None, user_proxy: None }
+    }
+
+    pub fn with_plan_library(mut self, library: Arc<dyn PlanLibrary>) -> Self {
+        self.plan_library = Some(library);
+        self
+    }
+
+    pub fn with_action_guard(mut self, guard: Arc<dyn ActionGuard>) -> Self {
+        self.action_guard = Some(guard);
+        self
+    }
+
+    /// 同时注册 coder_agent，guard 审批第一次执行代码，没有时直接执行
+    pub fn with_coder_agent(mut self, config: CoderAgentConfig, guard: Option<Arc<dyn ActionGuard>>) -> Self {
+        self.coder_agent = Some((config, guard));
+        self
+    }
+
+    /// 同时注册 user_proxy，提问交给 guard 的 get_answer
+    pub fn with_user_proxy(mut self, guard: Arc<dyn ActionGuard>) -> Self {
+        self.user_proxy = Some(guard);
+        self
+    }
+
+    pub fn with_dry_run(mut self, log: DryRunLog, sites: SitePolicy) -> Self {
+        self.dry_run = Some((log, sites));
+        self
+    }
+
+    // 演练中代替 name 的代理，使用与真实代理相同的模型
+    fn simulated(&self, name: &str, description: &str, role: ModelRole) -> Option<Box<dyn Agent>> {
+        let (log, sites) = self.dry_run.as_ref()?;
+        let agent = SimulatedAgent::new(name, description, self.models.client(role), log.clone())
+            .with_url_policy(Arc::new(sites.to_url_status_manager()));
+        Some(Box::new(agent))
+    }
+}
+
+impl ServerFactory<Chrome> {
+    /* 按应用配置组装：orchestrator.config_file 未设置时使用不需要用户参与的默认配置；
+    web_surfer 为使用借出的 Chrome 的 WebAgent，模型按角色取自 ModelRegistry，
+    访问 [sites] 之外的网站和无法撤销的动作按 [approval] 的策略处理，需要询问时交给 prompt（终端中为 CliActionGuard），
+    没有 prompt 时拒绝。有 prompt 时同一个 guard 也交给 orchestrator 审批计划和步骤；
+    没有 prompt 时（后端和一次性运行）计划只按 orchestrator 配置的 plan_approval 处理。
+    有 prompt 时还注册 user_proxy，规划前的追问直接交给 prompt，不受审批策略影响。
+    coder_agent 使用默认配置（不联网），执行代码前同样经过这个 guard */
+    pub fn from_config(config: &AppConfig, browsers: BrowserPool<Chrome>, prompt: Option<Arc<dyn ActionGuard>>) -> Result<Self> {
+        // 没有 orchestrator 配置文件时截图、检查点和花费表仍然按 [output]，哨兵步骤按 [orchestrator] sentinel_tasks
+        let orchestrator = config.orchestrator_config()?.unwrap_or_else(|| OrchestratorConfig {
+            artifacts_dir: config.output.artifacts_dir.clone(),
+            session_dir: config.output.session_dir.clone(),
+            sentinel_tasks_enabled: config.orchestrator.sentinel_tasks,
+            cost_summary: CostSummaryConfig { enabled: config.output.cost_summary, ..CostSummaryConfig::default() },
+            ..OrchestratorConfig::default()
+        });
+        let models = ModelRegistry::from_config(config);
+        let user_proxy = prompt.clone();
+        let guard = approval_guard(config, prompt);
+        let (app, agent_models, agent_guard) = (config.clone(), models.clone(), guard.clone());
+        let web_surfer: WebSurferBuilder<Chrome> = Arc::new(move |chrome| {
+            let mut agent = web_agent(&app, &agent_models, agent_guard.clone());
+            agent.attach_browser(&app.sites, chrome)?;
+            Ok(Box::new(agent))
+        });
+        let factory = Self::new(orchestrator, models, browsers, web_surfer)
+            .with_coder_agent(CoderAgentConfig::default(), Some(guard.clone()));
+        Ok(match user_proxy {
+            Some(prompt) => factory.with_action_guard(guard).with_user_proxy(prompt),
+            None => factory,
+        })
+    }
+}
+
+// 借到浏览器之前的 web_surfer：模型、审批和 [web_agent] 等设置
+fn web_agent(config: &AppConfig, models: &ModelRegistry, guard: Arc<dyn ActionGuard>) -> WebAgent {
+    let mut agent = WebAgent::default();
+    agent.set_models(models);
+    agent.set_action_guard(Some(guard));
+    config.apply_to_web_agent(&mut agent);
+    agent
+}
+
+/// 按 [approval] 的策略决定哪些请求交给 prompt，见 PolicyGuard
+pub fn approval_guard(config: &AppConfig, prompt: Option<Arc<dyn ActionGuard>>) -> Arc<dyn ActionGuard> {
+    Arc::new(PolicyGuard::new(config.approval.policy, prompt, config.approval.approve_all))
+}
+
+#[async_trait]
+impl<B: PooledBrowser> OrchestratorFactory for ServerFactory<B> {
+    async fn build(&self, _run: &QueuedRun) -> Result<BuiltRun> {
+        // 组装失败时租约随之丢弃，浏览器在后台归还
+        let (web_surfer, browser) = match self.simulated("web_surfer", WEB_SURFER_DESCRIPTION, ModelRole::WebAgent) {
+            Some(agent) => (agent, None),
+            None => {
+                let lease = self.browsers.acquire().await?;
+                ((self.web_surfer)(lease.share())?, Some(Box::new(lease) as Box<dyn Lease>))
+            }
+        };
+        let mut agents = vec![(WEB_SURFER_DESCRIPTION.to_string(), web_surfer)];
+        if let Some((config, guard)) = &self.coder_agent {
+            // 写代码不需要看截图，使用与规划相同的文本模型
+            let coder = match self.simulated(&config.name, &config.description, ModelRole::Orchestrator) {
+                Some(agent) => agent,
+                None => {
+                    let mut coder = CoderAgent::new(config.clone(), self.models.client(ModelRole::Orchestrator));
+                    if let Some(guard) = guard {
+                        coder.set_action_guard(guard.clone());
+                    }
+                    Box::new(coder)
+                }
+            };
+            agents.push((config.description.clone(), coder));
+        }
+        if let Some(guard) = &self.user_proxy {
+            agents.push((USER_PROXY_DESCRIPTION.to_string(), Box::new(UserProxy::new(guard.clone()))));
+        }
+        let mut orchestrator = Orchestrator::new(
+            "orchestrator".to_string(),
+            ChatMessage::new_text(MessageRole::User, "user".to_string(), String::new()),
+            agents.iter().map(|(description, _)| description.clone()).collect(),
+            agents.iter().map(|(_, agent)| agent.name().to_string()).collect(),
+            self.models.client(ModelRole::Orchestrator),
+            self.config.clone(),
+            None,
+            None,
+        )
+        .await?;
+        for (_, agent) in agents {
+            orchestrator.register_agent(agent);
+        }
+        // 规划模型不支持图片时，用户附带的图片交给 web_surfer 的模型描述
+        orchestrator.set_caption_client(self.models.client(ModelRole::WebAgent));
+        if let Some(library) = &self.plan_library {
+            orchestrator.set_plan_library(library.clone());
+        }
+        if let Some(guard) = &self.action_guard {
+            orchestrator.set_action_guard(guard.clone());
+        }
+        Ok(BuiltRun { orchestrator, browser })
+    }
+
+    /// 与 Orchestrator::set_plan 相同的代理名映射和校验，团队为 web_surfer 和设置了时的 coder_agent
+    fn check_plan(&self, plan: &Plan) -> Result<()> {
+        let mut team = vec!["web_surfer".to_string()];
+        team.extend(self.coder_agent.as_ref().map(|(config, _)| config.name.clone()));
+        let mut plan = plan.clone();
+        remap_plan_steps(&mut plan.steps, &team, &self.config.agent_aliases);
+        validate_plan_steps(&plan, &team, self.config.sentinel_tasks_enabled, &self.config.plan_validation)
+            .map_err(|errors| anyhow!("The plan is invalid: {}", format_validation_errors(&errors)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use axum::http::header;
+    use axum::response::IntoResponse;
+    use axum::routing::post;
+    use axum::Json;
+    use serde_json::{json, Value};
+
+    use crate::cli::terminal::TerminalRunner;
+    use crate::config::{ConfigSources, WebAgentSettings};
+    use crate::orchestrator::config::StepApprovalPolicy;
+    use crate::orchestrator::metrics::OrchestratorMetrics;
+    use crate::orchestrator::plan::Plan;
+    use crate::orchestrator::session::{CheckpointReason, SessionCheckpoint};
+    use crate::orchestrator::types::{OrchestratorState, RunOptions};
+    use crate::testing::{
+        direct_answer_json, ledger_json, mock_browser_pool, plan_json, test_config, MockAgent, MockBrowser, MockGuard,
+    };
+
+    // 按顺序给出回答的模型接口，最后一个回答一直重复，流式请求按 SSE 返回；记录请求的模型名
+    async fn mock_api(replies: Vec<String>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let models = Arc::new(Mutex::new(Vec::new()));
+        let recorded = models.clone();
+        let replies = Arc::new(Mutex::new(replies));
+        let app = axum::Router::new().route(
+            "/chat/completions",
+            post(move |Json(body): Json<Value>| {
+                recorded.lock().unwrap().push(body["model"].as_str().unwrap_or_default().to_string());
+                let content = {
+                    let mut replies = replies.lock().unwrap();
+                    if replies.len() > 1 { replies.remove(0) } else { replies[0].clone() }
+                };
+                async move {
+                    if body["stream"] == true {
+                        let sse = format!(
+                            "data: {}\n\ndata: {}\n\ndata: [DONE]\n\n",
+                            json!({ "choices": [{ "index": 0, "delta": { "content": content } }] }),
+                            json!({ "choices": [{ "index": 0, "delta": {}, "finish_reason": "stop" }] }),
+                        );
+                        return ([(header::CONTENT_TYPE, "text/event-stream")], sse).into_response();
+                    }
+                    Json(json!({
+                        "choices": [{ "message": { "role": "assistant", "content": content }, "finish_reason": "stop" }],
+                        "usage": { "prompt_tokens": 3, "completion_tokens": 1 }
+                    }))
+                    .into_response()
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (base_url, models)
+    }
+
+    fn queued_run(task: &str) -> QueuedRun {
+        QueuedRun {
+            session_id: "session".to_string(),
+            message_id: "message".to_string(),
+            user_id: None,
+            task: task.to_string(),
+            resume_run_id: None,
+            plan: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    // 模型按 replies 依次回答，web_surfer 为 agent
+    async fn scripted_factory(config: OrchestratorConfig, replies: Vec<Value>, agent: MockAgent) -> ServerFactory<MockBrowser> {
+        // 字符串按原文回答，其余按 JSON
+        let replies = replies.iter().map(|reply| reply.as_str().map(str::to_string).unwrap_or_else(|| reply.to_string()));
+        let (base_url, _) = mock_api(replies.collect()).await;
+        let mut app = AppConfig::default();
+        app.llm.base_url = Some(base_url);
+        let agent = Mutex::new(Some(agent));
+        let web_surfer: WebSurferBuilder<MockBrowser> = Arc::new(move |_| {
+            let agent = agent.lock().unwrap().take().expect("one run per factory");
+            Ok(Box::new(agent))
+        });
+        ServerFactory::new(config, ModelRegistry::from_config(&app), mock_browser_pool(1), web_surfer)
+    }
+
+    #[tokio::test]
+    async fn test_runs_lease_a_browser_and_use_the_configured_model() -> Result<()> {
+        let task = "What is the capital of France?";
+        let (base_url, requested) = mock_api(vec![direct_answer_json(task, "Paris").to_string()]).await;
+        let mut config = AppConfig::default();
+        config.llm.base_url = Some(base_url);
+        config.llm.model = Some("qwen-plus".to_string());
+
+        let browsers = mock_browser_pool(1);
+        // 记录每个 web_surfer 拿到的浏览器
+        let leased = Arc::new(Mutex::new(Vec::new()));
+        let seen = leased.clone();
+        let web_surfer: WebSurferBuilder<MockBrowser> = Arc::new(move |browser: Arc<MockBrowser>| {
+            seen.lock().unwrap().push(browser.id);
+            Ok(Box::new(MockAgent::new("web_surfer")))
+        });
+        let factory = ServerFactory::new(
+            OrchestratorConfig::default(),
+            ModelRegistry::from_config(&config),
+            browsers.clone(),
+            web_surfer,
+        );
+        let run = queued_run(task);
+
+        for _ in 0..2 {
+            let BuiltRun { mut orchestrator, browser } = factory.build(&run).await?;
+            assert_eq!(browsers.stats().in_use, 1);
+            let outcome = orchestrator.run_task(task.to_string(), RunOptions::default()).await?;
+            assert!(outcome.final_answer.contains("Paris"), "{}", outcome.final_answer);
+            drop(orchestrator);
+            browser.expect("the run leases a browser").release().await;
+            assert_eq!(browsers.stats().in_use, 0);
+        }
+
+        // 第二次运行复用了归还并重置的同一个浏览器
+        assert_eq!(*leased.lock().unwrap(), [0, 0]);
+        let stats = browsers.stats();
+        assert_eq!((stats.created, stats.resets), (1, 2));
+        assert!(!requested.lock().unwrap().is_empty());
+        assert!(requested.lock().unwrap().iter().all(|model| model == "qwen-plus"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_web_surfer_settings_come_from_the_config() -> Result<()> {
+        // 每一项：配置项、从 WebAgent 读出的值、按配置项设置后应有的值
+        type Setting = (&'static str, fn(&WebAgent) -> String, &'static str);
+        let settings: [Setting; 8] = [
+            ("browser.uploads_dir=uploads", |agent| format!("{:?}", agent.uploads_dir()), r#"Some("uploads")"#),
+            ("web_agent.max_steps=4", |agent| agent.step_limits().max_steps.to_string(), "4"),
+            ("web_agent.per_step_timeout_secs=30", |agent| format!("{:?}", agent.step_limits().per_step_timeout), "Some(30s)"),
+            ("web_agent.overall_deadline_secs=300", |agent| format!("{:?}", agent.step_limits().overall_deadline), "Some(300s)"),
+            ("web_agent.on_step_timeout=abort", |agent| format!("{:?}", agent.step_limits().on_step_timeout), "Abort"),
+            ("web_agent.page_max_tokens=2000", |agent| agent.page_max_tokens().to_string(), "2000"),
+            ("web_agent.max_sleep_secs=15", |agent| agent.max_sleep_secs().to_string(), "15"),
+            ("web_agent.include_raw_screenshot=true", |agent| agent.include_raw_screenshot().to_string(), "true"),
+        ];
+        // [web_agent] 的每一项都在表中
+        for key in serde_json::to_value(WebAgentSettings::default())?.as_object().unwrap().keys() {
+            let prefix = format!("web_agent.{}=", key);
+            assert!(settings.iter().any(|(setting, ..)| setting.starts_with(&prefix)), "web_agent.{} is not checked", key);
+        }
+
+        let config = AppConfig::load(&ConfigSources {
+            overrides: settings.iter().map(|(setting, ..)| setting.to_string()).collect(),
+            ..Default::default()
+        })?;
+        let agent = web_agent(&config, &ModelRegistry::from_config(&config), approval_guard(&config, None));
+        let unset = AppConfig::default();
+        let default = web_agent(&unset, &ModelRegistry::from_config(&unset), approval_guard(&unset, None));
+        for (setting, value, expected) in settings {
+            assert_eq!(value(&agent), expected, "{}", setting);
+            // 未设置的项保持 WebAgent 的默认值
+            assert_eq!(value(&default), value(&WebAgent::default()), "{}", setting);
+            assert_ne!(value(&default), expected, "{} does not change the default", setting);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_runs_ask_the_action_guard_before_each_step() -> Result<()> {
+        let task = "Find the opening hours";
+        let replies = vec![
+            plan_json(task, &[("Search", "Search for the opening hours", "web_surfer")]),
+            ledger_json(false, false, "web_surfer", "Search for the opening hours"),
+            ledger_json(true, false, "web_surfer", "Nothing left to do"),
+            json!("Open from 9 to 5."),
+        ];
+        let config = OrchestratorConfig { step_approval: StepApprovalPolicy::Always, ..test_config() };
+        let web_surfer = MockAgent::new("web_surfer").reply("Open from 9 to 5");
+        let log = web_surfer.log();
+        let guard = Arc::new(MockGuard::approve_all());
+        let factory = scripted_factory(config, replies, web_surfer).await.with_action_guard(guard.clone());
+
+        let BuiltRun { mut orchestrator, .. } = factory.build(&queued_run(task)).await?;
+        orchestrator.run_task(task.to_string(), RunOptions::default()).await?;
+
+        // 第一步经过了工厂设置的 guard
+        let requests = guard.requests();
+        assert!(requests.iter().any(|r| r.starts_with("Step 1: Search")), "{:?}", requests);
+        assert_eq!(log.executes().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_clarification_questions_are_asked_through_the_user_proxy() -> Result<()> {
+        let task = "Book a table";
+        let mut clarification = direct_answer_json(task, "");
+        clarification["needs_clarification"] = json!(true);
+        clarification["clarification_question"] = json!("For which day and how many people?");
+        let replies = vec![
+            clarification,
+            plan_json(task, &[("Book", "Book a table for two tomorrow", "web_surfer")]),
+            ledger_json(false, false, "web_surfer", "Book a table for two tomorrow"),
+            ledger_json(true, false, "web_surfer", "Nothing left to do"),
+            json!("Booked a table for two tomorrow."),
+        ];
+        let web_surfer = MockAgent::new("web_surfer").reply("Booked");
+        let log = web_surfer.log();
+        let prompt = Arc::new(MockGuard::approve_all().with_answer("Tomorrow, two people"));
+        let factory = scripted_factory(test_config(), replies, web_surfer).await.with_user_proxy(prompt.clone());
+
+        let BuiltRun { mut orchestrator, .. } = factory.build(&queued_run(task)).await?;
+        let outcome = orchestrator.run_task(task.to_string(), RunOptions::default()).await?;
+
+        // 追问交给了用户，回答之后按计划执行
+        assert_eq!(prompt.requests(), ["For which day and how many people?"]);
+        let executes = log.executes();
+        assert_eq!(executes.len(), 1);
+        assert!(outcome.final_answer.contains("Booked a table for two tomorrow."), "{}", outcome.final_answer);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_uploaded_plans_are_checked_against_the_team() -> Result<()> {
+        let plan = |agent: &str| Plan::from_list_of_dicts_or_str(json!({
+            "task": "Check the opening hours",
+            "steps": [{ "title": "Search", "details": "Search for the opening hours", "agent_name": agent }]
+        }))
+        .unwrap();
+        let factory = scripted_factory(test_config(), Vec::new(), MockAgent::new("web_surfer")).await;
+        factory.check_plan(&plan("web_surfer"))?;
+        // 没有注册 coder_agent 时不能交给它
+        let error = factory.check_plan(&plan("coder_agent")).unwrap_err();
+        assert!(error.to_string().starts_with("The plan is invalid"), "{}", error);
+        let factory = factory.with_coder_agent(CoderAgentConfig::default(), None);
+        factory.check_plan(&plan("coder_agent"))?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_coder_agent_steps_run_code() -> Result<()> {
+        let task = "Write hello to a file";
+        let replies = vec![
+            plan_json(task, &[("Write", "Write hello to hello.txt with Python", "coder_agent")]),
+            ledger_json(false, false, "coder_agent", "Write hello to hello.txt with Python"),
+            json!("```python\nopen('hello.txt', 'w').write('hello')\n```"),
+            json!("The script wrote hello.txt."),
+            ledger_json(true, false, "coder_agent", "Nothing left to do"),
+            json!("hello.txt contains hello."),
+        ];
+        let dir = tempfile::tempdir()?;
+        let guard = Arc::new(MockGuard::approve_all());
+        // 测试环境不一定允许创建网络命名空间
+        let coder = CoderAgentConfig {
+            allow_network: true,
+            timeout_secs: 10,
+            work_dir: Some(dir.path().display().to_string()),
+            ..CoderAgentConfig::default()
+        };
+        let factory = scripted_factory(test_config(), replies, MockAgent::new("web_surfer"))
+            .await
+            .with_coder_agent(coder, Some(guard.clone()));
+
+        let BuiltRun { mut orchestrator, .. } = factory.build(&queued_run(task)).await?;
+        let outcome = orchestrator.run_task(task.to_string(), RunOptions::default()).await?;
+
+        // 执行代码前经过 guard，代码在 coder_agent 的工作目录中执行
+        let requests = guard.requests();
+        assert_eq!(requests.len(), 1, "{:?}", requests);
+        assert!(requests[0].starts_with("coder_agent wants to execute the following code"), "{}", requests[0]);
+        let work_dir = std::fs::read_dir(dir.path())?.next().expect("the run has a working directory")?.path();
+        assert_eq!(std::fs::read_to_string(work_dir.join("hello.txt"))?, "hello");
+        assert!(outcome.final_answer.contains("hello.txt contains hello."), "{}", outcome.final_answer);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dry_runs_simulate_the_steps_without_a_browser() -> Result<()> {
+        let task = "Find the menu";
+        let replies = vec![
+            plan_json(task, &[("Find the menu", "Open the restaurant menu", "web_surfer")]),
+            ledger_json(false, false, "web_surfer", "Open the restaurant menu"),
+            json!({
+                "actions": [{ "tool": "visit_url", "description": "Open the restaurant site", "url": "https://roma.example.org/menu" }],
+                "summary": "The menu page would be open",
+            }),
+            ledger_json(true, false, "web_surfer", "Nothing left to do"),
+            json!("The menu would be on roma.example.org."),
+        ];
+        let web_surfer = MockAgent::new("web_surfer");
+        let log = web_surfer.log();
+        let dry_run = DryRunLog::default();
+        let sites = SitePolicy { allowed_sites: vec!["wikipedia.org".to_string()], ..SitePolicy::default() };
+        let factory = Arc::new(scripted_factory(test_config(), replies, web_surfer).await.with_dry_run(dry_run.clone(), sites));
+
+        // 与 run --dry-run 相同
+        let mut out = Vec::new();
+        TerminalRunner::non_interactive(factory.clone()).dry_run(Some(dry_run)).run(task, &mut out).await?;
+
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("[SIMULATED] Dry-run report, nothing was executed."), "{}", out);
+        assert!(out.contains("[needs approval: https://roma.example.org/menu]"), "{}", out);
+        // 没有借浏览器，也没有调用真实的 web_surfer
+        assert_eq!(factory.browsers.stats().created, 0);
+        assert!(log.executes().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejected_plans_are_not_executed() -> Result<()> {
+        let task = "Buy a concert ticket";
+        let replies = vec![
+            plan_json(task, &[("Buy", "Buy the cheapest ticket", "web_surfer")]),
+            json!("The plan was not approved."),
+        ];
+        let web_surfer = MockAgent::new("web_surfer").reply("Bought a ticket");
+        let log = web_surfer.log();
+        let guard = Arc::new(MockGuard::deny_all());
+        let factory = scripted_factory(test_config(), replies, web_surfer).await.with_action_guard(guard.clone());
+
+        // 与 interactive 子命令相同，终端中的运行先审批计划
+        let runner = TerminalRunner::non_interactive(Arc::new(factory)).approve_plans(true);
+        let outcome = runner.run(task, &mut Vec::new()).await?;
+
+        let requests = guard.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("Do you approve the following plan?"), "{}", requests[0]);
+        assert!(requests[0].contains("Buy (web_surfer)"), "{}", requests[0]);
+        assert!(log.executes().is_empty());
+        assert!(outcome.final_answer.contains("not approved"), "{}", outcome.final_answer);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resume_asks_before_continuing_the_session() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let task = "Find the opening hours";
+        let mut plan: Plan = serde_json::from_value(plan_json(task, &[("Search", "Search for the opening hours", "web_surfer")]))?;
+        plan.ensure_unique_step_ids();
+        let state = OrchestratorState { task: task.to_string(), plan: Some(plan), ..OrchestratorState::default() };
+        SessionCheckpoint::capture(&state, &OrchestratorMetrics::new(), CheckpointReason::PlanAccepted, true)
+            .unwrap()
+            .save(dir.path())?;
+
+        let web_surfer = MockAgent::new("web_surfer").reply("Open from 9 to 5");
+        let log = web_surfer.log();
+        let guard = Arc::new(MockGuard::deny_all());
+        let factory = scripted_factory(test_config(), vec![json!("unused")], web_surfer).await.with_action_guard(guard.clone());
+
+        // 与终端中的 resume 子命令相同
+        let runner = TerminalRunner::non_interactive(Arc::new(factory)).approve_plans(true);
+        let outcome = runner.resume(dir.path(), &mut Vec::new()).await?;
+
+        let requests = guard.requests();
+        assert_eq!(requests.len(), 1, "{:?}", requests);
+        assert!(requests[0].starts_with("Resume the following session?"), "{}", requests[0]);
+        assert!(log.executes().is_empty());
+        assert!(outcome.final_answer.contains("not resumed"), "{}", outcome.final_answer);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_auto_conservative_policy_only_asks_for_the_plan() -> Result<()> {
+        let task = "Find the opening hours";
+        let replies = vec![
+            plan_json(task, &[("Search", "Search for the opening hours", "web_surfer")]),
+            ledger_json(false, false, "web_surfer", "Search for the opening hours"),
+            ledger_json(true, false, "web_surfer", "Nothing left to do"),
+            json!("Open from 9 to 5."),
+        ];
+        let config = OrchestratorConfig { step_approval: StepApprovalPolicy::Always, ..test_config() };
+        let web_surfer = MockAgent::new("web_surfer").reply("Open from 9 to 5");
+        let log = web_surfer.log();
+        // 与 --approval-policy auto-conservative 相同
+        let app = AppConfig::load(&ConfigSources {
+            overrides: vec!["approval.policy=auto-conservative".to_string()],
+            ..Default::default()
+        })?;
+        let prompt = Arc::new(MockGuard::approve_all());
+        let factory = scripted_factory(config, replies, web_surfer).await.with_action_guard(approval_guard(&app, Some(prompt.clone())));
+
+        TerminalRunner::non_interactive(Arc::new(factory)).approve_plans(true).run(task, &mut Vec::new()).await?;
+
+        // 计划交给用户，计划中的步骤自动批准
+        let requests = prompt.requests();
+        assert_eq!(requests.len(), 1, "{:?}", requests);
+        assert!(requests[0].starts_with("Do you approve the following plan?"));
+        assert_eq!(log.executes().len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use axum::body::Body;
+use axum::extract::multipart::{Field, MultipartError, MultipartRejection};
+use axum::extract::{Multipart, Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+
+use crate::api::auth::AuthUser;
+use crate::api::error::ApiError;
+use crate::api::server::AppState;
+use crate::api::sessions::find_session;
+use crate::database::SessionFileRecord;
+
+// multipart 的边界和各部分的头，请求体的上限在文件大小之外留出这些
+pub const MULTIPART_OVERHEAD: usize = 64 * 1024;
+
+const MAX_NAME_BYTES: usize = 255;
+
+/* POST /api/sessions/:id/files：multipart 请求中名为 file 的部分，文件名取自该部分。
+类型不在 uploads.allowed_types 中时 400，超过 uploads.max_file_bytes 时 413。
+内容和产物一样保存在外部存储中，session_files 记录它属于哪个会话，
+之后这个会话的运行可以通过 session://<file-id> 使用它 */
+pub async fn upload_file(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    multipart: Result<Multipart, MultipartRejection>,
+) -> Result<(StatusCode, Json<SessionFileRecord>), ApiError> {
+    let mut multipart = multipart.map_err(|rejection| ApiError::bad_request(rejection.body_text()))?;
+    find_session(&state, &user, &id).await?;
+
+    let uploads = &state.config.uploads;
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let name = field
+            .file_name()
+            .and_then(sanitize_name)
+            .ok_or_else(|| ApiError::bad_request("The file part must have a file name"))?;
+        let mime = field_mime(field.content_type(), &name);
+        if !uploads.allowed_types.iter().any(|allowed| allowed.eq_ignore_ascii_case(&mime)) {
+            return Err(ApiError::bad_request(format!("Files of type {} are not allowed", mime)));
+        }
+        let bytes = read_limited(field, uploads.max_file_bytes).await?;
+        let blob = state.blobs.put(&bytes, &mime).await?;
+        let record = state.files.add_file(&id, &name, &blob).await?;
+        return Ok((StatusCode::CREATED, Json(record)));
+    }
+    Err(ApiError::bad_request("The request has no file part"))
+}
+
+/// GET /api/sessions/:id/files：会话中上传的文件，按上传顺序
+pub async fn list_files(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<SessionFileRecord>>, ApiError> {
+    find_session(&state, &user, &id).await?;
+    Ok(Json(state.files.list_files(&id).await?))
+}
+
+// 作为附件下载，文件名为上传时的名字；其他用户的会话和其他会话的文件一样返回 404
+pub async fn download_file(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path((id, file_id)): Path<(String, String)>,
+) -> Result<Response, ApiError> {
+    find_session(&state, &user, &id).await?;
+    let Some(record) = state.files.get_file(&id, &file_id).await? else {
+        return Err(file_not_found(&file_id));
+    };
+    let Some(bytes) = state.blobs.get(&record.key).await? else {
+        tracing::warn!("File {} is indexed but missing from the {} store", record.id, record.store);
+        return Err(file_not_found(&file_id));
+    };
+    let headers = [
+        (header::CONTENT_TYPE, record.mime),
+        (header::CONTENT_DISPOSITION, content_disposition(&record.name)),
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+    ];
+    Ok((headers, Body::from(bytes)).into_response())
+}
+
+// 边读边检查大小，不把超限的文件整个读进内存
+pub(crate) async fn read_limited(mut field: Field<'_>, max_bytes: u64) -> Result<Vec<u8>, ApiError> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+        if (bytes.len() + chunk.len()) as u64 > max_bytes {
+            return Err(too_large(max_bytes));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+// 请求体超过路由上的限制时也是 413
+pub(crate) fn multipart_error(error: MultipartError) -> ApiError {
+    match error.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", error.body_text()),
+        _ => ApiError::bad_request(error.body_text()),
+    }
+}
+
+fn too_large(max_bytes: u64) -> ApiError {
+    ApiError::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        "payload_too_large",
+        format!("Files must not be larger than {} bytes", max_bytes),
+    )
+}
+
+fn file_not_found(id: &str) -> ApiError {
+    ApiError::not_found(format!("File {} not found", id))
+}
+
+/// 只保留路径中最后一段，去掉控制字符，最长 255 字节；剩下的名字为空或为 . / .. 时为 None
+pub fn sanitize_name(raw: &str) -> Option<String> {
+    let last = raw.rsplit(['/', '\\']).next().unwrap_or_default();
+    let mut name: String = last.chars().filter(|c| !c.is_control()).collect::<String>().trim().to_string();
+    while name.len() > MAX_NAME_BYTES {
+        name.pop();
+    }
+    match name.as_str() {
+        "" | "." | ".." => None,
+        _ => Some(name),
+    }
+}
+
+// 客户端给出的类型优先，没有给出或者是通用的二进制类型时按扩展名判断
+fn field_mime(content_type: Option<&str>, name: &str) -> String {
+    let given = content_type
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .filter(|value| !value.is_empty() && value != "application/octet-stream");
+    given.unwrap_or_else(|| mime_from_name(name).to_string())
+}
+
+fn mime_from_name(name: &str) -> &'static str {
+    let extension = name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).unwrap_or_default();
+    match extension.as_str() {
+        "txt" => "text/plain",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+// filename 只放 ASCII，完整的名字按 RFC 5987 编码在 filename* 中
+fn content_disposition(name: &str) -> String {
+    let fallback: String =
+        name.chars().map(|c| if (c.is_ascii_graphic() && c != '"' && c != '\\') || c == ' ' { c } else { '_' }).collect();
+    let mut encoded = String::new();
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upload_names_and_types() {
+        assert_eq!(sanitize_name("prices.csv").as_deref(), Some("prices.csv"));
+        assert_eq!(sanitize_name("../../etc/passwd").as_deref(), Some("passwd"));
+        assert_eq!(sanitize_name("C:\\Users\\me\\report.pdf").as_deref(), Some("report.pdf"));
+        assert_eq!(sanitize_name(" a\u{0}b\n.txt ").as_deref(), Some("ab.txt"));
+        assert_eq!(sanitize_name(&"x".repeat(300)).map(|name| name.len()), Some(255));
+        for invalid in ["", "dir/", "..", " . "] {
+            assert_eq!(sanitize_name(invalid), None, "{:?}", invalid);
+        }
+
+        assert_eq!(field_mime(Some("text/csv; charset=utf-8"), "prices.csv"), "text/csv");
+        assert_eq!(field_mime(Some("application/octet-stream"), "photo.JPG"), "image/jpeg");
+        assert_eq!(field_mime(None, "notes"), "application/octet-stream");
+
+        assert_eq!(
+            content_disposition("价格 \"v2\".csv"),
+            "attachment; filename=\"__ _v2_.csv\"; filename*=UTF-8''%E4%BB%B7%E6%A0%BC%20%22v2%22.csv"
+        );
+    }
+}
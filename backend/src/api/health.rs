@@ -0,0 +1,366 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_openai::config::OpenAIConfig;
+use async_trait::async_trait;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::PgPool;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::api::server::AppState;
+use crate::config::AppConfig;
+use crate::tools::chrome::{BrowserPool, PooledBrowser};
+
+/// 就绪检查中的一项依赖
+#[async_trait]
+pub trait DependencyCheck: Send + Sync {
+    fn name(&self) -> &str;
+    async fn check(&self) -> Result<()>;
+}
+
+/// 数据库：在超时内执行 SELECT 1
+pub struct PostgresCheck {
+    pool: PgPool,
+}
+
+impl PostgresCheck {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl DependencyCheck for PostgresCheck {
+    fn name(&self) -> &str {
+        "postgres"
+    }
+
+    async fn check(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+}
+
+/* 模型服务：列出模型，不消耗 token。配置不完整（缺少 api_key）时检查总是失败，
+原因和配置错误的提示相同 */
+pub struct LlmCheck {
+    config: Result<OpenAIConfig, String>,
+}
+
+impl LlmCheck {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self { config: config.openai_config().map_err(|e| format!("{:#}", e)) }
+    }
+}
+
+#[async_trait]
+impl DependencyCheck for LlmCheck {
+    fn name(&self) -> &str {
+        "llm"
+    }
+
+    async fn check(&self) -> Result<()> {
+        let config = self.config.clone().map_err(|e| anyhow!(e))?;
+        async_openai::Client::with_config(config).models().list().await?;
+        Ok(())
+    }
+}
+
+/// 浏览器池：至少一个实例通过 get_title 探测，见 BrowserPool::probe
+pub struct BrowserPoolCheck<B> {
+    pool: BrowserPool<B>,
+}
+
+impl<B: PooledBrowser> BrowserPoolCheck<B> {
+    pub fn new(pool: BrowserPool<B>) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl<B: PooledBrowser> DependencyCheck for BrowserPoolCheck<B> {
+    fn name(&self) -> &str {
+        "browser"
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.pool.probe().await
+    }
+}
+
+/// 一项依赖最近一次检查的结果；last_error 在恢复之后仍然保留，便于排查时有时无的故障
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DependencyStatus {
+    pub name: String,
+    /// ok | failing
+    pub status: &'static str,
+    /// 为 false 时失败不影响就绪状态
+    pub required: bool,
+    pub latency_ms: u64,
+    pub checked_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+}
+
+impl DependencyStatus {
+    pub fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+struct Dependency {
+    check: Arc<dyn DependencyCheck>,
+    required: bool,
+    // 两次检查的最短间隔，之间的请求使用缓存的结果
+    min_interval: Option<Duration>,
+    // 检查进行中时锁住，同时到来的请求等它完成后使用它的结果
+    last: Mutex<Option<(Instant, DependencyStatus)>>,
+}
+
+impl Dependency {
+    async fn status(&self, timeout: Duration) -> DependencyStatus {
+        let mut last = self.last.lock().await;
+        if let (Some(interval), Some((checked, status))) = (self.min_interval, last.as_ref()) {
+            if checked.elapsed() < interval {
+                return status.clone();
+            }
+        }
+        let started = Instant::now();
+        let result = match tokio::time::timeout(timeout, self.check.check()).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("The check did not finish within {} ms", timeout.as_millis())),
+        };
+        let previous_error = last.as_ref().and_then(|(_, status)| status.last_error.clone());
+        let status = DependencyStatus {
+            name: self.check.name().to_string(),
+            status: if result.is_ok() { "ok" } else { "failing" },
+            required: self.required,
+            latency_ms: started.elapsed().as_millis() as u64,
+            checked_at: Utc::now(),
+            last_error: result.err().map(|e| format!("{:#}", e)).or(previous_error),
+        };
+        *last = Some((started, status.clone()));
+        status
+    }
+}
+
+/// 一次就绪检查的结果：所有必需的依赖都通过时 200，否则 503
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub dependencies: Vec<DependencyStatus>,
+}
+
+impl ReadinessReport {
+    pub fn failing(&self) -> impl Iterator<Item = &DependencyStatus> {
+        self.dependencies.iter().filter(|dependency| !dependency.is_ok())
+    }
+}
+
+impl IntoResponse for ReadinessReport {
+    fn into_response(self) -> Response {
+        let status = if self.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+        (status, Json(self)).into_response()
+    }
+}
+
+/// GET /readyz 检查的依赖，各项并发检查，每项有单独的超时
+#[derive(Clone)]
+pub struct Readiness {
+    dependencies: Arc<Vec<Dependency>>,
+    timeout: Duration,
+}
+
+impl Readiness {
+    pub fn new(timeout: Duration) -> Self {
+        Self { dependencies: Arc::new(Vec::new()), timeout }
+    }
+
+    /// 按配置检查数据库、模型服务和（pool_size 大于 0 时）浏览器池
+    pub fn from_config<B: PooledBrowser>(config: &AppConfig, pool: PgPool, browsers: BrowserPool<B>) -> Self {
+        let health = &config.health;
+        let mut readiness = Self::new(Duration::from_millis(health.timeout_ms.max(1)))
+            .with_dependency(Arc::new(PostgresCheck::new(pool)), true, None)
+            .with_dependency(
+                Arc::new(LlmCheck::from_config(config)),
+                health.llm_required,
+                Some(Duration::from_secs(health.llm_interval_secs)),
+            );
+        if config.browser.pool_size > 0 {
+            readiness = readiness.with_dependency(Arc::new(BrowserPoolCheck::new(browsers)), true, None);
+        }
+        readiness
+    }
+
+    // 只在启动时调用，已经共享出去的 Readiness 不能再添加依赖
+    pub fn with_dependency(
+        mut self,
+        check: Arc<dyn DependencyCheck>,
+        required: bool,
+        min_interval: Option<Duration>,
+    ) -> Self {
+        Arc::get_mut(&mut self.dependencies)
+            .expect("dependencies are added before the readiness checks are shared")
+            .push(Dependency { check, required, min_interval, last: Mutex::new(None) });
+        self
+    }
+
+    pub async fn check(&self) -> ReadinessReport {
+        let dependencies =
+            futures::future::join_all(self.dependencies.iter().map(|dependency| dependency.status(self.timeout))).await;
+        let ready = dependencies.iter().all(|dependency| dependency.is_ok() || !dependency.required);
+        ReadinessReport { ready, dependencies }
+    }
+
+    /// 启动时等到所有必需的依赖都通过，每次失败后间隔 retry 重试并记录失败的依赖
+    pub async fn wait_until_ready(&self, retry: Duration) {
+        loop {
+            let report = self.check().await;
+            if report.ready {
+                return;
+            }
+            for dependency in report.failing().filter(|dependency| dependency.required) {
+                tracing::warn!(
+                    "Waiting for {}: {}",
+                    dependency.name,
+                    dependency.last_error.as_deref().unwrap_or("unknown error")
+                );
+            }
+            tokio::time::sleep(retry).await;
+        }
+    }
+}
+
+/// GET /healthz：进程存活即 200，不检查依赖，不需要 API key
+pub async fn healthz() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+/* GET /readyz：检查数据库、模型服务和浏览器池，不需要 API key。
+body 中给出每项依赖的状态、耗时和最近的错误；模型服务的检查最多每分钟一次 */
+pub async fn readyz(State(state): State<AppState>) -> ReadinessReport {
+    state.readiness.check().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    // 按 healthy 返回结果的依赖，记录检查次数
+    struct StubCheck {
+        name: &'static str,
+        healthy: AtomicBool,
+        calls: AtomicUsize,
+    }
+
+    impl StubCheck {
+        fn new(name: &'static str) -> Arc<Self> {
+            Arc::new(Self { name, healthy: AtomicBool::new(true), calls: AtomicUsize::new(0) })
+        }
+    }
+
+    #[async_trait]
+    impl DependencyCheck for StubCheck {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn check(&self) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            match self.healthy.load(Ordering::SeqCst) {
+                true => Ok(()),
+                false => Err(anyhow!("{} is down", self.name)),
+            }
+        }
+    }
+
+    fn statuses(report: &ReadinessReport) -> Vec<(&str, &str)> {
+        report.dependencies.iter().map(|dependency| (dependency.name.as_str(), dependency.status)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_each_dependency_failing_in_turn() {
+        let postgres = StubCheck::new("postgres");
+        let llm = StubCheck::new("llm");
+        let browser = StubCheck::new("browser");
+        let readiness = Readiness::new(Duration::from_secs(1))
+            .with_dependency(postgres.clone(), true, None)
+            .with_dependency(llm.clone(), true, None)
+            .with_dependency(browser.clone(), true, None);
+
+        let report = readiness.check().await;
+        assert!(report.ready);
+        assert_eq!(statuses(&report), vec![("postgres", "ok"), ("llm", "ok"), ("browser", "ok")]);
+        assert_eq!(report.into_response().status(), StatusCode::OK);
+
+        for (index, stub) in [&postgres, &llm, &browser].into_iter().enumerate() {
+            stub.healthy.store(false, Ordering::SeqCst);
+            let report = readiness.check().await;
+            assert!(!report.ready, "{} is failing", stub.name);
+            for (i, dependency) in report.dependencies.iter().enumerate() {
+                assert_eq!(dependency.status, if i == index { "failing" } else { "ok" });
+            }
+            let failing = &report.dependencies[index];
+            assert_eq!(failing.last_error.as_deref(), Some(format!("{} is down", stub.name).as_str()));
+            assert_eq!(report.into_response().status(), StatusCode::SERVICE_UNAVAILABLE);
+            stub.healthy.store(true, Ordering::SeqCst);
+        }
+
+        // 恢复之后状态为 ok，最近的错误仍然保留
+        let report = readiness.check().await;
+        assert!(report.ready);
+        assert_eq!(report.dependencies[2].last_error.as_deref(), Some("browser is down"));
+    }
+
+    #[tokio::test]
+    async fn test_optional_and_rate_limited_checks() {
+        let postgres = StubCheck::new("postgres");
+        let llm = StubCheck::new("llm");
+        let readiness = Readiness::new(Duration::from_secs(1))
+            .with_dependency(postgres.clone(), true, None)
+            .with_dependency(llm.clone(), false, Some(Duration::from_secs(60)));
+
+        // 可选的依赖失败时仍然就绪，但报告中给出失败
+        llm.healthy.store(false, Ordering::SeqCst);
+        let report = readiness.check().await;
+        assert!(report.ready);
+        assert_eq!(statuses(&report), vec![("postgres", "ok"), ("llm", "failing")]);
+        assert!(!report.dependencies[1].required);
+
+        // 间隔之内使用缓存的结果，不再调用
+        llm.healthy.store(true, Ordering::SeqCst);
+        let report = readiness.check().await;
+        assert_eq!(report.dependencies[1].status, "failing");
+        assert_eq!((postgres.calls.load(Ordering::SeqCst), llm.calls.load(Ordering::SeqCst)), (2, 1));
+    }
+
+    // 卡住的依赖在超时后算作失败
+    struct HangingCheck;
+
+    #[async_trait]
+    impl DependencyCheck for HangingCheck {
+        fn name(&self) -> &str {
+            "postgres"
+        }
+
+        async fn check(&self) -> Result<()> {
+            futures::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checks_time_out() {
+        let readiness = Readiness::new(Duration::from_millis(50)).with_dependency(Arc::new(HangingCheck), true, None);
+        let report = readiness.check().await;
+        assert!(!report.ready);
+        assert_eq!(report.dependencies[0].last_error.as_deref(), Some("The check did not finish within 50 ms"));
+    }
+}
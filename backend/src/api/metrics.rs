@@ -0,0 +1,22 @@
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+
+use crate::api::error::ApiError;
+use crate::api::server::AppState;
+
+/* GET /metrics：Prometheus 文本格式的指标，和其他接口一样需要 API key，
+抓取时用 bearer token 认证。队列、浏览器池和连接池的当前值在这里读取 */
+pub async fn scrape(State(state): State<AppState>) -> Result<impl IntoResponse, ApiError> {
+    let metrics = state.runs.metrics();
+    metrics.run_queue_depth.set(state.runs.queue_depth() as i64);
+    let browsers = state.browsers.stats();
+    metrics.browser_pool_size.set(browsers.size as i64);
+    metrics.browser_pool_in_use.set(browsers.in_use as i64);
+    let pool = state.sessions.pool();
+    let idle = pool.num_idle() as i64;
+    metrics.db_pool_connections.with_label_values(&["idle"]).set(idle);
+    metrics.db_pool_connections.with_label_values(&["in_use"]).set((pool.size() as i64 - idle).max(0));
+    let body = metrics.render()?;
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body))
+}
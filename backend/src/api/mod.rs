@@ -1 +1,26 @@
-pub mod knowledge_base;
\ No newline at end of file
+pub mod artifacts;
+pub mod auth;
+pub mod cleanup;
+pub mod error;
+pub mod events;
+pub mod executor;
+pub mod factory;
+pub mod files;
+pub mod health;
+pub mod knowledge_base;
+pub mod metrics;
+pub mod plans;
+pub mod quotas;
+pub mod replay;
+pub mod runs;
+pub mod server;
+pub mod sessions;
+
+pub use auth::{require_admin, require_api_key, AuthUser};
+pub use cleanup::RetentionJob;
+pub use error::ApiError;
+pub use executor::{BuiltRun, OrchestratorFactory, QueueError, RunEvent, RunExecutor, RunHandle};
+pub use factory::{ServerFactory, WebSurferBuilder};
+pub use health::{Readiness, ReadinessReport};
+pub use quotas::{QuotaExceeded, QuotaGate};
+pub use server::{router, serve, AppState, QueuedRun};
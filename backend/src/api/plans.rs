@@ -0,0 +1,54 @@
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::api::auth::AuthUser;
+use crate::api::error::ApiError;
+use crate::api::server::AppState;
+use crate::orchestrator::plan::Plan;
+
+const DEFAULT_SIMILAR_PLANS: usize = 3;
+const MAX_SIMILAR_PLANS: usize = 20;
+
+/// GET /api/plans/similar?task=…&k=… 的查询参数
+#[derive(Debug, Deserialize)]
+pub struct SimilarPlansQuery {
+    pub task: String,
+    pub k: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimilarPlan {
+    pub task: String,
+    pub plan: Plan,
+    pub similarity: f64,
+    pub created_at: i64,
+}
+
+/* GET /api/plans/similar：只检索调用方自己保存的成功计划，按相似度从高到低，k 默认 3，最多 20；
+没有配置计划记忆（pgvector 和嵌入模型）时返回 503 */
+pub async fn similar_plans(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    query: Result<Query<SimilarPlansQuery>, QueryRejection>,
+) -> Result<Json<Vec<SimilarPlan>>, ApiError> {
+    let Query(query) = query?;
+    if query.task.trim().is_empty() {
+        return Err(ApiError::bad_request("task must not be empty"));
+    }
+    let store = state.plans.as_ref().ok_or_else(|| ApiError::unavailable("plan memory is not configured"))?;
+    let k = query.k.unwrap_or(DEFAULT_SIMILAR_PLANS).clamp(1, MAX_SIMILAR_PLANS);
+    let plans = store.clone().with_user(user.user_id).find_similar(&query.task, k).await?;
+    Ok(Json(
+        plans
+            .into_iter()
+            .map(|p| SimilarPlan {
+                task: p.entry.task,
+                plan: p.entry.plan,
+                similarity: p.similarity,
+                created_at: p.entry.timestamp.timestamp(),
+            })
+            .collect(),
+    ))
+}
@@ -0,0 +1,233 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{Path, State};
+use axum::{Extension, Json};
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+
+use crate::api::auth::{require_admin, AuthUser};
+use crate::api::error::ApiError;
+use crate::api::server::AppState;
+use crate::clients::{RateLimiter, TokenUsage, UsageMeter};
+use crate::config::QuotaSettings;
+use crate::database::{DailyUsage, Quota, QuotaOverride, QuotaStore};
+
+// 并发数超出时建议的重试间隔
+const CONCURRENCY_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// 超出的配额，接口层返回 429，retry_after 为建议的重试间隔
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("The {quota} quota of {limit} is exceeded")]
+pub struct QuotaExceeded {
+    pub quota: &'static str,
+    pub limit: u64,
+    pub retry_after: Duration,
+}
+
+/// 提交运行时读取的配额和当天用量
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Admission {
+    pub quota: Quota,
+    pub usage: DailyUsage,
+}
+
+impl Admission {
+    /* in_flight 为该用户已经排队和执行中的运行数；resumed 的运行已经计过当天的运行数，
+    只检查并发。token 用完时不再开始新的运行 */
+    pub fn check(&self, in_flight: u64, resumed: bool) -> Result<(), QuotaExceeded> {
+        let quota = &self.quota;
+        if quota.max_concurrent_runs > 0 && in_flight >= quota.max_concurrent_runs {
+            return Err(QuotaExceeded {
+                quota: "max_concurrent_runs",
+                limit: quota.max_concurrent_runs,
+                retry_after: CONCURRENCY_RETRY_AFTER,
+            });
+        }
+        if resumed {
+            return Ok(());
+        }
+        if quota.max_runs_per_day > 0 && self.usage.runs.max(0) as u64 + in_flight >= quota.max_runs_per_day {
+            return Err(QuotaExceeded { quota: "max_runs_per_day", limit: quota.max_runs_per_day, retry_after: until_tomorrow() });
+        }
+        if quota.max_tokens_per_day > 0 && self.usage.tokens.max(0) as u64 >= quota.max_tokens_per_day {
+            return Err(QuotaExceeded {
+                quota: "max_tokens_per_day",
+                limit: quota.max_tokens_per_day,
+                retry_after: until_tomorrow(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/* 执行器使用的配额检查：提交时按用户的配额和当天用量放行，执行时按用户限速并累计 token；
+用量写入 user_usage，重启后当天的计数仍然有效 */
+#[derive(Clone)]
+pub struct QuotaGate {
+    store: QuotaStore,
+    settings: QuotaSettings,
+    limiter: Arc<RateLimiter>,
+}
+
+impl QuotaGate {
+    pub fn new(store: QuotaStore, settings: QuotaSettings) -> Self {
+        let limiter = Arc::new(RateLimiter::new(settings.llm_requests_per_minute));
+        Self { store, settings, limiter }
+    }
+
+    pub fn store(&self) -> &QuotaStore {
+        &self.store
+    }
+
+    pub fn limiter(&self) -> Arc<RateLimiter> {
+        self.limiter.clone()
+    }
+
+    pub async fn quota_for(&self, user_id: &str) -> Result<Quota> {
+        Ok(self.store.get_override(user_id).await?.apply(&self.settings.defaults))
+    }
+
+    pub async fn admission(&self, user_id: &str) -> Result<Admission> {
+        let quota = self.quota_for(user_id).await?;
+        let usage = self.store.usage(user_id, today()).await?;
+        Ok(Admission { quota, usage })
+    }
+
+    // 运行开始时计入当天的运行数
+    pub async fn record_run(&self, user_id: &str) -> Result<()> {
+        self.store.add_usage(user_id, today(), 1, 0).await
+    }
+
+    pub async fn record_tokens(&self, user_id: &str, usage: &TokenUsage) -> Result<()> {
+        if usage.total() == 0 {
+            return Ok(());
+        }
+        self.store.add_usage(user_id, today(), 0, usage.total() as i64).await
+    }
+
+    /// 运行期间模型调用的计量，当天的 token 用完之后拒绝继续调用
+    pub fn meter(&self, user_id: &str, quota: Quota) -> Arc<dyn UsageMeter> {
+        Arc::new(DailyTokenMeter { gate: self.clone(), user_id: user_id.to_string(), quota })
+    }
+}
+
+struct DailyTokenMeter {
+    gate: QuotaGate,
+    user_id: String,
+    quota: Quota,
+}
+
+#[async_trait]
+impl UsageMeter for DailyTokenMeter {
+    async fn check(&self) -> Result<()> {
+        let limit = self.quota.max_tokens_per_day;
+        if limit == 0 {
+            return Ok(());
+        }
+        let usage = self.gate.store.usage(&self.user_id, today()).await?;
+        if usage.tokens.max(0) as u64 >= limit {
+            return Err(QuotaExceeded { quota: "max_tokens_per_day", limit, retry_after: until_tomorrow() }.into());
+        }
+        Ok(())
+    }
+
+    async fn record(&self, usage: &TokenUsage) -> Result<()> {
+        self.gate.record_tokens(&self.user_id, usage).await
+    }
+}
+
+fn today() -> NaiveDate {
+    Utc::now().date_naive()
+}
+
+// 到下一个 UTC 零点的时间，当天的配额在那时重置
+fn until_tomorrow() -> Duration {
+    let now = Utc::now();
+    let tomorrow = (now.date_naive() + chrono::Days::new(1)).and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+    (tomorrow - now).to_std().unwrap_or_default()
+}
+
+/// GET / PUT /api/admin/quotas/:user_id 的响应
+#[derive(Debug, Serialize)]
+pub struct QuotaView {
+    pub user_id: String,
+    pub quota: Quota,
+    pub overrides: QuotaOverride,
+    pub usage_today: DailyUsage,
+}
+
+pub async fn get_quota(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(user_id): Path<String>,
+) -> Result<Json<QuotaView>, ApiError> {
+    let gate = admin_gate(&state, &user)?;
+    Ok(Json(quota_view(gate, user_id).await?))
+}
+
+// 请求体为 QuotaOverride，省略或为 null 的项恢复为默认值
+pub async fn put_quota(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(user_id): Path<String>,
+    payload: Result<Json<QuotaOverride>, JsonRejection>,
+) -> Result<Json<QuotaView>, ApiError> {
+    let gate = admin_gate(&state, &user)?;
+    let Json(overrides) = payload?;
+    let values = [overrides.max_concurrent_runs, overrides.max_runs_per_day, overrides.max_tokens_per_day];
+    if values.iter().flatten().any(|value| *value < 0) {
+        return Err(ApiError::bad_request("quota values must not be negative"));
+    }
+    gate.store().set_override(&user_id, &overrides).await?;
+    Ok(Json(quota_view(gate, user_id).await?))
+}
+
+#[allow(clippy::result_large_err)]
+fn admin_gate<'a>(state: &'a AppState, user: &AuthUser) -> Result<&'a QuotaGate, ApiError> {
+    require_admin(state, user)?;
+    state.runs.quotas().ok_or_else(|| ApiError::unavailable("Quotas are not enabled"))
+}
+
+async fn quota_view(gate: &QuotaGate, user_id: String) -> Result<QuotaView> {
+    let overrides = gate.store().get_override(&user_id).await?;
+    let usage_today = gate.store().usage(&user_id, today()).await?;
+    let quota = gate.quota_for(&user_id).await?;
+    Ok(QuotaView { user_id, quota, overrides, usage_today })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admission_checks() {
+        let quota = Quota { max_concurrent_runs: 2, max_runs_per_day: 3, max_tokens_per_day: 1000 };
+        let fresh = Admission { quota, usage: DailyUsage::default() };
+        assert!(fresh.check(1, false).is_ok());
+
+        let error = fresh.check(2, false).unwrap_err();
+        assert_eq!(error.quota, "max_concurrent_runs");
+        assert_eq!(error.retry_after, CONCURRENCY_RETRY_AFTER);
+
+        // 已经开始的运行加上排队中的运行达到上限
+        let busy = Admission { quota, usage: DailyUsage { runs: 2, tokens: 0 } };
+        let error = busy.check(1, false).unwrap_err();
+        assert_eq!(error.quota, "max_runs_per_day");
+        assert!(error.retry_after <= Duration::from_secs(24 * 3600));
+        // 恢复的运行只检查并发
+        assert!(busy.check(1, true).is_ok());
+
+        let spent = Admission { quota, usage: DailyUsage { runs: 0, tokens: 1000 } };
+        assert_eq!(spent.check(0, false).unwrap_err().quota, "max_tokens_per_day");
+
+        let unlimited = Admission {
+            quota: Quota { max_concurrent_runs: 0, max_runs_per_day: 0, max_tokens_per_day: 0 },
+            usage: DailyUsage { runs: 1_000, tokens: 1_000_000 },
+        };
+        assert!(unlimited.check(50, false).is_ok());
+    }
+}
@@ -0,0 +1,99 @@
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+
+use crate::api::runs::RunEventPage;
+use crate::database::RunEventRecord;
+use crate::orchestrator::event_log::{decode_event, render_event};
+
+const PAGE_SIZE: i64 = 500;
+
+/// 通过 GET /api/runs/:id/events 读取运行事件，replay 子命令使用
+pub struct ReplayClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+}
+
+impl ReplayClient {
+    pub fn new(base_url: &str, api_key: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    pub async fn fetch_page(&self, run_id: &str, after_seq: i64, limit: i64) -> Result<RunEventPage> {
+        let url = format!("{}/api/runs/{}/events", self.base_url, urlencoding::encode(run_id));
+        let response = self
+            .http
+            .get(&url)
+            .bearer_auth(&self.api_key)
+            .query(&[("after_seq", after_seq), ("limit", limit)])
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", self.base_url))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body: Value = response.json().await.unwrap_or_default();
+            let message = body["error"]["message"].as_str().unwrap_or("no error message");
+            return Err(anyhow!("Reading the events of run {} failed with {}: {}", run_id, status, message));
+        }
+        Ok(response.json().await?)
+    }
+
+    /// 从第一条开始按页读完目前所有的事件
+    pub async fn fetch_all(&self, run_id: &str) -> Result<Vec<RunEventRecord>> {
+        let mut events = Vec::new();
+        let mut after_seq = 0;
+        loop {
+            let page = self.fetch_page(run_id, after_seq, PAGE_SIZE).await?;
+            events.extend(page.events);
+            match page.next_after_seq {
+                Some(next) => after_seq = next,
+                None => return Ok(events),
+            }
+        }
+    }
+}
+
+/* 按事件在运行中的先后在终端中重新展示：每行前面是距第一条事件的时间，之后是 render_event 的输出。
+事件之间按原来的间隔等待，max_gap 为上限（为零时不等待），时间前缀总是原来的时间 */
+pub async fn replay(events: &[RunEventRecord], out: &mut (dyn Write + Send), max_gap: Option<Duration>) -> Result<()> {
+    let Some(first) = events.first() else {
+        writeln!(out, "The run has no events")?;
+        return Ok(());
+    };
+    let mut previous = first.emitted_at_ms;
+    for record in events {
+        let gap = Duration::from_millis((record.emitted_at_ms - previous).max(0) as u64);
+        let wait = max_gap.map_or(gap, |max_gap| gap.min(max_gap));
+        if !wait.is_zero() {
+            out.flush()?;
+            tokio::time::sleep(wait).await;
+        }
+        previous = record.emitted_at_ms;
+        for line in transcript_lines(record, first.emitted_at_ms) {
+            writeln!(out, "{}", line)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+// 多行的事件，后面的行和第一行的内容对齐
+fn transcript_lines(record: &RunEventRecord, started_at_ms: i64) -> Vec<String> {
+    let prefix = format!("[+{:>7.1}s]", (record.emitted_at_ms - started_at_ms) as f64 / 1000.0);
+    let event = decode_event(record.version, record.event_json.clone());
+    render_event(&event)
+        .into_iter()
+        .enumerate()
+        .map(|(index, line)| match index {
+            0 => format!("{} {}", prefix, line),
+            _ => format!("{} {}", " ".repeat(prefix.len()), line),
+        })
+        .collect()
+}
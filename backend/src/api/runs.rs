@@ -0,0 +1,121 @@
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::api::auth::AuthUser;
+use crate::api::error::{submission_error, ApiError};
+use crate::api::server::AppState;
+use crate::database::runs::{RUN_STATUS_CANCELLED, RUN_STATUS_INTERRUPTED, RUN_STATUS_RUNNING};
+use crate::database::{RunEventRecord, RunRecord};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+const DEFAULT_EVENT_PAGE_SIZE: i64 = 200;
+const MAX_EVENT_PAGE_SIZE: i64 = 1000;
+
+/// GET /api/runs?status=…&limit=… 的查询参数
+#[derive(Debug, Default, Deserialize)]
+pub struct RunsQuery {
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+}
+
+// 用户的运行，按创建时间倒序；status=interrupted 列出可以恢复的运行
+pub async fn list_runs(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    query: Result<Query<RunsQuery>, QueryRejection>,
+) -> Result<Json<Vec<RunRecord>>, ApiError> {
+    let Query(query) = query?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let status = query.status.as_deref().map(str::trim).filter(|s| !s.is_empty());
+    Ok(Json(state.run_store.list_runs(&user.user_id, status, limit).await?))
+}
+
+/// GET /api/runs/:id/events?after_seq=…&limit=… 的查询参数
+#[derive(Debug, Default, Deserialize)]
+pub struct RunEventsQuery {
+    pub after_seq: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// 运行事件的一页；next_after_seq 为 None 时已经读到目前的最后一条，运行还在执行时之后可能还有新的事件
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunEventPage {
+    pub events: Vec<RunEventRecord>,
+    pub next_after_seq: Option<i64>,
+}
+
+/* GET /api/runs/:id/events：运行的事件日志，按 seq 从 after_seq（默认 0）之后开始，每页最多 limit 条。
+事件是清理过的 JSON，按 version 解析；不属于该用户的运行返回 404 */
+pub async fn list_run_events(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    query: Result<Query<RunEventsQuery>, QueryRejection>,
+) -> Result<Json<RunEventPage>, ApiError> {
+    let Query(query) = query?;
+    match state.run_store.run_detail(&id).await? {
+        Some(detail) if detail.run.user_id.as_deref() == Some(user.user_id.as_str()) => {}
+        _ => return Err(ApiError::not_found(format!("Run {} not found", id))),
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_EVENT_PAGE_SIZE).clamp(1, MAX_EVENT_PAGE_SIZE);
+    let events = state.run_store.run_events_after(&id, query.after_seq.unwrap_or(0).max(0), limit).await?;
+    let next_after_seq = events.last().map(|event| event.seq).filter(|_| events.len() as i64 == limit);
+    Ok(Json(RunEventPage { events, next_after_seq }))
+}
+
+/* POST /api/runs/:id/resume：把被中断的运行放回队列，从最新的检查点继续。
+不属于该用户的运行返回 404，不是 interrupted 的运行返回 409，超出并发配额时返回 429，队列满时返回 503 */
+pub async fn resume_run(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<RunRecord>), ApiError> {
+    let resumed = state.runs.resume(&id, Some(&user.user_id)).await.map_err(submission_error)?;
+    if let Some(run) = resumed {
+        return Ok((StatusCode::ACCEPTED, Json(run)));
+    }
+    match state.run_store.run_detail(&id).await? {
+        Some(detail) if detail.run.user_id.as_deref() == Some(user.user_id.as_str()) => Err(ApiError::conflict(
+            format!("Run {} is {} and cannot be resumed", id, detail.run.status),
+        )),
+        _ => Err(ApiError::not_found(format!("Run {} not found", id))),
+    }
+}
+
+/* POST /api/runs/:id/cancel：停止运行。正在执行的运行返回 202，它随后以目前为止的总结结束，
+事件流中发出 Cancelled；排队等待恢复或被中断的运行直接记为 cancelled。
+已经取消的运行再次取消返回 200，已经完成或失败的运行返回 409，error.status 为它的最终状态 */
+pub async fn cancel_run(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<RunRecord>), ApiError> {
+    let run = match state.run_store.run_detail(&id).await? {
+        Some(detail) if detail.run.user_id.as_deref() == Some(user.user_id.as_str()) => detail.run,
+        _ => return Err(ApiError::not_found(format!("Run {} not found", id))),
+    };
+    match run.status.as_str() {
+        RUN_STATUS_RUNNING if state.runs.cancel_run(&id) => return Ok((StatusCode::ACCEPTED, Json(run))),
+        RUN_STATUS_RUNNING | RUN_STATUS_INTERRUPTED => {
+            state.run_store.cancel_pending_run(&id).await?;
+            // 检查之后刚开始执行的运行
+            state.runs.cancel_run(&id);
+        }
+        _ => {}
+    }
+    // 运行可能在检查的同时结束，以重新读取的状态为准
+    let run = state.run_store.run_detail(&id).await?.map(|detail| detail.run).unwrap_or(run);
+    match run.status.as_str() {
+        RUN_STATUS_CANCELLED | RUN_STATUS_RUNNING => Ok((StatusCode::OK, Json(run))),
+        status => {
+            let mut error = ApiError::conflict(format!("Run {} has already ended as {}", id, status));
+            error.details.insert("status".to_string(), json!(status));
+            Err(error)
+        }
+    }
+}
@@ -0,0 +1,1232 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use anyhow::Result;
+use axum::extract::DefaultBodyLimit;
+use axum::middleware;
+use axum::routing::{get, post};
+use axum::Router;
+use tokio::net::TcpListener;
+
+use crate::api::auth::require_api_key;
+use crate::api::executor::RunExecutor;
+use crate::api::cleanup::{self, RetentionJob};
+use crate::api::health::{self, Readiness};
+use crate::api::{artifacts, events, files, metrics, plans, quotas, runs, sessions};
+use crate::config::AppConfig;
+use crate::database::{ApiKeyStore, ArtifactIndex, PgPlanStore, RunStore, SessionFileStore, SessionStore};
+use crate::orchestrator::plan::Plan;
+use crate::orchestrator::types::ImageAttachment;
+use crate::storage::BlobStore;
+use crate::tools::chrome::chrome_ctrl::Chrome;
+use crate::tools::chrome::BrowserPool;
+
+/// 用户消息触发的一次运行，由后台的执行器从队列中取出
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueuedRun {
+    pub session_id: String,
+    /// 触发运行的消息，恢复的运行为空
+    pub message_id: String,
+    pub user_id: Option<String>,
+    pub task: String,
+    /// 恢复被中断的运行时为它的 id，从它最新的检查点继续，不新建运行记录
+    pub resume_run_id: Option<String>,
+    /// 会话上传的计划（POST /api/sessions/:id/plan），执行时直接使用，不调用模型规划
+    pub plan: Option<Plan>,
+    /// 消息附带的图片（multipart 的 image 部分），规划时一并交给模型
+    pub attachments: Vec<ImageAttachment>,
+}
+
+/// 各个处理函数共享的状态
+#[derive(Clone)]
+pub struct AppState {
+    pub sessions: SessionStore,
+    pub api_keys: ApiKeyStore,
+    pub runs: RunExecutor,
+    pub run_store: RunStore,
+    pub blobs: Arc<dyn BlobStore>,
+    pub artifacts: ArtifactIndex,
+    /// 会话中上传的文件，内容和产物在同一个 BlobStore 中
+    pub files: SessionFileStore,
+    /// 运行借用的预热浏览器
+    pub browsers: BrowserPool<Chrome>,
+    /// 定时执行的保留策略清理，管理接口也可以立即执行一次
+    pub retention: RetentionJob,
+    /// GET /readyz 检查的依赖
+    pub readiness: Readiness,
+    /// 计划记忆，GET /api/plans/similar 从中检索；未配置 pgvector 时为 None
+    pub plans: Option<PgPlanStore>,
+    pub config: Arc<AppConfig>,
+}
+
+// 新的接口都加在这里，统一经过 API key 认证
+pub fn router(state: AppState) -> Router {
+    let upload_limit = (state.config.uploads.max_file_bytes as usize).saturating_add(files::MULTIPART_OVERHEAD);
+    Router::new()
+        .route("/api/sessions", post(sessions::create_session))
+        .route(
+            "/api/sessions/:id",
+            get(sessions::get_session).patch(sessions::update_session).delete(sessions::delete_session),
+        )
+        .route(
+            "/api/sessions/:id/messages",
+            post(sessions::post_message).layer(DefaultBodyLimit::max(upload_limit)).get(sessions::list_messages),
+        )
+        .route("/api/sessions/:id/plan", post(sessions::upload_plan))
+        .route(
+            "/api/sessions/:id/files",
+            post(files::upload_file).layer(DefaultBodyLimit::max(upload_limit)).get(files::list_files),
+        )
+        .route("/api/sessions/:id/files/:file_id", get(files::download_file))
+        .route("/api/sessions/:id/events", get(events::session_events))
+        .route("/api/artifacts/:key", get(artifacts::get_artifact))
+        .route("/api/runs", get(runs::list_runs))
+        .route("/api/runs/:id/events", get(runs::list_run_events))
+        .route("/api/runs/:id/resume", post(runs::resume_run))
+        .route("/api/runs/:id/cancel", post(runs::cancel_run))
+        .route("/api/plans/similar", get(plans::similar_plans))
+        .route("/api/admin/quotas/:user_id", get(quotas::get_quota).put(quotas::put_quota))
+        .route("/api/admin/cleanup", post(cleanup::run_cleanup))
+        .route("/metrics", get(metrics::scrape))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_api_key))
+        // 给负载均衡和容器编排的探针，加在认证之后，不需要 API key
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .with_state(state)
+}
+
+/// 在 listener 上提供接口，shutdown 完成后停止接收新连接，等进行中的请求结束再返回
+pub async fn serve(
+    listener: TcpListener,
+    state: AppState,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    axum::serve(listener, router(state)).with_graceful_shutdown(shutdown).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::executor::{BuiltRun, OrchestratorFactory};
+    use crate::api::health::PostgresCheck;
+    use crate::orchestrator::message::{ChatMessage, MessageRole, MultiModalContent};
+    use crate::storage::LocalDirStore;
+    use crate::database::RetentionStore;
+    use crate::tools::chrome::{BrowserPoolOptions, ChromeFactory};
+    use crate::orchestrator::events::OrchestratorEvent;
+    use crate::orchestrator::event_log::encode_event;
+    use crate::agents::events::AgentEvent;
+    use crate::api::replay::{replay, ReplayClient};
+    use crate::orchestrator::plan::Plan;
+    use crate::orchestrator::plan_library::PlanOutcome;
+    use crate::testing::{
+        direct_answer_json, ledger_json, mock_browser_pool, plan_json, KeywordEmbedder, MessageLog, MockAgent, MockBrowser,
+        MockProvider, MockReply, MockUpload, OrchestratorBuilder,
+    };
+    use std::time::Duration;
+    use std::sync::Mutex;
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+
+    // 执行器没有启动，运行只排队
+    struct UnusedFactory;
+
+    #[async_trait::async_trait]
+    impl OrchestratorFactory for UnusedFactory {
+        async fn build(&self, _run: &QueuedRun) -> Result<BuiltRun> {
+            Err(anyhow::anyhow!("The executor is not started in this test"))
+        }
+    }
+
+    // 一步计划，web_surfer 返回一张截图
+    struct ScreenshotFactory {
+        screenshot: Vec<u8>,
+    }
+
+    #[async_trait::async_trait]
+    impl OrchestratorFactory for ScreenshotFactory {
+        async fn build(&self, run: &QueuedRun) -> Result<BuiltRun> {
+            let provider = MockProvider::new()
+                .respond_json(plan_json(&run.task, &[("Open", "Open the menu page", "web_surfer")]))
+                .respond_json(ledger_json(false, false, "web_surfer", "Open the menu page"))
+                .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+                .respond("The menu is open.");
+            let reply = ChatMessage::new_multimodal(
+                MessageRole::Assistant,
+                "web_surfer".to_string(),
+                vec![
+                    MultiModalContent::Text("Opened the menu page".to_string()),
+                    MultiModalContent::Image(self.screenshot.clone()),
+                ],
+            );
+            OrchestratorBuilder::new()
+                .provider(Arc::new(provider))
+                .agent("Browses the web", MockAgent::new("web_surfer").reply_message(reply))
+                .build()
+                .await
+                .map(Into::into)
+        }
+    }
+
+    // 一步计划，web_surfer 上传任务中 session:// 开头的文件；记录计划请求和上传的文件
+    #[derive(Default)]
+    struct UploadFactory {
+        planning_request: Arc<Mutex<String>>,
+        // 最近一次运行的代理的上传记录
+        uploads: Mutex<Option<Arc<Mutex<Vec<MockUpload>>>>>,
+    }
+
+    impl UploadFactory {
+        fn uploads(&self) -> Vec<MockUpload> {
+            self.uploads.lock().unwrap().as_ref().map(|uploads| uploads.lock().unwrap().clone()).unwrap_or_default()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl OrchestratorFactory for UploadFactory {
+        async fn build(&self, run: &QueuedRun) -> Result<BuiltRun> {
+            let path = run.task.split_whitespace().find(|word| word.starts_with("session://")).unwrap_or_default();
+            let (task, planning_request) = (run.task.clone(), self.planning_request.clone());
+            let provider = MockProvider::new()
+                .respond_with(move |messages| {
+                    *planning_request.lock().unwrap() = format!("{:?}", messages);
+                    plan_json(&task, &[("Upload", "Upload the price list", "web_surfer")]).to_string()
+                })
+                .respond_json(ledger_json(false, false, "web_surfer", "Upload the price list"))
+                .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+                .respond("The price list is uploaded.");
+            let agent = MockAgent::new("web_surfer").then(MockReply::Upload(path.to_string()));
+            *self.uploads.lock().unwrap() = Some(agent.uploads());
+            OrchestratorBuilder::new()
+                .provider(Arc::new(provider))
+                .agent("Browses the web", agent)
+                .build()
+                .await
+                .map(Into::into)
+        }
+    }
+
+    /* 以 Quick 开头的任务直接回答；其他任务为两步计划，第一步的代理迟迟不返回，用来测试取消。
+    两步计划的运行从池中借一个浏览器 */
+    struct SlowStepFactory {
+        log: Mutex<Option<MessageLog>>,
+        browsers: BrowserPool<MockBrowser>,
+    }
+
+    impl Default for SlowStepFactory {
+        fn default() -> Self {
+            Self { log: Mutex::new(None), browsers: mock_browser_pool(1) }
+        }
+    }
+
+    impl SlowStepFactory {
+        fn executes(&self) -> usize {
+            self.log.lock().unwrap().as_ref().map(|log| log.executes().len()).unwrap_or(0)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl OrchestratorFactory for SlowStepFactory {
+        async fn build(&self, run: &QueuedRun) -> Result<BuiltRun> {
+            if run.task.starts_with("Quick") {
+                let provider = MockProvider::new().respond_json(direct_answer_json(&run.task, "Done."));
+                return OrchestratorBuilder::new()
+                    .provider(Arc::new(provider))
+                    .agent("Browses the web", MockAgent::new("web_surfer"))
+                    .build()
+                    .await
+                    .map(Into::into);
+            }
+            let provider = MockProvider::new()
+                .respond_json(plan_json(
+                    &run.task,
+                    &[("Open", "Open the menu page", "web_surfer"), ("Order", "Order a latte", "web_surfer")],
+                ))
+                .respond_json(ledger_json(false, false, "web_surfer", "Open the menu page"))
+                .respond("Stopped before the menu page finished loading.");
+            let agent = MockAgent::new("web_surfer")
+                .delayed_reply(Duration::from_secs(60), "Opened the menu page")
+                .reply("Ordered a latte");
+            *self.log.lock().unwrap() = Some(agent.log());
+            let browser = self.browsers.acquire().await?;
+            let orchestrator = OrchestratorBuilder::new()
+                .provider(Arc::new(provider))
+                .agent("Browses the web", agent)
+                .build()
+                .await?;
+            Ok(BuiltRun { orchestrator, browser: Some(Box::new(browser)) })
+        }
+    }
+
+    // 一步计划，代理执行期间用户发来的消息应当出现在下一次 ledger 评估中
+    #[derive(Default)]
+    struct MidRunFactory {
+        log: Mutex<Option<MessageLog>>,
+        provider: Mutex<Option<Arc<MockProvider>>>,
+    }
+
+    impl MidRunFactory {
+        fn executes(&self) -> usize {
+            self.log.lock().unwrap().as_ref().map(|log| log.executes().len()).unwrap_or(0)
+        }
+
+        fn saw(&self, text: &str) -> bool {
+            let provider = self.provider.lock().unwrap().clone().expect("a run was built");
+            provider.requests().iter().any(|request| serde_json::to_string(request).unwrap().contains(text))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl OrchestratorFactory for MidRunFactory {
+        async fn build(&self, run: &QueuedRun) -> Result<BuiltRun> {
+            let provider = Arc::new(MockProvider::new()
+                .respond_json(plan_json(&run.task, &[("Search", "Search for trains", "web_surfer")]))
+                .respond_json(ledger_json(false, false, "web_surfer", "Search for trains"))
+                .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+                .respond("Searched for buses."));
+            let agent = MockAgent::new("web_surfer").delayed_reply(Duration::from_secs(1), "Found trains");
+            *self.log.lock().unwrap() = Some(agent.log());
+            *self.provider.lock().unwrap() = Some(provider.clone());
+            OrchestratorBuilder::new()
+                .provider(provider)
+                .agent("Browses the web", agent)
+                .build()
+                .await
+                .map(Into::into)
+        }
+    }
+
+    struct TestServer {
+        base: String,
+        runs: RunExecutor,
+        api_keys: ApiKeyStore,
+        stop: oneshot::Sender<()>,
+        server: JoinHandle<Result<()>>,
+        _blob_dir: tempfile::TempDir,
+    }
+
+    impl TestServer {
+        // 执行器不启动，提交的运行可以用 next_queued 取出
+        async fn start(pool: PgPool) -> Result<Self> {
+            Self::start_with(pool, Arc::new(UnusedFactory), false).await
+        }
+
+        async fn start_with(pool: PgPool, factory: Arc<dyn OrchestratorFactory>, execute: bool) -> Result<Self> {
+            Self::launch(pool, factory, execute, None).await
+        }
+
+        // 计划记忆使用同一个数据库
+        async fn start_with_plans(pool: PgPool, plans: PgPlanStore) -> Result<Self> {
+            Self::launch(pool, Arc::new(UnusedFactory), false, Some(plans)).await
+        }
+
+        async fn launch(
+            pool: PgPool,
+            factory: Arc<dyn OrchestratorFactory>,
+            execute: bool,
+            plans: Option<PgPlanStore>,
+        ) -> Result<Self> {
+            let sessions = SessionStore::new(pool.clone());
+            sessions.migrate().await?;
+            let api_keys = ApiKeyStore::new(pool.clone());
+            let blob_dir = tempfile::tempdir()?;
+            let blobs: Arc<dyn BlobStore> = Arc::new(LocalDirStore::new(blob_dir.path())?);
+            let run_store = RunStore::new(pool.clone());
+            let runs = RunExecutor::new(factory, run_store.clone(), sessions.clone(), blobs.clone(), 8);
+            if execute {
+                runs.start(1);
+            }
+            let config = AppConfig::default();
+            let retention = RetentionJob::new(RetentionStore::new(pool.clone()), blobs.clone(), config.retention.policy);
+            let state = AppState {
+                sessions,
+                api_keys: api_keys.clone(),
+                runs: runs.clone(),
+                run_store,
+                blobs,
+                artifacts: ArtifactIndex::new(pool.clone()),
+                files: SessionFileStore::new(pool.clone()),
+                // 测试不借用浏览器，池中的实例只在借用时创建
+                browsers: BrowserPool::new(
+                    Arc::new(ChromeFactory::new(config.browser.clone())),
+                    BrowserPoolOptions::from_settings(&config.browser),
+                ),
+                retention,
+                readiness: Readiness::new(Duration::from_secs(1))
+                    .with_dependency(Arc::new(PostgresCheck::new(pool.clone())), true, None),
+                plans,
+                config: Arc::new(config),
+            };
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let base = format!("http://{}", listener.local_addr()?);
+            let (stop, stop_rx) = oneshot::channel::<()>();
+            let server = tokio::spawn(serve(listener, state, async {
+                stop_rx.await.ok();
+            }));
+            Ok(Self { base, runs, api_keys, stop, server, _blob_dir: blob_dir })
+        }
+
+        async fn stop(self) -> Result<()> {
+            self.stop.send(()).ok();
+            self.server.await??;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_session_message_flow() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let server = TestServer::start(PgPool::connect(&database_url).await?).await?;
+        let base = server.base.clone();
+        let key = server.api_keys.mint("test-user", "flow").await?.key;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post(format!("{}/api/sessions", base))
+            .bearer_auth(&key)
+            .json(&json!({ "title": "Menus" }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 201);
+        let session: Value = response.json().await?;
+        let id = session["id"].as_str().unwrap().to_string();
+        assert_eq!(session["status"], "idle");
+        assert_eq!(session["user_id"], "test-user");
+
+        for content in ["Find the menu of Cafe A", "Also find Cafe B"] {
+            let response = client
+                .post(format!("{}/api/sessions/{}/messages", base, id))
+                .bearer_auth(&key)
+                .json(&json!({ "content": content }))
+                .send()
+                .await?;
+            assert_eq!(response.status(), 202);
+            let queued = server.runs.next_queued().await.expect("queued run");
+            assert_eq!(queued.session_id, id);
+            assert_eq!(queued.task, content);
+        }
+
+        let response = client
+            .get(format!("{}/api/sessions/{}/messages?limit=1&offset=1", base, id))
+            .bearer_auth(&key)
+            .send()
+            .await?;
+        assert_eq!(response.status(), 200);
+        let page: Value = response.json().await?;
+        assert_eq!(page["total"], 2);
+        assert_eq!(page["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(page["messages"][0]["content_json"]["text"], "Also find Cafe B");
+
+        let session: Value = client
+            .get(format!("{}/api/sessions/{}", base, id))
+            .bearer_auth(&key)
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(session["status"], "queued");
+
+        let response = client
+            .post(format!("{}/api/sessions/{}/messages", base, id))
+            .bearer_auth(&key)
+            .json(&json!({ "content": "  " }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 400);
+        let error: Value = response.json().await?;
+        assert_eq!(error["error"]["code"], "bad_request");
+
+        let response = client
+            .patch(format!("{}/api/sessions/{}", base, id))
+            .bearer_auth(&key)
+            .json(&json!({ "pinned": true }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 200);
+        let pinned: Value = response.json().await?;
+        assert_eq!(pinned["pinned"], true);
+        let response = client.post(format!("{}/api/admin/cleanup", base)).bearer_auth(&key).send().await?;
+        assert_eq!(response.status(), 403);
+
+        assert_eq!(client.delete(format!("{}/api/sessions/{}", base, id)).bearer_auth(&key).send().await?.status(), 204);
+        let response = client.get(format!("{}/api/sessions/{}", base, id)).bearer_auth(&key).send().await?;
+        assert_eq!(response.status(), 404);
+        let response = client.get(format!("{}/api/sessions/{}/messages", base, id)).bearer_auth(&key).send().await?;
+        assert_eq!(response.status(), 404);
+
+        server.stop().await
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_uploaded_plan_is_queued_with_the_session() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let server = TestServer::start(PgPool::connect(&database_url).await?).await?;
+        let base = server.base.clone();
+        let key = server.api_keys.mint("test-user", "plans").await?.key;
+        let client = reqwest::Client::new();
+        let session: Value = client
+            .post(format!("{}/api/sessions", base))
+            .bearer_auth(&key)
+            .json(&json!({ "title": "Nightly" }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let id = session["id"].as_str().unwrap().to_string();
+        let plan_url = format!("{}/api/sessions/{}/plan", base, id);
+
+        // 与 plan -o 写出的文件相同
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("nightly.json");
+        let plan = Plan::from_list_of_dicts_or_str(json!({
+            "task": "Check the price of a latte",
+            "steps": [{ "title": "Open", "details": "Open the menu page", "agent_name": "web_surfer" }]
+        }))
+        .unwrap();
+        plan.to_json_file(&path)?;
+        let file: Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+
+        let response = client.post(&plan_url).bearer_auth(&key).json(&file).send().await?;
+        assert_eq!(response.status(), 202);
+        let posted: Value = response.json().await?;
+        assert_eq!(posted["session"]["status"], "queued");
+        assert_eq!(posted["message"]["content_json"]["text"], "Check the price of a latte");
+        assert_eq!(posted["message"]["content_json"]["plan"]["steps"][0]["title"], "Open");
+        let queued = server.runs.next_queued().await.expect("queued run");
+        assert_eq!(queued.task, "Check the price of a latte");
+        assert_eq!(queued.plan.map(|plan| plan.steps[0].id.clone()), Some(plan.steps[0].id.clone()));
+
+        // 损坏的文件、没有任务的计划和其他用户的会话
+        let response = client.post(&plan_url).bearer_auth(&key).json(&json!({ "version": 1, "steps": [{ "title": "Open" }] })).send().await?;
+        assert_eq!(response.status(), 400);
+        let error: Value = response.json().await?;
+        assert!(error["error"]["message"].as_str().unwrap().starts_with("Plan file upload is invalid"), "{}", error);
+        let mut untitled = file.clone();
+        untitled["task"] = Value::Null;
+        assert_eq!(client.post(&plan_url).bearer_auth(&key).json(&untitled).send().await?.status(), 400);
+        let other = server.api_keys.mint("other-user", "plans").await?.key;
+        assert_eq!(client.post(&plan_url).bearer_auth(&other).json(&file).send().await?.status(), 404);
+
+        assert_eq!(client.delete(format!("{}/api/sessions/{}", base, id)).bearer_auth(&key).send().await?.status(), 204);
+        server.stop().await
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_images_posted_with_a_message_are_queued() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let server = TestServer::start(PgPool::connect(&database_url).await?).await?;
+        let base = server.base.clone();
+        let key = server.api_keys.mint("test-user", "images").await?.key;
+        let client = reqwest::Client::new();
+        let session: Value = client
+            .post(format!("{}/api/sessions", base))
+            .bearer_auth(&key)
+            .json(&json!({ "title": "Receipts" }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let id = session["id"].as_str().unwrap().to_string();
+        let messages_url = format!("{}/api/sessions/{}/messages", base, id);
+        let receipt = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/src/orchestrator/testdata/receipt.png"))?;
+        let form = |name: &str, bytes: Vec<u8>| {
+            let image = reqwest::multipart::Part::bytes(bytes).file_name(name.to_string());
+            reqwest::multipart::Form::new().text("content", "What is the total on this receipt?").part("image", image)
+        };
+
+        let response = client.post(&messages_url).bearer_auth(&key).multipart(form("receipt.png", receipt.clone())).send().await?;
+        assert_eq!(response.status(), 202);
+        let posted: Value = response.json().await?;
+        let attachment = &posted["message"]["content_json"]["attachments"][0];
+        assert_eq!(attachment["name"], "receipt.png");
+        assert_eq!(attachment["mime"], "image/png");
+        let queued = server.runs.next_queued().await.expect("queued run");
+        assert_eq!(queued.task, "What is the total on this receipt?");
+        assert_eq!(queued.attachments, [ImageAttachment::new("receipt.png", receipt)]);
+
+        // 不是图片的部分
+        let response = client.post(&messages_url).bearer_auth(&key).multipart(form("notes.txt", b"total".to_vec())).send().await?;
+        assert_eq!(response.status(), 400);
+        let error: Value = response.json().await?;
+        assert_eq!(error["error"]["message"], "notes.txt is not an image");
+
+        assert_eq!(client.delete(format!("{}/api/sessions/{}", base, id)).bearer_auth(&key).send().await?.status(), 204);
+        server.stop().await
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_api_key_checks() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let server = TestServer::start(PgPool::connect(&database_url).await?).await?;
+        let sessions_url = format!("{}/api/sessions", server.base);
+        let client = reqwest::Client::new();
+
+        let response = client.post(&sessions_url).json(&json!({})).send().await?;
+        assert_eq!(response.status(), 401);
+        let error: Value = response.json().await?;
+        assert_eq!(error["error"]["code"], "unauthorized");
+
+        for invalid in ["not-a-key", "mm_0123456789abcdef_wrong"] {
+            let response = client.post(&sessions_url).bearer_auth(invalid).json(&json!({})).send().await?;
+            assert_eq!(response.status(), 401);
+        }
+
+        let revoked = server.api_keys.mint("tenant-a", "revoked").await?;
+        assert!(server.api_keys.revoke(&revoked.record.id).await?);
+        let response = client.post(&sessions_url).bearer_auth(&revoked.key).json(&json!({})).send().await?;
+        assert_eq!(response.status(), 403);
+        let error: Value = response.json().await?;
+        assert_eq!(error["error"]["code"], "forbidden");
+
+        // 同一 id 配上错误的 secret 也是无效的
+        let tenant_a = server.api_keys.mint("tenant-a", "valid").await?.key;
+        let forged = format!("{}x", tenant_a);
+        let response = client.post(&sessions_url).bearer_auth(&forged).json(&json!({})).send().await?;
+        assert_eq!(response.status(), 401);
+
+        let response = client.post(&sessions_url).bearer_auth(&tenant_a).json(&json!({ "title": "Private" })).send().await?;
+        assert_eq!(response.status(), 201);
+        let session: Value = response.json().await?;
+        let session_url = format!("{}/{}", sessions_url, session["id"].as_str().unwrap());
+
+        // 另一个用户看不到、也删不掉这个会话
+        let tenant_b = server.api_keys.mint("tenant-b", "valid").await?.key;
+        assert_eq!(client.get(&session_url).bearer_auth(&tenant_b).send().await?.status(), 404);
+        assert_eq!(client.get(format!("{}/messages", session_url)).bearer_auth(&tenant_b).send().await?.status(), 404);
+        let response = client
+            .post(format!("{}/messages", session_url))
+            .bearer_auth(&tenant_b)
+            .json(&json!({ "content": "Read it anyway" }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 404);
+        assert_eq!(client.delete(&session_url).bearer_auth(&tenant_b).send().await?.status(), 404);
+        assert_eq!(client.get(&session_url).bearer_auth(&tenant_a).send().await?.status(), 200);
+
+        assert_eq!(client.delete(&session_url).bearer_auth(&tenant_a).send().await?.status(), 204);
+
+        // 探针不需要 API key
+        assert_eq!(client.get(format!("{}/healthz", server.base)).send().await?.status(), 200);
+        let response = client.get(format!("{}/readyz", server.base)).send().await?;
+        assert_eq!(response.status(), 200);
+        let report: Value = response.json().await?;
+        assert_eq!(report["ready"], true);
+        assert_eq!(report["dependencies"][0]["name"], "postgres");
+        assert_eq!(report["dependencies"][0]["status"], "ok");
+        server.stop().await
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_screenshots_are_served_as_artifacts() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let mut screenshot = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+        screenshot.extend_from_slice(uuid::Uuid::new_v4().as_bytes());
+        let factory = Arc::new(ScreenshotFactory { screenshot: screenshot.clone() });
+        let server = TestServer::start_with(PgPool::connect(&database_url).await?, factory, true).await?;
+        let base = server.base.clone();
+        let key = server.api_keys.mint("tenant-a", "artifacts").await?.key;
+        let client = reqwest::Client::new();
+
+        let session: Value = client
+            .post(format!("{}/api/sessions", base))
+            .bearer_auth(&key)
+            .json(&json!({ "title": "Screenshots" }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let id = session["id"].as_str().unwrap().to_string();
+        let response = client
+            .post(format!("{}/api/sessions/{}/messages", base, id))
+            .bearer_auth(&key)
+            .json(&json!({ "content": "Open the menu" }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 202);
+        server.runs.drain().await;
+
+        let page: Value = client
+            .get(format!("{}/api/sessions/{}/messages", base, id))
+            .bearer_auth(&key)
+            .send()
+            .await?
+            .json()
+            .await?;
+        let messages = page["messages"].as_array().unwrap();
+        // 代理的回复保存为文本和截图引用，消息中没有图片内容
+        let step = messages.iter().find(|m| m["source"] == "web_surfer").expect("agent message");
+        assert_eq!(step["content_json"]["text"], "Opened the menu page");
+        let attachment = &step["content_json"]["attachments"][0];
+        assert_eq!(attachment["store"], "local");
+        assert_eq!(attachment["mime"], "image/png");
+        assert_eq!(attachment["size"], screenshot.len());
+        let answer = messages.last().unwrap();
+        assert_eq!(answer["content_json"]["text"], "The menu is open.");
+        assert_eq!(answer["content_json"]["attachments"][0]["key"], attachment["key"]);
+
+        let artifact_url = format!("{}/api/artifacts/{}", base, attachment["key"].as_str().unwrap());
+        let response = client.get(&artifact_url).bearer_auth(&key).send().await?;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "image/png");
+        assert_eq!(response.bytes().await?.to_vec(), screenshot);
+
+        // 其他用户、未认证和格式不对的 key 都拿不到
+        let other = server.api_keys.mint("tenant-b", "artifacts").await?.key;
+        assert_eq!(client.get(&artifact_url).bearer_auth(&other).send().await?.status(), 404);
+        assert_eq!(client.get(&artifact_url).send().await?.status(), 401);
+        let response = client.get(format!("{}/api/artifacts/not-a-key", base)).bearer_auth(&key).send().await?;
+        assert_eq!(response.status(), 404);
+
+        assert_eq!(client.delete(format!("{}/api/sessions/{}", base, id)).bearer_auth(&key).send().await?.status(), 204);
+        assert_eq!(client.get(&artifact_url).bearer_auth(&key).send().await?.status(), 404);
+        server.stop().await
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_metrics_after_a_run() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let factory = Arc::new(ScreenshotFactory { screenshot: b"\x89PNG\r\n\x1a\nmetrics".to_vec() });
+        let server = TestServer::start_with(PgPool::connect(&database_url).await?, factory, true).await?;
+        server.runs.set_model_labels("dashscope", "qwen-max");
+        let base = server.base.clone();
+        let key = server.api_keys.mint("tenant-a", "metrics").await?.key;
+        let client = reqwest::Client::new();
+
+        assert_eq!(client.get(format!("{}/metrics", base)).send().await?.status(), 401);
+        let session: Value = client
+            .post(format!("{}/api/sessions", base))
+            .bearer_auth(&key)
+            .json(&json!({ "title": "Metrics" }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let id = session["id"].as_str().unwrap().to_string();
+        let response = client
+            .post(format!("{}/api/sessions/{}/messages", base, id))
+            .bearer_auth(&key)
+            .json(&json!({ "content": "Open the menu" }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 202);
+        server.runs.drain().await;
+
+        let response = client.get(format!("{}/metrics", base)).bearer_auth(&key).send().await?;
+        assert_eq!(response.status(), 200);
+        assert!(response.headers()["content-type"].to_str()?.starts_with("text/plain"));
+        let text = response.text().await?;
+        assert!(text.contains("runs_started_total 1"));
+        assert!(text.contains("runs_finished_total 1"));
+        assert!(text.contains("runs_failed_total 0"));
+        assert!(text.contains("run_duration_seconds_count 1"));
+        assert!(text.contains("run_queue_depth 0"));
+        assert!(text.contains("browser_pool_in_use 0"));
+        assert!(text.contains(r#"db_pool_connections{state="idle"}"#));
+        assert!(text.contains("# TYPE event_ws_clients gauge"));
+        // 计划、两次进度判断和最终回答，共四次模型调用
+        assert!(text.contains(r#"llm_requests_total{model="qwen-max",outcome="ok",provider="dashscope"} 4"#), "{}", text);
+        assert!(text.contains(r#"llm_latency_seconds_count{model="qwen-max",provider="dashscope"} 4"#));
+        // 标签中没有会话或用户
+        assert!(!text.contains(&id));
+        assert!(!text.contains("tenant-a"));
+
+        assert_eq!(client.delete(format!("{}/api/sessions/{}", base, id)).bearer_auth(&key).send().await?.status(), 204);
+        server.stop().await
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_uploaded_files_reach_the_agent() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let factory = Arc::new(UploadFactory::default());
+        let server = TestServer::start_with(PgPool::connect(&database_url).await?, factory.clone(), true).await?;
+        let base = server.base.clone();
+        let key = server.api_keys.mint("tenant-a", "files").await?.key;
+        let client = reqwest::Client::new();
+
+        let session: Value = client
+            .post(format!("{}/api/sessions", base))
+            .bearer_auth(&key)
+            .json(&json!({ "title": "Uploads" }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let id = session["id"].as_str().unwrap().to_string();
+        let files_url = format!("{}/api/sessions/{}/files", base, id);
+        let content = format!("item,price\nlatte,{}\n", uuid::Uuid::new_v4());
+        let upload = |name: &str, mime: &str, bytes: Vec<u8>| {
+            let part = reqwest::multipart::Part::bytes(bytes).file_name(name.to_string()).mime_str(mime).unwrap();
+            client.post(&files_url).bearer_auth(&key).multipart(reqwest::multipart::Form::new().part("file", part))
+        };
+
+        let response = upload("../prices.csv", "text/csv", content.clone().into_bytes()).send().await?;
+        assert_eq!(response.status(), 201);
+        let file: Value = response.json().await?;
+        let file_id = file["id"].as_str().unwrap().to_string();
+        assert_eq!(file["name"], "prices.csv");
+        assert_eq!(file["mime"], "text/csv");
+        assert_eq!(file["size"], content.len());
+
+        // 类型和大小的限制，以及其他用户的会话
+        let response = upload("run.sh", "application/x-sh", b"echo hi".to_vec()).send().await?;
+        assert_eq!(response.status(), 400);
+        let too_large = vec![b'a'; AppConfig::default().uploads.max_file_bytes as usize + 1];
+        let response = upload("big.txt", "text/plain", too_large).send().await?;
+        assert_eq!(response.status(), 413);
+        let other = server.api_keys.mint("tenant-b", "files").await?.key;
+        let file_url = format!("{}/{}", files_url, file_id);
+        assert_eq!(client.get(&file_url).bearer_auth(&other).send().await?.status(), 404);
+        let part = reqwest::multipart::Part::bytes(b"x".to_vec()).file_name("x.txt").mime_str("text/plain")?;
+        let response = client
+            .post(&files_url)
+            .bearer_auth(&other)
+            .multipart(reqwest::multipart::Form::new().part("file", part))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 404);
+
+        let response = client.get(&file_url).bearer_auth(&key).send().await?;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/csv");
+        assert!(response.headers()["content-disposition"].to_str()?.starts_with("attachment; filename=\"prices.csv\""));
+        assert_eq!(response.text().await?, content);
+        let listed: Value = client.get(&files_url).bearer_auth(&key).send().await?.json().await?;
+        assert_eq!(listed.as_array().unwrap().len(), 1);
+
+        let task = format!("Upload session://{} to the order form", file_id);
+        let response = client
+            .post(format!("{}/api/sessions/{}/messages", base, id))
+            .bearer_auth(&key)
+            .json(&json!({ "content": task }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 202);
+        server.runs.drain().await;
+
+        // 计划请求中列出了文件名和大小，代理把会话文件解析成了本地的临时文件
+        let planning_request = factory.planning_request.lock().unwrap().clone();
+        assert!(planning_request.contains(&format!("prices.csv ({} bytes): session://{}", content.len(), file_id)));
+        let uploads = factory.uploads();
+        assert_eq!(uploads.len(), 1, "the agent should upload one file");
+        assert!(uploads[0].path.ends_with(format!("{}/prices.csv", file_id)));
+        assert_eq!(uploads[0].content, content.as_bytes());
+        // 临时文件随运行结束删除
+        assert!(!uploads[0].path.exists());
+
+        assert_eq!(client.delete(format!("{}/api/sessions/{}", base, id)).bearer_auth(&key).send().await?.status(), 204);
+        assert_eq!(client.get(&file_url).bearer_auth(&key).send().await?.status(), 404);
+        server.stop().await
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_cancel_a_run_mid_step() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let factory = Arc::new(SlowStepFactory::default());
+        let server = TestServer::start_with(PgPool::connect(&database_url).await?, factory.clone(), true).await?;
+        let base = server.base.clone();
+        let key = server.api_keys.mint("tenant-a", "cancel").await?.key;
+        let client = reqwest::Client::new();
+
+        let session: Value = client
+            .post(format!("{}/api/sessions", base))
+            .bearer_auth(&key)
+            .json(&json!({ "title": "Cancel" }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let id = session["id"].as_str().unwrap().to_string();
+        let post_message = |content: &str| {
+            client
+                .post(format!("{}/api/sessions/{}/messages", base, id))
+                .bearer_auth(&key)
+                .json(&json!({ "content": content }))
+                .send()
+        };
+        assert_eq!(post_message("Order a latte").await?.status(), 202);
+
+        // 等到代理开始执行第一步
+        let handle = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Some(handle) = server.runs.active_run(&id) {
+                    if factory.executes() == 1 {
+                        return handle;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await?;
+        assert_eq!(factory.browsers.stats().in_use, 1);
+        let mut events = handle.subscribe();
+        let cancel_url = format!("{}/api/runs/{}/cancel", base, handle.run_id);
+
+        let other = server.api_keys.mint("tenant-b", "cancel").await?.key;
+        assert_eq!(client.post(&cancel_url).bearer_auth(&other).send().await?.status(), 404);
+        let response = client.post(&cancel_url).bearer_auth(&key).send().await?;
+        assert_eq!(response.status(), 202);
+        let run: Value = response.json().await?;
+        assert_eq!(run["id"], handle.run_id.as_str());
+        // 重复取消没有额外的影响
+        let status = client.post(&cancel_url).bearer_auth(&key).send().await?.status();
+        assert!(status == 200 || status == 202, "{}", status);
+
+        let cancelled = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                match events.recv().await {
+                    Ok(OrchestratorEvent::Cancelled { step_index, .. }) => return Some(step_index),
+                    Ok(_) => continue,
+                    Err(_) => return None,
+                }
+            }
+        })
+        .await?;
+        assert_eq!(cancelled, Some(0));
+        assert_eq!(post_message("Quick question").await?.status(), 202);
+        server.runs.drain().await;
+
+        // 第二步没有执行，运行以目前为止的总结结束，借用的浏览器重置后回到池中
+        assert_eq!(factory.executes(), 1);
+        let browsers = factory.browsers.stats();
+        assert_eq!((browsers.in_use, browsers.resets), (0, 1));
+        let response = client.post(&cancel_url).bearer_auth(&key).send().await?;
+        assert_eq!(response.status(), 200);
+        let run: Value = response.json().await?;
+        assert_eq!(run["status"], "cancelled");
+        let page: Value = client
+            .get(format!("{}/api/sessions/{}/messages", base, id))
+            .bearer_auth(&key)
+            .send()
+            .await?
+            .json()
+            .await?;
+        let messages = page["messages"].as_array().unwrap();
+        let answer = messages.iter().rev().find(|m| m["content_json"]["run_id"] == handle.run_id.as_str()).unwrap();
+        assert_eq!(answer["content_json"]["text"], "Stopped before the menu page finished loading.");
+
+        // 已经完成的运行不能取消，409 中给出它的最终状态
+        let runs: Value = client.get(format!("{}/api/runs", base)).bearer_auth(&key).send().await?.json().await?;
+        let quick = runs.as_array().unwrap().iter().find(|r| r["task"] == "Quick question").expect("quick run");
+        assert_eq!(quick["status"], "completed");
+        let response = client
+            .post(format!("{}/api/runs/{}/cancel", base, quick["id"].as_str().unwrap()))
+            .bearer_auth(&key)
+            .send()
+            .await?;
+        assert_eq!(response.status(), 409);
+        let error: Value = response.json().await?;
+        assert_eq!(error["error"]["status"], "completed");
+
+        assert_eq!(client.delete(format!("{}/api/sessions/{}", base, id)).bearer_auth(&key).send().await?.status(), 204);
+        server.stop().await
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_message_posted_mid_run_reaches_the_running_orchestrator() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let factory = Arc::new(MidRunFactory::default());
+        let server = TestServer::start_with(PgPool::connect(&database_url).await?, factory.clone(), true).await?;
+        let base = server.base.clone();
+        let key = server.api_keys.mint("tenant-a", "mid-run").await?.key;
+        let client = reqwest::Client::new();
+
+        let session: Value = client
+            .post(format!("{}/api/sessions", base))
+            .bearer_auth(&key)
+            .json(&json!({ "title": "Trains" }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let id = session["id"].as_str().unwrap().to_string();
+        let post_message = |content: &str| {
+            client
+                .post(format!("{}/api/sessions/{}/messages", base, id))
+                .bearer_auth(&key)
+                .json(&json!({ "content": content }))
+                .send()
+        };
+        assert_eq!(post_message("Search for trains").await?.status(), 202);
+
+        // 等到代理开始执行第一步
+        let handle = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Some(handle) = server.runs.active_run(&id) {
+                    if factory.executes() == 1 {
+                        return handle;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await?;
+        let response = post_message("actually search for buses instead").await?;
+        assert_eq!(response.status(), 202);
+        server.runs.drain().await;
+
+        // 消息交给了正在执行的运行，没有排队新的运行
+        assert!(factory.saw("New user message received: actually search for buses instead"));
+        let runs: Value = client.get(format!("{}/api/runs", base)).bearer_auth(&key).send().await?.json().await?;
+        let runs = runs.as_array().unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0]["id"], handle.run_id.as_str());
+        assert_eq!(runs[0]["status"], "completed");
+
+        assert_eq!(client.delete(format!("{}/api/sessions/{}", base, id)).bearer_auth(&key).send().await?.status(), 204);
+        server.stop().await
+    }
+
+    // 读取 SSE 事件直到 until 返回 true，返回 (id, data)；心跳注释跳过
+    async fn read_sse(
+        response: &mut reqwest::Response,
+        pending: &mut String,
+        until: impl Fn(&str, &Value) -> bool,
+    ) -> Result<Vec<(String, Value)>> {
+        let mut events = Vec::new();
+        loop {
+            while let Some(end) = pending.find("\n\n") {
+                let block: String = pending.drain(..end + 2).collect();
+                let field = |name: &str| {
+                    block.lines().find_map(|line| line.strip_prefix(name).map(|value| value.trim().to_string()))
+                };
+                let (Some(id), Some(data)) = (field("id:"), field("data:")) else {
+                    continue;
+                };
+                let data: Value = serde_json::from_str(&data)?;
+                let done = until(&id, &data);
+                events.push((id, data));
+                if done {
+                    return Ok(events);
+                }
+            }
+            let chunk = tokio::time::timeout(Duration::from_secs(10), response.chunk()).await??;
+            let chunk = chunk.ok_or_else(|| anyhow::anyhow!("The event stream ended"))?;
+            pending.push_str(&String::from_utf8_lossy(&chunk));
+        }
+    }
+
+    fn event_seq(id: &str) -> (String, i64) {
+        crate::api::events::parse_event_id(id).expect("event id")
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_session_events_over_sse() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let factory = Arc::new(ScreenshotFactory { screenshot: b"\x89PNG\r\n\x1a\nsse".to_vec() });
+        let server = TestServer::start_with(PgPool::connect(&database_url).await?, factory, true).await?;
+        let base = server.base.clone();
+        let key = server.api_keys.mint("tenant-a", "sse").await?.key;
+        let client = reqwest::Client::new();
+
+        let session: Value = client
+            .post(format!("{}/api/sessions", base))
+            .bearer_auth(&key)
+            .json(&json!({ "title": "Events" }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let id = session["id"].as_str().unwrap().to_string();
+        let events_url = format!("{}/api/sessions/{}/events", base, id);
+        let post_message = |content: &str| {
+            client
+                .post(format!("{}/api/sessions/{}/messages", base, id))
+                .bearer_auth(&key)
+                .json(&json!({ "content": content }))
+                .send()
+        };
+
+        let other = server.api_keys.mint("tenant-b", "sse").await?.key;
+        assert_eq!(client.get(&events_url).bearer_auth(&other).send().await?.status(), 404);
+        let response = client.get(&events_url).bearer_auth(&key).header("Last-Event-ID", "garbage").send().await?;
+        assert_eq!(response.status(), 400);
+
+        // 第一次连接读到第一步开始，然后断开
+        let mut response = client.get(&events_url).bearer_auth(&key).send().await?;
+        assert_eq!(response.status(), 200);
+        assert!(response.headers()["content-type"].to_str()?.starts_with("text/event-stream"));
+        assert_eq!(post_message("Open the menu").await?.status(), 202);
+        let mut pending = String::new();
+        let before = read_sse(&mut response, &mut pending, |_, data| data["type"] == "step_started").await?;
+        drop(response);
+        let (run_id, last_seq) = event_seq(&before.last().unwrap().0);
+        let seqs: Vec<i64> = before.iter().map(|(id, _)| event_seq(id).1).collect();
+        assert_eq!(seqs, (1..=last_seq).collect::<Vec<_>>());
+
+        // 带 Last-Event-ID 重新连接，断开期间的事件从表中补上，不重复也不遗漏
+        let mut response = client
+            .get(&events_url)
+            .bearer_auth(&key)
+            .header("Last-Event-ID", before.last().unwrap().0.as_str())
+            .send()
+            .await?;
+        assert_eq!(response.status(), 200);
+        let mut pending = String::new();
+        let after = read_sse(&mut response, &mut pending, |_, data| data["type"] == "metrics").await?;
+        let seqs: Vec<i64> = after.iter().map(|(id, _)| event_seq(id).1).collect();
+        assert_eq!(seqs, (last_seq + 1..=last_seq + after.len() as i64).collect::<Vec<_>>());
+        assert!(after.iter().all(|(id, _)| event_seq(id).0 == run_id));
+        assert!(after.iter().any(|(_, data)| data["type"] == "ledger_evaluated"));
+
+        // 同一个连接继续收到之后的运行的事件
+        assert_eq!(post_message("Open it again").await?.status(), 202);
+        let next = read_sse(&mut response, &mut pending, |id, data| {
+            data["type"] == "metrics" && event_seq(id).0 != run_id
+        })
+        .await?;
+        assert_eq!(event_seq(&next[0].0).1, 1);
+        assert!(next.iter().all(|(id, _)| event_seq(id).0 != run_id));
+        drop(response);
+
+        server.runs.drain().await;
+        assert_eq!(client.delete(format!("{}/api/sessions/{}", base, id)).bearer_auth(&key).send().await?.status(), 204);
+        server.stop().await
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL with pgvector"]
+    async fn test_similar_plans_endpoint() -> Result<()> {
+        dotenv::dotenv().ok();
+        let pool = PgPool::connect(&std::env::var("DATABASE_URL")?).await?;
+        let store = PgPlanStore::new(pool.clone(), Arc::new(KeywordEmbedder));
+        store.migrate().await?;
+        let user_id = format!("plans-{}", uuid::Uuid::new_v4());
+        let saved = store.clone().with_user(user_id.clone());
+        for (task, title) in [("Book a flight", "Search flights"), ("Find a train to Paris", "Search trains"), ("Read the menu", "Open the menu")] {
+            let plan: Plan = serde_json::from_value(plan_json(task, &[(title, title, "web_surfer")]))?;
+            saved.save_plan(task, &plan, PlanOutcome::Success).await?;
+        }
+        let server = TestServer::start_with_plans(pool.clone(), store).await?;
+        let key = server.api_keys.mint(&user_id, "plans").await?.key;
+        let url = format!("{}/api/plans/similar", server.base);
+        let client = reqwest::Client::new();
+
+        let response = client.get(&url).bearer_auth(&key).query(&[("task", "Find a cheap train to Paris"), ("k", "2")]).send().await?;
+        assert_eq!(response.status(), 200);
+        let plans: Value = response.json().await?;
+        assert_eq!(plans.as_array().unwrap().len(), 2);
+        assert_eq!(plans[0]["task"], "Find a train to Paris");
+        assert_eq!(plans[0]["plan"]["steps"][0]["title"], "Search trains");
+        assert!(plans[0]["similarity"].as_f64() > plans[1]["similarity"].as_f64());
+
+        // 其他用户的计划不会被检索到
+        let other = server.api_keys.mint("plans-other", "plans").await?.key;
+        let response = client.get(&url).bearer_auth(&other).query(&[("task", "Find a train to Paris")]).send().await?;
+        assert_eq!(response.json::<Value>().await?, json!([]));
+
+        let response = client.get(&url).bearer_auth(&key).query(&[("task", " ")]).send().await?;
+        assert_eq!(response.status(), 400);
+        assert_eq!(client.get(&url).send().await?.status(), 401);
+        server.stop().await?;
+
+        // 没有配置计划记忆
+        let server = TestServer::start(pool).await?;
+        let key = server.api_keys.mint(&user_id, "plans").await?.key;
+        let response = client.get(format!("{}/api/plans/similar", server.base)).bearer_auth(&key).query(&[("task", "Book a flight")]).send().await?;
+        assert_eq!(response.status(), 503);
+        server.stop().await
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_run_event_log_replay() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let pool = PgPool::connect(&database_url).await?;
+        let server = TestServer::start(pool.clone()).await?;
+        let key = server.api_keys.mint("replay-user", "replay").await?.key;
+        let run_store = RunStore::new(pool);
+        let run = run_store.start_run(Some("replay-user"), "Find the menu").await?;
+
+        // 脚本化的事件序列，其中一条是之后删除了的变体
+        let started = 1_700_000_000_000i64;
+        let scripted = [
+            (0, encode_event(&OrchestratorEvent::StepStarted {
+                step_index: 0,
+                agent_name: "web_surfer".to_string(),
+                instruction: "Open the menu page".to_string(),
+            })?),
+            (1500, encode_event(&OrchestratorEvent::AgentEvent {
+                agent_name: "web_surfer".to_string(),
+                step_index: 0,
+                event: AgentEvent::Thought { text: "Logging in with password=hunter2".to_string() },
+            })?),
+            (1700, json!({ "type": "browser_crashed", "step_index": 0 })),
+            (4000, encode_event(&OrchestratorEvent::LedgerEvaluated {
+                step_index: 0,
+                step_complete: true,
+                need_to_replan: false,
+                next_speaker: "web_surfer".to_string(),
+            })?),
+            (65000, encode_event(&OrchestratorEvent::Cancelled {
+                step_index: 1,
+                reason: "Stopped by the user".to_string(),
+            })?),
+        ];
+        for (seq, (offset, data)) in scripted.iter().enumerate() {
+            run_store.add_event(&run.id, seq as i64 + 1, data, started + offset).await?;
+        }
+
+        // 按 seq 分页，读到最后一条时 next_after_seq 为 None
+        let client = ReplayClient::new(&server.base, &key);
+        let mut pages = Vec::new();
+        let mut after_seq = 0;
+        loop {
+            let page = client.fetch_page(&run.id, after_seq, 2).await?;
+            pages.push(page.events.iter().map(|event| event.seq).collect::<Vec<_>>());
+            match page.next_after_seq {
+                Some(next) => after_seq = next,
+                None => break,
+            }
+        }
+        assert_eq!(pages, vec![vec![1, 2], vec![3, 4], vec![5]]);
+        assert!(client.fetch_page(&run.id, 5, 2).await?.events.is_empty());
+
+        let events = client.fetch_all(&run.id).await?;
+        assert_eq!(events.len(), 5);
+        let mut transcript = Vec::new();
+        replay(&events, &mut transcript, Some(Duration::ZERO)).await?;
+        assert_eq!(
+            String::from_utf8(transcript)?,
+            [
+                "[+    0.0s] step 1 -> web_surfer: Open the menu page",
+                "[+    1.5s]   [web_surfer] thought: Logging in with password=********",
+                // 事件存为 jsonb，读回时键按 jsonb 的顺序排列
+                r#"[+    1.7s] unrecognized event {"type":"browser_crashed","step_index":0}"#,
+                "[+    4.0s] step 1 complete, next speaker web_surfer",
+                "[+   65.0s] cancelled at step 2: Stopped by the user",
+                "",
+            ]
+            .join("\n")
+        );
+
+        // 其他用户看不到这次运行的事件
+        let other = server.api_keys.mint("replay-other", "replay").await?.key;
+        let error = ReplayClient::new(&server.base, &other).fetch_all(&run.id).await.unwrap_err();
+        assert!(error.to_string().contains("404"), "{}", error);
+        server.stop().await
+    }
+}
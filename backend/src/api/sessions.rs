@@ -0,0 +1,277 @@
+use axum::async_trait;
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{FromRequest, Multipart, Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::{Extension, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::api::auth::AuthUser;
+use crate::api::error::{submission_error, ApiError};
+use crate::api::files::{multipart_error, read_limited, sanitize_name};
+use crate::api::server::{AppState, QueuedRun};
+use crate::database::sessions::SESSION_STATUS_QUEUED;
+use crate::database::{MessagePage, SessionMessage, SessionRecord};
+use crate::orchestrator::message::{ChatMessage, MessageRole, MultiModalContent};
+use crate::orchestrator::plan::Plan;
+use crate::orchestrator::types::ImageAttachment;
+use crate::storage::sniff_mime;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+/// POST /api/sessions 的请求体，会话属于 API key 对应的用户
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateSessionRequest {
+    #[serde(default)]
+    pub title: String,
+}
+
+/// PATCH /api/sessions/:id 的请求体，省略的项不修改
+#[derive(Debug, Default, Deserialize)]
+pub struct UpdateSessionRequest {
+    /// 置顶的会话不会被保留策略清理
+    pub pinned: Option<bool>,
+}
+
+/// POST /api/sessions/:id/messages 的请求体：新的任务或追加的要求
+#[derive(Debug, Deserialize)]
+pub struct PostMessageRequest {
+    pub content: String,
+}
+
+/* POST /api/sessions/:id/messages 的请求体：JSON 的 PostMessageRequest，
+或者 multipart 的 content 文本部分加上任意个 image 部分。图片的文件名取自各部分，计划步骤通过文件名引用；
+不是图片时 400，单张超过 uploads.max_file_bytes 时 413 */
+#[derive(Debug)]
+pub struct MessagePayload {
+    pub content: String,
+    pub images: Vec<ImageAttachment>,
+}
+
+#[async_trait]
+impl FromRequest<AppState> for MessagePayload {
+    type Rejection = ApiError;
+
+    async fn from_request(request: Request, state: &AppState) -> Result<Self, ApiError> {
+        let multipart = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("multipart/form-data"));
+        if !multipart {
+            let Json(PostMessageRequest { content }) = Json::from_request(request, state).await?;
+            return Ok(Self { content, images: Vec::new() });
+        }
+        let mut multipart = Multipart::from_request(request, state)
+            .await
+            .map_err(|rejection| ApiError::bad_request(rejection.body_text()))?;
+        let mut payload = Self { content: String::new(), images: Vec::new() };
+        while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+            match field.name() {
+                Some("content") => payload.content = field.text().await.map_err(multipart_error)?,
+                Some("image") => {
+                    let name = field
+                        .file_name()
+                        .and_then(sanitize_name)
+                        .ok_or_else(|| ApiError::bad_request("Each image part must have a file name"))?;
+                    let bytes = read_limited(field, state.config.uploads.max_file_bytes).await?;
+                    if !sniff_mime(&bytes).starts_with("image/") {
+                        return Err(ApiError::bad_request(format!("{} is not an image", name)));
+                    }
+                    payload.images.push(ImageAttachment::new(name, bytes));
+                }
+                _ => {}
+            }
+        }
+        Ok(payload)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PostMessageResponse {
+    pub message: SessionMessage,
+    pub session: SessionRecord,
+}
+
+/// GET /api/sessions/:id/messages?offset=…&limit=… 的查询参数
+#[derive(Debug, Default, Deserialize)]
+pub struct MessagesQuery {
+    pub offset: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+pub async fn create_session(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    payload: Result<Json<CreateSessionRequest>, JsonRejection>,
+) -> Result<(StatusCode, Json<SessionRecord>), ApiError> {
+    let Json(request) = payload?;
+    let session = state.sessions.create_session(Some(&user.user_id), request.title.trim()).await?;
+    Ok((StatusCode::CREATED, Json(session)))
+}
+
+pub async fn get_session(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<Json<SessionRecord>, ApiError> {
+    Ok(Json(find_session(&state, &user, &id).await?))
+}
+
+pub async fn update_session(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    payload: Result<Json<UpdateSessionRequest>, JsonRejection>,
+) -> Result<Json<SessionRecord>, ApiError> {
+    let Json(request) = payload?;
+    let session = match request.pinned {
+        Some(pinned) => state.sessions.set_pinned(&id, &user.user_id, pinned).await?,
+        None => state.sessions.get_session(&id, &user.user_id).await?,
+    };
+    Ok(Json(session.ok_or_else(|| session_not_found(&id))?))
+}
+
+pub async fn delete_session(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    if !state.sessions.delete_session(&id, &user.user_id).await? {
+        return Err(session_not_found(&id));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/* 保存用户消息，附带的图片保存在外部存储中，消息的 attachments 列出文件名和引用。
+会话有正在执行的运行时把消息（连同图片）交给它（ledger 据此调整或重新规划），
+否则新建一次运行交给执行器，规划时图片一并交给模型，见 queue_run */
+pub async fn post_message(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    payload: MessagePayload,
+) -> Result<(StatusCode, Json<PostMessageResponse>), ApiError> {
+    let MessagePayload { content, images } = payload;
+    let content = content.trim();
+    if content.is_empty() {
+        return Err(ApiError::bad_request("content must not be empty"));
+    }
+    let session = find_session(&state, &user, &id).await?;
+
+    let mut attachments = Vec::with_capacity(images.len());
+    for image in &images {
+        let blob = state.blobs.put(&image.data, sniff_mime(&image.data)).await?;
+        let mut attachment = serde_json::to_value(blob).map_err(anyhow::Error::from)?;
+        attachment["name"] = json!(image.filename);
+        attachments.push(attachment);
+    }
+    let text = if attachments.is_empty() {
+        json!({ "text": content })
+    } else {
+        json!({ "text": content, "attachments": attachments })
+    };
+    let message = state.sessions.add_message(&id, "user", "user", text).await?;
+    // 已经取消的运行不再读取消息
+    if let Some(run) = state.runs.active_run(&id).filter(|run| !run.is_cancelled()) {
+        run.send_message(user_message(content, &images));
+        return Ok((StatusCode::ACCEPTED, Json(PostMessageResponse { message, session })));
+    }
+    queue_run(&state, session, message, content, None, images).await
+}
+
+// 交给正在执行的运行的消息，有图片时按文件名逐张附上
+fn user_message(content: &str, images: &[ImageAttachment]) -> ChatMessage {
+    if images.is_empty() {
+        return ChatMessage::new_text(MessageRole::User, "user".to_string(), content.to_string());
+    }
+    let mut parts = vec![MultiModalContent::Text(content.to_string())];
+    for image in images {
+        parts.push(MultiModalContent::Text(format!("Image {}:", image.filename)));
+        parts.push(MultiModalContent::Image(image.data.clone()));
+    }
+    ChatMessage::new_multimodal(MessageRole::User, "user".to_string(), parts)
+}
+
+/* 上传计划文件（与 plan -o 写出的格式相同）：校验格式和执行的代理之后作为一条消息保存在会话中，
+并新建一次运行直接执行这个计划，不调用模型规划。任务取自计划文件，没有任务、计划不合法时返回 400，
+会话中有正在执行的运行时返回 409 */
+pub async fn upload_plan(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    payload: Result<Json<Value>, JsonRejection>,
+) -> Result<(StatusCode, Json<PostMessageResponse>), ApiError> {
+    let Json(value) = payload?;
+    let session = find_session(&state, &user, &id).await?;
+    let plan = Plan::from_plan_file_value(value, "upload").map_err(|e| ApiError::bad_request(format!("{:#}", e)))?;
+    let task = plan.task.clone().unwrap_or_default();
+    let task = task.trim();
+    if task.is_empty() {
+        return Err(ApiError::bad_request("the plan file has no task"));
+    }
+    state.runs.check_plan(&plan).map_err(|e| ApiError::bad_request(format!("{:#}", e)))?;
+    if state.runs.active_run(&id).is_some() {
+        return Err(ApiError::conflict(format!("Session {} already has a running task", id)));
+    }
+
+    let content = json!({ "text": task, "plan": plan });
+    let message = state.sessions.add_message(&id, "user", "user", content).await?;
+    queue_run(&state, session, message, task, Some(plan), Vec::new()).await
+}
+
+// 为消息新建一次运行交给执行器；超出配额时返回 429，队列满时返回 503，消息仍然保留在记录中
+async fn queue_run(
+    state: &AppState,
+    session: SessionRecord,
+    message: SessionMessage,
+    task: &str,
+    plan: Option<Plan>,
+    attachments: Vec<ImageAttachment>,
+) -> Result<(StatusCode, Json<PostMessageResponse>), ApiError> {
+    // 先标记为排队，避免覆盖 worker 立即写入的 running
+    state.sessions.set_status(&session.id, SESSION_STATUS_QUEUED).await?;
+    let queued = QueuedRun {
+        session_id: session.id.clone(),
+        message_id: message.id.clone(),
+        user_id: session.user_id.clone(),
+        task: task.to_string(),
+        resume_run_id: None,
+        plan,
+        attachments,
+    };
+    if let Err(e) = state.runs.submit(queued).await {
+        state.sessions.set_status(&session.id, &session.status).await?;
+        return Err(submission_error(e));
+    }
+
+    let session = SessionRecord { status: SESSION_STATUS_QUEUED.to_string(), ..session };
+    Ok((StatusCode::ACCEPTED, Json(PostMessageResponse { message, session })))
+}
+
+// offset 默认 0，limit 默认 50，最多 200
+pub async fn list_messages(
+    State(state): State<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<String>,
+    query: Result<Query<MessagesQuery>, QueryRejection>,
+) -> Result<Json<MessagePage>, ApiError> {
+    let Query(query) = query?;
+    let offset = query.offset.unwrap_or(0);
+    if offset < 0 {
+        return Err(ApiError::bad_request("offset must not be negative"));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    find_session(&state, &user, &id).await?;
+    Ok(Json(state.sessions.list_messages(&id, offset, limit).await?))
+}
+
+// 其他用户的会话和不存在的会话一样返回 404
+pub(crate) async fn find_session(state: &AppState, user: &AuthUser, id: &str) -> Result<SessionRecord, ApiError> {
+    state.sessions.get_session(id, &user.user_id).await?.ok_or_else(|| session_not_found(id))
+}
+
+fn session_not_found(id: &str) -> ApiError {
+    ApiError::not_found(format!("Session {} not found", id))
+}
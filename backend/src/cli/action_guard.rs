@@ -0,0 +1,969 @@
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use dialoguer::{Confirm, Input, MultiSelect, Select};
+use url::Url;
+
+use crate::cli::interrupt::Interrupts;
+use crate::cli::strings::Locale;
+use crate::config::AppConfig;
+use crate::orchestrator::message::ChatMessage;
+use crate::orchestrator::plan::Plan;
+use crate::orchestrator::plan_editor::{default_export_path, PlanEditor, StepEdit};
+use crate::orchestrator::plan_history::diff;
+use crate::tools::approval_guard::{
+    ActionGuard, PlanApprovalDecision, PlanApprovalRequest, StepApprovalDecision, StepApprovalRequest,
+};
+
+/// 向用户提问并阻塞等待回答，测试中替换成脚本化的实现
+pub trait ConfirmPrompt: Send + Sync + Debug {
+    /// 是/否问题
+    fn confirm(&self, prompt: &str) -> Result<bool>;
+
+    /// 从 items 中选一项，返回序号；用户取消时返回 None
+    fn select(&self, prompt: &str, items: &[&str]) -> Result<Option<usize>>;
+
+    /// 读一行文字，initial 作为可编辑的初始内容
+    fn input(&self, prompt: &str, initial: &str) -> Result<String>;
+
+    /// 从 items 中选任意多项，返回序号；用户取消时返回 None
+    fn multi_select(&self, prompt: &str, items: &[&str]) -> Result<Option<Vec<usize>>>;
+}
+
+/// 用 dialoguer 在终端中询问，是/否问题默认回答为否
+#[derive(Debug, Default)]
+pub struct DialoguerPrompt;
+
+impl ConfirmPrompt for DialoguerPrompt {
+    fn confirm(&self, prompt: &str) -> Result<bool> {
+        let answer = Confirm::new().with_prompt(prompt).default(false).interact_opt()?;
+        Ok(answer.unwrap_or(false))
+    }
+
+    fn select(&self, prompt: &str, items: &[&str]) -> Result<Option<usize>> {
+        Ok(Select::new().with_prompt(prompt).items(items).default(0).interact_opt()?)
+    }
+
+    fn input(&self, prompt: &str, initial: &str) -> Result<String> {
+        Ok(Input::<String>::new()
+            .with_prompt(prompt)
+            .with_initial_text(initial)
+            .allow_empty(true)
+            .interact_text()?)
+    }
+
+    fn multi_select(&self, prompt: &str, items: &[&str]) -> Result<Option<Vec<usize>>> {
+        Ok(MultiSelect::new().with_prompt(prompt).items(items).interact_opt()?)
+    }
+}
+
+/// 终端正在等待用户回答；EventPrinter 在此期间暂存事件，回答之后再输出，避免打乱提问
+#[derive(Debug, Clone, Default)]
+pub struct PromptActivity(Arc<AtomicBool>);
+
+impl PromptActivity {
+    pub fn is_active(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set(&self, active: bool) {
+        self.0.store(active, Ordering::SeqCst);
+    }
+}
+
+// 步骤审批时的选项，顺序与 StepApprovalDecision 的处理对应
+const STEP_CHOICES: [&str; 3] = ["approval.step.approve", "approval.step.reject", "approval.step.edit"];
+
+// 计划审批时的选项，选择修改时打开 PlanEditor，选择重新生成时询问原因
+const PLAN_CHOICES: [&str; 4] =
+    ["approval.plan.approve", "approval.plan.reject", "approval.plan.edit", "approval.plan.regenerate"];
+
+// 修改计划的菜单，每次修改之后重新显示计划
+const EDIT_CHOICES: [&str; 7] = [
+    "plan_editor.edit",
+    "plan_editor.move",
+    "plan_editor.remove",
+    "plan_editor.undo",
+    "plan_editor.export",
+    "plan_editor.import",
+    "plan_editor.done",
+];
+
+/* 在终端中审批的 ActionGuard：打印请求内容和涉及的网址、域名，等待 y/n。
+超过 timeout 没有回答、终端出错或者直接回车都按拒绝处理；提问期间按了 Ctrl+C 时同样拒绝，
+并把提问中读到的 Ctrl+C（终端处于 raw 模式，不产生信号）交给 Interrupts。
+作为 PolicyGuard 的 prompt 或直接通过 WebAgent::set_action_guard 注入 */
+#[derive(Debug)]
+pub struct CliActionGuard {
+    prompt: Arc<dyn ConfirmPrompt>,
+    timeout: Duration,
+    activity: PromptActivity,
+    interrupts: Option<Interrupts>,
+    locale: Locale,
+}
+
+impl CliActionGuard {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+    pub fn new(timeout: Duration) -> Self {
+        Self::with_prompt(Arc::new(DialoguerPrompt), timeout)
+    }
+
+    pub fn with_prompt(prompt: Arc<dyn ConfirmPrompt>, timeout: Duration) -> Self {
+        Self { prompt, timeout, activity: PromptActivity::default(), interrupts: None, locale: Locale::default() }
+    }
+
+    /// 等待时间取自 approval.timeout_secs
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self::new(Duration::from_secs(config.approval.timeout_secs))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn interruptible(mut self, interrupts: Interrupts) -> Self {
+        self.interrupts = Some(interrupts);
+        self
+    }
+
+    /// 提问和选项使用的语言
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    /// 提问期间为 active，交给 EventPrinter 暂停输出
+    pub fn activity(&self) -> PromptActivity {
+        self.activity.clone()
+    }
+
+    // 在阻塞线程中提问，超时、终端出错都返回 None
+    async fn ask<T: Send + 'static>(
+        &self,
+        question: impl FnOnce(&dyn ConfirmPrompt) -> Result<T> + Send + 'static,
+    ) -> Option<T> {
+        let prompt = self.prompt.clone();
+        let interrupted_before = self.interrupts.as_ref().map(Interrupts::count);
+        self.activity.set(true);
+        let answer = tokio::time::timeout(
+            self.timeout,
+            tokio::task::spawn_blocking(move || question(prompt.as_ref())),
+        )
+        .await;
+        self.activity.set(false);
+        if let (Some(interrupts), Ok(Ok(Err(e)))) = (&self.interrupts, &answer) {
+            if is_interrupted(e) {
+                interrupts.trigger();
+            }
+        }
+        // 提问期间按过 Ctrl+C：运行已经在取消，不再采用回答
+        if self.interrupts.as_ref().map(Interrupts::count) != interrupted_before {
+            return None;
+        }
+        match answer {
+            Ok(Ok(Ok(value))) => Some(value),
+            Ok(Ok(Err(e))) => {
+                tracing::warn!("Failed to read the answer: {:#}", e);
+                None
+            }
+            Ok(Err(e)) => {
+                tracing::warn!("Prompt panicked: {}", e);
+                None
+            }
+            Err(_) => {
+                println!("{}", self.locale.format("approval.no_answer", &[&self.timeout.as_secs()]));
+                None
+            }
+        }
+    }
+}
+
+impl CliActionGuard {
+    /* 修改计划的菜单：修改、移动、批量删除步骤、撤销以及导出、导入计划文件，
+    选择完成、取消或者没有回答时回到审批。不合法的修改（例如删光所有步骤）显示原因，计划保持不变 */
+    async fn edit_plan(&self, editor: &mut PlanEditor) {
+        loop {
+            println!("\n{}", self.describe_plan(editor));
+            let question = self.locale.text("plan_editor.prompt");
+            let choices = self.locale.choices(&EDIT_CHOICES);
+            let edited = match self.ask(move |prompt| prompt.select(question, &choices)).await.flatten() {
+                Some(0) => self.edit_step(editor).await,
+                Some(1) => self.move_step(editor).await,
+                Some(2) => self.remove_steps(editor).await,
+                Some(3) => {
+                    if !editor.undo() {
+                        println!("{}", self.locale.text("plan_editor.nothing_to_undo"));
+                    }
+                    Ok(())
+                }
+                Some(4) => self.export_plan(editor).await,
+                Some(5) => self.import_plan(editor).await,
+                _ => return,
+            };
+            if let Err(e) = edited {
+                println!("{}", self.locale.format("plan_editor.invalid", &[&e]));
+            }
+        }
+    }
+
+    async fn edit_step(&self, editor: &mut PlanEditor) -> Result<()> {
+        let Some(index) = self.choose_step(editor.plan(), "plan_editor.step").await else {
+            return Ok(());
+        };
+        let step = editor.plan().steps[index].clone();
+        let question = self.locale.text("plan_editor.title");
+        let title = self.ask(move |prompt| prompt.input(question, &step.title)).await;
+        let question = self.locale.text("plan_editor.details");
+        let details = self.ask(move |prompt| prompt.input(question, &step.details)).await;
+        let question = self.locale.text("plan_editor.agent");
+        let team = editor.team().to_vec();
+        let agent = self
+            .ask(move |prompt| prompt.select(question, &team.iter().map(String::as_str).collect::<Vec<_>>()))
+            .await
+            .flatten()
+            .map(|i| editor.team()[i].clone());
+        // 清空的文字视为不修改
+        let changed = |text: Option<String>| text.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+        editor.modify_step(index, StepEdit { title: changed(title), details: changed(details), agent_name: agent })
+    }
+
+    async fn move_step(&self, editor: &mut PlanEditor) -> Result<()> {
+        let Some(from) = self.choose_step(editor.plan(), "plan_editor.step").await else {
+            return Ok(());
+        };
+        let question = self.locale.text("plan_editor.position");
+        let positions: Vec<String> = (1..=editor.plan().steps.len()).map(|n| n.to_string()).collect();
+        let to = self
+            .ask(move |prompt| prompt.select(question, &positions.iter().map(String::as_str).collect::<Vec<_>>()))
+            .await
+            .flatten();
+        match to {
+            Some(to) if to != from => editor.reorder_step(from, to),
+            _ => Ok(()),
+        }
+    }
+
+    async fn remove_steps(&self, editor: &mut PlanEditor) -> Result<()> {
+        let question = self.locale.text("plan_editor.remove_prompt");
+        let steps = plan_lines(editor.plan());
+        let chosen = self
+            .ask(move |prompt| prompt.multi_select(question, &steps.iter().map(String::as_str).collect::<Vec<_>>()))
+            .await
+            .flatten()
+            .unwrap_or_default();
+        if chosen.is_empty() {
+            return Ok(());
+        }
+        editor.remove_steps(&chosen)
+    }
+
+    // 默认写到 plans/<任务>-<时间>.json，清空路径时同样使用默认路径
+    async fn export_plan(&self, editor: &PlanEditor) -> Result<()> {
+        let task = editor.plan().task.clone().unwrap_or_default();
+        let default_path = default_export_path(&task, Utc::now());
+        let question = self.locale.text("plan_editor.export_path");
+        let initial = default_path.display().to_string();
+        let Some(path) = self.ask(move |prompt| prompt.input(question, &initial)).await else {
+            return Ok(());
+        };
+        let path = match path.trim() {
+            "" => default_path,
+            path => PathBuf::from(path),
+        };
+        editor.export_to_file(&path)?;
+        println!("{}", self.locale.format("plan_editor.exported", &[&path.display()]));
+        Ok(())
+    }
+
+    // 先显示导入的计划与当前计划的差异，确认之后才替换
+    async fn import_plan(&self, editor: &mut PlanEditor) -> Result<()> {
+        let question = self.locale.text("plan_editor.import_path");
+        let Some(path) = self.ask(move |prompt| prompt.input(question, "")).await else {
+            return Ok(());
+        };
+        let path = path.trim();
+        if path.is_empty() {
+            return Ok(());
+        }
+        let import = editor.preview_import(path)?;
+        println!("\n{}", self.locale.format("plan_editor.import_preview", &[&import.path.display()]));
+        println!("{}", import.diff.summary());
+        let question = self.locale.text("plan_editor.import_confirm");
+        if self.ask(move |prompt| prompt.confirm(question)).await.unwrap_or(false) {
+            editor.apply_import(import)?;
+        }
+        Ok(())
+    }
+
+    // 从计划的步骤中选一个
+    async fn choose_step(&self, plan: &Plan, question: &'static str) -> Option<usize> {
+        let question = self.locale.text(question);
+        let steps = plan_lines(plan);
+        self.ask(move |prompt| prompt.select(question, &steps.iter().map(String::as_str).collect::<Vec<_>>()))
+            .await
+            .flatten()
+    }
+
+    fn describe_plan(&self, editor: &PlanEditor) -> String {
+        let mut lines = vec![self.locale.format("plan_editor.version", &[&editor.version_label()])];
+        lines.extend(plan_lines(editor.plan()));
+        lines.join("\n")
+    }
+}
+
+// 重新生成的计划与之前计划的差异，与导入计划时的预览相同
+fn describe_changes(request: &PlanApprovalRequest, locale: Locale) -> Option<String> {
+    let previous = request.previous_plan.as_ref()?;
+    Some(format!("{}\n{}", locale.text("approval.plan.changes"), diff(previous, &request.plan).summary()))
+}
+
+// 与计划审批请求中相同的步骤列表：序号、步骤和执行的代理
+fn plan_lines(plan: &Plan) -> Vec<String> {
+    plan.steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| format!("{}. {} ({})", i + 1, step.label(), step.agent_name))
+        .collect()
+}
+
+// dialoguer 读到 Ctrl+C 时返回 Interrupted 的 IO 错误
+fn is_interrupted(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| cause.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::Interrupted))
+}
+
+impl Default for CliActionGuard {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_TIMEOUT)
+    }
+}
+
+#[async_trait]
+impl ActionGuard for CliActionGuard {
+    async fn get_approval(&self, request: ChatMessage) -> bool {
+        println!("\n{}", describe_request(&request, self.locale));
+        let question = self.locale.format("approval.action", &[&self.timeout.as_secs()]);
+        self.ask(move |prompt| prompt.confirm(&question)).await.unwrap_or(false)
+    }
+
+    /// 批准、拒绝或者改写发给代理的指令；没有回答、取消选择都按拒绝处理
+    async fn get_step_approval(&self, request: &StepApprovalRequest) -> StepApprovalDecision {
+        println!("\n{}", describe_request(&request.to_chat_message(), self.locale));
+        let question = self.locale.format("approval.step", &[&self.timeout.as_secs()]);
+        let choices = self.locale.choices(&STEP_CHOICES);
+        let choice = self.ask(move |prompt| prompt.select(&question, &choices)).await.flatten();
+        match choice {
+            Some(0) => StepApprovalDecision::Approve,
+            Some(2) => {
+                let initial = request.instruction.clone();
+                let question = self.locale.format("approval.instruction", &[&request.agent_name]);
+                match self.ask(move |prompt| prompt.input(&question, &initial)).await {
+                    Some(instruction) => StepApprovalDecision::EditInstruction(instruction),
+                    None => StepApprovalDecision::Reject,
+                }
+            }
+            _ => StepApprovalDecision::Reject,
+        }
+    }
+
+    /// 可选的拒绝原因，直接回车表示不填
+    async fn get_rejection_reason(&self, _request: &StepApprovalRequest) -> Option<String> {
+        let question = self.locale.text("approval.rejection_reason");
+        let reason = self.ask(move |prompt| prompt.input(question, "")).await?;
+        let reason = reason.trim();
+        (!reason.is_empty()).then(|| reason.to_string())
+    }
+
+    /* 批准、拒绝、修改或者重新生成计划；修改之后回到审批，批准时采用修改后的计划。
+    重新生成时询问可选的原因，交给重规划，之前的修改不再保留；重新生成的计划再次审批时先显示与之前计划的差异。
+    没有回答、取消选择都按拒绝处理 */
+    async fn get_plan_approval(&self, request: &PlanApprovalRequest) -> PlanApprovalDecision {
+        println!("\n{}", describe_request(&request.message, self.locale));
+        if let Some(changes) = describe_changes(request, self.locale) {
+            println!("\n{}", changes);
+        }
+        let mut editor: Option<PlanEditor> = None;
+        loop {
+            let question = self.locale.format("approval.plan", &[&self.timeout.as_secs()]);
+            let choices = self.locale.choices(&PLAN_CHOICES);
+            match self.ask(move |prompt| prompt.select(&question, &choices)).await.flatten() {
+                Some(0) => {
+                    return match editor {
+                        Some(editor) if editor.current_version() > 1 => PlanApprovalDecision::Edited(editor.plan().clone()),
+                        _ => PlanApprovalDecision::Approve,
+                    }
+                }
+                Some(2) => {
+                    if editor.is_none() {
+                        let opened = PlanEditor::new(
+                            request.plan.clone(),
+                            request.team.clone(),
+                            request.sentinel_enabled,
+                            request.limits.clone(),
+                        );
+                        match opened {
+                            Ok(opened) => editor = Some(opened),
+                            Err(e) => {
+                                println!("{}", self.locale.format("plan_editor.invalid", &[&e]));
+                                continue;
+                            }
+                        }
+                    }
+                    if let Some(editor) = editor.as_mut() {
+                        self.edit_plan(editor).await;
+                        println!("\n{}", self.describe_plan(editor));
+                    }
+                }
+                Some(3) => {
+                    let question = self.locale.text("approval.plan.regenerate_reason");
+                    let reason = self.ask(move |prompt| prompt.input(question, "")).await.unwrap_or_default();
+                    let reason = reason.trim();
+                    return PlanApprovalDecision::Regenerate((!reason.is_empty()).then(|| reason.to_string()));
+                }
+                _ => return PlanApprovalDecision::Reject,
+            }
+        }
+    }
+
+    /// 显示提问并读取一行回答，直接回车或没有回答时返回 None
+    async fn get_answer(&self, question: &ChatMessage) -> Option<String> {
+        println!("\n{}", describe_request(question, self.locale));
+        let prompt_text = self.locale.text("clarification.answer");
+        let answer = self.ask(move |prompt| prompt.input(prompt_text, "")).await?;
+        let answer = answer.trim();
+        (!answer.is_empty()).then(|| answer.to_string())
+    }
+}
+
+/// 终端中展示的请求：来源和内容，内容中有网址时再单独列出网址和域名
+pub fn describe_request(request: &ChatMessage, locale: Locale) -> String {
+    let (source, content) = match request {
+        ChatMessage::Text { source, content, .. } => (source.as_str(), content.as_str()),
+        ChatMessage::MultiModal { source, .. } => (source.as_str(), ""),
+    };
+    let mut lines = vec![format!("[{}] {}", source, content.trim())];
+    let target = content
+        .split_whitespace()
+        .map(|word| word.trim_end_matches(['.', ',', '?', '!', ')', '\'', '"']))
+        .find_map(|word| Url::parse(word).ok().filter(|url| url.host_str().is_some()));
+    if let Some(url) = target {
+        lines.push(locale.format("approval.target", &[&url, &url.host_str().unwrap_or_default()]));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    use anyhow::anyhow;
+
+    use crate::orchestrator::message::MessageRole;
+    use crate::orchestrator::plan::{new_step_id, PlanStep, StepKind};
+    use crate::orchestrator::validation::PlanValidationLimits;
+
+    #[derive(Debug)]
+    enum Answer {
+        Yes,
+        No,
+        Fail,
+        Never,
+        // 终端 raw 模式下读到 Ctrl+C
+        CtrlC,
+    }
+
+    // answer 决定是/否问题的回答和是否出错、超时；选择和输入按 choices、inputs 依次回答
+    #[derive(Debug)]
+    struct ScriptedPrompt {
+        answer: Answer,
+        choices: Mutex<Vec<Option<usize>>>,
+        inputs: Mutex<Vec<String>>,
+        questions: Mutex<Vec<String>>,
+    }
+
+    impl ScriptedPrompt {
+        fn new(answer: Answer) -> Arc<Self> {
+            Self::scripted(answer, Vec::new(), Vec::new())
+        }
+
+        fn scripted(answer: Answer, choices: Vec<Option<usize>>, inputs: Vec<&str>) -> Arc<Self> {
+            Arc::new(Self {
+                answer,
+                choices: Mutex::new(choices),
+                inputs: Mutex::new(inputs.into_iter().map(str::to_string).collect()),
+                questions: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn check(&self, prompt: &str) -> Result<()> {
+            self.questions.lock().unwrap().push(prompt.to_string());
+            match self.answer {
+                Answer::Fail => Err(anyhow!("not a terminal")),
+                Answer::CtrlC => Err(std::io::Error::from(std::io::ErrorKind::Interrupted).into()),
+                // 用户一直不回答
+                Answer::Never => {
+                    std::thread::sleep(Duration::from_secs(2));
+                    Ok(())
+                }
+                Answer::Yes | Answer::No => Ok(()),
+            }
+        }
+    }
+
+    impl ConfirmPrompt for ScriptedPrompt {
+        fn confirm(&self, prompt: &str) -> Result<bool> {
+            self.check(prompt)?;
+            Ok(!matches!(self.answer, Answer::No))
+        }
+
+        fn select(&self, prompt: &str, items: &[&str]) -> Result<Option<usize>> {
+            assert_eq!(items, Locale::En.choices(&STEP_CHOICES));
+            self.check(prompt)?;
+            Ok(self.choices.lock().unwrap().remove(0))
+        }
+
+        fn input(&self, prompt: &str, initial: &str) -> Result<String> {
+            self.check(&format!("{} [{}]", prompt, initial))?;
+            Ok(self.inputs.lock().unwrap().remove(0))
+        }
+
+        fn multi_select(&self, prompt: &str, _items: &[&str]) -> Result<Option<Vec<usize>>> {
+            self.check(prompt)?;
+            Ok(None)
+        }
+    }
+
+    fn url_request() -> ChatMessage {
+        ChatMessage::new_text(
+            MessageRole::User,
+            "web_surfer".to_string(),
+            "The website https://shop.example.com/cart is not allowed. Would you like to allow the domain example.com for this session?"
+                .to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_answers_are_passed_through() {
+        let prompt = ScriptedPrompt::new(Answer::Yes);
+        let guard = CliActionGuard::with_prompt(prompt.clone(), Duration::from_secs(5));
+        assert!(guard.get_approval(url_request()).await);
+        assert_eq!(
+            prompt.questions.lock().unwrap().as_slice(),
+            ["Approve? (denied after 5s without an answer)".to_string()]
+        );
+
+        let guard = CliActionGuard::with_prompt(ScriptedPrompt::new(Answer::No), Duration::from_secs(5));
+        assert!(!guard.get_approval(url_request()).await);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_and_errors_deny() {
+        let guard = CliActionGuard::with_prompt(ScriptedPrompt::new(Answer::Never), Duration::from_millis(100));
+        let started = std::time::Instant::now();
+        assert!(!guard.get_approval(url_request()).await);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let guard = CliActionGuard::with_prompt(ScriptedPrompt::new(Answer::Fail), Duration::from_secs(5));
+        assert!(!guard.get_approval(url_request()).await);
+
+        assert_eq!(CliActionGuard::default().timeout(), Duration::from_secs(60));
+        assert_eq!(CliActionGuard::from_config(&AppConfig::default()).timeout(), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_clarification_answers_are_read_from_the_terminal() {
+        let question = ChatMessage::new_text(MessageRole::Assistant, "orchestrator".to_string(), "Which city?".to_string());
+        let prompt = ScriptedPrompt::scripted(Answer::Yes, Vec::new(), vec!["  Paris ", ""]);
+        let guard = CliActionGuard::with_prompt(prompt.clone(), Duration::from_secs(5));
+        assert_eq!(guard.get_answer(&question).await.as_deref(), Some("Paris"));
+        // 直接回车表示不回答
+        assert_eq!(guard.get_answer(&question).await, None);
+        assert_eq!(
+            prompt.questions.lock().unwrap()[0],
+            "Your answer (optional, press Enter to let the assistant decide) []"
+        );
+
+        let guard = CliActionGuard::with_prompt(ScriptedPrompt::new(Answer::Fail), Duration::from_secs(5));
+        assert_eq!(guard.get_answer(&question).await, None);
+    }
+
+    // 回答“是”，但提问期间收到了 SIGINT
+    #[derive(Debug)]
+    struct SignalDuringPrompt(Interrupts);
+
+    impl ConfirmPrompt for SignalDuringPrompt {
+        fn confirm(&self, _prompt: &str) -> Result<bool> {
+            self.0.trigger();
+            Ok(true)
+        }
+
+        fn select(&self, _prompt: &str, _items: &[&str]) -> Result<Option<usize>> {
+            Ok(Some(0))
+        }
+
+        fn input(&self, _prompt: &str, _initial: &str) -> Result<String> {
+            Ok(String::new())
+        }
+
+        fn multi_select(&self, _prompt: &str, _items: &[&str]) -> Result<Option<Vec<usize>>> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ctrl_c_during_a_prompt_denies() {
+        let interrupts = Interrupts::new(Arc::new(|| {}));
+        let guard = CliActionGuard::with_prompt(ScriptedPrompt::new(Answer::CtrlC), Duration::from_secs(5))
+            .interruptible(interrupts.clone());
+        assert!(!guard.get_approval(url_request()).await);
+        assert_eq!(interrupts.count(), 1);
+
+        let interrupts = Interrupts::new(Arc::new(|| {}));
+        let prompt = Arc::new(SignalDuringPrompt(interrupts.clone()));
+        let guard = CliActionGuard::with_prompt(prompt, Duration::from_secs(5)).interruptible(interrupts);
+        assert!(!guard.get_approval(url_request()).await);
+        // 提问之外的 Ctrl+C 不影响之后的回答
+        let interrupts = Interrupts::new(Arc::new(|| {}));
+        interrupts.trigger();
+        let guard = CliActionGuard::with_prompt(ScriptedPrompt::new(Answer::Yes), Duration::from_secs(5)).interruptible(interrupts);
+        assert!(guard.get_approval(url_request()).await);
+    }
+
+    fn step_request() -> StepApprovalRequest {
+        StepApprovalRequest {
+            step_index: 0,
+            step_title: "Search".to_string(),
+            step_details: "Search for the restaurant".to_string(),
+            agent_name: "web_surfer".to_string(),
+            instruction: "Search for the restaurant".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_step_decisions() {
+        let prompt = ScriptedPrompt::scripted(Answer::Yes, vec![Some(0), Some(1), None, Some(2)], vec!["Search on Bing"]);
+        let guard = CliActionGuard::with_prompt(prompt.clone(), Duration::from_secs(5));
+        assert_eq!(guard.get_step_approval(&step_request()).await, StepApprovalDecision::Approve);
+        assert_eq!(guard.get_step_approval(&step_request()).await, StepApprovalDecision::Reject);
+        // 取消选择按拒绝处理
+        assert_eq!(guard.get_step_approval(&step_request()).await, StepApprovalDecision::Reject);
+        assert_eq!(
+            guard.get_step_approval(&step_request()).await,
+            StepApprovalDecision::EditInstruction("Search on Bing".to_string())
+        );
+        let questions = prompt.questions.lock().unwrap().clone();
+        assert_eq!(questions[0], "Run this step? (rejected after 5s without an answer)");
+        assert_eq!(questions[4], "Instruction for web_surfer [Search for the restaurant]");
+
+        let guard = CliActionGuard::with_prompt(ScriptedPrompt::new(Answer::Never), Duration::from_millis(100));
+        assert_eq!(guard.get_step_approval(&step_request()).await, StepApprovalDecision::Reject);
+    }
+
+    #[tokio::test]
+    async fn test_rejection_reason() {
+        let prompt = ScriptedPrompt::scripted(Answer::Yes, Vec::new(), vec!["  Wrong restaurant ", ""]);
+        let guard = CliActionGuard::with_prompt(prompt, Duration::from_secs(5));
+        assert_eq!(guard.get_rejection_reason(&step_request()).await, Some("Wrong restaurant".to_string()));
+        assert_eq!(guard.get_rejection_reason(&step_request()).await, None);
+
+        let guard = CliActionGuard::with_prompt(ScriptedPrompt::new(Answer::Fail), Duration::from_secs(5));
+        assert_eq!(guard.get_rejection_reason(&step_request()).await, None);
+    }
+
+    // 回答时记录终端是否处于提问状态
+    #[derive(Debug, Default)]
+    struct ActivityProbe {
+        activity: Mutex<Option<PromptActivity>>,
+        seen: Mutex<Vec<bool>>,
+    }
+
+    impl ConfirmPrompt for ActivityProbe {
+        fn confirm(&self, _prompt: &str) -> Result<bool> {
+            let active = self.activity.lock().unwrap().as_ref().is_some_and(PromptActivity::is_active);
+            self.seen.lock().unwrap().push(active);
+            Ok(true)
+        }
+
+        fn select(&self, _prompt: &str, _items: &[&str]) -> Result<Option<usize>> {
+            Ok(None)
+        }
+
+        fn input(&self, _prompt: &str, _initial: &str) -> Result<String> {
+            Ok(String::new())
+        }
+
+        fn multi_select(&self, _prompt: &str, _items: &[&str]) -> Result<Option<Vec<usize>>> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prompts_mark_the_terminal_busy() {
+        let probe = Arc::new(ActivityProbe::default());
+        let guard = CliActionGuard::with_prompt(probe.clone(), Duration::from_secs(5));
+        *probe.activity.lock().unwrap() = Some(guard.activity());
+        assert!(!guard.activity().is_active());
+        assert!(guard.get_approval(url_request()).await);
+        assert_eq!(*probe.seen.lock().unwrap(), [true]);
+        assert!(!guard.activity().is_active());
+    }
+
+    /* 计划审批和修改菜单：依次给出 selects 中的选择、inputs 中的输入、removals 中的多选和 confirms 中的是/否，
+    记录显示过的菜单；confirms 用完之后回答否 */
+    #[derive(Debug, Default)]
+    struct MenuPrompt {
+        selects: Mutex<VecDeque<Option<usize>>>,
+        inputs: Mutex<VecDeque<String>>,
+        removals: Mutex<VecDeque<Vec<usize>>>,
+        confirms: Mutex<VecDeque<bool>>,
+        menus: Mutex<Vec<String>>,
+    }
+
+    impl MenuPrompt {
+        fn new(selects: Vec<Option<usize>>, inputs: Vec<&str>, removals: Vec<Vec<usize>>) -> Arc<Self> {
+            Arc::new(Self {
+                selects: Mutex::new(selects.into()),
+                inputs: Mutex::new(inputs.into_iter().map(str::to_string).collect()),
+                removals: Mutex::new(removals.into()),
+                confirms: Mutex::new(VecDeque::new()),
+                menus: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn confirming(selects: Vec<Option<usize>>, inputs: Vec<&str>, confirms: Vec<bool>) -> Arc<Self> {
+            let prompt = Self::new(selects, inputs, Vec::new());
+            *prompt.confirms.lock().unwrap() = confirms.into();
+            prompt
+        }
+    }
+
+    impl ConfirmPrompt for MenuPrompt {
+        fn confirm(&self, prompt: &str) -> Result<bool> {
+            self.menus.lock().unwrap().push(prompt.to_string());
+            Ok(self.confirms.lock().unwrap().pop_front().unwrap_or(false))
+        }
+
+        fn select(&self, prompt: &str, items: &[&str]) -> Result<Option<usize>> {
+            self.menus.lock().unwrap().push(format!("{}: {}", prompt, items.join(" | ")));
+            Ok(self.selects.lock().unwrap().pop_front().flatten())
+        }
+
+        fn input(&self, _prompt: &str, _initial: &str) -> Result<String> {
+            Ok(self.inputs.lock().unwrap().pop_front().unwrap_or_default())
+        }
+
+        fn multi_select(&self, prompt: &str, items: &[&str]) -> Result<Option<Vec<usize>>> {
+            self.menus.lock().unwrap().push(format!("{}: {}", prompt, items.join(" | ")));
+            Ok(self.removals.lock().unwrap().pop_front())
+        }
+    }
+
+    fn plan_request() -> PlanApprovalRequest {
+        let step = |title: &str| PlanStep {
+            id: new_step_id(),
+            title: title.to_string(),
+            details: format!("{} details", title),
+            agent_name: "web_surfer".to_string(),
+            expected_outcome: None,
+            kind: StepKind::Standard,
+        };
+        let plan = Plan {
+            task: Some("Order a latte".to_string()),
+            steps: vec![step("Open"), step("Order"), step("Check the menu")],
+        };
+        PlanApprovalRequest {
+            message: ChatMessage::new_text(MessageRole::Assistant, "orchestrator".to_string(), "Do you approve the following plan?".to_string()),
+            plan,
+            team: vec!["web_surfer".to_string(), "coder_agent".to_string()],
+            sentinel_enabled: false,
+            limits: PlanValidationLimits::default(),
+            previous_plan: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plan_approval_opens_the_plan_editor() {
+        let request = plan_request();
+        let ids: Vec<String> = request.plan.steps.iter().map(|s| s.id.clone()).collect();
+        let selects = vec![
+            // 修改计划：改第 3 步的标题和代理
+            Some(2), Some(0), Some(2), Some(1),
+            // 再改第 1 步，然后撤销
+            Some(0), Some(0), Some(0), Some(3),
+            // 完成后批准
+            Some(6), Some(0),
+        ];
+        let prompt = MenuPrompt::new(selects, vec!["Read the menu", "", "Open the cafe", ""], Vec::new());
+        let guard = CliActionGuard::with_prompt(prompt.clone(), Duration::from_secs(5));
+        let PlanApprovalDecision::Edited(plan) = guard.get_plan_approval(&request).await else {
+            panic!("expected an edited plan");
+        };
+        let titles: Vec<&str> = plan.steps.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(titles, ["Open", "Order", "Read the menu"]);
+        // 清空的详细说明保持不变，步骤的 id 不变
+        assert_eq!(plan.steps[2].details, "Check the menu details");
+        assert_eq!(plan.steps[2].agent_name, "coder_agent");
+        assert_eq!(plan.steps.iter().map(|s| s.id.clone()).collect::<Vec<_>>(), ids);
+
+        let menus = prompt.menus.lock().unwrap().clone();
+        assert_eq!(
+            menus[0],
+            "Run this plan? (rejected after 5s without an answer): Approve | Reject | Edit the plan | Regenerate the plan"
+        );
+        assert_eq!(
+            menus[1],
+            "How would you like to change the plan?: Edit a step | Move a step | Remove steps | Undo the last edit | \
+             Export to a file | Import from a file | Done"
+        );
+        assert!(menus[2].starts_with("Which step?: 1. ["), "{}", menus[2]);
+        assert_eq!(menus[3], "Agent: web_surfer | coder_agent");
+    }
+
+    #[tokio::test]
+    async fn test_plan_editor_moves_and_removes_steps() {
+        let request = plan_request();
+        let ids: Vec<String> = request.plan.steps.iter().map(|s| s.id.clone()).collect();
+        let selects = vec![
+            // 把第 3 步移到最前面
+            Some(2), Some(1), Some(2), Some(0),
+            // 批量删除第 3 步
+            Some(2),
+            // 删光所有步骤不合法，计划不变
+            Some(2),
+            // 再删第 1 步之后撤销
+            Some(2), Some(3),
+            // 完成后批准
+            Some(6), Some(0),
+        ];
+        let removals = vec![vec![2], vec![0, 1], vec![0]];
+        let prompt = MenuPrompt::new(selects, Vec::new(), removals);
+        let guard = CliActionGuard::with_prompt(prompt.clone(), Duration::from_secs(5));
+        let PlanApprovalDecision::Edited(plan) = guard.get_plan_approval(&request).await else {
+            panic!("expected an edited plan");
+        };
+        let titles: Vec<&str> = plan.steps.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(titles, ["Check the menu", "Open"]);
+        // 移动之后步骤的 id 不变
+        assert_eq!(plan.steps[0].id, ids[2]);
+        assert_eq!(plan.steps[1].id, ids[0]);
+
+        let menus = prompt.menus.lock().unwrap().clone();
+        assert_eq!(menus[3], "Move it to position: 1 | 2 | 3");
+        assert!(menus[5].starts_with("Steps to remove (space to select): 1. ["), "{}", menus[5]);
+    }
+
+    #[tokio::test]
+    async fn test_plan_editor_exports_and_imports_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let exported = dir.path().join("exported").join("latte.json");
+        let imported = dir.path().join("import.json");
+        let request = plan_request();
+        let mut replacement = request.plan.clone();
+        replacement.task = None;
+        replacement.steps.truncate(2);
+        replacement.steps[1].title = "Pay".to_string();
+        replacement.to_json_file(&imported)?;
+
+        let selects = vec![
+            // 导出，再导入两次：第一次不确认，第二次确认
+            Some(2), Some(4), Some(5), Some(5),
+            // 完成后批准
+            Some(6), Some(0),
+        ];
+        let exported_path = exported.display().to_string();
+        let imported_path = imported.display().to_string();
+        let inputs = vec![exported_path.as_str(), imported_path.as_str(), imported_path.as_str()];
+        let prompt = MenuPrompt::confirming(selects, inputs, vec![false, true]);
+        let guard = CliActionGuard::with_prompt(prompt.clone(), Duration::from_secs(5));
+        let PlanApprovalDecision::Edited(plan) = guard.get_plan_approval(&request).await else {
+            panic!("expected an edited plan");
+        };
+        let titles: Vec<&str> = plan.steps.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(titles, ["Open", "Pay"]);
+        // 文件中没有任务时沿用当前计划的任务
+        assert_eq!(plan.task.as_deref(), Some("Order a latte"));
+
+        // 导出的是编辑之前的计划
+        let written = Plan::from_json_file(&exported)?;
+        assert_eq!(written.steps.len(), 3);
+        assert_eq!(written.steps[2].title, "Check the menu");
+
+        // 每次导入都先确认
+        let menus = prompt.menus.lock().unwrap().clone();
+        let confirms = menus.iter().filter(|m| *m == "Replace the plan with the imported one?").count();
+        assert_eq!(confirms, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_plan_editor_reports_bad_imports() {
+        // 导入不存在的文件时显示原因，计划不变
+        let prompt = MenuPrompt::confirming(vec![Some(2), Some(5), Some(6), Some(0)], vec!["missing-plan.json"], vec![true]);
+        let guard = CliActionGuard::with_prompt(prompt.clone(), Duration::from_secs(5));
+        assert_eq!(guard.get_plan_approval(&plan_request()).await, PlanApprovalDecision::Approve);
+        assert!(prompt.menus.lock().unwrap().iter().all(|m| !m.starts_with("Replace the plan")));
+    }
+
+    #[tokio::test]
+    async fn test_plan_approval_without_edits() {
+        let guard = CliActionGuard::with_prompt(MenuPrompt::new(vec![Some(0)], Vec::new(), Vec::new()), Duration::from_secs(5));
+        assert_eq!(guard.get_plan_approval(&plan_request()).await, PlanApprovalDecision::Approve);
+        let guard = CliActionGuard::with_prompt(MenuPrompt::new(vec![Some(1)], Vec::new(), Vec::new()), Duration::from_secs(5));
+        assert_eq!(guard.get_plan_approval(&plan_request()).await, PlanApprovalDecision::Reject);
+        // 打开修改菜单但没有改动，批准原计划；取消选择按拒绝处理
+        let prompt = MenuPrompt::new(vec![Some(2), Some(6), Some(0)], Vec::new(), Vec::new());
+        let guard = CliActionGuard::with_prompt(prompt, Duration::from_secs(5));
+        assert_eq!(guard.get_plan_approval(&plan_request()).await, PlanApprovalDecision::Approve);
+        let guard = CliActionGuard::with_prompt(MenuPrompt::new(vec![None], Vec::new(), Vec::new()), Duration::from_secs(5));
+        assert_eq!(guard.get_plan_approval(&plan_request()).await, PlanApprovalDecision::Reject);
+    }
+
+    #[tokio::test]
+    async fn test_plan_approval_regenerates_with_a_reason() {
+        let prompt = MenuPrompt::new(vec![Some(3), Some(3)], vec!["  Use the cafe's own site ", ""], Vec::new());
+        let guard = CliActionGuard::with_prompt(prompt, Duration::from_secs(5));
+        assert_eq!(
+            guard.get_plan_approval(&plan_request()).await,
+            PlanApprovalDecision::Regenerate(Some("Use the cafe's own site".to_string()))
+        );
+        // 原因可以不填
+        assert_eq!(guard.get_plan_approval(&plan_request()).await, PlanApprovalDecision::Regenerate(None));
+    }
+
+    #[test]
+    fn test_regenerated_plans_show_the_changes() {
+        let mut request = plan_request();
+        assert_eq!(describe_changes(&request, Locale::En), None);
+        let mut previous = request.plan.clone();
+        previous.steps.remove(2);
+        previous.steps[1].title = "Pay".to_string();
+        request.previous_plan = Some(previous);
+        assert_eq!(
+            describe_changes(&request, Locale::En).unwrap(),
+            "Changes from the previous plan:\n~ 2. title changed\n+ 3. Check the menu"
+        );
+        assert!(describe_changes(&request, Locale::Zh).unwrap().starts_with("与之前计划相比："));
+    }
+
+    #[test]
+    fn test_describe_request_lists_the_target() {
+        assert_eq!(
+            describe_request(&url_request(), Locale::En),
+            "[web_surfer] The website https://shop.example.com/cart is not allowed. Would you like to allow the domain example.com for this session?\n\
+             Target: https://shop.example.com/cart (domain shop.example.com)"
+        );
+        let request = ChatMessage::new_text(MessageRole::User, "coder_agent".to_string(), "Run the script?".to_string());
+        assert_eq!(describe_request(&request, Locale::En), "[coder_agent] Run the script?");
+        assert!(describe_request(&url_request(), Locale::Zh).ends_with("\n目标：https://shop.example.com/cart（域名 shop.example.com）"));
+    }
+}
@@ -0,0 +1,677 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::{ArgAction, Args, Parser, Subcommand};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+
+use crate::cli::strings::Locale;
+use crate::config::ConfigSources;
+use crate::tools::approval_guard::ApprovalPolicy;
+use crate::tools::url_status_manager::SitePolicy;
+
+/// 终端输出的格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// 给人看的输出：计划、动作和最终答案
+    #[default]
+    Pretty,
+    /// 每行一个 JSON 对象：运行事件，最后是运行结果；出错时为 {"type": "error"}
+    Json,
+}
+
+impl OutputFormat {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            other => bail!("Unknown output format '{}'; available: pretty, json", other),
+        }
+    }
+}
+
+/* 命令行：server [共用参数] <子命令>。
+run、plan、resume、interactive 在终端中执行，不连接数据库；serve、migrate、keys 需要数据库，replay 通过 HTTP 接口读取，
+report 只读写运行目录，config 只读写配置文件。
+没有子命令时，标准输入和输出都是终端时进入 interactive，否则（容器、服务管理器）启动后端服务 */
+#[derive(Debug, Clone, PartialEq, Parser)]
+#[command(name = "server", about = "Plan and run web tasks with a team of agents, in the terminal or as a backend service")]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+    /// Start serving without waiting for the database, model service and browsers (without a subcommand)
+    #[arg(long)]
+    pub skip_ready: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+    /// Plan and run one task, then exit
+    Run(RunArgs),
+    /// Print the plan for a task without running it
+    Plan(PlanArgs),
+    /// Continue a stopped run from the checkpoint in its session directory
+    Resume(ResumeArgs),
+    /// Ask for tasks and run them one after another
+    Interactive,
+    /// Start the backend service
+    Serve(ServeArgs),
+    /// Replay the events of a run at their original pace
+    Replay(ReplayArgs),
+    /// Regenerate the HTML report of a run from its run directory
+    Report(ReportArgs),
+    /// Apply pending database migrations
+    Migrate(MigrateArgs),
+    /// Manage API keys
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommand,
+    },
+    /// Write a config template or print the merged config
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+impl Cli {
+    /* 解析命令行（不含程序名）；--json 是 --output json 的简写。
+    terminal 为标准输入和输出是否都是终端，决定没有子命令时进入 interactive 还是启动服务 */
+    pub fn parse_args<I, T>(args: I, terminal: bool) -> Result<(GlobalArgs, Command), clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let cli = Self::try_parse_from(std::iter::once(OsString::from("server")).chain(args.into_iter().map(Into::into)))?;
+        let mut global = cli.global;
+        if global.json {
+            global.output = OutputFormat::Json;
+        }
+        let command = match cli.command {
+            Some(command) => command,
+            None if terminal && !cli.skip_ready => Command::Interactive,
+            None => Command::Serve(ServeArgs { skip_ready: cli.skip_ready }),
+        };
+        Ok((global, command))
+    }
+
+    /// 命令行解析失败之前是否要求了 JSON 输出，此时错误也输出为 JSON
+    pub fn wants_json(args: &[String]) -> bool {
+        args.iter().enumerate().any(|(i, arg)| {
+            arg == "--json"
+                || arg == "--output=json"
+                || (arg == "--output" && args.get(i + 1).map(String::as_str) == Some("json"))
+        })
+    }
+}
+
+/* 所有子命令共用的参数，可以出现在命令行的任何位置：
+--output pretty|json（--json 为 --output json 的简写），-v 输出每次工具调用的细节，-vv 再加上模型请求和响应的摘要；
+--provider、--model、--planner-model、--temperature 作为命令行一层覆盖 [llm] 和 [models.orchestrator]；
+--lang en|zh 选择终端界面的语言，--approval-policy 覆盖 [approval] policy；
+--sites-file、--allow-site、--block-site 合并在 [sites] 之上，见 site_policy；
+--config 指定项目一层的配置文件，代替当前目录的 magentic.toml */
+#[derive(Debug, Clone, Default, PartialEq, Args)]
+pub struct GlobalArgs {
+    /// Config file to use instead of ./magentic.toml; ~/.magentic/config.toml is still read first
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<String>,
+    /// Output format: pretty or json (one JSON object per line)
+    #[arg(long, global = true, value_name = "FORMAT", value_parser = OutputFormat::parse, default_value = "pretty")]
+    pub output: OutputFormat,
+    /// Shorthand for --output json
+    #[arg(long, global = true)]
+    pub json: bool,
+    /// -v logs every tool call, -vv also logs model requests and responses
+    #[arg(short = 'v', long = "verbose", global = true, action = ArgAction::Count)]
+    pub verbosity: u8,
+    /// LLM provider: dashscope, openai, ollama or anthropic
+    #[arg(long, global = true, value_name = "NAME")]
+    pub provider: Option<String>,
+    /// Model for all agents without their own [models.<role>]
+    #[arg(long, global = true, value_name = "MODEL")]
+    pub model: Option<String>,
+    /// Model for planning and progress tracking (the orchestrator role)
+    #[arg(long, global = true, value_name = "MODEL")]
+    pub planner_model: Option<String>,
+    /// Sampling temperature between 0 and 2
+    #[arg(long, global = true, value_name = "T")]
+    pub temperature: Option<f64>,
+    /// Language of prompts and menus: en or zh
+    #[arg(long, global = true, value_name = "LANG", value_parser = Locale::parse)]
+    pub lang: Option<Locale>,
+    /// Which requests need approval: always, auto-conservative (plan steps are approved) or never
+    #[arg(long, global = true, value_name = "POLICY", value_parser = |value: &str| value.parse::<ApprovalPolicy>())]
+    pub approval_policy: Option<ApprovalPolicy>,
+
+    /// Directory for session checkpoints; `resume <DIR>` continues from it
+    #[arg(long, global = true, value_name = "DIR")]
+    pub session_dir: Option<String>,
+    /// Allow a site without asking, e.g. *.wikipedia.org (repeatable)
+    #[arg(long = "allow-site", global = true, value_name = "SITE")]
+    pub allow_sites: Vec<String>,
+    /// Never visit a site (repeatable)
+    #[arg(long = "block-site", global = true, value_name = "SITE")]
+    pub block_sites: Vec<String>,
+    /// JSON file with allowed_sites and blocked_sites
+    #[arg(long, global = true, value_name = "FILE")]
+    pub sites_file: Option<String>,
+}
+
+impl GlobalArgs {
+    /// 参数对应的配置项；服务名和温度的范围由 AppConfig::load 检查
+    pub fn config_overrides(&self) -> Vec<String> {
+        let mut overrides = Vec::new();
+        if let Some(provider) = &self.provider {
+            overrides.push(format!("llm.provider={}", provider));
+        }
+        if let Some(model) = &self.model {
+            overrides.push(format!("llm.model={}", model));
+        }
+        if let Some(model) = &self.planner_model {
+            overrides.push(format!("models.orchestrator.model={}", model));
+        }
+        if let Some(temperature) = self.temperature {
+            // 保留小数点，0 也按小数解析
+            overrides.push(format!("llm.temperature={:?}", temperature));
+        }
+        if let Some(policy) = self.approval_policy {
+            overrides.push(format!("approval.policy={}", policy.name()));
+        }
+        if let Some(dir) = &self.session_dir {
+            overrides.push(format!("output.session_dir={}", dir));
+        }
+        overrides
+    }
+
+    /// 参与合并的配置来源：默认位置的文件和环境变量，--config 代替项目配置文件，overrides 为命令行一层
+    pub fn config_sources(&self, overrides: Vec<String>) -> ConfigSources {
+        let mut sources = ConfigSources::discover(overrides);
+        if let Some(path) = &self.config {
+            sources.project_file = Some(PathBuf::from(path));
+        }
+        sources
+    }
+
+    /* 命令行一层的网站策略：--sites-file 中的策略上合并 --allow-site 和 --block-site，
+    同一个网站同时出现时以参数为准；调用方再把它合并到配置的 [sites] 之上 */
+    pub fn site_policy(&self) -> Result<SitePolicy> {
+        let file = match &self.sites_file {
+            Some(path) => SitePolicy::from_json_file(path)?,
+            None => SitePolicy::default(),
+        };
+        Ok(file.merge(SitePolicy { allowed_sites: self.allow_sites.clone(), blocked_sites: self.block_sites.clone() }))
+    }
+
+    /// 终端界面的语言：--lang，其次 MAGENTIC_LANG 和系统语言
+    pub fn locale(&self) -> Locale {
+        Locale::detect(self.lang, &std::env::vars().collect::<Vec<_>>())
+    }
+
+    /// 本项目的日志级别：默认 info，-v 为 debug，-vv 为 trace；依赖库的日志保持 info
+    pub fn log_filter(&self) -> Targets {
+        let level = match self.verbosity {
+            0 => LevelFilter::INFO,
+            1 => LevelFilter::DEBUG,
+            _ => LevelFilter::TRACE,
+        };
+        Targets::new()
+            .with_default(LevelFilter::INFO)
+            .with_target("mini_magentic_backend", level)
+            .with_target("server", level)
+    }
+}
+
+/* run 子命令的参数：run [--headless] [--approve-all] [--quiet] <任务>，任务可以分成多个参数；
+run --resume <会话目录> 与 resume 子命令相同，从目录中的检查点继续被停止的运行，不需要任务；
+run --plan-file <文件> 执行保存的计划，不调用模型规划，没有给出任务时使用文件中的任务 */
+#[derive(Debug, Clone, Default, PartialEq, Args)]
+pub struct RunArgs {
+    /// The task, quoted or as separate words
+    #[arg(value_name = "TASK", required_unless_present_any = ["resume", "plan_file"])]
+    pub words: Vec<String>,
+    /// Run the browser without a window
+    #[arg(long)]
+    pub headless: bool,
+    /// Approve every action and step without asking
+    #[arg(long)]
+    pub approve_all: bool,
+    /// Print one line per step
+    #[arg(long)]
+    pub quiet: bool,
+    /// Continue the stopped run in this session directory instead of starting a task
+    #[arg(long, value_name = "SESSION_DIR", conflicts_with = "words")]
+    pub resume: Option<String>,
+    /// Simulate the steps without a browser and print what they would do
+    #[arg(long, conflicts_with = "resume")]
+    pub dry_run: bool,
+    /// Run the steps of a saved plan (JSON) instead of planning
+    #[arg(long, value_name = "FILE", conflicts_with = "resume")]
+    pub plan_file: Option<String>,
+    /// Allow sentinel steps that repeat a check until a condition is met
+    #[arg(long)]
+    pub sentinel: bool,
+    /// Do not print the token and cost summary after the answer
+    #[arg(long)]
+    pub no_cost: bool,
+}
+
+impl RunArgs {
+    pub fn task(&self) -> String {
+        self.words.join(" ").trim().to_string()
+    }
+
+    /// 参数对应的配置项，作为命令行一层合并进 AppConfig
+    pub fn config_overrides(&self) -> Vec<String> {
+        let mut overrides = Vec::new();
+        if self.headless {
+            overrides.push("browser.headless=true".to_string());
+        }
+        if self.approve_all {
+            overrides.push("approval.approve_all=true".to_string());
+        }
+        if self.sentinel {
+            overrides.push("orchestrator.sentinel_tasks=true".to_string());
+        }
+        if self.no_cost {
+            overrides.push("output.cost_summary=false".to_string());
+        }
+        overrides
+    }
+}
+
+/* plan 子命令：规划流程与 run 相同，输出计划后退出，不执行任何步骤。
+-o 把计划写成计划文件，之后用 run --plan-file 执行；--output 已经是全局的输出格式，长参数名为 --output-file */
+#[derive(Debug, Clone, Default, PartialEq, Args)]
+pub struct PlanArgs {
+    /// The task, quoted or as separate words
+    #[arg(value_name = "TASK", required = true)]
+    pub words: Vec<String>,
+    /// Run the browser without a window
+    #[arg(long)]
+    pub headless: bool,
+    /// Allow sentinel steps that repeat a check until a condition is met
+    #[arg(long)]
+    pub sentinel: bool,
+    /// Also write the plan to this file, for run --plan-file
+    #[arg(short = 'o', long, value_name = "FILE")]
+    pub output_file: Option<PathBuf>,
+}
+
+impl PlanArgs {
+    pub fn task(&self) -> String {
+        self.words.join(" ").trim().to_string()
+    }
+}
+
+impl From<PlanArgs> for RunArgs {
+    fn from(args: PlanArgs) -> Self {
+        Self { words: args.words, headless: args.headless, sentinel: args.sentinel, ..Self::default() }
+    }
+}
+
+// resume 子命令：resume <会话目录>，等同于 run --resume <会话目录>
+#[derive(Debug, Clone, Default, PartialEq, Args)]
+pub struct ResumeArgs {
+    /// Session directory with the checkpoint of the stopped run
+    #[arg(value_name = "SESSION_DIR")]
+    pub dir: String,
+    /// Run the browser without a window
+    #[arg(long)]
+    pub headless: bool,
+    /// Approve every action and step without asking
+    #[arg(long)]
+    pub approve_all: bool,
+    /// Print one line per step
+    #[arg(long)]
+    pub quiet: bool,
+}
+
+impl From<ResumeArgs> for RunArgs {
+    fn from(args: ResumeArgs) -> Self {
+        Self {
+            words: Vec::new(),
+            headless: args.headless,
+            approve_all: args.approve_all,
+            quiet: args.quiet,
+            resume: Some(args.dir),
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Args)]
+pub struct ServeArgs {
+    /// Start serving without waiting for the database, model service and browsers
+    #[arg(long)]
+    pub skip_ready: bool,
+}
+
+/* replay 子命令：replay <run-id> [--max-gap <秒>] [--url <地址>]，按原来的节奏在终端中重新展示一次运行的事件。
+--max-gap 限制事件之间的等待（0 为不等待），地址默认为 server.bind */
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct ReplayArgs {
+    pub run_id: String,
+    /// Longest wait between two events in seconds, 0 replays without waiting
+    #[arg(long, value_name = "SECONDS")]
+    pub max_gap: Option<f64>,
+    /// Base URL of the backend, defaults to http://<server.bind>
+    #[arg(long, value_name = "BASE_URL")]
+    pub url: Option<String>,
+}
+
+// report 子命令：report <运行目录>，用目录中的 run.json 重新生成 report.html
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct ReportArgs {
+    /// Run directory with the run.json written at the end of the run
+    #[arg(value_name = "RUN_DIR")]
+    pub run_dir: String,
+}
+
+// migrate 子命令：执行未执行的迁移；migrate --down <版本> 回退到该版本，只用于本地开发
+#[derive(Debug, Clone, Default, PartialEq, Args)]
+pub struct MigrateArgs {
+    /// Revert the migrations after this version
+    #[arg(long, value_name = "VERSION")]
+    pub down: Option<i64>,
+}
+
+/* keys 子命令：keys create <user_id> [label] 生成 key 并只打印这一次明文，
+keys list <user_id> 列出该用户的 key，keys revoke <key_id> 撤销 */
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum KeysCommand {
+    /// Create a key; its value is printed only once
+    Create { user_id: String, label: Option<String> },
+    /// List the keys of a user
+    List { user_id: String },
+    /// Revoke a key
+    Revoke { key_id: String },
+}
+
+/* config 子命令：config init [路径] 写出带注释的模板，默认为 ~/.magentic/config.toml，不覆盖已有文件；
+config show 输出各层合并后的配置（包括命令行参数），api_key 等敏感字段被遮盖 */
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum ConfigCommand {
+    /// Write a commented config template, by default to ~/.magentic/config.toml
+    Init { path: Option<String> },
+    /// Print the merged config with secrets masked
+    Show,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::AppConfig;
+
+    fn parse(words: &[&str]) -> Result<(GlobalArgs, Command), clap::Error> {
+        Cli::parse_args(words, false)
+    }
+
+    fn run_args(words: &[&str]) -> Result<RunArgs, clap::Error> {
+        match parse(&[&["run"], words].concat())?.1 {
+            Command::Run(args) => Ok(args),
+            other => panic!("expected run, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_run_args() -> Result<()> {
+        let parsed = run_args(&["check if example.com is reachable", "--headless", "--approve-all"])?;
+        assert_eq!(
+            parsed,
+            RunArgs {
+                words: vec!["check if example.com is reachable".to_string()],
+                headless: true,
+                approve_all: true,
+                quiet: false,
+                resume: None,
+                dry_run: false,
+                plan_file: None,
+                sentinel: false,
+                no_cost: false,
+            }
+        );
+        assert_eq!(parsed.task(), "check if example.com is reachable");
+        assert_eq!(run_args(&["--resume", "sessions/latest"])?.resume.as_deref(), Some("sessions/latest"));
+        assert!(run_args(&["--resume"]).is_err());
+        assert!(run_args(&["--quiet", "task"])?.quiet);
+        assert!(run_args(&["--dry-run", "task"])?.dry_run);
+        assert!(run_args(&["--dry-run", "--resume", "sessions/latest"]).is_err());
+        // 计划文件中有任务，可以不给出任务
+        assert_eq!(run_args(&["--plan-file", "nightly.json"])?.plan_file.as_deref(), Some("nightly.json"));
+        assert!(run_args(&["--plan-file", "nightly.json", "--resume", "sessions/latest"]).is_err());
+        // 没有引号时各个词拼成任务
+        assert_eq!(run_args(&["summarize", "example.com"])?.task(), "summarize example.com");
+
+        assert!(run_args(&["--quiet"]).unwrap_err().to_string().contains("<TASK>"));
+        assert!(run_args(&["--headles", "task"]).unwrap_err().to_string().contains("unexpected argument '--headles'"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_subcommands() -> Result<()> {
+        let (_, command) = parse(&["plan", "Find", "a", "hotel"])?;
+        let words = vec!["Find".into(), "a".into(), "hotel".into()];
+        assert_eq!(command, Command::Plan(PlanArgs { words, headless: false, sentinel: false, output_file: None }));
+        assert!(parse(&["plan"]).is_err());
+        // -o 写计划文件，与全局的 --output 格式互不影响
+        let (global, command) = parse(&["plan", "Find a hotel", "-o", "plans/hotel.json", "--output", "json"])?;
+        let Command::Plan(plan) = command else { panic!("expected plan") };
+        assert_eq!(plan.output_file, Some(PathBuf::from("plans/hotel.json")));
+        assert_eq!(global.output, OutputFormat::Json);
+        let (_, command) = parse(&["plan", "--output-file", "hotel.json", "Find a hotel"])?;
+        let Command::Plan(plan) = command else { panic!("expected plan") };
+        assert_eq!(plan.output_file, Some(PathBuf::from("hotel.json")));
+
+        // resume <目录> 与 run --resume <目录> 相同
+        let (_, command) = parse(&["resume", "sessions/latest", "--quiet"])?;
+        let Command::Resume(resume) = command else { panic!("expected resume") };
+        assert_eq!(RunArgs::from(resume), run_args(&["--resume", "sessions/latest", "--quiet"])?);
+
+        assert_eq!(parse(&["interactive"])?.1, Command::Interactive);
+        assert_eq!(parse(&["migrate", "--down", "3"])?.1, Command::Migrate(MigrateArgs { down: Some(3) }));
+        assert_eq!(
+            parse(&["keys", "create", "alice"])?.1,
+            Command::Keys { command: KeysCommand::Create { user_id: "alice".into(), label: None } }
+        );
+        assert!(parse(&["replay", "run-1", "--max-gap", "soon"]).is_err());
+        assert_eq!(parse(&["report", "artifacts/run-1"])?.1, Command::Report(ReportArgs { run_dir: "artifacts/run-1".into() }));
+        assert!(parse(&["report"]).is_err());
+        assert_eq!(parse(&["config", "init"])?.1, Command::Config { command: ConfigCommand::Init { path: None } });
+        assert_eq!(
+            parse(&["config", "init", "magentic.toml"])?.1,
+            Command::Config { command: ConfigCommand::Init { path: Some("magentic.toml".into()) } }
+        );
+        assert_eq!(parse(&["--model", "qwen-max", "config", "show"])?.1, Command::Config { command: ConfigCommand::Show });
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_command_depends_on_the_terminal() -> Result<()> {
+        let no_args: [&str; 0] = [];
+        assert_eq!(Cli::parse_args(no_args, true)?.1, Command::Interactive);
+        assert_eq!(Cli::parse_args(no_args, false)?.1, Command::Serve(ServeArgs { skip_ready: false }));
+        // --skip-ready 只对服务有意义
+        assert_eq!(Cli::parse_args(["--skip-ready"], true)?.1, Command::Serve(ServeArgs { skip_ready: true }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_global_args() -> Result<()> {
+        let (global, command) = parse(&["--output", "json", "run", "-vv", "Find", "the", "menu"])?;
+        assert_eq!(global, GlobalArgs { output: OutputFormat::Json, verbosity: 2, ..Default::default() });
+        assert_eq!(command, Command::Run(RunArgs { words: vec!["Find".into(), "the".into(), "menu".into()], ..Default::default() }));
+
+        let (global, command) = parse(&["run", "--json", "-v", "--quiet", "task"])?;
+        assert_eq!(global, GlobalArgs { output: OutputFormat::Json, json: true, verbosity: 1, ..Default::default() });
+        assert_eq!(command, Command::Run(RunArgs { words: vec!["task".into()], quiet: true, ..Default::default() }));
+        assert_eq!(parse(&["--output=pretty", "run", "task"])?.0.output, OutputFormat::Pretty);
+
+        assert!(parse(&["--output", "yaml", "run", "task"])
+            .unwrap_err()
+            .to_string()
+            .contains("Unknown output format 'yaml'; available: pretty, json"));
+        assert!(parse(&["run", "task", "--output"]).is_err());
+
+        assert_eq!(parse(&["--lang", "zh", "interactive"])?.0.lang, Some(Locale::Zh));
+        assert_eq!(parse(&["--lang=en", "interactive"])?.0.locale(), Locale::En);
+        assert!(parse(&["--lang", "fr", "interactive"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_wants_json() {
+        let args = |words: &[&str]| words.iter().map(|word| word.to_string()).collect::<Vec<_>>();
+        assert!(Cli::wants_json(&args(&["run", "--json", "--bogus"])));
+        assert!(Cli::wants_json(&args(&["--output", "json", "run"])));
+        assert!(Cli::wants_json(&args(&["run", "--output=json"])));
+        assert!(!Cli::wants_json(&args(&["run", "json"])));
+    }
+
+    #[test]
+    fn test_model_flags_override_the_config() -> Result<()> {
+        let (global, command) = parse(&[
+            "--provider=openai",
+            "run",
+            "--model",
+            "gpt-4o",
+            "--planner-model",
+            "o3-mini",
+            "--temperature",
+            "0",
+            "task",
+        ])?;
+        assert_eq!(command, Command::Run(RunArgs { words: vec!["task".into()], ..Default::default() }));
+        let sources = ConfigSources {
+            env: vec![("MAGENTIC_LLM__MODEL".to_string(), "qwen-plus".to_string())],
+            overrides: global.config_overrides(),
+            ..Default::default()
+        };
+        let config = AppConfig::load(&sources)?;
+        assert_eq!(config.llm.provider.as_deref(), Some("openai"));
+        // 命令行覆盖环境变量，规划使用单独的模型
+        assert_eq!(config.llm.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(config.models["orchestrator"].model.as_deref(), Some("o3-mini"));
+        assert_eq!(config.llm.temperature, Some(0.0));
+
+        let (global, _) = parse(&["--provider", "foo", "run", "task"])?;
+        let sources = ConfigSources { overrides: global.config_overrides(), ..Default::default() };
+        assert_eq!(
+            AppConfig::load(&sources).unwrap_err().to_string(),
+            "unknown provider 'foo'; available: dashscope, openai, ollama, anthropic"
+        );
+        assert!(parse(&["--temperature", "warm", "run", "task"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_approval_policy_flag_overrides_the_config() -> Result<()> {
+        let (global, _) = parse(&["interactive", "--approval-policy", "auto-conservative"])?;
+        let sources = ConfigSources {
+            env: vec![("MAGENTIC_APPROVAL__POLICY".to_string(), "never".to_string())],
+            overrides: global.config_overrides(),
+            ..Default::default()
+        };
+        assert_eq!(AppConfig::load(&sources)?.approval.policy, ApprovalPolicy::AutoConservative);
+
+        assert!(parse(&["run", "--approval-policy", "sometimes", "task"])
+            .unwrap_err()
+            .to_string()
+            .contains("expected one of: always, auto-conservative, never"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_dir_flag_sets_the_checkpoint_directory() -> Result<()> {
+        let (global, _) = parse(&["run", "--session-dir", "/tmp/sessions", "Find the menu"])?;
+        let sources = ConfigSources { overrides: global.config_overrides(), ..Default::default() };
+        assert_eq!(AppConfig::load(&sources)?.output.session_dir.as_deref(), Some("/tmp/sessions"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_flag_replaces_the_project_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("nightly.toml");
+        std::fs::write(&file, "[llm]\nmodel = \"qwen-turbo\"\n\n[browser]\nheadless = true\n")?;
+        let (global, command) = parse(&["--config", file.to_str().unwrap(), "run", "--model", "qwen-max", "task"])?;
+        let Command::Run(args) = command else { panic!("expected run") };
+        let sources = global.config_sources([global.config_overrides(), args.config_overrides()].concat());
+        assert_eq!(sources.project_file.as_deref(), Some(file.as_path()));
+        let config = AppConfig::load(&sources)?;
+        // 文件中的设置生效，命令行参数仍然优先
+        assert!(config.browser.headless);
+        assert_eq!(config.llm.model.as_deref(), Some("qwen-max"));
+
+        // 给出的文件不存在时报错，不会静默使用默认值
+        let (global, _) = parse(&["--config", "missing.toml", "interactive"])?;
+        let error = AppConfig::load(&global.config_sources(Vec::new())).unwrap_err();
+        assert!(error.to_string().contains("missing.toml"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_site_flags_take_precedence_over_the_sites_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("sites.json");
+        std::fs::write(&file, r#"{"allowed_sites": ["example.com", "bing.com"], "blocked_sites": ["ads.example.com"]}"#)?;
+        let (global, _) = parse(&[
+            "run",
+            "--sites-file",
+            file.to_str().unwrap(),
+            "--allow-site",
+            "*.wikipedia.org",
+            "--block-site",
+            "example.com",
+            "--allow-site=ads.example.com",
+            "Find the menu",
+        ])?;
+        let policy = global.site_policy()?;
+        assert_eq!(policy.allowed_sites, vec!["bing.com", "*.wikipedia.org", "ads.example.com"]);
+        assert_eq!(policy.blocked_sites, vec!["example.com"]);
+
+        // 没有参数时为空策略，文件读取失败时给出路径
+        assert_eq!(parse(&["interactive"])?.0.site_policy()?, SitePolicy::default());
+        let (global, _) = parse(&["run", "--sites-file", "missing.json", "task"])?;
+        assert!(global.site_policy().unwrap_err().to_string().contains("missing.json"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verbosity_sets_the_log_level() -> Result<()> {
+        for (flags, level) in [(vec![], LevelFilter::INFO), (vec!["-v"], LevelFilter::DEBUG), (vec!["-v", "-v"], LevelFilter::TRACE)] {
+            let filter = parse(&[flags, vec!["interactive"]].concat())?.0.log_filter();
+            assert!(filter.would_enable("mini_magentic_backend::clients::models", &level.into_level().unwrap()));
+            // 依赖库不跟着变得啰嗦
+            assert!(!filter.would_enable("hyper::proto", &tracing::Level::DEBUG));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_flags_override_the_config() -> Result<()> {
+        let parsed = run_args(&["--headless", "--approve-all", "--no-cost", "task"])?;
+        let config = AppConfig::load(&ConfigSources { overrides: parsed.config_overrides(), ..Default::default() })?;
+        assert!(config.browser.headless);
+        assert!(config.approval.approve_all);
+        assert!(!config.output.cost_summary);
+
+        // --sentinel 打开哨兵步骤，没有 orchestrator 配置文件时也生效
+        let (_, command) = parse(&["plan", "--sentinel", "Tell me when the price drops"])?;
+        let Command::Plan(plan) = command else { panic!("expected plan") };
+        let config = AppConfig::load(&ConfigSources { overrides: RunArgs::from(plan).config_overrides(), ..Default::default() })?;
+        assert!(config.orchestrator.sentinel_tasks);
+
+        assert!(run_args(&["task"])?.config_overrides().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cli_definition() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+    }
+}
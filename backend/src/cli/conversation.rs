@@ -0,0 +1,134 @@
+use crate::orchestrator::message::estimate_text_tokens;
+use crate::orchestrator::types::RunOutcome;
+
+// 一轮对话：任务、最终答案和 ledger 收集到的信息
+#[derive(Debug, Clone, PartialEq)]
+struct Turn {
+    task: String,
+    answer: String,
+    information: String,
+}
+
+impl Turn {
+    fn render(&self) -> String {
+        let mut text = format!("User: {}\nAnswer: {}", self.task, self.answer);
+        let information = self.information.trim();
+        if !information.is_empty() && information != self.answer.trim() {
+            text.push_str(&format!("\nInformation collected: {}", information));
+        }
+        text
+    }
+}
+
+/* 交互模式中同一会话的对话。继续上一个任务时渲染成上下文交给下一次规划（RunOptions::context），
+使后续请求中的“同样的”“那里”等指代可以理解；开始新任务时清空。
+最多占用 [cli] context_tokens 个 token，超过时从最旧的一轮开始丢弃，只剩一轮仍然放不下时截断它的结尾；max_tokens 为 0 时不带入上下文 */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conversation {
+    turns: Vec<Turn>,
+    max_tokens: usize,
+}
+
+impl Conversation {
+    pub fn new(max_tokens: usize) -> Self {
+        Self { turns: Vec::new(), max_tokens }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.turns.is_empty()
+    }
+
+    /// 记录完成的任务
+    pub fn record(&mut self, task: &str, outcome: &RunOutcome) {
+        if self.max_tokens == 0 {
+            return;
+        }
+        self.turns.push(Turn {
+            task: task.trim().to_string(),
+            answer: outcome.final_answer.trim().to_string(),
+            information: outcome.information_collected.clone(),
+        });
+        while self.turns.len() > 1 && estimate_text_tokens(&self.render()) > self.max_tokens {
+            self.turns.remove(0);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.turns.clear();
+    }
+
+    /// 交给下一次规划的上下文，没有记录时为 None
+    pub fn context(&self) -> Option<String> {
+        if self.turns.is_empty() {
+            return None;
+        }
+        let text = self.render();
+        if estimate_text_tokens(&text) <= self.max_tokens {
+            return Some(text);
+        }
+        // 按 estimate_text_tokens 的估算截断，留出省略号的位置
+        let mut truncated = String::new();
+        for c in text.chars() {
+            truncated.push(c);
+            if estimate_text_tokens(&truncated) + 1 > self.max_tokens {
+                truncated.pop();
+                break;
+            }
+        }
+        truncated.push('…');
+        Some(truncated)
+    }
+
+    fn render(&self) -> String {
+        self.turns.iter().map(Turn::render).collect::<Vec<_>>().join("\n\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::metrics::OrchestratorMetrics;
+
+    fn outcome(final_answer: &str, information_collected: &str) -> RunOutcome {
+        RunOutcome {
+            final_answer: final_answer.to_string(),
+            plan: None,
+            metrics: OrchestratorMetrics::new(),
+            artifacts: Vec::new(),
+            step_outcomes: Vec::new(),
+            information_collected: information_collected.to_string(),
+            cost_summary: None,
+        }
+    }
+
+    #[test]
+    fn test_context_is_capped_by_tokens() {
+        let mut conversation = Conversation::new(40);
+        assert_eq!(conversation.context(), None);
+
+        conversation.record("Find the weather for this week", &outcome("Sunny all week.", "Forecast from weather.example.com"));
+        assert_eq!(
+            conversation.context().as_deref(),
+            Some("User: Find the weather for this week\nAnswer: Sunny all week.\nInformation collected: Forecast from weather.example.com")
+        );
+
+        // 放不下时丢弃最旧的一轮
+        conversation.record("Now do the same for next week", &outcome("Rain on Monday.", "Rain on Monday."));
+        assert_eq!(conversation.context().as_deref(), Some("User: Now do the same for next week\nAnswer: Rain on Monday."));
+
+        // 只剩一轮仍然放不下时截断
+        conversation.record("Summarize the month", &outcome(&"warm ".repeat(100), ""));
+        let context = conversation.context().unwrap();
+        assert!(context.starts_with("User: Summarize the month\nAnswer: warm warm"), "{}", context);
+        assert!(context.ends_with('…'));
+        assert!(estimate_text_tokens(&context) <= 40);
+
+        conversation.clear();
+        assert!(conversation.is_empty());
+
+        // 0 表示不带入上下文
+        let mut disabled = Conversation::new(0);
+        disabled.record("Find the menu", &outcome("Pizza.", ""));
+        assert_eq!(disabled.context(), None);
+    }
+}
@@ -0,0 +1,541 @@
+use std::io::{self, Write};
+use std::time::Instant;
+
+use colored::Colorize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::cli::action_guard::PromptActivity;
+use crate::agents::AgentEvent;
+use crate::cli::progress::{StepProgress, REFRESH_INTERVAL};
+use crate::cli::strings::Locale;
+use crate::orchestrator::event_log::encode_event;
+use crate::orchestrator::events::OrchestratorEvent;
+
+/* 在终端中展示运行事件。规划时模型输出的片段（PlanTextStreamed）直接接着输出，
+规划模型还在输出时不再单独打印 PlanStepStreamed（文字中已经能看到）；其他事件按 render_localized 成行输出，
+输出前先结束没有换行的片段。代理的动作逐行输出，结果和保存的截图缩进在下面，截图路径显示为暗色。
+quiet 时每个步骤只输出一行；设置了 activity 时，终端提问期间的事件暂存，回答之后再输出。
+设置了 progress 时运行中的步骤显示为 spinner（用时或哨兵步骤的倒计时），事件行输出在 spinner 上方，
+步骤结束后输出用时 */
+pub struct EventPrinter<W: Write> {
+    out: W,
+    // 上一个片段没有以换行结束
+    mid_line: bool,
+    // 本次规划已经收到过片段，直到 PlanReady
+    streaming_plan: bool,
+    quiet: bool,
+    activity: Option<PromptActivity>,
+    // 提问期间暂存的行
+    pending: Vec<String>,
+    progress: Option<StepProgress>,
+    locale: Locale,
+}
+
+impl<W: Write> EventPrinter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            mid_line: false,
+            streaming_plan: false,
+            quiet: false,
+            activity: None,
+            pending: Vec::new(),
+            progress: None,
+            locale: Locale::default(),
+        }
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// 事件行的语言，进度在 timed 中单独设置
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    /// 终端提问期间（CliActionGuard::activity）暂停输出
+    pub fn pause_during(mut self, activity: PromptActivity) -> Self {
+        self.activity = Some(activity);
+        self
+    }
+
+    /// 步骤进度，只在终端中显示（输出重定向或 quiet 时不设置）
+    pub fn timed(mut self, progress: StepProgress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub fn print(&mut self, event: &OrchestratorEvent) -> io::Result<()> {
+        if self.quiet {
+            let lines = quiet_line(event, self.locale).into_iter().collect();
+            return self.write_lines(lines);
+        }
+        match event {
+            OrchestratorEvent::PlanTextStreamed { text } => {
+                write!(self.out, "{}", text)?;
+                if !text.is_empty() {
+                    self.mid_line = !text.ends_with('\n');
+                }
+                self.streaming_plan = true;
+                self.out.flush()
+            }
+            OrchestratorEvent::PlanStepStreamed { .. } if self.streaming_plan => Ok(()),
+            _ => {
+                if self.mid_line {
+                    writeln!(self.out)?;
+                    self.mid_line = false;
+                }
+                if matches!(event, OrchestratorEvent::PlanReady { .. }) {
+                    self.streaming_plan = false;
+                }
+                let mut lines = render_localized(event, self.locale);
+                if matches!(event, OrchestratorEvent::ScreenshotSaved { .. }) {
+                    lines = lines.into_iter().map(|line| line.dimmed().to_string()).collect();
+                }
+                if let Some(progress) = &mut self.progress {
+                    lines.extend(progress.observe(event, Instant::now()));
+                }
+                self.write_lines(lines)
+            }
+        }
+    }
+
+    // 提问期间只暂存，之后先输出暂存的行
+    fn write_lines(&mut self, lines: Vec<String>) -> io::Result<()> {
+        self.pending.extend(lines);
+        if self.activity.as_ref().is_some_and(PromptActivity::is_active) {
+            return Ok(());
+        }
+        self.flush_pending()
+    }
+
+    fn flush_pending(&mut self) -> io::Result<()> {
+        let (out, pending) = (&mut self.out, &mut self.pending);
+        let mut write = || {
+            for line in pending.drain(..) {
+                writeln!(out, "{}", line)?;
+            }
+            out.flush()
+        };
+        match &self.progress {
+            Some(progress) => progress.suspend(write),
+            None => write(),
+        }
+    }
+
+    // 刷新 spinner；终端提问期间收起，避免和提问抢同一行
+    fn refresh(&mut self) {
+        let asking = self.activity.as_ref().is_some_and(PromptActivity::is_active);
+        if let Some(progress) = &mut self.progress {
+            if asking {
+                progress.hide();
+            } else {
+                progress.refresh(Instant::now());
+            }
+        }
+    }
+
+    /// 打印订阅到的事件直到 orchestrator 被释放（通道关闭）；跟不上时跳过错过的事件
+    pub async fn print_all(mut self, mut events: broadcast::Receiver<OrchestratorEvent>) -> io::Result<W> {
+        let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            let received = tokio::select! {
+                received = events.recv() => received,
+                _ = refresh.tick(), if self.progress.is_some() => {
+                    self.refresh();
+                    continue;
+                }
+            };
+            match received {
+                Ok(event) => self.print(&event)?,
+                Err(RecvError::Lagged(skipped)) => tracing::debug!("The terminal skipped {} events", skipped),
+                Err(RecvError::Closed) => {
+                    if let Some(progress) = &mut self.progress {
+                        progress.hide();
+                    }
+                    self.flush_pending()?;
+                    return Ok(self.into_inner());
+                }
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+// quiet 时的输出：计划、每个步骤开始、重规划和取消各一行
+fn quiet_line(event: &OrchestratorEvent, locale: Locale) -> Option<String> {
+    match event {
+        OrchestratorEvent::PlanReady { plan } => Some(locale.format("event.plan_ready", &[&plan.steps.len()])),
+        OrchestratorEvent::StepStarted { step_index, agent_name, .. } => {
+            Some(locale.format("event.step_agent", &[&(step_index + 1), agent_name]))
+        }
+        OrchestratorEvent::LedgerEvaluated { need_to_replan: true, .. }
+        | OrchestratorEvent::StallDetected { .. }
+        | OrchestratorEvent::Cancelled { .. } => render_localized(event, locale).into_iter().next(),
+        _ => None,
+    }
+}
+
+/* 终端中的事件行，文字从 Locale 的表中取；行的划分和 render_event（回放使用）一致，
+英文时输出完全相同 */
+pub fn render_localized(event: &OrchestratorEvent, locale: Locale) -> Vec<String> {
+    let line = |key: &'static str, args: &[&dyn std::fmt::Display]| vec![locale.format(key, args)];
+    match event {
+        OrchestratorEvent::PlanStepStreamed { step_index, step } => {
+            line("event.planning_step", &[&(step_index + 1), &step.title, &step.agent_name])
+        }
+        // 片段由终端直接接着输出，不单独成行
+        OrchestratorEvent::PlanTextStreamed { .. } => Vec::new(),
+        OrchestratorEvent::PlanReady { plan } => {
+            let mut lines = line("event.plan_ready", &[&plan.steps.len()]);
+            for (index, step) in plan.steps.iter().enumerate() {
+                lines.push(format!("  {}. {} [{}]", index + 1, step.title, step.agent_name));
+            }
+            lines
+        }
+        OrchestratorEvent::PlanEstimated { estimate, warnings } => {
+            let mut lines = line("event.estimate", &[&estimate.summary()]);
+            lines.extend(warnings.iter().map(|warning| locale.format("event.estimate_warning", &[warning])));
+            lines
+        }
+        OrchestratorEvent::PlanAutoApproved { policy, reason } => line("event.plan_auto_approved", &[policy, reason]),
+        OrchestratorEvent::SessionResumed { step_index, remaining_steps } => {
+            line("event.resumed", &[&(step_index + 1), remaining_steps])
+        }
+        OrchestratorEvent::StepApprovalRequested { step_index, title, .. } => {
+            line("event.step_approval", &[&(step_index + 1), title])
+        }
+        OrchestratorEvent::StepApproved { step_index, edited } => {
+            let key = if *edited { "event.step_approved_edited" } else { "event.step_approved" };
+            line(key, &[&(step_index + 1)])
+        }
+        OrchestratorEvent::StepRejected { step_index, reason } => match reason {
+            Some(reason) => line("event.step_rejected_reason", &[&(step_index + 1), reason]),
+            None => line("event.step_rejected", &[&(step_index + 1)]),
+        },
+        OrchestratorEvent::StepStarted { step_index, agent_name, instruction } => {
+            line("event.step_started", &[&(step_index + 1), agent_name, instruction])
+        }
+        OrchestratorEvent::LedgerEvaluated { step_index, step_complete, need_to_replan, next_speaker } => {
+            let state = match (step_complete, need_to_replan) {
+                (_, true) => "event.ledger.replan",
+                (true, false) => "event.ledger.complete",
+                (false, false) => "event.ledger.in_progress",
+            };
+            line("event.ledger", &[&(step_index + 1), &locale.text(state), next_speaker])
+        }
+        OrchestratorEvent::AgentEvent { agent_name, event, .. } => match event {
+            AgentEvent::Thought { text } => line("event.thought", &[agent_name, text]),
+            AgentEvent::ActionProposed { action, explanation } => line("event.action", &[agent_name, action, explanation]),
+            // 结果缩进在对应的动作下面
+            AgentEvent::ActionResult { action, result } => line("event.action_result", &[agent_name, action, result]),
+        },
+        OrchestratorEvent::ScreenshotSaved { agent_name, path, .. } => line("event.screenshot", &[agent_name, path]),
+        OrchestratorEvent::AgentNameReassigned { step_index, requested, assigned } => {
+            line("event.reassigned", &[&(step_index + 1), requested, assigned])
+        }
+        OrchestratorEvent::AgentNotRegistered { step_index, agent_name } => {
+            line("event.not_registered", &[&(step_index + 1), agent_name])
+        }
+        OrchestratorEvent::StallDetected { step_index, rounds } => line("event.stalled", &[&(step_index + 1), rounds]),
+        OrchestratorEvent::SentinelWaiting { step_index, check, total, next_check_secs } => {
+            let check = match total {
+                Some(total) => format!("{}/{}", check, total),
+                None => check.to_string(),
+            };
+            line("event.sentinel_waiting", &[&(step_index + 1), &check, next_check_secs])
+        }
+        OrchestratorEvent::Cancelled { step_index, reason } => line("event.cancelled", &[&(step_index + 1), reason]),
+        OrchestratorEvent::Metrics { metrics } => {
+            let tokens = metrics.orchestrator_usage.total()
+                + metrics.agent_usage.values().map(|usage| usage.total()).sum::<u64>();
+            let seconds = format!("{:.1}", metrics.total_duration_ms as f64 / 1000.0);
+            line("event.metrics", &[&metrics.rounds, &metrics.replans, &tokens, &seconds])
+        }
+    }
+}
+
+/// 打印订阅到的事件直到 orchestrator 被释放（通道关闭）；跟不上时跳过错过的事件
+pub async fn print_events<W: Write>(events: broadcast::Receiver<OrchestratorEvent>, out: W) -> io::Result<W> {
+    EventPrinter::new(out).print_all(events).await
+}
+
+/// --output json：事件按事件日志的格式（凭据已遮盖）每行输出一个 JSON 对象，直到通道关闭
+pub async fn write_ndjson<W: Write>(mut events: broadcast::Receiver<OrchestratorEvent>, mut out: W) -> io::Result<W> {
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                writeln!(out, "{}", encode_event(&event)?)?;
+                out.flush()?;
+            }
+            Err(RecvError::Lagged(skipped)) => tracing::debug!("The terminal skipped {} events", skipped),
+            Err(RecvError::Closed) => return Ok(out),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    use axum::http::header;
+    use axum::response::IntoResponse;
+    use axum::routing::post;
+    use axum::Json;
+    use indicatif::ProgressDrawTarget;
+    use serde_json::{json, Value};
+
+    use crate::clients::{ModelRegistry, ModelRole};
+    use crate::config::AppConfig;
+    use crate::orchestrator::event_log::{render_event, LoggedEvent};
+    use crate::orchestrator::metrics::OrchestratorMetrics;
+    use crate::orchestrator::plan::{Plan, PlanStep, StepKind};
+    use crate::orchestrator::types::RunOptions;
+    use crate::testing::{ledger_json, plan_json, MockAgent, OrchestratorBuilder};
+
+    fn step(title: &str) -> PlanStep {
+        PlanStep {
+            id: "a3f2c1d0".to_string(),
+            title: title.to_string(),
+            details: String::new(),
+            agent_name: "web_surfer".to_string(),
+            expected_outcome: None,
+            kind: StepKind::Standard,
+        }
+    }
+
+    fn text(text: &str) -> OrchestratorEvent {
+        OrchestratorEvent::PlanTextStreamed { text: text.to_string() }
+    }
+
+    #[tokio::test]
+    async fn test_plan_text_is_printed_incrementally() -> io::Result<()> {
+        let (tx, rx) = broadcast::channel(16);
+        for event in [
+            text("{\"steps\": [{\"title\": "),
+            OrchestratorEvent::PlanStepStreamed { step_index: 0, step: step("Search") },
+            text("\"Search\"}]}"),
+            OrchestratorEvent::PlanReady { plan: Plan { task: None, steps: vec![step("Search")] } },
+            OrchestratorEvent::PlanStepStreamed { step_index: 0, step: step("Read") },
+        ] {
+            tx.send(event).unwrap();
+        }
+        drop(tx);
+
+        let out = String::from_utf8(print_events(rx, Vec::new()).await?).unwrap();
+        assert_eq!(
+            out,
+            "{\"steps\": [{\"title\": \"Search\"}]}\n\
+             plan ready with 1 steps\n  1. Search [web_surfer]\n\
+             planning step 1: Read [web_surfer]\n"
+        );
+        Ok(())
+    }
+
+    fn started(step_index: usize) -> OrchestratorEvent {
+        OrchestratorEvent::StepStarted {
+            step_index,
+            agent_name: "web_surfer".to_string(),
+            instruction: "Open the menu page".to_string(),
+        }
+    }
+
+    fn action(action: &str) -> OrchestratorEvent {
+        OrchestratorEvent::AgentEvent {
+            agent_name: "web_surfer".to_string(),
+            step_index: 0,
+            event: AgentEvent::ActionProposed { action: action.to_string(), explanation: "Open the menu".to_string() },
+        }
+    }
+
+    #[test]
+    fn test_quiet_prints_one_line_per_step() -> io::Result<()> {
+        let mut printer = EventPrinter::new(Vec::new()).quiet(true);
+        for event in [
+            OrchestratorEvent::PlanReady { plan: Plan { task: None, steps: vec![step("Search"), step("Read")] } },
+            started(0),
+            action("click"),
+            OrchestratorEvent::ScreenshotSaved { agent_name: "web_surfer".to_string(), step_index: 0, path: "a.png".to_string() },
+            started(1),
+        ] {
+            printer.print(&event)?;
+        }
+        let out = String::from_utf8(printer.into_inner()).unwrap();
+        assert_eq!(out, "plan ready with 2 steps\nstep 1 -> web_surfer\nstep 2 -> web_surfer\n");
+        Ok(())
+    }
+
+    fn sample_events() -> Vec<OrchestratorEvent> {
+        vec![
+            OrchestratorEvent::PlanStepStreamed { step_index: 0, step: step("Search") },
+            OrchestratorEvent::PlanReady { plan: Plan { task: None, steps: vec![step("Search"), step("Read")] } },
+            OrchestratorEvent::PlanAutoApproved { policy: "cheap".to_string(), reason: "under $0.10".to_string() },
+            OrchestratorEvent::SessionResumed { step_index: 1, remaining_steps: 2 },
+            OrchestratorEvent::StepApprovalRequested { step_index: 0, title: "Search".to_string(), instruction: String::new() },
+            OrchestratorEvent::StepApproved { step_index: 0, edited: true },
+            OrchestratorEvent::StepRejected { step_index: 0, reason: Some("wrong site".to_string()) },
+            OrchestratorEvent::StepRejected { step_index: 0, reason: None },
+            started(0),
+            OrchestratorEvent::LedgerEvaluated { step_index: 0, step_complete: false, need_to_replan: true, next_speaker: "web_surfer".to_string() },
+            OrchestratorEvent::AgentEvent {
+                agent_name: "web_surfer".to_string(),
+                step_index: 0,
+                event: AgentEvent::Thought { text: "The menu is linked".to_string() },
+            },
+            action("click"),
+            OrchestratorEvent::AgentEvent {
+                agent_name: "web_surfer".to_string(),
+                step_index: 0,
+                event: AgentEvent::ActionResult { action: "click".to_string(), result: "Clicked".to_string() },
+            },
+            OrchestratorEvent::ScreenshotSaved { agent_name: "web_surfer".to_string(), step_index: 0, path: "a.png".to_string() },
+            OrchestratorEvent::AgentNameReassigned { step_index: 0, requested: "surfer".to_string(), assigned: "web_surfer".to_string() },
+            OrchestratorEvent::AgentNotRegistered { step_index: 0, agent_name: "surfer".to_string() },
+            OrchestratorEvent::StallDetected { step_index: 0, rounds: 3 },
+            OrchestratorEvent::SentinelWaiting { step_index: 0, check: 2, total: Some(5), next_check_secs: 60 },
+            OrchestratorEvent::Cancelled { step_index: 0, reason: "stopped by the user".to_string() },
+            OrchestratorEvent::Metrics { metrics: OrchestratorMetrics::new() },
+        ]
+    }
+
+    #[test]
+    fn test_event_lines_follow_the_locale() {
+        // 英文与回放使用的 render_event 一致
+        for event in sample_events() {
+            assert_eq!(render_localized(&event, Locale::En), render_event(&LoggedEvent::Known(event.clone())), "{:?}", event);
+        }
+        assert_eq!(
+            render_localized(&sample_events()[1], Locale::Zh),
+            ["计划已生成，共 2 步", "  1. Search [web_surfer]", "  2. Read [web_surfer]"]
+        );
+        assert_eq!(render_localized(&started(0), Locale::Zh), ["第 1 步 -> web_surfer：Open the menu page"]);
+        assert_eq!(
+            render_localized(&sample_events()[9], Locale::Zh),
+            ["第 1 步需要重新规划，下一个执行者 web_surfer"]
+        );
+        assert_eq!(quiet_line(&started(1), Locale::Zh).as_deref(), Some("第 2 步 -> web_surfer"));
+    }
+
+    #[test]
+    fn test_events_wait_for_the_prompt() -> io::Result<()> {
+        let activity = PromptActivity::default();
+        let mut printer = EventPrinter::new(Vec::new()).pause_during(activity.clone());
+        activity.set(true);
+        printer.print(&action("click"))?;
+        printer.print(&action("scroll"))?;
+        assert!(printer.out.is_empty());
+
+        // 回答之后按原来的顺序输出
+        activity.set(false);
+        printer.print(&action("type"))?;
+        let out = String::from_utf8(printer.into_inner()).unwrap();
+        let actions: Vec<_> = out.lines().map(|line| line.split_whitespace().nth(2).unwrap()).collect();
+        assert_eq!(actions, ["click", "scroll", "type"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sentinel_step_shows_a_countdown() -> io::Result<()> {
+        colored::control::set_override(false);
+        let progress = StepProgress::with_target(ProgressDrawTarget::hidden());
+        let mut printer = EventPrinter::new(Vec::new()).timed(progress);
+        printer.print(&started(0))?;
+        printer.print(&OrchestratorEvent::SentinelWaiting { step_index: 0, check: 1, total: Some(3), next_check_secs: 60 })?;
+        let status = printer.progress.as_ref().and_then(StepProgress::message).unwrap();
+        assert!(status.starts_with("step 1 [web_surfer] check 1/3, next in "), "{}", status);
+
+        // 事件行照常输出，步骤完成后 spinner 换成用时行
+        printer.print(&OrchestratorEvent::LedgerEvaluated {
+            step_index: 0,
+            step_complete: true,
+            need_to_replan: false,
+            next_speaker: "web_surfer".to_string(),
+        })?;
+        assert_eq!(printer.progress.as_ref().and_then(StepProgress::message), None);
+        let out = String::from_utf8(printer.into_inner()).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[1], "step 1 check 1/3 done, next check in 60s");
+        assert_eq!(lines[2], "step 1 complete, next speaker web_surfer");
+        assert!(lines[3].starts_with("step 1 [web_surfer] done in "), "{}", out);
+        Ok(())
+    }
+
+    /* 兼容 OpenAI 的模拟接口：流式请求把 streamed 分成两段按 SSE 返回，
+    非流式请求依次返回 replies */
+    async fn model_api(streamed: String, replies: Vec<String>) -> String {
+        let replies = Arc::new(Mutex::new(VecDeque::from(replies)));
+        let app = axum::Router::new().route(
+            "/chat/completions",
+            post(move |Json(body): Json<Value>| {
+                let streamed = streamed.clone();
+                let replies = replies.clone();
+                async move {
+                    if body["stream"] == true {
+                        let (head, tail) = streamed.split_at(streamed.len() / 2);
+                        let mut sse: String = [head, tail]
+                            .iter()
+                            .map(|text| format!("data: {}\n\n", json!({ "choices": [{ "index": 0, "delta": { "content": text } }] })))
+                            .collect();
+                        sse.push_str("data: {\"choices\": [{\"index\": 0, \"delta\": {}, \"finish_reason\": \"stop\"}]}\n\ndata: [DONE]\n\n");
+                        ([(header::CONTENT_TYPE, "text/event-stream")], sse).into_response()
+                    } else {
+                        let content = replies.lock().unwrap().pop_front().unwrap_or_default();
+                        Json(json!({
+                            "choices": [{ "message": { "role": "assistant", "content": content }, "finish_reason": "stop" }],
+                            "usage": { "prompt_tokens": 3, "completion_tokens": 1 }
+                        }))
+                        .into_response()
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        base_url
+    }
+
+    #[tokio::test]
+    async fn test_streamed_plan_reaches_the_terminal() -> anyhow::Result<()> {
+        let plan = plan_json("Find the menu", &[("Search", "Search for the restaurant", "web_surfer")]).to_string();
+        let base_url = model_api(
+            plan.clone(),
+            vec![
+                ledger_json(false, false, "web_surfer", "Search for the restaurant").to_string(),
+                "The menu has pizza.".to_string(),
+            ],
+        )
+        .await;
+        let mut config = AppConfig::default();
+        config.llm.base_url = Some(base_url);
+        // 与实际运行相同的客户端：RetryingClient 包装的 HttpChatClient，规划时走 SSE 流式接口
+        let client = ModelRegistry::from_config(&config).client(ModelRole::Orchestrator);
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(client)
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .configure(|c| {
+                c.max_turns = Some(0);
+                c.stream_plan_text = true;
+            })
+            .build()
+            .await?;
+        let events = orchestrator.subscribe_events();
+        orchestrator.run_task("Find the menu".to_string(), RunOptions::default()).await?;
+        drop(orchestrator);
+
+        let out = String::from_utf8(print_events(events, Vec::new()).await?).unwrap();
+        // 模型输出的两段文字接着输出在同一行，之后才是计划
+        assert!(out.starts_with(&format!("{}\n", plan)), "{}", out);
+        assert!(out.contains("plan ready with 1 steps\n  1. Search [web_surfer]\n"));
+        Ok(())
+    }
+}
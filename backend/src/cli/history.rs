@@ -0,0 +1,249 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use crate::cli::terminal::TaskSource;
+use crate::config::CliSettings;
+
+lazy_static! {
+    // API key、token 等不应该写进历史文件的内容
+    static ref SECRET_PATTERNS: Vec<Regex> = vec![
+        Regex::new(r"\bsk-[A-Za-z0-9_\-]{16,}").unwrap(),
+        Regex::new(r"(?i)\b(api[_-]?key|token|secret|password|passwd)\s*[:=]\s*\S+").unwrap(),
+        Regex::new(r"\b[0-9a-fA-F]{32,}\b").unwrap(),
+        Regex::new(r"\b[A-Za-z0-9_\-]{40,}\b").unwrap(),
+    ];
+}
+
+/// 输入中疑似包含密钥
+pub fn looks_secret(line: &str) -> bool {
+    SECRET_PATTERNS.iter().any(|pattern| pattern.is_match(line))
+}
+
+/* 输入过的任务，按文件保存，每行一条；多行任务中的换行和反斜杠转义后保存。
+连续重复的输入只记一次，疑似密钥的输入不记录，超过 max_entries 时丢弃最旧的 */
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskHistory {
+    path: Option<PathBuf>,
+    max_entries: usize,
+    entries: Vec<String>,
+}
+
+impl TaskHistory {
+    /// 从文件读取，文件不存在时为空；path 为 None 或 max_entries 为 0 时只在内存中记录
+    pub fn load(path: Option<PathBuf>, max_entries: usize) -> Result<Self> {
+        let path = path.filter(|_| max_entries > 0);
+        let mut entries = Vec::new();
+        if let Some(path) = path.as_deref().filter(|path| path.exists()) {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read the history file {}", path.display()))?;
+            entries = content.lines().filter(|line| !line.is_empty()).map(unescape).collect();
+        }
+        let mut history = Self { path, max_entries, entries };
+        history.truncate();
+        Ok(history)
+    }
+
+    pub fn from_settings(settings: &CliSettings) -> Result<Self> {
+        Self::load(settings.history_path(), settings.history_size)
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// 记录一条输入，返回是否记录了
+    pub fn add(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        if line.is_empty() || looks_secret(line) || self.entries.last().map(String::as_str) == Some(line) {
+            return false;
+        }
+        self.entries.push(line.to_string());
+        self.truncate();
+        true
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let content: String = self.entries.iter().map(|entry| format!("{}\n", escape(entry))).collect();
+        std::fs::write(path, content).with_context(|| format!("Failed to write the history file {}", path.display()))
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn truncate(&mut self) {
+        let excess = self.entries.len().saturating_sub(self.max_entries.max(1));
+        self.entries.drain(..excess);
+    }
+}
+
+fn escape(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            entry.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => entry.push('\n'),
+            Some(other) => entry.push(other),
+            None => entry.push('\\'),
+        }
+    }
+    entry
+}
+
+// 以 ``` 开头的行
+fn is_fence(line: &str) -> bool {
+    line.trim_start().starts_with("```")
+}
+
+/// 已经输入的行是否还需要继续：最后一行以反斜杠结尾，或者 ``` 围起的内容还没有结束
+pub fn needs_more_lines(lines: &[String]) -> bool {
+    let open_fence = lines.iter().filter(|line| is_fence(line)).count() % 2 == 1;
+    open_fence || lines.last().is_some_and(|line| line.ends_with('\\'))
+}
+
+/// 把多行输入合成一个任务：去掉续行的反斜杠和 ``` 行
+pub fn join_lines(lines: &[String]) -> String {
+    lines
+        .iter()
+        .filter(|line| !is_fence(line))
+        .map(|line| line.strip_suffix('\\').unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/* 用 rustyline 读取任务：方向键翻看和 Ctrl+R 搜索历史，行尾的反斜杠或者 ``` 开始多行输入。
+输入的任务记入 TaskHistory 并立即写回文件，Ctrl+C / Ctrl+D 结束 */
+pub struct LineEditor {
+    editor: DefaultEditor,
+    history: TaskHistory,
+}
+
+impl LineEditor {
+    pub fn new(history: TaskHistory) -> Result<Self> {
+        let mut editor = DefaultEditor::new()?;
+        for entry in history.entries() {
+            editor.add_history_entry(entry.as_str())?;
+        }
+        Ok(Self { editor, history })
+    }
+}
+
+impl TaskSource for LineEditor {
+    fn next_task(&mut self, prompt: &str) -> Result<Option<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let prompt = if lines.is_empty() { format!("{} ", prompt) } else { "... ".to_string() };
+            match self.editor.readline(&prompt) {
+                Ok(line) => lines.push(line),
+                Err(ReadlineError::Interrupted | ReadlineError::Eof) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+            if !needs_more_lines(&lines) {
+                break;
+            }
+        }
+        let task = join_lines(&lines);
+        if self.history.add(&task) {
+            self.editor.add_history_entry(task.as_str())?;
+            if let Err(e) = self.history.save() {
+                tracing::warn!("{:#}", e);
+            }
+        }
+        Ok(Some(task))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_history_round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("magentic").join("history");
+        let mut history = TaskHistory::load(Some(path.clone()), 3)?;
+        assert!(history.entries().is_empty());
+        for task in ["Find the menu", "Summarize\nthe page", "Order a \\latte", "Book a table"] {
+            assert!(history.add(task));
+        }
+        history.save()?;
+
+        // 超过 3 条时丢弃最旧的，多行和反斜杠原样读回
+        let loaded = TaskHistory::load(Some(path.clone()), 3)?;
+        assert_eq!(loaded.entries(), ["Summarize\nthe page", "Order a \\latte", "Book a table"]);
+        assert_eq!(TaskHistory::load(Some(path.clone()), 2)?.entries(), ["Order a \\latte", "Book a table"]);
+
+        // 保存的条数为 0 时不读也不写文件
+        let disabled = TaskHistory::load(Some(path), 0)?;
+        assert!(disabled.entries().is_empty() && disabled.path().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_consecutive_repeats_are_recorded_once() -> Result<()> {
+        let mut history = TaskHistory::load(None, 10)?;
+        assert!(history.add("Find the menu"));
+        assert!(!history.add("  Find the menu "));
+        assert!(!history.add(""));
+        assert!(history.add("Book a table"));
+        assert!(history.add("Find the menu"));
+        assert_eq!(history.entries(), ["Find the menu", "Book a table", "Find the menu"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_secrets_are_not_recorded() -> Result<()> {
+        let mut history = TaskHistory::load(None, 10)?;
+        for secret in [
+            "use the key sk-proj-abcdefghijklmnop1234",
+            "log in with password: hunter2",
+            "set API_KEY=abc123",
+            "the token is 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08",
+        ] {
+            assert!(looks_secret(secret), "{}", secret);
+            assert!(!history.add(secret));
+        }
+        assert!(history.add("check the weather in https://example.com/forecast"));
+        assert_eq!(history.entries().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_line_input() {
+        assert!(!needs_more_lines(&lines(&["Find the menu"])));
+        assert!(needs_more_lines(&lines(&["Find the menu \\"])));
+        assert_eq!(join_lines(&lines(&["Find the menu \\", "and order a latte"])), "Find the menu \nand order a latte");
+
+        let fenced = lines(&["```", "Summarize:", "", "The first paragraph."]);
+        assert!(needs_more_lines(&fenced));
+        let mut closed = fenced.clone();
+        closed.push("```".to_string());
+        assert!(!needs_more_lines(&closed));
+        assert_eq!(join_lines(&closed), "Summarize:\n\nThe first paragraph.");
+    }
+}
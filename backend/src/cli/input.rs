@@ -0,0 +1,43 @@
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt};
+
+use crate::orchestrator::message::{ChatMessage, MessageRole};
+use crate::orchestrator::types::UserMessageQueue;
+
+/* 运行期间在终端中输入的每一行都交给 orchestrator 的消息队列（Orchestrator::user_message_queue），
+下一次 ledger 评估时出现；停止指令由队列直接取消当前运行。空行忽略，输入结束（EOF）时返回 */
+pub async fn forward_user_input<R: AsyncBufRead + Unpin>(input: R, queue: UserMessageQueue) -> io::Result<()> {
+    let mut lines = input.lines();
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if queue.push(ChatMessage::new_text(MessageRole::User, "user".to_string(), line.to_string())) {
+            tracing::info!("Stop requested from the terminal");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_terminal_lines_reach_the_queue() -> io::Result<()> {
+        let queue = UserMessageQueue::new();
+        forward_user_input("search for buses instead\n\n  also check prices  \nstop\n".as_bytes(), queue.clone()).await?;
+
+        let contents: Vec<String> = queue
+            .drain()
+            .into_iter()
+            .map(|message| match message {
+                ChatMessage::Text { content, .. } => content,
+                ChatMessage::MultiModal { .. } => panic!("expected a text message"),
+            })
+            .collect();
+        assert_eq!(contents, ["search for buses instead", "also check prices"]);
+        assert!(queue.is_cancelled());
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::broadcast;
+
+use crate::orchestrator::types::UserMessageQueue;
+
+/// 两次 Ctrl+C 的间隔不超过这个时间时强制退出
+pub const FORCE_EXIT_WINDOW: Duration = Duration::from_secs(3);
+
+// 强制退出时的退出码，与被 SIGINT 结束的进程相同
+const FORCE_EXIT_CODE: i32 = 130;
+
+/* 终端中的 Ctrl+C。第一次按下时通知正在进行的运行取消（UserMessageQueue::cancel，
+与输入 stop 的效果相同：代理中止当前的工具调用，orchestrator 输出部分进度的总结，浏览器归还后关闭）；
+FORCE_EXIT_WINDOW 内再按一次时调用 force_exit 直接退出。
+dialoguer 提问时终端处于 raw 模式，Ctrl+C 不产生信号，由 CliActionGuard 在提问返回后调用 trigger */
+#[derive(Clone)]
+pub struct Interrupts {
+    tx: broadcast::Sender<()>,
+    last: Arc<Mutex<Option<Instant>>>,
+    count: Arc<AtomicUsize>,
+    force_exit: Arc<dyn Fn() + Send + Sync>,
+}
+
+impl std::fmt::Debug for Interrupts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Interrupts").field("count", &self.count()).finish()
+    }
+}
+
+impl Interrupts {
+    pub fn new(force_exit: Arc<dyn Fn() + Send + Sync>) -> Self {
+        let (tx, _) = broadcast::channel(4);
+        Self { tx, last: Arc::new(Mutex::new(None)), count: Arc::new(AtomicUsize::new(0)), force_exit }
+    }
+
+    /// 监听 SIGINT；安装之后 Ctrl+C 不再直接结束进程
+    pub fn install() -> Self {
+        let interrupts = Self::new(Arc::new(|| std::process::exit(FORCE_EXIT_CODE)));
+        let handler = interrupts.clone();
+        tokio::spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                handler.trigger();
+            }
+        });
+        interrupts
+    }
+
+    /// 记录一次 Ctrl+C
+    pub fn trigger(&self) {
+        let now = Instant::now();
+        let previous = self.last.lock().unwrap().replace(now);
+        self.count.fetch_add(1, Ordering::SeqCst);
+        if previous.is_some_and(|previous| now.duration_since(previous) <= FORCE_EXIT_WINDOW) {
+            (self.force_exit)();
+            return;
+        }
+        let _ = self.tx.send(());
+    }
+
+    /// 到目前为止按下的次数，提问前后比较可以知道提问期间是否按过
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// 下一次 Ctrl+C 时取消 queue 所属的运行，运行结束后由调用方中止
+    pub fn cancel_on_interrupt(&self, queue: UserMessageQueue) -> tokio::task::JoinHandle<()> {
+        let mut rx = self.tx.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(()) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                        tracing::info!("Interrupted, stopping the run");
+                        queue.cancel();
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_second_interrupt_forces_exit() {
+        let exits = Arc::new(AtomicUsize::new(0));
+        let counted = exits.clone();
+        let interrupts = Interrupts::new(Arc::new(move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+        }));
+        let queue = UserMessageQueue::default();
+        let watcher = interrupts.cancel_on_interrupt(queue.clone());
+
+        interrupts.trigger();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !queue.is_cancelled() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("the run is cancelled");
+        assert_eq!(exits.load(Ordering::SeqCst), 0);
+
+        interrupts.trigger();
+        assert_eq!(exits.load(Ordering::SeqCst), 1);
+        assert_eq!(interrupts.count(), 2);
+        watcher.abort();
+    }
+}
@@ -0,0 +1,22 @@
+// 终端前端：在命令行中向用户展示审批请求和运行过程，并把运行中输入的消息交给 orchestrator
+pub mod action_guard;
+pub mod args;
+pub mod conversation;
+pub mod events;
+pub mod history;
+pub mod input;
+pub mod interrupt;
+pub mod progress;
+pub mod strings;
+pub mod terminal;
+
+pub use action_guard::{CliActionGuard, ConfirmPrompt, DialoguerPrompt};
+pub use args::{Cli, Command, GlobalArgs, OutputFormat, RunArgs};
+pub use conversation::Conversation;
+pub use events::{print_events, write_ndjson, EventPrinter};
+pub use history::{LineEditor, TaskHistory};
+pub use input::forward_user_input;
+pub use interrupt::Interrupts;
+pub use progress::{StepProgress, StepTimer};
+pub use strings::Locale;
+pub use terminal::{describe_models, error_json, outcome_json, TaskSource, TerminalRunner};
@@ -0,0 +1,285 @@
+use std::time::{Duration, Instant};
+
+use colored::Colorize;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::cli::strings::Locale;
+use crate::orchestrator::events::OrchestratorEvent;
+
+// spinner 转动和倒计时刷新的间隔
+pub const REFRESH_INTERVAL: Duration = Duration::from_millis(200);
+
+// 哨兵步骤两次检查之间的等待
+struct SentinelWait {
+    check: u32,
+    total: Option<u32>,
+    next_check: Instant,
+}
+
+struct RunningStep {
+    step_index: usize,
+    agent_name: String,
+    started: Instant,
+    waiting: Option<SentinelWait>,
+}
+
+/* 步骤计时：StepStarted 开始计时，同一步骤再次分发时继续计时；步骤完成时输出绿色的用时，
+重规划、停滞或取消时输出红色的用时。哨兵步骤在两次检查之间（SentinelWaiting）显示检查进度和倒计时。
+时间由调用方传入，测试中不依赖真实时钟 */
+#[derive(Default)]
+pub struct StepTimer {
+    running: Option<RunningStep>,
+    locale: Locale,
+}
+
+impl StepTimer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    /// 根据事件更新计时，步骤结束时返回要输出的一行
+    pub fn observe(&mut self, event: &OrchestratorEvent, now: Instant) -> Option<String> {
+        match event {
+            OrchestratorEvent::StepStarted { step_index, agent_name, .. } => {
+                match &mut self.running {
+                    // 同一步骤再次分发（包括哨兵步骤的下一次检查）
+                    Some(running) if running.step_index == *step_index => running.waiting = None,
+                    _ => {
+                        self.running = Some(RunningStep {
+                            step_index: *step_index,
+                            agent_name: agent_name.clone(),
+                            started: now,
+                            waiting: None,
+                        })
+                    }
+                }
+                None
+            }
+            OrchestratorEvent::SentinelWaiting { step_index, check, total, next_check_secs } => {
+                if let Some(running) = self.running.as_mut().filter(|running| running.step_index == *step_index) {
+                    running.waiting = Some(SentinelWait {
+                        check: *check,
+                        total: *total,
+                        next_check: now + Duration::from_secs(*next_check_secs),
+                    });
+                }
+                None
+            }
+            OrchestratorEvent::LedgerEvaluated { need_to_replan: true, .. }
+            | OrchestratorEvent::StallDetected { .. }
+            | OrchestratorEvent::Cancelled { .. } => self.finish(now, false),
+            OrchestratorEvent::LedgerEvaluated { step_index, step_complete: true, .. }
+                if self.running.as_ref().is_some_and(|running| running.step_index == *step_index) =>
+            {
+                self.finish(now, true)
+            }
+            _ => None,
+        }
+    }
+
+    /// 运行中步骤的状态：用时，哨兵步骤等待时是检查进度和下一次检查的倒计时
+    pub fn status(&self, now: Instant) -> Option<String> {
+        let running = self.running.as_ref()?;
+        let detail = match &running.waiting {
+            Some(wait) => {
+                let check = match wait.total {
+                    Some(total) => format!("{}/{}", wait.check, total),
+                    None => wait.check.to_string(),
+                };
+                // 向上取整，倒计时到 0 时正好开始下一次检查
+                let left = wait.next_check.saturating_duration_since(now).as_millis().div_ceil(1000);
+                self.locale.format("progress.check", &[&check, &left])
+            }
+            None => seconds(now.duration_since(running.started)),
+        };
+        Some(self.locale.format("progress.running", &[&(running.step_index + 1), &running.agent_name, &detail]))
+    }
+
+    fn finish(&mut self, now: Instant, succeeded: bool) -> Option<String> {
+        let running = self.running.take()?;
+        let elapsed = seconds(now.duration_since(running.started));
+        let key = if succeeded { "progress.done" } else { "progress.failed" };
+        let line = self.locale.format(key, &[&(running.step_index + 1), &running.agent_name, &elapsed]);
+        let line = if succeeded { line.green() } else { line.red() };
+        Some(line.to_string())
+    }
+}
+
+/* 终端中的步骤进度：运行中的步骤显示为 indicatif 的 spinner，文字是 StepTimer::status；
+步骤结束时 spinner 收起，换成绿色或红色的用时行。事件行通过 suspend 输出在 spinner 上方。
+只在终端中使用，输出重定向、--json 或 quiet 时不创建 */
+pub struct StepProgress {
+    timer: StepTimer,
+    bars: MultiProgress,
+    spinner: Option<ProgressBar>,
+}
+
+impl StepProgress {
+    pub fn new() -> Self {
+        Self::with_target(ProgressDrawTarget::stderr())
+    }
+
+    pub fn with_target(target: ProgressDrawTarget) -> Self {
+        Self {
+            timer: StepTimer::new(),
+            bars: MultiProgress::with_draw_target(target),
+            spinner: None,
+        }
+    }
+
+    /// 步骤状态和用时行的语言
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.timer.locale = locale;
+        self
+    }
+
+    /// 根据事件更新 spinner，步骤结束时返回要输出的一行
+    pub fn observe(&mut self, event: &OrchestratorEvent, now: Instant) -> Option<String> {
+        let line = self.timer.observe(event, now);
+        if line.is_some() {
+            self.hide();
+        }
+        self.refresh(now);
+        line
+    }
+
+    /// 更新 spinner 的文字并转动一格，没有运行中的步骤时收起
+    pub fn refresh(&mut self, now: Instant) {
+        let Some(status) = self.timer.status(now) else {
+            self.hide();
+            return;
+        };
+        let bars = &self.bars;
+        let spinner = self.spinner.get_or_insert_with(|| {
+            let spinner = bars.add(ProgressBar::new_spinner());
+            spinner.set_style(ProgressStyle::default_spinner());
+            spinner
+        });
+        spinner.set_message(status);
+        spinner.tick();
+    }
+
+    /// 收起 spinner，终端提问期间也调用，之后的 refresh 重新显示
+    pub fn hide(&mut self) {
+        if let Some(spinner) = self.spinner.take() {
+            spinner.finish_and_clear();
+            self.bars.remove(&spinner);
+        }
+    }
+
+    /// 先收起 spinner 再输出，输出的行出现在 spinner 上方
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.bars.suspend(f)
+    }
+
+    /// spinner 当前的文字，没有显示时为 None
+    pub fn message(&self) -> Option<String> {
+        self.spinner.as_ref().map(|spinner| spinner.message())
+    }
+}
+
+impl Default for StepProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn started(step_index: usize) -> OrchestratorEvent {
+        OrchestratorEvent::StepStarted {
+            step_index,
+            agent_name: "web_surfer".to_string(),
+            instruction: "Open the menu page".to_string(),
+        }
+    }
+
+    fn evaluated(step_index: usize, step_complete: bool, need_to_replan: bool) -> OrchestratorEvent {
+        OrchestratorEvent::LedgerEvaluated {
+            step_index,
+            step_complete,
+            need_to_replan,
+            next_speaker: "web_surfer".to_string(),
+        }
+    }
+
+    fn waiting(check: u32, total: Option<u32>) -> OrchestratorEvent {
+        OrchestratorEvent::SentinelWaiting { step_index: 0, check, total, next_check_secs: 30 }
+    }
+
+    #[test]
+    fn test_steps_are_timed() {
+        colored::control::set_override(false);
+        let start = Instant::now();
+        let at = |secs: f64| start + Duration::from_secs_f64(secs);
+        let mut timer = StepTimer::new();
+
+        assert_eq!(timer.status(at(0.0)), None);
+        assert_eq!(timer.observe(&started(0), at(0.0)), None);
+        assert_eq!(timer.status(at(12.0)).as_deref(), Some("step 1 [web_surfer] 12.0s"));
+        // 同一步骤再次分发时继续计时
+        assert_eq!(timer.observe(&evaluated(0, false, false), at(16.0)), None);
+        assert_eq!(timer.observe(&started(0), at(17.0)), None);
+        assert_eq!(timer.observe(&evaluated(0, true, false), at(18.5)).as_deref(), Some("step 1 [web_surfer] done in 18.5s"));
+        assert_eq!(timer.status(at(60.0)), None);
+
+        timer.observe(&started(1), at(20.0));
+        assert_eq!(timer.observe(&evaluated(1, false, true), at(23.0)).as_deref(), Some("step 2 [web_surfer] failed after 3.0s"));
+        // 没有运行中的步骤时不输出
+        assert_eq!(timer.observe(&evaluated(1, true, false), at(24.0)), None);
+    }
+
+    #[test]
+    fn test_sentinel_checks_count_down() {
+        let start = Instant::now();
+        let at = |secs: f64| start + Duration::from_secs_f64(secs);
+        let mut timer = StepTimer::new();
+
+        timer.observe(&started(0), at(0.0));
+        timer.observe(&waiting(3, Some(5)), at(2.0));
+        assert_eq!(timer.status(at(5.0)).as_deref(), Some("step 1 [web_surfer] check 3/5, next in 27s"));
+        assert_eq!(timer.status(at(40.0)).as_deref(), Some("step 1 [web_surfer] check 3/5, next in 0s"));
+        // 下一次检查开始后恢复显示用时；按文字条件结束时没有总次数
+        timer.observe(&started(0), at(32.0));
+        assert_eq!(timer.status(at(33.0)).as_deref(), Some("step 1 [web_surfer] 33.0s"));
+        timer.observe(&waiting(4, None), at(34.0));
+        assert_eq!(timer.status(at(34.5)).as_deref(), Some("step 1 [web_surfer] check 4, next in 30s"));
+
+        let mut timer = StepTimer::new().locale(Locale::Zh);
+        timer.observe(&started(0), at(0.0));
+        timer.observe(&waiting(3, Some(5)), at(2.0));
+        assert_eq!(timer.status(at(5.0)).as_deref(), Some("第 1 步 [web_surfer] 第 3/5 次检查，27 秒后再次检查"));
+    }
+
+    #[test]
+    fn test_spinner_follows_the_running_step() {
+        colored::control::set_override(false);
+        let start = Instant::now();
+        let at = |secs: f64| start + Duration::from_secs_f64(secs);
+        let mut progress = StepProgress::with_target(ProgressDrawTarget::hidden());
+
+        assert_eq!(progress.observe(&started(0), at(0.0)), None);
+        assert_eq!(progress.message().as_deref(), Some("step 1 [web_surfer] 0.0s"));
+        progress.observe(&waiting(1, Some(2)), at(1.0));
+        progress.refresh(at(11.0));
+        assert_eq!(progress.message().as_deref(), Some("step 1 [web_surfer] check 1/2, next in 20s"));
+
+        // 步骤结束时 spinner 收起，换成用时行
+        let line = progress.observe(&evaluated(0, true, false), at(40.0));
+        assert_eq!(line.as_deref(), Some("step 1 [web_surfer] done in 40.0s"));
+        assert_eq!(progress.message(), None);
+        progress.refresh(at(41.0));
+        assert_eq!(progress.message(), None);
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt::Display;
+
+use anyhow::{bail, Result};
+
+/* 终端界面的语言：提问、菜单、错误前缀和运行事件按 Locale 从下面的表中取，表中没有的键用英文，
+英文中也没有时直接显示键。计划和答案的语言由模型决定，不在这里 */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    En,
+    Zh,
+}
+
+// 依次查看的系统语言环境变量
+const SYSTEM_LOCALE_VARS: [&str; 3] = ["LC_ALL", "LC_MESSAGES", "LANG"];
+
+const EN: &[(&str, &str)] = &[
+    ("task.prompt", "What would you like me to help you with?"),
+    ("follow_up.prompt", "Would you like to do something else?"),
+    ("follow_up.continue", "Continue the previous task"),
+    ("follow_up.new", "Start a new task"),
+    ("attach.added", "Attached {}, it will be sent with the next task"),
+    ("attach.failed", "Could not attach the image: {}"),
+    ("failure.prompt", "What would you like to do?"),
+    ("failure.retry", "Retry"),
+    ("failure.skip", "Skip the step and continue"),
+    ("failure.abort", "Abort"),
+    ("run.failed", "Run failed: {}"),
+    ("run.stopped", "Stopped. Resume with: {}"),
+    ("approval.action", "Approve? (denied after {}s without an answer)"),
+    ("approval.step", "Run this step? (rejected after {}s without an answer)"),
+    ("approval.step.approve", "Approve"),
+    ("approval.step.reject", "Reject"),
+    ("approval.step.edit", "Edit the instruction"),
+    ("approval.instruction", "Instruction for {}"),
+    ("approval.rejection_reason", "Why was the step rejected? (optional)"),
+    ("approval.no_answer", "No answer within {}s"),
+    ("approval.target", "Target: {} (domain {})"),
+    ("approval.plan", "Run this plan? (rejected after {}s without an answer)"),
+    ("approval.plan.approve", "Approve"),
+    ("approval.plan.reject", "Reject"),
+    ("approval.plan.edit", "Edit the plan"),
+    ("approval.plan.regenerate", "Regenerate the plan"),
+    ("approval.plan.regenerate_reason", "What should the new plan do differently? (optional)"),
+    ("approval.plan.changes", "Changes from the previous plan:"),
+    ("clarification.answer", "Your answer (optional, press Enter to let the assistant decide)"),
+    ("plan_editor.prompt", "How would you like to change the plan?"),
+    ("plan_editor.edit", "Edit a step"),
+    ("plan_editor.move", "Move a step"),
+    ("plan_editor.remove", "Remove steps"),
+    ("plan_editor.undo", "Undo the last edit"),
+    ("plan_editor.export", "Export to a file"),
+    ("plan_editor.import", "Import from a file"),
+    ("plan_editor.done", "Done"),
+    ("plan_editor.step", "Which step?"),
+    ("plan_editor.position", "Move it to position"),
+    ("plan_editor.title", "Title"),
+    ("plan_editor.details", "Details"),
+    ("plan_editor.agent", "Agent"),
+    ("plan_editor.remove_prompt", "Steps to remove (space to select)"),
+    ("plan_editor.invalid", "Cannot apply the edit: {}"),
+    ("plan_editor.nothing_to_undo", "Nothing to undo"),
+    ("plan_editor.version", "Plan {}:"),
+    ("plan_editor.export_path", "Write the plan to"),
+    ("plan_editor.exported", "Plan written to {}"),
+    ("plan_editor.import_path", "Plan file to import"),
+    ("plan_editor.import_preview", "Changes from {}:"),
+    ("plan_editor.import_confirm", "Replace the plan with the imported one?"),
+    ("event.planning_step", "planning step {}: {} [{}]"),
+    ("event.plan_ready", "plan ready with {} steps"),
+    ("event.estimate", "estimate: {}"),
+    ("event.estimate_warning", "  warning: {}"),
+    ("event.plan_auto_approved", "plan approved automatically ({}): {}"),
+    ("event.resumed", "resumed at step {}, {} steps remaining"),
+    ("event.step_approval", "step {} waiting for approval: {}"),
+    ("event.step_approved", "step {} approved"),
+    ("event.step_approved_edited", "step {} approved with edits"),
+    ("event.step_rejected", "step {} rejected"),
+    ("event.step_rejected_reason", "step {} rejected: {}"),
+    ("event.step_started", "step {} -> {}: {}"),
+    ("event.step_agent", "step {} -> {}"),
+    ("event.ledger", "step {} {}, next speaker {}"),
+    ("event.ledger.replan", "needs a new plan"),
+    ("event.ledger.complete", "complete"),
+    ("event.ledger.in_progress", "in progress"),
+    ("event.thought", "  [{}] thought: {}"),
+    ("event.action", "  [{}] action: {} ({})"),
+    ("event.action_result", "    [{}] result of {}: {}"),
+    ("event.screenshot", "    [{}] screenshot saved to {}"),
+    ("event.reassigned", "step {} reassigned from {} to {}"),
+    ("event.not_registered", "step {}: agent {} is not registered, instruction not dispatched"),
+    ("event.stalled", "step {} stalled for {} rounds, replanning"),
+    ("event.sentinel_waiting", "step {} check {} done, next check in {}s"),
+    ("event.cancelled", "cancelled at step {}: {}"),
+    ("event.metrics", "finished after {} rounds and {} replans, {} tokens, {}s"),
+    ("progress.running", "step {} [{}] {}"),
+    ("progress.check", "check {}, next in {}s"),
+    ("progress.done", "step {} [{}] done in {}"),
+    ("progress.failed", "step {} [{}] failed after {}"),
+];
+
+const ZH: &[(&str, &str)] = &[
+    ("task.prompt", "需要我帮你做什么？"),
+    ("follow_up.prompt", "还需要做其他事情吗？"),
+    ("follow_up.continue", "继续上一个任务"),
+    ("follow_up.new", "开始新任务"),
+    ("attach.added", "已附加 {}，将随下一个任务一起发送"),
+    ("attach.failed", "无法附加图片：{}"),
+    ("failure.prompt", "接下来怎么做？"),
+    ("failure.retry", "重试"),
+    ("failure.skip", "跳过这个步骤继续"),
+    ("failure.abort", "放弃"),
+    ("run.failed", "运行失败：{}"),
+    ("run.stopped", "已停止。继续运行：{}"),
+    ("approval.action", "批准吗？（{} 秒内没有回答时拒绝）"),
+    ("approval.step", "执行这个步骤吗？（{} 秒内没有回答时拒绝）"),
+    ("approval.step.approve", "批准"),
+    ("approval.step.reject", "拒绝"),
+    ("approval.step.edit", "修改指令"),
+    ("approval.instruction", "{} 的指令"),
+    ("approval.rejection_reason", "拒绝这个步骤的原因（可选）"),
+    ("approval.no_answer", "{} 秒内没有回答"),
+    ("approval.target", "目标：{}（域名 {}）"),
+    ("approval.plan", "执行这个计划吗？（{} 秒内没有回答时拒绝）"),
+    ("approval.plan.approve", "批准"),
+    ("approval.plan.reject", "拒绝"),
+    ("approval.plan.edit", "修改计划"),
+    ("approval.plan.regenerate", "重新生成计划"),
+    ("approval.plan.regenerate_reason", "新计划需要有什么不同？（可选）"),
+    ("approval.plan.changes", "与之前计划相比："),
+    ("clarification.answer", "你的回答（可选，直接回车由助手自行决定）"),
+    ("plan_editor.prompt", "怎样修改计划？"),
+    ("plan_editor.edit", "修改步骤"),
+    ("plan_editor.move", "移动步骤"),
+    ("plan_editor.remove", "批量删除"),
+    ("plan_editor.undo", "撤销上一次修改"),
+    ("plan_editor.export", "导出到文件"),
+    ("plan_editor.import", "从文件导入"),
+    ("plan_editor.done", "完成"),
+    ("plan_editor.step", "哪个步骤？"),
+    ("plan_editor.position", "移动到第几步"),
+    ("plan_editor.title", "标题"),
+    ("plan_editor.details", "详细说明"),
+    ("plan_editor.agent", "执行的代理"),
+    ("plan_editor.remove_prompt", "要删除的步骤（空格选择）"),
+    ("plan_editor.invalid", "无法修改：{}"),
+    ("plan_editor.nothing_to_undo", "没有可以撤销的修改"),
+    ("plan_editor.version", "计划 {}："),
+    ("plan_editor.export_path", "计划写到"),
+    ("plan_editor.exported", "计划已写到 {}"),
+    ("plan_editor.import_path", "要导入的计划文件"),
+    ("plan_editor.import_preview", "{} 带来的修改："),
+    ("plan_editor.import_confirm", "用导入的计划替换当前计划吗？"),
+    ("event.planning_step", "正在规划第 {} 步：{} [{}]"),
+    ("event.plan_ready", "计划已生成，共 {} 步"),
+    ("event.estimate", "估算：{}"),
+    ("event.estimate_warning", "  警告：{}"),
+    ("event.plan_auto_approved", "计划已自动批准（{}）：{}"),
+    ("event.resumed", "从第 {} 步继续，还剩 {} 步"),
+    ("event.step_approval", "第 {} 步等待批准：{}"),
+    ("event.step_approved", "第 {} 步已批准"),
+    ("event.step_approved_edited", "第 {} 步修改后已批准"),
+    ("event.step_rejected", "第 {} 步被拒绝"),
+    ("event.step_rejected_reason", "第 {} 步被拒绝：{}"),
+    ("event.step_started", "第 {} 步 -> {}：{}"),
+    ("event.step_agent", "第 {} 步 -> {}"),
+    ("event.ledger", "第 {} 步{}，下一个执行者 {}"),
+    ("event.ledger.replan", "需要重新规划"),
+    ("event.ledger.complete", "已完成"),
+    ("event.ledger.in_progress", "进行中"),
+    ("event.thought", "  [{}] 想法：{}"),
+    ("event.action", "  [{}] 动作：{}（{}）"),
+    ("event.action_result", "    [{}] {} 的结果：{}"),
+    ("event.screenshot", "    [{}] 截图已保存到 {}"),
+    ("event.reassigned", "第 {} 步从 {} 改派给 {}"),
+    ("event.not_registered", "第 {} 步：代理 {} 没有注册，指令没有分发"),
+    ("event.stalled", "第 {} 步连续 {} 轮没有进展，重新规划"),
+    ("event.sentinel_waiting", "第 {} 步的第 {} 次检查完成，{} 秒后再次检查"),
+    ("event.cancelled", "在第 {} 步取消：{}"),
+    ("event.metrics", "运行结束：{} 轮，重规划 {} 次，{} 个 token，用时 {} 秒"),
+    ("progress.running", "第 {} 步 [{}] {}"),
+    ("progress.check", "第 {} 次检查，{} 秒后再次检查"),
+    ("progress.done", "第 {} 步 [{}] 完成，用时 {}"),
+    ("progress.failed", "第 {} 步 [{}] 失败，用时 {}"),
+];
+
+impl Locale {
+    /// en、zh，也接受 zh_CN.UTF-8、zh-Hans、en_US 这样的系统写法
+    pub fn parse(value: &str) -> Result<Self> {
+        let language = value.trim().split(['_', '-', '.']).next().unwrap_or_default().to_lowercase();
+        match language.as_str() {
+            "en" => Ok(Self::En),
+            "zh" => Ok(Self::Zh),
+            _ => bail!("Unknown language '{}'; available: en, zh", value),
+        }
+    }
+
+    /// --lang 优先，其次 MAGENTIC_LANG，再次系统的 LC_ALL、LC_MESSAGES、LANG；都没有或者无法识别时为英文
+    pub fn detect(flag: Option<Self>, env: &[(String, String)]) -> Self {
+        if let Some(locale) = flag {
+            return locale;
+        }
+        let var = |name: &str| env.iter().find(|(key, value)| key == name && !value.is_empty()).map(|(_, value)| value);
+        if let Some(value) = var("MAGENTIC_LANG") {
+            match Self::parse(value) {
+                Ok(locale) => return locale,
+                Err(e) => tracing::warn!("Ignoring MAGENTIC_LANG: {:#}", e),
+            }
+        }
+        SYSTEM_LOCALE_VARS
+            .iter()
+            .find_map(|name| var(name))
+            .and_then(|value| Self::parse(value).ok())
+            .unwrap_or_default()
+    }
+
+    /// key 对应的文字
+    pub fn text(self, key: &'static str) -> &'static str {
+        let table = match self {
+            Self::En => EN,
+            Self::Zh => ZH,
+        };
+        lookup(table, key)
+    }
+
+    /// 依次替换文字中的 {}
+    pub fn format(self, key: &'static str, args: &[&dyn Display]) -> String {
+        let mut args = args.iter();
+        let mut pieces = self.text(key).split("{}");
+        let mut text = pieces.next().unwrap_or_default().to_string();
+        for piece in pieces {
+            if let Some(arg) = args.next() {
+                text.push_str(&arg.to_string());
+            }
+            text.push_str(piece);
+        }
+        text
+    }
+
+    /// 菜单的选项
+    pub fn choices(self, keys: &[&'static str]) -> Vec<&'static str> {
+        keys.iter().map(|key| self.text(key)).collect()
+    }
+}
+
+fn lookup(table: &[(&str, &'static str)], key: &'static str) -> &'static str {
+    let find = |table: &[(&str, &'static str)]| table.iter().find(|(k, _)| *k == key).map(|(_, text)| *text);
+    find(table).or_else(|| find(EN)).unwrap_or(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_locale_selection() -> Result<()> {
+        assert_eq!(Locale::parse("zh_CN.UTF-8")?, Locale::Zh);
+        assert_eq!(Locale::parse("en-US")?, Locale::En);
+        assert_eq!(Locale::parse("fr").unwrap_err().to_string(), "Unknown language 'fr'; available: en, zh");
+
+        let system = env(&[("LANG", "zh_CN.UTF-8")]);
+        assert_eq!(Locale::detect(None, &system), Locale::Zh);
+        assert_eq!(Locale::detect(Some(Locale::En), &system), Locale::En);
+        assert_eq!(Locale::detect(None, &env(&[("MAGENTIC_LANG", "en"), ("LANG", "zh_CN.UTF-8")])), Locale::En);
+        // 无法识别的 MAGENTIC_LANG 和 C 语言环境都回到默认
+        assert_eq!(Locale::detect(None, &env(&[("MAGENTIC_LANG", "fr"), ("LC_ALL", "zh_TW")])), Locale::Zh);
+        assert_eq!(Locale::detect(None, &env(&[("LC_ALL", ""), ("LANG", "C.UTF-8")])), Locale::En);
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_keys_fall_back_to_english() {
+        for (key, _) in EN {
+            assert!(ZH.iter().any(|(k, _)| k == key), "{} has no Chinese text", key);
+        }
+        assert_eq!(lookup(&[], "failure.retry"), "Retry");
+        assert_eq!(Locale::Zh.text("no.such.key"), "no.such.key");
+        assert_eq!(Locale::Zh.format("approval.target", &[&"https://example.com/", &"example.com"]), "目标：https://example.com/（域名 example.com）");
+    }
+}
@@ -0,0 +1,1188 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use colored::Colorize;
+use serde_json::{json, Value};
+use tokio::io::BufReader;
+
+use crate::agents::{DryRunLog, DryRunReport};
+use crate::api::executor::{BuiltRun, OrchestratorFactory};
+use crate::api::server::QueuedRun;
+use crate::cli::action_guard::{ConfirmPrompt, DialoguerPrompt, PromptActivity};
+use crate::cli::args::OutputFormat;
+use crate::cli::conversation::Conversation;
+use crate::cli::events::{write_ndjson, EventPrinter};
+use crate::cli::progress::StepProgress;
+use crate::cli::strings::Locale;
+use crate::cli::input::forward_user_input;
+use crate::cli::interrupt::Interrupts;
+use crate::orchestrator::plan::Plan;
+use crate::orchestrator::session::SessionCheckpoint;
+use crate::orchestrator::types::{ImageAttachment, RunOptions, RunOutcome};
+use crate::storage::sniff_mime;
+
+// 终端中的运行没有后端会话，组装时使用这个会话名
+const TERMINAL_SESSION: &str = "terminal";
+
+// 运行失败后的选项；出错时还有没完成的步骤才提供跳过
+const FAILURE_CHOICES: [&str; 3] = ["failure.retry", "failure.skip", "failure.abort"];
+
+// 交互模式中输入这些词或者直接回车时退出
+const EXIT_COMMANDS: [&str; 3] = ["exit", "quit", "退出"];
+
+// 交互模式中附带图片的命令，后面是图片的路径
+const ATTACH_COMMAND: &str = "attach image ";
+
+// 按扩展名识别拖进终端的图片路径
+const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "gif", "webp"];
+
+// 交互模式中一个任务完成后的选项：带着之前的对话继续，或者清空后开始新任务
+const FOLLOW_UP_CHOICES: [&str; 2] = ["follow_up.continue", "follow_up.new"];
+
+// 一次运行要做的事：执行新任务，只生成计划，从会话目录中的检查点继续，或者跳过出错的步骤后继续
+#[derive(Debug, Clone, Copy)]
+enum Job<'a> {
+    Task(&'a str),
+    Plan(&'a str),
+    Resume(&'a Path),
+    Skip(&'a SessionCheckpoint),
+}
+
+/// 交互模式中读取任务的来源，终端中为 LineEditor；返回 None 表示输入结束
+pub trait TaskSource: Send {
+    fn next_task(&mut self, prompt: &str) -> Result<Option<String>>;
+}
+
+/* 在终端中执行任务。orchestrator 与后端一样由 OrchestratorFactory 组装，
+ServerFactory 的 web_surfer 是使用池中浏览器的 WebAgent，运行结束后浏览器归还。
+运行期间事件按 EventPrinter 的格式输出，设置了 forward_stdin 时终端输入交给消息队列；
+运行失败时用红色显示错误，询问重试还是放弃；没有 prompt（一次性运行）时直接返回错误。
+OutputFormat::Json 时事件、最终结果和错误都按行输出为 JSON 对象。
+设置了 interrupts 时 Ctrl+C 取消当前运行，输出部分进度的总结和继续运行的命令 */
+pub struct TerminalRunner {
+    factory: Arc<dyn OrchestratorFactory>,
+    prompt: Option<Arc<dyn ConfirmPrompt>>,
+    forward_stdin: bool,
+    quiet: bool,
+    activity: Option<PromptActivity>,
+    timed: bool,
+    output: OutputFormat,
+    // ModelRegistry::summary，写入 JSON 的运行结果
+    models: Option<Value>,
+    interrupts: Option<Interrupts>,
+    // 检查点所在的会话目录，运行被停止时提示用 resume 子命令继续
+    session_dir: Option<PathBuf>,
+    // 交互模式中带入下一个任务的对话上下文最多占用的 token，0 表示不带入
+    context_tokens: usize,
+    // 执行前把计划交给组装时设置的 ActionGuard 审批
+    approve_plans: bool,
+    // 演练运行：工厂的代理记录预测的动作，运行结束后输出 DryRunReport
+    dry_run: Option<DryRunLog>,
+    // 从计划文件读入的计划，执行任务时不再调用模型规划
+    preset_plan: Option<Plan>,
+    locale: Locale,
+}
+
+impl TerminalRunner {
+    pub fn new(factory: Arc<dyn OrchestratorFactory>) -> Self {
+        Self::with_prompt(factory, Arc::new(DialoguerPrompt))
+    }
+
+    pub fn with_prompt(factory: Arc<dyn OrchestratorFactory>, prompt: Arc<dyn ConfirmPrompt>) -> Self {
+        Self {
+            factory,
+            prompt: Some(prompt),
+            forward_stdin: false,
+            quiet: false,
+            activity: None,
+            timed: false,
+            output: OutputFormat::Pretty,
+            models: None,
+            interrupts: None,
+            session_dir: None,
+            context_tokens: 0,
+            approve_plans: false,
+            dry_run: None,
+            preset_plan: None,
+            locale: Locale::default(),
+        }
+    }
+
+    /// 不向用户提问，用于脚本中的一次性运行
+    pub fn non_interactive(factory: Arc<dyn OrchestratorFactory>) -> Self {
+        Self { prompt: None, ..Self::with_prompt(factory, Arc::new(DialoguerPrompt)) }
+    }
+
+    /// 运行期间从标准输入读取补充的消息和停止指令
+    pub fn forward_stdin(mut self, enabled: bool) -> Self {
+        self.forward_stdin = enabled;
+        self
+    }
+
+    /// 每个步骤只输出一行，不显示代理的动作和截图
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// 显示步骤的 spinner 和用时，quiet 时不显示
+    pub fn timed(mut self, timed: bool) -> Self {
+        self.timed = timed;
+        self
+    }
+
+    pub fn output(mut self, output: OutputFormat) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// 使用的模型（ModelRegistry::summary），--output json 时写入运行结果的 models
+    pub fn models(mut self, summary: Value) -> Self {
+        self.models = Some(summary);
+        self
+    }
+
+    /// Ctrl+C 时取消正在进行的运行
+    pub fn interruptible(mut self, interrupts: Interrupts) -> Self {
+        self.interrupts = Some(interrupts);
+        self
+    }
+
+    /// orchestrator 写检查点的目录（output.session_dir）
+    pub fn session_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.session_dir = dir;
+        self
+    }
+
+    /// 交互模式中继续上一个任务时带入之前的任务和答案，最多 tokens 个 token（[cli] context_tokens）
+    pub fn context_tokens(mut self, tokens: usize) -> Self {
+        self.context_tokens = tokens;
+        self
+    }
+
+    /// 执行前把计划、恢复前把剩余的步骤交给 ActionGuard 审批（RunOptions::approve_plan）
+    pub fn approve_plans(mut self, enabled: bool) -> Self {
+        self.approve_plans = enabled;
+        self
+    }
+
+    /// 以演练方式运行（RunOptions::dry_run），log 与工厂的 with_dry_run 相同
+    pub fn dry_run(mut self, log: Option<DryRunLog>) -> Self {
+        self.dry_run = log;
+        self
+    }
+
+    /// 执行任务时直接使用这个计划（Orchestrator::set_plan），例如 run --plan-file 读入的计划
+    pub fn preset_plan(mut self, plan: Option<Plan>) -> Self {
+        self.preset_plan = plan;
+        self
+    }
+
+    /// 提问、菜单和错误前缀使用的语言
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    /// 审批提问期间（CliActionGuard::activity）暂停输出事件
+    pub fn pause_during(mut self, activity: PromptActivity) -> Self {
+        self.activity = Some(activity);
+        self
+    }
+
+    /// 执行任务直到成功或者用户放弃，放弃时返回最后一次的错误
+    pub async fn run<W: Write + Send>(&self, task: &str, out: &mut W) -> Result<RunOutcome> {
+        self.run_job(Job::Task(task), None, &[], out).await
+    }
+
+    /// 只生成计划并输出，不执行任何步骤；请求不需要计划时输出直接回答，outcome.plan 为 None
+    pub async fn plan<W: Write + Send>(&self, task: &str, out: &mut W) -> Result<RunOutcome> {
+        self.run_job(Job::Plan(task), None, &[], out).await
+    }
+
+    /// 从会话目录中的检查点继续被停止的运行
+    pub async fn resume<W: Write + Send>(&self, dir: &Path, out: &mut W) -> Result<RunOutcome> {
+        self.run_job(Job::Resume(dir), None, &[], out).await
+    }
+
+    /* 运行失败时询问重试、跳过出错的步骤还是放弃：跳过时从出错时的状态继续下一步，
+    之后再失败时重试的也是跳过之后的运行 */
+    async fn run_job<W: Write + Send>(
+        &self,
+        job: Job<'_>,
+        context: Option<String>,
+        attachments: &[ImageAttachment],
+        out: &mut W,
+    ) -> Result<RunOutcome> {
+        let mut skipped: Option<SessionCheckpoint> = None;
+        loop {
+            let current = skipped.as_ref().map_or(job, Job::Skip);
+            let mut failed_at = None;
+            let error = match self.attempt(current, context.clone(), attachments, out, &mut failed_at).await {
+                Ok(outcome) => return Ok(outcome),
+                Err(e) => e,
+            };
+            match self.output {
+                OutputFormat::Pretty => writeln!(out, "{}", self.locale.format("run.failed", &[&format!("{:#}", error)]).red())?,
+                OutputFormat::Json => writeln!(out, "{}", error_json(&error))?,
+            }
+            out.flush()?;
+            let Some(prompt) = self.prompt.clone() else {
+                return Err(error);
+            };
+            let failed_at = failed_at.filter(|checkpoint| checkpoint.remaining_steps() > 0);
+            let choices: Vec<&'static str> = FAILURE_CHOICES
+                .into_iter()
+                .filter(|choice| *choice != "failure.skip" || failed_at.is_some())
+                .collect();
+            let choice = self.select(prompt, "failure.prompt", &choices).await?.map(|i| choices[i]);
+            match (choice, failed_at) {
+                (Some("failure.retry"), _) => {}
+                (Some("failure.skip"), Some(mut checkpoint)) => {
+                    checkpoint.skip_current_step();
+                    skipped = Some(checkpoint);
+                }
+                _ => return Err(error),
+            }
+        }
+    }
+
+    /* 交互模式：反复从 tasks 读取任务并执行，输入结束、直接回车或者输入 exit 时结束。
+    单个任务失败（用户放弃重试）时显示错误后继续读取下一个任务。
+    输入 attach image <路径> 或者只输入一个图片文件的路径（拖进终端）时读取图片，随下一个任务交给规划。
+    设置了 context_tokens 时，任务完成后询问继续上一个任务还是开始新任务：
+    继续时之前的任务和答案随下一个任务交给规划，开始新任务时清空；取消选择时结束 */
+    pub async fn interactive<W: Write + Send>(&self, mut tasks: Box<dyn TaskSource>, out: &mut W) -> Result<()> {
+        let mut conversation = Conversation::new(self.context_tokens);
+        let mut images = Vec::new();
+        // 附带图片之后直接读取任务，不再询问
+        let mut finished_task = false;
+        loop {
+            if let Some(prompt) = self.prompt.clone().filter(|_| finished_task && !conversation.is_empty()) {
+                match self.select(prompt, "follow_up.prompt", &FOLLOW_UP_CHOICES).await? {
+                    Some(0) => {}
+                    Some(_) => conversation.clear(),
+                    None => return Ok(()),
+                }
+            }
+            finished_task = false;
+            // 读取时阻塞，放在阻塞线程中，读完交还
+            let question = self.locale.text("task.prompt");
+            let (returned, task) = tokio::task::spawn_blocking(move || {
+                let task = tasks.next_task(question);
+                (tasks, task)
+            })
+            .await?;
+            tasks = returned;
+            let Some(task) = task? else {
+                return Ok(());
+            };
+            let task = task.trim();
+            if task.is_empty() || EXIT_COMMANDS.contains(&task.to_lowercase().as_str()) {
+                return Ok(());
+            }
+            if let Some(path) = attached_image(task) {
+                match read_image(&path) {
+                    Ok(image) => {
+                        writeln!(out, "{}", self.locale.format("attach.added", &[&image.filename]))?;
+                        images.push(image);
+                    }
+                    Err(e) => writeln!(out, "{}", self.locale.format("attach.failed", &[&format!("{:#}", e)]).red())?,
+                }
+                continue;
+            }
+            let attachments = std::mem::take(&mut images);
+            match self.run_job(Job::Task(task), conversation.context(), &attachments, out).await {
+                Ok(outcome) => conversation.record(task, &outcome),
+                Err(e) => tracing::info!("Task abandoned: {:#}", e),
+            }
+            finished_task = true;
+        }
+    }
+
+    /* 组装并执行一次，事件输出和运行在同一个任务中交替进行，orchestrator 释放后输出结束。
+    执行步骤时出错，failed_at 为出错时的检查点 */
+    async fn attempt<W: Write + Send>(
+        &self,
+        job: Job<'_>,
+        context: Option<String>,
+        attachments: &[ImageAttachment],
+        out: &mut W,
+        failed_at: &mut Option<SessionCheckpoint>,
+    ) -> Result<RunOutcome> {
+        let run = QueuedRun {
+            session_id: TERMINAL_SESSION.to_string(),
+            message_id: String::new(),
+            user_id: None,
+            task: match job {
+                Job::Task(task) | Job::Plan(task) => task.to_string(),
+                Job::Resume(_) => String::new(),
+                Job::Skip(checkpoint) => checkpoint.task.clone(),
+            },
+            resume_run_id: None,
+            plan: None,
+            attachments: attachments.to_vec(),
+        };
+        let BuiltRun { mut orchestrator, browser } = self.factory.build(&run).await?;
+        let events = orchestrator.subscribe_events();
+        let queue = orchestrator.user_message_queue();
+        let input = self
+            .forward_stdin
+            .then(|| tokio::spawn(forward_user_input(BufReader::new(tokio::io::stdin()), queue.clone())));
+        let interrupt = self.interrupts.as_ref().map(|interrupts| interrupts.cancel_on_interrupt(queue.clone()));
+        let opts = RunOptions { approve_plan: self.approve_plans, dry_run: self.dry_run.is_some(), ..RunOptions::default() };
+        let preset_plan = self.preset_plan.clone();
+        let running = async move {
+            let outcome = match job {
+                Job::Task(task) => match preset_plan.map(|plan| orchestrator.set_plan(plan)).transpose() {
+                    Ok(_) => orchestrator.run_task(task.to_string(), RunOptions { context, attachments: run.attachments, ..opts }).await,
+                    Err(e) => Err(e),
+                },
+                Job::Plan(task) => match orchestrator.generate_plan(task.to_string(), opts).await {
+                    Ok(_) => Ok(orchestrator.run_outcome()),
+                    Err(e) => Err(e),
+                },
+                Job::Resume(dir) => orchestrator.resume_session(dir, opts).await,
+                // 跳过是用户刚刚做出的选择，不再确认
+                Job::Skip(checkpoint) => {
+                    orchestrator.resume_checkpoint(checkpoint.clone(), RunOptions { approve_plan: false, ..opts }).await
+                }
+            };
+            let estimate = orchestrator.plan_estimate().cloned();
+            let checkpoint = match job {
+                Job::Plan(_) => None,
+                _ => outcome.as_ref().err().and_then(|_| orchestrator.checkpoint()),
+            };
+            drop(orchestrator);
+            (outcome, estimate, checkpoint)
+        };
+        let printing = async {
+            if self.output == OutputFormat::Json {
+                return write_ndjson(events, &mut *out).await.map(drop);
+            }
+            let mut printer = EventPrinter::new(&mut *out).quiet(self.quiet).locale(self.locale);
+            if let Some(activity) = &self.activity {
+                printer = printer.pause_during(activity.clone());
+            }
+            if self.timed && !self.quiet {
+                printer = printer.timed(StepProgress::new().locale(self.locale));
+            }
+            printer.print_all(events).await.map(drop)
+        };
+        let ((outcome, estimate, checkpoint), printed) = tokio::join!(running, printing);
+        *failed_at = checkpoint;
+        if let Some(input) = input {
+            input.abort();
+        }
+        if let Some(interrupt) = interrupt {
+            interrupt.abort();
+        }
+        if let Some(browser) = browser {
+            browser.release().await;
+        }
+        printed?;
+        let outcome = outcome?;
+        let report = self.dry_run.as_ref().map(|log| DryRunReport::new(log, estimate));
+        // 被 Ctrl+C 或 stop 停止时，最终答案是部分进度的总结，检查点可以继续
+        let resume_command = queue
+            .is_cancelled()
+            .then_some(self.session_dir.as_ref())
+            .flatten()
+            .map(|dir| format!("server resume {}", dir.display()));
+        match self.output {
+            // 计划已经随事件输出，没有计划时才有直接回答
+            OutputFormat::Pretty if matches!(job, Job::Plan(_)) => {
+                if outcome.plan.is_none() {
+                    writeln!(out, "\n{}", outcome.final_answer)?;
+                }
+            }
+            OutputFormat::Pretty => {
+                writeln!(out, "\n{}", outcome.final_answer)?;
+                if let Some(report) = &report {
+                    writeln!(out, "\n{}", report.render())?;
+                }
+                if let Some(command) = &resume_command {
+                    writeln!(out, "{}", self.locale.format("run.stopped", &[command]).yellow())?;
+                }
+            }
+            OutputFormat::Json => {
+                let mut summary = outcome_json(&outcome);
+                if matches!(job, Job::Plan(_)) {
+                    summary["plan"] = json!(outcome.plan);
+                }
+                if let Some(report) = &report {
+                    summary["dry_run"] = json!(report);
+                }
+                if let Some(models) = &self.models {
+                    summary["models"] = models.clone();
+                }
+                if let Some(command) = resume_command {
+                    summary["resume_command"] = json!(command);
+                }
+                writeln!(out, "{}", summary)?
+            }
+        }
+        out.flush()?;
+        Ok(outcome)
+    }
+
+    // 在阻塞线程中提问，读取失败时记录日志并当作取消选择
+    async fn select(&self, prompt: Arc<dyn ConfirmPrompt>, question: &'static str, choices: &[&'static str]) -> Result<Option<usize>> {
+        let question = self.locale.text(question);
+        let choices = self.locale.choices(choices);
+        let choice = tokio::task::spawn_blocking(move || prompt.select(question, &choices)).await?;
+        Ok(choice.unwrap_or_else(|e| {
+            tracing::warn!("Failed to read the answer: {:#}", e);
+            None
+        }))
+    }
+}
+
+/// --output json 最后输出的运行结果：最终答案、统计和保存的产物
+/* 交互模式中要附带的图片路径：attach image <路径>，或者整行只有一个存在的图片文件路径
+（拖进终端时粘贴的路径，可能带引号、file:// 前缀或者用反斜杠转义空格）。其他输入按任务处理 */
+fn attached_image(line: &str) -> Option<PathBuf> {
+    let line = line.trim();
+    let command = line.get(..ATTACH_COMMAND.len()).filter(|prefix| prefix.eq_ignore_ascii_case(ATTACH_COMMAND));
+    if command.is_some() {
+        return Some(PathBuf::from(unquote_path(&line[ATTACH_COMMAND.len()..])));
+    }
+    let path = PathBuf::from(unquote_path(line));
+    let is_image = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()));
+    (is_image && path.is_file()).then_some(path)
+}
+
+fn unquote_path(path: &str) -> String {
+    let path = path.trim();
+    let path = path.strip_prefix("file://").unwrap_or(path);
+    for quote in ['\'', '"'] {
+        if let Some(inner) = path.strip_prefix(quote).and_then(|rest| rest.strip_suffix(quote)) {
+            return inner.to_string();
+        }
+    }
+    path.replace("\\ ", " ")
+}
+
+// 读取要附带的图片，不是图片的文件报错
+fn read_image(path: &Path) -> Result<ImageAttachment> {
+    let image = ImageAttachment::from_path(path)?;
+    if !sniff_mime(&image.data).starts_with("image/") {
+        anyhow::bail!("{} is not an image", path.display());
+    }
+    Ok(image)
+}
+
+pub fn outcome_json(outcome: &RunOutcome) -> Value {
+    json!({
+        "type": "run_outcome",
+        "final_answer": outcome.final_answer,
+        "metrics": outcome.metrics,
+        "artifacts": outcome.artifacts,
+    })
+}
+
+/// 启动时显示的模型配置，例如 "Models: orchestrator=o3-mini, web_agent=gpt-4o ... via openai (https://api.openai.com/v1)"
+pub fn describe_models(summary: &Value) -> String {
+    let models: Vec<String> = summary["models"]
+        .as_object()
+        .map(|models| models.iter().map(|(role, model)| format!("{}={}", role, model.as_str().unwrap_or_default())).collect())
+        .unwrap_or_default();
+    let mut line = format!("Models: {}", models.join(", "));
+    match summary["provider"].as_str() {
+        Some(provider) => line.push_str(&format!(" via {} ({})", provider, summary["base_url"].as_str().unwrap_or_default())),
+        None => line.push_str(&format!(" via {}", summary["base_url"].as_str().unwrap_or_default())),
+    }
+    if let Some(temperature) = summary["temperature"].as_f64() {
+        line.push_str(&format!(", temperature {}", temperature));
+    }
+    line
+}
+
+/// --output json 时的错误
+pub fn error_json(error: &anyhow::Error) -> Value {
+    json!({ "type": "error", "message": format!("{:#}", error) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+
+    use crate::agents::AgentEvent;
+    use crate::clients::ModelRegistry;
+    use crate::config::{AppConfig, BrowserSettings, ModelSettings};
+    use crate::orchestrator::message::{ChatMessage, MessageRole, MultiModalContent};
+    use crate::orchestrator::session::CHECKPOINT_FILE;
+    use crate::testing::{
+        ledger_json, mock_browser_pool, plan_json, MockAgent, MockBrowser, MockProvider, MockReply, OrchestratorBuilder,
+    };
+    use crate::agents::web_agent::WebAgent;
+    use crate::tools::chrome::chrome_ctrl::Chrome;
+    use crate::tools::chrome::{BrowserPool, BrowserPoolOptions, ChromeFactory, Lease};
+    use crate::tools::url_status_manager::SitePolicy;
+
+    // 依次给出 choices 中的选择
+    #[derive(Debug, Default)]
+    struct ScriptedPrompt {
+        choices: Mutex<Vec<Option<usize>>>,
+    }
+
+    impl ConfirmPrompt for ScriptedPrompt {
+        fn confirm(&self, _prompt: &str) -> Result<bool> {
+            Ok(false)
+        }
+
+        fn select(&self, _prompt: &str, items: &[&str]) -> Result<Option<usize>> {
+            let failure = Locale::En.choices(&FAILURE_CHOICES);
+            assert!(items.iter().all(|item| failure.contains(item)) || items == Locale::En.choices(&FOLLOW_UP_CHOICES), "{:?}", items);
+            Ok(self.choices.lock().unwrap().remove(0))
+        }
+
+        fn input(&self, _prompt: &str, _initial: &str) -> Result<String> {
+            Ok(String::new())
+        }
+
+        fn multi_select(&self, _prompt: &str, _items: &[&str]) -> Result<Option<Vec<usize>>> {
+            Ok(None)
+        }
+    }
+
+    // 依次读出的任务，读完后为输入结束
+    struct ScriptedTasks(Arc<Mutex<VecDeque<&'static str>>>);
+
+    impl TaskSource for ScriptedTasks {
+        fn next_task(&mut self, _prompt: &str) -> Result<Option<String>> {
+            Ok(self.0.lock().unwrap().pop_front().map(str::to_string))
+        }
+    }
+
+    type ProviderScript = Box<dyn Fn(&str, usize) -> MockProvider + Send + Sync>;
+    type AgentScript = Box<dyn Fn() -> MockAgent + Send + Sync>;
+
+    /* 终端测试共用的工厂：默认是一步计划，web_surfer 打开菜单页之后回答 "The menu has pizza."。
+    前 failures 次组装失败；leasing_browsers 时每次运行从池中借一个浏览器；
+    provider 按任务和第几次成功的组装（从 0 开始）给出回答，组装出的 provider 留下来检查请求 */
+    struct StepFactory {
+        failures: usize,
+        builds: AtomicUsize,
+        browsers: Option<BrowserPool<MockBrowser>>,
+        provider: ProviderScript,
+        agent: AgentScript,
+        artifacts_dir: Option<PathBuf>,
+        session_dir: Option<PathBuf>,
+        providers: Mutex<Vec<Arc<MockProvider>>>,
+    }
+
+    impl StepFactory {
+        fn new() -> Self {
+            Self {
+                failures: 0,
+                builds: AtomicUsize::new(0),
+                browsers: None,
+                provider: Box::new(|task, _| one_step_provider(task, "Open the menu page", "The menu has pizza.")),
+                agent: Box::new(|| MockAgent::new("web_surfer").reply("Opened the menu page")),
+                artifacts_dir: None,
+                session_dir: None,
+                providers: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn failing(mut self, failures: usize) -> Self {
+            self.failures = failures;
+            self
+        }
+
+        fn leasing_browsers(mut self) -> Self {
+            self.browsers = Some(mock_browser_pool(1));
+            self
+        }
+
+        fn provider(mut self, script: impl Fn(&str, usize) -> MockProvider + Send + Sync + 'static) -> Self {
+            self.provider = Box::new(script);
+            self
+        }
+
+        fn agent(mut self, agent: impl Fn() -> MockAgent + Send + Sync + 'static) -> Self {
+            self.agent = Box::new(agent);
+            self
+        }
+
+        fn artifacts_dir(mut self, dir: &Path) -> Self {
+            self.artifacts_dir = Some(dir.to_path_buf());
+            self
+        }
+
+        fn session_dir(mut self, dir: &Path) -> Self {
+            self.session_dir = Some(dir.to_path_buf());
+            self
+        }
+
+        fn builds(&self) -> usize {
+            self.builds.load(Ordering::SeqCst)
+        }
+
+        fn browsers(&self) -> &BrowserPool<MockBrowser> {
+            self.browsers.as_ref().expect("the factory leases browsers")
+        }
+
+        fn providers(&self) -> Vec<Arc<MockProvider>> {
+            self.providers.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl OrchestratorFactory for StepFactory {
+        async fn build(&self, run: &QueuedRun) -> Result<BuiltRun> {
+            let build = self.builds.fetch_add(1, Ordering::SeqCst);
+            if build < self.failures {
+                return Err(anyhow!("chromedriver is not reachable"));
+            }
+            let provider = Arc::new((self.provider)(&run.task, build - self.failures));
+            self.providers.lock().unwrap().push(provider.clone());
+            let browser = match &self.browsers {
+                Some(browsers) => Some(Box::new(browsers.acquire().await?) as Box<dyn Lease>),
+                None => None,
+            };
+            let artifacts_dir = self.artifacts_dir.as_ref().map(|dir| dir.to_string_lossy().to_string());
+            let session_dir = self.session_dir.as_ref().map(|dir| dir.to_string_lossy().to_string());
+            let orchestrator = OrchestratorBuilder::new()
+                .provider(provider)
+                .agent("Browses the web", (self.agent)())
+                .configure(|c| {
+                    c.artifacts_dir = artifacts_dir;
+                    c.session_dir = session_dir;
+                })
+                .build()
+                .await?;
+            Ok(BuiltRun { orchestrator, browser })
+        }
+    }
+
+    // 一步计划：web_surfer 执行 step，完成后回答 answer
+    fn one_step_provider(task: &str, step: &str, answer: &str) -> MockProvider {
+        MockProvider::new()
+            .respond_json(plan_json(task, &[("Open", step, "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", step))
+            .respond_json(ledger_json(true, false, "web_surfer", "Done"))
+            .respond(answer)
+    }
+
+    // 前 failures 次组装失败，之后 web_surfer 借池中的浏览器执行
+    fn flaky(failures: usize) -> Arc<StepFactory> {
+        Arc::new(StepFactory::new().failing(failures).leasing_browsers())
+    }
+
+    fn terminal(failures: usize, choices: Vec<Option<usize>>) -> (TerminalRunner, Arc<StepFactory>) {
+        let factory = flaky(failures);
+        let prompt = Arc::new(ScriptedPrompt { choices: Mutex::new(choices) });
+        (TerminalRunner::with_prompt(factory.clone(), prompt), factory)
+    }
+
+    #[tokio::test]
+    async fn test_step_runs_with_a_leased_browser() -> Result<()> {
+        let (runner, factory) = terminal(0, Vec::new());
+        let mut out = Vec::new();
+        let outcome = runner.timed(true).run("Find the menu", &mut out).await?;
+        assert_eq!(outcome.final_answer, "The menu has pizza.");
+
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("plan ready with 1 steps\n  1. Open [web_surfer]\n"), "{}", out);
+        assert!(out.contains("step 1 complete"), "{}", out);
+        // 步骤完成后输出用时
+        assert!(out.contains("step 1 [web_surfer] done in "), "{}", out);
+        assert!(out.ends_with("\nThe menu has pizza.\n"), "{}", out);
+        // 运行结束后浏览器已经归还
+        let stats = factory.browsers().stats();
+        assert_eq!((stats.in_use, stats.resets), (0, 1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_plan_only_prints_the_plan() -> Result<()> {
+        let factory = flaky(0);
+        let mut out = Vec::new();
+        let outcome = TerminalRunner::non_interactive(factory.clone()).plan("Find the menu", &mut out).await?;
+        assert_eq!(outcome.plan.map(|plan| plan.steps.len()), Some(1));
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("plan ready with 1 steps\n  1. Open [web_surfer]\n"), "{}", out);
+        assert!(!out.contains("step 1 complete"), "{}", out);
+
+        // --output json 时最后一行带上计划
+        let mut out = Vec::new();
+        TerminalRunner::non_interactive(factory).output(OutputFormat::Json).plan("Find the menu", &mut out).await?;
+        let last: Value = serde_json::from_str(String::from_utf8(out)?.lines().last().unwrap())?;
+        assert_eq!(last["type"], "run_outcome");
+        assert_eq!(last["plan"]["steps"][0]["title"], "Open");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_preset_plan_skips_planning() -> Result<()> {
+        // 模型只回答进度和最终答案，没有规划的回复
+        let factory = Arc::new(StepFactory::new().provider(|_, _| {
+            MockProvider::new()
+                .respond_json(ledger_json(false, false, "web_surfer", "Read the saved menu"))
+                .respond_json(ledger_json(true, false, "web_surfer", "Done"))
+                .respond("The menu has pizza.")
+        }));
+        let plan = Plan::from_list_of_dicts_or_str(plan_json("Find the menu", &[("Read", "Read the saved menu", "web_surfer")]))
+            .expect("plan");
+        let mut out = Vec::new();
+        let outcome =
+            TerminalRunner::non_interactive(factory.clone()).preset_plan(Some(plan)).run("Find the menu", &mut out).await?;
+        assert_eq!(outcome.final_answer, "The menu has pizza.");
+        assert_eq!(outcome.metrics.planning_calls, 0);
+        assert!(String::from_utf8(out)?.contains("  1. Read [web_surfer]\n"));
+        assert_eq!(factory.providers()[0].requests().len(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failures_offer_retry_or_abort() -> Result<()> {
+        let (runner, factory) = terminal(1, vec![Some(0)]);
+        let mut out = Vec::new();
+        runner.run("Find the menu", &mut out).await?;
+        assert_eq!(factory.builds(), 2);
+        assert!(String::from_utf8(out)?.contains("Run failed: chromedriver is not reachable"));
+
+        // 放弃或取消选择时返回错误
+        for choice in [Some(1), None] {
+            let (runner, factory) = terminal(1, vec![choice]);
+            let error = runner.run("Find the menu", &mut Vec::new()).await.unwrap_err();
+            assert_eq!(error.to_string(), "chromedriver is not reachable");
+            assert_eq!(factory.builds(), 1);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_step_can_be_skipped() -> Result<()> {
+        // 第一次运行在步骤 1 执行后模型没有回答而出错，跳过后从步骤 2 继续
+        let factory = Arc::new(StepFactory::new().provider(|task, build| match build {
+            0 => MockProvider::new()
+                .respond_json(plan_json(task, &[("Open", "Open the menu page", "web_surfer"), ("Read", "Read the menu", "web_surfer")]))
+                .respond_json(ledger_json(false, false, "web_surfer", "Open the menu page")),
+            _ => MockProvider::new()
+                .respond_json(ledger_json(false, false, "web_surfer", "Read the menu"))
+                .respond_json(ledger_json(true, false, "web_surfer", "Done"))
+                .respond("The menu has pizza."),
+        }));
+        let prompt = Arc::new(ScriptedPrompt { choices: Mutex::new(vec![Some(1)]) });
+        let mut out = Vec::new();
+        let outcome = TerminalRunner::with_prompt(factory.clone(), prompt).run("Find the menu", &mut out).await?;
+        assert_eq!(outcome.final_answer, "The menu has pizza.");
+        assert_eq!(factory.builds(), 2);
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("Run failed: "), "{}", out);
+        assert!(out.contains("step 2 complete"), "{}", out);
+
+        // 跳过的步骤告诉了模型
+        let requests = factory.providers()[1].requests();
+        let prompts = format!("{:?}", requests);
+        assert!(prompts.contains("Step 1 (Open) failed and was skipped"), "{}", prompts);
+        Ok(())
+    }
+
+    /* 真实的 WebAgent 在无头 Chrome 中执行一步：测试页面由本地的 axum 提供，
+    规划和 WebAgent 的模型都是 MockProvider，浏览器从 ChromeFactory 的池中借出 */
+    struct WebStepFactory {
+        page: String,
+        browsers: BrowserPool<Chrome>,
+        model: Mutex<Option<Arc<MockProvider>>>,
+    }
+
+    #[async_trait]
+    impl OrchestratorFactory for WebStepFactory {
+        async fn build(&self, run: &QueuedRun) -> Result<BuiltRun> {
+            let step = format!("Open {} and read the menu", self.page);
+            let provider = MockProvider::new()
+                .respond_json(plan_json(&run.task, &[("Open", &step, "WebAgent")]))
+                .respond_json(ledger_json(false, false, "WebAgent", &step))
+                .respond_json(ledger_json(true, false, "WebAgent", "Done"))
+                .respond("The menu has pizza.");
+            let model = Arc::new(
+                MockProvider::new()
+                    .respond_tool_call("visit_url", json!({ "url": self.page }))
+                    .respond_tool_call("stop_action", json!({ "answer": "The menu has pizza." })),
+            );
+            *self.model.lock().unwrap() = Some(model.clone());
+            let lease = self.browsers.acquire().await?;
+            let mut agent = WebAgent::default();
+            agent.set_model_clients(model.clone(), model);
+            agent.attach_browser(&SitePolicy::default(), lease.share())?;
+            let orchestrator = OrchestratorBuilder::new()
+                .provider(Arc::new(provider))
+                .agent("Browses the web", agent)
+                .build()
+                .await?;
+            Ok(BuiltRun { orchestrator, browser: Some(Box::new(lease)) })
+        }
+    }
+
+    /// 运行方式：cargo test test_web_agent_step_runs_end_to_end -- --ignored
+    #[tokio::test]
+    #[ignore] // 需要浏览器，使用 cargo test -- --ignored 运行
+    async fn test_web_agent_step_runs_end_to_end() -> Result<()> {
+        let app = axum::Router::new().route(
+            "/menu",
+            axum::routing::get(|| async { axum::response::Html("<html><head><title>Menu</title></head><body><h1>Menu</h1><p>Pizza</p></body></html>") }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let page = format!("http://{}/menu", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let settings = BrowserSettings { headless: true, ..BrowserSettings::default() };
+        let browsers = BrowserPool::new(
+            Arc::new(ChromeFactory::new(settings.clone())),
+            BrowserPoolOptions { size: 1, ..BrowserPoolOptions::from_settings(&settings) },
+        );
+        let factory = Arc::new(WebStepFactory { page, browsers, model: Mutex::new(None) });
+        let mut out = Vec::new();
+        let outcome = TerminalRunner::non_interactive(factory.clone()).run("Find the menu", &mut out).await?;
+        factory.browsers.close().await;
+
+        assert_eq!(outcome.final_answer, "The menu has pizza.");
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("step 1 complete"), "{}", out);
+        // WebAgent 先打开页面，再看着打开的页面回答
+        let requests = factory.model.lock().unwrap().clone().expect("the step ran").requests();
+        assert_eq!(requests.len(), 2);
+        assert!(format!("{:?}", requests[1]).contains("Menu"));
+        Ok(())
+    }
+
+    // web_surfer 先发出动作和结果，再返回一张截图
+    fn feed_agent() -> MockAgent {
+        let screenshot = ChatMessage::new_multimodal(
+            MessageRole::Assistant,
+            "web_surfer".to_string(),
+            vec![
+                MultiModalContent::Text("Opened the menu page".to_string()),
+                MultiModalContent::Image(vec![0x89, b'P', b'N', b'G']),
+            ],
+        );
+        let events = vec![
+            AgentEvent::ActionProposed { action: "click".to_string(), explanation: "Open the menu".to_string() },
+            AgentEvent::ActionResult { action: "click".to_string(), result: "Clicked the menu link".to_string() },
+        ];
+        MockAgent::new("web_surfer").then(MockReply::Events(events, Box::new(MockReply::Message(screenshot))))
+    }
+
+    #[tokio::test]
+    async fn test_actions_are_printed_as_they_happen() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let factory = Arc::new(StepFactory::new().agent(feed_agent).artifacts_dir(dir.path()));
+        let mut out = Vec::new();
+        TerminalRunner::non_interactive(factory.clone()).run("Find the menu", &mut out).await?;
+
+        // 动作、结果和截图按发生的顺序输出，结果和截图缩进在动作下面
+        let out = String::from_utf8(out)?;
+        let action = out.find("  [web_surfer] action: click (Open the menu)\n").expect(&out);
+        let result = out.find("    [web_surfer] result of click: Clicked the menu link\n").expect(&out);
+        let saved = out.find("    [web_surfer] screenshot saved to ").expect(&out);
+        let complete = out.find("step 1 complete").expect(&out);
+        assert!(action < result && result < saved && saved < complete, "{}", out);
+
+        let path = out[saved..].lines().next().unwrap().trim_start().trim_start_matches("[web_surfer] screenshot saved to ");
+        assert!(Path::new(path).starts_with(dir.path()) && Path::new(path).exists(), "{}", path);
+
+        // quiet 时每个步骤一行
+        let mut out = Vec::new();
+        TerminalRunner::non_interactive(factory).quiet(true).run("Find the menu", &mut out).await?;
+        let out = String::from_utf8(out)?;
+        assert!(out.starts_with("plan ready with 1 steps\nstep 1 -> web_surfer\n"), "{}", out);
+        assert!(!out.contains("action: click"), "{}", out);
+        Ok(())
+    }
+
+    // web_surfer 很久才回答，停止时给出目前为止的总结；检查点写入 session_dir
+    fn slow(session_dir: &Path) -> Arc<StepFactory> {
+        let factory = StepFactory::new()
+            .leasing_browsers()
+            .session_dir(session_dir)
+            .provider(|task, _| {
+                MockProvider::new()
+                    .respond_json(plan_json(task, &[("Open", "Open the menu page", "web_surfer")]))
+                    .respond_json(ledger_json(false, false, "web_surfer", "Open the menu page"))
+                    .respond("Nothing was found yet.")
+            })
+            .agent(|| MockAgent::new("web_surfer").delayed_reply(Duration::from_secs(30), "Opened"));
+        Arc::new(factory)
+    }
+
+    #[tokio::test]
+    async fn test_ctrl_c_stops_the_run_with_a_summary() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let factory = slow(dir.path());
+        let interrupts = Interrupts::new(Arc::new(|| panic!("a single Ctrl+C must not force an exit")));
+        let runner = TerminalRunner::non_interactive(factory.clone())
+            .interruptible(interrupts.clone())
+            .session_dir(Some(dir.path().to_path_buf()));
+
+        // 步骤开始执行之后按下 Ctrl+C
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            interrupts.trigger();
+        });
+        let started = std::time::Instant::now();
+        let mut out = Vec::new();
+        let outcome = runner.run("Find the menu", &mut out).await?;
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(outcome.step_outcomes.is_empty());
+
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("cancelled at step 1: Stopped at the user's request"), "{}", out);
+        assert!(out.ends_with(&format!("Stopped. Resume with: server resume {}\n", dir.path().display())), "{}", out);
+        // 检查点已经写好，浏览器已经归还
+        assert!(dir.path().join(CHECKPOINT_FILE).exists());
+        let stats = factory.browsers().stats();
+        assert_eq!((stats.in_use, stats.resets), (0, 1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_one_shot_runs_do_not_ask() -> Result<()> {
+        let factory = flaky(1);
+        let runner = TerminalRunner::non_interactive(factory.clone());
+        let mut out = Vec::new();
+        assert!(runner.run("Find the menu", &mut out).await.is_err());
+        assert_eq!(factory.builds(), 1);
+
+        let outcome = runner.run("Find the menu", &mut Vec::new()).await?;
+        let summary = outcome_json(&outcome);
+        assert_eq!(summary["final_answer"], "The menu has pizza.");
+        assert_eq!(summary["artifacts"], serde_json::json!([]));
+        assert!(summary["metrics"].is_object());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_json_output_is_one_object_per_line() -> Result<()> {
+        let factory = flaky(1);
+        let runner = TerminalRunner::non_interactive(factory).output(OutputFormat::Json);
+        let mut out = Vec::new();
+        assert!(runner.run("Find the menu", &mut out).await.is_err());
+        let error: Value = serde_json::from_slice(&out)?;
+        assert_eq!(error, json!({ "type": "error", "message": "chromedriver is not reachable" }));
+
+        let mut out = Vec::new();
+        runner.run("Find the menu", &mut out).await?;
+        let objects = String::from_utf8(out)?
+            .lines()
+            .map(serde_json::from_str::<Value>)
+            .collect::<serde_json::Result<Vec<_>>>()?;
+        let types: Vec<&str> = objects.iter().map(|object| object["type"].as_str().unwrap()).collect();
+        for expected in ["plan_ready", "step_started", "ledger_evaluated", "metrics"] {
+            assert!(types.contains(&expected), "{:?}", types);
+        }
+        // 运行结果是最后一个对象
+        let outcome = objects.last().unwrap();
+        assert_eq!((outcome["type"].as_str(), outcome["final_answer"].as_str()), (Some("run_outcome"), Some("The menu has pizza.")));
+        assert!(outcome.get("models").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_outcome_includes_the_models() -> Result<()> {
+        let mut config = AppConfig::default();
+        config.llm.provider = Some("openai".to_string());
+        config.llm.base_url = Some("https://api.openai.com/v1".to_string());
+        config.llm.model = Some("gpt-4o".to_string());
+        config.llm.temperature = Some(0.2);
+        config.models.insert("orchestrator".to_string(), ModelSettings { model: Some("o3-mini".to_string()), ..Default::default() });
+        let summary = ModelRegistry::from_config(&config).summary(config.llm.provider.as_deref());
+        assert_eq!(
+            describe_models(&summary),
+            "Models: orchestrator=o3-mini, web_agent=gpt-4o, plan_agent=gpt-4o, summarizer=gpt-4o \
+             via openai (https://api.openai.com/v1), temperature 0.2"
+        );
+
+        let runner = TerminalRunner::non_interactive(flaky(0)).output(OutputFormat::Json).models(summary.clone());
+        let mut out = Vec::new();
+        runner.run("Find the menu", &mut out).await?;
+        let last: Value = serde_json::from_str(String::from_utf8(out)?.lines().last().unwrap())?;
+        assert_eq!(last["models"], summary);
+        Ok(())
+    }
+
+    // 查营业时间的一步计划，第一次运行回答本周、之后回答下周的营业时间
+    fn opening_hours() -> Arc<StepFactory> {
+        let factory = StepFactory::new()
+            .provider(|task, build| {
+                let answer = match build {
+                    0 => "Luigi's is open until 22:00 this week.",
+                    _ => "Luigi's is open until 23:00 next week.",
+                };
+                one_step_provider(task, "Open the opening hours page", answer)
+            })
+            .agent(|| MockAgent::new("web_surfer").reply("Opened the opening hours page"));
+        Arc::new(factory)
+    }
+
+    fn planning_request(provider: &MockProvider) -> String {
+        serde_json::to_string(&provider.requests()[0]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_follow_ups_carry_the_previous_answer() -> Result<()> {
+        let factory = opening_hours();
+        // 第一个任务后继续，第二个任务后开始新任务，第三个任务后取消选择
+        let prompt = Arc::new(ScriptedPrompt { choices: Mutex::new(vec![Some(0), Some(1), None]) });
+        let runner = TerminalRunner::with_prompt(factory.clone(), prompt).context_tokens(2000);
+        let tasks = Arc::new(Mutex::new(VecDeque::from([
+            "When is Luigi's open this week?",
+            "Now do the same for next week",
+            "Find a bakery nearby",
+        ])));
+        runner.interactive(Box::new(ScriptedTasks(tasks)), &mut Vec::new()).await?;
+
+        let providers = factory.providers();
+        assert_eq!(providers.len(), 3);
+        assert!(!planning_request(&providers[0]).contains("Earlier in this conversation"));
+        // 后续请求的规划中有上一个任务和它的答案
+        let follow_up = planning_request(&providers[1]);
+        assert!(follow_up.contains("When is Luigi's open this week?"), "{}", follow_up);
+        assert!(follow_up.contains("Luigi's is open until 22:00 this week."), "{}", follow_up);
+        assert!(follow_up.contains("Current request (it may refer to the conversation above): Now do the same for next week"));
+        // 开始新任务时清空
+        assert!(!planning_request(&providers[2]).contains("Luigi's"));
+        Ok(())
+    }
+
+    // 记录终端中显示的提问和菜单（默认选项前为 >）；第一次给出一个任务，菜单一律取消选择
+    #[derive(Debug, Clone, Default)]
+    struct Screen(Arc<Mutex<Vec<String>>>);
+
+    impl ConfirmPrompt for Screen {
+        fn confirm(&self, _prompt: &str) -> Result<bool> {
+            Ok(false)
+        }
+
+        fn select(&self, prompt: &str, items: &[&str]) -> Result<Option<usize>> {
+            let mut lines = vec![prompt.to_string()];
+            lines.extend(items.iter().enumerate().map(|(i, item)| format!("{} {}", if i == 0 { ">" } else { " " }, item)));
+            self.0.lock().unwrap().push(lines.join("\n"));
+            Ok(None)
+        }
+
+        fn input(&self, _prompt: &str, _initial: &str) -> Result<String> {
+            Ok(String::new())
+        }
+
+        fn multi_select(&self, _prompt: &str, _items: &[&str]) -> Result<Option<Vec<usize>>> {
+            Ok(None)
+        }
+    }
+
+    impl TaskSource for Screen {
+        fn next_task(&mut self, prompt: &str) -> Result<Option<String>> {
+            let mut shown = self.0.lock().unwrap();
+            let first = shown.is_empty();
+            shown.push(prompt.to_string());
+            Ok(first.then(|| "When is Luigi's open this week?".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_menus_follow_the_locale() -> Result<()> {
+        let expected = [
+            (
+                Locale::En,
+                "What would you like me to help you with?\n\
+                 Would you like to do something else?\n\
+                 > Continue the previous task\n  Start a new task",
+            ),
+            (Locale::Zh, "需要我帮你做什么？\n还需要做其他事情吗？\n> 继续上一个任务\n  开始新任务"),
+        ];
+        for (locale, menu) in expected {
+            let screen = Screen::default();
+            let runner = TerminalRunner::with_prompt(opening_hours(), Arc::new(screen.clone()))
+                .context_tokens(2000)
+                .locale(locale);
+            runner.interactive(Box::new(screen.clone()), &mut Vec::new()).await?;
+            assert_eq!(screen.0.lock().unwrap().join("\n"), menu);
+        }
+
+        // 运行失败的提示和选项
+        let screen = Screen::default();
+        let runner = TerminalRunner::with_prompt(flaky(1), Arc::new(screen.clone())).locale(Locale::Zh);
+        let mut out = Vec::new();
+        assert!(runner.run("Find the menu", &mut out).await.is_err());
+        assert!(String::from_utf8(out)?.contains("运行失败：chromedriver is not reachable"));
+        assert_eq!(*screen.0.lock().unwrap(), ["接下来怎么做？\n> 重试\n  放弃"]);
+        Ok(())
+    }
+
+    const RECEIPT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/orchestrator/testdata/receipt.png");
+
+    #[test]
+    fn test_attach_commands_and_dragged_images() {
+        let cases = [
+            ("attach image receipt.png", Some("receipt.png")),
+            ("Attach Image  'my receipt.png' ", Some("my receipt.png")),
+            // 拖进终端的路径只有文件存在时才按图片处理
+            (RECEIPT, Some(RECEIPT)),
+            (concat!("'", env!("CARGO_MANIFEST_DIR"), "/src/orchestrator/testdata/receipt.png'"), Some(RECEIPT)),
+            (concat!("file://", env!("CARGO_MANIFEST_DIR"), "/src/orchestrator/testdata/receipt.png"), Some(RECEIPT)),
+            ("/tmp/missing-receipt.png", None),
+            ("Summarize receipt.png", None),
+            ("attach the receipt", None),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(attached_image(line), expected.map(PathBuf::from), "{}", line);
+        }
+        assert_eq!(unquote_path("/tmp/my\\ receipt.png"), "/tmp/my receipt.png");
+    }
+
+    #[tokio::test]
+    async fn test_attached_images_go_with_the_next_task() -> Result<()> {
+        let factory = Arc::new(StepFactory::new());
+        let runner = TerminalRunner::with_prompt(factory.clone(), Arc::new(ScriptedPrompt { choices: Mutex::new(Vec::new()) }));
+        let tasks = Arc::new(Mutex::new(VecDeque::from([
+            "attach image /tmp/missing-receipt.png",
+            concat!("attach image ", env!("CARGO_MANIFEST_DIR"), "/src/orchestrator/testdata/receipt.png"),
+            "What is the total on this receipt?",
+            "Find the menu",
+        ])));
+        let mut out = Vec::new();
+        runner.interactive(Box::new(ScriptedTasks(tasks)), &mut out).await?;
+
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("Could not attach the image: Failed to read image /tmp/missing-receipt.png"), "{}", out);
+        assert!(out.contains("Attached receipt.png, it will be sent with the next task"), "{}", out);
+        // 图片只随下一个任务交给规划
+        let providers = factory.providers();
+        assert_eq!(providers.len(), 2);
+        assert!(planning_request(&providers[0]).contains("Attached images: receipt.png"));
+        assert!(!planning_request(&providers[1]).contains("receipt.png"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_interactive_runs_tasks_until_exit() -> Result<()> {
+        let factory = flaky(1);
+        // 第一个任务组装失败后放弃，第二个任务正常完成
+        let prompt = Arc::new(ScriptedPrompt { choices: Mutex::new(vec![Some(1)]) });
+        let runner = TerminalRunner::with_prompt(factory.clone(), prompt);
+        let tasks = Arc::new(Mutex::new(VecDeque::from(["Find the menu", "  Find the menu ", "exit", "Book a table"])));
+        let mut out = Vec::new();
+        runner.interactive(Box::new(ScriptedTasks(tasks.clone())), &mut out).await?;
+        assert_eq!(factory.builds(), 2);
+        assert_eq!(*tasks.lock().unwrap(), ["Book a table"]);
+        let out = String::from_utf8(out)?;
+        assert!(out.contains("Run failed: chromedriver is not reachable"), "{}", out);
+        assert!(out.ends_with("\nThe menu has pizza.\n"), "{}", out);
+        Ok(())
+    }
+}
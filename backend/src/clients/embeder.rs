@@ -1,6 +1,7 @@
 use std::env;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
 use crate::define_module_client;
 use crate::common::ModuleClient;
 
@@ -11,7 +12,7 @@ use async_openai::{
 };
 
 use crate::{
-    clients::consts::{Embedding, EMBEDDING_MODEL}
+    clients::consts::{Embedding, EMBEDDING_DIMS, EMBEDDING_MODEL}
 };
 
 define_module_client! {
@@ -55,4 +56,25 @@ impl EmbederClient {
 
         Ok(embeddings)
     }
-}   
\ No newline at end of file
+}
+
+/// 把一段文字转成向量，计划库等检索功能通过它调用嵌入模型，测试中可以替换
+#[async_trait]
+pub trait TextEmbedder: Send + Sync {
+    async fn embed_text(&self, text: &str) -> Result<Embedding>;
+
+    /// 向量的维度，写入和检索前用它校验
+    fn dimension(&self) -> usize {
+        EMBEDDING_DIMS as usize
+    }
+}
+
+#[async_trait]
+impl TextEmbedder for EmbederClient {
+    async fn embed_text(&self, text: &str) -> Result<Embedding> {
+        self.embed(vec![text.to_string()])
+            .await?
+            .pop()
+            .ok_or_else(|| anyhow!("Embedding response is empty"))
+    }
+}
\ No newline at end of file
@@ -1,5 +1,10 @@
 use std::env;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use crate::define_module_client;
+use crate::orchestrator::message::{AssistantContent, LLMMessage, MultiModalContent, UserContent};
 use async_openai::{
     config::OpenAIConfig,
     Client,
@@ -22,4 +27,447 @@ define_module_client! {
             Default::default()
         )
     }
-}
\ No newline at end of file
+}
+
+/// 模型能力描述，决定上下文里能否携带图片等
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    pub vision: bool,
+    pub function_calling: bool,
+    pub json_output: bool,
+}
+
+impl Default for ModelInfo {
+    fn default() -> Self {
+        Self {
+            vision: true,
+            function_calling: true,
+            json_output: true,
+        }
+    }
+}
+
+/// 一次或多次模型调用的 token 用量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// 每 1000 token 的价格，用于估算花费
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelPricing {
+    pub prompt_per_1k: f64,
+    pub completion_per_1k: f64,
+}
+
+impl ModelPricing {
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        usage.prompt_tokens as f64 / 1000.0 * self.prompt_per_1k
+            + usage.completion_tokens as f64 / 1000.0 * self.completion_per_1k
+    }
+}
+
+/// 提供给模型调用的函数，parameters 为参数的 JSON Schema
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+/// 模型返回的函数调用，arguments 为 JSON 字符串
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    Stop,
+    Length,
+    ToolCalls,
+    ContentFilter,
+    #[serde(other)]
+    Other,
+}
+
+/// 一次模型调用的结果
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CreateResult {
+    pub content: String,
+    pub usage: TokenUsage,
+    pub tool_calls: Vec<ToolCall>,
+    pub finish_reason: Option<FinishReason>,
+}
+
+impl CreateResult {
+    pub fn tool_call(&self, name: &str) -> Option<&ToolCall> {
+        self.tool_calls.iter().find(|call| call.name == name)
+    }
+
+    /* 解析 chat completion 的响应体，支持 OpenAI（以及 DashScope 兼容模式）的
+    choices[0].message 和 DashScope 原生接口的 output.choices / output.text，
+    usage 的 input_tokens/output_tokens 按 prompt/completion 处理 */
+    pub fn from_response_json(value: &Value) -> Result<Self> {
+        let response: RawResponse = serde_json::from_value(value.clone())?;
+        let (choices, text, output_finish_reason) = match response.output {
+            Some(output) => (output.choices, output.text, output.finish_reason),
+            None => (response.choices, None, None),
+        };
+        let usage = response
+            .usage
+            .map(|usage| TokenUsage { prompt_tokens: usage.prompt_tokens, completion_tokens: usage.completion_tokens })
+            .unwrap_or_default();
+
+        let Some(choice) = choices.into_iter().next() else {
+            return Ok(Self {
+                content: text.ok_or_else(|| anyhow::anyhow!("The response has no choices"))?,
+                usage,
+                tool_calls: Vec::new(),
+                finish_reason: output_finish_reason,
+            });
+        };
+        let tool_calls = choice
+            .message
+            .tool_calls
+            .into_iter()
+            .map(|call| ToolCall { id: call.id, name: call.function.name, arguments: call.function.arguments })
+            .collect();
+        Ok(Self {
+            content: raw_content_text(choice.message.content),
+            usage,
+            tool_calls,
+            finish_reason: choice.finish_reason.or(output_finish_reason),
+        })
+    }
+}
+
+// content 可能为 null（只有工具调用时），多模态模型返回的是 [{"text": ...}] 数组
+fn raw_content_text(content: Option<Value>) -> String {
+    match content {
+        Some(Value::String(text)) => text,
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(|text| text.as_str()))
+            .collect::<Vec<_>>()
+            .join(""),
+        _ => String::new(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RawResponse {
+    #[serde(default)]
+    choices: Vec<RawChoice>,
+    output: Option<RawOutput>,
+    usage: Option<RawUsage>,
+}
+
+#[derive(Deserialize)]
+struct RawOutput {
+    #[serde(default)]
+    choices: Vec<RawChoice>,
+    text: Option<String>,
+    finish_reason: Option<FinishReason>,
+}
+
+#[derive(Deserialize)]
+struct RawChoice {
+    message: RawMessage,
+    finish_reason: Option<FinishReason>,
+}
+
+#[derive(Deserialize)]
+struct RawMessage {
+    content: Option<Value>,
+    #[serde(default)]
+    tool_calls: Vec<RawToolCall>,
+}
+
+#[derive(Deserialize)]
+struct RawToolCall {
+    #[serde(default)]
+    id: String,
+    function: RawFunction,
+}
+
+#[derive(Deserialize)]
+struct RawFunction {
+    name: String,
+    #[serde(default)]
+    arguments: String,
+}
+
+#[derive(Deserialize)]
+struct RawUsage {
+    #[serde(alias = "input_tokens")]
+    prompt_tokens: u64,
+    #[serde(alias = "output_tokens")]
+    completion_tokens: u64,
+}
+
+/// 请求体中单张图片 base64 之后默认最大的字节数，超过时改为质量更低的 JPEG
+pub const DEFAULT_MAX_IMAGE_BYTES: usize = 1_500_000;
+// 依次尝试的 JPEG 质量
+const JPEG_QUALITIES: [u8; 4] = [85, 70, 50, 30];
+
+/* 把消息转换成 OpenAI（以及 DashScope 兼容模式）chat completion 请求中的 messages：
+图片作为 image_url 的 base64 data URL；模型不支持图片时跳过图片只发送文字，
+压缩到 max_image_bytes 以内仍然过大的图片替换为占位文字 */
+pub fn request_messages(messages: &[LLMMessage], model_info: &ModelInfo, max_image_bytes: usize) -> Vec<Value> {
+    messages
+        .iter()
+        .map(|message| match message {
+            LLMMessage::System(system) => json!({ "role": "system", "content": system.content }),
+            LLMMessage::User(user) => match &user.content {
+                UserContent::String(text) => json!({ "role": "user", "content": text }),
+                UserContent::MultiModal(parts) if model_info.vision => {
+                    let content: Vec<Value> = parts
+                        .iter()
+                        .map(|part| match part {
+                            MultiModalContent::Text(text) => json!({ "type": "text", "text": text }),
+                            MultiModalContent::Image(bytes) => match image_data_url(bytes, max_image_bytes) {
+                                Some(url) => json!({ "type": "image_url", "image_url": { "url": url } }),
+                                None => json!({ "type": "text", "text": "[image omitted: too large]" }),
+                            },
+                        })
+                        .collect();
+                    json!({ "role": "user", "content": content })
+                }
+                UserContent::MultiModal(parts) => {
+                    let text: Vec<&str> = parts
+                        .iter()
+                        .filter_map(|part| match part {
+                            MultiModalContent::Text(text) => Some(text.as_str()),
+                            MultiModalContent::Image(_) => None,
+                        })
+                        .collect();
+                    json!({ "role": "user", "content": text.join("\n") })
+                }
+            },
+            LLMMessage::Assistant(assistant) => match &assistant.content {
+                AssistantContent::String(text) => json!({ "role": "assistant", "content": text }),
+                AssistantContent::FunctionCalls(calls) => json!({
+                    "role": "assistant",
+                    "content": Value::Null,
+                    "tool_calls": calls
+                        .iter()
+                        .map(|call| json!({
+                            "id": call.id,
+                            "type": "function",
+                            "function": { "name": call.name, "arguments": call.arguments },
+                        }))
+                        .collect::<Vec<_>>(),
+                }),
+            },
+            LLMMessage::Tool(tool) => json!({ "role": "tool", "tool_call_id": tool.call_id, "content": tool.content }),
+        })
+        .collect()
+}
+
+/// 图片的 base64 data URL；超过 max_bytes 时依次用更低的质量重新编码为 JPEG，都不行时为 None
+pub fn image_data_url(bytes: &[u8], max_bytes: usize) -> Option<String> {
+    let mime = match image::guess_format(bytes) {
+        Ok(image::ImageFormat::Jpeg) => "image/jpeg",
+        Ok(image::ImageFormat::Gif) => "image/gif",
+        Ok(image::ImageFormat::WebP) => "image/webp",
+        _ => "image/png",
+    };
+    let url = data_url(mime, bytes);
+    if url.len() <= max_bytes {
+        return Some(url);
+    }
+    let rgb = image::load_from_memory(bytes).ok()?.to_rgb8();
+    JPEG_QUALITIES.iter().find_map(|&quality| {
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, quality).encode_image(&rgb).ok()?;
+        Some(data_url("image/jpeg", &jpeg)).filter(|url| url.len() <= max_bytes)
+    })
+}
+
+fn data_url(mime: &str, bytes: &[u8]) -> String {
+    use base64::Engine as _;
+    format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+/// orchestrator 调用模型的统一接口，测试中可以换成脚本化的实现
+#[async_trait]
+pub trait ChatCompletionClient: Send + Sync {
+    fn model_info(&self) -> ModelInfo;
+
+    async fn create(&self, messages: &[LLMMessage]) -> Result<CreateResult>;
+
+    /// 允许模型调用 tools 中的函数；不支持函数调用的实现忽略 tools，只返回文字
+    async fn create_with_tools(&self, messages: &[LLMMessage], _tools: &[ToolSpec]) -> Result<CreateResult> {
+        self.create(messages).await
+    }
+
+    /* 流式调用，每收到一段文字（或函数调用参数的一段）调用一次 on_chunk；
+    不支持流式的实现把完整回复作为一段 */
+    async fn create_stream(
+        &self,
+        messages: &[LLMMessage],
+        tools: &[ToolSpec],
+        on_chunk: &(dyn for<'a> Fn(&'a str) + Send + Sync),
+    ) -> Result<CreateResult> {
+        let result = self.create_with_tools(messages, tools).await?;
+        on_chunk(&result.content);
+        for call in &result.tool_calls {
+            on_chunk(&call.arguments);
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::message::{SystemMessage, UserMessage};
+
+    #[test]
+    fn test_openai_tool_call_response() -> Result<()> {
+        let response = json!({
+            "id": "chatcmpl-1",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "emit_plan", "arguments": "{\"steps\": []}" }
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": { "prompt_tokens": 120, "completion_tokens": 30, "total_tokens": 150 }
+        });
+
+        let result = CreateResult::from_response_json(&response)?;
+        assert_eq!(result.content, "");
+        assert_eq!(result.finish_reason, Some(FinishReason::ToolCalls));
+        assert_eq!(result.usage, TokenUsage { prompt_tokens: 120, completion_tokens: 30 });
+        let call = result.tool_call("emit_plan").expect("tool call");
+        assert_eq!(call.id, "call_1");
+        assert_eq!(call.arguments, "{\"steps\": []}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dashscope_native_responses() -> Result<()> {
+        let message = json!({
+            "output": {
+                "choices": [{
+                    "message": { "role": "assistant", "content": [{ "text": "Hello" }, { "text": " there" }] },
+                    "finish_reason": "stop"
+                }]
+            },
+            "usage": { "input_tokens": 10, "output_tokens": 2 }
+        });
+        let result = CreateResult::from_response_json(&message)?;
+        assert_eq!(result.content, "Hello there");
+        assert_eq!(result.finish_reason, Some(FinishReason::Stop));
+        assert_eq!(result.usage.total(), 12);
+        assert!(result.tool_calls.is_empty());
+
+        // result_format 为 text 时没有 choices
+        let text = json!({ "output": { "text": "Hi", "finish_reason": "length" }, "usage": { "input_tokens": 3, "output_tokens": 1 } });
+        let result = CreateResult::from_response_json(&text)?;
+        assert_eq!(result.content, "Hi");
+        assert_eq!(result.finish_reason, Some(FinishReason::Length));
+
+        assert!(CreateResult::from_response_json(&json!({ "output": {} })).is_err());
+        Ok(())
+    }
+
+    // tests/fixtures/llm 中记录的真实响应
+    fn recorded(name: &str) -> Result<CreateResult> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/llm").join(name);
+        CreateResult::from_response_json(&serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    #[test]
+    fn test_recorded_response_usage() -> Result<()> {
+        let result = recorded("openai_tool_call.json")?;
+        assert_eq!(result.usage, TokenUsage { prompt_tokens: 2312, completion_tokens: 41 });
+        assert_eq!(result.usage.total(), 2353);
+        assert_eq!(result.tool_call("visit_url").map(|call| call.id.as_str()), Some("call_Qm2xv8FJ3kL0pN4rS6tU7wY9"));
+
+        let result = recorded("dashscope_compatible.json")?;
+        assert_eq!(result.usage, TokenUsage { prompt_tokens: 1874, completion_tokens: 17 });
+        assert!(result.content.starts_with("The page lists three pricing plans"));
+
+        let result = recorded("dashscope_native.json")?;
+        assert_eq!(result.usage, TokenUsage { prompt_tokens: 956, completion_tokens: 12 });
+        assert_eq!(result.content, "I searched for the menu and found it.");
+        Ok(())
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        // 噪点图片，PNG 压缩不了多少
+        let img = image::RgbImage::from_fn(width, height, |x, y| {
+            let v = (x.wrapping_mul(7919) ^ y.wrapping_mul(104729)).wrapping_mul(2654435761) >> 8;
+            image::Rgb([v as u8, (v >> 8) as u8, (v >> 16) as u8])
+        });
+        let mut bytes = Vec::new();
+        image::DynamicImage::ImageRgb8(img)
+            .write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    fn page_prompt(image: Vec<u8>) -> Vec<LLMMessage> {
+        vec![
+            LLMMessage::System(SystemMessage::new("You control a browser.".to_string())),
+            LLMMessage::User(UserMessage::new(
+                UserContent::MultiModal(vec![MultiModalContent::Text("The page:".to_string()), MultiModalContent::Image(image)]),
+                "web_surfer".to_string(),
+            )),
+        ]
+    }
+
+    #[test]
+    fn test_request_messages_attach_images_for_vision_models() {
+        let messages = request_messages(&page_prompt(png(8, 8)), &ModelInfo::default(), DEFAULT_MAX_IMAGE_BYTES);
+        assert_eq!(messages[0], json!({ "role": "system", "content": "You control a browser." }));
+        let content = messages[1]["content"].as_array().unwrap();
+        assert_eq!(content[0], json!({ "type": "text", "text": "The page:" }));
+        assert_eq!(content[1]["type"], "image_url");
+        assert!(content[1]["image_url"]["url"].as_str().unwrap().starts_with("data:image/png;base64,"));
+    }
+
+    #[test]
+    fn test_request_messages_skip_images_for_text_only_models() {
+        let text_only = ModelInfo { vision: false, ..ModelInfo::default() };
+        let messages = request_messages(&page_prompt(png(8, 8)), &text_only, DEFAULT_MAX_IMAGE_BYTES);
+        assert_eq!(messages[1], json!({ "role": "user", "content": "The page:" }));
+    }
+
+    #[test]
+    fn test_large_images_are_reencoded_as_jpeg() {
+        let image = png(400, 400);
+        let original = data_url("image/png", &image).len();
+        let url = image_data_url(&image, original / 2).unwrap();
+        assert!(url.starts_with("data:image/jpeg;base64,") && url.len() <= original / 2);
+        // 压缩之后仍然过大时用占位文字代替
+        assert_eq!(image_data_url(&image, 100), None);
+        let messages = request_messages(&page_prompt(image), &ModelInfo::default(), 100);
+        assert_eq!(messages[1]["content"][1], json!({ "type": "text", "text": "[image omitted: too large]" }));
+    }
+}
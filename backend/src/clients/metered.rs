@@ -0,0 +1,88 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::time::Instant;
+
+use crate::clients::llm::{ChatCompletionClient, CreateResult, ModelInfo, ToolSpec};
+use crate::common::metrics::Metrics;
+use crate::orchestrator::message::LLMMessage;
+
+/// 记录调用次数、结果和耗时的模型客户端，provider 和 model 来自配置，作为指标的标签
+pub struct MeteredClient {
+    inner: Arc<dyn ChatCompletionClient>,
+    metrics: Arc<Metrics>,
+    provider: String,
+    model: String,
+}
+
+impl MeteredClient {
+    pub fn new(
+        inner: Arc<dyn ChatCompletionClient>,
+        metrics: Arc<Metrics>,
+        provider: impl Into<String>,
+        model: impl Into<String>,
+    ) -> Self {
+        Self { inner, metrics, provider: provider.into(), model: model.into() }
+    }
+
+    fn observe(&self, started: Instant, result: &Result<CreateResult>) {
+        self.metrics.llm_request(&self.provider, &self.model, result.is_ok(), started.elapsed());
+    }
+}
+
+#[async_trait]
+impl ChatCompletionClient for MeteredClient {
+    fn model_info(&self) -> ModelInfo {
+        self.inner.model_info()
+    }
+
+    async fn create(&self, messages: &[LLMMessage]) -> Result<CreateResult> {
+        let started = Instant::now();
+        let result = self.inner.create(messages).await;
+        self.observe(started, &result);
+        result
+    }
+
+    async fn create_with_tools(&self, messages: &[LLMMessage], tools: &[ToolSpec]) -> Result<CreateResult> {
+        let started = Instant::now();
+        let result = self.inner.create_with_tools(messages, tools).await;
+        self.observe(started, &result);
+        result
+    }
+
+    async fn create_stream(
+        &self,
+        messages: &[LLMMessage],
+        tools: &[ToolSpec],
+        on_chunk: &(dyn for<'a> Fn(&'a str) + Send + Sync),
+    ) -> Result<CreateResult> {
+        let started = Instant::now();
+        let result = self.inner.create_stream(messages, tools, on_chunk).await;
+        self.observe(started, &result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockProvider;
+
+    #[tokio::test]
+    async fn test_client_counts_outcomes() -> Result<()> {
+        let provider = MockProvider::new().respond("one").fail("upstream timeout").respond("two");
+        let metrics = Arc::new(Metrics::new());
+        let client = MeteredClient::new(Arc::new(provider), metrics.clone(), "dashscope", "qwen-max");
+
+        assert_eq!(client.create(&[]).await?.content, "one");
+        assert!(client.create_with_tools(&[], &[]).await.is_err());
+        assert_eq!(client.create_stream(&[], &[], &|_| {}).await?.content, "two");
+
+        let requests = |outcome: &str| metrics.llm_requests.with_label_values(&["dashscope", "qwen-max", outcome]).get();
+        assert_eq!(requests("ok"), 2);
+        assert_eq!(requests("error"), 1);
+        assert_eq!(metrics.llm_latency.with_label_values(&["dashscope", "qwen-max"]).get_sample_count(), 3);
+        Ok(())
+    }
+}
@@ -2,9 +2,19 @@ mod postgres;
 mod embeder;
 pub mod consts;
 pub mod llm;
+pub mod metered;
+pub mod models;
 pub mod py_client;
+pub mod rate_limit;
+pub mod retry;
+pub mod stream;
 
 pub use postgres::{PostgresClient, PgvectorClient};
-pub use embeder::EmbederClient;
-pub use llm::LlmClient;
+pub use embeder::{EmbederClient, TextEmbedder};
+pub use llm::{ChatCompletionClient, CreateResult, FinishReason, LlmClient, ModelInfo, ModelPricing, TokenUsage, ToolCall, ToolSpec};
+pub use metered::MeteredClient;
+pub use models::{HttpChatClient, ModelProfile, ModelRegistry, ModelRole};
+pub use rate_limit::{RateLimitedClient, RateLimiter, UsageMeter};
+pub use retry::{LlmHttpError, LlmRetryPolicy, RetryingClient};
+pub use stream::StreamAssembler;
 pub use consts::*;
\ No newline at end of file
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use crate::clients::llm::{request_messages, ChatCompletionClient, CreateResult, ModelInfo, ToolSpec, DEFAULT_MAX_IMAGE_BYTES};
+use crate::clients::retry::{post_chat_completion, LlmRetryPolicy, RetryingClient};
+use crate::clients::stream::stream_chat_completion;
+use crate::config::AppConfig;
+use crate::orchestrator::message::LLMMessage;
+
+/// [llm] 没有设置 model 时使用的模型
+pub const DEFAULT_MODEL: &str = "qwen-max";
+/// [llm] 没有设置 base_url 时使用的服务地址
+pub const DEFAULT_BASE_URL: &str = "https://dashscope.aliyuncs.com/compatible-mode/v1";
+
+/// 调用模型的角色，[models.<role>] 可以为每个角色指定不同的模型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModelRole {
+    /// 规划和 ledger 的 JSON 调用，文本模型即可
+    Orchestrator,
+    /// 需要看截图的 web_surfer
+    WebAgent,
+    PlanAgent,
+    /// answer_question / summarize_page
+    Summarizer,
+}
+
+impl ModelRole {
+    pub const ALL: [ModelRole; 4] = [ModelRole::Orchestrator, ModelRole::WebAgent, ModelRole::PlanAgent, ModelRole::Summarizer];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModelRole::Orchestrator => "orchestrator",
+            ModelRole::WebAgent => "web_agent",
+            ModelRole::PlanAgent => "plan_agent",
+            ModelRole::Summarizer => "summarizer",
+        }
+    }
+}
+
+/// 一个角色实际使用的模型和服务
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelProfile {
+    pub model: String,
+    pub base_url: String,
+    pub api_key: String,
+    pub info: ModelInfo,
+    /// [llm] temperature，未设置时不发送
+    pub temperature: Option<f64>,
+}
+
+impl Default for ModelProfile {
+    fn default() -> Self {
+        Self {
+            model: DEFAULT_MODEL.to_string(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            api_key: String::new(),
+            info: ModelInfo::default(),
+            temperature: None,
+        }
+    }
+}
+
+/* 按角色取模型：[models.<role>] 中设置的项优先，没有设置的项回退到 [llm]，
+所以只配置了 [llm] 的旧配置所有角色仍然使用同一个模型 */
+#[derive(Debug, Clone)]
+pub struct ModelRegistry {
+    default: ModelProfile,
+    profiles: HashMap<ModelRole, ModelProfile>,
+    retry: LlmRetryPolicy,
+    max_image_bytes: usize,
+}
+
+impl ModelRegistry {
+    pub fn from_config(config: &AppConfig) -> Self {
+        let default = ModelProfile {
+            model: config.llm.model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+            base_url: config.llm.base_url.clone().unwrap_or_else(|| DEFAULT_BASE_URL.to_string()),
+            api_key: config.llm.api_key.clone().unwrap_or_default(),
+            info: ModelInfo::default(),
+            temperature: config.llm.temperature,
+        };
+        let profiles = ModelRole::ALL
+            .iter()
+            .filter_map(|role| {
+                let settings = config.models.get(role.as_str())?;
+                let profile = ModelProfile {
+                    model: settings.model.clone().unwrap_or_else(|| default.model.clone()),
+                    base_url: settings.base_url.clone().unwrap_or_else(|| default.base_url.clone()),
+                    api_key: settings.api_key.clone().unwrap_or_else(|| default.api_key.clone()),
+                    info: ModelInfo { vision: settings.vision.unwrap_or(default.info.vision), ..default.info },
+                    temperature: default.temperature,
+                };
+                Some((*role, profile))
+            })
+            .collect();
+        Self {
+            default,
+            profiles,
+            retry: LlmRetryPolicy::from_settings(&config.llm),
+            max_image_bytes: config.llm.max_image_bytes.unwrap_or(DEFAULT_MAX_IMAGE_BYTES),
+        }
+    }
+
+    pub fn profile(&self, role: ModelRole) -> &ModelProfile {
+        self.profiles.get(&role).unwrap_or(&self.default)
+    }
+
+    /// 各角色使用的模型和服务，不含 api_key；启动时显示，也写入 --output json 的运行结果
+    pub fn summary(&self, provider: Option<&str>) -> Value {
+        let models: serde_json::Map<String, Value> =
+            ModelRole::ALL.iter().map(|role| (role.as_str().to_string(), json!(self.profile(*role).model))).collect();
+        json!({
+            "provider": provider,
+            "base_url": self.default.base_url,
+            "temperature": self.default.temperature,
+            "models": models,
+        })
+    }
+
+    /// 角色使用的客户端，失败时按 [llm] 的重试配置重试
+    pub fn client(&self, role: ModelRole) -> Arc<dyn ChatCompletionClient> {
+        let client = HttpChatClient::new(self.profile(role).clone(), self.max_image_bytes);
+        Arc::new(RetryingClient::new(Arc::new(client), self.retry.clone()))
+    }
+}
+
+/// 通过兼容 OpenAI 的 chat/completions 接口调用 profile 中的模型
+#[derive(Debug, Clone)]
+pub struct HttpChatClient {
+    http: reqwest::Client,
+    profile: ModelProfile,
+    max_image_bytes: usize,
+}
+
+impl HttpChatClient {
+    pub fn new(profile: ModelProfile, max_image_bytes: usize) -> Self {
+        Self { http: reqwest::Client::new(), profile, max_image_bytes }
+    }
+
+    pub fn profile(&self) -> &ModelProfile {
+        &self.profile
+    }
+
+    // -vv 时输出的请求和响应摘要，不包含消息内容
+    fn trace_request(&self, messages: &[LLMMessage], tools: &[ToolSpec]) {
+        tracing::trace!("LLM request to {}: {} messages, {} tools", self.profile.model, messages.len(), tools.len());
+    }
+
+    fn trace_response(&self, result: &CreateResult) {
+        tracing::trace!(
+            "LLM response from {}: {} chars, {} tool calls, {} prompt + {} completion tokens, finish reason {:?}",
+            self.profile.model,
+            result.content.chars().count(),
+            result.tool_calls.len(),
+            result.usage.prompt_tokens,
+            result.usage.completion_tokens,
+            result.finish_reason
+        );
+    }
+
+    /// 请求体：模型、按模型能力转换的消息和可以调用的函数
+    pub fn request_body(&self, messages: &[LLMMessage], tools: &[ToolSpec]) -> Value {
+        let mut body = json!({
+            "model": self.profile.model,
+            "messages": request_messages(messages, &self.profile.info, self.max_image_bytes),
+        });
+        if let Some(temperature) = self.profile.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if !tools.is_empty() {
+            body["tools"] = tools
+                .iter()
+                .map(|tool| json!({
+                    "type": "function",
+                    "function": { "name": tool.name, "description": tool.description, "parameters": tool.parameters },
+                }))
+                .collect();
+        }
+        body
+    }
+}
+
+#[async_trait]
+impl ChatCompletionClient for HttpChatClient {
+    fn model_info(&self) -> ModelInfo {
+        self.profile.info
+    }
+
+    async fn create(&self, messages: &[LLMMessage]) -> Result<CreateResult> {
+        self.create_with_tools(messages, &[]).await
+    }
+
+    async fn create_with_tools(&self, messages: &[LLMMessage], tools: &[ToolSpec]) -> Result<CreateResult> {
+        let body = self.request_body(messages, tools);
+        self.trace_request(messages, tools);
+        let result = post_chat_completion(&self.http, &self.profile.base_url, &self.profile.api_key, &body).await?;
+        self.trace_response(&result);
+        Ok(result)
+    }
+
+    // 函数调用在流结束后才完整，调用方拿到 CreateResult 之后再执行
+    async fn create_stream(
+        &self,
+        messages: &[LLMMessage],
+        tools: &[ToolSpec],
+        on_chunk: &(dyn for<'a> Fn(&'a str) + Send + Sync),
+    ) -> Result<CreateResult> {
+        let body = self.request_body(messages, tools);
+        self.trace_request(messages, tools);
+        let result = stream_chat_completion(&self.http, &self.profile.base_url, &self.profile.api_key, &body, on_chunk).await?;
+        self.trace_response(&result);
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use axum::routing::post;
+    use axum::Json;
+
+    use crate::config::ModelSettings;
+    use crate::orchestrator::message::{UserContent, UserMessage};
+
+    // 记录请求体的模拟接口
+    async fn mock_api() -> (String, Arc<Mutex<Vec<Value>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let app = axum::Router::new().route(
+            "/chat/completions",
+            post(move |Json(body): Json<Value>| {
+                recorded.lock().unwrap().push(body);
+                async {
+                    Json(json!({
+                        "choices": [{ "message": { "role": "assistant", "content": "ok" }, "finish_reason": "stop" }],
+                        "usage": { "prompt_tokens": 3, "completion_tokens": 1 }
+                    }))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (base_url, requests)
+    }
+
+    fn model(name: &str) -> ModelSettings {
+        ModelSettings { model: Some(name.to_string()), ..ModelSettings::default() }
+    }
+
+    #[tokio::test]
+    async fn test_each_role_sends_its_model() -> Result<()> {
+        let (base_url, requests) = mock_api().await;
+        let mut config = AppConfig::default();
+        config.llm.base_url = Some(base_url);
+        config.llm.model = Some("qwen-plus".to_string());
+        config.models.insert("orchestrator".to_string(), model("qwen-turbo"));
+        config.models.insert("web_agent".to_string(), ModelSettings { vision: Some(true), ..model("qwen-vl-max") });
+        let registry = ModelRegistry::from_config(&config);
+
+        let prompt = [LLMMessage::User(UserMessage::new(UserContent::String("Hi".to_string()), "user".to_string()))];
+        for role in ModelRole::ALL {
+            registry.client(role).create(&prompt).await?;
+        }
+        let models: Vec<String> = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|body| body["model"].as_str().unwrap_or_default().to_string())
+            .collect();
+        // 没有单独配置的角色回退到 [llm] 的模型
+        assert_eq!(models, ["qwen-turbo", "qwen-vl-max", "qwen-plus", "qwen-plus"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_roles_fall_back_to_the_global_model() {
+        let registry = ModelRegistry::from_config(&AppConfig::default());
+        for role in ModelRole::ALL {
+            assert_eq!(registry.profile(role), &ModelProfile::default());
+        }
+
+        let mut config = AppConfig::default();
+        config.llm.api_key = Some("sk-global".to_string());
+        config.models.insert("orchestrator".to_string(), ModelSettings { vision: Some(false), ..model("qwen-turbo") });
+        let registry = ModelRegistry::from_config(&config);
+        let orchestrator = registry.profile(ModelRole::Orchestrator);
+        assert_eq!(orchestrator.api_key, "sk-global");
+        assert!(!orchestrator.info.vision);
+        assert!(registry.profile(ModelRole::WebAgent).info.vision);
+    }
+
+    #[test]
+    fn test_request_body_includes_tools() {
+        let client = HttpChatClient::new(ModelProfile { model: "qwen-vl-max".to_string(), ..ModelProfile::default() }, DEFAULT_MAX_IMAGE_BYTES);
+        let tool = ToolSpec { name: "click".to_string(), description: "Click".to_string(), parameters: json!({ "type": "object" }) };
+        let body = client.request_body(&[], &[tool]);
+        assert_eq!(body["model"], "qwen-vl-max");
+        assert_eq!(body["tools"][0]["function"]["name"], "click");
+        assert!(client.request_body(&[], &[]).get("tools").is_none());
+        assert!(body.get("temperature").is_none());
+    }
+
+    #[test]
+    fn test_planner_model_and_temperature() {
+        let mut config = AppConfig::default();
+        config.llm.model = Some("gpt-4o".to_string());
+        config.llm.temperature = Some(0.2);
+        config.llm.api_key = Some("sk-secret".to_string());
+        config.models.insert("orchestrator".to_string(), model("o3-mini"));
+        let registry = ModelRegistry::from_config(&config);
+
+        let planner = HttpChatClient::new(registry.profile(ModelRole::Orchestrator).clone(), DEFAULT_MAX_IMAGE_BYTES);
+        let body = planner.request_body(&[], &[]);
+        assert_eq!((body["model"].as_str(), body["temperature"].as_f64()), (Some("o3-mini"), Some(0.2)));
+
+        let summary = registry.summary(Some("openai"));
+        assert_eq!(summary["models"]["orchestrator"], "o3-mini");
+        assert_eq!(summary["models"]["web_agent"], "gpt-4o");
+        assert_eq!(summary["provider"], "openai");
+        assert!(!summary.to_string().contains("sk-secret"));
+    }
+}
@@ -0,0 +1,371 @@
+/* 与 Python 端工具通信的客户端。PyClient 通过换行分隔的 JSON-RPC 2.0 调用一个 Python
+工作进程（由这里启动的子进程，或者已经在监听的 socket），请求带 id，可以同时有多个调用在等待响应。
+工作进程退出时，等待中的调用全部以错误返回，下一次调用时自动重启 */
+pub mod pdf_service;
+pub mod protocol;
+
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+use tokio::sync::{mpsc, oneshot};
+
+pub use pdf_service::PdfServiceClient;
+pub use protocol::{RpcError, RpcRequest, RpcResponse};
+
+const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 工作进程的来源
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PyWorker {
+    /// 启动子进程，请求写入 stdin，响应从 stdout 读取，stderr 记入日志
+    Process { program: String, args: Vec<String> },
+    /// 连接已经在运行的工作进程，例如 127.0.0.1:8765
+    Socket { addr: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PyClientConfig {
+    pub worker: PyWorker,
+    /// 单次调用等待响应的时间
+    pub call_timeout: Duration,
+}
+
+impl PyClientConfig {
+    pub fn new(worker: PyWorker) -> Self {
+        Self { worker, call_timeout: DEFAULT_CALL_TIMEOUT }
+    }
+}
+
+/// 工作进程提供的一个方法：名称和参数、返回值的类型
+pub trait PyMethod {
+    const NAME: &'static str;
+    type Params: Serialize + Sync;
+    type Output: DeserializeOwned;
+}
+
+/// 用 readability 提取网页正文，markitdown 不可用时作为后备
+pub struct ReadabilityExtract;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReadabilityRequest {
+    pub html: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReadableText {
+    pub title: String,
+    pub text: String,
+}
+
+impl PyMethod for ReadabilityExtract {
+    const NAME: &'static str = "readability_extract";
+    type Params = ReadabilityRequest;
+    type Output = ReadableText;
+}
+
+type PendingCalls = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Value>>>>>;
+
+// 与一个工作进程的连接，reader / writer 各由一个任务负责
+struct Connection {
+    lines: mpsc::UnboundedSender<String>,
+    pending: PendingCalls,
+    alive: Arc<AtomicBool>,
+    // 连接被替换或 PyClient 释放时结束子进程
+    _child: Option<Child>,
+}
+
+impl Connection {
+    fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::SeqCst)
+    }
+}
+
+struct Inner {
+    config: PyClientConfig,
+    next_id: AtomicU64,
+    restarts: AtomicU64,
+    connection: tokio::sync::Mutex<Option<Arc<Connection>>>,
+}
+
+#[derive(Clone)]
+pub struct PyClient {
+    inner: Arc<Inner>,
+}
+
+impl std::fmt::Debug for PyClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PyClient").field("worker", &self.inner.config.worker).finish()
+    }
+}
+
+impl PyClient {
+    /// 工作进程在第一次调用时才启动
+    pub fn new(config: PyClientConfig) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                config,
+                next_id: AtomicU64::new(1),
+                restarts: AtomicU64::new(0),
+                connection: tokio::sync::Mutex::new(None),
+            }),
+        }
+    }
+
+    /// 工作进程退出后被重启的次数
+    pub fn restarts(&self) -> u64 {
+        self.inner.restarts.load(Ordering::SeqCst)
+    }
+
+    pub async fn invoke<M: PyMethod>(&self, params: &M::Params) -> Result<M::Output> {
+        self.call(M::NAME, params).await
+    }
+
+    pub async fn call<Req: Serialize + ?Sized, Resp: DeserializeOwned>(&self, method: &str, params: &Req) -> Result<Resp> {
+        self.call_with_timeout(method, params, self.inner.config.call_timeout).await
+    }
+
+    /* 超时后不再等待这个调用的响应，工作进程之后返回的结果会被丢弃；
+    工作进程返回的错误为 RpcError，可以用 downcast_ref 取出错误码 */
+    pub async fn call_with_timeout<Req: Serialize + ?Sized, Resp: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &Req,
+        timeout: Duration,
+    ) -> Result<Resp> {
+        let params = serde_json::to_value(params)?;
+        let connection = self.connection().await?;
+        let id = self.inner.next_id.fetch_add(1, Ordering::SeqCst);
+        let line = serde_json::to_string(&RpcRequest::new(id, method, params))?;
+
+        let (tx, rx) = oneshot::channel();
+        {
+            // 与 reader 退出时的清理互斥，连接已经断开时不再登记
+            let mut pending = connection.pending.lock().unwrap();
+            if !connection.is_alive() {
+                return Err(anyhow!("Python worker exited before {} could be sent", method));
+            }
+            pending.insert(id, tx);
+        }
+        if connection.lines.send(line).is_err() {
+            connection.pending.lock().unwrap().remove(&id);
+            return Err(anyhow!("Python worker exited before {} could be sent", method));
+        }
+
+        let value = match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(result)) => result?,
+            Ok(Err(_)) => return Err(anyhow!("Python worker dropped the call to {}", method)),
+            Err(_) => {
+                connection.pending.lock().unwrap().remove(&id);
+                return Err(anyhow!("Python worker call {} timed out after {:?}", method, timeout));
+            }
+        };
+        serde_json::from_value(value).with_context(|| format!("Unexpected result from Python method {}", method))
+    }
+
+    pub async fn readability_extract(&self, html: &str) -> Result<ReadableText> {
+        self.invoke::<ReadabilityExtract>(&ReadabilityRequest { html: html.to_string() }).await
+    }
+
+    // 当前的连接；还没有连接或工作进程已经退出时重新启动
+    async fn connection(&self) -> Result<Arc<Connection>> {
+        let mut slot = self.inner.connection.lock().await;
+        if let Some(connection) = slot.as_ref() {
+            if connection.is_alive() {
+                return Ok(connection.clone());
+            }
+            self.inner.restarts.fetch_add(1, Ordering::SeqCst);
+            tracing::warn!("Python worker exited, restarting {:?}", self.inner.config.worker);
+        }
+        let connection = Arc::new(connect(&self.inner.config.worker).await?);
+        *slot = Some(connection.clone());
+        Ok(connection)
+    }
+}
+
+async fn connect(worker: &PyWorker) -> Result<Connection> {
+    match worker {
+        PyWorker::Process { program, args } => {
+            let mut child = Command::new(program)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .with_context(|| format!("Failed to start Python worker {}", program))?;
+            let stdin = child.stdin.take().expect("stdin is piped");
+            let stdout = child.stdout.take().expect("stdout is piped");
+            let stderr = child.stderr.take().expect("stderr is piped");
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    tracing::warn!("Python worker: {}", line);
+                }
+            });
+            Ok(start_connection(stdout, stdin, Some(child)))
+        }
+        PyWorker::Socket { addr } => {
+            let stream = TcpStream::connect(addr)
+                .await
+                .with_context(|| format!("Failed to connect to Python worker at {}", addr))?;
+            let (reader, writer) = stream.into_split();
+            Ok(start_connection(reader, writer, None))
+        }
+    }
+}
+
+fn start_connection(
+    reader: impl AsyncRead + Unpin + Send + 'static,
+    mut writer: impl AsyncWrite + Unpin + Send + 'static,
+    child: Option<Child>,
+) -> Connection {
+    let pending: PendingCalls = Arc::new(Mutex::new(HashMap::new()));
+    let alive = Arc::new(AtomicBool::new(true));
+    let (lines, mut outgoing) = mpsc::unbounded_channel::<String>();
+
+    let (writer_pending, writer_alive) = (pending.clone(), alive.clone());
+    tokio::spawn(async move {
+        while let Some(line) = outgoing.recv().await {
+            let written = async {
+                writer.write_all(line.as_bytes()).await?;
+                writer.write_all(b"\n").await?;
+                writer.flush().await
+            };
+            if let Err(e) = written.await {
+                fail_pending(&writer_pending, &writer_alive, &format!("Failed to write to Python worker: {}", e));
+                break;
+            }
+        }
+    });
+
+    let (reader_pending, reader_alive) = (pending.clone(), alive.clone());
+    tokio::spawn(async move {
+        let mut responses = BufReader::new(reader).lines();
+        let reason = loop {
+            match responses.next_line().await {
+                Ok(Some(line)) if line.trim().is_empty() => continue,
+                Ok(Some(line)) => dispatch_response(&reader_pending, &line),
+                Ok(None) => break "Python worker exited".to_string(),
+                Err(e) => break format!("Failed to read from Python worker: {}", e),
+            }
+        };
+        fail_pending(&reader_pending, &reader_alive, &reason);
+    });
+
+    Connection { lines, pending, alive, _child: child }
+}
+
+fn dispatch_response(pending: &PendingCalls, line: &str) {
+    let response: RpcResponse = match serde_json::from_str(line) {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::warn!("Ignoring malformed line from Python worker ({}): {}", e, line);
+            return;
+        }
+    };
+    let Some(id) = response.id else {
+        tracing::warn!("Python worker reported an error without a request id: {:?}", response.error);
+        return;
+    };
+    // 已经超时的调用不在 pending 中，结果直接丢弃
+    let Some(tx) = pending.lock().unwrap().remove(&id) else {
+        return;
+    };
+    let result = match (response.result, response.error) {
+        (_, Some(error)) => Err(anyhow::Error::new(error)),
+        (Some(result), None) => Ok(result),
+        (None, None) => Ok(Value::Null),
+    };
+    let _ = tx.send(result);
+}
+
+// 连接断开：标记为不可用，等待中的调用全部以 reason 返回
+fn fail_pending(pending: &PendingCalls, alive: &AtomicBool, reason: &str) {
+    let mut pending = pending.lock().unwrap();
+    alive.store(false, Ordering::SeqCst);
+    for (_, tx) in pending.drain() {
+        let _ = tx.send(Err(anyhow!("{}", reason)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // 需要 PY_WORKER_PYTHON（python3 的路径），未设置时跳过
+    fn reference_worker() -> Option<PyClient> {
+        let python = std::env::var("PY_WORKER_PYTHON").ok()?;
+        let script = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/py_worker.py");
+        let mut config = PyClientConfig::new(PyWorker::Process { program: python, args: vec![script.to_string()] });
+        config.call_timeout = Duration::from_secs(10);
+        Some(PyClient::new(config))
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_and_concurrency() -> Result<()> {
+        let Some(client) = reference_worker() else {
+            return Ok(());
+        };
+        let echoed: Value = client.call("echo", &json!({ "value": [1, 2, 3] })).await?;
+        assert_eq!(echoed, json!({ "value": [1, 2, 3] }));
+
+        // 先发出的慢调用后返回，响应按 id 对应
+        let (slow_params, fast_params) = (json!({ "seconds": 0.5, "reply": "slow" }), json!({ "seconds": 0.0, "reply": "fast" }));
+        let (slow, fast) = tokio::join!(
+            client.call::<_, String>("sleep", &slow_params),
+            client.call::<_, String>("sleep", &fast_params),
+        );
+        assert_eq!((slow?, fast?), ("slow".to_string(), "fast".to_string()));
+
+        let page = client
+            .readability_extract("<html><head><title>Menu</title><script>var x;</script></head><body><p>Soup</p><p>Bread</p></body></html>")
+            .await?;
+        assert_eq!(page.title, "Menu");
+        assert!(page.text.contains("Soup") && page.text.contains("Bread"));
+        assert!(!page.text.contains("var x"));
+
+        let error = client.call::<_, Value>("no_such_method", &json!({})).await.unwrap_err();
+        assert_eq!(error.downcast_ref::<RpcError>().map(|e| e.code), Some(protocol::METHOD_NOT_FOUND));
+
+        let error = client
+            .call_with_timeout::<_, Value>("sleep", &json!({ "seconds": 2.0 }), Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("timed out"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_crash_fails_pending_calls_and_restarts() -> Result<()> {
+        let Some(client) = reference_worker() else {
+            return Ok(());
+        };
+        assert_eq!(client.call::<_, String>("echo", &"before").await?, "before");
+
+        let params = json!({ "seconds": 5.0, "reply": "never" });
+        let waiting = client.call::<_, String>("sleep", &params);
+        let crash = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            client.call::<_, Value>("crash", &json!({})).await
+        };
+        let (waiting, crash) = tokio::join!(waiting, crash);
+        assert!(waiting.unwrap_err().to_string().contains("Python worker exited"));
+        assert!(crash.is_err());
+
+        assert_eq!(client.call::<_, String>("echo", &"after").await?, "after");
+        assert_eq!(client.restarts(), 1);
+        Ok(())
+    }
+}
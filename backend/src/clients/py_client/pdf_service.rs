@@ -2,12 +2,13 @@ use std::time::Duration;
 
 use anyhow::{Result, anyhow};
 
-pub struct PyClient {
+/// python-service 中 PDF 服务的 HTTP 客户端
+pub struct PdfServiceClient {
     base_url: String,
     client: reqwest::Client,
 }
 
-impl PyClient {
+impl PdfServiceClient {
     pub fn new(base_url: &str) -> Self {
         let base_url = base_url.replace("localhost", "127.0.0.1");
         // 禁用系统代理以避免在沙盒环境中访问系统配置失败
@@ -55,8 +56,9 @@ mod tests {
     use super::*;
 
     #[tokio::test]
+    #[ignore = "requires the PDF service on localhost:8000"]
     async fn test_load_pdf() {
-        let client = PyClient::new("http://localhost:8000");
+        let client = PdfServiceClient::new("http://localhost:8000");
         let pages = client.load_pdf("test.pdf").await.unwrap();
         println!("Pages: {:?}", pages);
     }
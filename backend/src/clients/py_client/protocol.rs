@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+/// 一行一个的 JSON-RPC 2.0 请求
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    pub id: u64,
+    pub method: String,
+    pub params: Value,
+}
+
+impl RpcRequest {
+    pub fn new(id: u64, method: &str, params: Value) -> Self {
+        Self { jsonrpc: JSONRPC_VERSION.to_string(), id, method: method.to_string(), params }
+    }
+}
+
+/// 工作进程的响应，result 和 error 只有一个；无法解析的请求 id 为 null
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+/// 工作进程返回的错误，调用方可以从 anyhow::Error 中 downcast 出来
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, thiserror::Error)]
+#[error("Python worker error {code}: {message}")]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+// JSON-RPC 2.0 规定的错误码
+pub const PARSE_ERROR: i64 = -32700;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_wire_format() -> anyhow::Result<()> {
+        let request = RpcRequest::new(7, "readability_extract", json!({ "html": "<p>Hi</p>" }));
+        assert_eq!(
+            serde_json::to_value(&request)?,
+            json!({ "jsonrpc": "2.0", "id": 7, "method": "readability_extract", "params": { "html": "<p>Hi</p>" } })
+        );
+
+        let ok: RpcResponse = serde_json::from_str(r#"{"jsonrpc": "2.0", "id": 7, "result": {"title": "Hi"}}"#)?;
+        assert_eq!(ok.id, Some(7));
+        assert_eq!(ok.result, Some(json!({ "title": "Hi" })));
+        assert!(ok.error.is_none());
+
+        let failed: RpcResponse =
+            serde_json::from_str(r#"{"jsonrpc": "2.0", "id": null, "error": {"code": -32700, "message": "Parse error"}}"#)?;
+        assert_eq!(failed.id, None);
+        let error = failed.error.unwrap();
+        assert_eq!(error.code, PARSE_ERROR);
+        assert_eq!(error.to_string(), "Python worker error -32700: Parse error");
+        Ok(())
+    }
+}
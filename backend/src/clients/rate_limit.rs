@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::time::Instant;
+
+use crate::clients::llm::{ChatCompletionClient, CreateResult, ModelInfo, TokenUsage, ToolSpec};
+use crate::orchestrator::message::LLMMessage;
+
+/* 所有运行共用的模型调用限速：每个 key（用户）一个令牌桶，容量为每分钟的调用次数，
+按时间连续补充。per_minute 为 0 时不限速 */
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        Self { per_minute, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// 等到 key 的桶里有令牌并取走一个
+    pub async fn acquire(&self, key: &str) {
+        while let Some(wait) = self.try_acquire_at(key, Instant::now()) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // 取到令牌时返回 None，否则返回还需要等待的时间
+    fn try_acquire_at(&self, key: &str, now: Instant) -> Option<Duration> {
+        if self.per_minute == 0 {
+            return None;
+        }
+        let capacity = self.per_minute as f64;
+        let per_second = capacity / 60.0;
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: capacity, updated: now });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return None;
+        }
+        Some(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+    }
+}
+
+/// 按用户计量模型用量：调用前检查是否还有额度（超出时返回错误），调用后记录用量
+#[async_trait]
+pub trait UsageMeter: Send + Sync {
+    async fn check(&self) -> Result<()>;
+    async fn record(&self, usage: &TokenUsage) -> Result<()>;
+}
+
+/// 经过限速和计量的模型客户端，后端执行器为每次运行按用户包装 orchestrator 的模型
+pub struct RateLimitedClient {
+    inner: Arc<dyn ChatCompletionClient>,
+    limiter: Arc<RateLimiter>,
+    key: String,
+    meter: Option<Arc<dyn UsageMeter>>,
+}
+
+impl RateLimitedClient {
+    pub fn new(inner: Arc<dyn ChatCompletionClient>, limiter: Arc<RateLimiter>, key: impl Into<String>) -> Self {
+        Self { inner, limiter, key: key.into(), meter: None }
+    }
+
+    pub fn with_meter(mut self, meter: Arc<dyn UsageMeter>) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    async fn before_call(&self) -> Result<()> {
+        if let Some(meter) = &self.meter {
+            meter.check().await?;
+        }
+        self.limiter.acquire(&self.key).await;
+        Ok(())
+    }
+
+    // 记录失败不影响这次调用的结果
+    async fn after_call(&self, result: &CreateResult) {
+        if let Some(meter) = &self.meter {
+            if let Err(e) = meter.record(&result.usage).await {
+                tracing::warn!("Failed to record model usage for {}: {:?}", self.key, e);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ChatCompletionClient for RateLimitedClient {
+    fn model_info(&self) -> ModelInfo {
+        self.inner.model_info()
+    }
+
+    async fn create(&self, messages: &[LLMMessage]) -> Result<CreateResult> {
+        self.before_call().await?;
+        let result = self.inner.create(messages).await?;
+        self.after_call(&result).await;
+        Ok(result)
+    }
+
+    async fn create_with_tools(&self, messages: &[LLMMessage], tools: &[ToolSpec]) -> Result<CreateResult> {
+        self.before_call().await?;
+        let result = self.inner.create_with_tools(messages, tools).await?;
+        self.after_call(&result).await;
+        Ok(result)
+    }
+
+    async fn create_stream(
+        &self,
+        messages: &[LLMMessage],
+        tools: &[ToolSpec],
+        on_chunk: &(dyn for<'a> Fn(&'a str) + Send + Sync),
+    ) -> Result<CreateResult> {
+        self.before_call().await?;
+        let result = self.inner.create_stream(messages, tools, on_chunk).await?;
+        self.after_call(&result).await;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockProvider;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_buckets_refill_per_key() {
+        let limiter = RateLimiter::new(2);
+        let start = Instant::now();
+        assert_eq!(limiter.try_acquire_at("alice", start), None);
+        assert_eq!(limiter.try_acquire_at("alice", start), None);
+        let wait = limiter.try_acquire_at("alice", start).expect("the bucket should be empty");
+        assert_eq!(wait.as_secs(), 30);
+        // 其他用户不受影响
+        assert_eq!(limiter.try_acquire_at("bob", start), None);
+        assert_eq!(limiter.try_acquire_at("alice", start + Duration::from_secs(31)), None);
+
+        let unlimited = RateLimiter::new(0);
+        assert!((0..100).all(|_| unlimited.try_acquire_at("alice", start).is_none()));
+    }
+
+    // 额度为 budget 个 token，用完之后的调用被拒绝
+    struct BudgetMeter {
+        budget: u64,
+        used: Mutex<u64>,
+    }
+
+    #[async_trait]
+    impl UsageMeter for BudgetMeter {
+        async fn check(&self) -> Result<()> {
+            if *self.used.lock().unwrap() >= self.budget {
+                return Err(anyhow!("The daily token quota is exhausted"));
+            }
+            Ok(())
+        }
+
+        async fn record(&self, usage: &TokenUsage) -> Result<()> {
+            *self.used.lock().unwrap() += usage.total();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_meters_usage() -> Result<()> {
+        let provider = MockProvider::new()
+            .with_usage(TokenUsage { prompt_tokens: 6, completion_tokens: 4 })
+            .respond("one")
+            .respond("two")
+            .respond("three");
+        let meter = Arc::new(BudgetMeter { budget: 20, used: Mutex::new(0) });
+        let client = RateLimitedClient::new(Arc::new(provider), Arc::new(RateLimiter::new(60)), "alice")
+            .with_meter(meter.clone());
+
+        assert_eq!(client.create(&[]).await?.content, "one");
+        assert_eq!(client.create_with_tools(&[], &[]).await?.content, "two");
+        assert_eq!(*meter.used.lock().unwrap(), 20);
+        let error = client.create(&[]).await.unwrap_err();
+        assert!(error.to_string().contains("quota is exhausted"));
+        Ok(())
+    }
+}
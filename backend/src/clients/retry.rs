@@ -0,0 +1,371 @@
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::Value;
+use tokio::time::Instant;
+
+use crate::clients::llm::{ChatCompletionClient, CreateResult, ModelInfo, ToolSpec};
+use crate::config::LlmSettings;
+use crate::orchestrator::message::LLMMessage;
+
+/// 模型接口返回的 HTTP 错误，retry_after 来自 Retry-After 响应头
+#[derive(Debug, Clone, PartialEq)]
+pub struct LlmHttpError {
+    pub status: u16,
+    pub retry_after: Option<Duration>,
+    pub body: String,
+}
+
+impl std::fmt::Display for LlmHttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The model API returned HTTP {}: {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for LlmHttpError {}
+
+/// 模型调用的重试策略：指数退避加随机抖动，次数和总时间都有上限
+#[derive(Debug, Clone, PartialEq)]
+pub struct LlmRetryPolicy {
+    /// 包含第一次调用
+    pub max_attempts: usize,
+    pub initial_backoff: Duration,
+    pub multiplier: f64,
+    pub max_backoff: Duration,
+    /// 从第一次调用开始最多重试多久，超过之后不再等待
+    pub max_elapsed: Duration,
+    /// 在 [backoff/2, backoff] 之间随机等待，避免多个运行同时重试
+    pub jitter: bool,
+}
+
+impl Default for LlmRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            multiplier: 2.0,
+            max_backoff: Duration::from_secs(30),
+            max_elapsed: Duration::from_secs(120),
+            jitter: true,
+        }
+    }
+}
+
+impl LlmRetryPolicy {
+    /// 按 [llm] 配置（也可以用 MAGENTIC_LLM__RETRY_MAX_ATTEMPTS 等环境变量设置），未设置的项使用默认值
+    pub fn from_settings(settings: &LlmSettings) -> Self {
+        let default = Self::default();
+        Self {
+            max_attempts: settings.retry_max_attempts.unwrap_or(default.max_attempts),
+            initial_backoff: settings.retry_initial_backoff_ms.map(Duration::from_millis).unwrap_or(default.initial_backoff),
+            max_elapsed: settings.retry_max_elapsed_secs.map(Duration::from_secs).unwrap_or(default.max_elapsed),
+            ..default
+        }
+    }
+
+    pub fn no_retry() -> Self {
+        Self { max_attempts: 1, ..Self::default() }
+    }
+
+    // 第 attempt 次失败之后（从 1 开始）等待的时间，不含抖动
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(attempt.saturating_sub(1).min(32) as i32);
+        self.initial_backoff.mul_f64(factor).min(self.max_backoff)
+    }
+
+    fn jittered(&self, backoff: Duration) -> Duration {
+        if !self.jitter {
+            return backoff;
+        }
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        backoff.mul_f64(0.5 + random / 2.0)
+    }
+}
+
+/* 错误是否值得重试，值得时返回服务端要求的等待时间（如果有）。
+429、408 和 5xx 以及连接被重置、超时等网络错误重试；认证失败、参数错误等其他 4xx 不重试 */
+pub fn retry_hint(error: &anyhow::Error) -> Option<Option<Duration>> {
+    for cause in error.chain() {
+        if let Some(http) = cause.downcast_ref::<LlmHttpError>() {
+            let retryable = matches!(http.status, 408 | 429) || (500..600).contains(&http.status);
+            return retryable.then_some(http.retry_after);
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            if let Some(status) = e.status() {
+                let retryable = matches!(status.as_u16(), 408 | 429) || status.is_server_error();
+                return retryable.then_some(None);
+            }
+            if e.is_connect() || e.is_timeout() || e.is_request() {
+                return Some(None);
+            }
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            use std::io::ErrorKind::*;
+            if matches!(e.kind(), ConnectionReset | ConnectionAborted | ConnectionRefused | BrokenPipe | TimedOut | UnexpectedEof) {
+                return Some(None);
+            }
+        }
+    }
+    None
+}
+
+/// 按策略重复执行 operation，直到成功、遇到不可重试的错误、次数用完或超过 max_elapsed
+pub async fn with_retry<T, F, Fut>(policy: &LlmRetryPolicy, mut operation: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let started = Instant::now();
+    let max_attempts = policy.max_attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        let error = match operation().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        let Some(retry_after) = retry_hint(&error) else {
+            return Err(error);
+        };
+        if attempt >= max_attempts {
+            return Err(error.context(format!("The model call failed after {} attempts", attempt)));
+        }
+        // Retry-After 优先于退避时间
+        let wait = retry_after.unwrap_or_else(|| policy.jittered(policy.backoff(attempt)));
+        if started.elapsed() + wait > policy.max_elapsed {
+            return Err(error.context(format!(
+                "The model call failed after {} attempts and the retry budget of {}s is used up",
+                attempt,
+                policy.max_elapsed.as_secs_f64()
+            )));
+        }
+        tracing::warn!("Model call failed (attempt {}/{}), retrying in {:?}: {:#}", attempt, max_attempts, wait, error);
+        tokio::time::sleep(wait).await;
+        attempt += 1;
+    }
+}
+
+/// 调用兼容 OpenAI 的 chat/completions 接口；非 2xx 的响应返回 LlmHttpError，供 retry_hint 判断
+pub async fn post_chat_completion(http: &reqwest::Client, base_url: &str, api_key: &str, body: &Value) -> Result<CreateResult> {
+    let url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
+    let response = http.post(&url).bearer_auth(api_key).json(body).send().await?;
+    if !response.status().is_success() {
+        return Err(http_error(response).await.into());
+    }
+    CreateResult::from_response_json(&response.json::<Value>().await?)
+}
+
+/// 非 2xx 的响应，流式调用同样使用
+pub(crate) async fn http_error(response: reqwest::Response) -> LlmHttpError {
+    let status = response.status().as_u16();
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64);
+    let body = response.text().await.unwrap_or_default();
+    LlmHttpError { status, retry_after, body }
+}
+
+/// 调用失败时按 LlmRetryPolicy 重试的模型客户端
+pub struct RetryingClient {
+    inner: Arc<dyn ChatCompletionClient>,
+    policy: LlmRetryPolicy,
+}
+
+impl RetryingClient {
+    pub fn new(inner: Arc<dyn ChatCompletionClient>, policy: LlmRetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+}
+
+#[async_trait]
+impl ChatCompletionClient for RetryingClient {
+    fn model_info(&self) -> ModelInfo {
+        self.inner.model_info()
+    }
+
+    async fn create(&self, messages: &[LLMMessage]) -> Result<CreateResult> {
+        with_retry(&self.policy, || self.inner.create(messages)).await
+    }
+
+    async fn create_with_tools(&self, messages: &[LLMMessage], tools: &[ToolSpec]) -> Result<CreateResult> {
+        with_retry(&self.policy, || self.inner.create_with_tools(messages, tools)).await
+    }
+
+    // 已经输出了部分内容之后不再重试，否则调用方会收到重复的片段
+    async fn create_stream(
+        &self,
+        messages: &[LLMMessage],
+        tools: &[ToolSpec],
+        on_chunk: &(dyn for<'a> Fn(&'a str) + Send + Sync),
+    ) -> Result<CreateResult> {
+        let streamed = AtomicBool::new(false);
+        let forward = |chunk: &str| {
+            streamed.store(true, Ordering::SeqCst);
+            on_chunk(chunk);
+        };
+        let mut attempts = 0;
+        with_retry(&self.policy, || {
+            attempts += 1;
+            let streamed = &streamed;
+            let forward = &forward;
+            async move {
+                if attempts > 1 && streamed.load(Ordering::SeqCst) {
+                    return Err(anyhow::anyhow!("The model stream failed after output had started"));
+                }
+                self.inner.create_stream(messages, tools, forward).await
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    use axum::http::{header, StatusCode};
+    use axum::response::IntoResponse;
+    use axum::routing::post;
+
+    fn fast_policy(max_attempts: usize) -> LlmRetryPolicy {
+        LlmRetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(40),
+            jitter: false,
+            ..LlmRetryPolicy::default()
+        }
+    }
+
+    /* 本地的模拟接口：按顺序返回 responses 中的状态码（用完之后返回最后一个），
+    200 时返回一个正常的 chat completion；记录收到的请求数 */
+    async fn mock_api(responses: Vec<(u16, Option<&'static str>)>) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let app = axum::Router::new().route(
+            "/chat/completions",
+            post(move || {
+                let index = counter.fetch_add(1, Ordering::SeqCst).min(responses.len() - 1);
+                let (status, retry_after) = responses[index];
+                async move {
+                    let status = StatusCode::from_u16(status).unwrap();
+                    if status.is_success() {
+                        let body = serde_json::json!({
+                            "choices": [{ "message": { "role": "assistant", "content": "ok" }, "finish_reason": "stop" }],
+                            "usage": { "prompt_tokens": 5, "completion_tokens": 1 }
+                        });
+                        return (status, axum::Json(body)).into_response();
+                    }
+                    match retry_after {
+                        Some(secs) => (status, [(header::RETRY_AFTER, secs)], "slow down").into_response(),
+                        None => (status, "error").into_response(),
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (base_url, calls)
+    }
+
+    async fn call(base_url: &str, policy: &LlmRetryPolicy) -> Result<CreateResult> {
+        let http = reqwest::Client::new();
+        let body = serde_json::json!({ "model": "qwen-max", "messages": [] });
+        with_retry(policy, || post_chat_completion(&http, base_url, "sk-test", &body)).await
+    }
+
+    #[tokio::test]
+    async fn test_retries_rate_limits_and_server_errors() -> Result<()> {
+        let (base_url, calls) = mock_api(vec![(429, Some("0")), (503, None), (200, None)]).await;
+        let result = call(&base_url, &fast_policy(5)).await?;
+        assert_eq!(result.content, "ok");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_client_errors() {
+        let (base_url, calls) = mock_api(vec![(401, None)]).await;
+        let error = call(&base_url, &fast_policy(5)).await.unwrap_err();
+        assert_eq!(error.downcast_ref::<LlmHttpError>().map(|e| e.status), Some(401));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let (base_url, calls) = mock_api(vec![(400, None)]).await;
+        assert!(call(&base_url, &fast_policy(5)).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let (base_url, calls) = mock_api(vec![(502, None)]).await;
+        let started = Instant::now();
+        let error = call(&base_url, &fast_policy(3)).await.unwrap_err();
+        assert!(error.to_string().contains("after 3 attempts"), "{}", error);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        // 两次退避：10ms + 20ms
+        assert!(started.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[tokio::test]
+    async fn test_respects_retry_after_and_max_elapsed() {
+        // Retry-After 超过剩余的重试时间时立即放弃
+        let (base_url, calls) = mock_api(vec![(429, Some("30"))]).await;
+        let policy = LlmRetryPolicy { max_elapsed: Duration::from_secs(5), ..fast_policy(5) };
+        let started = Instant::now();
+        let error = call(&base_url, &policy).await.unwrap_err();
+        assert!(error.to_string().contains("retry budget"), "{}", error);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let (base_url, calls) = mock_api(vec![(429, Some("0.05")), (200, None)]).await;
+        let started = Instant::now();
+        call(&base_url, &fast_policy(2)).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let policy = fast_policy(10);
+        let waits: Vec<u128> = (1..=4).map(|attempt| policy.backoff(attempt).as_millis()).collect();
+        assert_eq!(waits, [10, 20, 40, 40]);
+
+        let jittered = LlmRetryPolicy { jitter: true, ..policy };
+        for _ in 0..20 {
+            let wait = jittered.jittered(Duration::from_millis(100));
+            assert!(wait >= Duration::from_millis(50) && wait <= Duration::from_millis(100), "{:?}", wait);
+        }
+    }
+
+    #[test]
+    fn test_retry_hint() {
+        let http = |status| anyhow::Error::new(LlmHttpError { status, retry_after: None, body: String::new() });
+        assert_eq!(retry_hint(&http(429)), Some(None));
+        assert_eq!(retry_hint(&http(500)), Some(None));
+        assert_eq!(retry_hint(&http(403)), None);
+        assert_eq!(retry_hint(&http(422)), None);
+        let reset = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::ConnectionReset)).context("sending request");
+        assert_eq!(retry_hint(&reset), Some(None));
+        assert_eq!(retry_hint(&anyhow::anyhow!("invalid JSON")), None);
+    }
+
+    #[test]
+    fn test_policy_from_settings() {
+        let settings = LlmSettings { retry_max_attempts: Some(2), retry_max_elapsed_secs: Some(10), ..LlmSettings::default() };
+        let policy = LlmRetryPolicy::from_settings(&settings);
+        assert_eq!(policy.max_attempts, 2);
+        assert_eq!(policy.max_elapsed, Duration::from_secs(10));
+        assert_eq!(policy.initial_backoff, LlmRetryPolicy::default().initial_backoff);
+    }
+}
@@ -0,0 +1,308 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::clients::llm::{CreateResult, FinishReason, TokenUsage, ToolCall};
+use crate::clients::retry::http_error;
+
+/* 兼容 OpenAI 的流式回复（SSE）：按行读入 data: 块，返回其中新的文字和函数调用参数片段，
+流结束后组装成和非流式调用相同的 CreateResult。函数调用按 index 拼接，只在流结束后才完整 */
+#[derive(Debug, Default)]
+pub struct StreamAssembler {
+    // 还没有读到换行的字节，一个字符可能被分在两次读入中
+    pending: Vec<u8>,
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    finish_reason: Option<FinishReason>,
+    usage: TokenUsage,
+    done: bool,
+}
+
+impl StreamAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 读入一段响应体，返回其中完整的行带来的片段
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Vec<String>> {
+        self.pending.extend_from_slice(bytes);
+        let mut deltas = Vec::new();
+        while let Some(end) = self.pending.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let line = String::from_utf8(line).context("The model stream is not valid UTF-8")?;
+            deltas.extend(self.push_line(line.trim_end())?);
+        }
+        Ok(deltas)
+    }
+
+    /// 收到 data: [DONE] 之后不再有内容
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    // 只处理 data: 行，event:、id: 和注释行（心跳）忽略
+    fn push_line(&mut self, line: &str) -> Result<Vec<String>> {
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            return Ok(Vec::new());
+        };
+        if data == "[DONE]" {
+            self.done = true;
+            return Ok(Vec::new());
+        }
+        let value: Value = serde_json::from_str(data).with_context(|| format!("Invalid model stream chunk: {}", data))?;
+        if let Some(error) = value.get("error") {
+            bail!("The model stream returned an error: {}", error);
+        }
+        let chunk: RawChunk = serde_json::from_value(value)?;
+        if let Some(usage) = chunk.usage {
+            self.usage = usage;
+        }
+
+        let mut deltas = Vec::new();
+        for choice in chunk.choices {
+            if let Some(text) = choice.delta.content.filter(|text| !text.is_empty()) {
+                self.content.push_str(&text);
+                deltas.push(text);
+            }
+            for call in choice.delta.tool_calls {
+                while self.tool_calls.len() <= call.index {
+                    self.tool_calls.push(ToolCall { id: String::new(), name: String::new(), arguments: String::new() });
+                }
+                let target = &mut self.tool_calls[call.index];
+                // id 和函数名只在第一块中给出，之后的块只有参数片段
+                if let Some(id) = call.id.filter(|_| target.id.is_empty()) {
+                    target.id = id;
+                }
+                let Some(function) = call.function else { continue };
+                if let Some(name) = function.name.filter(|_| target.name.is_empty()) {
+                    target.name = name;
+                }
+                if let Some(arguments) = function.arguments.filter(|arguments| !arguments.is_empty()) {
+                    target.arguments.push_str(&arguments);
+                    deltas.push(arguments);
+                }
+            }
+            if choice.finish_reason.is_some() {
+                self.finish_reason = choice.finish_reason;
+            }
+        }
+        Ok(deltas)
+    }
+
+    /// 流中断（既没有 [DONE] 也没有 finish_reason）时返回错误，不把半截的回复当作完整结果
+    pub fn finish(mut self) -> Result<CreateResult> {
+        if !self.pending.is_empty() {
+            let rest = std::mem::take(&mut self.pending);
+            self.push_line(String::from_utf8_lossy(&rest).trim_end())?;
+        }
+        if !self.done && self.finish_reason.is_none() {
+            return Err(anyhow!("The model stream ended before the response was complete"));
+        }
+        Ok(CreateResult {
+            content: self.content,
+            usage: self.usage,
+            tool_calls: self.tool_calls.into_iter().filter(|call| !call.name.is_empty()).collect(),
+            finish_reason: self.finish_reason,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct RawChunk {
+    #[serde(default)]
+    choices: Vec<RawChunkChoice>,
+    usage: Option<TokenUsage>,
+}
+
+#[derive(Deserialize)]
+struct RawChunkChoice {
+    #[serde(default)]
+    delta: RawDelta,
+    finish_reason: Option<FinishReason>,
+}
+
+#[derive(Default, Deserialize)]
+struct RawDelta {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<RawToolCallDelta>,
+}
+
+#[derive(Deserialize)]
+struct RawToolCallDelta {
+    #[serde(default)]
+    index: usize,
+    id: Option<String>,
+    function: Option<RawFunctionDelta>,
+}
+
+#[derive(Deserialize)]
+struct RawFunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+/* 流式调用兼容 OpenAI 的 chat/completions 接口，每个片段调用一次 on_chunk。
+请求用量随最后一块返回（stream_options.include_usage）；非 2xx 的响应和非流式调用一样返回 LlmHttpError */
+pub async fn stream_chat_completion(
+    http: &reqwest::Client,
+    base_url: &str,
+    api_key: &str,
+    body: &Value,
+    on_chunk: &(dyn for<'a> Fn(&'a str) + Send + Sync),
+) -> Result<CreateResult> {
+    let mut body = body.clone();
+    body["stream"] = json!(true);
+    body["stream_options"] = json!({ "include_usage": true });
+    let url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
+    let mut response = http.post(&url).bearer_auth(api_key).json(&body).send().await?;
+    if !response.status().is_success() {
+        return Err(http_error(response).await.into());
+    }
+
+    let mut assembler = StreamAssembler::new();
+    while let Some(bytes) = response.chunk().await? {
+        for delta in assembler.push(&bytes)? {
+            on_chunk(&delta);
+        }
+        if assembler.is_done() {
+            break;
+        }
+    }
+    assembler.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    use axum::http::header;
+    use axum::routing::post;
+    use axum::response::IntoResponse;
+    use axum::Json;
+
+    fn sse(chunks: &[Value]) -> String {
+        let mut body: String = chunks.iter().map(|chunk| format!("data: {}\n\n", chunk)).collect();
+        body.push_str("data: [DONE]\n\n");
+        body
+    }
+
+    fn text_chunk(text: &str) -> Value {
+        json!({ "choices": [{ "index": 0, "delta": { "content": text }, "finish_reason": null }] })
+    }
+
+    // 按固定的字节数切开，模拟网络分包（包括把一个汉字切成两半）
+    fn feed(assembler: &mut StreamAssembler, body: &str, size: usize) -> Result<Vec<String>> {
+        let mut deltas = Vec::new();
+        for piece in body.as_bytes().chunks(size) {
+            deltas.extend(assembler.push(piece)?);
+        }
+        Ok(deltas)
+    }
+
+    #[test]
+    fn test_assembles_text_deltas() -> Result<()> {
+        let body = format!(
+            ": keep-alive\n\n{}",
+            sse(&[
+                json!({ "choices": [{ "index": 0, "delta": { "role": "assistant", "content": "" } }] }),
+                text_chunk("{\"steps\": "),
+                text_chunk("[\"搜索\"]}"),
+                json!({ "choices": [{ "index": 0, "delta": {}, "finish_reason": "stop" }] }),
+                json!({ "choices": [], "usage": { "prompt_tokens": 12, "completion_tokens": 4, "total_tokens": 16 } }),
+            ])
+        );
+        for size in [1, 7, body.len()] {
+            let mut assembler = StreamAssembler::new();
+            let deltas = feed(&mut assembler, &body, size)?;
+            assert_eq!(deltas, ["{\"steps\": ", "[\"搜索\"]}"]);
+            let result = assembler.finish()?;
+            assert_eq!(result.content, "{\"steps\": [\"搜索\"]}");
+            assert_eq!(result.finish_reason, Some(FinishReason::Stop));
+            assert_eq!(result.usage, TokenUsage { prompt_tokens: 12, completion_tokens: 4 });
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconstructs_tool_calls() -> Result<()> {
+        let call = |index: usize, id: Option<&str>, name: Option<&str>, arguments: &str| {
+            json!({ "choices": [{ "index": 0, "delta": { "tool_calls": [{
+                "index": index,
+                "id": id,
+                "type": "function",
+                "function": { "name": name, "arguments": arguments },
+            }] } }] })
+        };
+        let body = sse(&[
+            call(0, Some("call_1"), Some("emit_plan"), ""),
+            call(0, None, None, "{\"task\":"),
+            call(1, Some("call_2"), Some("sleep"), "{\"seconds\": 1}"),
+            call(0, None, None, " \"Find the menu\"}"),
+            json!({ "choices": [{ "index": 0, "delta": {}, "finish_reason": "tool_calls" }] }),
+        ]);
+        let mut assembler = StreamAssembler::new();
+        let deltas = feed(&mut assembler, &body, 16)?;
+        assert_eq!(deltas, ["{\"task\":", "{\"seconds\": 1}", " \"Find the menu\"}"]);
+
+        let result = assembler.finish()?;
+        assert_eq!(result.content, "");
+        assert_eq!(result.finish_reason, Some(FinishReason::ToolCalls));
+        assert_eq!(
+            result.tool_calls,
+            vec![
+                ToolCall { id: "call_1".to_string(), name: "emit_plan".to_string(), arguments: "{\"task\": \"Find the menu\"}".to_string() },
+                ToolCall { id: "call_2".to_string(), name: "sleep".to_string(), arguments: "{\"seconds\": 1}".to_string() },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_incomplete_or_failed_streams() {
+        let mut assembler = StreamAssembler::new();
+        assembler.push(format!("data: {}\n\n", text_chunk("half")).as_bytes()).unwrap();
+        assert!(assembler.finish().unwrap_err().to_string().contains("ended before the response was complete"));
+
+        let mut assembler = StreamAssembler::new();
+        let error = assembler.push(b"data: {\"error\": {\"message\": \"quota exceeded\"}}\n").unwrap_err();
+        assert!(error.to_string().contains("quota exceeded"));
+    }
+
+    #[tokio::test]
+    async fn test_stream_chat_completion() -> Result<()> {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let app = axum::Router::new().route(
+            "/chat/completions",
+            post(move |Json(body): Json<Value>| {
+                recorded.lock().unwrap().push(body);
+                async {
+                    let body = sse(&[
+                        text_chunk("Hello"),
+                        text_chunk(", world"),
+                        json!({ "choices": [{ "index": 0, "delta": {}, "finish_reason": "stop" }] }),
+                    ]);
+                    ([(header::CONTENT_TYPE, "text/event-stream")], body).into_response()
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base_url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let chunks = Mutex::new(Vec::new());
+        let body = json!({ "model": "qwen-max", "messages": [] });
+        let result = stream_chat_completion(&reqwest::Client::new(), &base_url, "sk-test", &body, &|chunk: &str| {
+            chunks.lock().unwrap().push(chunk.to_string())
+        })
+        .await?;
+        assert_eq!(result.content, "Hello, world");
+        assert_eq!(*chunks.lock().unwrap(), ["Hello", ", world"]);
+        let sent = requests.lock().unwrap()[0].clone();
+        assert_eq!(sent["stream"], true);
+        assert_eq!(sent["model"], "qwen-max");
+        Ok(())
+    }
+}
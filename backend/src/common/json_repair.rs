@@ -0,0 +1,253 @@
+use serde_json::Value;
+
+/// 解析时做过的修复，调用方据此记录日志
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonRepair {
+    /// 去掉了 JSON 前后的说明文字或代码块标记
+    StrippedSurroundingText,
+    /// 去掉了 } 或 ] 之前多余的逗号
+    RemovedTrailingCommas,
+    /// 单引号的键和字符串改成双引号
+    ConvertedSingleQuotes,
+    /// 转义了字符串中的原始换行、制表符等控制字符
+    EscapedControlCharacters,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepairedJson {
+    pub value: Value,
+    /// 为空时原文就是合法的 JSON
+    pub repairs: Vec<JsonRepair>,
+}
+
+// 最多尝试多少个 { 作为起点，避免前面的说明文字里带着大括号时找错位置
+const MAX_START_CANDIDATES: usize = 8;
+
+/* 宽松地解析模型输出的 JSON 对象，不依赖模型调用，便于单独测试。
+合法的 JSON 原样解析；否则依次尝试每个 { 起点，截取到与之匹配的 }（找不到时到最后一个 }），
+再做保守的修复：只在字符串外删除多余的逗号、把单引号字符串改成双引号，只在字符串内转义控制字符。
+修复后仍然无法解析时返回 None，由调用方决定是否再请求模型 */
+pub fn parse_json_lenient(text: &str) -> Option<RepairedJson> {
+    let trimmed = text.trim();
+    if let Ok(value) = serde_json::from_str::<Value>(trimmed) {
+        if value.is_object() {
+            return Some(RepairedJson { value, repairs: Vec::new() });
+        }
+    }
+
+    for (start, _) in trimmed.match_indices('{').take(MAX_START_CANDIDATES) {
+        let end = match matching_brace(&trimmed[start..]) {
+            Some(offset) => start + offset,
+            None => match trimmed.rfind('}') {
+                Some(end) if end > start => end,
+                _ => continue,
+            },
+        };
+        let candidate = &trimmed[start..=end];
+        let mut repairs = Vec::new();
+        if candidate.len() != trimmed.len() {
+            repairs.push(JsonRepair::StrippedSurroundingText);
+        }
+
+        if let Ok(value) = serde_json::from_str::<Value>(candidate) {
+            return Some(RepairedJson { value, repairs });
+        }
+        let (fixed, fixes) = repair(candidate);
+        if let Ok(value) = serde_json::from_str::<Value>(&fixed) {
+            repairs.extend(fixes);
+            return Some(RepairedJson { value, repairs });
+        }
+    }
+    None
+}
+
+// 与开头的 { 匹配的 } 的位置，单引号和双引号字符串里的括号不计
+fn matching_brace(text: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// 单次扫描完成所有修复，返回修复后的文本和实际做过的修复
+fn repair(text: &str) -> (String, Vec<JsonRepair>) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut repairs = Vec::new();
+    let note = |repair: JsonRepair, repairs: &mut Vec<JsonRepair>| {
+        if !repairs.contains(&repair) {
+            repairs.push(repair);
+        }
+    };
+
+    let mut quote: Option<char> = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if let Some(q) = quote {
+            match c {
+                '\\' if i + 1 < chars.len() => {
+                    let next = chars[i + 1];
+                    // 单引号字符串里的 \' 在 JSON 中不合法，直接写成 '
+                    if q == '\'' && next == '\'' {
+                        out.push('\'');
+                    } else {
+                        out.push(c);
+                        out.push(next);
+                    }
+                    i += 2;
+                    continue;
+                }
+                _ if c == q => {
+                    out.push('"');
+                    quote = None;
+                }
+                '"' => {
+                    out.push_str("\\\"");
+                }
+                '\n' | '\r' | '\t' => {
+                    out.push_str(match c {
+                        '\n' => "\\n",
+                        '\r' => "\\r",
+                        _ => "\\t",
+                    });
+                    note(JsonRepair::EscapedControlCharacters, &mut repairs);
+                }
+                _ if (c as u32) < 0x20 => {
+                    out.push_str(&format!("\\u{:04x}", c as u32));
+                    note(JsonRepair::EscapedControlCharacters, &mut repairs);
+                }
+                _ => out.push(c),
+            }
+            i += 1;
+            continue;
+        }
+
+        match c {
+            '"' => {
+                quote = Some('"');
+                out.push('"');
+            }
+            '\'' => {
+                quote = Some('\'');
+                out.push('"');
+                note(JsonRepair::ConvertedSingleQuotes, &mut repairs);
+            }
+            ',' => {
+                let next = chars[i + 1..].iter().find(|c| !c.is_whitespace());
+                if matches!(next, Some('}') | Some(']')) {
+                    note(JsonRepair::RemovedTrailingCommas, &mut repairs);
+                } else {
+                    out.push(c);
+                }
+            }
+            _ => out.push(c),
+        }
+        i += 1;
+    }
+    (out, repairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // 每个样本以 "=== " 开头的一行分隔，该行其余部分是样本说明
+    fn corpus() -> Vec<(String, String)> {
+        include_str!("testdata/malformed_plans.txt")
+            .split("=== ")
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (name, body) = entry.split_once('\n').unwrap_or((entry, ""));
+                (name.trim().to_string(), body.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_repair_corpus() {
+        let corpus = corpus();
+        assert!(corpus.len() >= 15);
+
+        let mut failed = Vec::new();
+        for (name, body) in &corpus {
+            match parse_json_lenient(body) {
+                Some(repaired) => {
+                    assert!(!repaired.repairs.is_empty(), "{} should need a repair", name);
+                    let steps = repaired.value["steps"].as_array();
+                    assert!(steps.is_some_and(|steps| !steps.is_empty()), "{} lost its steps", name);
+                }
+                None => failed.push(name.clone()),
+            }
+        }
+        // 无法确定原意的样本（例如被截断的输出）允许失败
+        assert!(failed.len() <= 2, "failed to repair: {:?}", failed);
+    }
+
+    #[test]
+    fn test_specific_repairs() {
+        let repaired = parse_json_lenient("Sure! Here is the plan:\n```json\n{\"steps\": [1, 2,],}\n```").unwrap();
+        assert_eq!(repaired.value, json!({ "steps": [1, 2] }));
+        assert_eq!(repaired.repairs, vec![JsonRepair::StrippedSurroundingText, JsonRepair::RemovedTrailingCommas]);
+
+        let repaired = parse_json_lenient("{'title': 'Say \"hi\"', 'details': 'It\\'s late'}").unwrap();
+        assert_eq!(repaired.value, json!({ "title": "Say \"hi\"", "details": "It's late" }));
+        assert_eq!(repaired.repairs, vec![JsonRepair::ConvertedSingleQuotes]);
+
+        let repaired = parse_json_lenient("{\"details\": \"line one\nline two\tend\"}").unwrap();
+        assert_eq!(repaired.value["details"], "line one\nline two\tend");
+        assert_eq!(repaired.repairs, vec![JsonRepair::EscapedControlCharacters]);
+
+        // 前面的说明文字里带着大括号
+        let repaired = parse_json_lenient("Format: {title, details}. {\"steps\": []}").unwrap();
+        assert_eq!(repaired.value, json!({ "steps": [] }));
+
+        assert!(parse_json_lenient("no json here").is_none());
+        assert!(parse_json_lenient("{\"steps\": [").is_none());
+    }
+
+    #[test]
+    fn test_valid_json_is_never_changed() {
+        let samples = [
+            json!({ "title": "It's 5 o'clock, {really}", "details": "Trailing comma, ]" }),
+            json!({ "steps": [{ "title": "A, B", "details": "Use 'single' quotes" }], "needs_plan": true }),
+            json!({ "text": "Tab\there\nnewline \\ backslash \"quoted\"", "n": 1.5, "empty": {} }),
+            json!({ "unicode": "计划：第一步，打开网页", "list": [[], {}, null, false] }),
+        ];
+        for sample in samples {
+            for text in [sample.to_string(), serde_json::to_string_pretty(&sample).unwrap()] {
+                let repaired = parse_json_lenient(&text).unwrap();
+                assert_eq!(repaired.value, sample);
+                assert!(repaired.repairs.is_empty());
+            }
+            // 有前后说明文字时只去掉说明文字
+            let repaired = parse_json_lenient(&format!("Here you go: {} Hope it helps!", sample)).unwrap();
+            assert_eq!(repaired.value, sample);
+            assert_eq!(repaired.repairs, vec![JsonRepair::StrippedSurroundingText]);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+/// 判断一段文字的主要语言，返回英文的语言名称，例如 "Chinese"；无法判断时返回 None
+pub trait LanguageDetector: Send + Sync {
+    fn detect(&self, text: &str) -> Option<String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Script {
+    Han,
+    Kana,
+    Hangul,
+    Cyrillic,
+    Arabic,
+    Latin,
+}
+
+fn script_of(c: char) -> Option<Script> {
+    match c as u32 {
+        0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF => Some(Script::Han),
+        0x3040..=0x30FF => Some(Script::Kana),
+        0x1100..=0x11FF | 0xAC00..=0xD7AF => Some(Script::Hangul),
+        0x0400..=0x04FF => Some(Script::Cyrillic),
+        0x0600..=0x06FF => Some(Script::Arabic),
+        _ if c.is_alphabetic() && (c.is_ascii() || ('\u{00C0}'..='\u{024F}').contains(&c)) => Some(Script::Latin),
+        _ => None,
+    }
+}
+
+/* 按 Unicode 区段统计的启发式判断：汉字、假名、谚文按字计数，其余文字按词计数，
+这样中文请求里夹杂的英文单词（产品名、网址）不会让结果变成英文。出现假名时判为日文 */
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScriptLanguageDetector;
+
+impl LanguageDetector for ScriptLanguageDetector {
+    fn detect(&self, text: &str) -> Option<String> {
+        let mut counts: HashMap<Script, usize> = HashMap::new();
+        let mut previous = None;
+        for c in text.chars() {
+            let script = script_of(c);
+            if let Some(script) = script {
+                let per_char = matches!(script, Script::Han | Script::Kana | Script::Hangul);
+                if per_char || previous != Some(script) {
+                    *counts.entry(script).or_default() += 1;
+                }
+            }
+            previous = script;
+        }
+
+        if counts.contains_key(&Script::Kana) {
+            return Some("Japanese".to_string());
+        }
+        let (script, _) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+        let language = match script {
+            Script::Han => "Chinese",
+            Script::Kana => "Japanese",
+            Script::Hangul => "Korean",
+            Script::Cyrillic => "Russian",
+            Script::Arabic => "Arabic",
+            Script::Latin => "English",
+        };
+        Some(language.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(text: &str) -> Option<String> {
+        ScriptLanguageDetector.detect(text)
+    }
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(detect("帮我找一家评分最高的餐厅").as_deref(), Some("Chinese"));
+        assert_eq!(detect("帮我找 GitHub 上 star 最多的 Rust 项目").as_deref(), Some("Chinese"));
+        assert_eq!(detect("Find the best rated restaurant near me").as_deref(), Some("English"));
+        assert_eq!(detect("東京の天気を教えてください").as_deref(), Some("Japanese"));
+        assert_eq!(detect("서울 날씨 알려줘").as_deref(), Some("Korean"));
+        assert_eq!(detect("Найди лучший ресторан").as_deref(), Some("Russian"));
+        assert_eq!(detect("12345 !?"), None);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+// 运行从几秒到几十分钟不等
+const RUN_DURATION_BUCKETS: &[f64] = &[1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 3600.0];
+const LLM_LATENCY_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 40.0, 80.0];
+
+/* 后端的 Prometheus 指标，由 GET /metrics 以文本格式导出。
+计数和耗时在执行器、模型客户端中记录，队列、浏览器池和连接池的当前值在导出时读取。
+标签只用取值有限的 provider、model、outcome 和 state，不带会话、用户等标识 */
+pub struct Metrics {
+    registry: Registry,
+    pub runs_started: IntCounter,
+    /// 结束的运行，包括失败和取消的
+    pub runs_finished: IntCounter,
+    pub runs_failed: IntCounter,
+    pub run_duration: Histogram,
+    pub run_queue_depth: IntGauge,
+    /// provider、model、outcome（ok 或 error）
+    pub llm_requests: IntCounterVec,
+    pub llm_latency: HistogramVec,
+    pub browser_pool_size: IntGauge,
+    pub browser_pool_in_use: IntGauge,
+    /// state 为 idle 或 in_use
+    pub db_pool_connections: IntGaugeVec,
+    pub event_ws_clients: IntGauge,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let metrics = Self {
+            runs_started: IntCounter::new("runs_started_total", "Runs started by the executor").unwrap(),
+            runs_finished: IntCounter::new("runs_finished_total", "Runs that ended with any status").unwrap(),
+            runs_failed: IntCounter::new("runs_failed_total", "Runs that ended as failed").unwrap(),
+            run_duration: Histogram::with_opts(
+                HistogramOpts::new("run_duration_seconds", "Wall time of a run").buckets(RUN_DURATION_BUCKETS.to_vec()),
+            )
+            .unwrap(),
+            run_queue_depth: IntGauge::new("run_queue_depth", "Runs waiting in the executor queue").unwrap(),
+            llm_requests: IntCounterVec::new(
+                Opts::new("llm_requests_total", "Model requests by outcome"),
+                &["provider", "model", "outcome"],
+            )
+            .unwrap(),
+            llm_latency: HistogramVec::new(
+                HistogramOpts::new("llm_latency_seconds", "Latency of model requests").buckets(LLM_LATENCY_BUCKETS.to_vec()),
+                &["provider", "model"],
+            )
+            .unwrap(),
+            browser_pool_size: IntGauge::new("browser_pool_size", "Live browsers in the pool").unwrap(),
+            browser_pool_in_use: IntGauge::new("browser_pool_in_use", "Browsers lent to runs").unwrap(),
+            db_pool_connections: IntGaugeVec::new(
+                Opts::new("db_pool_connections", "Database pool connections by state"),
+                &["state"],
+            )
+            .unwrap(),
+            event_ws_clients: IntGauge::new("event_ws_clients", "Connected run event subscribers").unwrap(),
+            registry,
+        };
+        // 指标名各不相同，注册不会失败
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(metrics.runs_started.clone()),
+            Box::new(metrics.runs_finished.clone()),
+            Box::new(metrics.runs_failed.clone()),
+            Box::new(metrics.run_duration.clone()),
+            Box::new(metrics.run_queue_depth.clone()),
+            Box::new(metrics.llm_requests.clone()),
+            Box::new(metrics.llm_latency.clone()),
+            Box::new(metrics.browser_pool_size.clone()),
+            Box::new(metrics.browser_pool_in_use.clone()),
+            Box::new(metrics.db_pool_connections.clone()),
+            Box::new(metrics.event_ws_clients.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).expect("metric names are unique");
+        }
+        metrics
+    }
+
+    pub fn run_finished(&self, failed: bool, duration: Duration) {
+        self.runs_finished.inc();
+        if failed {
+            self.runs_failed.inc();
+        }
+        self.run_duration.observe(duration.as_secs_f64());
+    }
+
+    pub fn llm_request(&self, provider: &str, model: &str, ok: bool, latency: Duration) {
+        let outcome = if ok { "ok" } else { "error" };
+        self.llm_requests.with_label_values(&[provider, model, outcome]).inc();
+        self.llm_latency.with_label_values(&[provider, model]).observe(latency.as_secs_f64());
+    }
+
+    /// Prometheus 文本格式
+    pub fn render(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_exports_all_families() -> Result<()> {
+        let metrics = Metrics::new();
+        metrics.runs_started.inc();
+        metrics.run_finished(true, Duration::from_secs(3));
+        metrics.llm_request("dashscope", "qwen-max", false, Duration::from_millis(300));
+
+        let text = metrics.render()?;
+        assert!(text.contains("runs_started_total 1"));
+        assert!(text.contains("runs_finished_total 1"));
+        assert!(text.contains("runs_failed_total 1"));
+        assert!(text.contains("run_duration_seconds_count 1"));
+        assert!(text.contains(r#"llm_requests_total{model="qwen-max",outcome="error",provider="dashscope"} 1"#));
+        assert!(text.contains(r#"llm_latency_seconds_count{model="qwen-max",provider="dashscope"} 1"#));
+        for family in ["run_queue_depth", "browser_pool_in_use", "event_ws_clients"] {
+            assert!(text.contains(&format!("# TYPE {} gauge", family)), "{} should be exported", family);
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,10 @@
 mod client;
 mod env;
+pub mod json_repair;
+pub mod language;
+pub mod metrics;
+pub mod template;
+pub mod text;
 
 pub use env::EnvVars;
 pub use client::ModuleClient;
\ No newline at end of file
@@ -0,0 +1,66 @@
+use anyhow::{anyhow, Result};
+
+/// 用 {name} 形式的占位符渲染提示词模板，未提供的占位符保持原样
+pub fn render_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut rendered = template.to_string();
+    for (name, value) in values {
+        rendered = rendered.replace(&format!("{{{}}}", name), value);
+    }
+    rendered
+}
+
+/// 检查模板中的占位符：括号必须成对，名字必须在 allowed 中
+pub fn validate_template(template: &str, allowed: &[&str]) -> Result<()> {
+    let mut chars = template.char_indices();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '{' => {
+                let mut name = String::new();
+                let mut closed = false;
+                for (_, c) in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    if c == '{' {
+                        return Err(anyhow!("Nested '{{' in template at byte {}", start));
+                    }
+                    name.push(c);
+                }
+                if !closed {
+                    return Err(anyhow!("Unclosed placeholder starting at byte {}", start));
+                }
+                if !allowed.contains(&name.as_str()) {
+                    return Err(anyhow!(
+                        "Unknown placeholder {{{}}}; allowed placeholders are: {}",
+                        name,
+                        allowed.iter().map(|a| format!("{{{}}}", a)).collect::<Vec<_>>().join(", ")
+                    ));
+                }
+            }
+            '}' => return Err(anyhow!("Unmatched '}}' in template at byte {}", start)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_template() {
+        let rendered = render_template("Task: {task}\nPlan: {plan}", &[("task", "buy milk"), ("plan", "1. go")]);
+        assert_eq!(rendered, "Task: buy milk\nPlan: 1. go");
+    }
+
+    #[test]
+    fn test_validate_template() {
+        let allowed = ["task", "plan"];
+        assert!(validate_template("Task: {task} {plan}", &allowed).is_ok());
+        assert!(validate_template("Task: {tsak}", &allowed).is_err());
+        assert!(validate_template("Task: {task", &allowed).is_err());
+        assert!(validate_template("Task: task}", &allowed).is_err());
+    }
+}
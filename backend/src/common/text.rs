@@ -0,0 +1,54 @@
+// 字符级编辑距离
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// 小写、去掉标点、合并空白，用于比较两段文字是否基本相同
+pub fn normalize_text(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_ascii_punctuation())
+        .flat_map(|c| c.to_lowercase())
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 归一化之后的相似度，1.0 表示完全相同
+pub fn text_similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (normalize_text(a), normalize_text(b));
+    let max_len = a.chars().count().max(b.chars().count());
+    if max_len == 0 {
+        return 1.0;
+    }
+    1.0 - edit_distance(&a, &b) as f64 / max_len as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+
+    #[test]
+    fn test_text_similarity() {
+        assert_eq!(text_similarity("Click the  Search button.", "click the search button"), 1.0);
+        assert!(text_similarity("Click the search button", "Click the search buttons") > 0.9);
+        assert!(text_similarity("Click the search button", "Open the settings page") < 0.5);
+    }
+}
\ No newline at end of file
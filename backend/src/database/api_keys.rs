@@ -0,0 +1,160 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+
+use crate::clients::PostgresClient;
+use crate::common::ModuleClient;
+
+// 明文形如 mm_<id>_<secret>，id 用于查找记录，secret 只保存哈希
+const KEY_PREFIX: &str = "mm";
+
+/// 一个 API key 的记录，不含哈希
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct ApiKeyRecord {
+    pub id: String,
+    pub user_id: String,
+    pub label: String,
+    pub created_at: i64,
+    pub revoked_at: Option<i64>,
+}
+
+/// 新生成的 key，明文只在这里出现一次
+#[derive(Debug, Clone)]
+pub struct MintedKey {
+    pub key: String,
+    pub record: ApiKeyRecord,
+}
+
+/// 校验 Authorization 中的 key 的结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyCheck {
+    Valid(ApiKeyRecord),
+    Revoked,
+    Invalid,
+}
+
+#[derive(FromRow)]
+struct KeyRow {
+    key_hash: String,
+    #[sqlx(flatten)]
+    record: ApiKeyRecord,
+}
+
+/// api_keys 表的读写，表结构由 DomainSchema 迁移
+#[derive(Debug, Clone)]
+pub struct ApiKeyStore {
+    pool: PgPool,
+}
+
+impl ApiKeyStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub fn from_client(client: &PostgresClient) -> Self {
+        let pool: &PgPool = ***client.get_client();
+        Self::new(pool.clone())
+    }
+
+    pub async fn mint(&self, user_id: &str, label: &str) -> Result<MintedKey> {
+        let id = uuid::Uuid::new_v4().simple().to_string()[..16].to_string();
+        let secret = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+        let record = sqlx::query_as::<_, ApiKeyRecord>(
+            r#"
+            INSERT INTO api_keys (id, key_hash, user_id, label)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, label, created_at, revoked_at
+            "#,
+        )
+        .bind(&id)
+        .bind(hash_secret(&secret))
+        .bind(user_id)
+        .bind(label)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(MintedKey { key: format!("{}_{}_{}", KEY_PREFIX, id, secret), record })
+    }
+
+    /// 返回是否撤销了一个还有效的 key
+    pub async fn revoke(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query(
+            r#"UPDATE api_keys SET revoked_at = floor(extract(epoch from now())) WHERE id = $1 AND revoked_at IS NULL"#,
+        )
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn list(&self, user_id: &str) -> Result<Vec<ApiKeyRecord>> {
+        let records = sqlx::query_as::<_, ApiKeyRecord>(
+            r#"SELECT id, user_id, label, created_at, revoked_at FROM api_keys WHERE user_id = $1 ORDER BY created_at"#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(records)
+    }
+
+    // 按 id 找到记录后用常量时间比较哈希，格式不对和哈希不匹配都视为无效
+    pub async fn verify(&self, key: &str) -> Result<KeyCheck> {
+        let Some((id, secret)) = parse_key(key) else {
+            return Ok(KeyCheck::Invalid);
+        };
+        let row = sqlx::query_as::<_, KeyRow>(
+            r#"SELECT id, key_hash, user_id, label, created_at, revoked_at FROM api_keys WHERE id = $1"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(row) = row else {
+            return Ok(KeyCheck::Invalid);
+        };
+        if !constant_time_eq(hash_secret(secret).as_bytes(), row.key_hash.as_bytes()) {
+            return Ok(KeyCheck::Invalid);
+        }
+        if row.record.revoked_at.is_some() {
+            return Ok(KeyCheck::Revoked);
+        }
+        Ok(KeyCheck::Valid(row.record))
+    }
+}
+
+fn parse_key(key: &str) -> Option<(&str, &str)> {
+    let rest = key.strip_prefix(KEY_PREFIX)?.strip_prefix('_')?;
+    let (id, secret) = rest.split_once('_')?;
+    if id.is_empty() || secret.is_empty() {
+        return None;
+    }
+    Some((id, secret))
+}
+
+fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+// 长度不同直接返回 false（哈希长度固定，不泄露信息），内容逐字节比较不提前退出
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_hash() {
+        assert_eq!(parse_key("mm_0123abcd_s3cret"), Some(("0123abcd", "s3cret")));
+        assert_eq!(parse_key("mm_0123abcd_"), None);
+        assert_eq!(parse_key("sk_0123abcd_s3cret"), None);
+        assert_eq!(parse_key("mm0123abcd_s3cret"), None);
+        assert_eq!(hash_secret("abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+}
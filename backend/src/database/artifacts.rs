@@ -0,0 +1,92 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+
+use crate::clients::PostgresClient;
+use crate::common::ModuleClient;
+use crate::storage::BlobRef;
+
+/// 会话中引用的一个产物，内容保存在外部存储中
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct ArtifactRecord {
+    pub key: String,
+    pub session_id: String,
+    pub run_id: Option<String>,
+    pub store: String,
+    pub mime: String,
+    pub size: i64,
+    pub created_at: i64,
+}
+
+/// artifacts 表的读写，表结构由 DomainSchema 迁移
+#[derive(Debug, Clone)]
+pub struct ArtifactIndex {
+    pool: PgPool,
+}
+
+impl ArtifactIndex {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub fn from_client(client: &PostgresClient) -> Self {
+        let pool: &PgPool = ***client.get_client();
+        Self::new(pool.clone())
+    }
+
+    // 同一会话中重复出现的内容只记录一次
+    pub async fn record(&self, session_id: &str, run_id: Option<&str>, blob: &BlobRef) -> Result<()> {
+        Self::record_in(&mut *self.pool.acquire().await?, session_id, run_id, blob).await
+    }
+
+    /// 同 record，在调用方的事务中执行
+    pub async fn record_in(conn: &mut PgConnection, session_id: &str, run_id: Option<&str>, blob: &BlobRef) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO artifacts (key, session_id, run_id, store, mime, size)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (key, session_id) DO NOTHING
+            "#,
+        )
+        .bind(&blob.key)
+        .bind(session_id)
+        .bind(run_id)
+        .bind(&blob.store)
+        .bind(&blob.mime)
+        .bind(blob.size as i64)
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    /// 该用户的某个会话引用了这个产物时返回记录，否则为 None
+    pub async fn find_for_user(&self, key: &str, user_id: &str) -> Result<Option<ArtifactRecord>> {
+        let record = sqlx::query_as::<_, ArtifactRecord>(
+            r#"
+            SELECT a.key, a.session_id, a.run_id, a.store, a.mime, a.size, a.created_at
+            FROM artifacts a JOIN sessions s ON s.id = a.session_id
+            WHERE a.key = $1 AND s.user_id = $2
+            ORDER BY a.created_at
+            LIMIT 1
+            "#,
+        )
+        .bind(key)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(record)
+    }
+
+    pub async fn list_for_session(&self, session_id: &str) -> Result<Vec<ArtifactRecord>> {
+        let records = sqlx::query_as::<_, ArtifactRecord>(
+            r#"
+            SELECT key, session_id, run_id, store, mime, size, created_at
+            FROM artifacts WHERE session_id = $1 ORDER BY created_at, key
+            "#,
+        )
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(records)
+    }
+}
@@ -0,0 +1,468 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use sqlx::{Executor, PgPool};
+
+use crate::database::{RunFact, RunRecord, SchemaMigrator, SessionMessage, SessionRecord, SqlxSchema};
+
+// 同时启动的多个进程只有一个在执行迁移
+const MIGRATION_LOCK_ID: i64 = 0x6d61_6769_6300;
+
+const SCHEMA_MIGRATIONS_SQL: &str = r#"
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version BIGINT PRIMARY KEY,
+    name TEXT NOT NULL,
+    applied_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now()))
+)
+"#;
+
+const TIMESTAMP_FUNCTION_SQL: &str = r#"
+CREATE OR REPLACE FUNCTION set_updated_at_unix_timestamp()
+RETURNS TRIGGER AS $$
+BEGIN NEW.updated_at = floor(extract(epoch from now())); RETURN NEW; END;
+$$ language 'plpgsql'
+"#;
+
+/* 一个版本的表结构变化。up 在一个事务里按顺序执行，down 用于本地开发时回退，
+版本号只增不改，已发布的迁移不要再修改 */
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: fn() -> Vec<String>,
+    pub down: fn() -> Vec<String>,
+}
+
+/// 执行过的迁移
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "create_runs", up: create_runs, down: drop_runs },
+    Migration { version: 2, name: "create_sessions_and_messages", up: create_sessions, down: drop_sessions },
+    Migration { version: 3, name: "create_plans", up: create_plans, down: drop_plans },
+    Migration { version: 4, name: "link_runs_to_sessions", up: link_runs, down: unlink_runs },
+    Migration { version: 5, name: "create_run_events", up: create_run_events, down: drop_run_events },
+    Migration { version: 6, name: "create_api_keys", up: create_api_keys, down: drop_api_keys },
+    Migration { version: 7, name: "create_artifacts", up: create_artifacts, down: drop_artifacts },
+    Migration { version: 8, name: "create_run_checkpoints", up: create_run_checkpoints, down: drop_run_checkpoints },
+    Migration { version: 9, name: "create_user_quotas", up: create_user_quotas, down: drop_user_quotas },
+    Migration { version: 10, name: "add_retention", up: add_retention, down: drop_retention },
+    Migration { version: 11, name: "create_session_files", up: create_session_files, down: drop_session_files },
+    Migration { version: 12, name: "version_run_events", up: version_run_events, down: unversion_run_events },
+];
+
+fn schema_sql<T: SqlxSchema>() -> Vec<String> {
+    let mut statements = vec![T::create_table_sql()];
+    statements.extend(T::INDEXES_SQL.iter().map(|sql| sql.to_string()));
+    let trigger = T::trigger_sql();
+    if !trigger.trim().is_empty() {
+        statements.push(trigger);
+    }
+    statements
+}
+
+fn create_runs() -> Vec<String> {
+    let mut statements = vec![TIMESTAMP_FUNCTION_SQL.to_string()];
+    statements.extend(schema_sql::<RunRecord>());
+    statements.extend(schema_sql::<RunFact>());
+    statements
+}
+
+fn drop_runs() -> Vec<String> {
+    vec![RunFact::drop_table_sql(), RunRecord::drop_table_sql()]
+}
+
+fn create_sessions() -> Vec<String> {
+    let mut statements = vec![TIMESTAMP_FUNCTION_SQL.to_string()];
+    statements.extend(schema_sql::<SessionRecord>());
+    statements.extend(schema_sql::<SessionMessage>());
+    statements
+}
+
+fn drop_sessions() -> Vec<String> {
+    vec![SessionMessage::drop_table_sql(), SessionRecord::drop_table_sql()]
+}
+
+fn create_plans() -> Vec<String> {
+    vec![
+        r#"
+        CREATE TABLE IF NOT EXISTS plans (
+            id TEXT PRIMARY KEY,
+            session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+            version BIGINT NOT NULL,
+            plan_json JSONB NOT NULL,
+            source TEXT NOT NULL,
+            created_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now())),
+            UNIQUE (session_id, version)
+        )
+        "#.to_string(),
+    ]
+}
+
+fn drop_plans() -> Vec<String> {
+    vec!["DROP TABLE IF EXISTS plans CASCADE".to_string()]
+}
+
+// 已有的 runs 表加上会话、计划和时间字段，旧记录这些字段为空
+fn link_runs() -> Vec<String> {
+    vec![
+        r#"
+        ALTER TABLE runs
+            ADD COLUMN IF NOT EXISTS session_id TEXT REFERENCES sessions(id) ON DELETE CASCADE,
+            ADD COLUMN IF NOT EXISTS plan_id TEXT REFERENCES plans(id) ON DELETE SET NULL,
+            ADD COLUMN IF NOT EXISTS metrics_json TEXT,
+            ADD COLUMN IF NOT EXISTS started_at BIGINT,
+            ADD COLUMN IF NOT EXISTS finished_at BIGINT
+        "#.to_string(),
+        "CREATE INDEX IF NOT EXISTS idx_runs_session_created ON runs (session_id, created_at DESC)".to_string(),
+        "CREATE INDEX IF NOT EXISTS idx_runs_status ON runs (status)".to_string(),
+    ]
+}
+
+fn unlink_runs() -> Vec<String> {
+    vec![
+        "DROP INDEX IF EXISTS idx_runs_status".to_string(),
+        "DROP INDEX IF EXISTS idx_runs_session_created".to_string(),
+        r#"
+        ALTER TABLE runs
+            DROP COLUMN IF EXISTS finished_at,
+            DROP COLUMN IF EXISTS started_at,
+            DROP COLUMN IF EXISTS metrics_json,
+            DROP COLUMN IF EXISTS plan_id,
+            DROP COLUMN IF EXISTS session_id
+        "#.to_string(),
+    ]
+}
+
+fn create_run_events() -> Vec<String> {
+    vec![
+        r#"
+        CREATE TABLE IF NOT EXISTS run_events (
+            run_id TEXT NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+            seq BIGINT NOT NULL,
+            event_json JSONB NOT NULL,
+            created_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now())),
+            PRIMARY KEY (run_id, seq)
+        )
+        "#.to_string(),
+    ]
+}
+
+fn drop_run_events() -> Vec<String> {
+    vec!["DROP TABLE IF EXISTS run_events".to_string()]
+}
+
+// 只保存 key 的哈希，明文在生成时返回一次
+fn create_api_keys() -> Vec<String> {
+    vec![
+        r#"
+        CREATE TABLE IF NOT EXISTS api_keys (
+            id TEXT PRIMARY KEY,
+            key_hash TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            label TEXT NOT NULL DEFAULT '',
+            created_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now())),
+            revoked_at BIGINT
+        )
+        "#.to_string(),
+        "CREATE INDEX IF NOT EXISTS idx_api_keys_user ON api_keys (user_id)".to_string(),
+    ]
+}
+
+fn drop_api_keys() -> Vec<String> {
+    vec!["DROP TABLE IF EXISTS api_keys".to_string()]
+}
+
+// 外部存储中的产物与会话的对应关系，同一内容可以出现在多个会话中
+fn create_artifacts() -> Vec<String> {
+    vec![
+        r#"
+        CREATE TABLE IF NOT EXISTS artifacts (
+            key TEXT NOT NULL,
+            session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+            run_id TEXT REFERENCES runs(id) ON DELETE SET NULL,
+            store TEXT NOT NULL,
+            mime TEXT NOT NULL,
+            size BIGINT NOT NULL,
+            created_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now())),
+            PRIMARY KEY (key, session_id)
+        )
+        "#.to_string(),
+        "CREATE INDEX IF NOT EXISTS idx_artifacts_run ON artifacts (run_id)".to_string(),
+    ]
+}
+
+fn drop_artifacts() -> Vec<String> {
+    vec!["DROP TABLE IF EXISTS artifacts".to_string()]
+}
+
+// 每个运行最新的会话检查点（SessionCheckpoint 的 JSON，含代理状态），用于恢复被中断的运行
+fn create_run_checkpoints() -> Vec<String> {
+    vec![
+        r#"
+        CREATE TABLE IF NOT EXISTS run_checkpoints (
+            run_id TEXT PRIMARY KEY REFERENCES runs(id) ON DELETE CASCADE,
+            step_index BIGINT NOT NULL,
+            reason TEXT NOT NULL,
+            checkpoint_json JSONB NOT NULL,
+            updated_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now()))
+        )
+        "#.to_string(),
+    ]
+}
+
+fn drop_run_checkpoints() -> Vec<String> {
+    vec!["DROP TABLE IF EXISTS run_checkpoints".to_string()]
+}
+
+// 单独设置的用户配额（为空的项使用默认值），以及按 UTC 日期累计的运行数和 token 数
+fn create_user_quotas() -> Vec<String> {
+    vec![
+        r#"
+        CREATE TABLE IF NOT EXISTS user_quotas (
+            user_id TEXT PRIMARY KEY,
+            max_concurrent_runs BIGINT,
+            max_runs_per_day BIGINT,
+            max_tokens_per_day BIGINT,
+            updated_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now()))
+        )
+        "#.to_string(),
+        r#"
+        CREATE TABLE IF NOT EXISTS user_usage (
+            user_id TEXT NOT NULL,
+            day DATE NOT NULL,
+            runs BIGINT NOT NULL DEFAULT 0,
+            tokens BIGINT NOT NULL DEFAULT 0,
+            PRIMARY KEY (user_id, day)
+        )
+        "#.to_string(),
+    ]
+}
+
+fn drop_user_quotas() -> Vec<String> {
+    vec!["DROP TABLE IF EXISTS user_usage".to_string(), "DROP TABLE IF EXISTS user_quotas".to_string()]
+}
+
+/* 保留策略：置顶的会话不清理；删除的产物内容先排队，确认没有引用后再从外部存储中删除；
+每次清理的结果记录在 cleanup_runs */
+fn add_retention() -> Vec<String> {
+    vec![
+        "ALTER TABLE sessions ADD COLUMN IF NOT EXISTS pinned BOOLEAN NOT NULL DEFAULT FALSE".to_string(),
+        r#"
+        CREATE TABLE IF NOT EXISTS pending_blob_deletions (
+            key TEXT PRIMARY KEY,
+            queued_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now()))
+        )
+        "#.to_string(),
+        r#"
+        CREATE TABLE IF NOT EXISTS cleanup_runs (
+            id BIGSERIAL PRIMARY KEY,
+            trigger TEXT NOT NULL,
+            sessions_deleted BIGINT NOT NULL DEFAULT 0,
+            runs_deleted BIGINT NOT NULL DEFAULT 0,
+            run_events_deleted BIGINT NOT NULL DEFAULT 0,
+            artifacts_deleted BIGINT NOT NULL DEFAULT 0,
+            blobs_deleted BIGINT NOT NULL DEFAULT 0,
+            blob_errors BIGINT NOT NULL DEFAULT 0,
+            started_at BIGINT NOT NULL,
+            finished_at BIGINT NOT NULL
+        )
+        "#.to_string(),
+        "CREATE INDEX IF NOT EXISTS idx_sessions_updated ON sessions (updated_at)".to_string(),
+        "CREATE INDEX IF NOT EXISTS idx_run_events_created ON run_events (created_at)".to_string(),
+        "CREATE INDEX IF NOT EXISTS idx_artifacts_created ON artifacts (created_at)".to_string(),
+    ]
+}
+
+fn drop_retention() -> Vec<String> {
+    vec![
+        "DROP INDEX IF EXISTS idx_artifacts_created".to_string(),
+        "DROP INDEX IF EXISTS idx_run_events_created".to_string(),
+        "DROP INDEX IF EXISTS idx_sessions_updated".to_string(),
+        "DROP TABLE IF EXISTS cleanup_runs".to_string(),
+        "DROP TABLE IF EXISTS pending_blob_deletions".to_string(),
+        "ALTER TABLE sessions DROP COLUMN IF EXISTS pinned".to_string(),
+    ]
+}
+
+// 用户上传到会话中的文件，同一内容在外部存储中只保存一份
+fn create_session_files() -> Vec<String> {
+    vec![
+        r#"
+        CREATE TABLE IF NOT EXISTS session_files (
+            id TEXT PRIMARY KEY,
+            session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+            name TEXT NOT NULL,
+            store TEXT NOT NULL,
+            key TEXT NOT NULL,
+            mime TEXT NOT NULL,
+            size BIGINT NOT NULL,
+            created_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now()))
+        )
+        "#.to_string(),
+        "CREATE INDEX IF NOT EXISTS idx_session_files_session ON session_files (session_id, created_at)".to_string(),
+        "CREATE INDEX IF NOT EXISTS idx_session_files_key ON session_files (key)".to_string(),
+    ]
+}
+
+fn drop_session_files() -> Vec<String> {
+    vec!["DROP TABLE IF EXISTS session_files".to_string()]
+}
+
+/* 运行事件的序列化版本和毫秒精度的时间，回放时按版本解析并还原事件之间的间隔；
+已有的事件为版本 1，时间取 created_at */
+fn version_run_events() -> Vec<String> {
+    vec![
+        "ALTER TABLE run_events ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1".to_string(),
+        "ALTER TABLE run_events ADD COLUMN IF NOT EXISTS emitted_at_ms BIGINT".to_string(),
+        "UPDATE run_events SET emitted_at_ms = created_at * 1000 WHERE emitted_at_ms IS NULL".to_string(),
+        "ALTER TABLE run_events ALTER COLUMN emitted_at_ms SET DEFAULT floor(extract(epoch from clock_timestamp()) * 1000)"
+            .to_string(),
+        "ALTER TABLE run_events ALTER COLUMN emitted_at_ms SET NOT NULL".to_string(),
+    ]
+}
+
+fn unversion_run_events() -> Vec<String> {
+    vec![
+        "ALTER TABLE run_events DROP COLUMN IF EXISTS emitted_at_ms".to_string(),
+        "ALTER TABLE run_events DROP COLUMN IF EXISTS version".to_string(),
+    ]
+}
+
+/// 已经执行过的版本号，按升序排列
+pub async fn applied_versions(pool: &PgPool) -> Result<Vec<i64>> {
+    pool.execute(SCHEMA_MIGRATIONS_SQL).await?;
+    let versions = sqlx::query_scalar(r#"SELECT version FROM schema_migrations ORDER BY version"#)
+        .fetch_all(pool)
+        .await?;
+    Ok(versions)
+}
+
+/* 执行所有还没有执行过的迁移，返回本次执行的迁移；可重复调用。
+每个迁移和它在 schema_migrations 中的记录在同一个事务里提交 */
+pub async fn migrate_up(pool: &PgPool) -> Result<Vec<AppliedMigration>> {
+    pool.execute(SCHEMA_MIGRATIONS_SQL).await?;
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS {
+        let mut tx = pool.begin().await?;
+        sqlx::query("SELECT pg_advisory_xact_lock($1)").bind(MIGRATION_LOCK_ID).execute(&mut *tx).await?;
+        let exists: bool = sqlx::query_scalar(r#"SELECT EXISTS (SELECT 1 FROM schema_migrations WHERE version = $1)"#)
+            .bind(migration.version)
+            .fetch_one(&mut *tx)
+            .await?;
+        if exists {
+            continue;
+        }
+        for statement in (migration.up)() {
+            (&mut *tx)
+                .execute(statement.as_str())
+                .await
+                .with_context(|| format!("Migration {} ({}) failed", migration.version, migration.name))?;
+        }
+        sqlx::query(r#"INSERT INTO schema_migrations (version, name) VALUES ($1, $2)"#)
+            .bind(migration.version)
+            .bind(migration.name)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        applied.push(AppliedMigration { version: migration.version, name: migration.name.to_string() });
+    }
+    Ok(applied)
+}
+
+/// 回退到 target 版本（不含）之后的所有迁移，只用于本地开发
+pub async fn migrate_down(pool: &PgPool, target: i64) -> Result<Vec<AppliedMigration>> {
+    if target < 0 {
+        return Err(anyhow!("Target version must not be negative"));
+    }
+    let applied = applied_versions(pool).await?;
+    let mut reverted = Vec::new();
+    for migration in MIGRATIONS.iter().rev().filter(|m| m.version > target && applied.contains(&m.version)) {
+        let mut tx = pool.begin().await?;
+        sqlx::query("SELECT pg_advisory_xact_lock($1)").bind(MIGRATION_LOCK_ID).execute(&mut *tx).await?;
+        for statement in (migration.down)() {
+            (&mut *tx)
+                .execute(statement.as_str())
+                .await
+                .with_context(|| format!("Reverting migration {} ({}) failed", migration.version, migration.name))?;
+        }
+        sqlx::query(r#"DELETE FROM schema_migrations WHERE version = $1"#)
+            .bind(migration.version)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        reverted.push(AppliedMigration { version: migration.version, name: migration.name.to_string() });
+    }
+    Ok(reverted)
+}
+
+/// 领域表（会话、计划、消息、运行、运行事件、API key、产物、运行检查点、用户配额、清理记录、会话文件）的整体迁移
+pub struct DomainSchema;
+
+#[async_trait::async_trait]
+impl SchemaMigrator for DomainSchema {
+    async fn migrate(pool: &PgPool) -> Result<()> {
+        for migration in migrate_up(pool).await? {
+            tracing::info!("Applied migration {} ({})", migration.version, migration.name);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions_are_increasing() {
+        assert!(MIGRATIONS.windows(2).all(|pair| pair[0].version < pair[1].version));
+        assert!(MIGRATIONS.iter().all(|m| !(m.up)().is_empty() && !(m.down)().is_empty()));
+    }
+
+    async fn table_exists(pool: &PgPool, table: &str) -> Result<bool> {
+        Ok(sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+            .bind(format!("public.{}", table))
+            .fetch_one(pool)
+            .await?)
+    }
+
+    // 在 DATABASE_URL 指向的服务器上建一个临时数据库
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_migrate_twice_is_idempotent() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let admin = PgPool::connect(&database_url).await?;
+        let name = format!("magentic_migrate_{}", uuid::Uuid::new_v4().simple());
+        admin.execute(format!("CREATE DATABASE {}", name).as_str()).await?;
+        let mut scratch_url = url::Url::parse(&database_url)?;
+        scratch_url.set_path(&name);
+        let pool = PgPool::connect(scratch_url.as_str()).await?;
+
+        let first = migrate_up(&pool).await?;
+        assert_eq!(first.len(), MIGRATIONS.len());
+        assert!(migrate_up(&pool).await?.is_empty());
+        DomainSchema::migrate(&pool).await?;
+        assert_eq!(applied_versions(&pool).await?, MIGRATIONS.iter().map(|m| m.version).collect::<Vec<_>>());
+        for table in ["sessions", "plans", "messages", "runs", "run_facts", "run_events", "api_keys", "artifacts", "run_checkpoints", "user_quotas", "user_usage", "pending_blob_deletions", "cleanup_runs", "session_files"] {
+            assert!(table_exists(&pool, table).await?, "{} should exist", table);
+        }
+
+        let reverted = migrate_down(&pool, 2).await?;
+        assert_eq!(reverted.iter().map(|m| m.version).collect::<Vec<_>>(), vec![12, 11, 10, 9, 8, 7, 6, 5, 4, 3]);
+        assert!(!table_exists(&pool, "plans").await?);
+        assert!(!table_exists(&pool, "artifacts").await?);
+        assert!(!table_exists(&pool, "run_checkpoints").await?);
+        assert!(!table_exists(&pool, "user_usage").await?);
+        assert!(!table_exists(&pool, "cleanup_runs").await?);
+        assert!(!table_exists(&pool, "session_files").await?);
+        assert!(table_exists(&pool, "sessions").await?);
+        assert_eq!(migrate_up(&pool).await?.len(), 10);
+
+        pool.close().await;
+        admin.execute(format!("DROP DATABASE {}", name).as_str()).await?;
+        Ok(())
+    }
+}
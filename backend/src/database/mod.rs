@@ -1,6 +1,28 @@
 pub mod env;
 pub mod sqlx_postgres;
 pub mod postgres_connect;
+pub mod runs;
+pub mod plans;
+pub mod sessions;
+pub mod migrations;
+pub mod vectors;
+pub mod api_keys;
+pub mod artifacts;
+pub mod quotas;
+pub mod tx;
+pub mod retention;
+pub mod session_files;
 
 pub use env::PostgresDbEnv;
-pub use sqlx_postgres::{SqlxSchema,SchemaMigrator};
\ No newline at end of file
+pub use sqlx_postgres::{SqlxSchema,SchemaMigrator};
+pub use runs::{RunStore, RunRecord, RunFact, RunDetail, RunEventRecord, StaleRuns, StepOutcome};
+pub use plans::{PgPlanStore, PlanMemoryRecord};
+pub use sessions::{SessionStore, SessionRecord, SessionMessage, MessagePage};
+pub use migrations::{DomainSchema, AppliedMigration};
+pub use vectors::{VectorStore, VectorIndexKind, VectorMatch};
+pub use api_keys::{ApiKeyStore, ApiKeyRecord, MintedKey, KeyCheck};
+pub use artifacts::{ArtifactIndex, ArtifactRecord};
+pub use quotas::{DailyUsage, Quota, QuotaOverride, QuotaStore};
+pub use tx::{with_tx, with_tx_retry, TxRetry};
+pub use retention::{CleanupSummary, RetentionPolicy, RetentionStore};
+pub use session_files::{SessionFileRecord, SessionFileStore};
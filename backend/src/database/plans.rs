@@ -0,0 +1,253 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use pgvector::Vector;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+use crate::clients::{PgvectorClient, TextEmbedder, EMBEDDING_DIMS};
+use crate::common::ModuleClient;
+use crate::database::vectors::check_dimension;
+use crate::database::{SchemaMigrator, SqlxSchema};
+use crate::orchestrator::plan::Plan;
+use crate::orchestrator::plan_library::{PlanLibrary, PlanLibraryEntry, PlanOutcome, ScoredPlan};
+
+/// 计划库中的一条记录，embedding 列只在数据库里使用，不读回
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PlanMemoryRecord {
+    pub id: String,
+    pub user_id: Option<String>,
+    pub task: String,
+    pub plan_json: String,
+    pub outcome: String,
+    pub created_at: i64,
+}
+
+pub const PLAN_OUTCOME_SUCCESS: &str = "success";
+pub const PLAN_OUTCOME_FAILURE: &str = "failure";
+
+fn outcome_str(outcome: PlanOutcome) -> &'static str {
+    match outcome {
+        PlanOutcome::Success => PLAN_OUTCOME_SUCCESS,
+        PlanOutcome::Failure => PLAN_OUTCOME_FAILURE,
+    }
+}
+
+impl SqlxSchema for PlanMemoryRecord {
+    type Id = String;
+    type Row = PlanMemoryRecord;
+
+    const TABLE_NAME: &'static str = "plans";
+    const ID_COLUMN_NAME: &'static str = "id";
+    const COLUMNS: &'static [&'static str] = &["id", "user_id", "task", "plan_json", "outcome", "embedding", "created_at"];
+    // hnsw 不需要先有数据再建索引，适合从空表开始增长
+    const INDEXES_SQL: &'static [&'static str] = &[
+        "CREATE INDEX IF NOT EXISTS idx_plans_embedding ON plans USING hnsw (embedding vector_cosine_ops)",
+        "CREATE INDEX IF NOT EXISTS idx_plans_user_created ON plans (user_id, created_at DESC)",
+    ];
+
+    fn get_id_value(&self) -> Self::Id {
+        self.id.clone()
+    }
+
+    fn from_row(row: Self::Row) -> Self {
+        row
+    }
+
+    fn create_table_sql() -> String {
+        format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS plans (
+                id TEXT PRIMARY KEY,
+                user_id TEXT,
+                task TEXT NOT NULL,
+                plan_json TEXT NOT NULL,
+                outcome TEXT NOT NULL,
+                embedding vector({}) NOT NULL,
+                created_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now()))
+            )
+            "#,
+            EMBEDDING_DIMS
+        )
+    }
+
+    fn drop_table_sql() -> String {
+        "DROP TABLE IF EXISTS plans".to_string()
+    }
+
+    fn insert_sql() -> String {
+        r#"
+        INSERT INTO plans (id, user_id, task, plan_json, outcome, embedding)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, user_id, task, plan_json, outcome, created_at
+        "#.to_string()
+    }
+
+    fn trigger_sql() -> String {
+        String::new()
+    }
+}
+
+#[async_trait]
+impl SchemaMigrator for PlanMemoryRecord {
+    async fn migrate(pool: &PgPool) -> Result<()> {
+        sqlx::query("CREATE EXTENSION IF NOT EXISTS vector").execute(pool).await?;
+        sqlx::query(&Self::create_table_sql()).execute(pool).await?;
+        for index_sql in Self::INDEXES_SQL {
+            sqlx::query(index_sql).execute(pool).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct SimilarPlanRow {
+    task: String,
+    plan_json: String,
+    created_at: i64,
+    similarity: f64,
+}
+
+/* 存在 pgvector 库中的计划记忆：保存时嵌入任务描述，检索时按余弦距离找最近的成功计划。
+设置 user_id 后保存和检索都只针对该用户 */
+#[derive(Clone)]
+pub struct PgPlanStore {
+    pool: PgPool,
+    embedder: Arc<dyn TextEmbedder>,
+    user_id: Option<String>,
+}
+
+impl PgPlanStore {
+    pub fn new(pool: PgPool, embedder: Arc<dyn TextEmbedder>) -> Self {
+        Self { pool, embedder, user_id: None }
+    }
+
+    pub fn from_client(client: &PgvectorClient, embedder: Arc<dyn TextEmbedder>) -> Self {
+        let pool: &PgPool = ***client.get_client();
+        Self::new(pool.clone(), embedder)
+    }
+
+    pub fn with_user(mut self, user_id: impl Into<String>) -> Self {
+        self.user_id = Some(user_id.into());
+        self
+    }
+
+    pub async fn migrate(&self) -> Result<()> {
+        PlanMemoryRecord::migrate(&self.pool).await
+    }
+
+    // 嵌入的维度必须和表中 vector 列一致
+    async fn embed(&self, text: &str) -> Result<Vector> {
+        let embedding = self.embedder.embed_text(text).await?;
+        check_dimension(EMBEDDING_DIMS as usize, &embedding)?;
+        Ok(Vector::from(embedding))
+    }
+
+    pub async fn save_plan(&self, task: &str, plan: &Plan, outcome: PlanOutcome) -> Result<PlanMemoryRecord> {
+        let embedding = self.embed(task).await?;
+        let rec = sqlx::query_as::<_, PlanMemoryRecord>(&PlanMemoryRecord::insert_sql())
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(self.user_id.as_deref())
+            .bind(task)
+            .bind(serde_json::to_string(plan)?)
+            .bind(outcome_str(outcome))
+            .bind(embedding)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(rec)
+    }
+
+    // 只返回成功的计划，similarity = 1 - 余弦距离
+    pub async fn find_similar(&self, task_text: &str, k: usize) -> Result<Vec<ScoredPlan>> {
+        let embedding = self.embed(task_text).await?;
+        let rows = sqlx::query_as::<_, SimilarPlanRow>(
+            r#"
+            SELECT task, plan_json, created_at, 1 - (embedding <=> $1)::FLOAT8 AS similarity
+            FROM plans
+            WHERE outcome = $2 AND ($3::TEXT IS NULL OR user_id = $3)
+            ORDER BY embedding <=> $1
+            LIMIT $4
+            "#,
+        )
+        .bind(embedding)
+        .bind(PLAN_OUTCOME_SUCCESS)
+        .bind(self.user_id.as_deref())
+        .bind(k as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let plan: Plan = serde_json::from_str(&row.plan_json)
+                    .map_err(|e| anyhow!("Stored plan for '{}' is invalid: {}", row.task, e))?;
+                let timestamp = chrono::DateTime::from_timestamp(row.created_at, 0).unwrap_or_default();
+                Ok(ScoredPlan {
+                    entry: PlanLibraryEntry { task: row.task, plan, outcome: PlanOutcome::Success, timestamp },
+                    similarity: row.similarity,
+                })
+            })
+            .collect()
+    }
+}
+
+// 让后端的规划也能使用向量检索的计划库
+#[async_trait]
+impl PlanLibrary for PgPlanStore {
+    async fn add(&self, entry: PlanLibraryEntry) -> Result<()> {
+        self.save_plan(&entry.task, &entry.plan, entry.outcome).await.map(|_| ())
+    }
+
+    async fn find_similar(&self, task: &str, k: usize) -> Result<Vec<ScoredPlan>> {
+        PgPlanStore::find_similar(self, task, k).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::plan::{new_step_id, PlanStep, StepKind};
+    use crate::testing::KeywordEmbedder;
+
+    fn plan(title: &str) -> Plan {
+        Plan {
+            task: None,
+            steps: vec![PlanStep {
+                id: new_step_id(),
+                title: title.to_string(),
+                details: title.to_string(),
+                agent_name: "web_surfer".to_string(),
+                expected_outcome: None,
+                kind: StepKind::Standard,
+            }],
+        }
+    }
+
+    // 需要 PGVECTOR_URI，未设置时跳过
+    #[tokio::test]
+    async fn test_find_similar_orders_by_distance() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = match std::env::var("PGVECTOR_URI") {
+            Ok(url) => url,
+            Err(_) => return Ok(()),
+        };
+        let pool = PgPool::connect(&database_url).await?;
+        let store = PgPlanStore::new(pool, Arc::new(KeywordEmbedder))
+            .with_user(format!("test-{}", uuid::Uuid::new_v4()));
+        store.migrate().await?;
+
+        store.save_plan("Book a flight", &plan("Search flights"), PlanOutcome::Success).await?;
+        store.save_plan("Find a train to Paris", &plan("Search trains to Paris"), PlanOutcome::Success).await?;
+        store.save_plan("Read the menu", &plan("Open the menu"), PlanOutcome::Success).await?;
+        store.save_plan("Find a train to Paris quickly", &plan("Failed"), PlanOutcome::Failure).await?;
+
+        let found = store.find_similar("Find a cheap train to Paris", 2).await?;
+
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].entry.task, "Find a train to Paris");
+        assert_eq!(found[0].entry.plan.steps[0].title, "Search trains to Paris");
+        assert!(found[0].similarity > found[1].similarity);
+        assert!(found.iter().all(|p| p.entry.task != "Find a train to Paris quickly"));
+        Ok(())
+    }
+}
\ No newline at end of file
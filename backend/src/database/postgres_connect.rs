@@ -4,7 +4,7 @@ macro_rules! init_databases {
         default: [$($default_type:ty),* $(,)?],
         pgvector: [$($pgvector_type:ty),* $(,)?]
     ) => {
-        use $crate::database::{SqlxSchema, SchemaMigrator};
+        use $crate::database::SchemaMigrator;
         use sqlx::postgres::PgPoolOptions;
 
         const MIN_POOL_CONN: u32 = 5;
@@ -14,14 +14,24 @@ macro_rules! init_databases {
         static POOL: tokio::sync::OnceCell<sqlx::PgPool> = tokio::sync::OnceCell::const_new();
 
         async fn connect(drop_tables: bool, create_tables: bool, run_migrations: bool) -> &'static sqlx::PgPool {
+            let database_url = std::env::var("DATABASE_URL")
+                .expect("DATABASE_URL environment variable not set");
+            let options = database_url.parse().expect("Invalid DATABASE_URL");
+            connect_with(options, drop_tables, create_tables, run_migrations).await
+        }
+
+        // 连接参数（地址、statement_timeout 等）由调用方给出
+        async fn connect_with(
+            options: sqlx::postgres::PgConnectOptions,
+            drop_tables: bool,
+            create_tables: bool,
+            run_migrations: bool,
+        ) -> &'static sqlx::PgPool {
             POOL.get_or_init(|| async {
-                let database_url = std::env::var("DATABASE_URL")
-                    .expect("DATABASE_URL environment variable not set");
-                
                 let pool = PgPoolOptions::new()
                     .max_connections(MAX_POOL_CONN)
                     .min_connections(MIN_POOL_CONN)
-                    .connect(&database_url).await
+                    .connect_with(options).await
                     .expect("Failed to connect to default database");
 
                 if drop_tables {
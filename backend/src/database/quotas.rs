@@ -0,0 +1,148 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+use crate::clients::PostgresClient;
+use crate::common::ModuleClient;
+
+/// 一个用户实际生效的配额，0 表示不限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Quota {
+    /// 同时排队和执行中的运行数
+    pub max_concurrent_runs: u64,
+    /// 每天（UTC）开始的运行数
+    pub max_runs_per_day: u64,
+    /// 每天（UTC）消耗的模型 token 数
+    pub max_tokens_per_day: u64,
+}
+
+impl Default for Quota {
+    fn default() -> Self {
+        Self {
+            max_concurrent_runs: 2,
+            max_runs_per_day: 100,
+            max_tokens_per_day: 2_000_000,
+        }
+    }
+}
+
+/// user_quotas 中为某个用户单独设置的配额，为空的项使用默认值
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct QuotaOverride {
+    #[serde(default)]
+    pub max_concurrent_runs: Option<i64>,
+    #[serde(default)]
+    pub max_runs_per_day: Option<i64>,
+    #[serde(default)]
+    pub max_tokens_per_day: Option<i64>,
+}
+
+impl QuotaOverride {
+    pub fn apply(&self, defaults: &Quota) -> Quota {
+        let pick = |value: Option<i64>, default: u64| value.map(|v| v.max(0) as u64).unwrap_or(default);
+        Quota {
+            max_concurrent_runs: pick(self.max_concurrent_runs, defaults.max_concurrent_runs),
+            max_runs_per_day: pick(self.max_runs_per_day, defaults.max_runs_per_day),
+            max_tokens_per_day: pick(self.max_tokens_per_day, defaults.max_tokens_per_day),
+        }
+    }
+}
+
+/// 用户某一天的用量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct DailyUsage {
+    pub runs: i64,
+    pub tokens: i64,
+}
+
+/// user_quotas 和 user_usage 表的读写，表结构由 DomainSchema 迁移；用量按天累计，重启后仍然有效
+#[derive(Debug, Clone)]
+pub struct QuotaStore {
+    pool: PgPool,
+}
+
+impl QuotaStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub fn from_client(client: &PostgresClient) -> Self {
+        let pool: &PgPool = ***client.get_client();
+        Self::new(pool.clone())
+    }
+
+    pub async fn get_override(&self, user_id: &str) -> Result<QuotaOverride> {
+        let rec = sqlx::query_as::<_, QuotaOverride>(
+            r#"SELECT max_concurrent_runs, max_runs_per_day, max_tokens_per_day FROM user_quotas WHERE user_id = $1"#,
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(rec.unwrap_or_default())
+    }
+
+    // 整行替换，为空的项恢复为默认值
+    pub async fn set_override(&self, user_id: &str, quota: &QuotaOverride) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_quotas (user_id, max_concurrent_runs, max_runs_per_day, max_tokens_per_day)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id) DO UPDATE
+            SET max_concurrent_runs = EXCLUDED.max_concurrent_runs, max_runs_per_day = EXCLUDED.max_runs_per_day,
+                max_tokens_per_day = EXCLUDED.max_tokens_per_day, updated_at = floor(extract(epoch from now()))
+            "#,
+        )
+        .bind(user_id)
+        .bind(quota.max_concurrent_runs)
+        .bind(quota.max_runs_per_day)
+        .bind(quota.max_tokens_per_day)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn usage(&self, user_id: &str, day: NaiveDate) -> Result<DailyUsage> {
+        let rec = sqlx::query_as::<_, DailyUsage>(r#"SELECT runs, tokens FROM user_usage WHERE user_id = $1 AND day = $2"#)
+            .bind(user_id)
+            .bind(day)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(rec.unwrap_or_default())
+    }
+
+    pub async fn add_usage(&self, user_id: &str, day: NaiveDate, runs: i64, tokens: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_usage (user_id, day, runs, tokens) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, day) DO UPDATE
+            SET runs = user_usage.runs + EXCLUDED.runs, tokens = user_usage.tokens + EXCLUDED.tokens
+            "#,
+        )
+        .bind(user_id)
+        .bind(day)
+        .bind(runs)
+        .bind(tokens)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_override_falls_back_to_defaults() {
+        let defaults = Quota::default();
+        assert_eq!(QuotaOverride::default().apply(&defaults), defaults);
+
+        let quota = QuotaOverride { max_runs_per_day: Some(5), max_tokens_per_day: Some(-1), ..QuotaOverride::default() }
+            .apply(&defaults);
+        assert_eq!(quota.max_concurrent_runs, defaults.max_concurrent_runs);
+        assert_eq!(quota.max_runs_per_day, 5);
+        assert_eq!(quota.max_tokens_per_day, 0);
+    }
+}
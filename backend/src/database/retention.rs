@@ -0,0 +1,240 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+
+use crate::clients::PostgresClient;
+use crate::common::ModuleClient;
+use crate::database::runs::RUN_STATUS_RUNNING;
+use crate::database::sessions::SESSION_STATUS_IDLE;
+use crate::database::tx::with_tx;
+
+const SECONDS_PER_DAY: i64 = 24 * 3600;
+
+/// 保留多少天的数据，0 表示不清理这一类；每类数据每次清理最多删除 batch_size * max_batches 行
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    /// 会话（连同消息、运行和产物记录）在最后一次更新后保留的天数；没有会话的 CLI 运行按创建时间
+    pub session_days: u32,
+    /// 产物记录的保留天数，不再被引用的内容从外部存储中删除
+    pub artifact_days: u32,
+    pub run_event_days: u32,
+    /// 每个事务删除的行数，避免长时间持有锁
+    pub batch_size: u32,
+    pub max_batches: u32,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self { session_days: 30, artifact_days: 30, run_event_days: 7, batch_size: 500, max_batches: 20 }
+    }
+}
+
+/// 一次清理的结果，写入 cleanup_runs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct CleanupSummary {
+    pub id: i64,
+    /// schedule 或 manual
+    pub trigger: String,
+    pub sessions_deleted: i64,
+    pub runs_deleted: i64,
+    pub run_events_deleted: i64,
+    pub artifacts_deleted: i64,
+    pub blobs_deleted: i64,
+    /// 删除失败的内容留在队列中，下次清理时重试
+    pub blob_errors: i64,
+    pub started_at: i64,
+    pub finished_at: i64,
+}
+
+/* 过期数据的批量删除，表结构由 DomainSchema 迁移。置顶的会话和它的一切都不清理，
+运行中的会话和运行也不清理。删除的产物记录的 key 放进 pending_blob_deletions，
+确认没有其他会话引用之后再从外部存储中删除 */
+#[derive(Debug, Clone)]
+pub struct RetentionStore {
+    pool: PgPool,
+}
+
+impl RetentionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub fn from_client(client: &PostgresClient) -> Self {
+        let pool: &PgPool = ***client.get_client();
+        Self::new(pool.clone())
+    }
+
+    // 最早的 limit 个过期会话，返回删除的数量
+    pub async fn delete_expired_sessions(&self, days: u32, limit: u32) -> Result<u64> {
+        with_tx(&self.pool, |conn| {
+            Box::pin(async move {
+                let ids: Vec<String> = sqlx::query_scalar(
+                    r#"
+                    SELECT id FROM sessions
+                    WHERE NOT pinned AND status = $1 AND updated_at < floor(extract(epoch from now())) - $2
+                    ORDER BY updated_at
+                    LIMIT $3
+                    FOR UPDATE SKIP LOCKED
+                    "#,
+                )
+                .bind(SESSION_STATUS_IDLE)
+                .bind(days as i64 * SECONDS_PER_DAY)
+                .bind(limit as i64)
+                .fetch_all(&mut *conn)
+                .await?;
+                Self::delete_sessions_in(conn, &ids).await
+            })
+        })
+        .await
+    }
+
+    /// 删除会话，消息、运行、产物和上传文件的记录随外键级联删除，它们的内容排队等待删除
+    pub async fn delete_sessions_in(conn: &mut PgConnection, ids: &[String]) -> Result<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        sqlx::query(
+            r#"
+            INSERT INTO pending_blob_deletions (key)
+            SELECT key FROM artifacts WHERE session_id = ANY($1)
+            UNION SELECT key FROM session_files WHERE session_id = ANY($1)
+            ON CONFLICT (key) DO NOTHING
+            "#,
+        )
+        .bind(ids)
+        .execute(&mut *conn)
+        .await?;
+        let result = sqlx::query(r#"DELETE FROM sessions WHERE id = ANY($1)"#).bind(ids).execute(&mut *conn).await?;
+        Ok(result.rows_affected())
+    }
+
+    // 不属于会话的运行（CLI 的运行），按创建时间
+    pub async fn delete_expired_runs(&self, days: u32, limit: u32) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM runs WHERE id IN (
+                SELECT id FROM runs
+                WHERE session_id IS NULL AND status <> $1 AND created_at < floor(extract(epoch from now())) - $2
+                ORDER BY created_at
+                LIMIT $3
+            )
+            "#,
+        )
+        .bind(RUN_STATUS_RUNNING)
+        .bind(days as i64 * SECONDS_PER_DAY)
+        .bind(limit as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn delete_expired_run_events(&self, days: u32, limit: u32) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM run_events WHERE (run_id, seq) IN (
+                SELECT e.run_id, e.seq FROM run_events e
+                JOIN runs r ON r.id = e.run_id
+                LEFT JOIN sessions s ON s.id = r.session_id
+                WHERE e.created_at < floor(extract(epoch from now())) - $2
+                    AND r.status <> $1 AND NOT COALESCE(s.pinned, FALSE)
+                ORDER BY e.created_at
+                LIMIT $3
+            )
+            "#,
+        )
+        .bind(RUN_STATUS_RUNNING)
+        .bind(days as i64 * SECONDS_PER_DAY)
+        .bind(limit as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn delete_expired_artifacts(&self, days: u32, limit: u32) -> Result<u64> {
+        with_tx(&self.pool, |conn| {
+            Box::pin(async move {
+                let keys: Vec<String> = sqlx::query_scalar(
+                    r#"
+                    DELETE FROM artifacts WHERE (key, session_id) IN (
+                        SELECT a.key, a.session_id FROM artifacts a
+                        JOIN sessions s ON s.id = a.session_id
+                        WHERE NOT s.pinned AND a.created_at < floor(extract(epoch from now())) - $1
+                        ORDER BY a.created_at
+                        LIMIT $2
+                    )
+                    RETURNING key
+                    "#,
+                )
+                .bind(days as i64 * SECONDS_PER_DAY)
+                .bind(limit as i64)
+                .fetch_all(&mut *conn)
+                .await?;
+                sqlx::query(
+                    r#"INSERT INTO pending_blob_deletions (key) SELECT DISTINCT unnest($1::TEXT[]) ON CONFLICT (key) DO NOTHING"#,
+                )
+                .bind(&keys)
+                .execute(&mut *conn)
+                .await?;
+                Ok(keys.len() as u64)
+            })
+        })
+        .await
+    }
+
+    /* 排队的 key 中已经没有产物或上传文件引用的，按排队顺序；仍被引用的 key 直接出队，
+    内容由引用它的会话继续使用 */
+    pub async fn orphaned_blobs(&self, limit: u32) -> Result<Vec<String>> {
+        sqlx::query(
+            r#"
+            DELETE FROM pending_blob_deletions p
+            WHERE EXISTS (SELECT 1 FROM artifacts a WHERE a.key = p.key)
+                OR EXISTS (SELECT 1 FROM session_files f WHERE f.key = p.key)
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        let keys = sqlx::query_scalar(r#"SELECT key FROM pending_blob_deletions ORDER BY queued_at, key LIMIT $1"#)
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(keys)
+    }
+
+    /// 内容已经从外部存储中删除
+    pub async fn forget_blob(&self, key: &str) -> Result<()> {
+        sqlx::query(r#"DELETE FROM pending_blob_deletions WHERE key = $1"#).bind(key).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn record_cleanup(&self, summary: &CleanupSummary) -> Result<CleanupSummary> {
+        let rec = sqlx::query_as::<_, CleanupSummary>(
+            r#"
+            INSERT INTO cleanup_runs (trigger, sessions_deleted, runs_deleted, run_events_deleted,
+                artifacts_deleted, blobs_deleted, blob_errors, started_at, finished_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, floor(extract(epoch from now())))
+            RETURNING *
+            "#,
+        )
+        .bind(&summary.trigger)
+        .bind(summary.sessions_deleted)
+        .bind(summary.runs_deleted)
+        .bind(summary.run_events_deleted)
+        .bind(summary.artifacts_deleted)
+        .bind(summary.blobs_deleted)
+        .bind(summary.blob_errors)
+        .bind(summary.started_at)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(rec)
+    }
+
+    /// 最近的清理记录，新的在前
+    pub async fn recent_cleanups(&self, limit: i64) -> Result<Vec<CleanupSummary>> {
+        let recs = sqlx::query_as::<_, CleanupSummary>(r#"SELECT * FROM cleanup_runs ORDER BY id DESC LIMIT $1"#)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(recs)
+    }
+}
@@ -0,0 +1,695 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+
+use crate::clients::PostgresClient;
+use crate::common::ModuleClient;
+use crate::database::migrations::DomainSchema;
+use crate::database::tx::with_tx;
+use crate::database::{SchemaMigrator, SqlxSchema};
+use crate::orchestrator::event_log::EVENT_VERSION;
+use crate::orchestrator::plan_history::PlanVersion;
+use crate::orchestrator::session::SessionCheckpoint;
+
+/// 一次 orchestrator 运行的记录
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct RunRecord {
+    pub id: String,
+    pub user_id: Option<String>,
+    pub task: String,
+    pub plan_json: Option<String>,
+    pub step_outcomes_json: String,
+    pub final_answer: Option<String>,
+    pub information_collected: String,
+    pub usage_json: Option<String>,
+    pub status: String,
+    pub n_rounds: i64,
+    pub created_at: i64,
+    pub updated_at: i64,
+    /// 后端执行器的运行所属的会话，CLI 的运行没有
+    #[sqlx(default)]
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+/// 运行过程中收集到的信息（progress_summary），包括定期的检查点和结束时的最终版本
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct RunFact {
+    pub id: String,
+    pub run_id: String,
+    pub kind: String,
+    pub round: i64,
+    pub content: String,
+    pub created_at: i64,
+}
+
+/// 某个步骤完成时 ledger 给出的结论
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StepOutcome {
+    pub step_index: usize,
+    /// 步骤的稳定 id，旧记录中没有
+    #[serde(default)]
+    pub step_id: String,
+    pub title: String,
+    pub summary: String,
+}
+
+/* run_events 中的一条事件，event_json 为清理过的 OrchestratorEvent 序列化结果，
+按 version 解析（见 orchestrator::event_log）；emitted_at_ms 为执行器收到事件的时间 */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct RunEventRecord {
+    pub run_id: String,
+    pub seq: i64,
+    pub version: i32,
+    pub event_json: serde_json::Value,
+    pub emitted_at_ms: i64,
+    pub created_at: i64,
+}
+
+/// 查询接口返回的完整运行详情
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunDetail {
+    pub run: RunRecord,
+    pub facts: Vec<RunFact>,
+}
+
+impl RunDetail {
+    // 按版本号排列的计划版本，无法解析的记录跳过
+    pub fn plan_versions(&self) -> Vec<PlanVersion> {
+        let mut versions: Vec<PlanVersion> = self
+            .facts
+            .iter()
+            .filter(|fact| fact.kind == FACT_KIND_PLAN_VERSION)
+            .filter_map(|fact| serde_json::from_str(&fact.content).ok())
+            .collect();
+        versions.sort_by_key(|v| v.version);
+        versions
+    }
+}
+
+pub const RUN_STATUS_RUNNING: &str = "running";
+pub const RUN_STATUS_COMPLETED: &str = "completed";
+pub const RUN_STATUS_FAILED: &str = "failed";
+pub const RUN_STATUS_CANCELLED: &str = "cancelled";
+/// 进程在运行中途停止，可以从 run_checkpoints 中最新的检查点继续
+pub const RUN_STATUS_INTERRUPTED: &str = "interrupted";
+
+pub const FACT_KIND_CHECKPOINT: &str = "checkpoint";
+pub const FACT_KIND_FINAL: &str = "final";
+/// 计划版本，content 为 PlanVersion 的 JSON，round 为版本号
+pub const FACT_KIND_PLAN_VERSION: &str = "plan_version";
+/// 运行失败的原因
+pub const FACT_KIND_ERROR: &str = "error";
+
+// 进程重启后仍处于 running 的运行不会再有人更新
+const STALE_RUN_ERROR: &str = "The server stopped before the run finished";
+
+/// 启动时清理的遗留运行：有检查点的标记为 interrupted，可以恢复；还没有检查点的记为失败
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StaleRuns {
+    pub interrupted: Vec<String>,
+    pub failed: Vec<String>,
+}
+
+impl SqlxSchema for RunRecord {
+    type Id = String;
+    type Row = RunRecord;
+
+    const TABLE_NAME: &'static str = "runs";
+    const ID_COLUMN_NAME: &'static str = "id";
+    const COLUMNS: &'static [&'static str] = &[
+        "id", "user_id", "task", "plan_json", "step_outcomes_json", "final_answer",
+        "information_collected", "usage_json", "status", "n_rounds", "created_at", "updated_at",
+    ];
+    const INDEXES_SQL: &'static [&'static str] = &[
+        "CREATE INDEX IF NOT EXISTS idx_runs_user_created ON runs (user_id, created_at DESC)",
+    ];
+
+    fn get_id_value(&self) -> Self::Id {
+        self.id.clone()
+    }
+
+    fn from_row(row: Self::Row) -> Self {
+        row
+    }
+
+    fn create_table_sql() -> String {
+        r#"
+        CREATE TABLE IF NOT EXISTS runs (
+            id TEXT PRIMARY KEY,
+            user_id TEXT,
+            task TEXT NOT NULL,
+            plan_json TEXT,
+            step_outcomes_json TEXT NOT NULL DEFAULT '[]',
+            final_answer TEXT,
+            information_collected TEXT NOT NULL DEFAULT '',
+            usage_json TEXT,
+            status TEXT NOT NULL,
+            n_rounds BIGINT NOT NULL DEFAULT 0,
+            created_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now())),
+            updated_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now()))
+        )
+        "#.to_string()
+    }
+
+    fn drop_table_sql() -> String {
+        "DROP TABLE IF EXISTS runs CASCADE".to_string()
+    }
+
+    fn insert_sql() -> String {
+        r#"
+        INSERT INTO runs (id, user_id, task, plan_json, step_outcomes_json, final_answer,
+            information_collected, usage_json, status, n_rounds)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        RETURNING *
+        "#.to_string()
+    }
+
+    fn trigger_sql() -> String {
+        r#"
+        DROP TRIGGER IF EXISTS trg_runs_updated_at ON runs;
+        CREATE TRIGGER trg_runs_updated_at BEFORE UPDATE ON runs
+            FOR EACH ROW EXECUTE FUNCTION set_updated_at_unix_timestamp();
+        "#.to_string()
+    }
+}
+
+impl SqlxSchema for RunFact {
+    type Id = String;
+    type Row = RunFact;
+
+    const TABLE_NAME: &'static str = "run_facts";
+    const ID_COLUMN_NAME: &'static str = "id";
+    const COLUMNS: &'static [&'static str] = &["id", "run_id", "kind", "round", "content", "created_at"];
+    const INDEXES_SQL: &'static [&'static str] = &[
+        "CREATE INDEX IF NOT EXISTS idx_run_facts_run ON run_facts (run_id, created_at)",
+    ];
+
+    fn get_id_value(&self) -> Self::Id {
+        self.id.clone()
+    }
+
+    fn from_row(row: Self::Row) -> Self {
+        row
+    }
+
+    fn create_table_sql() -> String {
+        r#"
+        CREATE TABLE IF NOT EXISTS run_facts (
+            id TEXT PRIMARY KEY,
+            run_id TEXT NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+            kind TEXT NOT NULL,
+            round BIGINT NOT NULL DEFAULT 0,
+            content TEXT NOT NULL,
+            created_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now()))
+        )
+        "#.to_string()
+    }
+
+    fn drop_table_sql() -> String {
+        "DROP TABLE IF EXISTS run_facts".to_string()
+    }
+
+    fn insert_sql() -> String {
+        r#"
+        INSERT INTO run_facts (id, run_id, kind, round, content)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *
+        "#.to_string()
+    }
+
+    fn trigger_sql() -> String {
+        String::new()
+    }
+}
+
+// 建表 + 索引，可重复执行
+pub(crate) async fn create_if_missing<T: SqlxSchema>(pool: &PgPool) -> Result<()> {
+    sqlx::query(&T::create_table_sql()).execute(pool).await?;
+    for index_sql in T::INDEXES_SQL {
+        sqlx::query(index_sql).execute(pool).await?;
+    }
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl SchemaMigrator for RunRecord {
+    async fn migrate(pool: &PgPool) -> Result<()> {
+        create_if_missing::<RunRecord>(pool).await
+    }
+}
+
+#[async_trait::async_trait]
+impl SchemaMigrator for RunFact {
+    async fn migrate(pool: &PgPool) -> Result<()> {
+        create_if_missing::<RunFact>(pool).await
+    }
+}
+
+/// orchestrator 和 HTTP 层共用的运行记录读写
+#[derive(Debug, Clone)]
+pub struct RunStore {
+    pool: PgPool,
+}
+
+impl RunStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub fn from_client(client: &PostgresClient) -> Self {
+        let pool: &PgPool = ***client.get_client();
+        Self::new(pool.clone())
+    }
+
+    // runs 的外键依赖会话和计划表，统一按版本迁移
+    pub async fn migrate(&self) -> Result<()> {
+        DomainSchema::migrate(&self.pool).await
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    pub async fn start_run(&self, user_id: Option<&str>, task: &str) -> Result<RunRecord> {
+        Self::insert_run(&mut *self.pool.acquire().await?, user_id, task).await
+    }
+
+    async fn insert_run(conn: &mut PgConnection, user_id: Option<&str>, task: &str) -> Result<RunRecord> {
+        let rec = sqlx::query_as::<_, RunRecord>(&RunRecord::insert_sql())
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(user_id)
+            .bind(task)
+            .bind(None::<String>)
+            .bind("[]")
+            .bind(None::<String>)
+            .bind("")
+            .bind(None::<String>)
+            .bind(RUN_STATUS_RUNNING)
+            .bind(0i64)
+            .fetch_one(&mut *conn)
+            .await?;
+        Ok(rec)
+    }
+
+    // 后端执行器在运行开始前创建记录，关联会话并记下开始时间
+    pub async fn start_session_run(&self, session_id: &str, user_id: Option<&str>, task: &str) -> Result<RunRecord> {
+        with_tx(&self.pool, |conn| {
+            let (session_id, user_id, task) = (session_id.to_string(), user_id.map(str::to_string), task.to_string());
+            Box::pin(async move { Self::start_session_run_in(conn, &session_id, user_id.as_deref(), &task).await })
+        })
+        .await
+    }
+
+    /// 同 start_session_run，在调用方的事务中执行
+    pub async fn start_session_run_in(
+        conn: &mut PgConnection,
+        session_id: &str,
+        user_id: Option<&str>,
+        task: &str,
+    ) -> Result<RunRecord> {
+        let run = Self::insert_run(&mut *conn, user_id, task).await?;
+        let run = sqlx::query_as::<_, RunRecord>(
+            r#"UPDATE runs SET session_id = $2, started_at = floor(extract(epoch from now())) WHERE id = $1 RETURNING *"#,
+        )
+        .bind(&run.id)
+        .bind(session_id)
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(run)
+    }
+
+    /// 写入运行的最终状态和结束时间，失败时把原因记为一条 error 事实
+    pub async fn end_run(&self, run_id: &str, status: &str, error: Option<&str>) -> Result<()> {
+        with_tx(&self.pool, |conn| {
+            let (run_id, status, error) = (run_id.to_string(), status.to_string(), error.map(str::to_string));
+            Box::pin(async move { Self::end_run_in(conn, &run_id, &status, error.as_deref()).await })
+        })
+        .await
+    }
+
+    /// 同 end_run，在调用方的事务中执行
+    pub async fn end_run_in(conn: &mut PgConnection, run_id: &str, status: &str, error: Option<&str>) -> Result<()> {
+        sqlx::query(r#"UPDATE runs SET status = $2, finished_at = floor(extract(epoch from now())) WHERE id = $1"#)
+            .bind(run_id)
+            .bind(status)
+            .execute(&mut *conn)
+            .await?;
+        if let Some(error) = error {
+            Self::insert_fact(conn, run_id, FACT_KIND_ERROR, 0, error).await?;
+        }
+        Ok(())
+    }
+
+    /* 把还没有在执行的运行（等待恢复的 running、interrupted）记为 cancelled，返回是否更新；
+    已经结束的运行不受影响，正在执行的运行由执行器取消 */
+    pub async fn cancel_pending_run(&self, run_id: &str) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE runs SET status = $2, finished_at = floor(extract(epoch from now()))
+            WHERE id = $1 AND status IN ($3, $4)
+            "#,
+        )
+        .bind(run_id)
+        .bind(RUN_STATUS_CANCELLED)
+        .bind(RUN_STATUS_RUNNING)
+        .bind(RUN_STATUS_INTERRUPTED)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// 处理遗留的 running 记录，见 StaleRuns；只在启动时、还没有运行开始之前调用
+    pub async fn sweep_stale_runs(&self) -> Result<StaleRuns> {
+        with_tx(&self.pool, |conn| Box::pin(Self::sweep_stale_runs_in(conn))).await
+    }
+
+    async fn sweep_stale_runs_in(conn: &mut PgConnection) -> Result<StaleRuns> {
+        let interrupted: Vec<String> = sqlx::query_scalar(
+            r#"
+            UPDATE runs SET status = $1, finished_at = floor(extract(epoch from now()))
+            WHERE status = $2 AND EXISTS (SELECT 1 FROM run_checkpoints c WHERE c.run_id = runs.id)
+            RETURNING id
+            "#,
+        )
+        .bind(RUN_STATUS_INTERRUPTED)
+        .bind(RUN_STATUS_RUNNING)
+        .fetch_all(&mut *conn)
+        .await?;
+        let failed: Vec<String> = sqlx::query_scalar(
+            r#"
+            UPDATE runs SET status = $1, finished_at = floor(extract(epoch from now()))
+            WHERE status = $2
+            RETURNING id
+            "#,
+        )
+        .bind(RUN_STATUS_FAILED)
+        .bind(RUN_STATUS_RUNNING)
+        .fetch_all(&mut *conn)
+        .await?;
+        for id in &failed {
+            Self::insert_fact(&mut *conn, id, FACT_KIND_ERROR, 0, STALE_RUN_ERROR).await?;
+        }
+        Ok(StaleRuns { interrupted, failed })
+    }
+
+    /* 把 interrupted 的运行重新标记为 running，返回更新后的记录；运行不存在、不属于 user_id
+    或不是 interrupted 时返回 None，同一个运行只会被恢复一次 */
+    pub async fn claim_interrupted_run(&self, run_id: &str, user_id: Option<&str>) -> Result<Option<RunRecord>> {
+        Self::claim_interrupted_run_in(&mut *self.pool.acquire().await?, run_id, user_id).await
+    }
+
+    /// 同 claim_interrupted_run，在调用方的事务中执行
+    pub async fn claim_interrupted_run_in(
+        conn: &mut PgConnection,
+        run_id: &str,
+        user_id: Option<&str>,
+    ) -> Result<Option<RunRecord>> {
+        let rec = sqlx::query_as::<_, RunRecord>(
+            r#"
+            UPDATE runs SET status = $2, finished_at = NULL
+            WHERE id = $1 AND status = $3 AND session_id IS NOT NULL AND ($4::TEXT IS NULL OR user_id = $4)
+            RETURNING *
+            "#,
+        )
+        .bind(run_id)
+        .bind(RUN_STATUS_RUNNING)
+        .bind(RUN_STATUS_INTERRUPTED)
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+        Ok(rec)
+    }
+
+    // 每次只保留最新的检查点，恢复时只需要它
+    pub async fn save_run_checkpoint(&self, run_id: &str, checkpoint: &SessionCheckpoint) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO run_checkpoints (run_id, step_index, reason, checkpoint_json) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (run_id) DO UPDATE
+            SET step_index = EXCLUDED.step_index, reason = EXCLUDED.reason,
+                checkpoint_json = EXCLUDED.checkpoint_json, updated_at = floor(extract(epoch from now()))
+            "#,
+        )
+        .bind(run_id)
+        .bind(checkpoint.current_step_idx as i64)
+        .bind(checkpoint.reason.as_str())
+        .bind(serde_json::to_value(checkpoint)?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn run_checkpoint(&self, run_id: &str) -> Result<Option<SessionCheckpoint>> {
+        let value: Option<serde_json::Value> =
+            sqlx::query_scalar(r#"SELECT checkpoint_json FROM run_checkpoints WHERE run_id = $1"#)
+                .bind(run_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        value
+            .map(|value| SessionCheckpoint::from_value(value, &format!("of run {}", run_id)))
+            .transpose()
+    }
+
+    /* 运行事件按 seq 追加，只追加不修改，重复的 seq 忽略；
+    event_json 由调用方清理（event_log::sanitize_event），按当前的 EVENT_VERSION 写入 */
+    pub async fn add_event(&self, run_id: &str, seq: i64, event_json: &serde_json::Value, emitted_at_ms: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO run_events (run_id, seq, version, event_json, emitted_at_ms) VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (run_id, seq) DO NOTHING
+            "#,
+        )
+        .bind(run_id)
+        .bind(seq)
+        .bind(EVENT_VERSION)
+        .bind(event_json)
+        .bind(emitted_at_ms)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 一次运行中 seq 大于 after_seq 的事件，按 seq 排列，最多 limit 条
+    pub async fn run_events_after(&self, run_id: &str, after_seq: i64, limit: i64) -> Result<Vec<RunEventRecord>> {
+        let recs = sqlx::query_as::<_, RunEventRecord>(
+            r#"
+            SELECT run_id, seq, version, event_json, emitted_at_ms, created_at FROM run_events
+            WHERE run_id = $1 AND seq > $2
+            ORDER BY seq
+            LIMIT $3
+            "#,
+        )
+        .bind(run_id)
+        .bind(after_seq)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(recs)
+    }
+
+    /* 会话中 after（运行 id 和 seq）之后的事件，按运行的创建顺序和 seq 排列；
+    after 为 None 时从会话的第一个事件开始，after 指向的运行不存在时没有结果 */
+    pub async fn session_events_after(
+        &self,
+        session_id: &str,
+        after: Option<(&str, i64)>,
+        limit: i64,
+    ) -> Result<Vec<RunEventRecord>> {
+        let (after_run, after_seq) = after.unzip();
+        let recs = sqlx::query_as::<_, RunEventRecord>(
+            r#"
+            SELECT e.run_id, e.seq, e.version, e.event_json, e.emitted_at_ms, e.created_at FROM run_events e
+            JOIN runs r ON r.id = e.run_id
+            WHERE r.session_id = $1 AND (
+                $2::TEXT IS NULL
+                OR (r.created_at, r.id, e.seq) > (SELECT c.created_at, c.id, $3::BIGINT FROM runs c WHERE c.id = $2)
+            )
+            ORDER BY r.created_at, r.id, e.seq
+            LIMIT $4
+            "#,
+        )
+        .bind(session_id)
+        .bind(after_run)
+        .bind(after_seq)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(recs)
+    }
+
+    // 增量检查点：在同一个事务中更新运行进度，并追加一条 checkpoint 事实
+    pub async fn checkpoint(
+        &self,
+        run_id: &str,
+        plan_json: Option<&str>,
+        step_outcomes: &[StepOutcome],
+        information_collected: &str,
+        n_rounds: usize,
+    ) -> Result<()> {
+        let step_outcomes = serde_json::to_string(step_outcomes)?;
+        with_tx(&self.pool, |conn| {
+            let (run_id, plan_json) = (run_id.to_string(), plan_json.map(str::to_string));
+            let (step_outcomes, information_collected) = (step_outcomes.clone(), information_collected.to_string());
+            Box::pin(async move {
+                sqlx::query(
+                    r#"
+                    UPDATE runs
+                    SET plan_json = $2, step_outcomes_json = $3, information_collected = $4, n_rounds = $5
+                    WHERE id = $1
+                    "#,
+                )
+                .bind(&run_id)
+                .bind(&plan_json)
+                .bind(&step_outcomes)
+                .bind(&information_collected)
+                .bind(n_rounds as i64)
+                .execute(&mut *conn)
+                .await?;
+                Self::insert_fact(conn, &run_id, FACT_KIND_CHECKPOINT, n_rounds, &information_collected).await
+            })
+        })
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn finish_run(
+        &self,
+        run_id: &str,
+        plan_json: Option<&str>,
+        step_outcomes: &[StepOutcome],
+        final_answer: &str,
+        information_collected: &str,
+        usage_json: Option<&str>,
+        n_rounds: usize,
+        status: &str,
+    ) -> Result<()> {
+        let step_outcomes = serde_json::to_string(step_outcomes)?;
+        with_tx(&self.pool, |conn| {
+            let (run_id, plan_json, final_answer) = (run_id.to_string(), plan_json.map(str::to_string), final_answer.to_string());
+            let status = status.to_string();
+            let (step_outcomes, information_collected) = (step_outcomes.clone(), information_collected.to_string());
+            let usage_json = usage_json.map(str::to_string);
+            Box::pin(async move {
+                sqlx::query(
+                    r#"
+                    UPDATE runs
+                    SET plan_json = $2, step_outcomes_json = $3, final_answer = $4,
+                        information_collected = $5, usage_json = $6, n_rounds = $7, status = $8,
+                        finished_at = floor(extract(epoch from now()))
+                    WHERE id = $1
+                    "#,
+                )
+                .bind(&run_id)
+                .bind(&plan_json)
+                .bind(&step_outcomes)
+                .bind(&final_answer)
+                .bind(&information_collected)
+                .bind(&usage_json)
+                .bind(n_rounds as i64)
+                .bind(&status)
+                .execute(&mut *conn)
+                .await?;
+                Self::insert_fact(conn, &run_id, FACT_KIND_FINAL, n_rounds, &information_collected).await
+            })
+        })
+        .await
+    }
+
+    pub async fn add_plan_version(&self, run_id: &str, version: &PlanVersion) -> Result<()> {
+        let content = serde_json::to_string(version)?;
+        Self::insert_fact(&mut *self.pool.acquire().await?, run_id, FACT_KIND_PLAN_VERSION, version.version, &content).await
+    }
+
+    async fn insert_fact(conn: &mut PgConnection, run_id: &str, kind: &str, round: usize, content: &str) -> Result<()> {
+        sqlx::query(&RunFact::insert_sql())
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(run_id)
+            .bind(kind)
+            .bind(round as i64)
+            .bind(content)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn recent_runs(&self, user_id: &str, limit: i64) -> Result<Vec<RunRecord>> {
+        self.list_runs(user_id, None, limit).await
+    }
+
+    /// 用户的运行，按创建时间倒序，status 给出时只返回该状态的运行
+    pub async fn list_runs(&self, user_id: &str, status: Option<&str>, limit: i64) -> Result<Vec<RunRecord>> {
+        let recs = sqlx::query_as::<_, RunRecord>(
+            r#"
+            SELECT * FROM runs WHERE user_id = $1 AND ($2::TEXT IS NULL OR status = $2)
+            ORDER BY created_at DESC LIMIT $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(status)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(recs)
+    }
+
+    pub async fn run_detail(&self, run_id: &str) -> Result<Option<RunDetail>> {
+        let run = sqlx::query_as::<_, RunRecord>(r#"SELECT * FROM runs WHERE id = $1"#)
+            .bind(run_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        let run = match run {
+            Some(run) => run,
+            None => return Ok(None),
+        };
+
+        let facts = sqlx::query_as::<_, RunFact>(
+            r#"SELECT * FROM run_facts WHERE run_id = $1 ORDER BY created_at, round"#,
+        )
+        .bind(run_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Some(RunDetail { run, facts }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_run_record_roundtrip() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let pool = PgPool::connect(&database_url).await?;
+        let store = RunStore::new(pool);
+        store.migrate().await?;
+
+        let run = store.start_run(Some("test-user"), "find three menus").await?;
+        let outcomes = vec![StepOutcome {
+            step_index: 0,
+            step_id: "a3f2c9e1".to_string(),
+            title: "Locate the first menu".to_string(),
+            summary: "Found the menu of Cafe A".to_string(),
+        }];
+        store.checkpoint(&run.id, Some("{\"steps\":[]}"), &outcomes, "Cafe A menu found", 3).await?;
+        store.finish_run(&run.id, Some("{\"steps\":[]}"), &outcomes, "Here are the menus", "All menus found", None, 6, RUN_STATUS_COMPLETED).await?;
+
+        let detail = store.run_detail(&run.id).await?.expect("run should exist");
+        assert_eq!(detail.run.task, "find three menus");
+        assert_eq!(detail.run.status, RUN_STATUS_COMPLETED);
+        assert_eq!(detail.run.final_answer.as_deref(), Some("Here are the menus"));
+        assert_eq!(detail.run.n_rounds, 6);
+        let stored: Vec<StepOutcome> = serde_json::from_str(&detail.run.step_outcomes_json)?;
+        assert_eq!(stored, outcomes);
+        assert_eq!(detail.facts.len(), 2);
+        assert_eq!(detail.facts.last().unwrap().kind, FACT_KIND_FINAL);
+
+        let recent = store.recent_runs("test-user", 10).await?;
+        assert!(recent.iter().any(|r| r.id == run.id));
+        Ok(())
+    }
+}
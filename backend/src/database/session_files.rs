@@ -0,0 +1,79 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+use crate::clients::PostgresClient;
+use crate::common::ModuleClient;
+use crate::storage::BlobRef;
+
+/// 用户上传到会话中的文件，内容保存在外部存储中，只能通过所属会话访问
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct SessionFileRecord {
+    pub id: String,
+    pub session_id: String,
+    /// 上传时的文件名
+    pub name: String,
+    pub store: String,
+    pub key: String,
+    pub mime: String,
+    pub size: i64,
+    pub created_at: i64,
+}
+
+/// session_files 表的读写，表结构由 DomainSchema 迁移；文件随会话删除，内容由保留策略清理
+#[derive(Debug, Clone)]
+pub struct SessionFileStore {
+    pool: PgPool,
+}
+
+impl SessionFileStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub fn from_client(client: &PostgresClient) -> Self {
+        let pool: &PgPool = ***client.get_client();
+        Self::new(pool.clone())
+    }
+
+    pub async fn add_file(&self, session_id: &str, name: &str, blob: &BlobRef) -> Result<SessionFileRecord> {
+        let rec = sqlx::query_as::<_, SessionFileRecord>(
+            r#"
+            INSERT INTO session_files (id, session_id, name, store, key, mime, size)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+            "#,
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(session_id)
+        .bind(name)
+        .bind(&blob.store)
+        .bind(&blob.key)
+        .bind(&blob.mime)
+        .bind(blob.size as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(rec)
+    }
+
+    // 其他会话的文件与不存在的一样
+    pub async fn get_file(&self, session_id: &str, id: &str) -> Result<Option<SessionFileRecord>> {
+        let rec = sqlx::query_as::<_, SessionFileRecord>(r#"SELECT * FROM session_files WHERE id = $1 AND session_id = $2"#)
+            .bind(id)
+            .bind(session_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(rec)
+    }
+
+    /// 按上传顺序
+    pub async fn list_files(&self, session_id: &str) -> Result<Vec<SessionFileRecord>> {
+        let recs = sqlx::query_as::<_, SessionFileRecord>(
+            r#"SELECT * FROM session_files WHERE session_id = $1 ORDER BY created_at, id"#,
+        )
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(recs)
+    }
+}
@@ -0,0 +1,316 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+
+use crate::clients::PostgresClient;
+use crate::common::ModuleClient;
+use crate::database::migrations::DomainSchema;
+use crate::database::retention::RetentionStore;
+use crate::database::runs::create_if_missing;
+use crate::database::tx::with_tx;
+use crate::database::{SchemaMigrator, SqlxSchema};
+
+/// 一个会话：同一个用户围绕一个任务的多轮消息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct SessionRecord {
+    pub id: String,
+    pub user_id: Option<String>,
+    pub title: String,
+    pub status: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    /// 置顶的会话不会被保留策略清理
+    #[sqlx(default)]
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// 会话中的一条消息，content_json 为消息内容的 JSON，seq 决定消息的先后
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct SessionMessage {
+    pub id: String,
+    pub seq: i64,
+    pub session_id: String,
+    pub role: String,
+    pub source: String,
+    pub content_json: serde_json::Value,
+    pub created_at: i64,
+}
+
+/// 分页读取的消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessagePage {
+    pub messages: Vec<SessionMessage>,
+    pub total: i64,
+    pub offset: i64,
+    pub limit: i64,
+}
+
+pub const SESSION_STATUS_IDLE: &str = "idle";
+pub const SESSION_STATUS_QUEUED: &str = "queued";
+pub const SESSION_STATUS_RUNNING: &str = "running";
+
+impl SqlxSchema for SessionRecord {
+    type Id = String;
+    type Row = SessionRecord;
+
+    const TABLE_NAME: &'static str = "sessions";
+    const ID_COLUMN_NAME: &'static str = "id";
+    const COLUMNS: &'static [&'static str] = &["id", "user_id", "title", "status", "created_at", "updated_at"];
+    const INDEXES_SQL: &'static [&'static str] = &[
+        "CREATE INDEX IF NOT EXISTS idx_sessions_user_created ON sessions (user_id, created_at DESC)",
+    ];
+
+    fn get_id_value(&self) -> Self::Id {
+        self.id.clone()
+    }
+
+    fn from_row(row: Self::Row) -> Self {
+        row
+    }
+
+    fn create_table_sql() -> String {
+        r#"
+        CREATE TABLE IF NOT EXISTS sessions (
+            id TEXT PRIMARY KEY,
+            user_id TEXT,
+            title TEXT NOT NULL DEFAULT '',
+            status TEXT NOT NULL,
+            created_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now())),
+            updated_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now()))
+        )
+        "#.to_string()
+    }
+
+    fn drop_table_sql() -> String {
+        "DROP TABLE IF EXISTS sessions CASCADE".to_string()
+    }
+
+    fn insert_sql() -> String {
+        r#"
+        INSERT INTO sessions (id, user_id, title, status)
+        VALUES ($1, $2, $3, $4)
+        RETURNING *
+        "#.to_string()
+    }
+
+    fn trigger_sql() -> String {
+        r#"
+        DROP TRIGGER IF EXISTS trg_sessions_updated_at ON sessions;
+        CREATE TRIGGER trg_sessions_updated_at BEFORE UPDATE ON sessions
+            FOR EACH ROW EXECUTE FUNCTION set_updated_at_unix_timestamp();
+        "#.to_string()
+    }
+}
+
+impl SqlxSchema for SessionMessage {
+    type Id = String;
+    type Row = SessionMessage;
+
+    const TABLE_NAME: &'static str = "messages";
+    const ID_COLUMN_NAME: &'static str = "id";
+    const COLUMNS: &'static [&'static str] = &["id", "seq", "session_id", "role", "source", "content_json", "created_at"];
+    const INDEXES_SQL: &'static [&'static str] = &[
+        "CREATE INDEX IF NOT EXISTS idx_messages_session_seq ON messages (session_id, seq)",
+    ];
+
+    fn get_id_value(&self) -> Self::Id {
+        self.id.clone()
+    }
+
+    fn from_row(row: Self::Row) -> Self {
+        row
+    }
+
+    fn create_table_sql() -> String {
+        r#"
+        CREATE TABLE IF NOT EXISTS messages (
+            id TEXT PRIMARY KEY,
+            seq BIGINT GENERATED ALWAYS AS IDENTITY,
+            session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+            role TEXT NOT NULL,
+            source TEXT NOT NULL,
+            content_json JSONB NOT NULL,
+            created_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now()))
+        )
+        "#.to_string()
+    }
+
+    fn drop_table_sql() -> String {
+        "DROP TABLE IF EXISTS messages".to_string()
+    }
+
+    fn insert_sql() -> String {
+        r#"
+        INSERT INTO messages (id, session_id, role, source, content_json)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *
+        "#.to_string()
+    }
+
+    fn trigger_sql() -> String {
+        String::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl SchemaMigrator for SessionRecord {
+    async fn migrate(pool: &PgPool) -> Result<()> {
+        create_if_missing::<SessionRecord>(pool).await
+    }
+}
+
+#[async_trait::async_trait]
+impl SchemaMigrator for SessionMessage {
+    async fn migrate(pool: &PgPool) -> Result<()> {
+        create_if_missing::<SessionMessage>(pool).await
+    }
+}
+
+/// 会话和消息的读写，供 HTTP 层使用
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    pool: PgPool,
+}
+
+impl SessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub fn from_client(client: &PostgresClient) -> Self {
+        let pool: &PgPool = ***client.get_client();
+        Self::new(pool.clone())
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    pub async fn migrate(&self) -> Result<()> {
+        DomainSchema::migrate(&self.pool).await
+    }
+
+    pub async fn create_session(&self, user_id: Option<&str>, title: &str) -> Result<SessionRecord> {
+        let rec = sqlx::query_as::<_, SessionRecord>(&SessionRecord::insert_sql())
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(user_id)
+            .bind(title)
+            .bind(SESSION_STATUS_IDLE)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(rec)
+    }
+
+    // 只返回属于 user_id 的会话，其他用户的会话与不存在的一样
+    pub async fn get_session(&self, id: &str, user_id: &str) -> Result<Option<SessionRecord>> {
+        let rec = sqlx::query_as::<_, SessionRecord>(r#"SELECT * FROM sessions WHERE id = $1 AND user_id = $2"#)
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(rec)
+    }
+
+    pub async fn set_status(&self, id: &str, status: &str) -> Result<()> {
+        Self::set_status_in(&mut *self.pool.acquire().await?, id, status).await
+    }
+
+    /// 同 set_status，在调用方的事务中执行
+    pub async fn set_status_in(conn: &mut PgConnection, id: &str, status: &str) -> Result<()> {
+        sqlx::query(r#"UPDATE sessions SET status = $2 WHERE id = $1"#)
+            .bind(id)
+            .bind(status)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// 启动时排队和运行中的会话都已经没有执行器在处理，恢复为 idle，返回更新的数量
+    pub async fn reset_active_sessions(&self) -> Result<u64> {
+        let result = sqlx::query(r#"UPDATE sessions SET status = $1 WHERE status IN ($2, $3)"#)
+            .bind(SESSION_STATUS_IDLE)
+            .bind(SESSION_STATUS_QUEUED)
+            .bind(SESSION_STATUS_RUNNING)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// 置顶或取消置顶，会话不属于 user_id 时返回 None
+    pub async fn set_pinned(&self, id: &str, user_id: &str, pinned: bool) -> Result<Option<SessionRecord>> {
+        let rec = sqlx::query_as::<_, SessionRecord>(
+            r#"UPDATE sessions SET pinned = $3 WHERE id = $1 AND user_id = $2 RETURNING *"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(pinned)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(rec)
+    }
+
+    // 消息随外键级联删除，产物的内容交给保留策略清理，返回是否删除了会话
+    pub async fn delete_session(&self, id: &str, user_id: &str) -> Result<bool> {
+        let deleted = with_tx(&self.pool, |conn| {
+            let (id, user_id) = (id.to_string(), user_id.to_string());
+            Box::pin(async move {
+                let ids: Vec<String> =
+                    sqlx::query_scalar(r#"SELECT id FROM sessions WHERE id = $1 AND user_id = $2 FOR UPDATE"#)
+                        .bind(&id)
+                        .bind(&user_id)
+                        .fetch_all(&mut *conn)
+                        .await?;
+                RetentionStore::delete_sessions_in(conn, &ids).await
+            })
+        })
+        .await?;
+        Ok(deleted > 0)
+    }
+
+    pub async fn add_message(
+        &self,
+        session_id: &str,
+        role: &str,
+        source: &str,
+        content: serde_json::Value,
+    ) -> Result<SessionMessage> {
+        Self::add_message_in(&mut *self.pool.acquire().await?, session_id, role, source, content).await
+    }
+
+    /// 同 add_message，在调用方的事务中执行
+    pub async fn add_message_in(
+        conn: &mut PgConnection,
+        session_id: &str,
+        role: &str,
+        source: &str,
+        content: serde_json::Value,
+    ) -> Result<SessionMessage> {
+        let rec = sqlx::query_as::<_, SessionMessage>(&SessionMessage::insert_sql())
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(session_id)
+            .bind(role)
+            .bind(source)
+            .bind(content)
+            .fetch_one(&mut *conn)
+            .await?;
+        Ok(rec)
+    }
+
+    // 按写入顺序分页
+    pub async fn list_messages(&self, session_id: &str, offset: i64, limit: i64) -> Result<MessagePage> {
+        let total: i64 = sqlx::query_scalar(r#"SELECT COUNT(*) FROM messages WHERE session_id = $1"#)
+            .bind(session_id)
+            .fetch_one(&self.pool)
+            .await?;
+        let messages = sqlx::query_as::<_, SessionMessage>(
+            r#"SELECT * FROM messages WHERE session_id = $1 ORDER BY seq OFFSET $2 LIMIT $3"#,
+        )
+        .bind(session_id)
+        .bind(offset)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(MessagePage { messages, total, offset, limit })
+    }
+}
@@ -95,7 +95,7 @@ impl OrderDirection {
 /// A trait to allow for boxing of different types that can be encoded as sqlx arguments.
 /// This is a helper for the `QueryCriteria` struct to store argument values of different types.
 pub trait AsSqlxArg: Send + Sync {
-    fn add_to_args<'q>(&self, args: &mut PgArguments) -> Result<(), SqlxError>;
+    fn add_to_args(&self, args: &mut PgArguments) -> Result<(), SqlxError>;
 }
 
 /// A blanket implementation of AsSqlxArg for any type that meets the bounds.
@@ -104,7 +104,7 @@ impl<T> AsSqlxArg for T
 where
     T: for<'a> sqlx::Encode<'a, Postgres> + sqlx::Type<Postgres> + Send + Sync + Clone + 'static,
 {
-    fn add_to_args<'q>(&self, args: &mut PgArguments) -> Result<(), SqlxError> {
+    fn add_to_args(&self, args: &mut PgArguments) -> Result<(), SqlxError> {
         use sqlx::Arguments;
         args.add(self.clone());
         Ok(())
@@ -0,0 +1,164 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use sqlx::{PgConnection, PgPool};
+
+// serialization_failure 和 deadlock_detected，整个事务重做即可
+const RETRYABLE_SQLSTATES: &[&str] = &["40001", "40P01"];
+
+/// 事务冲突时的重试：最多重试 max_retries 次，第 n 次前等待 base_delay * 2^n 以内的随机时间
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxRetry {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for TxRetry {
+    fn default() -> Self {
+        Self { max_retries: 5, base_delay: Duration::from_millis(20), max_delay: Duration::from_secs(1) }
+    }
+}
+
+impl TxRetry {
+    // 带抖动的指数退避，避免冲突的两个事务同时重试再次冲突
+    fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self.base_delay.saturating_mul(1 << retry.min(16)).min(self.max_delay);
+        let jitter = RandomState::new().build_hasher().finish() % 1000;
+        ceiling.mul_f64((jitter + 1) as f64 / 1000.0)
+    }
+}
+
+/* 在 REPEATABLE READ 事务中执行 f，成功时提交，出错时回滚；
+序列化冲突或死锁时整个事务按 TxRetry::default() 重做，所以 f 可能被调用多次。
+f 中只能做数据库读写，模型调用、浏览器操作等耗时的工作放在事务之外 */
+pub async fn with_tx<T, F>(pool: &PgPool, f: F) -> Result<T>
+where
+    F: for<'c> FnMut(&'c mut PgConnection) -> BoxFuture<'c, Result<T>>,
+{
+    with_tx_retry(pool, &TxRetry::default(), f).await
+}
+
+pub async fn with_tx_retry<T, F>(pool: &PgPool, retry: &TxRetry, mut f: F) -> Result<T>
+where
+    F: for<'c> FnMut(&'c mut PgConnection) -> BoxFuture<'c, Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        let error = match run_once(pool, &mut f).await {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        if attempt >= retry.max_retries || !is_retryable(&error) {
+            return Err(error);
+        }
+        let delay = retry.backoff(attempt);
+        attempt += 1;
+        tracing::debug!("Retrying transaction after a conflict ({}/{}): {:#}", attempt, retry.max_retries, error);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+async fn run_once<T, F>(pool: &PgPool, f: &mut F) -> Result<T>
+where
+    F: for<'c> FnMut(&'c mut PgConnection) -> BoxFuture<'c, Result<T>>,
+{
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ").execute(&mut *tx).await?;
+    match f(&mut tx).await {
+        Ok(value) => {
+            tx.commit().await?;
+            Ok(value)
+        }
+        Err(e) => {
+            // 连接已经断开时回滚也会失败，返回原来的错误
+            if let Err(rollback) = tx.rollback().await {
+                tracing::debug!("Failed to roll back transaction: {}", rollback);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// 错误链中是否有可以重试的数据库错误
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| match cause.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Database(db)) => db.code().is_some_and(|code| RETRYABLE_SQLSTATES.contains(&code.as_ref())),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::RunStore;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::sync::Barrier;
+
+    #[test]
+    fn test_backoff_is_bounded() {
+        let retry = TxRetry::default();
+        for attempt in 0..40 {
+            let delay = retry.backoff(attempt);
+            assert!(delay > Duration::ZERO);
+            assert!(delay <= retry.max_delay);
+        }
+        assert!(retry.backoff(0) <= retry.base_delay);
+        assert!(!is_retryable(&anyhow::anyhow!("not a database error")));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DATABASE_URL"]
+    async fn test_conflicting_transactions_are_retried() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL")?;
+        let pool = PgPool::connect(&database_url).await?;
+        let runs = RunStore::new(pool.clone());
+        runs.migrate().await?;
+        let run = runs.start_run(Some("test-user"), "tx").await?;
+
+        // 两个事务先读到同一个快照，再更新同一行：后提交的一方冲突并重做
+        let barrier = Arc::new(Barrier::new(2));
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let append = |suffix: &'static str| {
+            let (pool, barrier, attempts, run_id) = (pool.clone(), barrier.clone(), attempts.clone(), run.id.clone());
+            async move {
+                let first_try = Arc::new(std::sync::atomic::AtomicBool::new(true));
+                with_tx(&pool, |conn| {
+                    let (barrier, attempts, run_id, first_try) =
+                        (barrier.clone(), attempts.clone(), run_id.clone(), first_try.clone());
+                    Box::pin(async move {
+                        attempts.fetch_add(1, Ordering::SeqCst);
+                        let task: String = sqlx::query_scalar(r#"SELECT task FROM runs WHERE id = $1"#)
+                            .bind(&run_id)
+                            .fetch_one(&mut *conn)
+                            .await?;
+                        if first_try.swap(false, Ordering::SeqCst) {
+                            barrier.wait().await;
+                        }
+                        sqlx::query(r#"UPDATE runs SET task = $2 WHERE id = $1"#)
+                            .bind(&run_id)
+                            .bind(format!("{}{}", task, suffix))
+                            .execute(&mut *conn)
+                            .await?;
+                        Ok(())
+                    })
+                })
+                .await
+            }
+        };
+        let (a, b) = tokio::join!(append("-a"), append("-b"));
+        a?;
+        b?;
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        let task: String = sqlx::query_scalar(r#"SELECT task FROM runs WHERE id = $1"#).bind(&run.id).fetch_one(&pool).await?;
+        assert!(task == "tx-a-b" || task == "tx-b-a", "unexpected task {}", task);
+        sqlx::query(r#"DELETE FROM runs WHERE id = $1"#).bind(&run.id).execute(&pool).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::{anyhow, Result};
+use pgvector::Vector;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+use crate::clients::TextEmbedder;
+
+/// 向量索引的类型，距离都按余弦距离计算
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum VectorIndexKind {
+    /// 需要先有数据再建索引，lists 一般取行数的平方根
+    IvfFlat { lists: u32 },
+    /// 可以从空表开始增长
+    Hnsw { m: u32, ef_construction: u32 },
+}
+
+/// 一条检索结果，distance 为余弦距离，越小越相似
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct VectorMatch {
+    pub id: String,
+    pub distance: f64,
+    pub metadata: serde_json::Value,
+}
+
+/* pgvector 库中的向量表：每行是 id、向量和 JSONB 元数据，计划记忆和页面记忆都建在它上面。
+向量始终以参数绑定，表名和列名不能绑定，只接受小写字母、数字和下划线 */
+#[derive(Debug, Clone)]
+pub struct VectorStore {
+    pool: PgPool,
+    dimension: usize,
+}
+
+impl VectorStore {
+    pub fn new(pool: PgPool, dimension: usize) -> Self {
+        Self { pool, dimension }
+    }
+
+    /// 维度与嵌入模型一致
+    pub fn for_embedder(pool: PgPool, embedder: &dyn TextEmbedder) -> Self {
+        Self::new(pool, embedder.dimension())
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    pub async fn ensure_extension(&self) -> Result<()> {
+        sqlx::query("CREATE EXTENSION IF NOT EXISTS vector").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// 建表：id、embedding vector(dimension)、metadata JSONB，可重复执行
+    pub async fn create_table(&self, table: &str) -> Result<()> {
+        let table = identifier(table)?;
+        sqlx::query(&format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {table} (
+                id TEXT PRIMARY KEY,
+                embedding vector({dimension}) NOT NULL,
+                metadata JSONB NOT NULL DEFAULT '{{}}',
+                updated_at BIGINT NOT NULL DEFAULT floor(extract(epoch from now()))
+            )
+            "#,
+            table = table,
+            dimension = self.dimension
+        ))
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(&format!(
+            "CREATE INDEX IF NOT EXISTS idx_{table}_metadata ON {table} USING gin (metadata jsonb_path_ops)",
+            table = table
+        ))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn create_index(&self, table: &str, column: &str, kind: VectorIndexKind) -> Result<()> {
+        sqlx::query(&index_sql(table, column, kind)?).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn upsert_embedding(
+        &self,
+        table: &str,
+        id: &str,
+        embedding: &[f32],
+        metadata: serde_json::Value,
+    ) -> Result<()> {
+        let table = identifier(table)?;
+        check_dimension(self.dimension, embedding)?;
+        sqlx::query(&format!(
+            r#"
+            INSERT INTO {table} (id, embedding, metadata)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (id) DO UPDATE
+            SET embedding = EXCLUDED.embedding, metadata = EXCLUDED.metadata,
+                updated_at = floor(extract(epoch from now()))
+            "#,
+            table = table
+        ))
+        .bind(id)
+        .bind(Vector::from(embedding.to_vec()))
+        .bind(metadata)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 按余弦距离升序返回最近的 k 条；filter 为元数据需要包含的 JSON，例如 {"user_id": "…"}
+    pub async fn search(
+        &self,
+        table: &str,
+        query: &[f32],
+        k: usize,
+        filter: Option<&serde_json::Value>,
+    ) -> Result<Vec<VectorMatch>> {
+        let table = identifier(table)?;
+        check_dimension(self.dimension, query)?;
+        let matches = sqlx::query_as::<_, VectorMatch>(&format!(
+            r#"
+            SELECT id, (embedding <=> $1)::FLOAT8 AS distance, metadata
+            FROM {table}
+            WHERE ($2::JSONB IS NULL OR metadata @> $2::JSONB)
+            ORDER BY embedding <=> $1
+            LIMIT $3
+            "#,
+            table = table
+        ))
+        .bind(Vector::from(query.to_vec()))
+        .bind(filter.cloned())
+        .bind(k as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(matches)
+    }
+}
+
+pub fn check_dimension(expected: usize, embedding: &[f32]) -> Result<()> {
+    if embedding.len() != expected {
+        return Err(anyhow!(
+            "Vector dimension mismatch: expected {} (the embedder's dimension), got {}",
+            expected,
+            embedding.len()
+        ));
+    }
+    Ok(())
+}
+
+fn identifier(name: &str) -> Result<&str> {
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && name.len() <= 63;
+    if valid {
+        Ok(name)
+    } else {
+        Err(anyhow!("Invalid table or column name '{}'", name))
+    }
+}
+
+fn index_sql(table: &str, column: &str, kind: VectorIndexKind) -> Result<String> {
+    let table = identifier(table)?;
+    let column = identifier(column)?;
+    Ok(match kind {
+        VectorIndexKind::IvfFlat { lists } => format!(
+            "CREATE INDEX IF NOT EXISTS idx_{table}_{column}_ivfflat ON {table} USING ivfflat ({column} vector_cosine_ops) WITH (lists = {lists})",
+            table = table,
+            column = column,
+            lists = lists.max(1)
+        ),
+        VectorIndexKind::Hnsw { m, ef_construction } => format!(
+            "CREATE INDEX IF NOT EXISTS idx_{table}_{column}_hnsw ON {table} USING hnsw ({column} vector_cosine_ops) WITH (m = {m}, ef_construction = {ef})",
+            table = table,
+            column = column,
+            m = m.max(2),
+            ef = ef_construction.max(4)
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_index_sql_and_identifiers() -> Result<()> {
+        assert_eq!(
+            index_sql("page_memory", "embedding", VectorIndexKind::Hnsw { m: 16, ef_construction: 64 })?,
+            "CREATE INDEX IF NOT EXISTS idx_page_memory_embedding_hnsw ON page_memory USING hnsw (embedding vector_cosine_ops) WITH (m = 16, ef_construction = 64)"
+        );
+        assert!(index_sql("page_memory", "embedding", VectorIndexKind::IvfFlat { lists: 10 })?.ends_with("WITH (lists = 10)"));
+        assert!(identifier("plans; DROP TABLE plans").is_err());
+        assert!(identifier("Plans").is_err());
+        assert!(identifier("").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dimension_mismatch() -> Result<()> {
+        let store = VectorStore::new(PgPool::connect_lazy("postgres://localhost/unused")?, 4);
+        let error = store.search("page_memory", &[0.1, 0.2], 3, None).await.unwrap_err();
+        assert!(error.to_string().contains("expected 4 (the embedder's dimension), got 2"));
+        assert!(store.upsert_embedding("page_memory", "a", &[0.0; 5], json!({})).await.is_err());
+        Ok(())
+    }
+
+    // 可重复的伪随机数，避免引入 rand
+    fn random_vectors(count: usize, dimension: usize) -> Vec<Vec<f32>> {
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((state >> 33) as f32 / (1u64 << 31) as f32) * 2.0 - 1.0
+        };
+        (0..count).map(|_| (0..dimension).map(|_| next()).collect()).collect()
+    }
+
+    fn cosine_distance(a: &[f32], b: &[f32]) -> f64 {
+        let dot: f64 = a.iter().zip(b).map(|(x, y)| *x as f64 * *y as f64).sum();
+        let norm = |v: &[f32]| v.iter().map(|x| (*x as f64).powi(2)).sum::<f64>().sqrt();
+        1.0 - dot / (norm(a) * norm(b))
+    }
+
+    // 需要 PGVECTOR_URI，未设置时跳过
+    #[tokio::test]
+    async fn test_search_matches_brute_force() -> Result<()> {
+        dotenv::dotenv().ok();
+        let database_url = match std::env::var("PGVECTOR_URI") {
+            Ok(url) => url,
+            Err(_) => return Ok(()),
+        };
+        let store = VectorStore::new(PgPool::connect(&database_url).await?, 8);
+        let table = format!("test_vectors_{}", uuid::Uuid::new_v4().simple());
+        store.ensure_extension().await?;
+        store.create_table(&table).await?;
+
+        let vectors = random_vectors(100, 8);
+        for (i, vector) in vectors.iter().enumerate() {
+            let user = if i % 2 == 0 { "alice" } else { "bob" };
+            store.upsert_embedding(&table, &format!("v{}", i), vector, json!({ "user_id": user })).await?;
+        }
+        let query = &random_vectors(101, 8)[100];
+
+        // 没有索引时是精确检索，结果应与逐个计算的一致
+        let mut expected: Vec<(String, f64)> = vectors
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i % 2 == 0)
+            .map(|(i, v)| (format!("v{}", i), cosine_distance(query, v)))
+            .collect();
+        expected.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        let found = store.search(&table, query, 5, Some(&json!({ "user_id": "alice" }))).await?;
+        assert_eq!(found.iter().map(|m| m.id.clone()).collect::<Vec<_>>(), expected.iter().take(5).map(|e| e.0.clone()).collect::<Vec<_>>());
+        for (found, expected) in found.iter().zip(&expected) {
+            assert!((found.distance - expected.1).abs() < 1e-4);
+            assert_eq!(found.metadata["user_id"], "alice");
+        }
+        assert_eq!(store.search(&table, query, 200, None).await?.len(), 100);
+
+        store.create_index(&table, "embedding", VectorIndexKind::Hnsw { m: 16, ef_construction: 64 }).await?;
+        store.create_index(&table, "embedding", VectorIndexKind::Hnsw { m: 16, ef_construction: 64 }).await?;
+        sqlx::query(&format!("DROP TABLE {}", table)).execute(&store.pool).await?;
+        Ok(())
+    }
+}
@@ -1,9 +1,14 @@
 pub mod agents;
+pub mod cli;
 pub mod clients;
 pub mod common;
+pub mod config;
 pub mod tools;
 pub mod orchestrator;
 pub mod api;
 pub mod database;
+pub mod storage;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
 
 pub use database::{SqlxSchema, SchemaMigrator};
\ No newline at end of file
@@ -1,12 +1,420 @@
-use anyhow::Result;
-use mini_magentic_backend::clients::PostgresClient;
+use anyhow::{Context, Result};
+use mini_magentic_backend::agents::DryRunLog;
+use mini_magentic_backend::api::{serve, AppState, QuotaGate, Readiness, RetentionJob, RunExecutor, ServerFactory};
+use mini_magentic_backend::api::replay::{replay, ReplayClient};
+use mini_magentic_backend::cli::args::{ConfigCommand, KeysCommand, MigrateArgs, ReplayArgs, ReportArgs};
+use mini_magentic_backend::cli::{
+    describe_models, error_json, Cli, CliActionGuard, Command, GlobalArgs, Interrupts, LineEditor, OutputFormat, RunArgs, TaskHistory,
+    TerminalRunner,
+};
+use mini_magentic_backend::clients::{EmbederClient, ModelRegistry, PgvectorClient, PostgresClient};
 use mini_magentic_backend::common::ModuleClient;
-use std::path::Path;
+use mini_magentic_backend::config::{user_config_path, AppConfig};
+use mini_magentic_backend::database::migrations::{applied_versions, migrate_down, migrate_up};
+use mini_magentic_backend::database::{
+    ApiKeyStore, ArtifactIndex, PgPlanStore, QuotaStore, RetentionStore, RunStore, SessionFileStore, SessionStore,
+};
+use mini_magentic_backend::orchestrator::plan::Plan;
+use mini_magentic_backend::orchestrator::report::RunReport;
+use mini_magentic_backend::tools::approval_guard::ActionGuard;
+use mini_magentic_backend::tools::chrome::chrome_ctrl::Chrome;
+use mini_magentic_backend::tools::chrome::{BrowserPool, BrowserPoolOptions, ChromeFactory};
+use colored::Colorize;
+use std::io::IsTerminal;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use sqlx::PgPool;
+use tokio::net::TcpListener;
+
+// 启动时依赖未就绪，两次检查之间的间隔
+const READY_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv::from_path(Path::new("backend/.env")).ok();
-    println!("DATABASE_URL = {:?}", std::env::var("DATABASE_URL"));
-    let _postgres = PostgresClient::setup_connection().await;
-    println!("postgres 创建成功");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let terminal = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+    let (global, command) = match Cli::parse_args(&args, terminal) {
+        Ok(parsed) => parsed,
+        // --help 和 --version 照常输出；--output json 时参数错误也输出为 JSON
+        Err(e) if e.use_stderr() && Cli::wants_json(&args) => {
+            println!("{}", error_json(&anyhow::anyhow!("{}", e.render().to_string().trim().trim_start_matches("error: "))));
+            std::process::exit(1);
+        }
+        Err(e) => e.exit(),
+    };
+    // 日志写到 stderr，stdout 只有运行输出（--output json 时为 NDJSON）
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(global.log_filter())
+        .init();
+    // 输出重定向到文件或管道时不带颜色
+    if !std::io::stdout().is_terminal() {
+        colored::control::set_override(false);
+    }
+    let load_config = || load_config(&global, global.config_overrides());
+    let skip_ready = match command {
+        // 在终端中执行任务，不连接数据库；自己加载配置，--output json 时加载失败也输出为 JSON
+        Command::Run(args) => return run_in_terminal(&global, args, false, None).await,
+        Command::Plan(args) => {
+            let output = args.output_file.clone();
+            return run_in_terminal(&global, args.into(), true, output).await;
+        }
+        Command::Resume(args) => return run_in_terminal(&global, args.into(), false, None).await,
+        Command::Interactive => return interactive(&global, &load_config()?).await,
+        // replay 通过 HTTP 接口读取事件，不连接数据库
+        Command::Replay(args) => return replay_run(&load_config()?, args).await,
+        // report 只读写运行目录，不需要配置
+        Command::Report(args) => return report(args),
+        Command::Config { command: ConfigCommand::Init { path } } => return init_config(path),
+        Command::Config { command: ConfigCommand::Show } => {
+            print!("{}", load_config()?.show()?);
+            return Ok(());
+        }
+        Command::Migrate(args) => {
+            let postgres = PostgresClient::connect(&load_config()?.database).await?;
+            let pool: &PgPool = ***postgres.get_client();
+            return migrate(pool, args).await;
+        }
+        Command::Keys { command } => {
+            let postgres = PostgresClient::connect(&load_config()?.database).await?;
+            RunStore::from_client(&postgres).migrate().await?;
+            return keys(&ApiKeyStore::from_client(&postgres), command).await;
+        }
+        Command::Serve(args) => args.skip_ready,
+    };
+    let config = load_config()?;
+    let postgres = PostgresClient::connect(&config.database).await?;
+    let run_store = RunStore::from_client(&postgres);
+    run_store.migrate().await?;
+    let sessions = SessionStore::from_client(&postgres);
+
+    let blobs = config.blob_store()?;
+    // 每次运行从池中借一个浏览器，运行结束后归还
+    let browsers =
+        BrowserPool::new(Arc::new(ChromeFactory::new(config.browser.clone())), BrowserPoolOptions::from_settings(&config.browser));
+    let mut factory = ServerFactory::from_config(&config, browsers.clone(), None)?;
+    let plans = plan_store().await?;
+    if let Some(store) = &plans {
+        factory = factory.with_plan_library(Arc::new(store.clone()));
+    }
+    let runs = RunExecutor::new(
+        Arc::new(factory),
+        run_store.clone(),
+        sessions.clone(),
+        blobs.clone(),
+        config.server.run_queue_size,
+    );
+    runs.set_quotas(QuotaGate::new(QuotaStore::from_client(&postgres), config.quotas.clone()));
+    // 模型调用指标按配置的服务地址和模型区分
+    let provider = config
+        .llm
+        .base_url
+        .as_deref()
+        .and_then(|url| url::Url::parse(url).ok())
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_else(|| "unknown".to_string());
+    runs.set_model_labels(&provider, config.llm.model.as_deref().unwrap_or("unknown"));
+    let stale = runs.recover().await?;
+    if !stale.failed.is_empty() {
+        tracing::warn!("Marked {} runs without a checkpoint as failed", stale.failed.len());
+    }
+    if config.server.auto_resume {
+        for run_id in &stale.interrupted {
+            if let Err(e) = runs.resume(run_id, None).await {
+                tracing::warn!("Failed to resume run {}: {:#}", run_id, e);
+            }
+        }
+    } else if !stale.interrupted.is_empty() {
+        tracing::warn!("{} interrupted runs can be resumed with POST /api/runs/:id/resume", stale.interrupted.len());
+    }
+    runs.start(config.server.run_concurrency);
+
+    // 后台预热浏览器，chromedriver 不可用时只给出警告，运行借用时再重试
+    let warming = browsers.clone();
+    tokio::spawn(async move {
+        if let Err(e) = warming.warm_up().await {
+            tracing::warn!("Failed to warm up the browser pool: {:#}", e);
+        }
+    });
+    // 依赖都可用之后才开始监听，--skip-ready 跳过等待（例如本地开发时没有 chromedriver）
+    let pool: &PgPool = ***postgres.get_client();
+    let readiness = Readiness::from_config(&config, pool.clone(), browsers.clone());
+    if skip_ready {
+        tracing::warn!("Skipping the readiness checks, dependencies may be unavailable");
+    } else {
+        tracing::info!("Waiting for the database, model service and browsers to become ready");
+        readiness.wait_until_ready(READY_RETRY_INTERVAL).await;
+    }
+
+    let listener = TcpListener::bind(&config.server.bind)
+        .await
+        .with_context(|| format!("Failed to bind {}", config.server.bind))?;
+    tracing::info!("Listening on {}", listener.local_addr()?);
+    let api_keys = ApiKeyStore::from_client(&postgres);
+    let artifacts = ArtifactIndex::from_client(&postgres);
+    let retention = RetentionJob::new(RetentionStore::from_client(&postgres), blobs.clone(), config.retention.policy);
+    let cleanup = config.retention.enabled.then(|| retention.spawn(Duration::from_secs(config.retention.interval_secs)));
+    let state = AppState {
+        sessions,
+        api_keys,
+        runs: runs.clone(),
+        run_store,
+        blobs,
+        artifacts,
+        files: SessionFileStore::from_client(&postgres),
+        browsers: browsers.clone(),
+        retention,
+        readiness,
+        plans,
+        config: Arc::new(config),
+    };
+    serve(listener, state, async {
+        tokio::signal::ctrl_c().await.ok();
+        tracing::info!("Shutting down");
+    })
+    .await?;
+    if let Some(cleanup) = cleanup {
+        cleanup.abort();
+    }
+    runs.shutdown().await;
+    browsers.close().await;
+    Ok(())
+}
+
+// keys 子命令：create 生成 key 并只打印这一次明文，list 列出用户的 key，revoke 撤销
+async fn keys(store: &ApiKeyStore, command: KeysCommand) -> Result<()> {
+    match command {
+        KeysCommand::Create { user_id, label } => {
+            let minted = store.mint(&user_id, label.as_deref().unwrap_or("")).await?;
+            println!("Created key {} for {}", minted.record.id, minted.record.user_id);
+            println!("{}", minted.key);
+            println!("Store it now, it cannot be shown again");
+        }
+        KeysCommand::List { user_id } => {
+            for key in store.list(&user_id).await? {
+                let state = if key.revoked_at.is_some() { "revoked" } else { "active" };
+                println!("{}\t{}\t{}\t{}", key.id, state, key.created_at, key.label);
+            }
+        }
+        KeysCommand::Revoke { key_id } => {
+            if store.revoke(&key_id).await? {
+                println!("Revoked key {}", key_id);
+            } else {
+                anyhow::bail!("No active key {}", key_id);
+            }
+        }
+    }
+    Ok(())
+}
+
+// replay 子命令：按原来的节奏在终端中重新展示一次运行的事件，API key 从 MAGENTIC_API_KEY 读取
+async fn replay_run(config: &AppConfig, args: ReplayArgs) -> Result<()> {
+    let max_gap = args.max_gap.map(|seconds| Duration::from_secs_f64(seconds.max(0.0)));
+    let base_url = args.url.unwrap_or_else(|| format!("http://{}", config.server.bind));
+    let api_key = std::env::var("MAGENTIC_API_KEY").context("MAGENTIC_API_KEY must be set to an API key")?;
+    let events = ReplayClient::new(&base_url, &api_key).fetch_all(&args.run_id).await?;
+    replay(&events, &mut std::io::stdout(), max_gap).await
+}
+
+// config init：写出带注释的模板，没有给出路径时写到 ~/.magentic/config.toml
+fn init_config(path: Option<String>) -> Result<()> {
+    let path = path
+        .map(PathBuf::from)
+        .or_else(user_config_path)
+        .context("HOME is not set; give the path of the config file")?;
+    let path = AppConfig::init_file(path)?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+// report 子命令：用运行目录中的 run.json 重新生成 report.html
+fn report(args: ReportArgs) -> Result<()> {
+    let path = RunReport::regenerate(&args.run_dir)?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+/* run、plan 和 resume 子命令：在终端中规划并执行一个任务（plan 只输出计划，resume 从检查点继续）后退出，失败时退出码非 0。
+plan -o 时再把计划写到 plan_output。
+--plan-file 时执行文件中的计划，不调用模型规划。web_surfer 使用本机的 chromedriver；不提问，需要审批的动作按 [approval] 的策略处理（--approve-all 全部批准）。
+--quiet 时每个步骤只输出一行，输出到终端时显示步骤用时；--output json 时输出 NDJSON，参数和配置的错误也输出为 JSON。
+Ctrl+C 停止运行：代理中止当前动作，输出部分进度的总结和 resume 的命令，浏览器关闭后退出；3 秒内再按一次立即退出 */
+async fn run_in_terminal(global: &GlobalArgs, args: RunArgs, plan_only: bool, plan_output: Option<PathBuf>) -> Result<()> {
+    // 配置在启动浏览器之前检查
+    let overrides = [global.config_overrides(), args.config_overrides()].concat();
+    let config = match load_config(global, overrides) {
+        Ok(config) => config,
+        Err(e) => return exit_with(global, e),
+    };
+    // 计划文件同样在启动浏览器之前读取，任务默认取自文件
+    let plan = match args.plan_file.as_deref().map(Plan::from_json_file).transpose() {
+        Ok(plan) => plan,
+        Err(e) => return exit_with(global, e),
+    };
+    let task = match &plan {
+        Some(plan) if args.task().is_empty() => plan.task.clone().unwrap_or_default(),
+        _ => args.task(),
+    };
+    // 不询问用户时每次访问都会被拒绝的配置在调用模型之前报错；只规划和演练不访问网站
+    if !plan_only && !args.dry_run {
+        if let Err(e) = config.check_unattended_navigation() {
+            return exit_with(global, e);
+        }
+    }
+    let browsers = terminal_browsers(&config);
+    let json = global.output == OutputFormat::Json;
+    // 在终端中继续会话时先显示检查点并询问是否继续
+    let interrupts = Interrupts::install();
+    let confirm = args.resume.is_some() && !json && std::io::stdin().is_terminal();
+    let guard = confirm.then(|| {
+        Arc::new(CliActionGuard::from_config(&config).interruptible(interrupts.clone()).locale(global.locale()))
+    });
+    let activity = guard.as_ref().map(|guard| guard.activity());
+    let prompt = guard.map(|guard| guard as Arc<dyn ActionGuard>);
+    let mut factory = match ServerFactory::from_config(&config, browsers.clone(), prompt) {
+        Ok(factory) => factory,
+        Err(e) => return exit_with(global, e),
+    };
+    // 演练不启动浏览器，运行结束后输出报告
+    let dry_run = args.dry_run.then(DryRunLog::default);
+    if let Some(log) = &dry_run {
+        factory = factory.with_dry_run(log.clone(), config.sites.clone());
+    } else if plan_only {
+        // 只规划时不分发任何步骤，同样不需要浏览器
+        factory = factory.with_dry_run(DryRunLog::default(), config.sites.clone());
+    }
+    let models = ModelRegistry::from_config(&config).summary(config.llm.provider.as_deref());
+    if !json {
+        println!("{}", describe_models(&models).dimmed());
+    }
+    let mut runner = TerminalRunner::non_interactive(Arc::new(factory))
+        .models(models)
+        .output(global.output)
+        .quiet(args.quiet)
+        .timed(!json && std::io::stdout().is_terminal())
+        .interruptible(interrupts)
+        .session_dir(config.output.session_dir.as_ref().map(PathBuf::from))
+        .approve_plans(confirm)
+        .dry_run(dry_run)
+        .preset_plan(plan)
+        .locale(global.locale());
+    if let Some(activity) = activity {
+        runner = runner.pause_during(activity);
+    }
+    let result = match &args.resume {
+        Some(dir) => runner.resume(Path::new(dir), &mut std::io::stdout()).await,
+        None if plan_only => runner.plan(&task, &mut std::io::stdout()).await,
+        None => runner.run(&task, &mut std::io::stdout()).await,
+    };
+    browsers.close().await;
+    let outcome = match result {
+        // 错误已经由 runner 输出为 JSON
+        Err(_) if json => std::process::exit(1),
+        result => result?,
+    };
+    if let Some(path) = plan_output {
+        if let Err(e) = write_plan_file(outcome.plan.as_ref(), &path) {
+            return exit_with(global, e);
+        }
+        if !json {
+            println!("Wrote {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+// plan -o：把生成的计划写成计划文件，目录不存在时创建；直接回答、没有计划的任务不写文件
+fn write_plan_file(plan: Option<&Plan>, path: &Path) -> Result<()> {
+    let plan = plan.with_context(|| format!("The task was answered without a plan; nothing was written to {}", path.display()))?;
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    plan.to_json_file(path)
+}
+
+// 按各层配置文件、环境变量和命令行加载配置，命令行的网站策略合并在 [sites] 之上；-v 时输出最终的网站策略
+fn load_config(global: &GlobalArgs, overrides: Vec<String>) -> Result<AppConfig> {
+    let mut config = AppConfig::load(&global.config_sources(overrides))?;
+    config.sites = config.sites.merge(global.site_policy()?);
+    config.sites.validate()?;
+    tracing::debug!("Site policy:\n{}", config.sites.table());
+    Ok(config)
+}
+
+// 运行前的错误：--output json 时输出为 JSON 后以退出码 1 退出
+fn exit_with(global: &GlobalArgs, error: anyhow::Error) -> Result<()> {
+    if global.output == OutputFormat::Json {
+        println!("{}", error_json(&error));
+        std::process::exit(1);
+    }
+    Err(error)
+}
+
+/* interactive 子命令：反复询问任务并执行，直接回车或输入 exit 退出；输入过的任务保存在 [cli] history_file。
+输入 attach image <路径> 或者把图片拖进终端时，图片随下一个任务交给规划。
+需要审批的动作和步骤在终端中询问，提问期间暂停输出事件；运行中输入的消息交给 orchestrator，输入 stop 或按 Ctrl+C 停止当前任务。
+任务完成后可以继续上一个任务（之前的任务和答案随下一个任务交给规划，最多 [cli] context_tokens 个 token）或者开始新任务 */
+async fn interactive(global: &GlobalArgs, config: &AppConfig) -> Result<()> {
+    if global.output == OutputFormat::Json {
+        anyhow::bail!("--output json is only supported by the run subcommand");
+    }
+    println!("{}", describe_models(&ModelRegistry::from_config(config).summary(config.llm.provider.as_deref())).dimmed());
+    let browsers = terminal_browsers(config);
+    let interrupts = Interrupts::install();
+    let locale = global.locale();
+    let guard = Arc::new(CliActionGuard::from_config(config).interruptible(interrupts.clone()).locale(locale));
+    let activity = guard.activity();
+    let factory = ServerFactory::from_config(config, browsers.clone(), Some(guard))?;
+    let tasks = LineEditor::new(TaskHistory::from_settings(&config.cli)?)?;
+    let runner = TerminalRunner::new(Arc::new(factory))
+        .forward_stdin(true)
+        .pause_during(activity)
+        .timed(std::io::stdout().is_terminal())
+        .interruptible(interrupts)
+        .session_dir(config.output.session_dir.as_ref().map(PathBuf::from))
+        .context_tokens(config.cli.context_tokens)
+        .approve_plans(true)
+        .locale(locale);
+    let result = runner.interactive(Box::new(tasks), &mut std::io::stdout()).await;
+    browsers.close().await;
+    result
+}
+
+fn terminal_browsers(config: &AppConfig) -> BrowserPool<Chrome> {
+    BrowserPool::new(Arc::new(ChromeFactory::new(config.browser.clone())), BrowserPoolOptions::from_settings(&config.browser))
+}
+
+// 设置了 PGVECTOR_URI 和嵌入模型（EMBEDDING_BASE_URL / EMBEDDING_API_KEY）时，规划参考 pgvector 中的计划记忆，
+// GET /api/plans/similar 也从中检索
+async fn plan_store() -> Result<Option<PgPlanStore>> {
+    if ["PGVECTOR_URI", "EMBEDDING_BASE_URL", "EMBEDDING_API_KEY"].iter().any(|var| std::env::var_os(var).is_none()) {
+        return Ok(None);
+    }
+    let pgvector = PgvectorClient::setup_connection().await;
+    let store = PgPlanStore::from_client(&pgvector, Arc::new(EmbederClient::setup_connection().await));
+    store.migrate().await?;
+    Ok(Some(store))
+}
+
+// migrate 子命令：执行未执行的迁移；--down <版本> 回退到该版本，只用于本地开发
+async fn migrate(pool: &PgPool, args: MigrateArgs) -> Result<()> {
+    let changed = match args.down {
+        None => migrate_up(pool).await?,
+        Some(target) => migrate_down(pool, target).await?,
+    };
+    let verb = if args.down.is_none() { "Applied" } else { "Reverted" };
+    for migration in &changed {
+        println!("{} migration {} ({})", verb, migration.version, migration.name);
+    }
+    let current = applied_versions(pool).await?.last().copied().unwrap_or(0);
+    if changed.is_empty() {
+        println!("Schema is up to date at version {}", current);
+    } else {
+        println!("Schema is now at version {}", current);
+    }
     Ok(())
 }
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use crate::common::text::edit_distance;
+use crate::orchestrator::plan::PlanStep;
+
+// 不区分大小写，忽略下划线、连字符和空格："WebSurfer" / "web-surfer" / "websurfer" 都归一为 "websurfer"
+pub fn normalize_agent_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, '_' | '-' | ' '))
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/* 把模型给出的代理名映射到已注册的名字：先精确匹配，再查别名表，最后做归一化匹配。
+别名表的键同样按归一化比较，值必须是已注册的名字，否则忽略 */
+pub fn resolve_agent_name(name: &str, registered: &[String], aliases: &HashMap<String, String>) -> Option<String> {
+    if registered.iter().any(|r| r == name) {
+        return Some(name.to_string());
+    }
+
+    let normalized = normalize_agent_name(name);
+    let alias_target = aliases
+        .iter()
+        .find(|(alias, _)| normalize_agent_name(alias) == normalized)
+        .map(|(_, target)| target);
+    if let Some(target) = alias_target {
+        if registered.iter().any(|r| r == target) {
+            return Some(target.clone());
+        }
+    }
+
+    registered
+        .iter()
+        .find(|r| normalize_agent_name(r) == normalized)
+        .cloned()
+}
+
+/// 重映射计划步骤中的代理名，返回仍然无法识别的步骤下标
+pub fn remap_plan_steps(steps: &mut [PlanStep], registered: &[String], aliases: &HashMap<String, String>) -> Vec<usize> {
+    let mut unknown = Vec::new();
+    for (i, step) in steps.iter_mut().enumerate() {
+        match resolve_agent_name(&step.agent_name, registered, aliases) {
+            Some(name) => {
+                if name != step.agent_name {
+                    tracing::info!("Remapped agent_name '{}' to '{}' in step {}", step.agent_name, name, i + 1);
+                    step.agent_name = name;
+                }
+            }
+            None => unknown.push(i),
+        }
+    }
+    unknown
+}
+
+// 按编辑距离找最接近的名字；no_action_agent 只有在没有其他候选时才会被选中
+pub fn closest_agent_name(name: &str, registered: &[String]) -> Option<String> {
+    let normalized = normalize_agent_name(name);
+    let candidates: Vec<&String> = registered.iter().filter(|r| r.as_str() != "no_action_agent").collect();
+    let candidates = if candidates.is_empty() { registered.iter().collect() } else { candidates };
+    candidates
+        .into_iter()
+        .min_by_key(|r| edit_distance(&normalized, &normalize_agent_name(r)))
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::plan::{new_step_id, StepKind};
+
+    fn registered() -> Vec<String> {
+        vec!["web_surfer".to_string(), "coder_agent".to_string(), "no_action_agent".to_string()]
+    }
+
+    fn step(agent_name: &str) -> PlanStep {
+        PlanStep {
+            id: new_step_id(),
+            title: "title".to_string(),
+            details: "details".to_string(),
+            agent_name: agent_name.to_string(),
+            expected_outcome: None,
+            kind: StepKind::Standard,
+        }
+    }
+
+    #[test]
+    fn test_case_and_underscore_differences() {
+        let aliases = HashMap::new();
+        for name in ["web_surfer", "WebSurfer", "websurfer", "Web-Surfer", "WEB_SURFER"] {
+            assert_eq!(resolve_agent_name(name, &registered(), &aliases), Some("web_surfer".to_string()));
+        }
+        assert_eq!(resolve_agent_name("browser_agent", &registered(), &aliases), None);
+    }
+
+    #[test]
+    fn test_aliases() {
+        let aliases = HashMap::from([
+            ("browser_agent".to_string(), "web_surfer".to_string()),
+            ("coder".to_string(), "coder_agent".to_string()),
+            ("ghost".to_string(), "not_registered".to_string()),
+        ]);
+        assert_eq!(resolve_agent_name("BrowserAgent", &registered(), &aliases), Some("web_surfer".to_string()));
+        assert_eq!(resolve_agent_name("coder", &registered(), &aliases), Some("coder_agent".to_string()));
+        assert_eq!(resolve_agent_name("ghost", &registered(), &aliases), None);
+    }
+
+    #[test]
+    fn test_remap_plan_steps() {
+        let aliases = HashMap::from([("browser".to_string(), "web_surfer".to_string())]);
+        let mut steps = vec![step("WebSurfer"), step("browser"), step("file_surfer")];
+
+        let unknown = remap_plan_steps(&mut steps, &registered(), &aliases);
+
+        assert_eq!(unknown, vec![2]);
+        assert_eq!(steps[0].agent_name, "web_surfer");
+        assert_eq!(steps[1].agent_name, "web_surfer");
+        assert_eq!(steps[2].agent_name, "file_surfer");
+    }
+
+    #[test]
+    fn test_closest_agent_name() {
+        assert_eq!(closest_agent_name("web_surfing_agent", &registered()), Some("web_surfer".to_string()));
+        assert_eq!(closest_agent_name("code_runner", &registered()), Some("coder_agent".to_string()));
+        assert_eq!(
+            closest_agent_name("anything", &["no_action_agent".to_string()]),
+            Some("no_action_agent".to_string())
+        );
+    }
+}
\ No newline at end of file
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::orchestrator::message::{ChatMessage, MultiModalContent};
+
+/// metadata 中记录截图文件路径的键
+pub const SCREENSHOT_PATH_KEY: &str = "screenshot_path";
+
+/// 截图在运行目录中的子目录
+pub const SCREENSHOTS_DIR: &str = "screenshots";
+
+/// 每次运行一个目录，保存代理每一步的截图等产物
+#[derive(Debug, Clone)]
+pub struct ArtifactStore {
+    run_dir: PathBuf,
+    // 每个步骤已经保存的截图数
+    counters: HashMap<usize, usize>,
+    saved: Vec<PathBuf>,
+}
+
+impl ArtifactStore {
+    pub fn new(base_dir: impl AsRef<Path>, run_id: &str) -> Result<Self> {
+        let run_dir = base_dir.as_ref().join(run_id);
+        std::fs::create_dir_all(&run_dir)?;
+        Ok(Self { run_dir, counters: HashMap::new(), saved: Vec::new() })
+    }
+
+    pub fn run_dir(&self) -> &Path {
+        &self.run_dir
+    }
+
+    /// 保存为 screenshots/step{N}_{seq}.png，N 是从 1 开始的步骤序号，seq 在每个步骤内从 1 递增
+    pub fn save_screenshot(&mut self, step_index: usize, bytes: &[u8]) -> Result<PathBuf> {
+        let seq = self.counters.entry(step_index).or_default();
+        *seq += 1;
+        let dir = self.run_dir.join(SCREENSHOTS_DIR);
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("step{}_{}.png", step_index + 1, seq));
+        std::fs::write(&path, bytes)?;
+        self.saved.push(path.clone());
+        Ok(path)
+    }
+
+    pub fn saved(&self) -> &[PathBuf] {
+        &self.saved
+    }
+}
+
+// 多模态消息中的最后一张图片
+pub fn last_image(message: &ChatMessage) -> Option<&Vec<u8>> {
+    match message {
+        ChatMessage::MultiModal { content, .. } => content.iter().rev().find_map(|part| match part {
+            MultiModalContent::Image(bytes) if !bytes.is_empty() => Some(bytes),
+            _ => None,
+        }),
+        ChatMessage::Text { .. } => None,
+    }
+}
+
+/* 把代理响应中的每张截图按顺序保存到产物目录，返回保存的路径。
+响应只保留最后一张图片（见 keep_last_image），metadata 中记录它的路径 */
+pub fn store_response_screenshots(
+    message: ChatMessage,
+    step_index: usize,
+    store: &mut ArtifactStore,
+) -> Result<(ChatMessage, Vec<PathBuf>)> {
+    let mut paths = Vec::new();
+    if let ChatMessage::MultiModal { content, .. } = &message {
+        for part in content {
+            if let MultiModalContent::Image(bytes) = part {
+                if !bytes.is_empty() {
+                    paths.push(store.save_screenshot(step_index, bytes)?);
+                }
+            }
+        }
+    }
+    let mut message = keep_last_image(message);
+    if let Some(path) = paths.last() {
+        message
+            .metadata_mut()
+            .insert(SCREENSHOT_PATH_KEY.to_string(), path.to_string_lossy().to_string());
+    }
+    Ok((message, paths))
+}
+
+/// 代理响应带着步骤中途的截图，放进对话历史前只保留最后一张
+pub fn keep_last_image(message: ChatMessage) -> ChatMessage {
+    match message {
+        ChatMessage::MultiModal { role, source, content, metadata } => {
+            let last = content.iter().rposition(|part| matches!(part, MultiModalContent::Image(_)));
+            let content = content
+                .into_iter()
+                .enumerate()
+                .filter(|(i, part)| !matches!(part, MultiModalContent::Image(_)) || Some(*i) == last)
+                .map(|(_, part)| part)
+                .collect();
+            ChatMessage::MultiModal { role, source, content, metadata }
+        }
+        message => message,
+    }
+}
+
+/// 给最终答案附上最后一张截图，CLI 可以另存，后端可以展示
+pub fn attach_screenshot(message: ChatMessage, screenshot: &[u8], path: Option<&str>) -> ChatMessage {
+    let (role, source, mut content, mut metadata) = match message {
+        ChatMessage::Text { role, source, content, metadata } => {
+            (role, source, vec![MultiModalContent::Text(content)], metadata)
+        }
+        ChatMessage::MultiModal { role, source, content, metadata } => (role, source, content, metadata),
+    };
+    content.push(MultiModalContent::Image(screenshot.to_vec()));
+    if let Some(path) = path {
+        metadata.insert(SCREENSHOT_PATH_KEY.to_string(), path.to_string());
+    }
+    ChatMessage::MultiModal { role, source, content, metadata }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::message::MessageRole;
+
+    fn final_page() -> ChatMessage {
+        ChatMessage::new_multimodal(
+            MessageRole::Assistant,
+            "web_surfer".to_string(),
+            vec![
+                MultiModalContent::Text("The menu is shown on the page".to_string()),
+                MultiModalContent::Image(vec![0x89, b'P', b'N', b'G']),
+            ],
+        )
+    }
+
+    // 步骤中途的每个动作之后各有一张截图，最后一张是结束时的页面
+    fn step_pages(pages: u8) -> ChatMessage {
+        let mut content = vec![MultiModalContent::Text("The menu is shown on the page".to_string())];
+        content.extend((1..=pages).map(|page| MultiModalContent::Image(vec![0x89, b'P', b'N', b'G', page])));
+        ChatMessage::new_multimodal(MessageRole::Assistant, "web_surfer".to_string(), content)
+    }
+
+    #[test]
+    fn test_store_response_screenshot_writes_artifact() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = ArtifactStore::new(dir.path(), "run-1")?;
+
+        let (message, paths) = store_response_screenshots(final_page(), 0, &mut store)?;
+
+        let path = message.metadata().get(SCREENSHOT_PATH_KEY).expect("screenshot path recorded");
+        assert_eq!(Path::new(path), dir.path().join("run-1/screenshots/step1_1.png"));
+        assert_eq!(std::fs::read(path)?, vec![0x89, b'P', b'N', b'G']);
+        assert_eq!(paths, vec![PathBuf::from(path)]);
+        assert_eq!(store.saved(), &[PathBuf::from(path)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_every_screenshot_of_a_step_is_saved() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = ArtifactStore::new(dir.path(), "run-1")?;
+
+        let (message, _) = store_response_screenshots(step_pages(3), 0, &mut store)?;
+        // 同一步骤再次分发时接着编号
+        store_response_screenshots(step_pages(1), 0, &mut store)?;
+        store_response_screenshots(step_pages(2), 1, &mut store)?;
+
+        let screenshots = dir.path().join("run-1/screenshots");
+        let mut names: Vec<_> = std::fs::read_dir(&screenshots)?
+            .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().to_string()))
+            .collect::<std::io::Result<_>>()?;
+        names.sort();
+        assert_eq!(names, ["step1_1.png", "step1_2.png", "step1_3.png", "step1_4.png", "step2_1.png", "step2_2.png"]);
+        assert_eq!(std::fs::read(screenshots.join("step1_2.png"))?, vec![0x89, b'P', b'N', b'G', 2]);
+
+        // 历史中只留下最后一张，路径指向它
+        assert_eq!(last_image(&message), Some(&vec![0x89, b'P', b'N', b'G', 3]));
+        let ChatMessage::MultiModal { content, .. } = &message else { panic!("expected a multimodal message") };
+        assert_eq!(content.len(), 2);
+        assert_eq!(
+            message.metadata().get(SCREENSHOT_PATH_KEY).map(PathBuf::from),
+            Some(screenshots.join("step1_3.png"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_response_is_untouched() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut store = ArtifactStore::new(dir.path(), "run-1")?;
+        let message = ChatMessage::new_text(MessageRole::Assistant, "web_surfer".to_string(), "done".to_string());
+
+        let (stored, paths) = store_response_screenshots(message.clone(), 0, &mut store)?;
+        assert_eq!(stored, message);
+        assert!(paths.is_empty());
+        assert_eq!(std::fs::read_dir(store.run_dir())?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_attach_screenshot_to_final_answer() {
+        let answer = ChatMessage::new_text(MessageRole::Assistant, "orchestrator".to_string(), "Final answer: 42".to_string());
+        let message = attach_screenshot(answer, &[1, 2, 3], Some("/tmp/run-1/screenshots/step1_1.png"));
+
+        assert_eq!(
+            message.metadata().get(SCREENSHOT_PATH_KEY).map(|p| p.as_str()),
+            Some("/tmp/run-1/screenshots/step1_1.png")
+        );
+        assert_eq!(last_image(&message), Some(&vec![1, 2, 3]));
+    }
+}
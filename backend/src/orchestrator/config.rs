@@ -1,5 +1,18 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use regex::Regex;
 use serde::{Serialize, Deserialize};
+use crate::clients::{ModelInfo, ModelPricing};
+use crate::orchestrator::history::HistoryCompactionConfig;
+use crate::orchestrator::metrics::CostSummaryConfig;
+use crate::orchestrator::stall::StallDetectionConfig;
+use crate::orchestrator::validation::PlanValidationLimits;
+use crate::orchestrator::plan_library::PlanExamplesConfig;
+use crate::orchestrator::estimate::{EstimatedCost, PlanEstimateConfig};
+use crate::common::template::validate_template;
 use crate::orchestrator::plan::Plan;
+use crate::orchestrator::prompt::{FINAL_ANSWER_PLACEHOLDERS, FINAL_ANSWER_PROMPT};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrchestratorConfig {
@@ -14,7 +27,460 @@ pub struct OrchestratorConfig {
     pub saved_facts: Option<String>,
     pub allowed_websites: Option<Vec<String>>,
     pub do_bing_search: bool,
+    /// 最终答案提示词模板，可用占位符 {task} {progress_summary} {plan}
     pub final_answer_prompt: Option<String>,
+    /// 从文件加载最终答案提示词模板，优先于 final_answer_prompt
+    #[serde(default)]
+    pub final_answer_prompt_file: Option<String>,
     pub model_context_token_limit: Option<usize>,
+    /// orchestrator 使用的模型能力
+    #[serde(default)]
+    pub model_info: ModelInfo,
+    /// 用于估算花费，未设置时 cost 为 0
+    #[serde(default)]
+    pub pricing: Option<ModelPricing>,
+    /// 运行结束时按代理汇总的花费表，以及按代理覆盖的价格
+    #[serde(default)]
+    pub cost_summary: CostSummaryConfig,
     pub retrieve_relevant_plans: Option<String>,
+    #[serde(default)]
+    pub step_approval: StepApprovalPolicy,
+    /// 每隔多少轮把 information_collected 写一次检查点，0 表示只在结束时写入
+    #[serde(default = "default_checkpoint_every_n_rounds")]
+    pub checkpoint_every_n_rounds: usize,
+    /// 代理执行失败时的默认重试策略
+    #[serde(default)]
+    pub retry_policy: RetryPolicy,
+    /// 按步骤下标覆盖的重试策略
+    #[serde(default)]
+    pub step_retry_policies: HashMap<usize, RetryPolicy>,
+    /// 群聊历史压缩
+    #[serde(default)]
+    pub history_compaction: HistoryCompactionConfig,
+    /// 保存截图等运行产物的目录，每次运行一个子目录；未设置时不保存
+    #[serde(default)]
+    pub artifacts_dir: Option<String>,
+    /// 会话检查点目录，计划被接受和每个步骤完成时写入，可以用 Orchestrator::resume_session 恢复；未设置时不写
+    #[serde(default)]
+    pub session_dir: Option<String>,
+    /// 除 stop / cancel / 停止 之外额外识别的停止指令
+    #[serde(default)]
+    pub stop_commands: Vec<String>,
+    /// 代理名的别名，键为模型可能给出的名字，值为注册的代理名，例如 browser_agent: web_surfer
+    #[serde(default)]
+    pub agent_aliases: HashMap<String, String>,
+    /// ledger 在同一步骤上反复给出相同指令时的停滞检测
+    #[serde(default)]
+    pub stall_detection: StallDetectionConfig,
+    /// 广播通知时每个代理的超时
+    #[serde(default = "default_notify_timeout_ms")]
+    pub notify_timeout_ms: u64,
+    /// 是否允许规划出周期检查的哨兵步骤，关闭时计划中出现哨兵字段会被拒绝
+    #[serde(default)]
+    pub sentinel_tasks_enabled: bool,
+    /// 计划的步骤数和 details 长度上限
+    #[serde(default)]
+    pub plan_validation: PlanValidationLimits,
+    /// 规划前注入的相似成功计划
+    #[serde(default)]
+    pub plan_examples: PlanExamplesConfig,
+    /// 执行前的开销估算和超出阈值时的额外确认
+    #[serde(default)]
+    pub plan_estimate: PlanEstimateConfig,
+    /// 固定回答和计划使用的语言，例如 Chinese；未设置时跟随用户请求的语言
+    #[serde(default)]
+    pub force_language: Option<String>,
+    /// 计划生成后是否跳过用户确认直接执行
+    #[serde(default)]
+    pub plan_approval: PlanApprovalConfig,
+    /// 规划时逐段发出模型输出的 PlanTextStreamed 事件，供终端逐字显示；后端不需要
+    #[serde(default)]
+    pub stream_plan_text: bool,
+}
+
+fn default_checkpoint_every_n_rounds() -> usize {
+    5
+}
+
+/// 不需要用户参与的配置：直接执行生成的计划，不逐步审批；后端没有设置 orchestrator.config_file 时使用
+impl Default for OrchestratorConfig {
+    fn default() -> Self {
+        Self {
+            cooperative_planning: false,
+            autonomous_execution: true,
+            allow_follow_up_input: false,
+            max_replans: 3,
+            plan: None,
+            max_turns: Some(20),
+            allow_for_replans: true,
+            max_json_retries: 3,
+            saved_facts: None,
+            allowed_websites: None,
+            do_bing_search: false,
+            final_answer_prompt: None,
+            final_answer_prompt_file: None,
+            model_context_token_limit: None,
+            model_info: ModelInfo::default(),
+            pricing: None,
+            cost_summary: CostSummaryConfig::default(),
+            retrieve_relevant_plans: None,
+            step_approval: StepApprovalPolicy::Never,
+            checkpoint_every_n_rounds: default_checkpoint_every_n_rounds(),
+            retry_policy: RetryPolicy::default(),
+            step_retry_policies: HashMap::new(),
+            history_compaction: HistoryCompactionConfig::default(),
+            artifacts_dir: None,
+            session_dir: None,
+            stop_commands: Vec::new(),
+            agent_aliases: HashMap::new(),
+            stall_detection: StallDetectionConfig::default(),
+            notify_timeout_ms: default_notify_timeout_ms(),
+            sentinel_tasks_enabled: false,
+            plan_validation: PlanValidationLimits::default(),
+            plan_examples: PlanExamplesConfig::default(),
+            plan_estimate: PlanEstimateConfig::default(),
+            force_language: None,
+            plan_approval: PlanApprovalConfig::default(),
+            stream_plan_text: false,
+        }
+    }
+}
+
+fn default_notify_timeout_ms() -> u64 {
+    5000
+}
+
+/// 计划步骤分发前是否需要人工审批
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepApprovalPolicy {
+    #[default]
+    Never,
+    FirstStepOnly,
+    Always,
+}
+
+impl StepApprovalPolicy {
+    /// 每个步骤只审批一次，last_approved_step 记录上一次批准的步骤
+    pub fn requires_approval(&self, step_idx: usize, last_approved_step: Option<usize>) -> bool {
+        if last_approved_step == Some(step_idx) {
+            return false;
+        }
+        match self {
+            StepApprovalPolicy::Never => false,
+            StepApprovalPolicy::FirstStepOnly => step_idx == 0,
+            StepApprovalPolicy::Always => true,
+        }
+    }
+}
+
+/// 计划生成后什么情况下不再询问用户，直接开始执行
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum PlanApprovalPolicy {
+    #[default]
+    AlwaysAsk,
+    /// 估算花费的上限低于 usd 时自动批准；没有模型价格、无法估算花费时仍然询问
+    AutoApproveBelowCost { usd: f64 },
+    /// 任务匹配任一正则时自动批准，例如定期执行的可信任务
+    AutoApproveMatching { patterns: Vec<String> },
+    NeverAsk,
+}
+
+impl PlanApprovalPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlanApprovalPolicy::AlwaysAsk => "always_ask",
+            PlanApprovalPolicy::AutoApproveBelowCost { .. } => "auto_approve_below_cost",
+            PlanApprovalPolicy::AutoApproveMatching { .. } => "auto_approve_matching",
+            PlanApprovalPolicy::NeverAsk => "never_ask",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlanApprovalConfig {
+    /// 策略名和它的参数写在同一层，例如 policy: auto_approve_below_cost 加上 usd: 0.5
+    #[serde(flatten)]
+    pub policy: PlanApprovalPolicy,
+    /// 哨兵步骤估算耗时上限的安全阈值（秒），超过时不论策略如何都要用户确认
+    pub sentinel_wall_clock_cap_secs: u64,
+    /// 明确允许自动批准超过阈值的哨兵步骤
+    pub allow_long_sentinels: bool,
+}
+
+impl Default for PlanApprovalConfig {
+    fn default() -> Self {
+        Self {
+            policy: PlanApprovalPolicy::AlwaysAsk,
+            sentinel_wall_clock_cap_secs: 3600,
+            allow_long_sentinels: false,
+        }
+    }
+}
+
+impl PlanApprovalConfig {
+    /* 策略允许自动批准时返回批准的原因，需要用户确认时返回 None。
+    longest_sentinel_secs 为计划中估算耗时最长的哨兵步骤，超过安全阈值时总是需要确认 */
+    pub fn auto_approval(
+        &self,
+        task: &str,
+        estimate: &EstimatedCost,
+        longest_sentinel_secs: Option<u64>,
+    ) -> Option<String> {
+        if let Some(secs) = longest_sentinel_secs {
+            if secs > self.sentinel_wall_clock_cap_secs && !self.allow_long_sentinels {
+                return None;
+            }
+        }
+        match &self.policy {
+            PlanApprovalPolicy::AlwaysAsk => None,
+            PlanApprovalPolicy::AutoApproveBelowCost { usd } => {
+                let (_, max) = estimate.usd_range?;
+                (max < *usd).then(|| format!("the estimated cost of up to ${:.2} is below ${:.2}", max, usd))
+            }
+            PlanApprovalPolicy::AutoApproveMatching { patterns } => patterns
+                .iter()
+                .find(|pattern| Regex::new(pattern).is_ok_and(|regex| regex.is_match(task)))
+                .map(|pattern| format!("the task matches '{}'", pattern)),
+            PlanApprovalPolicy::NeverAsk => Some("plans are never confirmed".to_string()),
+        }
+    }
+}
+
+/// 代理步骤失败后的重试策略，max_attempts 包含第一次执行
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    pub max_attempts: usize,
+    pub initial_backoff_ms: u64,
+    pub backoff_multiplier: f64,
+    pub max_backoff_ms: u64,
+    /// 单次执行的超时，超时按失败处理；未设置时不限时
+    #[serde(default)]
+    pub step_timeout_ms: Option<u64>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff_ms: 1000,
+            backoff_multiplier: 2.0,
+            max_backoff_ms: 10_000,
+            step_timeout_ms: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn no_retry() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    // 第 attempt 次失败之后（从 1 开始）等待的时间
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        let factor = self.backoff_multiplier.max(1.0).powi(attempt.saturating_sub(1) as i32);
+        let ms = (self.initial_backoff_ms as f64 * factor).min(self.max_backoff_ms as f64);
+        Duration::from_millis(ms as u64)
+    }
+
+    pub fn step_timeout(&self) -> Option<Duration> {
+        self.step_timeout_ms.map(Duration::from_millis)
+    }
+}
+
+impl OrchestratorConfig {
+    // 依次使用模板文件、内联模板、默认模板
+    pub fn final_answer_template(&self) -> Result<String> {
+        if let Some(path) = &self.final_answer_prompt_file {
+            return std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read final answer prompt from {}", path));
+        }
+        Ok(self
+            .final_answer_prompt
+            .clone()
+            .unwrap_or_else(|| FINAL_ANSWER_PROMPT.to_string()))
+    }
+
+    // 启动时校验配置，模板占位符写错时直接报错
+    pub fn validate(&self) -> Result<()> {
+        let template = self.final_answer_template()?;
+        validate_template(&template, FINAL_ANSWER_PLACEHOLDERS).context("Invalid final answer prompt")?;
+        if let PlanApprovalPolicy::AutoApproveMatching { patterns } = &self.plan_approval.policy {
+            for pattern in patterns {
+                Regex::new(pattern).with_context(|| format!("Invalid plan approval pattern '{}'", pattern))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 代理使用的价格，没有单独配置时和 orchestrator 相同
+    pub fn agent_pricing(&self, agent_name: &str) -> Option<&ModelPricing> {
+        self.cost_summary.agent_pricing.get(agent_name).or(self.pricing.as_ref())
+    }
+
+    pub fn retry_policy_for_step(&self, step_idx: usize) -> &RetryPolicy {
+        self.step_retry_policies.get(&step_idx).unwrap_or(&self.retry_policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_approval_policy() {
+        assert!(!StepApprovalPolicy::Never.requires_approval(0, None));
+
+        assert!(StepApprovalPolicy::FirstStepOnly.requires_approval(0, None));
+        assert!(!StepApprovalPolicy::FirstStepOnly.requires_approval(0, Some(0)));
+        assert!(!StepApprovalPolicy::FirstStepOnly.requires_approval(1, Some(0)));
+
+        assert!(StepApprovalPolicy::Always.requires_approval(0, None));
+        assert!(!StepApprovalPolicy::Always.requires_approval(1, Some(1)));
+        assert!(StepApprovalPolicy::Always.requires_approval(2, Some(1)));
+    }
+
+    #[test]
+    fn test_step_approval_policy_serde() {
+        let policy: StepApprovalPolicy = serde_json::from_str("\"first_step_only\"").unwrap();
+        assert_eq!(policy, StepApprovalPolicy::FirstStepOnly);
+    }
+
+    #[test]
+    fn test_retry_policy_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            initial_backoff_ms: 100,
+            backoff_multiplier: 2.0,
+            max_backoff_ms: 300,
+            step_timeout_ms: None,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_plan_approval_policies() {
+        use crate::orchestrator::estimate::{estimate, longest_sentinel_wall_clock};
+        use crate::orchestrator::plan::{new_step_id, PlanStep, SentinelCondition, StepKind};
+
+        let pricing = ModelPricing { prompt_per_1k: 0.01, completion_per_1k: 0.03 };
+        let estimate_config = PlanEstimateConfig::default();
+        let step = |kind| PlanStep {
+            id: new_step_id(),
+            title: "Step".to_string(),
+            details: "Details".to_string(),
+            agent_name: "web_surfer".to_string(),
+            expected_outcome: None,
+            kind,
+        };
+        let plan = Plan { task: None, steps: vec![step(StepKind::Standard)] };
+        let priced = estimate(&plan, &estimate_config, Some(&pricing));
+        let unpriced = estimate(&plan, &estimate_config, None);
+        let (_, max_usd) = priced.usd_range.unwrap();
+
+        let mut config = PlanApprovalConfig::default();
+        assert_eq!(config.auto_approval("Check the weather", &priced, None), None);
+
+        config.policy = PlanApprovalPolicy::AutoApproveBelowCost { usd: max_usd + 0.01 };
+        assert!(config.auto_approval("Check the weather", &priced, None).is_some());
+        assert_eq!(config.auto_approval("Check the weather", &unpriced, None), None);
+        config.policy = PlanApprovalPolicy::AutoApproveBelowCost { usd: max_usd };
+        assert_eq!(config.auto_approval("Check the weather", &priced, None), None);
+
+        config.policy = PlanApprovalPolicy::AutoApproveMatching { patterns: vec!["^Daily report".to_string()] };
+        assert_eq!(
+            config.auto_approval("Daily report for today", &unpriced, None),
+            Some("the task matches '^Daily report'".to_string())
+        );
+        assert_eq!(config.auto_approval("Check the weather", &unpriced, None), None);
+
+        config.policy = PlanApprovalPolicy::NeverAsk;
+        assert!(config.auto_approval("Check the weather", &unpriced, None).is_some());
+
+        // 文字条件的哨兵步骤可能一直跑到 sentinel_max_duration_secs，超过安全阈值时必须确认
+        let sentinel = Plan {
+            task: None,
+            steps: vec![step(StepKind::Sentinel {
+                sleep_duration: 600,
+                condition: SentinelCondition::Text("The price drops".to_string()),
+            })],
+        };
+        let longest = longest_sentinel_wall_clock(&sentinel, &estimate_config);
+        assert!(longest.unwrap() > config.sentinel_wall_clock_cap_secs);
+        assert_eq!(config.auto_approval("Check the weather", &unpriced, longest), None);
+        config.allow_long_sentinels = true;
+        assert!(config.auto_approval("Check the weather", &unpriced, longest).is_some());
+    }
+
+    #[test]
+    fn test_plan_approval_policy_parameters_sit_next_to_the_policy() {
+        let config: PlanApprovalConfig = serde_yaml::from_str("policy: auto_approve_below_cost\nusd: 0.5\n").unwrap();
+        assert_eq!(config.policy, PlanApprovalPolicy::AutoApproveBelowCost { usd: 0.5 });
+        assert_eq!(config.sentinel_wall_clock_cap_secs, 3600);
+
+        let config: PlanApprovalConfig = serde_yaml::from_str("policy: never_ask\nallow_long_sentinels: true\n").unwrap();
+        assert_eq!(config.policy, PlanApprovalPolicy::NeverAsk);
+        assert!(config.allow_long_sentinels);
+    }
+
+    #[test]
+    fn test_invalid_plan_approval_pattern_fails_validation() {
+        let mut config = example_config();
+        assert_eq!(config.plan_approval, PlanApprovalConfig::default());
+        config.plan_approval.policy = PlanApprovalPolicy::AutoApproveMatching { patterns: vec!["(unclosed".to_string()] };
+        assert!(config.validate().is_err());
+    }
+
+    fn example_config() -> OrchestratorConfig {
+        serde_yaml::from_str(include_str!("../../config/orchestrator.example.yaml")).unwrap()
+    }
+
+    #[test]
+    fn test_example_config_final_answer_template() -> Result<()> {
+        let config = example_config();
+        config.validate()?;
+
+        let prompt = crate::orchestrator::prompt::build_final_answer_prompt(
+            &config.final_answer_template()?,
+            "找一家评分最高的餐厅",
+            "找到了三家餐厅",
+            "[\"step 1\"]",
+        );
+        assert!(prompt.starts_with("请用中文回答。"));
+        assert!(prompt.contains("任务：找一家评分最高的餐厅"));
+        assert!(prompt.contains("已收集的信息：找到了三家餐厅"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_final_answer_template_from_file() -> Result<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        std::io::Write::write_all(&mut file, b"Answer {task} briefly.")?;
+
+        let mut config = example_config();
+        config.final_answer_prompt_file = Some(file.path().to_string_lossy().to_string());
+        assert_eq!(config.final_answer_template()?, "Answer {task} briefly.");
+
+        config.final_answer_prompt_file = None;
+        config.final_answer_prompt = None;
+        assert_eq!(config.final_answer_template()?, FINAL_ANSWER_PROMPT);
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_final_answer_template_fails_validation() {
+        let mut config = example_config();
+        config.final_answer_prompt = Some("Answer {task} using {summary".to_string());
+        assert!(config.validate().is_err());
+
+        config.final_answer_prompt = Some("Answer {tsak}".to_string());
+        assert!(config.validate().is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clients::{ModelPricing, TokenUsage};
+use crate::orchestrator::plan::{Plan, PlanStep, SentinelCondition, StepKind};
+
+/// 一个步骤（哨兵步骤的一次检查）大约的模型调用开销
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StepCostModel {
+    pub min_llm_calls: u64,
+    pub max_llm_calls: u64,
+    pub prompt_tokens_per_call: u64,
+    pub completion_tokens_per_call: u64,
+    pub seconds_per_call: u64,
+}
+
+impl Default for StepCostModel {
+    fn default() -> Self {
+        Self {
+            min_llm_calls: 1,
+            max_llm_calls: 3,
+            prompt_tokens_per_call: 2000,
+            completion_tokens_per_call: 300,
+            seconds_per_call: 5,
+        }
+    }
+}
+
+/// 计划开销估算的启发式参数和告警阈值
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanEstimateConfig {
+    /// 没有单独配置的代理使用的开销
+    pub default_step: StepCostModel,
+    /// 按代理名配置的开销，web_surfer 带截图，每次调用的 prompt 更长
+    pub agent_steps: HashMap<String, StepCostModel>,
+    /// 每个步骤 orchestrator 自己的 ledger 调用
+    pub orchestrator_calls_per_step: u64,
+    pub orchestrator_tokens_per_call: u64,
+    /// 条件为文字描述的哨兵步骤最多运行多久，用来估算检查次数的上限
+    pub sentinel_max_duration_secs: u64,
+    /// 估算的上限超过这些值时提醒用户，未设置的不检查
+    pub warn_usd: Option<f64>,
+    pub warn_wall_clock_secs: Option<u64>,
+    pub warn_llm_calls: Option<u64>,
+}
+
+impl Default for PlanEstimateConfig {
+    fn default() -> Self {
+        let web_surfer = StepCostModel {
+            min_llm_calls: 3,
+            max_llm_calls: 10,
+            prompt_tokens_per_call: 6000,
+            completion_tokens_per_call: 300,
+            seconds_per_call: 8,
+        };
+        Self {
+            default_step: StepCostModel::default(),
+            agent_steps: HashMap::from([("web_surfer".to_string(), web_surfer)]),
+            orchestrator_calls_per_step: 2,
+            orchestrator_tokens_per_call: 3000,
+            sentinel_max_duration_secs: 86_400,
+            warn_usd: Some(1.0),
+            warn_wall_clock_secs: Some(600),
+            warn_llm_calls: Some(100),
+        }
+    }
+}
+
+/// 计划执行开销的粗略估算，每一项都是 (下限, 上限)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EstimatedCost {
+    pub llm_calls: (u64, u64),
+    pub tokens: (u64, u64),
+    /// 没有模型价格时为 None
+    pub usd_range: Option<(f64, f64)>,
+    /// 秒
+    pub wall_clock_range: (u64, u64),
+}
+
+impl EstimatedCost {
+    /// 展示给用户的一行说明，明确这是近似值
+    pub fn summary(&self) -> String {
+        let cost = match self.usd_range {
+            Some((min, max)) => format!("${:.2}-${:.2}", min, max),
+            None => "cost unknown (no pricing for this model)".to_string(),
+        };
+        format!(
+            "Approximate estimate: {}-{} LLM calls, {}-{} tokens, {}, {}-{}",
+            self.llm_calls.0,
+            self.llm_calls.1,
+            self.tokens.0,
+            self.tokens.1,
+            cost,
+            format_duration(self.wall_clock_range.0),
+            format_duration(self.wall_clock_range.1),
+        )
+    }
+
+    /// 上限超过阈值的各项，为空时不需要额外确认
+    pub fn warnings(&self, config: &PlanEstimateConfig) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let (Some(limit), Some((_, max))) = (config.warn_usd, self.usd_range) {
+            if max > limit {
+                warnings.push(format!("may cost up to ${:.2} (warning threshold ${:.2})", max, limit));
+            }
+        }
+        if let Some(limit) = config.warn_wall_clock_secs {
+            if self.wall_clock_range.1 > limit {
+                warnings.push(format!(
+                    "may take up to {} (warning threshold {})",
+                    format_duration(self.wall_clock_range.1),
+                    format_duration(limit)
+                ));
+            }
+        }
+        if let Some(limit) = config.warn_llm_calls {
+            if self.llm_calls.1 > limit {
+                warnings.push(format!("may make up to {} LLM calls (warning threshold {})", self.llm_calls.1, limit));
+            }
+        }
+        warnings
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    if secs < 120 {
+        format!("{}s", secs)
+    } else if secs < 7200 {
+        format!("{}min", secs / 60)
+    } else {
+        format!("{:.1}h", secs as f64 / 3600.0)
+    }
+}
+
+// 哨兵步骤的检查次数：指定次数时固定，文字条件时至少一次，最多跑满 sentinel_max_duration_secs
+fn step_iterations(step: &PlanStep, config: &PlanEstimateConfig) -> (u64, u64) {
+    match &step.kind {
+        StepKind::Standard => (1, 1),
+        StepKind::Sentinel { condition: SentinelCondition::Count(n), .. } => {
+            let n = u64::from(*n).max(1);
+            (n, n)
+        }
+        StepKind::Sentinel { sleep_duration, condition: SentinelCondition::Text(_) } => {
+            (1, (config.sentinel_max_duration_secs / (*sleep_duration).max(1)).max(1))
+        }
+    }
+}
+
+// 一个步骤执行 iterations 次的调用次数、token 和耗时（秒）
+fn step_cost(
+    step: &PlanStep,
+    model: &StepCostModel,
+    config: &PlanEstimateConfig,
+    agent_calls: u64,
+    iterations: u64,
+) -> (u64, TokenUsage, u64) {
+    let orchestrator_calls = config.orchestrator_calls_per_step;
+    let calls = (agent_calls + orchestrator_calls) * iterations;
+    let usage = TokenUsage {
+        prompt_tokens: (agent_calls * model.prompt_tokens_per_call
+            + orchestrator_calls * config.orchestrator_tokens_per_call)
+            * iterations,
+        completion_tokens: agent_calls * model.completion_tokens_per_call * iterations,
+    };
+    let sleep = match step.kind {
+        StepKind::Sentinel { sleep_duration, .. } => sleep_duration * iterations.saturating_sub(1),
+        StepKind::Standard => 0,
+    };
+    (calls, usage, calls * model.seconds_per_call + sleep)
+}
+
+/* 按步骤累加的启发式估算：每次执行 = 代理的模型调用 + orchestrator 的 ledger 调用，
+哨兵步骤再乘以检查次数并加上检查之间的等待。结果只用于提醒，不影响执行 */
+pub fn estimate(plan: &Plan, config: &PlanEstimateConfig, pricing: Option<&ModelPricing>) -> EstimatedCost {
+    let mut calls = (0u64, 0u64);
+    let mut usage = (TokenUsage::default(), TokenUsage::default());
+    let mut wall_clock = (0u64, 0u64);
+
+    for step in &plan.steps {
+        let model = config.agent_steps.get(&step.agent_name).unwrap_or(&config.default_step);
+        let (min_iterations, max_iterations) = step_iterations(step, config);
+
+        let (min_calls, min_usage, min_secs) = step_cost(step, model, config, model.min_llm_calls, min_iterations);
+        let (max_calls, max_usage, max_secs) = step_cost(step, model, config, model.max_llm_calls, max_iterations);
+        calls.0 += min_calls;
+        calls.1 += max_calls;
+        usage.0.add(&min_usage);
+        usage.1.add(&max_usage);
+        wall_clock.0 += min_secs;
+        wall_clock.1 += max_secs;
+    }
+
+    EstimatedCost {
+        llm_calls: calls,
+        tokens: (usage.0.total(), usage.1.total()),
+        usd_range: pricing.map(|p| (p.cost(&usage.0), p.cost(&usage.1))),
+        wall_clock_range: wall_clock,
+    }
+}
+
+/// 哨兵步骤中估算耗时上限最长的一个（秒），计划没有哨兵步骤时返回 None
+pub fn longest_sentinel_wall_clock(plan: &Plan, config: &PlanEstimateConfig) -> Option<u64> {
+    plan.steps
+        .iter()
+        .filter(|step| matches!(step.kind, StepKind::Sentinel { .. }))
+        .map(|step| {
+            let model = config.agent_steps.get(&step.agent_name).unwrap_or(&config.default_step);
+            let (_, max_iterations) = step_iterations(step, config);
+            step_cost(step, model, config, model.max_llm_calls, max_iterations).2
+        })
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::plan::new_step_id;
+
+    const PRICING: ModelPricing = ModelPricing { prompt_per_1k: 0.01, completion_per_1k: 0.03 };
+
+    fn fixed_config() -> PlanEstimateConfig {
+        let web_surfer = StepCostModel {
+            min_llm_calls: 2,
+            max_llm_calls: 4,
+            prompt_tokens_per_call: 1000,
+            completion_tokens_per_call: 100,
+            seconds_per_call: 10,
+        };
+        PlanEstimateConfig {
+            default_step: StepCostModel {
+                min_llm_calls: 1,
+                max_llm_calls: 1,
+                prompt_tokens_per_call: 500,
+                completion_tokens_per_call: 100,
+                seconds_per_call: 5,
+            },
+            agent_steps: HashMap::from([("web_surfer".to_string(), web_surfer)]),
+            orchestrator_calls_per_step: 1,
+            orchestrator_tokens_per_call: 1000,
+            sentinel_max_duration_secs: 3600,
+            warn_usd: Some(1.0),
+            warn_wall_clock_secs: Some(600),
+            warn_llm_calls: Some(100),
+        }
+    }
+
+    fn step(agent_name: &str, kind: StepKind) -> PlanStep {
+        PlanStep {
+            id: new_step_id(),
+            title: "Step".to_string(),
+            details: "Details".to_string(),
+            agent_name: agent_name.to_string(),
+            expected_outcome: None,
+            kind,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn test_estimate_standard_plan() {
+        let plan = Plan {
+            task: None,
+            steps: vec![step("web_surfer", StepKind::Standard), step("coder_agent", StepKind::Standard)],
+        };
+
+        let cost = estimate(&plan, &fixed_config(), Some(&PRICING));
+
+        // web_surfer: 2-4 次 + 1 次 ledger；coder_agent 使用默认的 1 次 + 1 次 ledger
+        assert_eq!(cost.llm_calls, (5, 7));
+        // 下限: prompt 2000+1000 + 500+1000, completion 200 + 100
+        // 上限: prompt 4000+1000 + 500+1000, completion 400 + 100
+        assert_eq!(cost.tokens, (4800, 7000));
+        let (min_usd, max_usd) = cost.usd_range.unwrap();
+        assert_close(min_usd, 4.5 * 0.01 + 0.3 * 0.03);
+        assert_close(max_usd, 6.5 * 0.01 + 0.5 * 0.03);
+        assert_eq!(cost.wall_clock_range, (40, 60));
+        assert!(cost.warnings(&fixed_config()).is_empty());
+        assert!(cost.summary().starts_with("Approximate estimate: 5-7 LLM calls"));
+    }
+
+    #[test]
+    fn test_estimate_sentinel_plan() {
+        let counted = StepKind::Sentinel { sleep_duration: 60, condition: SentinelCondition::Count(3) };
+        let open_ended = StepKind::Sentinel {
+            sleep_duration: 600,
+            condition: SentinelCondition::Text("The price drops".to_string()),
+        };
+        let plan = Plan { task: None, steps: vec![step("web_surfer", counted), step("web_surfer", open_ended)] };
+
+        let cost = estimate(&plan, &fixed_config(), Some(&PRICING));
+
+        // 指定 3 次：3 × (2..4 + 1)；文字条件：1..(3600 / 600 = 6) 次
+        assert_eq!(cost.llm_calls, (9 + 3, 15 + 30));
+        // 等待时间：3 次之间 2 × 60 秒，文字条件的上限 5 × 600 秒
+        assert_eq!(cost.wall_clock_range, (90 + 120 + 30, 150 + 120 + 300 + 3000));
+
+        let warnings = cost.warnings(&fixed_config());
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("may take up to 59min"));
+
+        // 文字条件的步骤最长：30 次调用 × 10 秒 + 5 × 600 秒等待
+        assert_eq!(longest_sentinel_wall_clock(&plan, &fixed_config()), Some(300 + 3000));
+        let standard = Plan { task: None, steps: vec![step("web_surfer", StepKind::Standard)] };
+        assert_eq!(longest_sentinel_wall_clock(&standard, &fixed_config()), None);
+    }
+
+    #[test]
+    fn test_estimate_without_pricing() {
+        let plan = Plan { task: None, steps: vec![step("web_surfer", StepKind::Standard)] };
+
+        let mut config = fixed_config();
+        config.warn_llm_calls = Some(3);
+        let cost = estimate(&plan, &config, None);
+
+        assert!(cost.usd_range.is_none());
+        assert!(cost.summary().contains("cost unknown (no pricing for this model)"));
+        assert_eq!(cost.warnings(&config), vec!["may make up to 5 LLM calls (warning threshold 3)".to_string()]);
+    }
+}
\ No newline at end of file
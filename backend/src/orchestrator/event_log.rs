@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use base64::Engine;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::agents::events::AgentEvent;
+use crate::orchestrator::events::OrchestratorEvent;
+
+/* run_events 中事件的序列化版本。OrchestratorEvent 的改动让旧记录无法按原样解析时加一，
+并在 decode_event 中把旧版本转换成新的形式，已经结束的运行因此一直可以回放 */
+pub const EVENT_VERSION: i32 = 1;
+
+const MASK: &str = "********";
+const IMAGE_OMITTED: &str = "[image omitted]";
+
+lazy_static::lazy_static! {
+    // 文字中的凭据：Authorization 头、sk- 形式的模型 key、后端的 mm_ key，以及 password=… 这样的键值
+    static ref SECRET_PATTERNS: Vec<(Regex, &'static str)> = vec![
+        (Regex::new(r"(?i)\bbearer\s+[A-Za-z0-9._~+/=-]{8,}").unwrap(), "Bearer ********"),
+        (Regex::new(r"\bsk-[A-Za-z0-9_-]{16,}").unwrap(), MASK),
+        (Regex::new(r"\bmm_[0-9a-f]{16}_[A-Za-z0-9_-]+").unwrap(), MASK),
+        (
+            Regex::new(r"(?i)\b(password|passwd|api_key|apikey|secret|token)(\s*[=:]\s*)[^\s,;&]+").unwrap(),
+            "${1}${2}********",
+        ),
+    ];
+    static ref DATA_URL: Regex = Regex::new(r"data:(image/[A-Za-z0-9.+-]+);base64,([A-Za-z0-9+/=]+)").unwrap();
+}
+
+/// 事件中内嵌的图片（data URL），执行器存入 BlobStore 后在日志中换成产物的引用
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlineImage {
+    pub data_url: String,
+    pub mime: String,
+    pub bytes: Vec<u8>,
+}
+
+/// 事件 JSON 中所有可以解码的内嵌图片，同一张图片只出现一次
+pub fn inline_images(value: &Value) -> Vec<InlineImage> {
+    let mut images: Vec<InlineImage> = Vec::new();
+    visit_strings(value, &mut |text| {
+        for captures in DATA_URL.captures_iter(text) {
+            let data_url = captures[0].to_string();
+            if images.iter().any(|image| image.data_url == data_url) {
+                continue;
+            }
+            if let Ok(bytes) = base64::engine::general_purpose::STANDARD.decode(&captures[2]) {
+                images.push(InlineImage { data_url, mime: captures[1].to_string(), bytes });
+            }
+        }
+    });
+    images
+}
+
+/* 写入 run_events 和推送给客户端之前的清理：键名像凭据的字段整个遮盖，文字中的凭据替换为 ********，
+内嵌图片换成 image_refs 中对应的引用（没有时为 [image omitted]）。只改字符串的内容，清理后仍然可以解析 */
+pub fn sanitize_event(value: &mut Value, image_refs: &HashMap<String, String>) {
+    match value {
+        Value::String(text) => *text = sanitize_text(text, image_refs),
+        Value::Array(items) => items.iter_mut().for_each(|item| sanitize_event(item, image_refs)),
+        Value::Object(map) => {
+            for (key, entry) in map.iter_mut() {
+                match entry {
+                    Value::String(text) if is_secret_key(key) && !text.is_empty() => *text = MASK.to_string(),
+                    _ => sanitize_event(entry, image_refs),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 产物在日志中的引用，和 GET /api/artifacts/:key 的地址相同
+pub fn artifact_ref(key: &str) -> String {
+    format!("/api/artifacts/{}", key)
+}
+
+/// 没有内嵌图片要保存时直接清理，内嵌图片都换成 [image omitted]
+pub fn encode_event(event: &OrchestratorEvent) -> serde_json::Result<Value> {
+    let mut value = serde_json::to_value(event)?;
+    sanitize_event(&mut value, &HashMap::new());
+    Ok(value)
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    key.ends_with("api_key") || key.contains("password") || key.contains("secret") || key.ends_with("token")
+        || key == "authorization" || key == "cookie"
+}
+
+fn sanitize_text(text: &str, image_refs: &HashMap<String, String>) -> String {
+    let mut text = DATA_URL
+        .replace_all(text, |captures: &regex::Captures| {
+            image_refs.get(&captures[0]).cloned().unwrap_or_else(|| IMAGE_OMITTED.to_string())
+        })
+        .into_owned();
+    for (pattern, replacement) in SECRET_PATTERNS.iter() {
+        text = pattern.replace_all(&text, *replacement).into_owned();
+    }
+    text
+}
+
+fn visit_strings(value: &Value, visit: &mut dyn FnMut(&str)) {
+    match value {
+        Value::String(text) => visit(text),
+        Value::Array(items) => items.iter().for_each(|item| visit_strings(item, visit)),
+        Value::Object(map) => map.values().for_each(|entry| visit_strings(entry, visit)),
+        _ => {}
+    }
+}
+
+/// 从 run_events 读出的事件，当前代码不认识的变体或版本保留原始 JSON
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoggedEvent {
+    Known(OrchestratorEvent),
+    Unknown(Value),
+}
+
+pub fn decode_event(version: i32, value: Value) -> LoggedEvent {
+    match version {
+        1 => match serde_json::from_value(value.clone()) {
+            Ok(event) => LoggedEvent::Known(event),
+            Err(_) => LoggedEvent::Unknown(value),
+        },
+        _ => LoggedEvent::Unknown(value),
+    }
+}
+
+/* 事件在终端中的展示，回放使用；实时输出按界面语言渲染（cli::events::render_localized），英文时与这里相同。
+第一行是事件本身，计划等多行内容在之后的行中，步骤编号从 1 开始 */
+pub fn render_event(event: &LoggedEvent) -> Vec<String> {
+    let event = match event {
+        LoggedEvent::Known(event) => event,
+        LoggedEvent::Unknown(value) => return vec![format!("unrecognized event {}", value)],
+    };
+    match event {
+        OrchestratorEvent::PlanStepStreamed { step_index, step } => {
+            vec![format!("planning step {}: {} [{}]", step_index + 1, step.title, step.agent_name)]
+        }
+        // 片段由终端直接接着输出，不单独成行
+        OrchestratorEvent::PlanTextStreamed { .. } => Vec::new(),
+        OrchestratorEvent::PlanReady { plan } => {
+            let mut lines = vec![format!("plan ready with {} steps", plan.steps.len())];
+            for (index, step) in plan.steps.iter().enumerate() {
+                lines.push(format!("  {}. {} [{}]", index + 1, step.title, step.agent_name));
+            }
+            lines
+        }
+        OrchestratorEvent::PlanEstimated { estimate, warnings } => {
+            let mut lines = vec![format!("estimate: {}", estimate.summary())];
+            lines.extend(warnings.iter().map(|warning| format!("  warning: {}", warning)));
+            lines
+        }
+        OrchestratorEvent::PlanAutoApproved { policy, reason } => {
+            vec![format!("plan approved automatically ({}): {}", policy, reason)]
+        }
+        OrchestratorEvent::SessionResumed { step_index, remaining_steps } => {
+            vec![format!("resumed at step {}, {} steps remaining", step_index + 1, remaining_steps)]
+        }
+        OrchestratorEvent::StepApprovalRequested { step_index, title, .. } => {
+            vec![format!("step {} waiting for approval: {}", step_index + 1, title)]
+        }
+        OrchestratorEvent::StepApproved { step_index, edited } => {
+            let suffix = if *edited { " with edits" } else { "" };
+            vec![format!("step {} approved{}", step_index + 1, suffix)]
+        }
+        OrchestratorEvent::StepRejected { step_index, reason } => match reason {
+            Some(reason) => vec![format!("step {} rejected: {}", step_index + 1, reason)],
+            None => vec![format!("step {} rejected", step_index + 1)],
+        },
+        OrchestratorEvent::StepStarted { step_index, agent_name, instruction } => {
+            vec![format!("step {} -> {}: {}", step_index + 1, agent_name, instruction)]
+        }
+        OrchestratorEvent::LedgerEvaluated { step_index, step_complete, need_to_replan, next_speaker } => {
+            let state = match (step_complete, need_to_replan) {
+                (_, true) => "needs a new plan",
+                (true, false) => "complete",
+                (false, false) => "in progress",
+            };
+            vec![format!("step {} {}, next speaker {}", step_index + 1, state, next_speaker)]
+        }
+        OrchestratorEvent::AgentEvent { agent_name, event, .. } => {
+            let line = match event {
+                AgentEvent::Thought { text } => format!("thought: {}", text),
+                AgentEvent::ActionProposed { action, explanation } => format!("action: {} ({})", action, explanation),
+                // 结果缩进在对应的动作下面
+                AgentEvent::ActionResult { action, result } => {
+                    return vec![format!("    [{}] result of {}: {}", agent_name, action, result)];
+                }
+            };
+            vec![format!("  [{}] {}", agent_name, line)]
+        }
+        OrchestratorEvent::ScreenshotSaved { agent_name, path, .. } => {
+            vec![format!("    [{}] screenshot saved to {}", agent_name, path)]
+        }
+        OrchestratorEvent::AgentNameReassigned { step_index, requested, assigned } => {
+            vec![format!("step {} reassigned from {} to {}", step_index + 1, requested, assigned)]
+        }
+        OrchestratorEvent::AgentNotRegistered { step_index, agent_name } => {
+            vec![format!("step {}: agent {} is not registered, instruction not dispatched", step_index + 1, agent_name)]
+        }
+        OrchestratorEvent::StallDetected { step_index, rounds } => {
+            vec![format!("step {} stalled for {} rounds, replanning", step_index + 1, rounds)]
+        }
+        OrchestratorEvent::SentinelWaiting { step_index, check, total, next_check_secs } => {
+            let check = match total {
+                Some(total) => format!("{}/{}", check, total),
+                None => check.to_string(),
+            };
+            vec![format!("step {} check {} done, next check in {}s", step_index + 1, check, next_check_secs)]
+        }
+        OrchestratorEvent::Cancelled { step_index, reason } => {
+            vec![format!("cancelled at step {}: {}", step_index + 1, reason)]
+        }
+        OrchestratorEvent::Metrics { metrics } => {
+            let tokens = metrics.orchestrator_usage.total()
+                + metrics.agent_usage.values().map(|usage| usage.total()).sum::<u64>();
+            vec![format!(
+                "finished after {} rounds and {} replans, {} tokens, {:.1}s",
+                metrics.rounds,
+                metrics.replans,
+                tokens,
+                metrics.total_duration_ms as f64 / 1000.0
+            )]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_secrets_and_images_are_sanitized() {
+        let png = base64::engine::general_purpose::STANDARD.encode([0x89, b'P', b'N', b'G', 1, 2, 3]);
+        let data_url = format!("data:image/png;base64,{}", png);
+        let event = OrchestratorEvent::AgentEvent {
+            agent_name: "web_surfer".to_string(),
+            step_index: 1,
+            event: AgentEvent::ActionResult {
+                action: "input_text".to_string(),
+                result: format!(
+                    "Typed password=hunter2 with Bearer abcdefghijklmnop and sk-0123456789abcdefXYZ, screenshot {}",
+                    data_url
+                ),
+            },
+        };
+        let mut value = serde_json::to_value(&event).unwrap();
+
+        let images = inline_images(&value);
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].mime, "image/png");
+        assert_eq!(images[0].bytes, vec![0x89, b'P', b'N', b'G', 1, 2, 3]);
+
+        let refs = HashMap::from([(data_url.clone(), artifact_ref("abc.png"))]);
+        sanitize_event(&mut value, &refs);
+        assert_eq!(
+            value["event"]["result"],
+            "Typed password=******** with Bearer ******** and ********, screenshot /api/artifacts/abc.png"
+        );
+        // 清理之后仍然是同一个变体
+        assert!(matches!(decode_event(EVENT_VERSION, value), LoggedEvent::Known(OrchestratorEvent::AgentEvent { .. })));
+
+        let mut value = json!({ "headers": { "Authorization": "Basic dXNlcjpwdw==" }, "api_key": "k", "image": data_url });
+        sanitize_event(&mut value, &HashMap::new());
+        assert_eq!(value, json!({ "headers": { "Authorization": MASK }, "api_key": MASK, "image": IMAGE_OMITTED }));
+    }
+
+    #[test]
+    fn test_unknown_events_stay_readable() {
+        let event = OrchestratorEvent::StallDetected { step_index: 2, rounds: 3 };
+        let value = encode_event(&event).unwrap();
+        assert_eq!(decode_event(EVENT_VERSION, value.clone()), LoggedEvent::Known(event));
+
+        // 之后删除的变体和更新的版本都按原始 JSON 展示
+        let removed = json!({ "type": "browser_crashed", "step_index": 0 });
+        let decoded = decode_event(1, removed.clone());
+        assert_eq!(decoded, LoggedEvent::Unknown(removed.clone()));
+        assert_eq!(render_event(&decoded), vec![format!("unrecognized event {}", removed)]);
+        assert_eq!(decode_event(EVENT_VERSION + 1, value.clone()), LoggedEvent::Unknown(value));
+    }
+}
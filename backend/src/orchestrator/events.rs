@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+
+use crate::agents::events::AgentEvent;
+use crate::orchestrator::estimate::EstimatedCost;
+use crate::orchestrator::plan::{Plan, PlanStep};
+use crate::orchestrator::metrics::OrchestratorMetrics;
+
+/// orchestrator 对外广播的事件，CLI / 后端订阅后用于展示和记录
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OrchestratorEvent {
+    /// 规划模型还在输出时已经完整生成的步骤；JSON 重试时会从第 0 步重新发送
+    PlanStepStreamed {
+        step_index: usize,
+        step: PlanStep,
+    },
+    /// 规划模型输出的一段文字（或 emit_plan 参数的一段），设置了 stream_plan_text 时才发送
+    PlanTextStreamed {
+        text: String,
+    },
+    /// 通过完整校验的计划，规划和重规划之后各发送一次
+    PlanReady {
+        plan: Plan,
+    },
+    /// 新计划的开销估算，warnings 为超出阈值的项
+    PlanEstimated {
+        estimate: EstimatedCost,
+        warnings: Vec<String>,
+    },
+    /// 计划按 plan_approval 策略自动批准，不再询问用户
+    PlanAutoApproved {
+        policy: String,
+        reason: String,
+    },
+    /// 从会话检查点恢复，从 step_index 继续执行
+    SessionResumed {
+        step_index: usize,
+        remaining_steps: usize,
+    },
+    StepApprovalRequested {
+        step_index: usize,
+        title: String,
+        instruction: String,
+    },
+    StepApproved {
+        step_index: usize,
+        edited: bool,
+    },
+    StepRejected {
+        step_index: usize,
+        reason: Option<String>,
+    },
+    /// 步骤分发给代理之前
+    StepStarted {
+        step_index: usize,
+        agent_name: String,
+        instruction: String,
+    },
+    /// progress ledger 评估完成
+    LedgerEvaluated {
+        step_index: usize,
+        step_complete: bool,
+        need_to_replan: bool,
+        next_speaker: String,
+    },
+    /// 代理执行过程中的事件，带上代理名称和步骤下标
+    AgentEvent {
+        agent_name: String,
+        step_index: usize,
+        event: AgentEvent,
+    },
+    /// 代理响应中的截图已经保存到产物目录（设置了 artifacts_dir 时）
+    ScreenshotSaved {
+        agent_name: String,
+        step_index: usize,
+        path: String,
+    },
+    /// 计划步骤指定的代理不存在，已分配给最接近的代理
+    AgentNameReassigned {
+        step_index: usize,
+        requested: String,
+        assigned: String,
+    },
+    /// ledger 指定的代理没有注册，指令没有分发；这一轮计入停滞检测
+    AgentNotRegistered {
+        step_index: usize,
+        agent_name: String,
+    },
+    /// 同一步骤连续多轮没有进展，将强制重规划
+    StallDetected {
+        step_index: usize,
+        rounds: usize,
+    },
+    /// 哨兵步骤完成一次检查但条件还没有满足，next_check_secs 秒后再检查；total 是按次数结束时的总次数
+    SentinelWaiting {
+        step_index: usize,
+        check: u32,
+        total: Option<u32>,
+        next_check_secs: u64,
+    },
+    /// 运行被取消（后端的取消接口或 CLI 的停止指令），随后给出目前为止的总结
+    Cancelled {
+        step_index: usize,
+        reason: String,
+    },
+    /// 运行结束时的统计
+    Metrics {
+        metrics: OrchestratorMetrics,
+    },
+}
\ No newline at end of file
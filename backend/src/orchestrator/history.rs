@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::orchestrator::message::{ChatMessage, MessageRole, MultiModalContent, MESSAGE_KIND_KEY, PLAN_MESSAGE_KIND};
+
+/// 群聊历史的压缩配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryCompactionConfig {
+    /// 历史超过这个长度时触发压缩
+    pub max_messages: usize,
+    /// 最近的若干条消息不参与压缩
+    pub keep_recent: usize,
+}
+
+impl Default for HistoryCompactionConfig {
+    fn default() -> Self {
+        Self {
+            max_messages: 200,
+            keep_recent: 20,
+        }
+    }
+}
+
+/// 从历史中移出的截图，历史里只保留 ref
+#[derive(Debug, Default)]
+pub struct ImageArchive {
+    images: HashMap<String, Vec<u8>>,
+    next_id: usize,
+}
+
+impl ImageArchive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn store(&mut self, bytes: Vec<u8>) -> String {
+        self.next_id += 1;
+        let id = format!("img-{}", self.next_id);
+        self.images.insert(id.clone(), bytes);
+        id
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Vec<u8>> {
+        self.images.get(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+}
+
+fn is_plan_message(message: &ChatMessage) -> bool {
+    message.metadata().get(MESSAGE_KIND_KEY).map(|k| k.as_str()) == Some(PLAN_MESSAGE_KIND)
+}
+
+// 比较时忽略 metadata
+fn same_content(a: &ChatMessage, b: &ChatMessage) -> bool {
+    match (a, b) {
+        (
+            ChatMessage::Text { role: r1, source: s1, content: c1, .. },
+            ChatMessage::Text { role: r2, source: s2, content: c2, .. },
+        ) => r1 == r2 && s1 == s2 && c1 == c2,
+        (
+            ChatMessage::MultiModal { role: r1, source: s1, content: c1, .. },
+            ChatMessage::MultiModal { role: r2, source: s2, content: c2, .. },
+        ) => r1 == r2 && s1 == s2 && c1 == c2,
+        _ => false,
+    }
+}
+
+// 把多模态消息中的图片移入 archive，替换为引用文本
+fn archive_images(message: ChatMessage, archive: &mut ImageArchive) -> ChatMessage {
+    match message {
+        ChatMessage::MultiModal { role, source, content, mut metadata } => {
+            let mut refs = Vec::new();
+            let content = content
+                .into_iter()
+                .map(|part| match part {
+                    MultiModalContent::Image(bytes) => {
+                        let id = archive.store(bytes);
+                        let text = format!("[screenshot archived: {}]", id);
+                        refs.push(id);
+                        MultiModalContent::Text(text)
+                    }
+                    text => text,
+                })
+                .collect();
+            if !refs.is_empty() {
+                metadata.insert("archived_images".to_string(), refs.join(","));
+            }
+            ChatMessage::MultiModal { role, source, content, metadata }
+        }
+        text => text,
+    }
+}
+
+/// 历史长度为 len 时 compact_history 是否会改写历史
+pub fn needs_compaction(len: usize, config: &HistoryCompactionConfig) -> bool {
+    len > config.max_messages && len > config.keep_recent + 1
+}
+
+/* 压缩群聊历史，第一条消息（原始任务）和最近 keep_recent 条消息始终保留：
+1. 连续重复的通知合并为一条
+2. 被新计划取代的旧计划广播替换为指向最新计划的提示
+3. 截图移入 archive，历史中只保留引用
+4. 如果仍然超过上限，把最旧的消息折叠为一条省略说明 */
+pub fn compact_history(
+    history: Vec<ChatMessage>,
+    config: &HistoryCompactionConfig,
+    archive: &mut ImageArchive,
+) -> Vec<ChatMessage> {
+    if !needs_compaction(history.len(), config) {
+        return history;
+    }
+
+    let total = history.len();
+    let recent_start = total - config.keep_recent;
+    let latest_plan = history.iter().rposition(is_plan_message);
+
+    let mut iter = history.into_iter().enumerate();
+    let (_, task_message) = iter.next().unwrap();
+    let mut middle: Vec<ChatMessage> = Vec::new();
+    let mut recent: Vec<ChatMessage> = Vec::with_capacity(config.keep_recent);
+
+    for (i, message) in iter {
+        if i >= recent_start {
+            recent.push(message);
+            continue;
+        }
+
+        let message = if is_plan_message(&message) && Some(i) != latest_plan {
+            ChatMessage::new_text(
+                message_role(&message),
+                message.source().to_string(),
+                "[superseded plan omitted; see the latest plan below]".to_string(),
+            )
+        } else {
+            archive_images(message, archive)
+        };
+
+        if let Some(last) = middle.last_mut() {
+            if same_content(last, &message) {
+                let count = last
+                    .metadata()
+                    .get("repeat_count")
+                    .and_then(|c| c.parse::<usize>().ok())
+                    .unwrap_or(1);
+                last.metadata_mut().insert("repeat_count".to_string(), (count + 1).to_string());
+                continue;
+            }
+        }
+        middle.push(message);
+    }
+
+    // 第一条 + 中间 + 最近的消息仍超过上限时，把最旧的中间消息折叠为一条说明
+    let room = config.max_messages.saturating_sub(1 + recent.len());
+    if middle.len() > room {
+        let omitted = middle.len() - room.saturating_sub(1);
+        let note = ChatMessage::new_text(
+            message_role(&task_message),
+            "orchestrator".to_string(),
+            format!("[{} earlier messages omitted to keep the conversation short]", omitted),
+        );
+        middle = std::iter::once(note).chain(middle.into_iter().skip(omitted)).collect();
+    }
+
+    std::iter::once(task_message).chain(middle).chain(recent).collect()
+}
+
+fn message_role(message: &ChatMessage) -> MessageRole {
+    match message {
+        ChatMessage::Text { role, .. } => role.clone(),
+        ChatMessage::MultiModal { role, .. } => role.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::ModelInfo;
+    use crate::orchestrator::message::{convert_agent_messages_to_llm_messages, LLMMessage, UserContent};
+
+    fn text(source: &str, content: &str) -> ChatMessage {
+        ChatMessage::new_text(MessageRole::User, source.to_string(), content.to_string())
+    }
+
+    fn plan(content: &str) -> ChatMessage {
+        let mut message = text("orchestrator", content);
+        message.metadata_mut().insert(MESSAGE_KIND_KEY.to_string(), PLAN_MESSAGE_KIND.to_string());
+        message
+    }
+
+    fn synthetic_history(n: usize) -> Vec<ChatMessage> {
+        let mut history = vec![text("user_proxy", "Find the cheapest flight to Tokyo")];
+        for i in 1..n {
+            let message = match i % 5 {
+                0 => plan(&format!("Plan v{}", i)),
+                1 | 2 => text("orchestrator", "Notify: plan updated"),
+                3 => ChatMessage::new_multimodal(
+                    MessageRole::Assistant,
+                    "web_surfer".to_string(),
+                    vec![MultiModalContent::Text(format!("Observation {}", i)), MultiModalContent::Image(vec![i as u8])],
+                ),
+                _ => text("web_surfer", &format!("Clicked result {}", i)),
+            };
+            history.push(message);
+        }
+        history
+    }
+
+    #[test]
+    fn test_short_history_is_untouched() {
+        let history = synthetic_history(10);
+        let mut archive = ImageArchive::new();
+        let compacted = compact_history(history.clone(), &HistoryCompactionConfig::default(), &mut archive);
+        assert_eq!(compacted, history);
+        assert!(archive.is_empty());
+    }
+
+    #[test]
+    fn test_compacts_500_messages_below_threshold() {
+        let config = HistoryCompactionConfig { max_messages: 100, keep_recent: 10 };
+        let history = synthetic_history(500);
+        let recent: Vec<ChatMessage> = history[490..].to_vec();
+        let mut archive = ImageArchive::new();
+
+        let compacted = compact_history(history, &config, &mut archive);
+
+        assert!(compacted.len() <= config.max_messages);
+        assert_eq!(compacted[0], text("user_proxy", "Find the cheapest flight to Tokyo"));
+        assert_eq!(compacted[compacted.len() - 10..].to_vec(), recent);
+        assert!(!archive.is_empty());
+
+        // 压缩后的上下文里仍然能看到任务
+        let context = convert_agent_messages_to_llm_messages(&compacted, "orchestrator", &ModelInfo::default());
+        match &context[0] {
+            LLMMessage::User(message) => match &message.content {
+                UserContent::String(content) => assert!(content.contains("Find the cheapest flight to Tokyo")),
+                _ => panic!("Expected text task message"),
+            },
+            _ => panic!("Expected User message"),
+        }
+    }
+
+    #[test]
+    fn test_collapses_duplicates_and_superseded_plans() {
+        let config = HistoryCompactionConfig { max_messages: 5, keep_recent: 1 };
+        let history = vec![
+            text("user_proxy", "task"),
+            plan("Plan v1"),
+            text("orchestrator", "Notify"),
+            text("orchestrator", "Notify"),
+            text("orchestrator", "Notify"),
+            plan("Plan v2"),
+            text("web_surfer", "done"),
+        ];
+        let mut archive = ImageArchive::new();
+
+        let compacted = compact_history(history, &config, &mut archive);
+
+        assert_eq!(compacted.len(), 5);
+        match &compacted[1] {
+            ChatMessage::Text { content, .. } => assert!(content.contains("superseded plan")),
+            _ => panic!("Expected text message"),
+        }
+        assert_eq!(compacted[2].metadata().get("repeat_count").map(|c| c.as_str()), Some("3"));
+        assert!(is_plan_message(&compacted[3]));
+    }
+
+    #[test]
+    fn test_archives_screenshots_with_references() {
+        let config = HistoryCompactionConfig { max_messages: 4, keep_recent: 1 };
+        let history = vec![
+            text("user_proxy", "task"),
+            ChatMessage::new_multimodal(
+                MessageRole::Assistant,
+                "web_surfer".to_string(),
+                vec![MultiModalContent::Text("page".to_string()), MultiModalContent::Image(vec![7, 7])],
+            ),
+            text("web_surfer", "scrolled"),
+            text("web_surfer", "scrolled"),
+            text("web_surfer", "scrolled"),
+            text("web_surfer", "done"),
+        ];
+        let mut archive = ImageArchive::new();
+
+        let compacted = compact_history(history, &config, &mut archive);
+
+        assert_eq!(compacted.len(), 4);
+        assert_eq!(archive.get("img-1"), Some(&vec![7, 7]));
+        assert_eq!(compacted[1].metadata().get("archived_images").map(|r| r.as_str()), Some("img-1"));
+        match &compacted[1] {
+            ChatMessage::MultiModal { content, .. } => {
+                assert_eq!(content[1], MultiModalContent::Text("[screenshot archived: img-1]".to_string()));
+            }
+            _ => panic!("Expected multimodal message"),
+        }
+    }
+}
\ No newline at end of file
@@ -1,6 +1,10 @@
 use std::collections::HashMap;
+use std::io::Cursor;
 use anyhow::{Result,anyhow};
+use image::{imageops::FilterType, ImageFormat};
 use serde::{Deserialize, Serialize};
+use crate::clients::ModelInfo;
+use crate::orchestrator::artifacts::SCREENSHOT_PATH_KEY;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Message {
@@ -10,6 +14,28 @@ pub struct Message {
     pub msg_type: MessageType,
 }
 
+impl Message {
+    /* 分发给代理的执行消息：只包含任务背景和本次的指令，而不是全部的群聊历史，
+    代理需要的其他信息应当由 orchestrator 写进指令里 */
+    pub fn execute(from: &str, to: &str, task: &str, instruction: ChatMessage) -> Self {
+        let mut chat_history = Vec::with_capacity(2);
+        if !task.trim().is_empty() {
+            chat_history.push(ChatMessage::new_text(
+                MessageRole::User,
+                from.to_string(),
+                format!("We are working on the following task: {}", task),
+            ));
+        }
+        chat_history.push(instruction);
+        Self {
+            from: from.to_string(),
+            to: to.to_string(),
+            chat_history,
+            msg_type: MessageType::Execute,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum MessageType {
@@ -44,7 +70,7 @@ pub enum ChatMessage {
     },
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct FunctionCall {
     pub id: String,
     pub name: String,
@@ -57,12 +83,12 @@ pub enum MultiModalContent {
     Image(Vec<u8>),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct SystemMessage {
     pub content: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum UserContent {
     #[serde(rename = "string")]
     String(String),
@@ -70,7 +96,7 @@ pub enum UserContent {
     MultiModal(Vec<MultiModalContent>),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct UserMessage {
     pub content: UserContent,
     pub source: String,
@@ -78,7 +104,7 @@ pub struct UserMessage {
     pub message_type: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum AssistantContent {
     #[serde(rename = "string")]
     String(String),
@@ -86,7 +112,7 @@ pub enum AssistantContent {
     FunctionCalls(Vec<FunctionCall>),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct AssistantMessage {
     pub content: AssistantContent,
     pub source: Option<String>,
@@ -94,14 +120,14 @@ pub struct AssistantMessage {
     pub message_type: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ToolMessage {
     pub content: String,
     pub name: String,
     pub call_id: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(tag = "role", content = "content")]
 pub enum LLMMessage {
     #[serde(rename = "system")]
@@ -141,6 +167,22 @@ impl AssistantMessage {
     }
 }
 
+/// 代理在 metadata 中用这个键标记失败的响应，orchestrator 据此决定是否重试
+pub const ERROR_METADATA_KEY: &str = "error";
+/// WebAgent 在页面元数据与上一次相同时标记为 "true"，orchestrator 据此判断步骤是否停滞
+pub const PAGE_UNCHANGED_KEY: &str = "page_unchanged";
+/// 代理在步骤执行期间收到了新的用户消息时标记为 "true"
+pub const USER_INTERRUPT_KEY: &str = "user_interrupt";
+/// metadata 中标记消息种类，计划广播使用 PLAN_MESSAGE_KIND，压缩历史时据此识别旧计划
+pub const MESSAGE_KIND_KEY: &str = "message_kind";
+pub const PLAN_MESSAGE_KIND: &str = "plan";
+/// 最终答案消息，保存会话记录时据此与过程中的消息区分
+pub const FINAL_ANSWER_MESSAGE_KIND: &str = "final_answer";
+/// 计划消息中附带的开销估算（EstimatedCost 的 JSON）
+pub const PLAN_ESTIMATE_KEY: &str = "plan_estimate";
+/// 代理一轮执行或整个任务消耗的 token（TokenUsage 的 JSON）
+pub const TOKEN_USAGE_KEY: &str = "token_usage";
+
 impl ChatMessage {
     pub fn new_text(role: MessageRole, source: String, content: String) -> Self {
         ChatMessage::Text {
@@ -159,6 +201,37 @@ impl ChatMessage {
             metadata: HashMap::new(),
         }
     }
+
+    pub fn metadata(&self) -> &HashMap<String, String> {
+        match self {
+            ChatMessage::Text { metadata, .. } => metadata,
+            ChatMessage::MultiModal { metadata, .. } => metadata,
+        }
+    }
+
+    pub fn metadata_mut(&mut self) -> &mut HashMap<String, String> {
+        match self {
+            ChatMessage::Text { metadata, .. } => metadata,
+            ChatMessage::MultiModal { metadata, .. } => metadata,
+        }
+    }
+
+    // 标记为失败响应，error 为失败原因
+    pub fn with_error(mut self, error: impl Into<String>) -> Self {
+        self.metadata_mut().insert(ERROR_METADATA_KEY.to_string(), error.into());
+        self
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.metadata().get(ERROR_METADATA_KEY).map(|e| e.as_str())
+    }
+
+    pub fn source(&self) -> &str {
+        match self {
+            ChatMessage::Text { source, .. } => source,
+            ChatMessage::MultiModal { source, .. } => source,
+        }
+    }
 }
 
 
@@ -220,6 +293,180 @@ pub fn chat_history_to_llm_messages(history: &[ChatMessage]) -> Result<Vec<LLMMe
     history.iter().map(chat_message_to_llm_message).collect()
 }
 
+// 放进上下文之前图片的最大边长
+pub const MAX_CONTEXT_IMAGE_DIM: u32 = 1024;
+// 粗略的 token 估算：英文大约 4 个字符一个 token，一张图片按固定值计
+const CHARS_PER_TOKEN: usize = 4;
+const IMAGE_TOKEN_ESTIMATE: usize = 765;
+
+// 等比缩小到最大边长不超过 max_dim，解码失败时原样返回
+pub fn downscale_image(bytes: &[u8], max_dim: u32) -> Vec<u8> {
+    let img = match image::load_from_memory(bytes) {
+        Ok(img) => img,
+        Err(_) => return bytes.to_vec(),
+    };
+    if img.width() <= max_dim && img.height() <= max_dim {
+        return bytes.to_vec();
+    }
+    let resized = img.resize(max_dim, max_dim, FilterType::Triangle);
+    let mut out = Cursor::new(Vec::new());
+    match resized.write_to(&mut out, ImageFormat::Png) {
+        Ok(_) => out.into_inner(),
+        Err(_) => bytes.to_vec(),
+    }
+}
+
+fn with_source_prefix(source: &str, content: &str) -> String {
+    format!("{}: {}", source, content)
+}
+
+/* 把群聊历史转换成发给模型的消息：
+1. orchestrator 自己发出的消息作为 Assistant，其余都作为 User
+2. 每条消息前加上来源名称，方便模型区分是谁说的
+3. 多模态消息保留图片并缩小；模型不支持图片时只保留最近的一张，其余替换为占位文本 */
+pub fn convert_agent_messages_to_llm_messages(
+    messages: &[ChatMessage],
+    orchestrator_name: &str,
+    model_info: &ModelInfo,
+) -> Vec<LLMMessage> {
+    // 不支持图片时，只保留最后一条带图片的消息里的最后一张
+    let last_image_position = if model_info.vision {
+        None
+    } else {
+        messages.iter().enumerate().rev().find_map(|(i, message)| match message {
+            ChatMessage::MultiModal { content, .. } => content
+                .iter()
+                .rposition(|part| matches!(part, MultiModalContent::Image(_)))
+                .map(|j| (i, j)),
+            _ => None,
+        })
+    };
+
+    let mut result = Vec::with_capacity(messages.len());
+    for (i, message) in messages.iter().enumerate() {
+        let from_orchestrator = message.source() == orchestrator_name;
+        match message {
+            ChatMessage::Text { source, content, .. } => {
+                let content = with_source_prefix(source, content);
+                if from_orchestrator {
+                    result.push(LLMMessage::Assistant(AssistantMessage::new(
+                        AssistantContent::String(content),
+                        Some(source.clone()),
+                    )));
+                } else {
+                    result.push(LLMMessage::User(UserMessage::new(
+                        UserContent::String(content),
+                        source.clone(),
+                    )));
+                }
+            }
+            ChatMessage::MultiModal { source, content, .. } => {
+                if from_orchestrator {
+                    // Assistant 消息不能携带图片，只保留文本部分
+                    let text = content
+                        .iter()
+                        .filter_map(|part| match part {
+                            MultiModalContent::Text(text) => Some(text.as_str()),
+                            MultiModalContent::Image(_) => None,
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    result.push(LLMMessage::Assistant(AssistantMessage::new(
+                        AssistantContent::String(with_source_prefix(source, &text)),
+                        Some(source.clone()),
+                    )));
+                    continue;
+                }
+
+                let mut parts = Vec::with_capacity(content.len() + 2);
+                parts.push(MultiModalContent::Text(format!("{}:", source)));
+                if model_info.vision && message.metadata().contains_key(SCREENSHOT_PATH_KEY) {
+                    parts.push(MultiModalContent::Text("A screenshot of the final page is attached.".to_string()));
+                }
+                for (j, part) in content.iter().enumerate() {
+                    match part {
+                        MultiModalContent::Text(text) => parts.push(MultiModalContent::Text(text.clone())),
+                        MultiModalContent::Image(bytes) => {
+                            if model_info.vision || last_image_position == Some((i, j)) {
+                                parts.push(MultiModalContent::Image(downscale_image(bytes, MAX_CONTEXT_IMAGE_DIM)));
+                            } else {
+                                parts.push(MultiModalContent::Text("[image omitted]".to_string()));
+                            }
+                        }
+                    }
+                }
+                result.push(LLMMessage::User(UserMessage::new(
+                    UserContent::MultiModal(parts),
+                    source.clone(),
+                )));
+            }
+        }
+    }
+    result
+}
+
+pub fn estimate_text_tokens(text: &str) -> usize {
+    text.len() / CHARS_PER_TOKEN + 1
+}
+
+pub fn estimate_tokens(message: &LLMMessage) -> usize {
+    let text_tokens = estimate_text_tokens;
+    match message {
+        LLMMessage::System(m) => text_tokens(&m.content),
+        LLMMessage::User(m) => match &m.content {
+            UserContent::String(text) => text_tokens(text),
+            UserContent::MultiModal(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    MultiModalContent::Text(text) => text_tokens(text),
+                    MultiModalContent::Image(_) => IMAGE_TOKEN_ESTIMATE,
+                })
+                .sum(),
+        },
+        LLMMessage::Assistant(m) => match &m.content {
+            AssistantContent::String(text) => text_tokens(text),
+            AssistantContent::FunctionCalls(calls) => calls
+                .iter()
+                .map(|call| text_tokens(&call.name) + text_tokens(&call.arguments))
+                .sum(),
+        },
+        LLMMessage::Tool(m) => text_tokens(&m.content),
+    }
+}
+
+/* 上下文窗口策略：超过 token_limit 时，保留 system 消息和第一条非 system 消息（通常是任务），
+从最旧的消息开始丢弃，直到放得下 */
+pub fn fit_to_context_window(messages: Vec<LLMMessage>, token_limit: Option<usize>) -> Vec<LLMMessage> {
+    let limit = match token_limit {
+        Some(limit) => limit,
+        None => return messages,
+    };
+    let mut total: usize = messages.iter().map(estimate_tokens).sum();
+    if total <= limit {
+        return messages;
+    }
+
+    let first_non_system = messages.iter().position(|m| !matches!(m, LLMMessage::System(_)));
+    let mut keep = vec![true; messages.len()];
+    for (i, message) in messages.iter().enumerate() {
+        if total <= limit {
+            break;
+        }
+        // 最后一条消息总是保留
+        if matches!(message, LLMMessage::System(_)) || Some(i) == first_non_system || i + 1 == messages.len() {
+            continue;
+        }
+        keep[i] = false;
+        total -= estimate_tokens(message);
+    }
+
+    messages
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(message, keep)| if keep { Some(message) } else { None })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -377,4 +624,172 @@ mod tests {
         let deserialized: ChatMessage = serde_json::from_str(&json).unwrap();
         assert_eq!(original, deserialized);
     }
+
+    #[test]
+    fn test_execute_message_contains_task_and_instruction() {
+        let instruction = ChatMessage::new_text(
+            MessageRole::User,
+            "orchestrator".to_string(),
+            "Instruction: Search Bing for flights to Tokyo".to_string(),
+        );
+        let message = Message::execute("orchestrator", "web_surfer", "Book a flight to Tokyo", instruction.clone());
+
+        assert_eq!(message.from, "orchestrator");
+        assert_eq!(message.to, "web_surfer");
+        assert!(matches!(message.msg_type, MessageType::Execute));
+        assert_eq!(
+            message.chat_history,
+            vec![
+                ChatMessage::new_text(
+                    MessageRole::User,
+                    "orchestrator".to_string(),
+                    "We are working on the following task: Book a flight to Tokyo".to_string(),
+                ),
+                instruction.clone(),
+            ]
+        );
+
+        // 没有任务时只发送指令
+        let message = Message::execute("orchestrator", "web_surfer", "", instruction.clone());
+        assert_eq!(message.chat_history, vec![instruction]);
+    }
+
+    #[test]
+    fn test_convert_mentions_attached_final_screenshot() {
+        let mut message = ChatMessage::new_multimodal(
+            MessageRole::Assistant,
+            "web_surfer".to_string(),
+            vec![MultiModalContent::Text("Done".to_string()), MultiModalContent::Image(vec![1])],
+        );
+        message.metadata_mut().insert(SCREENSHOT_PATH_KEY.to_string(), "run/screenshots/step1_1.png".to_string());
+
+        let converted = convert_agent_messages_to_llm_messages(&[message.clone()], "orchestrator", &ModelInfo::default());
+        match &converted[0] {
+            LLMMessage::User(UserMessage { content: UserContent::MultiModal(parts), .. }) => {
+                assert_eq!(parts[1], MultiModalContent::Text("A screenshot of the final page is attached.".to_string()));
+            }
+            _ => panic!("Expected multimodal User message"),
+        }
+
+        // 不支持图片的模型不提示
+        let converted = convert_agent_messages_to_llm_messages(&[message], "orchestrator", &text_only());
+        match &converted[0] {
+            LLMMessage::User(UserMessage { content: UserContent::MultiModal(parts), .. }) => {
+                assert_eq!(parts[1], MultiModalContent::Text("Done".to_string()));
+            }
+            _ => panic!("Expected multimodal User message"),
+        }
+    }
+
+    fn text_only() -> ModelInfo {
+        ModelInfo { vision: false, ..ModelInfo::default() }
+    }
+
+    #[test]
+    fn test_convert_empty_history() {
+        let converted = convert_agent_messages_to_llm_messages(&[], "orchestrator", &ModelInfo::default());
+        assert!(converted.is_empty());
+    }
+
+    #[test]
+    fn test_convert_uses_orchestrator_name_for_role() {
+        let history = vec![
+            ChatMessage::new_text(MessageRole::User, "user_proxy".to_string(), "Find a recipe".to_string()),
+            ChatMessage::new_text(MessageRole::User, "orchestrator".to_string(), "Here is the plan".to_string()),
+            ChatMessage::new_text(MessageRole::Assistant, "web_surfer".to_string(), "I searched Bing".to_string()),
+        ];
+
+        let converted = convert_agent_messages_to_llm_messages(&history, "orchestrator", &ModelInfo::default());
+        assert_eq!(
+            converted,
+            vec![
+                LLMMessage::User(UserMessage::new(
+                    UserContent::String("user_proxy: Find a recipe".to_string()),
+                    "user_proxy".to_string(),
+                )),
+                LLMMessage::Assistant(AssistantMessage::new(
+                    AssistantContent::String("orchestrator: Here is the plan".to_string()),
+                    Some("orchestrator".to_string()),
+                )),
+                LLMMessage::User(UserMessage::new(
+                    UserContent::String("web_surfer: I searched Bing".to_string()),
+                    "web_surfer".to_string(),
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_convert_multimodal_keeps_images_for_vision_model() {
+        let history = vec![ChatMessage::new_multimodal(
+            MessageRole::Assistant,
+            "web_surfer".to_string(),
+            vec![
+                MultiModalContent::Text("Clicked the link".to_string()),
+                MultiModalContent::Image(vec![1, 2, 3]),
+            ],
+        )];
+
+        let converted = convert_agent_messages_to_llm_messages(&history, "orchestrator", &ModelInfo::default());
+        assert_eq!(
+            converted,
+            vec![LLMMessage::User(UserMessage::new(
+                UserContent::MultiModal(vec![
+                    MultiModalContent::Text("web_surfer:".to_string()),
+                    MultiModalContent::Text("Clicked the link".to_string()),
+                    MultiModalContent::Image(vec![1, 2, 3]),
+                ]),
+                "web_surfer".to_string(),
+            ))]
+        );
+    }
+
+    #[test]
+    fn test_convert_multimodal_text_only_model_keeps_latest_image() {
+        let screenshot = |n: u8| ChatMessage::new_multimodal(
+            MessageRole::Assistant,
+            "web_surfer".to_string(),
+            vec![MultiModalContent::Text(format!("Step {}", n)), MultiModalContent::Image(vec![n])],
+        );
+        let history = vec![screenshot(1), screenshot(2)];
+
+        let converted = convert_agent_messages_to_llm_messages(&history, "orchestrator", &text_only());
+        let parts = |message: &LLMMessage| match message {
+            LLMMessage::User(UserMessage { content: UserContent::MultiModal(parts), .. }) => parts.clone(),
+            _ => panic!("Expected multimodal User message"),
+        };
+        assert_eq!(parts(&converted[0])[2], MultiModalContent::Text("[image omitted]".to_string()));
+        assert_eq!(parts(&converted[1])[2], MultiModalContent::Image(vec![2]));
+    }
+
+    #[test]
+    fn test_downscale_image() {
+        let img = image::DynamicImage::new_rgb8(2048, 512);
+        let mut bytes = Cursor::new(Vec::new());
+        img.write_to(&mut bytes, ImageFormat::Png).unwrap();
+
+        let scaled = downscale_image(&bytes.into_inner(), MAX_CONTEXT_IMAGE_DIM);
+        let scaled = image::load_from_memory(&scaled).unwrap();
+        assert_eq!((scaled.width(), scaled.height()), (1024, 256));
+    }
+
+    #[test]
+    fn test_fit_to_context_window_keeps_system_task_and_latest() {
+        let long = "x".repeat(400);
+        let messages = vec![
+            LLMMessage::System(SystemMessage::new("system".to_string())),
+            LLMMessage::User(UserMessage::new(UserContent::String("task".to_string()), "user".to_string())),
+            LLMMessage::User(UserMessage::new(UserContent::String(long.clone()), "web_surfer".to_string())),
+            LLMMessage::User(UserMessage::new(UserContent::String(long.clone()), "web_surfer".to_string())),
+            LLMMessage::User(UserMessage::new(UserContent::String("latest".to_string()), "web_surfer".to_string())),
+        ];
+
+        let fitted = fit_to_context_window(messages.clone(), Some(150));
+        assert_eq!(fitted.len(), 4);
+        assert_eq!(fitted[0], messages[0]);
+        assert_eq!(fitted[1], messages[1]);
+        assert_eq!(fitted[3], messages[4]);
+
+        assert_eq!(fit_to_context_window(messages.clone(), None).len(), 5);
+    }
 }
\ No newline at end of file
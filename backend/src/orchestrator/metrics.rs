@@ -0,0 +1,360 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::clients::{ModelPricing, TokenUsage};
+
+/// 单个计划步骤的耗时
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepTiming {
+    pub step_index: usize,
+    pub title: String,
+    pub duration_ms: u64,
+}
+
+/// 一次运行的统计：轮次、重规划、各代理执行次数、步骤耗时、token 用量和花费
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OrchestratorMetrics {
+    pub rounds: usize,
+    pub replans: usize,
+    pub planning_calls: usize,
+    pub ledger_calls: usize,
+    pub agent_steps: BTreeMap<String, usize>,
+    pub step_timings: Vec<StepTiming>,
+    pub orchestrator_usage: TokenUsage,
+    pub agent_usage: BTreeMap<String, TokenUsage>,
+    pub cost: f64,
+    pub total_duration_ms: u64,
+    /// orchestrator 自己的模型调用次数
+    #[serde(default)]
+    pub orchestrator_calls: usize,
+    /// 各代理执行步骤的累计耗时
+    #[serde(default)]
+    pub agent_duration_ms: BTreeMap<String, u64>,
+    #[serde(skip)]
+    run_started: Option<Instant>,
+    #[serde(skip)]
+    current_step: Option<(usize, Instant)>,
+}
+
+impl OrchestratorMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start_run(&mut self) {
+        self.run_started = Some(Instant::now());
+    }
+
+    pub fn record_round(&mut self) {
+        self.rounds += 1;
+    }
+
+    pub fn record_replan(&mut self) {
+        self.replans += 1;
+    }
+
+    pub fn record_planning_call(&mut self) {
+        self.planning_calls += 1;
+    }
+
+    pub fn record_ledger_call(&mut self) {
+        self.ledger_calls += 1;
+    }
+
+    pub fn record_agent_step(&mut self, agent_name: &str) {
+        *self.agent_steps.entry(agent_name.to_string()).or_insert(0) += 1;
+    }
+
+    // 步骤开始计时；同一步骤重复调用不会重置
+    pub fn start_step(&mut self, step_index: usize) {
+        match self.current_step {
+            Some((idx, _)) if idx == step_index => {}
+            _ => self.current_step = Some((step_index, Instant::now())),
+        }
+    }
+
+    pub fn finish_step(&mut self, step_index: usize, title: &str) {
+        if let Some((idx, started)) = self.current_step.take() {
+            if idx == step_index {
+                self.step_timings.push(StepTiming {
+                    step_index,
+                    title: title.to_string(),
+                    duration_ms: duration_ms(started.elapsed()),
+                });
+            } else {
+                self.current_step = Some((idx, started));
+            }
+        }
+    }
+
+    pub fn add_orchestrator_usage(&mut self, usage: &TokenUsage, pricing: Option<&ModelPricing>) {
+        self.orchestrator_calls += 1;
+        self.orchestrator_usage.add(usage);
+        if let Some(pricing) = pricing {
+            self.cost += pricing.cost(usage);
+        }
+    }
+
+    pub fn add_agent_usage(&mut self, agent_name: &str, usage: &TokenUsage, pricing: Option<&ModelPricing>) {
+        self.agent_usage.entry(agent_name.to_string()).or_default().add(usage);
+        if let Some(pricing) = pricing {
+            self.cost += pricing.cost(usage);
+        }
+    }
+
+    pub fn record_agent_time(&mut self, agent_name: &str, duration: Duration) {
+        *self.agent_duration_ms.entry(agent_name.to_string()).or_insert(0) += duration_ms(duration);
+    }
+
+    pub fn finish_run(&mut self) {
+        if let Some(started) = self.run_started {
+            self.total_duration_ms = duration_ms(started.elapsed());
+        }
+    }
+
+    pub fn total_usage(&self) -> TokenUsage {
+        let mut total = self.orchestrator_usage;
+        for usage in self.agent_usage.values() {
+            total.add(usage);
+        }
+        total
+    }
+
+    // 附在最终答案后面的简表
+    pub fn to_table(&self) -> String {
+        let usage = self.total_usage();
+        let mut lines = vec![
+            "| Metric | Value |".to_string(),
+            "| --- | --- |".to_string(),
+            format!("| Rounds | {} |", self.rounds),
+            format!("| Replans | {} |", self.replans),
+            format!("| Ledger calls | {} |", self.ledger_calls),
+        ];
+        for (agent, steps) in &self.agent_steps {
+            lines.push(format!("| Steps by {} | {} |", agent, steps));
+        }
+        for timing in &self.step_timings {
+            lines.push(format!(
+                "| Step {} ({}) | {:.1}s |",
+                timing.step_index + 1,
+                timing.title,
+                timing.duration_ms as f64 / 1000.0
+            ));
+        }
+        lines.push(format!(
+            "| Tokens (prompt / completion) | {} / {} |",
+            usage.prompt_tokens, usage.completion_tokens
+        ));
+        lines.push(format!("| Cost | ${:.4} |", self.cost));
+        lines.push(format!("| Total time | {:.1}s |", self.total_duration_ms as f64 / 1000.0));
+        lines.join("\n")
+    }
+
+    /* 按 orchestrator 和各代理汇总调用次数、token、花费和耗时。花费按 config 中代理的价格计算，
+    没有时用 orchestrator 的价格；有用量却没有价格的行标为未知，不计入合计 */
+    pub fn cost_summary(&self, config: &CostSummaryConfig, pricing: Option<&ModelPricing>) -> CostSummary {
+        let agent_time: u64 = self.agent_duration_ms.values().sum();
+        let mut rows = vec![CostRow::new(
+            "orchestrator",
+            self.orchestrator_calls,
+            self.orchestrator_usage,
+            pricing,
+            self.total_duration_ms.saturating_sub(agent_time),
+        )];
+        let mut agents: Vec<&String> = self.agent_steps.keys().chain(self.agent_usage.keys()).collect();
+        agents.sort();
+        agents.dedup();
+        for agent in agents {
+            rows.push(CostRow::new(
+                agent,
+                self.agent_steps.get(agent).copied().unwrap_or(0),
+                self.agent_usage.get(agent).copied().unwrap_or_default(),
+                config.agent_pricing.get(agent).or(pricing),
+                self.agent_duration_ms.get(agent).copied().unwrap_or(0),
+            ));
+        }
+
+        let mut total = CostRow {
+            name: "total".to_string(),
+            calls: 0,
+            usage: TokenUsage::default(),
+            cost: Some(0.0),
+            duration_ms: self.total_duration_ms,
+        };
+        for row in &rows {
+            total.calls += row.calls;
+            total.usage.add(&row.usage);
+            if let (Some(sum), Some(cost)) = (total.cost.as_mut(), row.cost) {
+                *sum += cost;
+            }
+        }
+        let unknown_pricing = rows.iter().any(|row| row.cost.is_none());
+        CostSummary { currency: config.currency.clone(), rows, total, unknown_pricing }
+    }
+}
+
+/// 运行结束时的花费汇总
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CostSummaryConfig {
+    /// 关闭时最终答案后不附花费表，RunOutcome 中也没有 cost_summary
+    pub enabled: bool,
+    /// 价格所用的货币符号
+    pub currency: String,
+    /// 按代理名覆盖的价格，未列出的代理使用 orchestrator 的 pricing
+    pub agent_pricing: HashMap<String, ModelPricing>,
+}
+
+impl Default for CostSummaryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            currency: "$".to_string(),
+            agent_pricing: HashMap::new(),
+        }
+    }
+}
+
+/// 花费表中的一行，cost 为 None 表示有用量但价格未知
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CostRow {
+    pub name: String,
+    pub calls: usize,
+    pub usage: TokenUsage,
+    pub cost: Option<f64>,
+    pub duration_ms: u64,
+}
+
+impl CostRow {
+    fn new(name: &str, calls: usize, usage: TokenUsage, pricing: Option<&ModelPricing>, duration_ms: u64) -> Self {
+        let cost = match pricing {
+            Some(pricing) => Some(pricing.cost(&usage)),
+            None if usage.total() == 0 => Some(0.0),
+            None => None,
+        };
+        Self { name: name.to_string(), calls, usage, cost, duration_ms }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CostSummary {
+    pub currency: String,
+    pub rows: Vec<CostRow>,
+    pub total: CostRow,
+    /// 有行的价格未知时合计只包含已知部分
+    pub unknown_pricing: bool,
+}
+
+impl CostSummary {
+    pub fn to_table(&self) -> String {
+        let mut lines = vec![
+            "| Agent | Calls | Prompt tokens | Completion tokens | Cost | Time |".to_string(),
+            "| --- | --- | --- | --- | --- | --- |".to_string(),
+        ];
+        for row in self.rows.iter().chain(std::iter::once(&self.total)) {
+            let cost = match row.cost {
+                None => "unknown pricing".to_string(),
+                Some(cost) if row.name == "total" && self.unknown_pricing => {
+                    format!("{}{:.4} + unknown", self.currency, cost)
+                }
+                Some(cost) => format!("{}{:.4}", self.currency, cost),
+            };
+            lines.push(format!(
+                "| {} | {} | {} | {} | {} | {:.1}s |",
+                row.name,
+                row.calls,
+                row.usage.prompt_tokens,
+                row.usage.completion_tokens,
+                cost,
+                row.duration_ms as f64 / 1000.0
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+// 不足 1ms 的也记为 1ms，避免出现 0
+fn duration_ms(duration: Duration) -> u64 {
+    (duration.as_millis() as u64).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scripted_two_step_run() {
+        let pricing = ModelPricing { prompt_per_1k: 1.0, completion_per_1k: 2.0 };
+        let mut metrics = OrchestratorMetrics::new();
+        metrics.start_run();
+        metrics.record_planning_call();
+        metrics.add_orchestrator_usage(&TokenUsage { prompt_tokens: 1000, completion_tokens: 500 }, Some(&pricing));
+
+        for (step_index, title) in ["Search flights", "Compare prices"].iter().enumerate() {
+            metrics.record_round();
+            metrics.record_ledger_call();
+            metrics.start_step(step_index);
+            metrics.record_agent_step("web_surfer");
+            metrics.add_agent_usage("web_surfer", &TokenUsage { prompt_tokens: 200, completion_tokens: 100 }, Some(&pricing));
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            metrics.finish_step(step_index, title);
+        }
+        metrics.finish_run();
+
+        assert_eq!(metrics.rounds, 2);
+        assert_eq!(metrics.ledger_calls, 2);
+        assert_eq!(metrics.planning_calls, 1);
+        assert_eq!(metrics.agent_steps.get("web_surfer"), Some(&2));
+        assert_eq!(metrics.step_timings.len(), 2);
+        assert!(metrics.step_timings.iter().all(|t| t.duration_ms > 0));
+        assert!(metrics.total_duration_ms >= 10);
+        assert_eq!(metrics.total_usage(), TokenUsage { prompt_tokens: 1400, completion_tokens: 700 });
+        assert!((metrics.cost - 2.8).abs() < 1e-9);
+
+        let table = metrics.to_table();
+        assert!(table.contains("| Rounds | 2 |"));
+        assert!(table.contains("| Steps by web_surfer | 2 |"));
+    }
+
+    #[test]
+    fn test_cost_summary_marks_unknown_pricing() {
+        let mut metrics = OrchestratorMetrics::new();
+        metrics.add_orchestrator_usage(&TokenUsage { prompt_tokens: 1000, completion_tokens: 0 }, None);
+        metrics.record_agent_step("web_surfer");
+        metrics.add_agent_usage("web_surfer", &TokenUsage { prompt_tokens: 2000, completion_tokens: 1000 }, None);
+        metrics.record_agent_step("coder_agent");
+
+        let mut config = CostSummaryConfig { currency: "¥".to_string(), ..CostSummaryConfig::default() };
+        config.agent_pricing.insert("web_surfer".to_string(), ModelPricing { prompt_per_1k: 0.5, completion_per_1k: 1.0 });
+        let summary = metrics.cost_summary(&config, None);
+
+        assert_eq!(summary.rows.len(), 3);
+        assert_eq!(summary.rows[0].cost, None);
+        assert_eq!(summary.rows[1].name, "coder_agent");
+        assert_eq!(summary.rows[1].cost, Some(0.0));
+        assert_eq!(summary.rows[2].cost, Some(2.0));
+        assert!(summary.unknown_pricing);
+        assert_eq!(summary.total.calls, 3);
+
+        let table = summary.to_table();
+        assert!(table.contains("| orchestrator | 1 | 1000 | 0 | unknown pricing |"));
+        assert!(table.contains("| web_surfer | 1 | 2000 | 1000 | ¥2.0000 |"));
+        assert!(table.contains("| total | 3 | 3000 | 1000 | ¥2.0000 + unknown |"));
+        assert!(table.contains("| coder_agent | 1 | 0 | 0 | ¥0.0000 |"));
+    }
+
+    #[test]
+    fn test_start_step_is_idempotent() {
+        let mut metrics = OrchestratorMetrics::new();
+        metrics.start_step(0);
+        let started = metrics.current_step.unwrap().1;
+        metrics.start_step(0);
+        assert_eq!(metrics.current_step.unwrap().1, started);
+
+        // 结束的不是当前步骤时不记录
+        metrics.finish_step(1, "other");
+        assert!(metrics.step_timings.is_empty());
+    }
+}
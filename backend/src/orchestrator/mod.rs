@@ -1,5 +1,23 @@
-// pub mod orchestrator; 
+#[allow(clippy::module_inception)]
+pub mod orchestrator;
 pub mod types;
 pub mod config;
 pub mod message;
-pub mod plan;
\ No newline at end of file
+pub mod plan;
+pub mod events;
+pub mod event_log;
+pub mod retry;
+pub mod history;
+pub mod metrics;
+pub mod artifacts;
+pub mod prompt;
+pub mod agent_names;
+pub mod stall;
+pub mod validation;
+pub mod plan_library;
+pub mod plan_editor;
+pub mod estimate;
+pub mod plan_stream;
+pub mod plan_history;
+pub mod session;
+pub mod report;
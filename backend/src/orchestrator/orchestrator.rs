@@ -3,17 +3,45 @@ use futures::lock::Mutex;
 use serde::de::DeserializeOwned;
 use serde_json::Value as JsonValue;
 use serde_json::Value;
-use crate::agents::Agent;
+use crate::agents::{Agent, AgentEventSink};
+use crate::clients::{ChatCompletionClient, CreateResult};
+use crate::database::runs::{RUN_STATUS_CANCELLED, RUN_STATUS_COMPLETED};
+use crate::database::{RunStore, StepOutcome};
+use crate::orchestrator::agent_names::{closest_agent_name, remap_plan_steps, resolve_agent_name};
+use crate::orchestrator::artifacts::{
+    attach_screenshot, keep_last_image, last_image, store_response_screenshots, ArtifactStore, SCREENSHOT_PATH_KEY,
+};
 use crate::orchestrator::config::OrchestratorConfig;
-use crate::orchestrator::message::{ChatMessage, LLMMessage, Message, MessageRole, MessageType, SystemMessage, UserContent, UserMessage, chat_history_to_llm_messages};
-use crate::orchestrator::types::{OrchestratorState, ProgressLedger};
-use crate::orchestrator::plan::{Plan, PlanResponse};
-use anyhow::{Ok, Result};
+use crate::common::json_repair::parse_json_lenient;
+use crate::common::language::{LanguageDetector, ScriptLanguageDetector};
+use crate::orchestrator::events::OrchestratorEvent;
+use crate::orchestrator::estimate::{estimate, longest_sentinel_wall_clock, EstimatedCost};
+use crate::orchestrator::message::{AssistantContent, AssistantMessage, ChatMessage, LLMMessage, Message, MessageRole, MessageType, MultiModalContent, SystemMessage, UserContent, UserMessage, FINAL_ANSWER_MESSAGE_KIND, MESSAGE_KIND_KEY, PAGE_UNCHANGED_KEY, PLAN_ESTIMATE_KEY, PLAN_MESSAGE_KIND, TOKEN_USAGE_KEY, USER_INTERRUPT_KEY, convert_agent_messages_to_llm_messages, fit_to_context_window};
+use crate::orchestrator::types::{as_new_user_message, ImageAttachment, OrchestratorState, ProgressLedger, RunOptions, RunOutcome, SessionFiles, UserMessageQueue};
+use crate::orchestrator::history::{compact_history, needs_compaction, ImageArchive};
+use crate::orchestrator::metrics::{CostSummary, OrchestratorMetrics};
+use crate::orchestrator::stall::StallDetector;
+use crate::orchestrator::plan::{
+    emit_plan_tool, new_step_id, Plan, PlanParseError, PlanResponse, PlanStep, SentinelCondition, StepKind, EMIT_PLAN_TOOL,
+};
+use crate::orchestrator::plan_stream::IncrementalPlanParser;
+use crate::orchestrator::plan_history::{PlanSource, PlanVersion};
+use crate::orchestrator::plan_library::{format_plan_examples, PlanLibrary, PlanLibraryEntry, PlanOutcome, ScoredPlan};
+use crate::orchestrator::validation::{format_validation_errors, validate_plan_steps, validate_plan_with_limits, PlanValidationLimits};
+use crate::orchestrator::prompt::{build_final_answer_prompt, build_replan_context, language_instruction, planning_examples_section, SENTINEL_STEPS_PROMPT};
+use crate::orchestrator::retry::dispatch_with_retry;
+use crate::orchestrator::session::{CheckpointReason, SessionCheckpoint};
+use crate::orchestrator::report::RunReport;
+use crate::tools::approval_guard::{
+    gate_step, ActionGuard, PlanApprovalDecision, PlanApprovalRequest, StepApprovalRequest, StepGateOutcome,
+};
+use anyhow::{anyhow, Result};
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::{Arc};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
 
-
-#[derive(Debug)]
 pub struct Orchestrator {
     // 基础字段
     pub name: String,
@@ -27,53 +55,221 @@ pub struct Orchestrator {
     // 特有字段
     pub message: ChatMessage,
     model_context: Vec<LLMMessage>,         // 可能有误，暂时先这样
-    model_client: Arc<LLMClient>,
+    model_client: Arc<dyn ChatCompletionClient>,
     config: OrchestratorConfig,
 
     // 内部状态字段
     state: OrchestratorState,
     agent_execution_names: Vec<String>,
     agent_execution_descriptions: Vec<String>,
-    team_description: String,
     last_browser_metadata_hash: String,
+    // 规划或重规划之后，下一轮执行重新广播计划
+    restart_execution: bool,
+    termination_condition: Option<Box<dyn TerminationConditionTrait>>,
+
+    // 人工审批与事件
+    action_guard: Option<Arc<dyn ActionGuard>>,
+    event_tx: broadcast::Sender<OrchestratorEvent>,
+
+    // 运行过程中用户追加的消息
+    user_messages: UserMessageQueue,
+
+    // 运行记录持久化，未设置时不写数据库
+    run_store: Option<RunStore>,
+    run_user_id: Option<String>,
+    run_id: Option<String>,
+
+    // 压缩历史时移出的截图
+    image_archive: ImageArchive,
+    // 压缩前的原始消息，以及当前历史中从哪一条开始还是原始消息，用于给出完整记录
+    transcript_archive: Vec<ChatMessage>,
+    transcript_start: usize,
+
+    // 运行统计
+    metrics: OrchestratorMetrics,
+
+    // 运行产物（截图）目录，以及最近一张代理返回的截图和它的保存路径
+    artifacts: Option<ArtifactStore>,
+    last_screenshot: Option<(Vec<u8>, Option<String>)>,
+
+    // 最近一次运行的最终答案（不含统计表）
+    final_answer: Option<String>,
+
+    // ledger 在同一步骤上原地打转的检测
+    stall: StallDetector,
+    // set_plan 给定的计划，下一次规划时直接使用
+    preset_plan: Option<Plan>,
+    // 历史计划库，规划前检索相似的成功计划作为示例
+    plan_library: Option<Arc<dyn PlanLibrary>>,
+    // 本次运行是否被用户停止、拒绝计划或因停滞放弃
+    aborted: bool,
+    // 当前计划的开销估算
+    plan_estimate: Option<EstimatedCost>,
+    // 判断用户请求的语言，回答和计划使用同样的语言
+    language_detector: Arc<dyn LanguageDetector>,
+    // 规划模型不支持图片时，用它为用户附带的图片生成文字描述
+    caption_client: Option<Arc<dyn ChatCompletionClient>>,
+    // 本次运行的计划是否已按 plan_approval 策略自动批准
+    plan_auto_approved: bool,
+    // 演练运行：不写运行记录、会话检查点、计划库和产物
+    dry_run: bool,
+    // 后端会话中用户上传的文件，规划时列出，分发步骤时交给代理
+    session_files: Option<SessionFiles>,
+}
+
+impl std::fmt::Debug for Orchestrator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Orchestrator")
+            .field("name", &self.name)
+            .field("agents", &self.agents.keys().collect::<Vec<_>>())
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+/// 每条代理响应写入历史后检查，返回 Some(原因) 时提前给出最终答案
+pub trait TerminationConditionTrait: Send + Sync {
+    fn check(&self, state: &OrchestratorState, last_message: &ChatMessage) -> Option<String>;
+}
+
+/// 代理响应中出现指定文本时结束
+pub struct TextMentionTermination {
+    pub text: String,
+}
+
+impl TerminationConditionTrait for TextMentionTermination {
+    fn check(&self, _state: &OrchestratorState, last_message: &ChatMessage) -> Option<String> {
+        let mentioned = match last_message {
+            ChatMessage::Text { content, .. } => content.contains(&self.text),
+            ChatMessage::MultiModal { content, .. } => content.iter().any(|part| match part {
+                MultiModalContent::Text(text) => text.contains(&self.text),
+                MultiModalContent::Image(_) => false,
+            }),
+        };
+        if mentioned {
+            Some(format!("Termination text '{}' mentioned by {}", self.text, last_message.source()))
+        } else {
+            None
+        }
+    }
+}
+
+/// 群聊历史达到指定条数时结束
+pub struct MaxMessageTermination {
+    pub max_messages: usize,
+}
+
+impl TerminationConditionTrait for MaxMessageTermination {
+    fn check(&self, state: &OrchestratorState, _last_message: &ChatMessage) -> Option<String> {
+        if state.message_history.len() >= self.max_messages {
+            Some(format!("Maximum number of messages ({}) reached", self.max_messages))
+        } else {
+            None
+        }
+    }
+}
+
+// 消息中的文本部分，多模态消息忽略图片
+fn message_text(message: &ChatMessage) -> String {
+    match message {
+        ChatMessage::Text { content, .. } => content.clone(),
+        ChatMessage::MultiModal { content, .. } => content
+            .iter()
+            .filter_map(|part| match part {
+                MultiModalContent::Text(text) => Some(text.as_str()),
+                MultiModalContent::Image(_) => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+// 重新规划时判断模型是否又写出了已完成的步骤，标题和代理相同即视为同一步
+fn is_same_step(a: &PlanStep, b: &PlanStep) -> bool {
+    a.title.trim().eq_ignore_ascii_case(b.title.trim()) && a.agent_name == b.agent_name
 }
 
-pub trait TerminationConditionTrait: Send + Sync {}
+/* 用户发回的计划先按原样解析，不是合法 JSON 时再做本地修复（代码块、多余的逗号等），
+仍然不是 JSON 时返回 NotJson */
+fn parse_plan_lenient(text: &str) -> std::result::Result<Plan, PlanParseError> {
+    match Plan::from_list_of_dicts_or_str(text) {
+        Err(PlanParseError::NotJson(error)) => match parse_json_lenient(text) {
+            Some(repaired) => Plan::from_list_of_dicts_or_str(repaired.value),
+            None => Err(PlanParseError::NotJson(error)),
+        },
+        result => result,
+    }
+}
 
-type ValidateJsonFn = Arc<dyn Fn(&JsonValue) -> bool + Send + Sync>;
+// 执行消息中最后一条（指令）的文本
+fn instruction_text(message: &Message) -> String {
+    match message.chat_history.last() {
+        Some(ChatMessage::Text { content, .. }) => content.clone(),
+        _ => String::new(),
+    }
+}
 
 impl Orchestrator {
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         name: String,
         message: ChatMessage,
         participant_descriptions: Vec<String>,
         participant_names: Vec<String>,
-        model_client: Arc<LLMClient>,
+        model_client: Arc<dyn ChatCompletionClient>,
         config: OrchestratorConfig,
         termination_condition: Option<Box<dyn TerminationConditionTrait>>,
         max_turns: Option<i32>,
     ) -> Result<Self> {
+        config.validate()?;
 
-        let user_agent_topic = "user_proxy".to_string();
-        let web_agent_topic = "web_agent".to_string();
+        let user_messages = UserMessageQueue::new().with_stop_commands(config.stop_commands.clone());
 
         // 初始化基础字段
         let mut orchestrator = Self {
             name,
+            agents: HashMap::new(),
+            chat_history: Vec::new(),
             participant_descriptions,
             participant_names,
             termination_conditions: Vec::new(),
             max_turns,
-            message: message,
+            message,
             model_context: Vec::new(),
-            model_client: model_client,
-            config: config,
+            model_client,
+            config,
             
             // 临时值，会在setup_internals中正确初始化
             state: OrchestratorState::default(),
-            team_description: String::new(),
+            agent_execution_names: Vec::new(),
+            agent_execution_descriptions: Vec::new(),
             last_browser_metadata_hash: String::new(),
+            restart_execution: false,
+            termination_condition,
+            action_guard: None,
+            event_tx: broadcast::channel(256).0,
+            user_messages,
+            run_store: None,
+            run_user_id: None,
+            run_id: None,
+            image_archive: ImageArchive::new(),
+            transcript_archive: Vec::new(),
+            transcript_start: 1,
+            metrics: OrchestratorMetrics::new(),
+            artifacts: None,
+            last_screenshot: None,
+            final_answer: None,
+            stall: StallDetector::new(),
+            preset_plan: None,
+            plan_library: None,
+            aborted: false,
+            plan_estimate: None,
+            language_detector: Arc::new(ScriptLanguageDetector),
+            caption_client: None,
+            plan_auto_approved: false,
+            dry_run: false,
+            session_files: None,
         };
 
         orchestrator.set_internal_variables()?;
@@ -105,311 +301,1662 @@ impl Orchestrator {
             "If for this step no action is needed, you can use this agent to perform no action".to_string()
         );
 
-        // 团队描述
-        self.team_description = self.agent_execution_names
-            .iter()
-            .zip(&self.agent_execution_descriptions)
-            .map(|(name, description)| format!("{} - {}", name, description.trim()))
-            .collect::<Vec<_>>()
-            .join("\n");
-
         // 初始化浏览器元数据哈希
         self.last_browser_metadata_hash = String::new();
 
         Ok(())
     }
 
-    async fn prepare_final_answer(
-        &mut self,
-        reason: String,
-        final_answer: Option<String>,
-    ) -> Result<()> {
-        if final_answer.is_none() {
-            let mut context = self.thread_to_context(None)?;
-            context.push(LLMMessage::User(
-                UserMessage::new(
-                    UserContent::String(reason),
-                    self.name.clone(),
-                ),
-            ));
+    // 注册参与执行的代理，描述取 participant_descriptions 中同名的一项；规划前注册即可
+    pub fn register_agent(&mut self, agent: Box<dyn Agent>) {
+        let name = agent.name().to_string();
+        self.agents.insert(name, Arc::new(Mutex::new(agent)));
+    }
 
-            let final_answer_prompt = format!(
-                r#"
-                We are working on the following task:
-                {task}
-                The above messages contain the steps that took place to complete the task.
-                Based on the information gathered, provide a final response to the user in response to the task.
-                Make sure the user can easily verify your answer, include links if there are any. 
-                Please refer to steps of the plan that was used to complete the task. Use the steps as a way to help the user verify your answer.
-                Make sure to also say whether the answer was found using online search or from your own knowledge.
-                There is no need to be verbose, but make sure it contains enough information for the user.
-                "#,
-                task = self.state.task.clone(),
-            );
+    pub fn set_action_guard(&mut self, guard: Arc<dyn ActionGuard>) {
+        self.action_guard = Some(guard);
+    }
 
-            let progress_summary = format!("Progress summary: {}", self.state.information_collected);
+    pub fn set_run_store(&mut self, store: RunStore, user_id: Option<String>) {
+        self.run_store = Some(store);
+        self.run_user_id = user_id;
+    }
 
-            let content = format!("{}\n\n{}", progress_summary, final_answer_prompt);
-            context.push(LLMMessage::User(
-                UserMessage::new( 
-                    UserContent::String(content),
-                    self.name.clone(),
-                ),
-            ));
+    pub fn model_client(&self) -> Arc<dyn ChatCompletionClient> {
+        self.model_client.clone()
+    }
 
-            self.model_context.clear();
-            for message in context {
-                self.model_context.push(message.clone());
-            }
+    // 替换规划和进度账本使用的模型，例如包装成按用户限速的客户端
+    pub fn set_model_client(&mut self, client: Arc<dyn ChatCompletionClient>) {
+        self.model_client = client;
+    }
 
-            // 调用LLM
-            let response = "";
-            let _final_answer = Some(response);
-        }
+    // 当前运行在数据库中的 id
+    pub fn run_id(&self) -> Option<&str> {
+        self.run_id.as_deref()
+    }
 
-        let content = format!("Final answer: {}", final_answer.unwrap());
-        let message = ChatMessage::new_text(
-            MessageRole::Assistant,
-            self.name.clone(),
-            content,
-        );
+    // 订阅 orchestrator 事件
+    pub fn subscribe_events(&self) -> broadcast::Receiver<OrchestratorEvent> {
+        self.event_tx.subscribe()
+    }
 
-        self.state.message_history.push(message.clone());
-        self.notify_all(message).await?;
+    // 没有订阅者时发送会失败，直接忽略
+    fn emit(&self, event: OrchestratorEvent) {
+        let _ = self.event_tx.send(event);
+    }
 
-        // 结束
-        // self.state.is_terminated = true;
-        Ok(())
+    // 供其他任务（CLI 输入循环、后端接口）并发投递消息的句柄
+    pub fn user_message_queue(&self) -> UserMessageQueue {
+        self.user_messages.clone()
     }
 
-    pub async fn notify_all(&self, content: ChatMessage) -> Result<()> {
-        let notify_msg = Message {
-            from: "orchestrator".to_string(),
-            to: "all".to_string(),
-            chat_history: vec![content.clone()],
-            msg_type: MessageType::Notify,
-        };
+    pub fn set_plan_library(&mut self, library: Arc<dyn PlanLibrary>) {
+        self.plan_library = Some(library);
+    }
 
-        for(_name, agent) in &self.agents {
-            let mut agent = agent.lock().await;
-            let _ = agent.on_message_stream(notify_msg.clone()).await?;
-        }
-        Ok(())
+    pub fn set_language_detector(&mut self, detector: Arc<dyn LanguageDetector>) {
+        self.language_detector = detector;
     }
 
-    pub async fn select_next_speaker(&self, agent_name: String, content: ChatMessage) -> Result<()> {
-        let execute_msg = Message {
-            from: "Orchestrator".to_string(),
-            to: agent_name.to_string(),
-            chat_history: vec![content.clone()],
-            msg_type: MessageType::Execute,
-        };
+    // 需要支持图片输入的模型
+    pub fn set_caption_client(&mut self, client: Arc<dyn ChatCompletionClient>) {
+        self.caption_client = Some(client);
+    }
 
-        let agent = self.agents.get(&agent_name)
-            .ok_or_else(|| anyhow::anyhow!("Agent {} not found", agent_name))?;
-        
-        let mut agent = agent.lock().await;
-        agent.on_message_stream(execute_msg).await?;
-        Ok(())
+    /* 回答使用的语言：配置了 force_language 时固定使用它，否则跟随用户请求。
+    英文请求不需要额外说明，返回 None */
+    fn response_language(&self) -> Option<String> {
+        if let Some(language) = self.config.force_language.as_ref().filter(|l| !l.trim().is_empty()) {
+            return Some(language.trim().to_string());
+        }
+        self.language_detector
+            .detect(&self.state.task)
+            .filter(|language| language != "English")
     }
 
-    async fn handle_agent_response(&mut self, _agent_name: &str, response: ChatMessage) -> Result<()> {
-        self.state.message_history.push(response.clone());
-        // self.orchestrator_step_execution(false).await?;
+    /* 直接使用给定的计划（例如从文件导入），下一次规划时不再调用模型。
+    计划先按当前团队做代理名映射再校验，不合法时返回全部错误 */
+    pub fn set_plan(&mut self, mut plan: Plan) -> Result<()> {
+        let names = self.registered_agent_names();
+        remap_plan_steps(&mut plan.steps, &names, &self.config.agent_aliases);
+        plan.ensure_unique_step_ids();
+        validate_plan_steps(&plan, &names, self.config.sentinel_tasks_enabled, &self.config.plan_validation)
+            .map_err(|errors| anyhow!("The plan is invalid: {}", format_validation_errors(&errors)))?;
+        self.preset_plan = Some(plan);
         Ok(())
     }
 
-    async fn orchestrator_step_planning(
-        &mut self,
-    ) -> Result<()> { 
-
-        // Planning stage
-        let mut plan_response: PlanResponse = PlanResponse::default();
+    /* 提交用户的后续消息：运行中的消息会在下一次 ledger 评估时出现；
+    如果本轮已经结束，则保留历史并用现有团队开始新一轮规划 */
+    pub async fn submit_user_message(&mut self, message: ChatMessage) -> Result<()> {
+        // 停止指令：取消当前步骤并给出目前为止的总结
+        if self.user_messages.push(message) {
+            if !self.state.is_terminated {
+                self.stop().await?;
+            }
+            return Ok(());
+        }
 
-        let mut context = self.thread_to_context(None)?;
-        context.push(LLMMessage::User(
-            UserMessage::new(
-                UserContent::String(self.get_task_ledger_plan_prompt(self.team_description)?),
-                self.name.clone(),
-            ),
-        ));
+        if self.state.is_terminated {
+            self.state.reset_with_context();
+            self.user_messages.reset_cancellation();
+            self.run_id = None;
+            for message in self.user_messages.drain() {
+                self.state.message_history.push(message);
+            }
+            self.orchestrator_step_planning().await?;
+            if !self.state.is_terminated {
+                self.save_session_checkpoint(CheckpointReason::PlanAccepted).await;
+            }
+            self.run_execution_loop().await?;
+        }
+        Ok(())
+    }
 
-        let plan_response = self.model_client.call(context).await?;
+    /* 完整执行一次任务：规划（不需要计划时直接回答或向用户澄清）、可选的计划审批、
+    逐轮的 ledger 评估和执行，直到终止条件触发或给出最终答案。CLI 和后端都只调用这里 */
+    pub async fn run_task(&mut self, task: String, opts: RunOptions) -> Result<RunOutcome> {
+        self.reset_run(opts.user_id);
+        self.run_id = opts.run_id;
+        self.dry_run = opts.dry_run;
+        self.session_files = opts.files;
+        self.state.task = task.clone();
+        self.message = self.task_message(task, opts.context.as_deref(), &opts.attachments).await;
+        self.state.message_history.push(self.message.clone());
+
+        self.orchestrator_step_planning().await?;
+        if opts.approve_plan && !self.state.is_terminated {
+            self.approve_plan().await?;
+        }
+        if !self.state.is_terminated {
+            self.save_session_checkpoint(CheckpointReason::PlanAccepted).await;
+        }
+        self.run_execution_loop().await?;
+        self.record_plan_outcome().await;
 
-        self.state.plan = Plan::from_list_of_dicts_or_str(plan_response.steps);
-        self.state.plan_str = serde_json::to_string(&self.state.plan.as_ref().unwrap())?;
+        let outcome = self.run_outcome();
+        self.write_run_report(&outcome);
+        Ok(outcome)
+    }
 
-        self.state.message_history.push(
-            ChatMessage::new_text(
-                MessageRole::Assistant,
-                self.name.clone(),
-                plan_response.response,
-            )
-        );
-        if true {
-            // self.orchestrator_step_execution(true).await?;
-            println!("开始进行执行");
-            return Ok(());
-        } else {
-            let user_plan = "";
-            if !user_plan.is_empty() {
-                self.state.plan = Plan::from_list_of_dicts_or_str(user_plan);
-                self.state.plan_str = user_plan.to_string();
-            }
+    /* 只生成计划、不执行：规划流程与 run_task 相同（包括协作规划和澄清），
+    请求不需要计划时返回 None，此时直接回答已经作为最终答案给出 */
+    pub async fn generate_plan(&mut self, task: String, opts: RunOptions) -> Result<Option<Plan>> {
+        self.reset_run(opts.user_id);
+        self.session_files = opts.files;
+        self.state.task = task.clone();
+        self.message = self.task_message(task, opts.context.as_deref(), &opts.attachments).await;
+        self.state.message_history.push(self.message.clone());
+
+        self.orchestrator_step_planning().await?;
+        if self.state.is_terminated {
+            return Ok(None);
+        }
+        Ok(self.state.plan.clone())
+    }
 
-            let mut context = self.thread_to_context(None)?;
+    // 开始新的运行之前清空上一次运行的状态
+    fn reset_run(&mut self, user_id: Option<String>) {
+        self.state.reset();
+        self.dry_run = false;
+        self.user_messages.reset_cancellation();
+        self.metrics = OrchestratorMetrics::new();
+        self.run_id = None;
+        self.transcript_archive.clear();
+        self.transcript_start = 1;
+        self.artifacts = None;
+        self.last_screenshot = None;
+        self.final_answer = None;
+        self.stall.reset();
+        self.aborted = false;
+        self.plan_estimate = None;
+        self.plan_auto_approved = false;
+        self.session_files = None;
+        if user_id.is_some() {
+            self.run_user_id = user_id;
+        }
+    }
 
-            context.push(LLMMessage::User(
-                UserMessage::new(
-                    UserContent::String(user_plan.to_string()), 
-                    self.name.clone()
-                )
+    /* 从会话目录恢复被中断的任务：状态和计划历史按检查点恢复，从当前步骤继续执行，
+    已完成的步骤不会再次执行。调用前需要注册剩余步骤用到的代理；
+    opts.approve_plan 时先把已完成和剩余的步骤交给 guard 确认，被拒绝时不执行任何步骤 */
+    pub async fn resume_session(&mut self, dir: impl AsRef<Path>, opts: RunOptions) -> Result<RunOutcome> {
+        let dir = dir.as_ref();
+        let checkpoint = SessionCheckpoint::load(dir)?;
+
+        let missing = checkpoint.missing_agents(&self.registered_agent_names());
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "Cannot resume the session in {}: the remaining steps need agents that are not registered: {}",
+                dir.display(),
+                missing.join(", ")
             ));
-
-            plan_response = self.get_json_response(context, self.validate_plan_json);
-
         }
+        // 之后的检查点继续写到同一个目录
+        self.config.session_dir = Some(dir.display().to_string());
+        self.resume_checkpoint(checkpoint, opts).await
     }
-    
-    /*
-    async fn orchestrator_step_execution(
-        &mut self,
-        first_step: bool,
-    ) -> Result<()> { 
-        // 第一次计划
-        if first_step {
-            
-            let content = format!(
-                r#"
-                We are working to address the following user request:
-                \\n\\n
-                {task}
-                \\n\\n
-                To answer this request we have assembled the following team:
-                \\n\\n
-                {team}
-                \\n\\n
-                Here is the plan to follow as best as possible:
-                \\n\\n
-                {plan}
-                "#,
-                task = self.state.task.clone(),
-                team = self.team_description.clone(),
-                plan = self.state.plan_str.clone(),
-            );
 
-            let ledger_message = ChatMessage::Text(TextMessage::new(content, self.name.clone()));
+    /* 从检查点恢复任务，与 resume_session 相同，检查点由调用方读取（后端从 run_checkpoints 表读取）。
+    opts.run_id 给出时继续写入原来的运行记录，否则新建一条 */
+    pub async fn resume_checkpoint(&mut self, checkpoint: SessionCheckpoint, opts: RunOptions) -> Result<RunOutcome> {
+        let missing = checkpoint.missing_agents(&self.registered_agent_names());
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "Cannot resume the run: the remaining steps need agents that are not registered: {}",
+                missing.join(", ")
+            ));
+        }
 
-            self.state.message_history.push(ledger_message.clone());
+        self.reset_run(opts.user_id);
+        self.run_id = opts.run_id;
+        self.session_files = opts.files;
+        checkpoint.restore(&mut self.state)?;
+        self.metrics = checkpoint.metrics.clone();
+        self.metrics.start_run();
+        self.message = ChatMessage::new_text(MessageRole::User, "user".to_string(), self.state.task.clone());
+        self.restart_execution = checkpoint.announce_plan;
+        self.restore_agent_states(&checkpoint).await;
+        if self.run_id.is_none() {
+            self.persist_run_start().await;
+        }
+        self.update_plan_estimate();
+        self.emit(OrchestratorEvent::SessionResumed {
+            step_index: checkpoint.current_step_idx,
+            remaining_steps: checkpoint.remaining_steps(),
+        });
+
+        if opts.approve_plan {
+            self.confirm_resume(&checkpoint).await?;
         }
+        self.run_execution_loop().await?;
+        self.record_plan_outcome().await;
 
-        let length = if let Some(plan) = &self.state.plan {
-            plan.steps.len()
-        } else {
-            0
-        };
+        let outcome = self.run_outcome();
+        self.write_run_report(&outcome);
+        Ok(outcome)
+    }
 
-        let max_turns = self.config.max_turns.unwrap_or(100) as usize;
-        if self.state.current_step_idx >= length || self.state.n_rounds > max_turns {
-            self.prepare_final_answer("Max rounds reached".to_string(), None).await?;
-            return Ok(());
+    // 代理状态恢复失败时代理从头开始（例如浏览器重新打开页面），只记录日志
+    async fn restore_agent_states(&self, checkpoint: &SessionCheckpoint) {
+        for (name, state) in &checkpoint.agent_states {
+            let Some(agent) = self.agents.get(name) else {
+                continue;
+            };
+            if let Err(e) = agent.lock().await.restore_state(state.clone()).await {
+                tracing::warn!("Failed to restore the state of agent {}: {:?}", name, e);
+            }
         }
+    }
 
-        self.state.n_rounds += 1;
-        let mut context = self.thread_to_context(None)?;
-
-        
-        let progress_ledger_prompt = self.get_progress_ledger_prompt(
-            self.state.task,
-            self.state.plan_str.clone(),
-            self.state.current_step_idx,
-            self.team_description,
-            self.agent_execution_names,
-        )?;
+    // 恢复前的确认，没有 guard 时直接继续
+    async fn confirm_resume(&mut self, checkpoint: &SessionCheckpoint) -> Result<()> {
+        let guard = match &self.action_guard {
+            Some(guard) => guard.clone(),
+            None => return Ok(()),
+        };
+        let request = ChatMessage::new_text(
+            MessageRole::Assistant,
+            self.name.clone(),
+            format!("Resume the following session?\n{}", checkpoint.summary()),
+        );
+        if !guard.get_approval(request).await {
+            self.aborted = true;
+            let summary = self.partial_progress_summary("The session was not resumed");
+            self.prepare_final_answer("The user declined to resume the session".to_string(), Some(summary)).await?;
+        }
+        Ok(())
+    }
 
-        context.push(LLMMessage::User(
-            UserMessage::new(
-                UserContent::String(progress_ledger_prompt), self.name.clone()
-            ),
-        ));
+    /// 当前运行的结果；generate_plan 之后只有计划（或者直接回答）
+    pub fn run_outcome(&self) -> RunOutcome {
+        RunOutcome {
+            final_answer: self.final_answer.clone().unwrap_or_default(),
+            plan: self.state.plan.clone(),
+            metrics: self.metrics.clone(),
+            artifacts: self.artifacts.as_ref().map(|store| store.saved().to_vec()).unwrap_or_default(),
+            step_outcomes: self.state.step_outcomes.clone(),
+            information_collected: self.state.information_collected.clone(),
+            cost_summary: self.cost_summary(),
+        }
+    }
 
-        let json_str = self.get_json_response(context, self).await?;
+    fn cost_summary(&self) -> Option<CostSummary> {
+        self.config
+            .cost_summary
+            .enabled
+            .then(|| self.metrics.cost_summary(&self.config.cost_summary, self.config.pricing.as_ref()))
+    }
 
-        let progress_ledger: ProgressLedger = self.get_json_response(context, self.validate_progress_ledger_json).await?;
+    // 有运行目录（保存过产物）时在其中生成 HTML 报告，失败只记录日志
+    fn write_run_report(&self, outcome: &RunOutcome) {
+        let Some(store) = &self.artifacts else {
+            return;
+        };
+        if let Err(e) = RunReport::from_outcome(&self.state.task, outcome).write(store.run_dir()) {
+            tracing::warn!("Failed to write the run report: {:?}", e);
+        }
+    }
 
-        if !first_step {
-            let need_to_replan = progress_ledger.need_to_replan.answer;
-            let replan_reason = progress_ledger.need_to_replan.reason;
+    /// 当前状态的检查点（不含代理状态），运行出错后可以从这里继续；没有计划时为 None
+    pub fn checkpoint(&self) -> Option<SessionCheckpoint> {
+        SessionCheckpoint::capture(&self.state, &self.metrics, CheckpointReason::StepFailed, self.restart_execution)
+    }
 
-            if need_to_replan {
-                if self.state.n_replans < self.config.max_replans {
-                    self.state.n_replans += 1;
-                    self.replan(replan_reason).await?;
-                    return Ok(());
-                } else {
-                    let reason = format!("We need to replan but max replan attempts reached: {replan_reason} ");
-                    self.prepare_final_answer(reason, None).await?;
-                    return Ok(());
-                }
+    /* 计划被接受或步骤完成时写会话检查点：配置了 session_dir 时写入目录，有运行记录时写入
+    run_checkpoints，都没有时跳过；写入失败只记录日志 */
+    async fn save_session_checkpoint(&self, reason: CheckpointReason) {
+        if self.dry_run {
+            return;
+        }
+        let dir = self.config.session_dir.as_ref();
+        let run = self.run_store.as_ref().zip(self.run_id.as_ref());
+        if dir.is_none() && run.is_none() {
+            return;
+        }
+        let Some(mut checkpoint) = SessionCheckpoint::capture(&self.state, &self.metrics, reason, self.restart_execution) else {
+            return;
+        };
+        checkpoint.agent_states = self.agent_states().await;
+        if let Some(dir) = dir {
+            if let Err(e) = checkpoint.save(dir) {
+                tracing::warn!("Failed to save session checkpoint: {:?}", e);
+            }
+        }
+        if let Some((store, run_id)) = run {
+            if let Err(e) = store.save_run_checkpoint(run_id, &checkpoint).await {
+                tracing::warn!("Failed to persist the checkpoint of run {}: {:?}", run_id, e);
             }
+        }
+    }
 
-            if progress_ledger.is_current_step_complete.answer {
-                self.state.current_step_idx += 1;
+    // 各代理需要随检查点保存的状态，读取失败的代理跳过
+    async fn agent_states(&self) -> HashMap<String, JsonValue> {
+        let mut states = HashMap::new();
+        for (name, agent) in &self.agents {
+            match agent.lock().await.save_state().await {
+                Ok(Some(state)) => {
+                    states.insert(name.clone(), state);
+                }
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Failed to save the state of agent {}: {:?}", name, e),
             }
         }
+        states
+    }
 
-        let plan_length = if let Some(plan) = &self.state.plan {
-            plan.steps.len()
-        } else {
-            0
+    /* 用户的任务消息。之前的对话放在任务前面，会话文件按名称和大小列在任务后面；附带的图片按文件名列出，
+    模型支持图片时直接放进消息，否则换成每张图片的文字描述，计划步骤都可以通过文件名引用图片 */
+    async fn task_message(&mut self, task: String, context: Option<&str>, attachments: &[ImageAttachment]) -> ChatMessage {
+        let task = match context.map(str::trim) {
+            Some(context) if !context.is_empty() => {
+                format!("Earlier in this conversation:\n{}\n\nCurrent request (it may refer to the conversation above): {}", context, task)
+            }
+            _ => task,
         };
-
-        if self.state.current_step_idx >= plan_length {
-            self.prepare_final_answer("Plan completed".to_string(), None).await?;
-            return Ok(());
+        let task = match &self.session_files {
+            Some(files) if !files.is_empty() => format!("{}\n\n{}", task, files.describe()),
+            _ => task,
+        };
+        if attachments.is_empty() {
+            return ChatMessage::new_text(MessageRole::User, "user".to_string(), task);
         }
-
-        let new_instruction = self.get_agent_instruction(
-            progress_ledger.instruction_or_question.answer.clone(),
-            progress_ledger.instruction_or_question.agent_name.clone()
-        )?;
-
-        let message_to_send = ChatMessage::Text(TextMessage::new(
-            new_instruction, 
-            self.name.clone()
-        ));
-        self.state.message_history.push(message_to_send);
-
-        let next_speaker = progress_ledger.instruction_or_question.agent_name;
-        for name in self.agent_execution_names {
-            if name == next_speaker {
-                self.select_next_speaker(next_speaker).await?;
-                break;
+        let filenames = attachments.iter().map(|a| a.filename.as_str()).collect::<Vec<_>>().join(", ");
+        let mut text = format!("{}\n\nAttached images: {}", task, filenames);
+
+        if self.config.model_info.vision {
+            let mut content = vec![MultiModalContent::Text(text)];
+            for attachment in attachments {
+                content.push(MultiModalContent::Text(format!("Image {}:", attachment.filename)));
+                content.push(MultiModalContent::Image(attachment.data.clone()));
             }
+            return ChatMessage::new_multimodal(MessageRole::User, "user".to_string(), content);
         }
-        Ok(())
+        for attachment in attachments {
+            let caption = self.caption_image(attachment).await;
+            text.push_str(&format!("\n\nImage {} (description): {}", attachment.filename, caption));
+        }
+        ChatMessage::new_text(MessageRole::User, "user".to_string(), text)
     }
 
-    async fn get_json_response<T: DeserializeOwned>(
-        &mut self,
-        messages: Vec<LLMMessage>,
-        validate_json: ValidateJsonFn,
-    ) -> Result<T> {
-
-        self.model_context.clear();
-
-        for message in messages {
-            self.model_context.push(message);
+    // 没有可用的描述模型或描述失败时，只说明有这张图片
+    async fn caption_image(&mut self, attachment: &ImageAttachment) -> String {
+        let unavailable = "No description is available.".to_string();
+        let Some(client) = self.caption_client.clone() else {
+            return unavailable;
+        };
+        let request = LLMMessage::User(UserMessage::new(
+            UserContent::MultiModal(vec![
+                MultiModalContent::Text(format!(
+                    "Describe the image {} in detail, including any text it contains, so that someone who cannot see it can use it to plan a task.",
+                    attachment.filename
+                )),
+                MultiModalContent::Image(attachment.data.clone()),
+            ]),
+            self.name.clone(),
+        ));
+        match client.create(&[request]).await {
+            Ok(result) => {
+                self.metrics.add_orchestrator_usage(&result.usage, self.config.pricing.as_ref());
+                result.content.trim().to_string()
+            }
+            Err(e) => {
+                tracing::warn!("Failed to caption image {}: {:?}", attachment.filename, e);
+                unavailable
+            }
         }
-
-        // llm_call 这里调用使用model_client，这里应该是LLM使用我们的prompt，给出xxx，暂时结构位置
-        // let response = Vec<messages>;
-        // Ok(response)
-        Ok(T::default())
     }
-    */
 
-    // ChatMessage转为LLMMessage
+    // 有计划并给出了最终答案的运行写入计划库，写入失败只打印警告
+    async fn record_plan_outcome(&self) {
+        let (library, plan) = match (&self.plan_library, &self.state.plan) {
+            (Some(library), Some(plan)) if self.config.plan_examples.record_runs && !self.dry_run => (library.clone(), plan.clone()),
+            _ => return,
+        };
+        let outcome = if !self.aborted && self.final_answer.is_some() {
+            PlanOutcome::Success
+        } else {
+            PlanOutcome::Failure
+        };
+        if let Err(e) = library.add(PlanLibraryEntry::new(self.state.task.clone(), plan, outcome)).await {
+            tracing::warn!("Failed to record the plan in the plan library: {:?}", e);
+        }
+    }
+
+    // 检索相似任务的成功计划，拼成规划提示词前的示例段落
+    async fn plan_examples(&self) -> Option<String> {
+        let config = &self.config.plan_examples;
+        let library = self.plan_library.as_ref().filter(|_| config.top_k > 0)?;
+        let plans = match library.find_similar(&self.state.task, config.top_k).await {
+            Ok(plans) => plans,
+            Err(e) => {
+                tracing::warn!("Failed to retrieve similar plans: {:?}", e);
+                return None;
+            }
+        };
+        let plans: Vec<ScoredPlan> = plans.into_iter().filter(|p| p.similarity >= config.min_similarity).collect();
+        format_plan_examples(&plans, config.max_tokens)
+    }
+
+    /* 执行前把整个计划和开销估算交给 guard 审批，没有 guard 时直接通过，被拒绝时结束本次运行。
+    用户在审批时修改了计划的话改用修改后的计划并记成新版本；要求重新生成时带着原因重规划，新计划再次审批。
+    估算超过阈值时还要再确认一次 */
+    async fn approve_plan(&mut self) -> Result<()> {
+        if self.plan_auto_approved {
+            return Ok(());
+        }
+        let guard = match &self.action_guard {
+            Some(guard) => guard.clone(),
+            None => return Ok(()),
+        };
+        let mut previous_plan = None;
+        let mut approved = loop {
+            let plan = match &self.state.plan {
+                Some(plan) => plan.clone(),
+                None => return Ok(()),
+            };
+            let steps = plan.steps
+                .iter()
+                .enumerate()
+                .map(|(i, step)| format!("{}. {} ({})", i + 1, step.label(), step.agent_name))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let mut text = format!("Do you approve the following plan?\n{}", steps);
+            if let Some(estimate) = &self.plan_estimate {
+                text.push_str(&format!("\n\n{}", estimate.summary()));
+            }
+            let request = PlanApprovalRequest {
+                message: ChatMessage::new_text(MessageRole::Assistant, self.name.clone(), text),
+                plan,
+                team: self.registered_agent_names(),
+                sentinel_enabled: self.config.sentinel_tasks_enabled,
+                limits: self.config.plan_validation.clone(),
+                previous_plan: previous_plan.take(),
+            };
+            match guard.get_plan_approval(&request).await {
+                PlanApprovalDecision::Approve => break true,
+                PlanApprovalDecision::Reject => break false,
+                PlanApprovalDecision::Edited(plan) => {
+                    self.state.plan_str = serde_json::to_string(&plan)?;
+                    self.state.plan = Some(plan);
+                    self.state.sync_current_step_id();
+                    self.record_plan_version(PlanSource::UserEdited, Some("edited before approval".to_string())).await;
+                    self.update_plan_estimate();
+                    break true;
+                }
+                PlanApprovalDecision::Regenerate(reason) => {
+                    let reason = match reason.as_deref().map(str::trim).filter(|r| !r.is_empty()) {
+                        Some(reason) => format!("The user asked for a new plan: {}", reason),
+                        None => "The user asked for a new plan".to_string(),
+                    };
+                    self.replan(Some(reason)).await?;
+                    previous_plan = Some(request.plan);
+                }
+            }
+        };
+        let warnings = match &self.plan_estimate {
+            Some(estimate) => estimate.warnings(&self.config.plan_estimate),
+            None => Vec::new(),
+        };
+        if approved && !warnings.is_empty() {
+            let confirmation = ChatMessage::new_text(
+                MessageRole::Assistant,
+                self.name.clone(),
+                format!(
+                    "This plan is expensive, the estimate is approximate:\n- {}\nStart it anyway?",
+                    warnings.join("\n- ")
+                ),
+            );
+            approved = guard.get_approval(confirmation).await;
+        }
+        if !approved {
+            self.aborted = true;
+            self.prepare_final_answer(
+                "The user rejected the plan".to_string(),
+                Some("The plan was not approved, so no steps were executed.".to_string()),
+            ).await?;
+        }
+        Ok(())
+    }
+
+    // 每次规划或重规划之后的第一轮会重新广播计划
+    async fn run_execution_loop(&mut self) -> Result<()> {
+        while !self.state.is_terminated {
+            let first_step = std::mem::take(&mut self.restart_execution);
+            self.orchestrator_step_execution(first_step).await?;
+        }
+        Ok(())
+    }
+
+    /// 按用户要求停止：CLI 输入循环和后端取消接口最终都走到这里
+    pub async fn stop(&mut self) -> Result<()> {
+        self.user_messages.cancel();
+        self.aborted = true;
+        let reason = "Stopped at the user's request".to_string();
+        self.emit(OrchestratorEvent::Cancelled { step_index: self.state.current_step_idx, reason: reason.clone() });
+        self.prepare_final_answer(reason, None).await
+    }
+
+    // 没有模型回答时，用已完成的步骤和收集到的信息拼出部分进度的总结
+    fn partial_progress_summary(&self, reason: &str) -> String {
+        let mut lines = vec![format!("{}.", reason)];
+        if self.state.step_outcomes.is_empty() {
+            lines.push("No plan steps were completed.".to_string());
+        } else {
+            lines.push("Completed steps:".to_string());
+            for outcome in &self.state.step_outcomes {
+                lines.push(format!("- Step {} ({}): {}", outcome.step_index + 1, outcome.title, outcome.summary));
+            }
+        }
+        if !self.state.information_collected.trim().is_empty() {
+            lines.push(format!("Information collected so far: {}", self.state.information_collected));
+        }
+        lines.join("\n")
+    }
+
+    // 把排队的用户消息放进历史，返回是否有新消息
+    fn drain_user_messages(&mut self) -> bool {
+        let pending = self.user_messages.drain();
+        let has_new = !pending.is_empty();
+        for message in pending {
+            self.state.message_history.push(as_new_user_message(message));
+        }
+        has_new
+    }
+
+    pub fn metrics(&self) -> &OrchestratorMetrics {
+        &self.metrics
+    }
+
+    /// 当前计划的开销估算，只是近似值
+    pub fn plan_estimate(&self) -> Option<&EstimatedCost> {
+        self.plan_estimate.as_ref()
+    }
+
+    // 计划变化后重新估算并广播
+    fn update_plan_estimate(&mut self) -> Option<EstimatedCost> {
+        let plan = self.state.plan.as_ref()?;
+        let estimate = estimate(plan, &self.config.plan_estimate, self.config.pricing.as_ref());
+        self.emit(OrchestratorEvent::PlanEstimated {
+            estimate: estimate.clone(),
+            warnings: estimate.warnings(&self.config.plan_estimate),
+        });
+        self.plan_estimate = Some(estimate.clone());
+        Some(estimate)
+    }
+
+    // 按 plan_approval 策略判断能否跳过用户确认，自动批准时广播批准它的策略
+    fn auto_approve_plan(&self) -> bool {
+        let (Some(plan), Some(estimate)) = (&self.state.plan, &self.plan_estimate) else {
+            return false;
+        };
+        let approval = &self.config.plan_approval;
+        let longest_sentinel = longest_sentinel_wall_clock(plan, &self.config.plan_estimate);
+        match approval.auto_approval(&self.state.task, estimate, longest_sentinel) {
+            Some(reason) => {
+                tracing::info!("Plan approved automatically by the {} policy: {}", approval.policy.name(), reason);
+                self.emit(OrchestratorEvent::PlanAutoApproved { policy: approval.policy.name().to_string(), reason });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 本次任务的所有计划版本，按版本号排列
+    pub fn plan_history(&self) -> &[PlanVersion] {
+        &self.state.plan_history
+    }
+
+    pub fn current_plan(&self) -> Option<&Plan> {
+        self.state.plan.as_ref()
+    }
+
+    // 把当前计划记成一个新版本，有运行记录时一并持久化
+    async fn record_plan_version(&mut self, source: PlanSource, reason: Option<String>) {
+        let plan = match &self.state.plan {
+            Some(plan) => plan.clone(),
+            None => return,
+        };
+        let version = PlanVersion::new(self.state.plan_history.len() + 1, plan, source, reason);
+        if let (Some(store), Some(run_id)) = (&self.run_store, &self.run_id) {
+            if let Err(e) = store.add_plan_version(run_id, &version).await {
+                tracing::warn!("Failed to persist plan version: {:?}", e);
+            }
+        }
+        self.state.plan_history.push(version);
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.state.message_history.len()
+    }
+
+    // 历史超过配置的上限时压缩，原始任务和最近的若干条消息不受影响
+    pub fn compact(&mut self) {
+        let history = std::mem::take(&mut self.state.message_history);
+        let config = &self.config.history_compaction;
+        let compacting = needs_compaction(history.len(), config);
+        // 将被改写的原始消息先留一份，最近的消息压缩后仍是原样
+        if compacting {
+            let end = history.len() - config.keep_recent;
+            let start = self.transcript_start.min(end);
+            self.transcript_archive.extend(history[start..end].iter().cloned());
+        }
+        self.state.message_history = compact_history(history, config, &mut self.image_archive);
+        if compacting {
+            self.transcript_start = self.state.message_history.len().saturating_sub(config.keep_recent);
+        }
+    }
+
+    /// 本次运行的完整消息记录（含截图），不受历史压缩影响
+    pub fn transcript(&self) -> Vec<ChatMessage> {
+        let history = &self.state.message_history;
+        let start = self.transcript_start.min(history.len());
+        history
+            .iter()
+            .take(1)
+            .chain(&self.transcript_archive)
+            .chain(&history[start..])
+            .cloned()
+            .collect()
+    }
+
+    /* 运行记录的持久化：写库失败只记录日志，不影响任务本身的执行 */
+    async fn persist_run_start(&mut self) {
+        let store = match &self.run_store {
+            Some(store) if !self.dry_run => store.clone(),
+            _ => return,
+        };
+        match store.start_run(self.run_user_id.as_deref(), &self.state.task).await {
+            std::result::Result::Ok(run) => self.run_id = Some(run.id),
+            Err(e) => tracing::warn!("Failed to persist run start: {:?}", e),
+        }
+    }
+
+    async fn persist_checkpoint(&self) {
+        let every = self.config.checkpoint_every_n_rounds;
+        if every == 0 || !self.state.n_rounds.is_multiple_of(every) {
+            return;
+        }
+        let (store, run_id) = match (&self.run_store, &self.run_id) {
+            (Some(store), Some(run_id)) => (store, run_id),
+            _ => return,
+        };
+        if let Err(e) = store.checkpoint(
+            run_id,
+            self.plan_json().as_deref(),
+            &self.state.step_outcomes,
+            &self.state.information_collected,
+            self.state.n_rounds,
+        ).await {
+            tracing::warn!("Failed to persist run checkpoint: {:?}", e);
+        }
+    }
+
+    async fn persist_run_end(&self, final_answer: &str) {
+        let (store, run_id) = match (&self.run_store, &self.run_id) {
+            (Some(store), Some(run_id)) => (store, run_id),
+            _ => return,
+        };
+        let metrics_json = serde_json::to_string(&self.metrics).ok();
+        // 取消的运行同样以目前为止的总结结束，记为 cancelled
+        let status = if self.user_messages.is_cancelled() { RUN_STATUS_CANCELLED } else { RUN_STATUS_COMPLETED };
+        if let Err(e) = store.finish_run(
+            run_id,
+            self.plan_json().as_deref(),
+            &self.state.step_outcomes,
+            final_answer,
+            &self.state.information_collected,
+            metrics_json.as_deref(),
+            self.state.n_rounds,
+            status,
+        ).await {
+            tracing::warn!("Failed to persist run result: {:?}", e);
+        }
+    }
+
+    fn plan_json(&self) -> Option<String> {
+        if self.state.plan_str.is_empty() {
+            None
+        } else {
+            Some(self.state.plan_str.clone())
+        }
+    }
+
+    async fn prepare_final_answer(
+        &mut self,
+        reason: String,
+        final_answer: Option<String>,
+    ) -> Result<()> {
+        let mut final_answer = final_answer;
+        if final_answer.is_none() {
+            let mut context = self.thread_to_context(None)?;
+            context.push(LLMMessage::User(
+                UserMessage::new(
+                    UserContent::String(reason.clone()),
+                    self.name.clone(),
+                ),
+            ));
+
+            let template = self.config.final_answer_template()?;
+            let mut content = build_final_answer_prompt(
+                &template,
+                &self.state.task,
+                &self.state.information_collected,
+                &self.state.plan_str,
+            );
+            if let Some(language) = self.response_language() {
+                content = format!("{}\n\n{}", content.trim_end(), language_instruction(&language));
+            }
+            context.push(LLMMessage::User(
+                UserMessage::new( 
+                    UserContent::String(content),
+                    self.name.clone(),
+                ),
+            ));
+
+            self.model_context.clear();
+            for message in context {
+                self.model_context.push(message.clone());
+            }
+
+            // 模型调用失败时退回到部分进度的总结
+            match self.model_client.create(&self.model_context).await {
+                Ok(result) => {
+                    self.metrics.add_orchestrator_usage(&result.usage, self.config.pricing.as_ref());
+                    if !result.content.trim().is_empty() {
+                        final_answer = Some(result.content);
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to generate final answer: {:?}", e),
+            }
+        }
+
+        let final_answer = final_answer.unwrap_or_else(|| self.partial_progress_summary(&reason));
+        self.final_answer = Some(final_answer.clone());
+        self.metrics.finish_run();
+        let mut content = format!("Final answer: {}\n\n{}", final_answer, self.metrics.to_table());
+        if let Some(summary) = self.cost_summary() {
+            content.push_str(&format!("\n\n{}", summary.to_table()));
+        }
+        let mut message = ChatMessage::new_text(
+            MessageRole::Assistant,
+            self.name.clone(),
+            content,
+        );
+        if let Some((screenshot, path)) = &self.last_screenshot {
+            message = attach_screenshot(message, screenshot, path.as_deref());
+        }
+        message.metadata_mut().insert(MESSAGE_KIND_KEY.to_string(), FINAL_ANSWER_MESSAGE_KIND.to_string());
+        if let Ok(usage) = serde_json::to_string(&self.metrics.total_usage()) {
+            message.metadata_mut().insert(TOKEN_USAGE_KEY.to_string(), usage);
+        }
+
+        self.state.message_history.push(message.clone());
+        self.notify_all(message).await;
+        self.emit(OrchestratorEvent::Metrics { metrics: self.metrics.clone() });
+        self.persist_run_end(&final_answer).await;
+
+        // 结束
+        self.state.is_terminated = true;
+        Ok(())
+    }
+
+    /* 尽力而为的广播：并发通知除消息来源之外的所有代理，每个代理有单独的超时，
+    失败只记录日志，不影响广播本身。返回每个代理的通知结果 */
+    pub async fn notify_all(&self, content: ChatMessage) -> Vec<(String, Result<()>)> {
+        let notify_msg = Message {
+            from: "orchestrator".to_string(),
+            to: "all".to_string(),
+            chat_history: vec![content.clone()],
+            msg_type: MessageType::Notify,
+        };
+        let timeout = Duration::from_millis(self.config.notify_timeout_ms);
+
+        let notifications = self.agents
+            .iter()
+            .filter(|(name, _)| name.as_str() != content.source())
+            .map(|(name, agent)| {
+                let notify_msg = notify_msg.clone();
+                async move {
+                    // 代理正在执行时拿不到锁，加锁也计入超时
+                    let notify = async {
+                        let mut agent = agent.lock().await;
+                        agent.on_message_stream(notify_msg).await.map(|_| ())
+                    };
+                    let result = match tokio::time::timeout(timeout, notify).await {
+                        Ok(result) => result,
+                        Err(_) => Err(anyhow!("Notification timed out after {}ms", timeout.as_millis())),
+                    };
+                    if let Err(e) = &result {
+                        tracing::warn!("Failed to notify agent {}: {:#}", name, e);
+                    }
+                    (name.clone(), result)
+                }
+            });
+        futures::future::join_all(notifications).await
+    }
+
+    // 把刚生成的指令分发给选中的代理
+    pub async fn select_next_speaker(&mut self, agent_name: &str, instruction: ChatMessage) -> Result<()> {
+        // 不需要任何代理执行的步骤
+        if agent_name == "no_action_agent" {
+            let response = ChatMessage::new_text(
+                MessageRole::Assistant,
+                agent_name.to_string(),
+                "No action is needed for this step.".to_string(),
+            );
+            return self.handle_agent_response(agent_name, response).await;
+        }
+
+        let execute_msg = Message::execute(&self.name, agent_name, &self.state.task, instruction);
+
+        let agent = self.agents.get(agent_name)
+            .ok_or_else(|| anyhow::anyhow!("Agent {} not found", agent_name))?
+            .clone();
+
+        // 失败时按当前步骤的重试策略重新分发同一条指令
+        let policy = self.config.retry_policy_for_step(self.state.current_step_idx).clone();
+        self.metrics.record_agent_step(agent_name);
+        let step_index = self.state.current_step_idx;
+        self.emit(OrchestratorEvent::StepStarted {
+            step_index,
+            agent_name: agent_name.to_string(),
+            instruction: instruction_text(&execute_msg),
+        });
+        {
+            let mut agent = agent.lock().await;
+            // 代理的中间事件直接写入 orchestrator 的事件通道
+            agent.set_event_sink(Some(AgentEventSink::new(agent_name, step_index, self.event_tx.clone())));
+            // 步骤执行期间到达的用户消息由代理自行轮询
+            agent.set_mailbox(Some(self.user_messages.mailbox()));
+            agent.set_session_files(self.session_files.clone());
+        }
+
+        let cancel = self.user_messages.cancellation_token();
+        let started = Instant::now();
+        let outcome = dispatch_with_retry(&agent, execute_msg, &policy, &self.name, &cancel).await;
+        self.metrics.record_agent_time(agent_name, started.elapsed());
+        {
+            let mut agent = agent.lock().await;
+            agent.set_event_sink(None);
+            agent.set_mailbox(None);
+            agent.set_session_files(None);
+            let usage = agent.take_usage();
+            self.metrics.add_agent_usage(agent_name, &usage, self.config.agent_pricing(agent_name));
+        }
+        for note in outcome.retry_notes {
+            self.state.message_history.push(note);
+        }
+        if outcome.cancelled {
+            self.state.message_history.push(outcome.response);
+            return self.stop().await;
+        }
+        self.handle_agent_response(agent_name, outcome.response).await
+    }
+
+    // 懒创建本次运行的产物目录，以数据库中的 run_id 命名，没有时用随机 id
+    fn artifact_store(&mut self) -> Option<&mut ArtifactStore> {
+        if self.artifacts.is_none() {
+            if self.dry_run {
+                return None;
+            }
+            let base_dir = self.config.artifacts_dir.clone()?;
+            let run_id = self.run_id.clone().unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+            match ArtifactStore::new(base_dir, &run_id) {
+                std::result::Result::Ok(store) => self.artifacts = Some(store),
+                Err(e) => {
+                    tracing::warn!("Failed to create artifacts directory: {:?}", e);
+                    return None;
+                }
+            }
+        }
+        self.artifacts.as_mut()
+    }
+
+    async fn handle_agent_response(&mut self, agent_name: &str, response: ChatMessage) -> Result<()> {
+        // 代理在步骤中的每张截图都保存到产物目录，历史中只保留最后一张，并记住它用于最终答案
+        let step_index = self.state.current_step_idx;
+        let (response, saved) = match self.artifact_store() {
+            Some(store) => match store_response_screenshots(response.clone(), step_index, store) {
+                std::result::Result::Ok(stored) => stored,
+                Err(e) => {
+                    tracing::warn!("Failed to store screenshot: {:?}", e);
+                    (keep_last_image(response), Vec::new())
+                }
+            },
+            None => (keep_last_image(response), Vec::new()),
+        };
+        for path in saved {
+            self.emit(OrchestratorEvent::ScreenshotSaved {
+                agent_name: agent_name.to_string(),
+                step_index,
+                path: path.to_string_lossy().to_string(),
+            });
+        }
+        if let Some(bytes) = last_image(&response) {
+            let path = response.metadata().get(SCREENSHOT_PATH_KEY).cloned();
+            self.last_screenshot = Some((bytes.clone(), path));
+        }
+
+        // 代理在步骤中途收到了新的用户消息时，页面没有变化也不算停滞；
+        // 消息本身会在下一次 ledger 评估前放进历史
+        let flag = |key: &str| response.metadata().get(key).map(|v| v.as_str()) == Some("true");
+        let interrupted = flag(USER_INTERRUPT_KEY);
+        if interrupted {
+            tracing::info!("{} received new user input during its step", agent_name);
+        }
+        self.stall.record_page_unchanged(flag(PAGE_UNCHANGED_KEY) && !interrupted);
+        self.state.message_history.push(response.clone());
+
+        let reason = self.termination_condition
+            .as_ref()
+            .and_then(|condition| condition.check(&self.state, &response));
+        if let Some(reason) = reason {
+            self.prepare_final_answer(reason, None).await?;
+        }
+        Ok(())
+    }
+
+    async fn orchestrator_step_planning(
+        &mut self,
+    ) -> Result<()> { 
+
+        // Planning stage
+        self.state.in_planning_mode = true;
+        if self.state.n_rounds == 0 && self.state.n_replans == 0 {
+            self.metrics.start_run();
+        }
+
+        let provided_plan = self.preset_plan.is_some();
+        let plan_response = match self.preset_plan.take() {
+            // 导入的计划已经在 set_plan 中校验过，跳过模型
+            Some(plan) => PlanResponse {
+                task: plan.task.clone().unwrap_or_else(|| self.state.task.clone()),
+                plan_summary: format!("Using the provided plan with {} step(s)", plan.steps.len()),
+                response: String::new(),
+                needs_plan: true,
+                steps: plan.steps,
+                needs_clarification: false,
+                clarification_question: String::new(),
+            },
+            None => {
+                let plan_response = self.request_plan(None, true).await?;
+                if plan_response.needs_clarification {
+                    match self.ask_clarification(plan_response.clarification_question).await? {
+                        Some(plan_response) => plan_response,
+                        // 用户在回答追问时停止了运行
+                        None => return Ok(()),
+                    }
+                } else {
+                    plan_response
+                }
+            }
+        };
+
+        if self.run_id.is_none() {
+            self.persist_run_start().await;
+        }
+
+        // 不需要计划的请求直接回答
+        if !plan_response.needs_plan || plan_response.steps.is_empty() {
+            self.state.in_planning_mode = false;
+            return self.prepare_final_answer("No plan needed".to_string(), Some(plan_response.response)).await;
+        }
+
+        let plan = Plan {
+            task: Some(plan_response.task.clone()),
+            steps: plan_response.steps.clone(),
+        };
+        self.state.plan_str = serde_json::to_string(&plan)?;
+        self.state.plan = Some(plan);
+        self.state.sync_current_step_id();
+        if provided_plan {
+            self.record_plan_version(PlanSource::UserEdited, Some("provided plan".to_string())).await;
+        } else {
+            self.record_plan_version(PlanSource::Generated, None).await;
+        }
+        let estimate = self.update_plan_estimate();
+
+        let mut plan_message = ChatMessage::new_text(
+            MessageRole::Assistant,
+            self.name.clone(),
+            plan_response.response,
+        );
+        plan_message.metadata_mut().insert(MESSAGE_KIND_KEY.to_string(), PLAN_MESSAGE_KIND.to_string());
+        if let Some(estimate) = estimate {
+            plan_message.metadata_mut().insert(PLAN_ESTIMATE_KEY.to_string(), serde_json::to_string(&estimate)?);
+        }
+        self.state.message_history.push(plan_message);
+
+        self.plan_auto_approved = self.auto_approve_plan();
+        if self.config.cooperative_planning && self.agents.contains_key("user_proxy") && !self.plan_auto_approved {
+            // 协作规划：只把计划摘要交给用户确认，用户可以直接回一份修改后的计划
+            let summary = ChatMessage::new_text(
+                MessageRole::Assistant,
+                self.name.clone(),
+                plan_response.plan_summary.clone(),
+            );
+            let mut request = summary;
+            let mut attempts = 0;
+            loop {
+                self.select_next_speaker("user_proxy", request).await?;
+                if self.state.is_terminated {
+                    return Ok(());
+                }
+
+                // 回复不是 JSON 时视为确认原计划；是 JSON 但不是合法的计划时把错误告诉用户，请用户重新发送
+                let user_plan = self.state.message_history.last().map(message_text).unwrap_or_default();
+                match parse_plan_lenient(&user_plan) {
+                    Ok(plan) => {
+                        self.state.plan_str = serde_json::to_string(&plan)?;
+                        self.state.plan = Some(plan);
+                        self.state.sync_current_step_id();
+                        self.record_plan_version(PlanSource::UserEdited, Some("edited during cooperative planning".to_string())).await;
+                        self.update_plan_estimate();
+                        break;
+                    }
+                    Err(PlanParseError::NotJson(_)) => break,
+                    Err(e) if attempts < self.config.max_json_retries => {
+                        attempts += 1;
+                        request = ChatMessage::new_text(
+                            MessageRole::Assistant,
+                            self.name.clone(),
+                            format!("The edited plan could not be used: {}. Please send the corrected plan, or approve the current plan.", e),
+                        );
+                    }
+                    Err(e) => {
+                        tracing::warn!("Keeping the generated plan, the edited plan could not be used: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+        if let Some(plan) = self.state.plan.clone() {
+            self.emit(OrchestratorEvent::PlanReady { plan });
+        }
+
+        self.state.in_planning_mode = false;
+        self.restart_execution = true;
+        Ok(())
+    }
+
+    async fn orchestrator_step_execution(
+        &mut self,
+        first_step: bool,
+    ) -> Result<()> { 
+        // 第一次计划
+        if first_step {
+            
+            let content = format!(
+                r#"
+                We are working to address the following user request:
+                \\n\\n
+                {task}
+                \\n\\n
+                To answer this request we have assembled the following team:
+                \\n\\n
+                {team}
+                \\n\\n
+                Here is the plan to follow as best as possible:
+                \\n\\n
+                {plan}
+                "#,
+                task = self.state.task.clone(),
+                team = self.team_description()?,
+                plan = self.state.plan_str.clone(),
+            );
+
+            let mut ledger_message = ChatMessage::new_text(MessageRole::User, self.name.clone(), content);
+            ledger_message.metadata_mut().insert(MESSAGE_KIND_KEY.to_string(), PLAN_MESSAGE_KIND.to_string());
+
+            self.state.message_history.push(ledger_message.clone());
+        }
+
+        let length = if let Some(plan) = &self.state.plan {
+            plan.steps.len()
+        } else {
+            0
+        };
+
+        // 用户在上一步执行期间发送了停止指令
+        if self.user_messages.is_cancelled() {
+            if !self.state.is_terminated {
+                self.stop().await?;
+            }
+            return Ok(());
+        }
+
+        let max_turns = self.config.max_turns.unwrap_or(100);
+        if self.state.current_step_idx >= length || self.state.n_rounds > max_turns {
+            self.prepare_final_answer("Max rounds reached".to_string(), None).await?;
+            return Ok(());
+        }
+
+        self.state.n_rounds += 1;
+        self.metrics.record_round();
+        self.metrics.start_step(self.state.current_step_idx);
+
+        // ledger 评估之前取出用户在运行中追加的消息
+        self.drain_user_messages();
+        self.compact();
+
+        let mut context = self.thread_to_context(None)?;
+
+        
+        let progress_ledger_prompt = self.get_progress_ledger_prompt(
+            self.state.task.clone(),
+            self.state.plan_str.clone(),
+            self.state.current_step_idx,
+            self.team_description()?,
+            self.registered_agent_names(),
+        )?;
+
+        context.push(LLMMessage::User(
+            UserMessage::new(
+                UserContent::String(progress_ledger_prompt), self.name.clone()
+            ),
+        ));
+
+        let validate_ledger = |value: &JsonValue| {
+            if Self::validate_progress_ledger_json(value) { Ok(()) } else { Err(String::new()) }
+        };
+        let progress_ledger: ProgressLedger = self.get_json_response(context, validate_ledger).await?;
+        self.metrics.record_ledger_call();
+
+        // 哨兵步骤按自己的条件结束：按次数时做满 n 次检查，按文字条件时至少检查一次后由 ledger 判断
+        let sentinel = self.current_sentinel().filter(|_| self.config.sentinel_tasks_enabled);
+        let step_complete = match &sentinel {
+            Some((_, _, 0)) => false,
+            Some((_, SentinelCondition::Count(total), checks)) => checks >= total,
+            _ => progress_ledger.is_current_step_complete.answer,
+        };
+        self.emit(OrchestratorEvent::LedgerEvaluated {
+            step_index: self.state.current_step_idx,
+            step_complete,
+            need_to_replan: progress_ledger.need_to_replan.answer,
+            next_speaker: progress_ledger.instruction_or_question.agent_name.clone(),
+        });
+
+        // ledger 的 progress_summary 即目前收集到的信息
+        if !progress_ledger.progress_summary.trim().is_empty() {
+            self.state.information_collected = progress_ledger.progress_summary.clone();
+        }
+        self.persist_checkpoint().await;
+
+        if !first_step {
+            let need_to_replan = progress_ledger.need_to_replan.answer;
+            let replan_reason = progress_ledger.need_to_replan.reason;
+
+            if need_to_replan {
+                if self.state.n_replans < self.config.max_replans {
+                    self.state.n_replans += 1;
+                    self.metrics.record_replan();
+                    self.replan(Some(replan_reason)).await?;
+                    return Ok(());
+                } else {
+                    let reason = format!("We need to replan but max replan attempts reached: {replan_reason} ");
+                    self.prepare_final_answer(reason, None).await?;
+                    return Ok(());
+                }
+            }
+
+            if step_complete {
+                let (step_id, title) = self.state.plan
+                    .as_ref()
+                    .and_then(|plan| plan.steps.get(self.state.current_step_idx))
+                    .map(|step| (step.id.clone(), step.title.clone()))
+                    .unwrap_or_default();
+                self.metrics.finish_step(self.state.current_step_idx, &title);
+                self.state.step_outcomes.push(StepOutcome {
+                    step_index: self.state.current_step_idx,
+                    step_id,
+                    title,
+                    summary: progress_ledger.is_current_step_complete.reason.clone(),
+                });
+                self.state.current_step_idx += 1;
+                self.state.sync_current_step_id();
+                self.save_session_checkpoint(CheckpointReason::StepFinished).await;
+            } else if let Some((sleep_duration, condition, checks)) = sentinel.filter(|(_, _, checks)| *checks > 0) {
+                self.wait_for_next_check(sleep_duration, &condition, checks).await;
+                if self.user_messages.is_cancelled() {
+                    self.stop().await?;
+                    return Ok(());
+                }
+            }
+        }
+
+        let plan_length = if let Some(plan) = &self.state.plan {
+            plan.steps.len()
+        } else {
+            0
+        };
+
+        if self.state.current_step_idx >= plan_length {
+            self.prepare_final_answer("Plan completed".to_string(), None).await?;
+            return Ok(());
+        }
+
+        // 步骤迟迟没有进展：强制重规划，重规划次数用完时给出部分答案
+        let step_index = self.state.current_step_idx;
+        if self.current_sentinel().is_none() && self.stall.observe(step_index, &progress_ledger.instruction_or_question.answer, &self.config.stall_detection) {
+            let rounds = self.stall.rounds();
+            self.emit(OrchestratorEvent::StallDetected { step_index, rounds });
+            let reason = format!("no progress on step {} after {} attempts", step_index + 1, rounds);
+            if self.state.n_replans < self.config.max_replans {
+                self.state.n_replans += 1;
+                self.metrics.record_replan();
+                self.replan(Some(reason)).await?;
+            } else {
+                self.aborted = true;
+                self.prepare_final_answer(format!("Stopping: {}", reason), None).await?;
+            }
+            return Ok(());
+        }
+
+        // ledger 给出的名字同样需要映射，无法识别时交给当前步骤计划的代理
+        let next_speaker = match resolve_agent_name(
+            &progress_ledger.instruction_or_question.agent_name,
+            &self.registered_agent_names(),
+            &self.config.agent_aliases,
+        ) {
+            Some(name) => name,
+            None => self.state.plan
+                .as_ref()
+                .and_then(|plan| plan.steps.get(self.state.current_step_idx))
+                .map(|step| step.agent_name.clone())
+                .unwrap_or_else(|| progress_ledger.instruction_or_question.agent_name.clone()),
+        };
+
+        // 哨兵步骤需要显式开启，否则不执行
+        if let Some(step) = self.state.plan.as_ref().and_then(|plan| plan.steps.get(self.state.current_step_idx)) {
+            if step.kind.is_sentinel() && !self.config.sentinel_tasks_enabled {
+                return Err(anyhow!(
+                    "Step {} ('{}') is a sentinel step, but sentinel tasks are disabled; set sentinel_tasks_enabled to run it",
+                    self.state.current_step_idx + 1,
+                    step.title
+                ));
+            }
+        }
+
+        // 分发之前的人工审批
+        let mut instruction = progress_ledger.instruction_or_question.answer.clone();
+        if self.config.step_approval.requires_approval(self.state.current_step_idx, self.state.last_approved_step) {
+            match self.request_step_approval(&instruction, &next_speaker).await? {
+                StepGateOutcome::Approved { instruction: approved, .. } => {
+                    instruction = approved;
+                    self.state.last_approved_step = Some(self.state.current_step_idx);
+                }
+                StepGateOutcome::Rejected { reason } => {
+                    let content = match reason {
+                        Some(reason) => format!("The user rejected step {}: {}", self.state.current_step_idx + 1, reason),
+                        None => format!("The user rejected step {}.", self.state.current_step_idx + 1),
+                    };
+                    self.state.message_history.push(
+                        ChatMessage::new_text(MessageRole::User, "user_proxy".to_string(), content)
+                    );
+                    // 下一轮重新评估 ledger（通常会触发重规划）
+                    return Ok(());
+                }
+            }
+        }
+
+        let new_instruction = self.get_agent_instruction(
+            instruction,
+            next_speaker.clone()
+        )?;
+
+        let message_to_send = ChatMessage::new_text(
+            MessageRole::User,
+            self.name.clone(),
+            new_instruction,
+        );
+        self.state.message_history.push(message_to_send.clone());
+
+        if !self.registered_agent_names().contains(&next_speaker) {
+            // 指令没有送达任何代理：告诉下一轮的 ledger，并计入停滞检测，避免反复空转
+            self.emit(OrchestratorEvent::AgentNotRegistered { step_index, agent_name: next_speaker.clone() });
+            self.state.message_history.push(ChatMessage::new_text(
+                MessageRole::User,
+                self.name.clone(),
+                format!("The instruction was not delivered: agent '{}' is not registered.", next_speaker),
+            ));
+            self.stall.record_not_dispatched();
+            return Ok(());
+        }
+        self.record_sentinel_check();
+        self.select_next_speaker(&next_speaker, message_to_send).await?;
+        Ok(())
+    }
+
+    // 当前步骤是哨兵步骤时返回它的检查间隔、结束条件和已经分发的检查次数
+    fn current_sentinel(&self) -> Option<(u64, SentinelCondition, u32)> {
+        let step = self.state.plan.as_ref()?.steps.get(self.state.current_step_idx)?;
+        let StepKind::Sentinel { sleep_duration, condition } = &step.kind else {
+            return None;
+        };
+        let checks = match &self.state.sentinel_checks {
+            Some((step_id, checks)) if *step_id == step.id => *checks,
+            _ => 0,
+        };
+        Some((*sleep_duration, condition.clone(), checks))
+    }
+
+    // 分发哨兵步骤的一次检查，换了步骤时从 1 重新计数
+    fn record_sentinel_check(&mut self) {
+        if let Some((_, _, checks)) = self.current_sentinel() {
+            let step_id = self.state.plan
+                .as_ref()
+                .and_then(|plan| plan.steps.get(self.state.current_step_idx))
+                .map(|step| step.id.clone())
+                .unwrap_or_default();
+            self.state.sentinel_checks = Some((step_id, checks + 1));
+        }
+    }
+
+    /* 哨兵步骤的条件还没有满足：发出 SentinelWaiting，并在历史里记一笔让下一次 ledger 知道检查进度，
+    然后等待 sleep_duration 秒再分发下一次检查。等待期间收到停止指令时立即返回 */
+    async fn wait_for_next_check(&mut self, sleep_duration: u64, condition: &SentinelCondition, checks: u32) {
+        let step_index = self.state.current_step_idx;
+        let total = match condition {
+            SentinelCondition::Count(total) => Some(*total),
+            SentinelCondition::Text(_) => None,
+        };
+        self.emit(OrchestratorEvent::SentinelWaiting { step_index, check: checks, total, next_check_secs: sleep_duration });
+        self.state.message_history.push(ChatMessage::new_text(
+            MessageRole::User,
+            self.name.clone(),
+            format!(
+                "Check {} of step {} finished without meeting the condition; the next check starts in {} seconds.",
+                checks,
+                step_index + 1,
+                sleep_duration
+            ),
+        ));
+
+        let cancel = self.user_messages.cancellation_token();
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(sleep_duration)) => {}
+            _ = cancel.cancelled() => {}
+        }
+    }
+
+    // 把当前步骤和 ledger 给出的指令交给 guard 审批，没有 guard 时直接通过
+    async fn request_step_approval(&self, instruction: &str, agent_name: &str) -> Result<StepGateOutcome> {
+        let step_index = self.state.current_step_idx;
+        let guard = match &self.action_guard {
+            Some(guard) => guard.clone(),
+            None => {
+                return Ok(StepGateOutcome::Approved {
+                    instruction: instruction.to_string(),
+                    edited: false,
+                })
+            }
+        };
+
+        let step = self.state.plan
+            .as_ref()
+            .and_then(|plan| plan.steps.get(step_index))
+            .ok_or_else(|| anyhow::anyhow!("Plan must be initialized"))?;
+
+        let request = StepApprovalRequest {
+            step_index,
+            step_title: step.title.clone(),
+            step_details: step.details.clone(),
+            agent_name: agent_name.to_string(),
+            instruction: instruction.to_string(),
+        };
+
+        self.emit(OrchestratorEvent::StepApprovalRequested {
+            step_index,
+            title: request.step_title.clone(),
+            instruction: request.instruction.clone(),
+        });
+
+        let outcome = gate_step(guard.as_ref(), &request).await;
+        match &outcome {
+            StepGateOutcome::Approved { edited, .. } => {
+                self.emit(OrchestratorEvent::StepApproved { step_index, edited: *edited });
+            }
+            StepGateOutcome::Rejected { reason } => {
+                self.emit(OrchestratorEvent::StepRejected { step_index, reason: reason.clone() });
+            }
+        }
+        Ok(outcome)
+    }
+
+    /* 调用模型并解析 JSON：输出不是 JSON、不符合 schema 或者反序列化失败时，
+    把错误反馈给模型重试，最多 max_json_retries 次 */
+    async fn get_json_response<T: DeserializeOwned>(
+        &mut self,
+        messages: Vec<LLMMessage>,
+        validate_json: impl Fn(&JsonValue) -> std::result::Result<(), String>,
+    ) -> Result<T> {
+        let mut messages = messages;
+        let mut last_error = String::new();
+
+        for _ in 0..=self.config.max_json_retries {
+            self.model_context = messages.clone();
+            let result = if self.state.in_planning_mode {
+                self.create_streaming_plan(&messages).await?
+            } else {
+                self.model_client.create(&messages).await?
+            };
+            self.metrics.add_orchestrator_usage(&result.usage, self.config.pricing.as_ref());
+
+            // 计划以 emit_plan 调用的形式给出时，调用参数就是计划的 JSON
+            let text = match result.tool_call(EMIT_PLAN_TOOL) {
+                Some(call) if self.state.in_planning_mode => call.arguments.clone(),
+                _ => result.content.clone(),
+            };
+            // 几乎合法的输出（代码块、多余的逗号、单引号等）先在本地修复，修不好才再请求模型
+            let parsed = parse_json_lenient(&text).map(|repaired| {
+                if !repaired.repairs.is_empty() {
+                    tracing::info!("Repaired the JSON response: {:?}", repaired.repairs);
+                }
+                repaired.value
+            });
+            match parsed {
+                Some(value) => match validate_json(&value) {
+                    Ok(()) => match serde_json::from_value::<T>(value) {
+                        Ok(parsed) => return Ok(parsed),
+                        Err(e) => last_error = format!("The JSON could not be parsed: {}", e),
+                    },
+                    Err(details) if details.is_empty() => {
+                        last_error = "The JSON does not follow the required schema".to_string()
+                    }
+                    Err(details) => last_error = format!("The JSON does not follow the required schema: {}", details),
+                },
+                None => last_error = "The response is not valid JSON".to_string(),
+            }
+
+            messages.push(LLMMessage::Assistant(AssistantMessage::new(
+                AssistantContent::String(text),
+                Some(self.name.clone()),
+            )));
+            messages.push(LLMMessage::User(UserMessage::new(
+                UserContent::String(format!("{}. Please output only JSON that follows the schema above.", last_error)),
+                self.name.clone(),
+            )));
+        }
+
+        Err(anyhow!(
+            "Failed to get a valid JSON response after {} attempt(s): {}",
+            self.config.max_json_retries + 1,
+            last_error
+        ))
+    }
+
+    // 规划模型的一次调用：历史、相似计划示例、额外说明（note）和规划提示词
+    async fn request_plan(&mut self, note: Option<String>, allow_clarification: bool) -> Result<PlanResponse> {
+        let mut context = self.thread_to_context(None)?;
+        if let Some(examples) = self.plan_examples().await {
+            context.push(LLMMessage::User(UserMessage::new(UserContent::String(examples), self.name.clone())));
+        }
+        if let Some(note) = note {
+            context.push(LLMMessage::User(UserMessage::new(UserContent::String(note), self.name.clone())));
+        }
+        context.push(LLMMessage::User(
+            UserMessage::new(
+                UserContent::String(self.get_task_ledger_plan_prompt(self.team_description()?)?),
+                self.name.clone(),
+            ),
+        ));
+        self.metrics.record_planning_call();
+        self.get_plan_response(context, allow_clarification).await
+    }
+
+    /* 模型要求追问时，把问题交给 user_proxy，带着用户的回答再规划一次。
+    每次运行最多追问一次：第二次规划不允许再返回 needs_clarification。
+    没有 user_proxy 时无法追问，让模型自行做出合理的假设。用户停止运行时返回 None */
+    async fn ask_clarification(&mut self, question: String) -> Result<Option<PlanResponse>> {
+        let note = if self.agents.contains_key("user_proxy") {
+            let question_message = ChatMessage::new_text(MessageRole::Assistant, self.name.clone(), question);
+            self.state.message_history.push(question_message.clone());
+            self.select_next_speaker("user_proxy", question_message).await?;
+            if self.state.is_terminated {
+                return Ok(None);
+            }
+            "The user answered your clarification question above. Do not ask for clarification again: create the plan or answer the request directly.".to_string()
+        } else {
+            format!(
+                "You asked \"{}\", but the user cannot answer clarification questions in this run. Make reasonable assumptions, state them, and create the plan or answer the request directly.",
+                question
+            )
+        };
+        Ok(Some(self.request_plan(Some(note), false).await?))
+    }
+
+    /* 规划时使用流式调用，每个步骤一生成完就发出 PlanStepStreamed，完整的计划仍由调用方整体校验。
+    模型支持函数调用时提供 emit_plan，计划可以作为调用参数给出，也可以仍然是文字 JSON。
+    设置了 stream_plan_text 时每个片段再发出一个 PlanTextStreamed */
+    async fn create_streaming_plan(&self, messages: &[LLMMessage]) -> Result<CreateResult> {
+        let tools = if self.model_client.model_info().function_calling {
+            vec![emit_plan_tool()]
+        } else {
+            Vec::new()
+        };
+        let parser = std::sync::Mutex::new(IncrementalPlanParser::new());
+        let event_tx = self.event_tx.clone();
+        let stream_text = self.config.stream_plan_text;
+        let on_chunk = |chunk: &str| {
+            if stream_text {
+                let _ = event_tx.send(OrchestratorEvent::PlanTextStreamed { text: chunk.to_string() });
+            }
+            let mut parser = parser.lock().unwrap();
+            let first = parser.emitted();
+            for (i, step) in parser.push(chunk).into_iter().enumerate() {
+                let _ = event_tx.send(OrchestratorEvent::PlanStepStreamed { step_index: first + i, step });
+            }
+        };
+        self.model_client.create_stream(messages, &tools, &on_chunk).await
+    }
+
+    /* 获取计划并校验每个步骤的 agent_name：先做大小写/别名映射，
+    仍有未知代理时把合法的名字告诉规划模型重试一次，再不行就分配给最接近的代理并发出警告事件 */
+    async fn get_plan_response(&mut self, context: Vec<LLMMessage>, allow_clarification: bool) -> Result<PlanResponse> {
+        let names = self.registered_agent_names();
+        let sentinel_tasks_enabled = self.config.sentinel_tasks_enabled;
+        let limits = self.config.plan_validation.clone();
+        let validate_plan = |value: &JsonValue| -> std::result::Result<(), String> {
+            Self::validate_plan_json(value, sentinel_tasks_enabled, &limits)?;
+            if !allow_clarification && value.get("needs_clarification").and_then(|v| v.as_bool()) == Some(true) {
+                return Err("needs_clarification must be false, a clarification question was already asked".to_string());
+            }
+            Ok(())
+        };
+        let mut plan_response: PlanResponse = self.get_json_response(context.clone(), &validate_plan).await?;
+        let unknown = remap_plan_steps(&mut plan_response.steps, &names, &self.config.agent_aliases);
+        if unknown.is_empty() {
+            return Ok(plan_response);
+        }
+
+        let invalid = unknown
+            .iter()
+            .map(|&i| format!("step {} uses \"{}\"", i + 1, plan_response.steps[i].agent_name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut context = context;
+        context.push(LLMMessage::Assistant(AssistantMessage::new(
+            AssistantContent::String(serde_json::to_string(&plan_response)?),
+            Some(self.name.clone()),
+        )));
+        context.push(LLMMessage::User(UserMessage::new(
+            UserContent::String(format!(
+                "The plan uses agents that are not part of the team: {}. The agent_name of every step must be one of: {}. Please output the whole plan again in the same JSON format.",
+                invalid,
+                names.join(", ")
+            )),
+            self.name.clone(),
+        )));
+
+        self.metrics.record_planning_call();
+        let mut plan_response: PlanResponse = self.get_json_response(context, &validate_plan).await?;
+        for i in remap_plan_steps(&mut plan_response.steps, &names, &self.config.agent_aliases) {
+            let requested = plan_response.steps[i].agent_name.clone();
+            let assigned = match closest_agent_name(&requested, &names) {
+                Some(name) => name,
+                None => continue,
+            };
+            tracing::warn!("Step {} uses unknown agent '{}', assigned to '{}'", i + 1, requested, assigned);
+            self.emit(OrchestratorEvent::AgentNameReassigned {
+                step_index: i,
+                requested,
+                assigned: assigned.clone(),
+            });
+            plan_response.steps[i].agent_name = assigned;
+        }
+        Ok(plan_response)
+    }
+
+    /* 可以分配步骤的代理，来自实际注册的代理：先按 participant_names 的顺序，
+    再按名字排列没有列在其中的代理；自主执行时不含 user_proxy，最后加上 no_action_agent */
+    fn registered_agent_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .agent_execution_names
+            .iter()
+            .filter(|name| self.agents.contains_key(name.as_str()))
+            .cloned()
+            .collect();
+        let mut unlisted: Vec<String> = self
+            .agents
+            .keys()
+            .filter(|name| !self.participant_names.contains(name))
+            .filter(|name| !(self.config.autonomous_execution && name.as_str() == "user_proxy"))
+            .cloned()
+            .collect();
+        unlisted.sort();
+        names.extend(unlisted);
+        names.push("no_action_agent".to_string());
+        names
+    }
+
+    // 规划和 ledger 提示词中的 {team}，与 registered_agent_names 同源；没有注册任何代理时无法规划
+    fn team_description(&self) -> Result<String> {
+        let names = self.registered_agent_names();
+        if names.len() == 1 {
+            return Err(anyhow!("Cannot plan the task: no agents are registered"));
+        }
+        Ok(names
+            .iter()
+            .map(|name| {
+                let description = self
+                    .agent_execution_names
+                    .iter()
+                    .position(|n| n == name)
+                    .map(|i| self.agent_execution_descriptions[i].trim())
+                    .unwrap_or_default();
+                if description.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{} - {}", name, description)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    // ChatMessage转为LLMMessage
     fn thread_to_context(&self, message:Option<Vec<ChatMessage>>) -> Result<Vec<LLMMessage>> {
 
         let chat_messages = message.unwrap_or_else(|| self.state.message_history.clone());
@@ -438,100 +1985,86 @@ impl Orchestrator {
         }
 
         // 转换所有 ChatMessage 到 LLMMessage
-        let converted_messages = chat_history_to_llm_messages(&chat_messages)?;
+        let converted_messages = convert_agent_messages_to_llm_messages(
+            &chat_messages,
+            &self.name,
+            &self.config.model_info,
+        );
         context_messages.extend(converted_messages);
 
-        Ok(context_messages)
+        Ok(fit_to_context_window(context_messages, self.config.model_context_token_limit))
 
     }
 
-    /* 
-    async fn replan(&self,reason:String) -> Result<()> {
+    /* 保留已完成的步骤，让模型为剩下的部分生成新计划。失败原因和已完成的步骤都放进提示词，
+    模型仍然把已完成的步骤写进新计划时去掉它们，避免重复执行。
+    已完成的步骤保留原来的 id，新步骤一律分配新的 id（即使模型抄了旧计划里的 id） */
+    async fn replan(&mut self, reason: Option<String>) -> Result<()> {
         self.state.in_planning_mode = true;
+        self.stall.reset();
+        // 新计划中同一序号的步骤可能已经换了内容，需要重新审批
+        self.state.last_approved_step = None;
 
-        let context = self.thread_to_context(None)?;
-
-        let completed_steps = if let Some(ref plan) = self.state.plan {
-            &plan.steps[..self.state.current_step_idx]
-        } else {
-            &[]
+        let completed_steps: Vec<PlanStep> = match &self.state.plan {
+            Some(plan) => plan.steps[..self.state.current_step_idx.min(plan.steps.len())].to_vec(),
+            None => Vec::new(),
         };
 
-        completed_steps
-            .iter()
-            .enumerate()
-            .map(|(i,step)| -> String {
-                format!(
-                    "COMPLETED STEP {}: title=\"{}\", details=\"{}\", agent=\"{}\"",
-                    i + 1,
-                    step.title,
-                    step.details,
-                    step.agent_name
-                )
-            })
-            .collect::<Vec<_>>()
-            .join("\n");
-
-        let replan_prompt = self.get_task_ledger_replan_prompt(self.team_description.clone(), self.state.task.clone(), self.state.plan_str.clone())?;
+        let replan_prompt = self.get_task_ledger_replan_prompt(
+            self.team_description()?,
+            self.state.task.clone(),
+            self.state.plan_str.clone(),
+        )?;
+        let content = format!(
+            "{}\n\n{}",
+            build_replan_context(reason.as_deref(), &completed_steps),
+            replan_prompt
+        );
 
+        let mut context = self.thread_to_context(None)?;
         context.push(LLMMessage::User(
             UserMessage::new(
-                UserContent::String(replan_prompt), 
+                UserContent::String(content), 
                 self.name.clone()
             )
         ));
 
-        let plan_response: PlanResponse = self.get_json_response(context, self.validate_plan_json);
-
-        let new_plan = if plan_response.steps.is_empty() {
-            None
-        } else {
-            Some(Plan {
-                task: Some(plan_response.task),
-                steps: plan_response.steps,
-            })
-        };
-
-        let combined_steps = if let Some(plan) = new_plan {
-            [completed_steps, &plan.steps].concat().to_vec()
-        } else {
-            completed_steps
-        };
-
-        let new_plan_obj = Plan {
-            task: self.state.task.clone(),
-            steps: combined_steps,
+        self.metrics.record_planning_call();
+        let plan_response = self.get_plan_response(context, false).await?;
+
+        let remaining_steps: Vec<PlanStep> = plan_response
+            .steps
+            .into_iter()
+            .filter(|step| !completed_steps.iter().any(|done| is_same_step(done, step)))
+            .map(|step| PlanStep { id: new_step_id(), ..step })
+            .collect();
+        let mut new_plan = Plan {
+            task: Some(self.state.task.clone()),
+            steps: completed_steps.into_iter().chain(remaining_steps).collect(),
         };
+        new_plan.ensure_unique_step_ids();
+        self.state.plan_str = serde_json::to_string(&new_plan)?;
+        self.state.plan = Some(new_plan.clone());
+        self.state.sync_current_step_id();
+        self.record_plan_version(PlanSource::Replanned, reason).await;
+        self.update_plan_estimate();
+        self.emit(OrchestratorEvent::PlanReady { plan: new_plan });
+
+        let mut plan_message = ChatMessage::new_text(
+            MessageRole::Assistant,
+            self.name.clone(),
+            format!("Replanning: {}", plan_response.plan_summary),
+        );
+        plan_message.metadata_mut().insert(MESSAGE_KIND_KEY.to_string(), PLAN_MESSAGE_KIND.to_string());
+        self.state.message_history.push(plan_message.clone());
+        self.notify_all(plan_message).await;
 
-        let json_str = serde_json::to_string(&new_plan_obj)?;
-
-        self.state.plan_str = json_str;
-        self.state.plan = Some(new_plan_obj);
-
-        let plan_summary = plan_response
-            .get("plan_summary")
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
-
-        let updated_summary = format!("Replanning: {}",plan_summary);
-
-        plan_response.plan_summary = updated_summary;
-
-        let json_string = serde_json::to_string(&plan_response)?;
-
-        self.notify_all(ChatMessage::Text(
-            TextMessage::new(
-                json_string, 
-                self.name.clone())
-            )).await?;
-        
-        // 操作交给用户
-        self.select_next_speaker(&self.name, ChatMessage::Text(TextMessage::new(json_string, self.name.clone()))).await?;
+        self.state.in_planning_mode = false;
+        self.restart_execution = true;
+        self.save_session_checkpoint(CheckpointReason::PlanAccepted).await;
         Ok(())
     }
 
-    */
-    
     fn get_progress_ledger_prompt(
         &self,
         task: String,
@@ -550,6 +2083,14 @@ impl Orchestrator {
 
         let names_str = names.join(", ");
         let additional_instructions = String::new();
+        // 有预期结果时要求模型按它判断步骤是否完成，而不是凭观察自行猜测
+        let (expected_outcome, completion_criterion) = match &step.expected_outcome {
+            Some(outcome) => (
+                format!("\n        Expected outcome: {}", outcome),
+                " Judge this against the expected outcome of the step: the step is only complete when the expected outcome has been achieved.",
+            ),
+            None => (String::new(), ""),
+        };
 
         let prompt = format!(
             r#"
@@ -561,14 +2102,14 @@ impl Orchestrator {
         We are at step index {step_index} in the plan which is 
         Title: {step_title}
         Details: {step_details}
-        agent_name: {agent_name}
+        agent_name: {agent_name}{expected_outcome}
         And we have assembled the following team:
         {team}
         The browser the web_surfer accesses is also controlled by the user.
 
         To make progress on the request, please answer the following questions, including necessary reasoning:
 
-            - is_current_step_complete: Is the current step complete? (True if complete, or False if the current step is not yet complete)
+            - is_current_step_complete: Is the current step complete? (True if complete, or False if the current step is not yet complete){completion_criterion}
             - need_to_replan: Do we need to create a new plan? (True if user has sent new instructions and the current plan can't address it. True if the current plan cannot address the user request because we are stuck in a loop, facing significant barriers, or the current approach is not working. False if we can continue with the current plan. Most of the time we don't need a new plan.)
             - instruction_or_question: Provide complete instructions to accomplish the current step with all context needed about the task and the plan. Provide a very detailed reasoning chain for how to complete the step. If the next agent is the user, pose it directly as a question. Otherwise pose it as something you will do.
             - agent_name: Decide which team member should complete the current step from the list of team members: {names}. 
@@ -603,251 +2144,2077 @@ impl Orchestrator {
             step_title = step.title,
             step_details = step.details,
             agent_name = step.agent_name,
+            expected_outcome = expected_outcome,
+            completion_criterion = completion_criterion,
             team = team,
             names = names_str,
             additional_instructions = additional_instructions,
         );
 
-        Ok(prompt)
+        Ok(prompt)
+    }
+
+    /* 计划 JSON 的校验交给 validation 模块，错误拼成一段文字放进重试提示词。
+    代理名不在这里检查：get_plan_response 会先做别名映射，再单独处理未知代理 */
+    pub fn validate_plan_json(
+        json_response: &Value,
+        sentinel_tasks_enabled: bool,
+        limits: &PlanValidationLimits,
+    ) -> std::result::Result<(), String> {
+        validate_plan_with_limits(json_response, &[], sentinel_tasks_enabled, limits)
+            .map_err(|errors| format_validation_errors(&errors))
+    }
+
+    pub fn validate_progress_ledger_json(json_response: &Value) -> bool {
+        let obj = match json_response.as_object() {
+            Some(obj) => obj,
+            None => return false,
+        };
+
+        for key in ["is_current_step_complete", "need_to_replan"] {
+            match obj.get(key).and_then(|v| v.as_object()) {
+                Some(inner) if inner.contains_key("reason") && inner.contains_key("answer") => {}
+                _ => return false,
+            }
+        }
+
+        match obj.get("instruction_or_question").and_then(|v| v.as_object()) {
+            Some(inner) if inner.contains_key("answer") && inner.contains_key("agent_name") => {}
+            _ => return false,
+        }
+
+        obj.contains_key("progress_summary")
+    }
+
+    pub fn get_agent_instruction(&self, instruction: String, agent_name: String) -> Result<String> {
+        
+        let steps = if let Some(plan) = &self.state.plan {
+            &plan.steps
+        } else {
+            return Err(anyhow::anyhow!("Plan must be initialized"));
+        };
+        
+        let prompt = format!(
+            r#"    Step {step_index}: {step_title}
+            \\n\\n
+            {step_details}
+            \\n\\n
+            Instruction for {agent_name}: {instruction}
+            "#,
+            step_index = self.state.current_step_idx + 1,
+            step_title = steps[self.state.current_step_idx].title,
+            step_details = steps[self.state.current_step_idx].details,
+            agent_name = agent_name,
+            instruction = instruction,
+        );
+
+        Ok(prompt)
+    }
+
+    pub fn get_task_ledger_plan_prompt(&self, team: String) -> Result<String> {
+        let base_prompt = format!(
+            r#"
+            You have access to the following team members that can help you address the request each with unique expertise:
+            {team}
+            Remember, there is no requirement to involve all team members -- a team member's particular expertise may not be needed for this task.
+            When you answer without a plan and your answer includes factual information, make sure to say whether the answer was found using online search or from your own internal knowledge.
+            Your plan should be a sequence of steps that will complete the task."#,
+            team = team,
+        );
+
+        let step_types_section = r#"
+            Each step should have a title, details and agent_name fields.
+
+            The title should be a short one sentence description of the step.
+
+            The details should be a detailed description of the step. The details should be concise and directly describe the action to be taken.
+            The details should start with a brief recap of the title in one short sentence. We then follow it with a new line. We then add any additional details without repeating information from the title. We should be concise but mention all crucial details to allow the human to verify the step.
+            The details should not be longer that 2 sentences.
+
+            The agent_name should be the name of the agent that will execute the step. The agent_name should be one of the team members listed above.
+
+            The expected_outcome should describe the observable result that shows the step is complete, for example "the three menus are listed with prices".
+
+            Output an answer in pure JSON format according to the following schema. The JSON object must be parsable as-is. DO NOT OUTPUT ANYTHING OTHER THAN JSON, AND DO NOT DEVIATE FROM THIS SCHEMA:
+
+            The JSON object should have the following structure:
+
+            {
+                "response": "a complete response to the user request for Case 1.",
+                "task": "a complete description of the task requested by the user",
+                "plan_summary": "a complete summary of the plan if a plan is needed, otherwise an empty string",
+                "needs_plan": boolean,
+                "needs_clarification": boolean,
+                "clarification_question": "the question to ask the user if needs_clarification is true, otherwise an empty string",
+                "steps":
+                [
+                    {
+                        "title": "title of step 1",
+                        "details": "recap the title in one short sentence \n remaining details of step 1",
+                        "agent_name": "the name of the agent that should complete the step",
+                        "expected_outcome": "the result that shows step 1 is complete"
+                    },
+                    {
+                        "title": "title of step 2",
+                        "details": "recap the title in one short sentence \n remaining details of step 2",
+                        "agent_name": "the name of the agent that should complete the step",
+                        "expected_outcome": "the result that shows step 2 is complete"
+                    },
+                    ...
+                ]
+            }"#;
+
+        let mut prompt = format!("{}\n\n{}", base_prompt.trim(), step_types_section.trim());
+        if self.config.sentinel_tasks_enabled {
+            prompt = format!("{}\n\n{}", prompt, SENTINEL_STEPS_PROMPT.trim());
+        }
+        Ok(prompt)
+    }
+
+    pub fn get_task_ledger_replan_prompt(&self, team: String,task: String, current_plan: String) -> Result<String> {
+        let replan_intro = format!(r#"
+            The task we are trying to complete is:
+            {}
+            The plan we have tried to complete is:
+            {}
+            We have not been able to make progress on our task.
+            We need to find a new plan to tackle the task that addresses the failures in trying to complete the task previously."#,
+            task, current_plan
+        );
+
+        let base_plan_prompt = self.get_task_ledger_plan_prompt(team)?;
+        Ok(format!("{}\n\n{}", replan_intro, base_plan_prompt))
+    }
+
+    pub fn get_orchestrator_system_message_planning(&self) -> Result<String> {
+        let date_today = Local::now().format("%A, %B %e, %Y").to_string();
+        let base_message = format!(
+            r#"
+            You are a helpful AI assistant named Magentic-UI built by Microsoft Research AI Frontiers.
+            Your goal is to help the user with their request.
+            You can complete actions on the web, complete actions on behalf of the user, execute code, and more.
+            You have access to a team of agents who can help you answer questions and complete tasks.
+            The browser the web_surfer accesses is also controlled by the user.
+            You are primarily a planner, and so you can devise a plan to do anything. 
+
+
+            The date today is: {}
+
+            First consider the following:
+
+            - is the user request missing information and can benefit from clarification? For instance, if the user asks "book a flight", the request is missing information about the destination, date and we should ask for clarification before proceeding. Do not ask to clarify more than once, after the first clarification, give a plan.
+            - is the user request something that can be answered from the context of the conversation history without executing code, or browsing the internet or executing other tools? If so, we should answer the question directly in as much detail as possible.
+            When you answer without a plan and your answer includes factual information, make sure to say whether the answer was found using online search or from your own internal knowledge.
+
+
+            Case 0: If the request is missing information, set "needs_clarification" to True, put the question in the "clarification_question" field and leave "steps" empty. You can only ask for clarification once.
+
+            Case 1: If the above is true, then we should provide our answer in the "response" field and set "needs_plan" to False.
+
+            Case 2: If the above is not true, then we should consider devising a plan for addressing the request. If you are unable to answer a request, always try to come up with a plan so that other agents can help you complete the task.
+
+
+            For Case 2:
+
+            You have access to the following team members that can help you address the request each with unique expertise:
+
+            {}
+            Your plan should should be a sequence of steps that will complete the task."#,
+            date_today,
+            self.team_description()?
+        );
+
+        let step_types_section = r#"
+            Each step should have a title, details and expected_outcome field.
+
+            The title should be a short one sentence description of the step.
+
+            The details should be a detailed description of the step. The details should be concise and directly describe the action to be taken.
+            The details should start with a brief recap of the title. We then follow it with a new line. We then add any additional details without repeating information from the title. We should be concise but mention all crucial details to allow the human to verify the step.
+
+            The expected_outcome should describe the observable result that shows the step is complete, for example "the three menus are listed with prices"."#;
+        
+        let examples_section = planning_examples_section(&self.registered_agent_names());
+        let sentinel_section = if self.config.sentinel_tasks_enabled { SENTINEL_STEPS_PROMPT } else { "" };
+        let mut message = base_message + step_types_section + sentinel_section + examples_section.as_str();
+        if let Some(language) = self.response_language() {
+            message = format!("{}\n\n{}", message.trim_end(), language_instruction(&language));
+        }
+        Ok(message)
+    } 
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::config::{PlanApprovalPolicy, RetryPolicy, StepApprovalPolicy};
+    use crate::orchestrator::history::HistoryCompactionConfig;
+    use crate::orchestrator::types::UserMailbox;
+    use crate::tools::approval_guard::StepApprovalDecision;
+    use crate::testing::{direct_answer_json, ledger_json, plan_json, test_config, MockAgent, MockGuard, MockProvider, MockReply, OrchestratorBuilder};
+
+    fn last_text(orchestrator: &Orchestrator) -> String {
+        orchestrator.state.message_history.last().map(message_text).unwrap_or_default()
+    }
+
+    // 请求序列化成 JSON 后查找，text 按同样的方式转义，引号和换行也能匹配
+    fn request_contains(request: &[LLMMessage], text: &str) -> bool {
+        let escaped = serde_json::to_string(text).unwrap();
+        serde_json::to_string(request).unwrap().contains(&escaped[1..escaped.len() - 1])
+    }
+
+    #[tokio::test]
+    async fn test_plan_two_steps_final_answer() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Find the menu", &[
+                ("Find the restaurant", "Search for the restaurant", "web_surfer"),
+                ("Read the menu", "Open the menu page", "web_surfer"),
+            ]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Search for the restaurant"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Open the menu page"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("The menu has three dishes."));
+        let web_surfer = MockAgent::new("web_surfer")
+            .reply("Found the restaurant")
+            .reply("The menu lists three dishes");
+        let log = web_surfer.log();
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .build()
+            .await?;
+        let outcome = orchestrator.run_task("Find the menu".to_string(), RunOptions::default()).await?;
+
+        assert_eq!(outcome.final_answer, "The menu has three dishes.");
+        assert_eq!(outcome.plan.as_ref().map(|plan| plan.steps.len()), Some(2));
+        assert_eq!(outcome.metrics.rounds, 3);
+        assert!(outcome.artifacts.is_empty());
+        assert!(orchestrator.state.is_terminated);
+        assert_eq!(orchestrator.state.current_step_idx, 2);
+        assert_eq!(orchestrator.state.step_outcomes.len(), 2);
+        assert_eq!(provider.remaining(), 0);
+
+        let executes = log.executes();
+        assert_eq!(executes.len(), 2);
+        assert!(instruction_text(&executes[0]).contains("Search for the restaurant"));
+        assert!(instruction_text(&executes[1]).contains("Open the menu page"));
+        // 最终答案也广播给了代理
+        assert_eq!(log.notifications().len(), 1);
+
+        assert!(last_text(&orchestrator).starts_with("Final answer: The menu has three dishes."));
+        assert_eq!(orchestrator.metrics().rounds, 3);
+        assert_eq!(orchestrator.metrics().agent_steps.get("web_surfer"), Some(&2));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replan_keeps_completed_steps() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Book a table", &[
+                ("Open site A", "Open the booking site A", "web_surfer"),
+                ("Book", "Book a table on site A", "web_surfer"),
+            ]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Open the booking site A"))
+            .respond_json(ledger_json(false, true, "web_surfer", "Site A is down"))
+            .respond_json(plan_json("Book a table", &[("Use site B", "Book a table on site B", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Book a table on site B"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("Booked on site B."));
+        let web_surfer = MockAgent::new("web_surfer")
+            .reply("Site A returns 503")
+            .reply("Booked a table for two");
+        let log = web_surfer.log();
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .build()
+            .await?;
+        orchestrator.run_task("Book a table".to_string(), RunOptions::default()).await?;
+
+        assert_eq!(orchestrator.state.n_replans, 1);
+        assert_eq!(orchestrator.metrics().replans, 1);
+        let plan = orchestrator.state.plan.as_ref().unwrap();
+        assert_eq!(plan.steps.len(), 1);
+        assert_eq!(plan.steps[0].title, "Use site B");
+
+        let replan_request = &provider.requests()[3];
+        assert!(request_contains(replan_request, "We need to replan because: scripted"));
+        assert!(log.notifications().iter().any(|n| instruction_text(n).starts_with("Replanning:")));
+        assert!(last_text(&orchestrator).starts_with("Final answer: Booked on site B."));
+        assert_eq!(provider.remaining(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replan_asks_for_step_approval_again() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Book a table", &[
+                ("Open site A", "Open the booking site A", "web_surfer"),
+                ("Book", "Book a table on site A", "web_surfer"),
+            ]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Open the booking site A"))
+            .respond_json(ledger_json(false, true, "web_surfer", "Site A is down"))
+            .respond_json(plan_json("Book a table", &[("Use site B", "Book a table on site B", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Book a table on site B"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("Booked on site B."));
+        let web_surfer = MockAgent::new("web_surfer")
+            .reply("Site A returns 503")
+            .reply("Booked a table for two");
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .configure(|c| c.step_approval = StepApprovalPolicy::FirstStepOnly)
+            .build()
+            .await?;
+        let guard = Arc::new(MockGuard::approve_all());
+        orchestrator.set_action_guard(guard.clone());
+        orchestrator.run_task("Book a table".to_string(), RunOptions::default()).await?;
+
+        // 第一步批准之后重规划，新计划的第一步重新询问
+        let requests = guard.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].starts_with("Step 1: Open site A"));
+        assert!(requests[1].starts_with("Step 1: Use site B"));
+        assert_eq!(orchestrator.state.n_replans, 1);
+        assert_eq!(provider.remaining(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_step_approval_decisions_through_run_task() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Plan a dinner", &[
+                ("Search", "Search for the restaurant", "web_surfer"),
+                ("Menu", "Open the menu page", "web_surfer"),
+                ("Book", "Book a table for two", "web_surfer"),
+            ]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Search for the restaurant"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Open the menu page"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Book a table for two"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("Found the menu, the table was not booked."));
+        let web_surfer = MockAgent::new("web_surfer")
+            .reply("Found the restaurant")
+            .reply("The menu lists three dishes");
+        let log = web_surfer.log();
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .configure(|c| c.step_approval = StepApprovalPolicy::Always)
+            .build()
+            .await?;
+        let guard = Arc::new(MockGuard::deny_all()
+            .with_step_decisions(vec![
+                StepApprovalDecision::Approve,
+                StepApprovalDecision::EditInstruction("Open the vegetarian menu page".to_string()),
+                StepApprovalDecision::Reject,
+            ])
+            .with_rejection_reason("Do not book anything yet"));
+        orchestrator.set_action_guard(guard.clone());
+        let outcome = orchestrator.run_task("Plan a dinner".to_string(), RunOptions::default()).await?;
+
+        assert_eq!(guard.requests().len(), 3);
+        // 批准的步骤按原指令执行，编辑过的步骤执行改写后的指令，被拒绝的步骤不执行
+        let executes = log.executes();
+        assert_eq!(executes.len(), 2);
+        assert!(instruction_text(&executes[0]).contains("Search for the restaurant"));
+        assert!(instruction_text(&executes[1]).contains("Open the vegetarian menu page"));
+        assert!(orchestrator.state.message_history
+            .iter()
+            .any(|m| message_text(m) == "The user rejected step 3: Do not book anything yet"));
+        assert_eq!(outcome.final_answer, "Found the menu, the table was not booked.");
+        assert_eq!(provider.remaining(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_termination_condition_ends_run() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Find a flight", &[
+                ("Search", "Search for flights", "web_surfer"),
+                ("Compare", "Compare prices", "web_surfer"),
+            ]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Search for flights"))
+            .respond("Stopped early with one flight found."));
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer").reply("Found one flight. TERMINATE"))
+            .termination_condition(TextMentionTermination { text: "TERMINATE".to_string() })
+            .build()
+            .await?;
+        orchestrator.run_task("Find a flight".to_string(), RunOptions::default()).await?;
+
+        assert!(orchestrator.state.is_terminated);
+        assert_eq!(orchestrator.state.current_step_idx, 0);
+        let final_request = provider.requests().pop().unwrap();
+        assert!(request_contains(&final_request, "Termination text 'TERMINATE' mentioned by web_surfer"));
+        assert!(last_text(&orchestrator).starts_with("Final answer: Stopped early with one flight found."));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_step_timeout_becomes_observation() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Load the page", &[("Load", "Load the slow page", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Load the slow page"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Give up on the page"))
+            .respond("The page did not load."));
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer").delayed_reply(Duration::from_secs(30), "loaded"))
+            .configure(|config| {
+                config.retry_policy = RetryPolicy { step_timeout_ms: Some(50), ..RetryPolicy::no_retry() };
+            })
+            .build()
+            .await?;
+        tokio::time::timeout(Duration::from_secs(5), orchestrator.run_task("Load the page".to_string(), RunOptions::default())).await??;
+
+        let timed_out = orchestrator.state.message_history.iter().any(|m| {
+            m.source() == "web_surfer" && message_text(m).contains("Step timed out after 50ms")
+        });
+        assert!(timed_out);
+        assert!(last_text(&orchestrator).starts_with("Final answer: The page did not load."));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invalid_json_is_retried_and_direct_answer() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond("Sure, let me think about it")
+            .respond_json(direct_answer_json("Capital of France", "Paris")));
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .build()
+            .await?;
+        let outcome = orchestrator.run_task("What is the capital of France?".to_string(), RunOptions::default()).await?;
+
+        assert_eq!(outcome.final_answer, "Paris");
+        assert!(outcome.plan.is_none());
+        let requests = provider.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(request_contains(&requests[1], "The response is not valid JSON"));
+        assert!(orchestrator.state.plan.is_none());
+        assert!(last_text(&orchestrator).starts_with("Final answer: Paris"));
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_unknown_agent_name_reprompts_planner() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Find the menu", &[("Search", "Search for the menu", "browser_bot")]))
+            .respond_json(plan_json("Find the menu", &[("Search", "Search for the menu", "WebSurfer")]))
+            .respond_json(ledger_json(false, false, "Web_Surfer", "Search for the menu"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("Found the menu."));
+        let web_surfer = MockAgent::new("web_surfer");
+        let log = web_surfer.log();
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .build()
+            .await?;
+        orchestrator.run_task("Find the menu".to_string(), RunOptions::default()).await?;
+
+        let correction = &provider.requests()[1];
+        assert!(request_contains(correction, "not part of the team: step 1 uses"));
+        assert!(request_contains(correction, "must be one of: web_surfer, no_action_agent"));
+        assert_eq!(orchestrator.state.plan.as_ref().unwrap().steps[0].agent_name, "web_surfer");
+        // ledger 给出的名字同样被映射
+        assert_eq!(log.executes().len(), 1);
+        assert_eq!(orchestrator.metrics().planning_calls, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_agent_name_falls_back_to_closest_match() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Find the menu", &[("Search", "Search for the menu", "web_surfing_bot")]))
+            .respond_json(plan_json("Find the menu", &[("Search", "Search for the menu", "web_surfing_bot")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Search for the menu"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("Found the menu."));
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .build()
+            .await?;
+        let mut events = orchestrator.subscribe_events();
+        orchestrator.run_task("Find the menu".to_string(), RunOptions::default()).await?;
+
+        assert_eq!(orchestrator.state.plan.as_ref().unwrap().steps[0].agent_name, "web_surfer");
+        let mut reassigned = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let OrchestratorEvent::AgentNameReassigned { step_index, requested, assigned } = event {
+                reassigned.push((step_index, requested, assigned));
+            }
+        }
+        assert_eq!(reassigned, vec![(0, "web_surfing_bot".to_string(), "web_surfer".to_string())]);
+        Ok(())
+    }
+    #[derive(Debug)]
+    struct RejectingGuard;
+
+    #[async_trait::async_trait]
+    impl ActionGuard for RejectingGuard {
+        async fn get_approval(&self, _request: ChatMessage) -> bool {
+            false
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejected_plan_is_not_executed() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Delete my emails", &[("Delete", "Delete all emails", "web_surfer")])));
+        let web_surfer = MockAgent::new("web_surfer");
+        let log = web_surfer.log();
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .build()
+            .await?;
+        orchestrator.set_action_guard(Arc::new(RejectingGuard));
+        let outcome = orchestrator
+            .run_task("Delete my emails".to_string(), RunOptions { approve_plan: true, ..RunOptions::default() })
+            .await?;
+
+        assert_eq!(outcome.final_answer, "The plan was not approved, so no steps were executed.");
+        assert!(log.executes().is_empty());
+        assert_eq!(provider.requests().len(), 1);
+        Ok(())
+    }
+
+    // 审批时删掉计划的第一步
+    #[derive(Debug)]
+    struct EditingGuard;
+
+    #[async_trait::async_trait]
+    impl ActionGuard for EditingGuard {
+        async fn get_approval(&self, _request: ChatMessage) -> bool {
+            true
+        }
+
+        async fn get_plan_approval(&self, request: &PlanApprovalRequest) -> PlanApprovalDecision {
+            let mut plan = request.plan.clone();
+            plan.steps.remove(0);
+            PlanApprovalDecision::Edited(plan)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plan_edited_during_approval_is_executed() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Order a latte", &[
+                ("Open", "Open the menu page", "web_surfer"),
+                ("Order", "Order a latte", "web_surfer"),
+            ]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Order a latte"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("Ordered a latte."));
+        let web_surfer = MockAgent::new("web_surfer").reply("Ordered");
+        let log = web_surfer.log();
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .build()
+            .await?;
+        orchestrator.set_action_guard(Arc::new(EditingGuard));
+        let outcome = orchestrator
+            .run_task("Order a latte".to_string(), RunOptions { approve_plan: true, ..RunOptions::default() })
+            .await?;
+
+        assert_eq!(outcome.final_answer, "Ordered a latte.");
+        assert_eq!(log.executes().len(), 1);
+        let plan = orchestrator.current_plan().unwrap();
+        assert_eq!(plan.steps.iter().map(|s| s.title.as_str()).collect::<Vec<_>>(), ["Order"]);
+        let history = orchestrator.plan_history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].source, PlanSource::UserEdited);
+        assert_eq!(history[1].reason.as_deref(), Some("edited before approval"));
+        Ok(())
+    }
+
+    // 第一次审批要求重新生成，之后批准
+    #[derive(Debug, Default)]
+    struct RegeneratingGuard {
+        asked: std::sync::Mutex<usize>,
+        previous: std::sync::Mutex<Vec<Option<Plan>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl ActionGuard for RegeneratingGuard {
+        async fn get_approval(&self, _request: ChatMessage) -> bool {
+            true
+        }
+
+        async fn get_plan_approval(&self, request: &PlanApprovalRequest) -> PlanApprovalDecision {
+            self.previous.lock().unwrap().push(request.previous_plan.clone());
+            let mut asked = self.asked.lock().unwrap();
+            *asked += 1;
+            if *asked == 1 {
+                PlanApprovalDecision::Regenerate(Some("Use the cafe's own site".to_string()))
+            } else {
+                PlanApprovalDecision::Approve
+            }
+        }
     }
 
-    pub fn validate_plan_json(json_response: &Value) -> bool {
-        let obj = match json_response.as_object() {
-            Some(obj) => obj,
-            None => return false,
-        };
+    #[tokio::test]
+    async fn test_plan_regenerated_during_approval_is_approved_again() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Order a latte", &[("Search", "Search for a delivery app", "web_surfer")]))
+            .respond_json(plan_json("Order a latte", &[("Order", "Order on the cafe's site", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Order on the cafe's site"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("Ordered a latte."));
+        let web_surfer = MockAgent::new("web_surfer").reply("Ordered");
+        let guard = Arc::new(RegeneratingGuard::default());
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .build()
+            .await?;
+        orchestrator.set_action_guard(guard.clone());
+        let outcome = orchestrator
+            .run_task("Order a latte".to_string(), RunOptions { approve_plan: true, ..RunOptions::default() })
+            .await?;
+
+        assert_eq!(outcome.final_answer, "Ordered a latte.");
+        assert_eq!(*guard.asked.lock().unwrap(), 2);
+        // 用户给出的原因出现在重规划的请求中
+        assert!(request_contains(&provider.requests()[1], "The user asked for a new plan: Use the cafe's own site"));
+        let history = orchestrator.plan_history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].source, PlanSource::Replanned);
+        assert_eq!(history[1].reason.as_deref(), Some("The user asked for a new plan: Use the cafe's own site"));
+        assert_eq!(outcome.plan.unwrap().steps[0].title, "Order");
+        // 再次审批时带着重新生成之前的计划
+        let previous = guard.previous.lock().unwrap().clone();
+        assert!(previous[0].is_none());
+        assert_eq!(previous[1].as_ref().map(|plan| plan.steps[0].title.as_str()), Some("Search"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cost_summary_matches_scripted_usage() -> Result<()> {
+        use crate::clients::{ModelPricing, TokenUsage};
+
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Find the menu", &[("Search", "Search for the restaurant", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Search for the restaurant"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Search for the restaurant"))
+            .respond("Found it.")
+            .with_usage(TokenUsage { prompt_tokens: 100, completion_tokens: 20 }));
+        let web_surfer = MockAgent::new("web_surfer")
+            .with_usage(TokenUsage { prompt_tokens: 3000, completion_tokens: 500 });
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .configure(|config| {
+                config.pricing = Some(ModelPricing { prompt_per_1k: 0.01, completion_per_1k: 0.03 });
+                config.cost_summary.currency = "¥".to_string();
+                config.cost_summary.agent_pricing.insert(
+                    "web_surfer".to_string(),
+                    ModelPricing { prompt_per_1k: 0.002, completion_per_1k: 0.006 },
+                );
+            })
+            .build()
+            .await?;
+        let outcome = orchestrator
+            .run_task("Find the menu".to_string(), RunOptions { approve_plan: false, ..RunOptions::default() })
+            .await?;
+
+        let summary = outcome.cost_summary.expect("cost summary");
+        assert_eq!(summary.rows.len(), 2);
+        let orchestrator_row = &summary.rows[0];
+        assert_eq!(orchestrator_row.calls, 4);
+        assert_eq!(orchestrator_row.usage, TokenUsage { prompt_tokens: 400, completion_tokens: 80 });
+        let agent_row = &summary.rows[1];
+        assert_eq!(agent_row.name, "web_surfer");
+        assert_eq!(agent_row.calls, 1);
+        assert_eq!(agent_row.usage, TokenUsage { prompt_tokens: 3000, completion_tokens: 500 });
+        assert_eq!(summary.total.usage, TokenUsage { prompt_tokens: 3400, completion_tokens: 580 });
+        // 0.004 + 0.0024 + 0.006 + 0.003
+        assert!((summary.total.cost.unwrap() - 0.0154).abs() < 1e-9);
+        assert!((outcome.metrics.cost - 0.0154).abs() < 1e-9);
+        assert!(!summary.unknown_pricing);
+        assert!(summary.to_table().contains("| total | 5 | 3400 | 580 | ¥0.0154 |"));
+        let json = serde_json::to_value(orchestrator.run_outcome())?;
+        assert_eq!(json["cost_summary"]["total"]["usage"]["prompt_tokens"], 3400);
+        // 最终答案的 metadata 中带有整个任务的用量
+        let final_message = orchestrator.state.message_history.last().expect("final answer");
+        let usage: TokenUsage = serde_json::from_str(&final_message.metadata()[TOKEN_USAGE_KEY])?;
+        assert_eq!(usage, TokenUsage { prompt_tokens: 3400, completion_tokens: 580 });
+
+        // 关闭后不再汇总
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(Arc::new(MockProvider::new().respond_json(direct_answer_json("Hi", "Hello!"))))
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .configure(|config| config.cost_summary.enabled = false)
+            .build()
+            .await?;
+        let outcome = orchestrator.run_task("Hi".to_string(), RunOptions::default()).await?;
+        assert!(outcome.cost_summary.is_none());
+        Ok(())
+    }
 
-        let keys = ["task", "steps", "needs_plan", "response", "plan_summary"];
-        for &key in &keys {
-            if !obj.contains_key(key) {
-                return false;
+    #[tokio::test]
+    async fn test_cheap_plan_is_auto_approved() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Find the menu", &[("Search", "Search for the restaurant", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Search for the restaurant"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Search for the restaurant"))
+            .respond("Found it."));
+        let web_surfer = MockAgent::new("web_surfer");
+        let log = web_surfer.log();
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .configure(|config| {
+                config.pricing = Some(crate::clients::ModelPricing { prompt_per_1k: 0.01, completion_per_1k: 0.03 });
+                config.plan_approval.policy = PlanApprovalPolicy::AutoApproveBelowCost { usd: 5.0 };
+            })
+            .build()
+            .await?;
+        // 自动批准时不会询问 guard
+        orchestrator.set_action_guard(Arc::new(RejectingGuard));
+        let mut events = orchestrator.subscribe_events();
+        let outcome = orchestrator
+            .run_task("Find the menu".to_string(), RunOptions { approve_plan: true, ..RunOptions::default() })
+            .await?;
+
+        assert_eq!(outcome.final_answer, "Found it.");
+        assert_eq!(log.executes().len(), 1);
+        let mut approvals = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let OrchestratorEvent::PlanAutoApproved { policy, reason } = event {
+                approvals.push((policy, reason));
+            }
+        }
+        assert_eq!(approvals.len(), 1);
+        assert_eq!(approvals[0].0, "auto_approve_below_cost");
+        assert!(approvals[0].1.ends_with("is below $5.00"));
+        Ok(())
+    }
+    fn stall_events(events: &mut broadcast::Receiver<OrchestratorEvent>) -> Vec<(usize, usize)> {
+        let mut stalls = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let OrchestratorEvent::StallDetected { step_index, rounds } = event {
+                stalls.push((step_index, rounds));
             }
         }
+        stalls
+    }
 
-        let steps = match obj.get("steps") {
-            Some(Value::Array(s)) => s,
-            _ => return false,
-        };
+    #[tokio::test]
+    async fn test_stall_forces_replan_at_threshold() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Find the price", &[("Find price", "Find the price on the page", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Scroll down to find the price"))
+            .respond_json(ledger_json(false, false, "web_surfer", "Scroll down to find the price."))
+            .respond_json(ledger_json(false, false, "web_surfer", "scroll down to find the price"))
+            .respond_json(plan_json("Find the price", &[("Search price", "Search for the price instead", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Search for the price"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("The price is $10."));
+        let web_surfer = MockAgent::new("web_surfer");
+        let log = web_surfer.log();
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .build()
+            .await?;
+        let mut events = orchestrator.subscribe_events();
+        let outcome = orchestrator.run_task("Find the price".to_string(), RunOptions::default()).await?;
+
+        // 第三次相同的指令没有再分发，而是触发重规划
+        assert_eq!(stall_events(&mut events), vec![(0, 3)]);
+        assert!(request_contains(&provider.requests()[4], "We need to replan because: no progress on step 1 after 3 attempts"));
+        assert_eq!(log.executes().len(), 3);
+        assert_eq!(outcome.metrics.replans, 1);
+        assert_eq!(outcome.final_answer, "The price is $10.");
+        Ok(())
+    }
 
-        for step in steps {
-            let step_obj = match step.as_object() {
-                Some(obj) => obj,
-                None => return false,
-            };
+    #[tokio::test]
+    async fn test_stall_with_replans_exhausted_gives_partial_answer() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Find the price", &[("Find price", "Find the price on the page", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Scroll down to find the price"))
+            .respond_json(ledger_json(false, false, "web_surfer", "Scroll down to find the price"))
+            .fail("final answer model unavailable"));
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .configure(|config| {
+                config.max_replans = 0;
+                config.stall_detection.max_rounds_without_progress = 2;
+            })
+            .build()
+            .await?;
+        let outcome = orchestrator.run_task("Find the price".to_string(), RunOptions::default()).await?;
 
-            if !step_obj.contains_key("title")
-                || !step_obj.contains_key("details")
-                || !step_obj.contains_key("agent_name")
-            {
-                return false;
+        assert!(outcome.final_answer.starts_with("Stopping: no progress on step 1 after 2 attempts."));
+        assert!(outcome.final_answer.contains("No plan steps were completed."));
+        assert_eq!(outcome.metrics.replans, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_notify_all_is_best_effort() -> Result<()> {
+        let broken = MockAgent::new("broken_agent").on_notify(MockReply::Error("crashed".to_string()));
+        let slow = MockAgent::new("slow_agent")
+            .on_notify(MockReply::Delayed(Duration::from_secs(30), Box::new(MockReply::Text(String::new()))));
+        let healthy = MockAgent::new("web_surfer");
+        let source = MockAgent::new("coder_agent");
+        let (broken_log, healthy_log, source_log) = (broken.log(), healthy.log(), source.log());
+
+        let orchestrator = OrchestratorBuilder::new()
+            .provider(Arc::new(MockProvider::new()))
+            .agent("Always crashes", broken)
+            .agent("Never answers", slow)
+            .agent("Browses the web", healthy)
+            .agent("Writes code", source)
+            .configure(|config| config.notify_timeout_ms = 50)
+            .build()
+            .await?;
+
+        let message = ChatMessage::new_text(MessageRole::Assistant, "coder_agent".to_string(), "Wrote the script".to_string());
+        let results = tokio::time::timeout(Duration::from_secs(5), orchestrator.notify_all(message)).await?;
+
+        let results: HashMap<String, bool> = results.into_iter().map(|(name, result)| (name, result.is_ok())).collect();
+        assert_eq!(results, HashMap::from([
+            ("broken_agent".to_string(), false),
+            ("slow_agent".to_string(), false),
+            ("web_surfer".to_string(), true),
+        ]));
+        assert_eq!(broken_log.notifications().len(), 1);
+        assert_eq!(healthy_log.notifications().len(), 1);
+        // 消息的来源不会收到自己的消息
+        assert!(source_log.all().is_empty());
+        Ok(())
+    }
+
+    /* 模拟 WebAgent 的工具调用循环：每次调用模型之前轮询 mailbox，
+    第一次工具调用之后通知测试并等待测试投递新消息 */
+    struct ToolLoopAgent {
+        provider: Arc<MockProvider>,
+        mailbox: Option<UserMailbox>,
+        after_first_call: Option<tokio::sync::oneshot::Sender<()>>,
+        resume: Option<tokio::sync::oneshot::Receiver<()>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Agent for ToolLoopAgent {
+        fn name(&self) -> &str {
+            "web_surfer"
+        }
+
+        fn set_mailbox(&mut self, mailbox: Option<UserMailbox>) {
+            self.mailbox = mailbox;
+        }
+
+        async fn on_message_stream(&mut self, message: Message) -> Result<ChatMessage> {
+            // 广播（例如最终答案）不触发工具调用
+            if !matches!(message.msg_type, MessageType::Execute) {
+                return Ok(ChatMessage::new_text(MessageRole::Assistant, "web_surfer".to_string(), String::new()));
+            }
+            let mut history = vec![LLMMessage::User(UserMessage::new(
+                UserContent::String(instruction_text(&message)),
+                "orchestrator".to_string(),
+            ))];
+            let mut interrupted = false;
+            for _ in 0..2 {
+                for new_message in self.mailbox.as_mut().map(|m| m.take_new()).unwrap_or_default() {
+                    interrupted = true;
+                    history.push(LLMMessage::User(UserMessage::new(
+                        UserContent::String(message_text(&as_new_user_message(new_message))),
+                        "user".to_string(),
+                    )));
+                }
+                self.provider.create(&history).await?;
+                if let (Some(done), Some(resume)) = (self.after_first_call.take(), self.resume.take()) {
+                    let _ = done.send(());
+                    let _ = resume.await;
+                }
             }
+
+            let mut response = ChatMessage::new_text(MessageRole::Assistant, "web_surfer".to_string(), "Searched".to_string());
+            response.metadata_mut().insert(USER_INTERRUPT_KEY.to_string(), interrupted.to_string());
+            Ok(response)
         }
-        true
     }
 
-    pub fn get_agent_instruction(&self, instruction: String, agent_name: String) -> Result<String> {
-        
-        let steps = if let Some(plan) = &self.state.plan {
-            &plan.steps
-        } else {
-            return Err(anyhow::anyhow!("Plan must be initialized"));
+    #[tokio::test]
+    async fn test_user_message_reaches_agent_between_tool_calls() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Search for trains", &[("Search", "Search for trains", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Search for trains"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("Searched for buses."));
+        let agent_provider = Arc::new(MockProvider::new().respond("click search").respond("type buses"));
+        let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+        let (resume_tx, resume_rx) = tokio::sync::oneshot::channel();
+        let agent = ToolLoopAgent {
+            provider: agent_provider.clone(),
+            mailbox: None,
+            after_first_call: Some(done_tx),
+            resume: Some(resume_rx),
         };
-        
-        let prompt = format!(
-            r#"    Step {step_index}: {step_title}
-            \\n\\n
-            {step_details}
-            \\n\\n
-            Instruction for {agent_name}: {instruction}
-            "#,
-            step_index = self.state.current_step_idx + 1,
-            step_title = steps[self.state.current_step_idx].title,
-            step_details = steps[self.state.current_step_idx].details,
-            agent_name = agent_name,
-            instruction = instruction,
-        );
 
-        Ok(prompt)
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", agent)
+            .build()
+            .await?;
+        let queue = orchestrator.user_message_queue();
+        tokio::spawn(async move {
+            if done_rx.await.is_ok() {
+                queue.push(ChatMessage::new_text(
+                    MessageRole::User,
+                    "user".to_string(),
+                    "actually search for buses instead".to_string(),
+                ));
+                let _ = resume_tx.send(());
+            }
+        });
+        let outcome = orchestrator.run_task("Search for trains".to_string(), RunOptions::default()).await?;
+
+        let agent_requests = agent_provider.requests();
+        assert_eq!(agent_requests.len(), 2);
+        assert!(!request_contains(&agent_requests[0], "actually search for buses instead"));
+        assert!(request_contains(&agent_requests[1], "New user message received: actually search for buses instead"));
+
+        // 代理的响应带有标记，消息本身仍然在下一次 ledger 评估前进入历史
+        let response = orchestrator.state.message_history.iter()
+            .find(|m| m.source() == "web_surfer")
+            .expect("agent response in history");
+        assert_eq!(response.metadata().get(USER_INTERRUPT_KEY).map(|v| v.as_str()), Some("true"));
+        assert!(request_contains(&provider.requests()[2], "New user message received: actually search for buses instead"));
+        assert_eq!(outcome.final_answer, "Searched for buses.");
+        Ok(())
     }
 
-    pub fn get_task_ledger_plan_prompt(&self, team: String) -> Result<String> {
-        let base_prompt = format!(
-            r#"
-            You have access to the following team members that can help you address the request each with unique expertise:
-            {team}
-            Remember, there is no requirement to involve all team members -- a team member's particular expertise may not be needed for this task.
-            When you answer without a plan and your answer includes factual information, make sure to say whether the answer was found using online search or from your own internal knowledge.
-            Your plan should be a sequence of steps that will complete the task."#,
-            team = team,
-        );
+    #[tokio::test]
+    async fn test_conflicting_user_message_between_steps_triggers_replan() -> Result<()> {
+        // 第一次 ledger 评估时队列才可用；消息在步骤执行期间到达，下一次 ledger 评估之前进入历史
+        let queue: Arc<std::sync::Mutex<Option<UserMessageQueue>>> = Arc::new(std::sync::Mutex::new(None));
+        let interject = queue.clone();
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Book a train", &[
+                ("Search", "Search for trains to Lyon", "web_surfer"),
+                ("Book", "Book the cheapest train", "web_surfer"),
+            ]))
+            .respond_with(move |_| {
+                if let Some(queue) = interject.lock().unwrap().as_ref() {
+                    queue.push(ChatMessage::new_text(MessageRole::User, "user".to_string(), "Take the bus instead of the train".to_string()));
+                }
+                ledger_json(false, false, "web_surfer", "Search for trains to Lyon").to_string()
+            })
+            .respond_json(ledger_json(false, true, "web_surfer", "The user now wants a bus"))
+            .respond_json(plan_json("Book a bus", &[("Book", "Book the cheapest bus to Lyon", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Book the cheapest bus to Lyon"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("Booked a bus to Lyon."));
+        let web_surfer = MockAgent::new("web_surfer").reply("Found trains").reply("Booked the bus");
+        let log = web_surfer.log();
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .build()
+            .await?;
+        *queue.lock().unwrap() = Some(orchestrator.user_message_queue());
+
+        let outcome = orchestrator.run_task("Book a train to Lyon".to_string(), RunOptions::default()).await?;
+
+        let requests = provider.requests();
+        assert!(!request_contains(&requests[1], "Take the bus instead of the train"));
+        // 消息出现在下一次 ledger 评估中，与计划冲突时 ledger 要求重规划，新计划同样看到这条消息
+        assert!(request_contains(&requests[2], "New user message received: Take the bus instead of the train"));
+        assert!(request_contains(&requests[3], "Take the bus instead of the train"));
+        assert_eq!(outcome.metrics.replans, 1);
+        assert_eq!(outcome.plan.as_ref().map(|plan| plan.steps.len()), Some(1));
+        assert_eq!(log.executes().len(), 2);
+        assert_eq!(outcome.final_answer, "Booked a bus to Lyon.");
+        Ok(())
+    }
 
-        let step_types_section = r#"
-            Each step should have a title, details and agent_name fields.
+    #[test]
+    fn test_validate_plan_json_checks_sentinel_fields() {
+        let limits = PlanValidationLimits::default();
+        let mut plan = plan_json("Watch the price", &[("Watch", "Check the price every hour", "web_surfer")]);
+        assert!(Orchestrator::validate_plan_json(&plan, true, &limits).is_ok());
 
-            The title should be a short one sentence description of the step.
+        plan["steps"][0]["step_type"] = serde_json::json!("SentinelPlanStep");
+        assert!(Orchestrator::validate_plan_json(&plan, true, &limits).is_err());
 
-            The details should be a detailed description of the step. The details should be concise and directly describe the action to be taken.
-            The details should start with a brief recap of the title in one short sentence. We then follow it with a new line. We then add any additional details without repeating information from the title. We should be concise but mention all crucial details to allow the human to verify the step.
-            The details should not be longer that 2 sentences.
+        plan["steps"][0]["sleep_duration"] = serde_json::json!(3600);
+        plan["steps"][0]["condition"] = serde_json::json!("The price drops below $100");
+        assert!(Orchestrator::validate_plan_json(&plan, true, &limits).is_ok());
+        assert!(Orchestrator::validate_plan_json(&plan, false, &limits).is_err());
 
-            The agent_name should be the name of the agent that will execute the step. The agent_name should be one of the team members listed above.
+        plan["steps"][0]["sleep_duration"] = serde_json::json!("hourly");
+        assert_eq!(
+            Orchestrator::validate_plan_json(&plan, true, &limits),
+            Err("step 1: sleep_duration must be a non-negative integer, got 'hourly'".to_string())
+        );
+    }
 
-            Output an answer in pure JSON format according to the following schema. The JSON object must be parsable as-is. DO NOT OUTPUT ANYTHING OTHER THAN JSON, AND DO NOT DEVIATE FROM THIS SCHEMA:
+    fn sentinel_plan() -> serde_json::Value {
+        let mut plan = plan_json("Watch the price", &[("Watch the price", "Check the price every hour", "web_surfer")]);
+        plan["steps"][0]["step_type"] = serde_json::json!("SentinelPlanStep");
+        plan["steps"][0]["sleep_duration"] = serde_json::json!(3600);
+        plan["steps"][0]["condition"] = serde_json::json!("The price drops below $100");
+        plan
+    }
 
-            The JSON object should have the following structure:
+    #[tokio::test]
+    async fn test_sentinel_plan_accepted_only_when_enabled() -> Result<()> {
+        // 开启时计划被接受，规划提示词里带有哨兵步骤的说明
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(sentinel_plan())
+            .respond_json(ledger_json(false, false, "web_surfer", "Check the price"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Check the price"))
+            .respond("The price dropped."));
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .configure(|config| config.sentinel_tasks_enabled = true)
+            .build()
+            .await?;
+        orchestrator.run_task("Watch the price".to_string(), RunOptions::default()).await?;
+        assert!(request_contains(&provider.requests()[0], "SentinelPlanStep"));
+        let plan = orchestrator.state.plan.clone().expect("plan");
+        assert!(plan.steps[0].kind.is_sentinel());
+
+        // 关闭时同样的输出不符合 schema，重试之后放弃
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(sentinel_plan())
+            .respond_json(sentinel_plan()));
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .build()
+            .await?;
+        let result = orchestrator.run_task("Watch the price".to_string(), RunOptions::default()).await;
+        assert!(result.is_err());
+        assert!(!request_contains(&provider.requests()[0], "SentinelPlanStep"));
+        assert!(request_contains(&provider.requests()[1], "The JSON does not follow the required schema"));
+        Ok(())
+    }
 
-            {
-                "response": "a complete response to the user request for Case 1.",
-                "task": "a complete description of the task requested by the user",
-                "plan_summary": "a complete summary of the plan if a plan is needed, otherwise an empty string",
-                "needs_plan": boolean,
-                "steps":
-                [
-                    {
-                        "title": "title of step 1",
-                        "details": "recap the title in one short sentence \n remaining details of step 1",
-                        "agent_name": "the name of the agent that should complete the step"
-                    },
-                    {
-                        "title": "title of step 2",
-                        "details": "recap the title in one short sentence \n remaining details of step 2",
-                        "agent_name": "the name of the agent that should complete the step"
-                    },
-                    ...
-                ]
-            }"#;
+    #[tokio::test]
+    async fn test_sentinel_step_refused_when_disabled() -> Result<()> {
+        let plan = Plan::from_list_of_dicts_or_str(sentinel_plan()).expect("plan");
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(ledger_json(false, false, "web_surfer", "Check the price")));
+        let web_surfer = MockAgent::new("web_surfer");
+        let log = web_surfer.log();
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider)
+            .agent("Browses the web", web_surfer)
+            .build()
+            .await?;
+        orchestrator.state.task = "Watch the price".to_string();
+        orchestrator.state.plan = Some(plan);
+        orchestrator.state.in_planning_mode = false;
+
+        let error = orchestrator.orchestrator_step_execution(true).await.unwrap_err();
+        assert!(error.to_string().contains("sentinel tasks are disabled"));
+        assert!(log.executes().is_empty());
+        Ok(())
+    }
 
-        Ok(format!("{}\n\n{}", base_prompt.trim(), step_types_section.trim()))
+    #[tokio::test]
+    async fn test_sentinel_step_repeats_checks_until_count_reached() -> Result<()> {
+        // ledger 第二次就认为完成，但按次数的哨兵步骤要做满 3 次检查
+        let mut plan = sentinel_plan();
+        plan["steps"][0]["sleep_duration"] = serde_json::json!(0);
+        plan["steps"][0]["condition"] = serde_json::json!(3);
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan)
+            .respond_json(ledger_json(false, false, "web_surfer", "Check the price"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Check the price"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Check the price"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Check the price"))
+            .respond("The price was checked three times."));
+        let web_surfer = MockAgent::new("web_surfer");
+        let log = web_surfer.log();
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider)
+            .agent("Browses the web", web_surfer)
+            .configure(|config| config.sentinel_tasks_enabled = true)
+            .build()
+            .await?;
+        let mut events = orchestrator.subscribe_events();
+        orchestrator.run_task("Watch the price".to_string(), RunOptions::default()).await?;
+
+        assert_eq!(log.executes().len(), 3);
+        let mut waiting = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let OrchestratorEvent::SentinelWaiting { step_index, check, total, next_check_secs } = event {
+                waiting.push((step_index, check, total, next_check_secs));
+            }
+        }
+        assert_eq!(waiting, vec![(0, 1, Some(3), 0), (0, 2, Some(3), 0)]);
+        assert_eq!(orchestrator.state.step_outcomes.len(), 1);
+        Ok(())
     }
 
-    pub fn get_task_ledger_replan_prompt(&self, team: String,task: String, current_plan: String) -> Result<String> {
-        let replan_intro = format!(r#"
-            The task we are trying to complete is:
-            {}
-            The plan we have tried to complete is:
-            {}
-            We have not been able to make progress on our task.
-            We need to find a new plan to tackle the task that addresses the failures in trying to complete the task previously."#,
-            task, current_plan
-        );
+    #[tokio::test]
+    async fn test_plan_retry_prompt_quotes_validation_errors() -> Result<()> {
+        let mut invalid = sentinel_plan();
+        invalid["steps"][0]["sleep_duration"] = serde_json::json!("daily");
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(invalid)
+            .respond_json(sentinel_plan())
+            .respond_json(ledger_json(false, false, "web_surfer", "Check the price"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Check the price"))
+            .respond("The price dropped."));
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .configure(|config| config.sentinel_tasks_enabled = true)
+            .build()
+            .await?;
+        orchestrator.run_task("Watch the price".to_string(), RunOptions::default()).await?;
+
+        assert!(request_contains(
+            &provider.requests()[1],
+            "step 1: sleep_duration must be a non-negative integer, got 'daily'"
+        ));
+        Ok(())
+    }
 
-        let base_plan_prompt = self.get_task_ledger_plan_prompt(team)?;
-        Ok(format!("{}\n\n{}", replan_intro, base_plan_prompt))
+    #[tokio::test]
+    async fn test_almost_valid_plan_is_repaired_without_retry() -> Result<()> {
+        let plan = "Here is the plan:\n```json\n{'task': 'Find the menu', 'response': '', 'plan_summary': 'One step', 'needs_plan': true, 'steps': [\n  {'title': 'Search', 'details': 'Search for the restaurant\\'s menu', 'agent_name': 'web_surfer',},\n],}\n```";
+        let provider = Arc::new(MockProvider::new()
+            .respond(plan)
+            .respond_json(ledger_json(false, false, "web_surfer", "Search for the restaurant"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Search for the restaurant"))
+            .respond("Found it."));
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .build()
+            .await?;
+        orchestrator.run_task("Find the menu".to_string(), RunOptions::default()).await?;
+
+        // 没有为修复 JSON 再请求模型
+        assert_eq!(provider.requests().len(), 4);
+        assert_eq!(orchestrator.current_plan().unwrap().steps[0].details, "Search for the restaurant's menu");
+        Ok(())
     }
 
-    pub fn get_orchestrator_system_message_planning(&self) -> Result<String> {
-        let date_today = Local::now().format("%A, %B %e, %Y").to_string();
-        let base_message = format!(
-            r#"
-            You are a helpful AI assistant named Magentic-UI built by Microsoft Research AI Frontiers.
-            Your goal is to help the user with their request.
-            You can complete actions on the web, complete actions on behalf of the user, execute code, and more.
-            You have access to a team of agents who can help you answer questions and complete tasks.
-            The browser the web_surfer accesses is also controlled by the user.
-            You are primarily a planner, and so you can devise a plan to do anything. 
+    fn fixture_image() -> ImageAttachment {
+        ImageAttachment::from_path(concat!(env!("CARGO_MANIFEST_DIR"), "/src/orchestrator/testdata/receipt.png")).unwrap()
+    }
 
+    fn request_images(request: &[LLMMessage]) -> Vec<Vec<u8>> {
+        request
+            .iter()
+            .filter_map(|message| match message {
+                LLMMessage::User(UserMessage { content: UserContent::MultiModal(parts), .. }) => Some(parts),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|part| match part {
+                MultiModalContent::Image(bytes) => Some(bytes.clone()),
+                MultiModalContent::Text(_) => None,
+            })
+            .collect()
+    }
 
-            The date today is: {}
+    fn attachment_run_options() -> RunOptions {
+        RunOptions { attachments: vec![fixture_image()], ..RunOptions::default() }
+    }
 
-            First consider the following:
+    fn receipt_plan_provider() -> MockProvider {
+        MockProvider::new()
+            .respond_json(plan_json("Total the receipt", &[
+                ("Read the receipt", "Add up the items on receipt.png", "coder_agent"),
+            ]))
+            .respond_json(ledger_json(false, false, "coder_agent", "Add up the items on receipt.png"))
+            .respond_json(ledger_json(true, false, "coder_agent", "Add up the items on receipt.png"))
+            .respond("The total is $42.10.")
+    }
 
-            - is the user request missing information and can benefit from clarification? For instance, if the user asks "book a flight", the request is missing information about the destination, date and we should ask for clarification before proceeding. Do not ask to clarify more than once, after the first clarification, give a plan.
-            - is the user request something that can be answered from the context of the conversation history without executing code, or browsing the internet or executing other tools? If so, we should answer the question directly in as much detail as possible.
-            When you answer without a plan and your answer includes factual information, make sure to say whether the answer was found using online search or from your own internal knowledge.
+    #[tokio::test]
+    async fn test_attached_image_reaches_vision_planner() -> Result<()> {
+        let provider = Arc::new(receipt_plan_provider());
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Writes and runs code", MockAgent::new("coder_agent"))
+            .build()
+            .await?;
+        orchestrator.run_task("What is the total on this receipt?".to_string(), attachment_run_options()).await?;
+
+        let plan_request = &provider.requests()[0];
+        assert_eq!(request_images(plan_request), vec![fixture_image().data]);
+        assert!(request_contains(plan_request, "Attached images: receipt.png"));
+        assert!(request_contains(plan_request, "Refer to each image by its filename"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_attached_image_is_captioned_for_text_only_planner() -> Result<()> {
+        let provider = Arc::new(receipt_plan_provider());
+        let captioner = Arc::new(MockProvider::new().respond("A grocery receipt with three items totalling $42.10"));
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Writes and runs code", MockAgent::new("coder_agent"))
+            .configure(|config| config.model_info.vision = false)
+            .build()
+            .await?;
+        orchestrator.set_caption_client(captioner.clone());
+        orchestrator.run_task("What is the total on this receipt?".to_string(), attachment_run_options()).await?;
+
+        // 图片只发给描述模型，规划模型收到的是文字描述
+        assert_eq!(request_images(&captioner.requests()[0]), vec![fixture_image().data]);
+        let plan_request = &provider.requests()[0];
+        assert!(request_images(plan_request).is_empty());
+        assert!(request_contains(
+            plan_request,
+            "Image receipt.png (description): A grocery receipt with three items totalling $42.10"
+        ));
+        Ok(())
+    }
 
+    #[tokio::test]
+    async fn test_caption_falls_back_without_caption_client() -> Result<()> {
+        let provider = Arc::new(receipt_plan_provider());
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Writes and runs code", MockAgent::new("coder_agent"))
+            .configure(|config| config.model_info.vision = false)
+            .build()
+            .await?;
+        orchestrator.run_task("What is the total on this receipt?".to_string(), attachment_run_options()).await?;
+
+        assert!(request_contains(&provider.requests()[0], "Image receipt.png (description): No description is available."));
+        Ok(())
+    }
 
-            Case 1: If the above is true, then we should provide our answer in the "response" field and set "needs_plan" to False.
+    #[tokio::test]
+    async fn test_team_comes_from_registered_agents() -> Result<()> {
+        // coder_agent 列在参与者中但没有注册
+        let mut orchestrator = Orchestrator::new(
+            "orchestrator".to_string(),
+            ChatMessage::new_text(MessageRole::User, "user".to_string(), String::new()),
+            vec!["Browses the web".to_string(), "Writes and runs code".to_string()],
+            vec!["web_surfer".to_string(), "coder_agent".to_string()],
+            Arc::new(MockProvider::new()),
+            test_config(),
+            None,
+            None,
+        ).await?;
+        orchestrator.register_agent(Box::new(MockAgent::new("web_surfer")));
+
+        let prompt = orchestrator.get_orchestrator_system_message_planning()?;
+        assert!(prompt.contains("web_surfer - Browses the web\nno_action_agent"));
+        assert!(!prompt.contains("coder_agent"));
+        assert!(!prompt.contains("{team}"));
+
+        let plan = Plan::from_list_of_dicts_or_str(plan_json("Run a script", &[
+            ("Run the script", "Run the script with Python", "coder_agent"),
+        ])).expect("plan");
+        let error = orchestrator.set_plan(plan).unwrap_err();
+        assert!(error.to_string().contains("agent_name 'coder_agent'"));
+        Ok(())
+    }
 
-            Case 2: If the above is not true, then we should consider devising a plan for addressing the request. If you are unable to answer a request, always try to come up with a plan so that other agents can help you complete the task.
+    #[tokio::test]
+    async fn test_refuses_to_plan_without_agents() -> Result<()> {
+        let provider = Arc::new(MockProvider::new().respond_json(plan_json("Find the menu", &[
+            ("Find the restaurant", "Search for the restaurant", "web_surfer"),
+        ])));
+        let mut orchestrator = OrchestratorBuilder::new().provider(provider.clone()).build().await?;
 
+        let error = orchestrator.run_task("Find the menu".to_string(), RunOptions::default()).await.unwrap_err();
+        assert!(error.to_string().contains("no agents are registered"));
+        assert!(provider.requests().is_empty());
+        Ok(())
+    }
 
-            For Case 2:
+    #[tokio::test]
+    async fn test_set_plan_skips_the_planner() -> Result<()> {
+        let plan = Plan::from_list_of_dicts_or_str(plan_json("Find the menu", &[
+            ("Find the restaurant", "Search for the restaurant", "WebSurfer"),
+        ])).expect("plan");
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(ledger_json(false, false, "web_surfer", "Search for the restaurant"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Search for the restaurant"))
+            .respond("Found it."));
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .build()
+            .await?;
+
+        orchestrator.set_plan(plan)?;
+        let outcome = orchestrator.run_task("Find the menu".to_string(), RunOptions::default()).await?;
+
+        assert_eq!(outcome.final_answer, "Found it.");
+        assert_eq!(outcome.metrics.planning_calls, 0);
+        assert_eq!(outcome.plan.expect("plan").steps[0].agent_name, "web_surfer");
+        assert_eq!(provider.requests().len(), 3);
+        Ok(())
+    }
 
-            You have access to the following team members that can help you address the request each with unique expertise:
+    #[tokio::test]
+    async fn test_set_plan_rejects_invalid_plan() -> Result<()> {
+        let plan = Plan::from_list_of_dicts_or_str(plan_json("Find the menu", &[
+            ("Find the restaurant", "Search for the restaurant", "file_surfer"),
+        ])).expect("plan");
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(Arc::new(MockProvider::new()))
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .build()
+            .await?;
+
+        let error = orchestrator.set_plan(plan).unwrap_err();
+        assert!(error.to_string().contains("step 1: agent_name 'file_surfer' is not one of"));
+        Ok(())
+    }
 
-            {{team}}
-            Your plan should should be a sequence of steps that will complete the task."#,
-            date_today
+    fn menu_plan_entry(task: &str, outcome: PlanOutcome) -> PlanLibraryEntry {
+        let plan = Plan::from_list_of_dicts_or_str(plan_json(task, &[
+            ("Open the restaurant website", "Search for the restaurant and open its website", "web_surfer"),
+        ])).expect("plan");
+        PlanLibraryEntry::new(task.to_string(), plan, outcome)
+    }
+
+    #[tokio::test]
+    async fn test_similar_successful_plans_are_injected_and_runs_recorded() -> Result<()> {
+        use crate::orchestrator::plan_library::InMemoryPlanLibrary;
+
+        let library = Arc::new(InMemoryPlanLibrary::new());
+        library.add(menu_plan_entry("Find the menu of Luigi's pizzeria", PlanOutcome::Success)).await?;
+        library.add(menu_plan_entry("Find the menu of Luigi's trattoria", PlanOutcome::Failure)).await?;
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Find the menu of Mario's pizzeria", &[("Search", "Search for the menu", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Search for the menu"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Search for the menu"))
+            .respond("The menu has pizza."));
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .build()
+            .await?;
+        orchestrator.set_plan_library(library.clone());
+
+        orchestrator.run_task("Find the menu of Mario's pizzeria".to_string(), RunOptions::default()).await?;
+
+        let planning_request = &provider.requests()[0];
+        assert!(request_contains(planning_request, "Here are plans that worked for similar tasks."));
+        assert!(request_contains(planning_request, "Task: Find the menu of Luigi's pizzeria"));
+        assert!(!request_contains(planning_request, "trattoria"));
+
+        let entries = library.entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].task, "Find the menu of Mario's pizzeria");
+        assert_eq!(entries[2].outcome, PlanOutcome::Success);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replan_prompt_has_reason_and_drops_repeated_steps() -> Result<()> {
+        let mut replan_ledger = ledger_json(false, true, "web_surfer", "Book a table on site A");
+        replan_ledger["need_to_replan"]["reason"] = serde_json::json!("Site A rejects bookings for two");
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Book a table", &[
+                ("Open site A", "Open the booking site A", "web_surfer"),
+                ("Book", "Book a table on site A", "web_surfer"),
+            ]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Open the booking site A"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Book a table on site A"))
+            .respond_json(replan_ledger)
+            // 模型把已完成的第一步又写了一遍
+            .respond_json(plan_json("Book a table", &[
+                ("Open site A", "Open the booking site A", "web_surfer"),
+                ("Use site B", "Book a table on site B", "web_surfer"),
+            ]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Book a table on site B"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("Booked on site B."));
+        let web_surfer = MockAgent::new("web_surfer")
+            .reply("Site A is open")
+            .reply("Site A only takes bookings for one")
+            .reply("Booked a table for two");
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .build()
+            .await?;
+        orchestrator.run_task("Book a table".to_string(), RunOptions::default()).await?;
+
+        let replan_request = &provider.requests()[4];
+        assert!(request_contains(replan_request, "We need to replan because: Site A rejects bookings for two"));
+        assert!(request_contains(replan_request, "COMPLETED STEP 1: title=\"Open site A\""));
+
+        let titles: Vec<&str> = orchestrator.state.plan.as_ref().unwrap().steps.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(titles, vec!["Open site A", "Use site B"]);
+
+        let history = orchestrator.plan_history();
+        let sources: Vec<PlanSource> = history.iter().map(|v| v.source).collect();
+        assert_eq!(sources, vec![PlanSource::Generated, PlanSource::Replanned]);
+        assert_eq!(history[1].version, 2);
+        assert_eq!(history[1].reason.as_deref(), Some("Site A rejects bookings for two"));
+        assert_eq!(
+            crate::orchestrator::plan_history::diff(&history[0].plan, &history[1].plan).summary(),
+            "- 2. Book\n+ 2. Use site B"
         );
+        assert_eq!(orchestrator.current_plan(), Some(&history[1].plan));
+
+        // 已完成的步骤保留 id，模型重新写出的步骤被合并掉，新步骤的 id 是新的
+        let (old, new) = (&history[0].plan.steps, &history[1].plan.steps);
+        assert_eq!(new[0].id, old[0].id);
+        assert!(old.iter().all(|step| step.id != new[1].id));
+        assert_eq!(orchestrator.state.step_outcomes[0].step_id, old[0].id);
+        assert_eq!(orchestrator.state.step_outcomes[1].step_id, new[1].id);
+        assert_eq!(provider.remaining(), 0);
+        Ok(())
+    }
 
-        let step_types_section = r#"
-            Each step should have a title and details field.
+    // 依次给出预设的审批结果，并记录收到的审批请求
+    #[derive(Debug)]
+    struct ScriptedGuard {
+        answers: std::sync::Mutex<Vec<bool>>,
+        requests: std::sync::Mutex<Vec<String>>,
+    }
 
-            The title should be a short one sentence description of the step.
+    #[async_trait::async_trait]
+    impl ActionGuard for ScriptedGuard {
+        async fn get_approval(&self, request: ChatMessage) -> bool {
+            self.requests.lock().unwrap().push(message_text(&request));
+            self.answers.lock().unwrap().remove(0)
+        }
+    }
 
-            The details should be a detailed description of the step. The details should be concise and directly describe the action to be taken.
-            The details should start with a brief recap of the title. We then follow it with a new line. We then add any additional details without repeating information from the title. We should be concise but mention all crucial details to allow the human to verify the step."#;
-        
-        let examples_section = r#"
-            Example 1:
+    #[tokio::test]
+    async fn test_expensive_plan_needs_extra_confirmation() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Compare prices", &[
+                ("Shop A", "Find the price in shop A", "web_surfer"),
+                ("Shop B", "Find the price in shop B", "web_surfer"),
+            ])));
+        let web_surfer = MockAgent::new("web_surfer");
+        let log = web_surfer.log();
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .configure(|c| c.plan_estimate.warn_llm_calls = Some(10))
+            .build()
+            .await?;
+        let guard = Arc::new(ScriptedGuard {
+            answers: std::sync::Mutex::new(vec![true, false]),
+            requests: std::sync::Mutex::new(Vec::new()),
+        });
+        orchestrator.set_action_guard(guard.clone());
+        let mut events = orchestrator.subscribe_events();
+        let outcome = orchestrator
+            .run_task("Compare prices".to_string(), RunOptions { approve_plan: true, ..RunOptions::default() })
+            .await?;
+
+        // 默认的 web_surfer 估算：每步 3-10 次调用加 2 次 ledger
+        let estimate = orchestrator.plan_estimate().unwrap();
+        assert_eq!(estimate.llm_calls, (10, 24));
+        assert!(estimate.usd_range.is_none());
+
+        let requests = guard.requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].contains("Approximate estimate: 10-24 LLM calls"));
+        assert!(requests[1].contains("may make up to 24 LLM calls (warning threshold 10)"));
+        assert_eq!(outcome.final_answer, "The plan was not approved, so no steps were executed.");
+        assert!(log.executes().is_empty());
+
+        let plan_message = orchestrator.state.message_history
+            .iter()
+            .find(|m| m.metadata().contains_key(PLAN_ESTIMATE_KEY))
+            .unwrap();
+        let attached: EstimatedCost = serde_json::from_str(&plan_message.metadata()[PLAN_ESTIMATE_KEY])?;
+        assert_eq!(&attached, estimate);
+
+        let mut warnings = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let OrchestratorEvent::PlanEstimated { warnings: w, .. } = event {
+                warnings = w;
+            }
+        }
+        assert_eq!(warnings.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chinese_request_gets_language_instruction() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("找到餐厅的菜单", &[
+                ("搜索餐厅", "搜索这家餐厅。\n 使用 Bing 找到餐厅的官网", "web_surfer"),
+            ]))
+            .respond_json(ledger_json(false, false, "web_surfer", "搜索这家餐厅"))
+            .respond_json(ledger_json(true, false, "web_surfer", "没有剩下的工作"))
+            .respond("菜单上有三道菜。"));
+        let web_surfer = MockAgent::new("web_surfer").reply("找到了官网");
+        let log = web_surfer.log();
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .build()
+            .await?;
+        let outcome = orchestrator.run_task("帮我找到这家餐厅的菜单".to_string(), RunOptions::default()).await?;
+
+        let requests = provider.requests();
+        assert!(request_contains(&requests[0], "Always respond to the user in Chinese"));
+        assert!(request_contains(&requests[0], "agent_name values in English"));
+        assert!(request_contains(requests.last().unwrap(), "Always respond to the user in Chinese"));
+        // 中文步骤照常通过校验并执行
+        assert_eq!(orchestrator.state.plan.as_ref().unwrap().steps[0].title, "搜索餐厅");
+        assert_eq!(log.executes().len(), 1);
+        assert_eq!(outcome.final_answer, "菜单上有三道菜。");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_force_language_overrides_detection() -> Result<()> {
+        let script = || MockProvider::new().respond_json(direct_answer_json("Say hi", "Hi"));
+
+        let provider = Arc::new(script());
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .build()
+            .await?;
+        orchestrator.run_task("Say hi".to_string(), RunOptions::default()).await?;
+        assert!(!request_contains(&provider.requests()[0], "Always respond to the user in"));
+
+        let provider = Arc::new(script());
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .configure(|c| c.force_language = Some("German".to_string()))
+            .build()
+            .await?;
+        orchestrator.run_task("Say hi".to_string(), RunOptions::default()).await?;
+        assert!(request_contains(&provider.requests()[0], "Always respond to the user in German"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invalid_edited_plan_is_sent_back_to_the_user() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan_json("Find the menu", &[("Search", "Search for the restaurant", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Read the reviews"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("The reviews are good."));
+        // 第一次发回的计划缺少 agent_name，第二次带着代码块和多余的逗号，修复后可以使用
+        let user_proxy = MockAgent::new("user_proxy")
+            .reply(r#"{"steps": [{"title": "Read reviews"}]}"#)
+            .reply("```json\n{\"steps\": [{\"title\": \"Read reviews\", \"details\": \"Read the reviews\", \"agent_name\": \"web_surfer\"},]}\n```");
+        let user_log = user_proxy.log();
+        let web_surfer = MockAgent::new("web_surfer").reply("Read the reviews");
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("The user", user_proxy)
+            .agent("Browses the web", web_surfer)
+            .configure(|config| config.cooperative_planning = true)
+            .build()
+            .await?;
+        let outcome = orchestrator.run_task("Find the menu".to_string(), RunOptions::default()).await?;
+
+        let asked = user_log.executes();
+        assert_eq!(asked.len(), 2);
+        assert_eq!(
+            instruction_text(&asked[1]),
+            "The edited plan could not be used: step 1: missing key 'agent_name'. Please send the corrected plan, or approve the current plan."
+        );
+        let plan = outcome.plan.unwrap();
+        assert_eq!(plan.steps.len(), 1);
+        assert_eq!(plan.steps[0].title, "Read reviews");
+        assert_eq!(outcome.final_answer, "The reviews are good.");
+        Ok(())
+    }
 
-            User request: "Report back the menus of three restaurants near the zipcode 98052"
+    fn clarification_json(question: &str) -> Value {
+        let mut response = direct_answer_json("Book a table", "");
+        response["needs_clarification"] = serde_json::json!(true);
+        response["clarification_question"] = serde_json::json!(question);
+        response
+    }
 
-            Step 1:
-            - title: "Locate the menu of the first restaurant"
-            - details: "Locate the menu of the first restaurant. \n Search for highly-rated restaurants in the 98052 area using Bing, select one with good reviews and an accessible menu, then extract and format the menu information for reporting."
-            - agent_name: "web_surfer"
+    #[tokio::test]
+    async fn test_clarification_then_plan() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(clarification_json("For which day and how many people?"))
+            // 第二次规划不允许再追问，会被要求修正
+            .respond_json(clarification_json("Which restaurant?"))
+            .respond_json(plan_json("Book a table", &[("Book", "Book a table for two tomorrow", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Book a table for two tomorrow"))
+            .respond_json(ledger_json(true, false, "web_surfer", "Nothing left to do"))
+            .respond("Booked a table for two tomorrow."));
+        let user_proxy = MockAgent::new("user_proxy").reply("Tomorrow, two people");
+        let user_log = user_proxy.log();
+        let web_surfer = MockAgent::new("web_surfer").reply("Booked");
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("The user", user_proxy)
+            .agent("Browses the web", web_surfer)
+            .build()
+            .await?;
+        let outcome = orchestrator.run_task("Book a table".to_string(), RunOptions::default()).await?;
+
+        let asked = user_log.executes();
+        assert_eq!(asked.len(), 1);
+        assert_eq!(instruction_text(&asked[0]), "For which day and how many people?");
+
+        let requests = provider.requests();
+        assert!(request_contains(&requests[1], "Tomorrow, two people"));
+        assert!(request_contains(&requests[1], "Do not ask for clarification again"));
+        assert!(request_contains(&requests[2], "a clarification question was already asked"));
+        assert_eq!(orchestrator.state.plan.as_ref().unwrap().steps[0].title, "Book");
+        assert_eq!(outcome.final_answer, "Booked a table for two tomorrow.");
+        assert_eq!(provider.remaining(), 0);
+        Ok(())
+    }
 
-            Step 2:
-            - title: "Locate the menu of the second restaurant"
-            - details: "Locate the menu of the second restaurant. \n After excluding the first restaurant, search for another well-reviewed establishment in 98052, ensuring it has a different cuisine type for variety, then collect and format its menu information."
-            - agent_name: "web_surfer"
+    #[tokio::test]
+    async fn test_clarification_without_user_proxy_makes_assumptions() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(clarification_json("For which day?"))
+            .respond_json(direct_answer_json("Book a table", "I can not book without a day, assuming today.")));
+
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .build()
+            .await?;
+        let outcome = orchestrator.run_task("Book a table".to_string(), RunOptions::default()).await?;
+
+        assert!(request_contains(&provider.requests()[1], "the user cannot answer clarification questions"));
+        assert_eq!(outcome.final_answer, "I can not book without a day, assuming today.");
+        Ok(())
+    }
 
-            Step 3:
-            - title: "Locate the menu of the third restaurant"
-            - details: "Locate the menu of the third restaurant. \n Building on the previous searches but excluding the first two restaurants, find a third establishment with a distinct cuisine type, verify its menu is available online, and compile the menu details."
-            - agent_name: "web_surfer"
+    #[tokio::test]
+    async fn test_plan_steps_are_streamed_before_the_plan_is_ready() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .with_stream_chunks(7)
+            .respond_json(plan_json("Compare prices", &[
+                ("Shop A", "Find the price in shop A", "web_surfer"),
+                ("Shop B", "Find the price in shop B", "web_surfer"),
+                ("Compare", "Compare the two prices", "web_surfer"),
+            ]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Find the price in shop A"))
+            .respond("Shop A is cheaper."));
+
+        // 只跑一轮，之后直接给出最终答案
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .configure(|c| c.max_turns = Some(0))
+            .build()
+            .await?;
+        let mut events = orchestrator.subscribe_events();
+        orchestrator.run_task("Compare prices".to_string(), RunOptions::default()).await?;
+
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            match event {
+                OrchestratorEvent::PlanStepStreamed { step_index, step } => {
+                    received.push(format!("step {} {}", step_index, step.title))
+                }
+                OrchestratorEvent::PlanReady { plan } => received.push(format!("plan {}", plan.steps.len())),
+                _ => {}
+            }
+        }
+        assert_eq!(received, vec!["step 0 Shop A", "step 1 Shop B", "step 2 Compare", "plan 3"]);
+        Ok(())
+    }
 
-            Example 2:
+    #[tokio::test]
+    async fn test_plan_text_is_streamed_when_enabled() -> Result<()> {
+        let plan = plan_json("Find the menu", &[("Search", "Search for the restaurant", "web_surfer")]);
+        let script = |provider: MockProvider| {
+            Arc::new(provider
+                .with_stream_chunks(9)
+                .respond_json(plan.clone())
+                .respond_json(ledger_json(false, false, "web_surfer", "Search for the restaurant"))
+                .respond("The menu has pizza."))
+        };
 
-            User request: "Execute the starter code for the autogen repo"
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(script(MockProvider::new()))
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .configure(|c| {
+                c.max_turns = Some(0);
+                c.stream_plan_text = true;
+            })
+            .build()
+            .await?;
+        let mut events = orchestrator.subscribe_events();
+        orchestrator.run_task("Find the menu".to_string(), RunOptions::default()).await?;
+        let mut pieces = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let OrchestratorEvent::PlanTextStreamed { text } = event {
+                pieces.push(text);
+            }
+        }
+        assert!(pieces.len() > 1);
+        assert_eq!(serde_json::from_str::<JsonValue>(&pieces.concat())?, plan);
+
+        // 默认不发送
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(script(MockProvider::new()))
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .configure(|c| c.max_turns = Some(0))
+            .build()
+            .await?;
+        let mut events = orchestrator.subscribe_events();
+        orchestrator.run_task("Find the menu".to_string(), RunOptions::default()).await?;
+        while let Ok(event) = events.try_recv() {
+            assert!(!matches!(event, OrchestratorEvent::PlanTextStreamed { .. }));
+        }
+        Ok(())
+    }
 
-            Step 1:
-            - title: "Locate the starter code for the autogen repo"
-            - details: "Locate the starter code for the autogen repo. \n Search for the official AutoGen repository on GitHub, navigate to their examples or getting started section, and identify the recommended starter code for new users."
-            - agent_name: "web_surfer"
+    #[tokio::test]
+    async fn test_plan_from_emit_plan_tool_call() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .with_stream_chunks(5)
+            .respond_tool_call(EMIT_PLAN_TOOL, plan_json("Find the menu", &[
+                ("Search", "Search for the restaurant", "web_surfer"),
+                ("Read", "Open the menu page", "web_surfer"),
+            ]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Search for the restaurant"))
+            .respond("The menu has pizza."));
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .configure(|c| c.max_turns = Some(0))
+            .build()
+            .await?;
+        let mut events = orchestrator.subscribe_events();
+        orchestrator.run_task("Find the menu".to_string(), RunOptions::default()).await?;
+
+        assert_eq!(provider.offered_tools()[0], vec![EMIT_PLAN_TOOL.to_string()]);
+        // ledger 调用不提供函数
+        assert!(provider.offered_tools()[1].is_empty());
+        let titles: Vec<String> = orchestrator.state.plan.as_ref().unwrap().steps.iter().map(|s| s.title.clone()).collect();
+        assert_eq!(titles, vec!["Search", "Read"]);
+
+        // 调用参数同样按步骤流式发出
+        let mut streamed = 0;
+        while let Ok(event) = events.try_recv() {
+            if matches!(event, OrchestratorEvent::PlanStepStreamed { .. }) {
+                streamed += 1;
+            }
+        }
+        assert_eq!(streamed, 2);
+        Ok(())
+    }
 
-            Step 2:
-            - title: "Execute the starter code for the autogen repo"
-            - details: "Execute the starter code for the autogen repo. \n Set up the Python environment with the correct dependencies, ensure all required packages are installed at their specified versions, and run the starter code while capturing any output or errors."
-            - agent_name: "coder_agent"
+    #[tokio::test]
+    async fn test_invalid_tool_call_plan_is_retried_as_text() -> Result<()> {
+        let mut invalid = plan_json("Find the menu", &[("Search", "Search for the restaurant", "web_surfer")]);
+        invalid["steps"][0].as_object_mut().unwrap().remove("details");
+        let provider = Arc::new(MockProvider::new()
+            .respond_tool_call(EMIT_PLAN_TOOL, invalid)
+            .respond_json(plan_json("Find the menu", &[("Search", "Search for the restaurant", "web_surfer")]))
+            .respond_json(ledger_json(false, false, "web_surfer", "Search for the restaurant"))
+            .respond("The menu has pizza."));
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .configure(|c| c.max_turns = Some(0))
+            .build()
+            .await?;
+        orchestrator.run_task("Find the menu".to_string(), RunOptions::default()).await?;
+
+        // 调用参数与文字 JSON 使用同样的校验，重试时模型改为文字回复也可以
+        assert!(request_contains(&provider.requests()[1], "The JSON does not follow the required schema"));
+        assert_eq!(orchestrator.state.plan.as_ref().unwrap().steps[0].details, "Search for the restaurant");
+        assert_eq!(provider.remaining(), 0);
+        Ok(())
+    }
 
+    #[tokio::test]
+    async fn test_no_plan_tool_without_function_calling() -> Result<()> {
+        let provider = Arc::new(MockProvider::new()
+            .with_model_info(crate::clients::ModelInfo { function_calling: false, ..Default::default() })
+            .respond_json(direct_answer_json("Say hi", "Hi!")));
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .build()
+            .await?;
+        let outcome = orchestrator.run_task("Say hi".to_string(), RunOptions::default()).await?;
+
+        assert!(provider.offered_tools()[0].is_empty());
+        assert!(outcome.final_answer.contains("Hi!"));
+        Ok(())
+    }
 
-            Example 3:
+    /* 模拟按提示词判断的模型：ledger 请求中代理回报之后才算完成，提示词中带预期结果时还要求回报里列出价格，
+    其他请求给出最终答案。返回代理执行的次数和 provider */
+    async fn run_menu_step(expected_outcome: Option<&str>) -> Result<(usize, Arc<MockProvider>)> {
+        fn judge(request: &[LLMMessage]) -> String {
+            if !request_contains(request, "is_current_step_complete") {
+                return "The menu has three dishes.".to_string();
+            }
+            let reported = request_contains(request, "Found the restaurant");
+            let strict = request_contains(request, "Expected outcome:");
+            let complete = reported && (!strict || request_contains(request, "Prices:"));
+            ledger_json(complete, false, "web_surfer", "Find the menu with prices").to_string()
+        }
 
-            User request: "On which social media platform does Autogen have the most followers?"
+        let mut plan = plan_json("Find the menu", &[("Find the menu", "Find the restaurant menu", "web_surfer")]);
+        if let Some(outcome) = expected_outcome {
+            plan["steps"][0]["expected_outcome"] = serde_json::json!(outcome);
+        }
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(plan)
+            .respond_with(judge)
+            .respond_with(judge)
+            .respond_with(judge)
+            .respond_with(judge));
+        let web_surfer = MockAgent::new("web_surfer")
+            .reply("Found the restaurant and its menu")
+            .reply("Found the restaurant. Prices: soup $5, salad $7, pasta $12");
+        let log = web_surfer.log();
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .build()
+            .await?;
+        let outcome = orchestrator.run_task("Find the menu".to_string(), RunOptions::default()).await?;
+        assert_eq!(outcome.final_answer, "The menu has three dishes.");
+        Ok((log.executes().len(), provider))
+    }
 
-            Step 1:
-            - title: "Find all social media platforms that Autogen is on"
-            - details: "Find all social media platforms that Autogen is on. \n Search for AutoGen's official presence across major platforms like GitHub, Twitter, LinkedIn, and others, then compile a comprehensive list of their verified accounts."
-            - agent_name: "web_surfer"
+    #[tokio::test]
+    async fn test_expected_outcome_decides_step_completion() -> Result<()> {
+        // 没有预期结果时代理第一次回报就算完成
+        let (executes, provider) = run_menu_step(None).await?;
+        assert_eq!(executes, 1);
+        assert!(!request_contains(&provider.requests()[1], "Expected outcome:"));
+
+        // 预期结果要求列出价格，第一次回报没有价格，步骤不算完成
+        let (executes, provider) = run_menu_step(Some("The menu is listed with prices")).await?;
+        assert_eq!(executes, 2);
+        assert!(request_contains(&provider.requests()[1], "Expected outcome: The menu is listed with prices"));
+        assert!(request_contains(&provider.requests()[1], "Judge this against the expected outcome of the step"));
+        Ok(())
+    }
 
-            Step 2:
-            - title: "Find the number of followers for each social media platform"
-            - details: "Find the number of followers for each social media platform. \n For each platform identified, visit AutoGen's official profile and record their current follower count, ensuring to note the date of collection for accuracy."
-            - agent_name: "web_surfer"
+    // 两个步骤的计划：web_surfer 搜索，coder_agent 汇总
+    fn two_step_plan() -> serde_json::Value {
+        plan_json("Summarize the menu", &[
+            ("Search", "Search for the menu", "web_surfer"),
+            ("Summarize", "Summarize the menu", "coder_agent"),
+        ])
+    }
 
-            Step 3:
-            - title: "Find the number of followers for the remaining social media platform that Autogen is on"
-            - details: "Find the number of followers for the remaining social media platforms. \n Visit the remaining platforms and record their follower counts."
-            - agent_name: "web_surfer"
+    #[tokio::test]
+    async fn test_resume_session_continues_from_the_next_step() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let session_dir = dir.path().join("session");
+
+        // 第一次运行：第一个步骤完成后模型调用失败，模拟进程中断
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(two_step_plan())
+            .respond_json(ledger_json(false, false, "web_surfer", "Search for the menu"))
+            .respond_json(ledger_json(true, false, "coder_agent", "Summarize the menu")));
+        let web_surfer = MockAgent::new("web_surfer")
+            .reply("Found the menu")
+            .with_state(serde_json::json!({ "cookies": ["sid=1"], "url": "https://menu.example" }));
+        let first_log = web_surfer.log();
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider)
+            .agent("Browses the web", web_surfer)
+            .agent("Writes code", MockAgent::new("coder_agent"))
+            .configure(|c| c.session_dir = Some(session_dir.display().to_string()))
+            .build()
+            .await?;
+        assert!(orchestrator.run_task("Summarize the menu".to_string(), RunOptions::default()).await.is_err());
+        assert_eq!(first_log.executes().len(), 1);
+        drop(orchestrator);
+
+        let checkpoint = SessionCheckpoint::load(&session_dir)?;
+        assert_eq!(checkpoint.reason, CheckpointReason::StepFinished);
+        assert_eq!(checkpoint.current_step_idx, 1);
+        assert_eq!(checkpoint.plan_history.len(), 1);
+        assert_eq!(checkpoint.agent_states["web_surfer"]["cookies"][0], "sid=1");
+        assert!(!checkpoint.agent_states.contains_key("coder_agent"));
+
+        // 恢复：重新注册代理，只执行第二个步骤
+        let provider = Arc::new(MockProvider::new()
+            .respond_json(ledger_json(false, false, "coder_agent", "Summarize the menu"))
+            .respond_json(ledger_json(true, false, "coder_agent", "Nothing left to do"))
+            .respond("The menu has three dishes."));
+        let web_surfer = MockAgent::new("web_surfer");
+        let coder = MockAgent::new("coder_agent").reply("Three dishes");
+        let (web_log, coder_log, web_state) = (web_surfer.log(), coder.log(), web_surfer.state());
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .agent("Writes code", coder)
+            .build()
+            .await?;
+        let mut events = orchestrator.subscribe_events();
+        let outcome = orchestrator.resume_session(&session_dir, RunOptions::default()).await?;
+
+        assert_eq!(outcome.final_answer, "The menu has three dishes.");
+        assert!(web_log.executes().is_empty());
+        assert_eq!(coder_log.executes().len(), 1);
+        // 浏览器状态交还给新注册的代理
+        assert_eq!(web_state.lock().unwrap().as_ref().map(|state| state["url"].clone()), Some("https://menu.example".into()));
+        assert_eq!(orchestrator.state.step_outcomes.len(), 2);
+        assert_eq!(orchestrator.plan_history().len(), 1);
+        // 恢复后的 ledger 仍然能看到第一次运行的历史
+        assert!(request_contains(&provider.requests()[0], "Found the menu"));
+        let mut resumed = false;
+        while let Ok(event) = events.try_recv() {
+            if let OrchestratorEvent::SessionResumed { step_index, remaining_steps } = event {
+                resumed = step_index == 1 && remaining_steps == 1;
+            }
+        }
+        assert!(resumed);
+        Ok(())
+    }
 
+    #[tokio::test]
+    async fn test_resume_session_errors() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let build = || OrchestratorBuilder::new()
+            .provider(Arc::new(MockProvider::new()))
+            .agent("Browses the web", MockAgent::new("web_surfer"))
+            .build();
+
+        // 没有检查点
+        let error = build().await?.resume_session(dir.path(), RunOptions::default()).await.unwrap_err();
+        assert!(error.to_string().contains("No session checkpoint found"));
+
+        // 损坏的检查点
+        std::fs::write(SessionCheckpoint::path(dir.path()), "not json")?;
+        let error = build().await?.resume_session(dir.path(), RunOptions::default()).await.unwrap_err();
+        assert!(error.to_string().contains("is corrupt"));
+
+        // 剩余步骤需要的代理没有注册
+        let mut plan: Plan = serde_json::from_value(two_step_plan())?;
+        plan.ensure_unique_step_ids();
+        let mut state = OrchestratorState {
+            task: "Summarize the menu".to_string(),
+            plan: Some(plan),
+            ..OrchestratorState::default()
+        };
+        state.current_step_idx = 1;
+        SessionCheckpoint::capture(&state, &OrchestratorMetrics::new(), CheckpointReason::StepFinished, false)
+            .unwrap()
+            .save(dir.path())?;
+        let error = build().await?.resume_session(dir.path(), RunOptions::default()).await.unwrap_err();
+        assert!(error.to_string().contains("not registered: coder_agent"));
+        Ok(())
+    }
 
-            Example 4:
-            User request: "Can you paraphrase the following sentence: 'The quick brown fox jumps over the lazy dog'"
+    #[tokio::test]
+    async fn test_declined_resume_runs_no_steps() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut plan: Plan = serde_json::from_value(two_step_plan())?;
+        plan.ensure_unique_step_ids();
+        let state = OrchestratorState {
+            task: "Summarize the menu".to_string(),
+            plan: Some(plan),
+            ..OrchestratorState::default()
+        };
+        SessionCheckpoint::capture(&state, &OrchestratorMetrics::new(), CheckpointReason::PlanAccepted, true)
+            .unwrap()
+            .save(dir.path())?;
+
+        let web_surfer = MockAgent::new("web_surfer");
+        let log = web_surfer.log();
+        let provider = Arc::new(MockProvider::new());
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .agent("Writes code", MockAgent::new("coder_agent"))
+            .build()
+            .await?;
+        orchestrator.set_action_guard(Arc::new(RejectingGuard));
+        let outcome = orchestrator
+            .resume_session(dir.path(), RunOptions { approve_plan: true, ..RunOptions::default() })
+            .await?;
+
+        assert!(outcome.final_answer.contains("The session was not resumed"));
+        assert!(log.executes().is_empty());
+        assert!(provider.requests().is_empty());
+        Ok(())
+    }
 
-            You should not provide a plan for this request. Instead, just answer the question directly.
+    #[tokio::test]
+    async fn test_generate_plan_does_not_execute() -> Result<()> {
+        let provider = Arc::new(MockProvider::new().respond_json(two_step_plan()));
+        let web_surfer = MockAgent::new("web_surfer");
+        let log = web_surfer.log();
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(provider.clone())
+            .agent("Browses the web", web_surfer)
+            .agent("Writes code", MockAgent::new("coder_agent"))
+            .build()
+            .await?;
+
+        let plan = orchestrator.generate_plan("Summarize the menu".to_string(), RunOptions::default()).await?.unwrap();
+        assert_eq!(plan.steps.len(), 2);
+        assert!(log.executes().is_empty());
+        assert_eq!(provider.requests().len(), 1);
+        Ok(())
+    }
 
+    #[tokio::test]
+    async fn test_transcript_survives_compaction() -> Result<()> {
+        let mut orchestrator = OrchestratorBuilder::new()
+            .provider(Arc::new(MockProvider::new()))
+            .configure(|config| config.history_compaction = HistoryCompactionConfig { max_messages: 6, keep_recent: 2 })
+            .build()
+            .await?;
+        let screenshot = |i: u8| ChatMessage::new_multimodal(
+            MessageRole::Assistant,
+            "web_surfer".to_string(),
+            vec![MultiModalContent::Text(format!("Step {}", i)), MultiModalContent::Image(vec![0x89, b'P', b'N', b'G', i])],
+        );
 
-            Helpful tips:
-            - If the plan needs information from the user, try to get that information before creating the plan.
-            - When creating the plan you only need to add a step to the plan if it requires a different agent to be completed, or if the step is very complicated and can be split into two steps.
-            - Remember, there is no requirement to involve all team members -- a team member's particular expertise may not be needed for this task.
-            - Aim for a plan with the least number of steps possible.
-            - Use a search engine or platform to find the information you need. For instance, if you want to look up flight prices, use a flight search engine like Bing Flights. However, your final answer should not stop with a Bing search only.
-            - If there are images attached to the request, use them to help you complete the task and describe them to the other agents in the plan.
-        "#;
-        Ok(base_message + step_types_section + examples_section)
-    } 
+        let mut expected = vec![ChatMessage::new_text(MessageRole::User, "user".to_string(), "Find the menu".to_string())];
+        orchestrator.state.message_history.push(expected[0].clone());
+        for round in 0..2 {
+            for i in 0..6 {
+                let message = screenshot(round * 10 + i);
+                expected.push(message.clone());
+                orchestrator.state.message_history.push(message);
+            }
+            orchestrator.compact();
+        }
 
+        // 历史中的截图已经换成引用，完整记录仍是原样
+        assert!(orchestrator.history_len() <= 6);
+        assert!(!orchestrator.image_archive.is_empty());
+        assert_eq!(orchestrator.transcript(), expected);
+        Ok(())
+    }
 }
@@ -1,17 +1,232 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Map, Value};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use crate::clients::ToolSpec;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Plan {
     pub task: Option<String>,
     pub steps: Vec<PlanStep>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "RawPlanStep", into = "RawPlanStep")]
 pub struct PlanStep {
+    /// 创建时分配的稳定 id，插入、删除、重规划都不会改变已有步骤的 id
+    pub id: String,
     pub title: String,
     pub details: String,
     pub agent_name: String,
+    /// 步骤完成时应该看到的结果，progress ledger 据此判断步骤是否完成；没有时由模型自行判断
+    pub expected_outcome: Option<String>,
+    pub kind: StepKind,
+}
+
+/// 哨兵步骤的结束条件：执行指定次数，或者满足一段自然语言描述的条件
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum SentinelCondition {
+    Count(u32),
+    Text(String),
+}
+
+/// 步骤种类：普通步骤执行一次；哨兵步骤每隔 sleep_duration 秒检查一次，直到满足 condition
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum StepKind {
+    #[default]
+    Standard,
+    Sentinel {
+        sleep_duration: u64,
+        condition: SentinelCondition,
+    },
+}
+
+pub const SENTINEL_STEP_TYPE: &str = "SentinelPlanStep";
+pub const STANDARD_STEP_TYPE: &str = "PlanStep";
+
+const STEP_ID_LEN: usize = 8;
+const SHORT_STEP_ID_LEN: usize = 4;
+
+/// 新步骤的 id：8 位小写十六进制
+pub fn new_step_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()[..STEP_ID_LEN].to_string()
+}
+
+impl PlanStep {
+    /// 展示用的 id 前缀，例如 "a3f2"
+    pub fn short_id(&self) -> &str {
+        let end = self.id.char_indices().nth(SHORT_STEP_ID_LEN).map_or(self.id.len(), |(i, _)| i);
+        &self.id[..end]
+    }
+
+    /// 例如 "[a3f2] Locate the menu"
+    pub fn label(&self) -> String {
+        format!("[{}] {}", self.short_id(), self.title)
+    }
+}
+
+/// 步骤种类字段不合法，field 为出错的字段名
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepKindError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl StepKindError {
+    fn new(field: &'static str, message: impl Into<String>) -> Self {
+        Self { field, message: message.into() }
+    }
+}
+
+impl std::fmt::Display for StepKindError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// 错误信息中引用模型给出的值：字符串加单引号，其他类型按 JSON 输出
+pub fn describe_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("'{}'", s),
+        other => other.to_string(),
+    }
+}
+
+impl StepKind {
+    /* 从模型输出的步骤对象中解析步骤种类：只有 step_type 为 SentinelPlanStep 时才检查
+    sleep_duration 和 condition，普通步骤缺少这些字段或带着多余字段都不报错 */
+    pub fn from_step(step: &Map<String, Value>) -> Result<Self, StepKindError> {
+        let is_sentinel = step
+            .get("step_type")
+            .and_then(|v| v.as_str())
+            .is_some_and(|t| t.eq_ignore_ascii_case(SENTINEL_STEP_TYPE));
+        if !is_sentinel {
+            return Ok(StepKind::Standard);
+        }
+
+        let sleep_duration = match step.get("sleep_duration") {
+            Some(v) => v.as_u64().ok_or_else(|| StepKindError::new(
+                "sleep_duration",
+                format!("sleep_duration must be a non-negative integer, got {}", describe_value(v)),
+            ))?,
+            None => return Err(StepKindError::new("sleep_duration", "sleep_duration is required for a SentinelPlanStep")),
+        };
+        let condition = match step.get("condition") {
+            Some(Value::String(text)) => SentinelCondition::Text(text.clone()),
+            Some(v) => v
+                .as_u64()
+                .and_then(|n| u32::try_from(n).ok())
+                .map(SentinelCondition::Count)
+                .ok_or_else(|| StepKindError::new(
+                    "condition",
+                    format!("condition must be a non-negative integer or a string, got {}", describe_value(v)),
+                ))?,
+            None => return Err(StepKindError::new("condition", "condition is required for a SentinelPlanStep")),
+        };
+        Ok(StepKind::Sentinel { sleep_duration, condition })
+    }
+
+    // 按配置校验：关闭哨兵任务时，步骤里出现任何哨兵字段都视为不合法
+    pub fn from_step_checked(step: &Map<String, Value>, sentinel_tasks_enabled: bool) -> Result<Self, StepKindError> {
+        if !sentinel_tasks_enabled {
+            if let Some(step_type) = step.get("step_type") {
+                if step_type.as_str().is_none_or(|t| !t.eq_ignore_ascii_case(STANDARD_STEP_TYPE)) {
+                    return Err(StepKindError::new(
+                        "step_type",
+                        format!("step_type must be '{}' because sentinel steps are disabled, got {}", STANDARD_STEP_TYPE, describe_value(step_type)),
+                    ));
+                }
+            }
+            for field in ["sleep_duration", "condition"] {
+                if step.contains_key(field) {
+                    return Err(StepKindError::new(field, format!("{} is not allowed because sentinel steps are disabled", field)));
+                }
+            }
+            return Ok(StepKind::Standard);
+        }
+        Self::from_step(step)
+    }
+
+    pub fn is_sentinel(&self) -> bool {
+        matches!(self, StepKind::Sentinel { .. })
+    }
+}
+
+// 空白的 expected_outcome 视为没有
+fn normalize_outcome(outcome: Option<String>) -> Option<String> {
+    outcome.map(|o| o.trim().to_string()).filter(|o| !o.is_empty())
+}
+
+// PlanStep 的序列化形式，与规划提示词中的 JSON 结构一致
+#[derive(Serialize, Deserialize)]
+struct RawPlanStep {
+    // 模型输出和旧的计划文件没有 id，解析时分配新的
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    id: String,
+    title: String,
+    details: String,
+    agent_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expected_outcome: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    step_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sleep_duration: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    condition: Option<Value>,
+}
+
+impl TryFrom<RawPlanStep> for PlanStep {
+    type Error = String;
+
+    fn try_from(raw: RawPlanStep) -> Result<Self, Self::Error> {
+        let mut fields = Map::new();
+        for (key, value) in [
+            ("step_type", raw.step_type.map(Value::String)),
+            ("sleep_duration", raw.sleep_duration),
+            ("condition", raw.condition),
+        ] {
+            if let Some(value) = value {
+                fields.insert(key.to_string(), value);
+            }
+        }
+        Ok(PlanStep {
+            id: if raw.id.trim().is_empty() { new_step_id() } else { raw.id },
+            title: raw.title,
+            details: raw.details,
+            agent_name: raw.agent_name,
+            expected_outcome: normalize_outcome(raw.expected_outcome),
+            kind: StepKind::from_step(&fields).map_err(|e| e.to_string())?,
+        })
+    }
+}
+
+impl From<PlanStep> for RawPlanStep {
+    fn from(step: PlanStep) -> Self {
+        let (step_type, sleep_duration, condition) = match step.kind {
+            StepKind::Standard => (None, None, None),
+            StepKind::Sentinel { sleep_duration, condition } => (
+                Some(SENTINEL_STEP_TYPE.to_string()),
+                Some(Value::from(sleep_duration)),
+                Some(serde_json::to_value(condition).unwrap_or(Value::Null)),
+            ),
+        };
+        RawPlanStep {
+            id: step.id,
+            title: step.title,
+            details: step.details,
+            agent_name: step.agent_name,
+            expected_outcome: step.expected_outcome,
+            step_type,
+            sleep_duration,
+            condition,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -21,63 +236,653 @@ pub struct PlanResponse {
     pub needs_plan: bool,
     pub response: String,
     pub plan_summary: String,
+    /// 请求缺少关键信息时先向用户追问，每次运行最多一次
+    #[serde(default)]
+    pub needs_clarification: bool,
+    #[serde(default)]
+    pub clarification_question: String,
+}
+
+pub const EMIT_PLAN_TOOL: &str = "emit_plan";
+
+/* 支持函数调用的模型通过 emit_plan 给出计划，参数就是 PlanResponse 的 JSON。
+这里的 schema 只描述结构，哨兵字段、代理名等规则仍由计划校验负责 */
+pub fn emit_plan_tool() -> ToolSpec {
+    let step = json!({
+        "type": "object",
+        "properties": {
+            "title": { "type": "string" },
+            "details": { "type": "string" },
+            "agent_name": { "type": "string" },
+            "expected_outcome": { "type": "string" },
+            "step_type": { "type": "string", "enum": [STANDARD_STEP_TYPE, SENTINEL_STEP_TYPE] },
+            "sleep_duration": { "type": "integer", "minimum": 0 },
+            "condition": { "type": ["integer", "string"] }
+        },
+        "required": ["title", "details", "agent_name"]
+    });
+    ToolSpec {
+        name: EMIT_PLAN_TOOL.to_string(),
+        description: "Submit the plan (or the direct answer) for the user's request.".to_string(),
+        parameters: json!({
+            "type": "object",
+            "properties": {
+                "response": { "type": "string" },
+                "task": { "type": "string" },
+                "plan_summary": { "type": "string" },
+                "needs_plan": { "type": "boolean" },
+                "needs_clarification": { "type": "boolean" },
+                "clarification_question": { "type": "string" },
+                "steps": { "type": "array", "items": step }
+            },
+            "required": ["response", "task", "plan_summary", "needs_plan", "steps"]
+        }),
+    }
+}
+
+/// 计划文件的格式版本，格式有不兼容的变化时递增
+pub const PLAN_FILE_VERSION: u32 = 1;
+const PLAN_FILE_KEYS: &[&str] = &["version", "created_at", "task", "steps"];
+
+/// 保存到磁盘的计划，外面包一层版本和创建时间
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlanFile {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub task: Option<String>,
+    pub steps: Vec<PlanStep>,
 }
 
 impl Plan {
 
-    pub fn from_list_of_dicts_or_str(plan_input: impl Into<Value>) -> Option<Self> {
-        let mut value = plan_input.into();
+    pub fn step_index(&self, id: &str) -> Option<usize> {
+        self.steps.iter().position(|step| step.id == id)
+    }
+
+    /// 给没有 id 或者 id 重复的步骤分配新的 id（保留第一次出现的），返回改动的步骤数
+    pub fn ensure_unique_step_ids(&mut self) -> usize {
+        let mut seen = HashSet::new();
+        let mut changed = 0;
+        for step in &mut self.steps {
+            if step.id.trim().is_empty() || !seen.insert(step.id.clone()) {
+                step.id = new_step_id();
+                while !seen.insert(step.id.clone()) {
+                    step.id = new_step_id();
+                }
+                changed += 1;
+            }
+        }
+        changed
+    }
+
+    pub fn to_json_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = PlanFile {
+            version: PLAN_FILE_VERSION,
+            created_at: Utc::now(),
+            task: self.task.clone(),
+            steps: self.steps.clone(),
+        };
+        let content = serde_json::to_string_pretty(&file)?;
+        std::fs::write(path, content).with_context(|| format!("Failed to write plan file {}", path.display()))
+    }
 
-        if let Value::String(s) = &value {
-            value = serde_json::from_str(s).ok()?;
+    /* 读取计划文件：未知字段忽略并打印警告，版本更高、JSON 损坏或者步骤不合法时
+    返回带文件路径的错误 */
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read plan file {}", path.display()))?;
+        let value: Value = serde_json::from_str(&content)
+            .with_context(|| format!("Plan file {} is not valid JSON", path.display()))?;
+        Self::from_plan_file_value(value, &path.display().to_string())
+    }
+
+    /// 已经解析成 JSON 的计划文件（例如后端上传的计划），规则与 from_json_file 相同，错误中用 name 指代文件
+    pub fn from_plan_file_value(value: Value, name: &str) -> Result<Self> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| anyhow!("Plan file {} must contain a JSON object", name))?;
+        for key in obj.keys().filter(|k| !PLAN_FILE_KEYS.contains(&k.as_str())) {
+            tracing::warn!("Ignoring unknown field '{}' in plan file {}", key, name);
+        }
+        if let Some(version) = obj.get("version").and_then(|v| v.as_u64()) {
+            if version > PLAN_FILE_VERSION as u64 {
+                return Err(anyhow!(
+                    "Plan file {} has version {}, this build supports up to version {}",
+                    name,
+                    version,
+                    PLAN_FILE_VERSION
+                ));
+            }
         }
 
-        if value.is_null() || (value.is_array() && value.as_array()?.is_empty()) {
-            return None;
+        let file: PlanFile = serde_json::from_value(value)
+            .map_err(|e| anyhow!("Plan file {} is invalid: {}", name, e))?;
+        if file.steps.is_empty() {
+            return Err(anyhow!("Plan file {} has no steps", name));
+        }
+        // 旧文件的步骤没有 id，解析时已经分配；手工编辑造成的重复 id 在这里修正
+        let mut plan = Plan { task: file.task, steps: file.steps };
+        let changed = plan.ensure_unique_step_ids();
+        if changed > 0 {
+            tracing::warn!("Assigned new ids to {} step(s) with duplicate ids in plan file {}", changed, name);
         }
+        Ok(plan)
+    }
 
-        let (task, steps_value) = match &value {
-            Value::Object(map) => {
-                let task = map.get("task").and_then(|v| v.as_str()).map(|s| s.to_string());
-                let steps_value = map.get("steps").cloned().unwrap_or(Value::Array(vec![]));
-                (task, steps_value)
-            }
-            Value::Array(arr) => {
-                (None, Value::Array(arr.clone()))
-            }
-            _ => {
-                return None;
-            }
+    /* 从模型或用户给出的各种形式构造计划：JSON 字符串（带 steps 的对象或者直接是步骤数组）、
+    Value，或者已经解析好的步骤。对象外面多包一层 plan、steps 被编码成字符串都能接受；
+    details、id、expected_outcome 可以省略，sleep_duration 可以是数字字符串。
+    title 和 agent_name 缺失或类型不对、哨兵字段不合法、没有任何步骤时返回错误，不会得到空计划 */
+    pub fn from_list_of_dicts_or_str(plan_input: impl Into<PlanInput>) -> Result<Self, PlanParseError> {
+        let (task, steps) = match plan_input.into() {
+            PlanInput::Steps(steps) => (None, steps),
+            PlanInput::Text(text) => parse_plan_value(Value::String(text), 0)?,
+            PlanInput::Value(value) => parse_plan_value(value, 0)?,
         };
+        if steps.is_empty() {
+            return Err(PlanParseError::Empty);
+        }
+        let mut plan = Plan { task, steps };
+        plan.ensure_unique_step_ids();
+        Ok(plan)
+    }
 
-        let steps_array = steps_value.as_array()?;
-        let mut steps = Vec::with_capacity(steps_array.len());
-
-        for step_value in steps_array {
-            let step_map = step_value.as_object()?;
-
-            let title = step_map.get("title")
-                .and_then(|v| v.as_str())
-                .unwrap_or("Untitled Step")
-                .to_string();
-            
-            let details = step_map.get("details")
-                .and_then(|v| v.as_str())
-                .unwrap_or("No details provided.")
-                .to_string();
-            
-            let agent_name = step_map.get("agent_name")
-                .and_then(|v| v.as_str())
-                .unwrap_or("agent")
-                .to_string();
-
-            steps.push(PlanStep { title, details, agent_name });
+}
+
+/// Plan::from_list_of_dicts_or_str 接受的输入
+#[derive(Debug, Clone)]
+pub enum PlanInput {
+    Text(String),
+    Value(Value),
+    Steps(Vec<PlanStep>),
+}
+
+impl From<&str> for PlanInput {
+    fn from(text: &str) -> Self {
+        PlanInput::Text(text.to_string())
+    }
+}
+
+impl From<String> for PlanInput {
+    fn from(text: String) -> Self {
+        PlanInput::Text(text)
+    }
+}
+
+impl From<Value> for PlanInput {
+    fn from(value: Value) -> Self {
+        PlanInput::Value(value)
+    }
+}
+
+impl From<Vec<PlanStep>> for PlanInput {
+    fn from(steps: Vec<PlanStep>) -> Self {
+        PlanInput::Steps(steps)
+    }
+}
+
+/// 无法从输入构造计划的原因，步骤下标从 0 开始，展示时从 1 开始
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlanParseError {
+    /// 输入的字符串不是合法的 JSON，调用方可以先尝试修复再解析
+    NotJson(String),
+    /// JSON 的结构不是计划，例如顶层是数字或者 steps 不是数组
+    UnexpectedShape(String),
+    /// 没有任何步骤
+    Empty,
+    InvalidStep { index: usize, message: String },
+}
+
+impl std::fmt::Display for PlanParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlanParseError::NotJson(e) => write!(f, "the plan is not valid JSON: {}", e),
+            PlanParseError::UnexpectedShape(message) => write!(f, "{}", message),
+            PlanParseError::Empty => write!(f, "the plan has no steps"),
+            PlanParseError::InvalidStep { index, message } => write!(f, "step {}: {}", index + 1, message),
         }
-        if !steps.is_empty() {
-            Some(Plan { task, steps })
-        } else {
-            None
+    }
+}
+
+impl std::error::Error for PlanParseError {}
+
+// 最多展开几层 {"plan": ...} 或被编码成字符串的 steps
+const MAX_PLAN_NESTING: usize = 3;
+
+fn parse_plan_value(value: Value, depth: usize) -> Result<(Option<String>, Vec<PlanStep>), PlanParseError> {
+    if depth > MAX_PLAN_NESTING {
+        return Err(PlanParseError::UnexpectedShape("the plan is nested too deeply".to_string()));
+    }
+    match value {
+        Value::Null => Err(PlanParseError::Empty),
+        Value::Array(steps) => Ok((None, parse_steps(steps)?)),
+        // 被编码成字符串的计划
+        Value::String(text) => {
+            let value = serde_json::from_str(text.trim()).map_err(|e| PlanParseError::NotJson(e.to_string()))?;
+            parse_plan_value(value, depth + 1)
+        }
+        Value::Object(mut map) => {
+            let task = map.get("task").and_then(|v| v.as_str()).map(|s| s.to_string());
+            let steps = match map.remove("steps") {
+                Some(steps) => steps,
+                None => match map.remove("plan") {
+                    Some(plan) => {
+                        let (inner_task, steps) = parse_plan_value(plan, depth + 1)?;
+                        return Ok((task.or(inner_task), steps));
+                    }
+                    None => return Err(PlanParseError::UnexpectedShape("the plan object has no 'steps' key".to_string())),
+                },
+            };
+            let steps = match steps {
+                Value::Array(steps) => steps,
+                Value::Null => Vec::new(),
+                Value::String(text) => match serde_json::from_str(text.trim()) {
+                    Ok(Value::Array(steps)) => steps,
+                    _ => return Err(PlanParseError::UnexpectedShape(format!("steps must be an array, got {}", describe_value(&Value::String(text))))),
+                },
+                other => return Err(PlanParseError::UnexpectedShape(format!("steps must be an array, got {}", describe_value(&other)))),
+            };
+            Ok((task, parse_steps(steps)?))
+        }
+        other => Err(PlanParseError::UnexpectedShape(format!(
+            "the plan must be a JSON object or an array of steps, got {}",
+            describe_value(&other)
+        ))),
+    }
+}
+
+fn parse_steps(steps: Vec<Value>) -> Result<Vec<PlanStep>, PlanParseError> {
+    steps
+        .into_iter()
+        .enumerate()
+        .map(|(index, step)| match step {
+            Value::Object(map) => parse_step(&map).map_err(|message| PlanParseError::InvalidStep { index, message }),
+            other => Err(PlanParseError::InvalidStep {
+                index,
+                message: format!("the step must be a JSON object, got {}", describe_value(&other)),
+            }),
+        })
+        .collect()
+}
+
+// 可选字段：给出时必须是字符串，null 与省略相同
+fn optional_string(step: &Map<String, Value>, key: &str) -> Result<Option<String>, String> {
+    match step.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(value) => Err(format!("{} must be a string, got {}", key, describe_value(value))),
+    }
+}
+
+fn required_string(step: &Map<String, Value>, key: &str) -> Result<String, String> {
+    optional_string(step, key)?
+        .filter(|s| !s.trim().is_empty())
+        .ok_or_else(|| format!("missing key '{}'", key))
+}
+
+fn parse_step(step: &Map<String, Value>) -> Result<PlanStep, String> {
+    let title = required_string(step, "title")?;
+    let agent_name = required_string(step, "agent_name")?;
+    // 没有 details 时用标题代替
+    let details = optional_string(step, "details")?
+        .filter(|d| !d.trim().is_empty())
+        .unwrap_or_else(|| title.clone());
+    let expected_outcome = normalize_outcome(optional_string(step, "expected_outcome")?);
+    let id = optional_string(step, "id")?.unwrap_or_default();
+
+    // 模型有时把 sleep_duration 写成字符串，例如 "600"
+    let mut fields = step.clone();
+    if let Some(Value::String(text)) = step.get("sleep_duration") {
+        if let Ok(secs) = text.trim().parse::<u64>() {
+            fields.insert("sleep_duration".to_string(), Value::from(secs));
         }
     }
+    // 哨兵字段不合法时整个计划视为无效，避免把哨兵步骤当成普通步骤执行
+    let kind = StepKind::from_step(&fields).map_err(|e| e.to_string())?;
+
+    Ok(PlanStep { id, title, details, agent_name, expected_outcome, kind })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
 
+    // 与规划提示词中的示例计划结构相同
+    fn sentinel_example() -> Value {
+        json!({
+            "task": "Monitor the repository and report new stars",
+            "needs_plan": true,
+            "response": "",
+            "plan_summary": "Check the star count periodically until it grows by 5",
+            "steps": [
+                {
+                    "title": "Record the current star count",
+                    "details": "Open the repository page and note the number of stars.",
+                    "agent_name": "web_surfer",
+                    "expected_outcome": "The current star count is recorded",
+                    "step_type": "PlanStep"
+                },
+                {
+                    "title": "Watch the star count",
+                    "details": "Every 10 minutes, check whether the repository gained 5 more stars.",
+                    "agent_name": "web_surfer",
+                    "step_type": "SentinelPlanStep",
+                    "sleep_duration": 600,
+                    "condition": "The repository has at least 5 more stars than before"
+                },
+                {
+                    "title": "Check the inbox",
+                    "details": "Refresh the inbox three times, once an hour.",
+                    "agent_name": "web_surfer",
+                    "step_type": "SentinelPlanStep",
+                    "sleep_duration": 3600,
+                    "condition": 3
+                }
+            ]
+        })
+    }
+
+    #[test]
+    fn test_parse_sentinel_example_plan() {
+        let response: PlanResponse = serde_json::from_value(sentinel_example()).unwrap();
+
+        assert_eq!(response.steps[0].kind, StepKind::Standard);
+        assert_eq!(response.steps[1].kind, StepKind::Sentinel {
+            sleep_duration: 600,
+            condition: SentinelCondition::Text("The repository has at least 5 more stars than before".to_string()),
+        });
+        assert_eq!(response.steps[2].kind, StepKind::Sentinel {
+            sleep_duration: 3600,
+            condition: SentinelCondition::Count(3),
+        });
+
+        let plan = Plan::from_list_of_dicts_or_str(sentinel_example()).unwrap();
+        let kinds: Vec<StepKind> = plan.steps.iter().map(|s| s.kind.clone()).collect();
+        let parsed: Vec<StepKind> = response.steps.iter().map(|s| s.kind.clone()).collect();
+        assert_eq!(kinds, parsed);
+    }
+
+    #[test]
+    fn test_standard_steps_tolerate_missing_sentinel_fields() {
+        let step: PlanStep = serde_json::from_value(json!({
+            "title": "Search",
+            "details": "Search for the restaurant",
+            "agent_name": "web_surfer"
+        })).unwrap();
+        assert_eq!(step.kind, StepKind::Standard);
+
+        // 普通步骤序列化时不输出哨兵字段
+        let value = serde_json::to_value(&step).unwrap();
+        assert!(value.get("step_type").is_none());
+        assert!(value.get("sleep_duration").is_none());
+    }
+
+    #[test]
+    fn test_invalid_sentinel_fields_are_rejected() {
+        let mut plan = sentinel_example();
+        plan["steps"][1]["sleep_duration"] = json!("daily");
+        assert!(serde_json::from_value::<PlanResponse>(plan.clone()).is_err());
+        assert_eq!(
+            Plan::from_list_of_dicts_or_str(plan).unwrap_err().to_string(),
+            "step 2: sleep_duration must be a non-negative integer, got 'daily'"
+        );
+
+        let mut plan = sentinel_example();
+        plan["steps"][2].as_object_mut().unwrap().remove("condition");
+        assert!(serde_json::from_value::<PlanResponse>(plan).is_err());
+
+        let mut plan = sentinel_example();
+        plan["steps"][2]["condition"] = json!(-1);
+        assert!(serde_json::from_value::<PlanResponse>(plan).is_err());
+    }
+
+    #[test]
+    fn test_sentinel_fields_rejected_when_disabled() {
+        let plan = sentinel_example();
+        let steps = plan["steps"].as_array().unwrap();
+        let step = |i: usize| steps[i].as_object().unwrap();
+
+        assert_eq!(StepKind::from_step_checked(step(0), false), Ok(StepKind::Standard));
+        assert!(StepKind::from_step_checked(step(1), false).is_err());
+        assert!(StepKind::from_step_checked(step(1), true).unwrap().is_sentinel());
+
+        // 没有 step_type 但带着哨兵字段
+        let mut stray = step(0).clone();
+        stray.insert("sleep_duration".to_string(), json!(60));
+        assert!(StepKind::from_step_checked(&stray, false).is_err());
+        assert_eq!(StepKind::from_step_checked(&stray, true), Ok(StepKind::Standard));
+    }
+
+    #[test]
+    fn test_plan_file_round_trip() -> Result<()> {
+        let plan = Plan::from_list_of_dicts_or_str(sentinel_example()).unwrap();
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("nightly.json");
+
+        plan.to_json_file(&path)?;
+        let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        assert_eq!(saved["version"], PLAN_FILE_VERSION);
+        assert!(saved["created_at"].is_string());
+
+        let loaded = Plan::from_json_file(&path)?;
+        assert_eq!(loaded.task, plan.task);
+        assert_eq!(loaded.steps.len(), 3);
+        assert_eq!(loaded.steps[0].id, plan.steps[0].id);
+        assert_eq!(loaded.steps[1].kind, plan.steps[1].kind);
+        assert_eq!(loaded.steps[2].details, plan.steps[2].details);
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_file_ignores_unknown_fields() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("plan.json");
+        std::fs::write(&path, json!({
+            "version": 1,
+            "created_at": "2025-01-01T00:00:00Z",
+            "task": "Find the menu",
+            "author": "someone",
+            "steps": [{ "title": "Search", "details": "Search", "agent_name": "web_surfer", "note": "x" }]
+        }).to_string())?;
+
+        let plan = Plan::from_json_file(&path)?;
+        assert_eq!(plan.steps[0].agent_name, "web_surfer");
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_plan_file_errors() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("plan.json");
+
+        std::fs::write(&path, "{ \"version\": 1, \"steps\": [")?;
+        let error = Plan::from_json_file(&path).unwrap_err();
+        assert!(format!("{:#}", error).contains("is not valid JSON"));
+
+        std::fs::write(&path, json!({ "version": 1, "created_at": "2025-01-01T00:00:00Z", "steps": [{ "title": "Search" }] }).to_string())?;
+        let error = Plan::from_json_file(&path).unwrap_err();
+        assert!(error.to_string().contains("is invalid"));
+
+        std::fs::write(&path, json!({ "version": 99, "created_at": "2025-01-01T00:00:00Z", "steps": [] }).to_string())?;
+        assert!(Plan::from_json_file(&path).unwrap_err().to_string().contains("version 99"));
+
+        assert!(Plan::from_json_file(dir.path().join("missing.json")).is_err());
+        // 上传的计划按同样的规则解析，错误中用上传时的名字
+        let error = Plan::from_plan_file_value(json!(["Search"]), "upload").unwrap_err();
+        assert_eq!(error.to_string(), "Plan file upload must contain a JSON object");
+        let error = Plan::from_plan_file_value(json!({ "version": 1, "steps": [] }), "upload").unwrap_err();
+        assert!(error.to_string().starts_with("Plan file upload is invalid"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_expected_outcome_survives_parsing() {
+        let response: PlanResponse = serde_json::from_value(sentinel_example()).unwrap();
+        assert_eq!(response.steps[0].expected_outcome.as_deref(), Some("The current star count is recorded"));
+        assert_eq!(response.steps[1].expected_outcome, None);
+
+        let plan = Plan::from_list_of_dicts_or_str(sentinel_example()).unwrap();
+        assert_eq!(plan.steps[0].expected_outcome, response.steps[0].expected_outcome);
+
+        let value = serde_json::to_value(&plan.steps[0]).unwrap();
+        assert_eq!(value["expected_outcome"], "The current star count is recorded");
+        assert!(serde_json::to_value(&plan.steps[1]).unwrap().get("expected_outcome").is_none());
+
+        // 空白的结果等同于没有
+        let step: PlanStep = serde_json::from_value(json!({
+            "title": "Search",
+            "details": "Search for the restaurant",
+            "agent_name": "web_surfer",
+            "expected_outcome": "  "
+        })).unwrap();
+        assert_eq!(step.expected_outcome, None);
+    }
+
+    // 从提示词中截取 { 到 } 之间的示例步骤
+    fn prompt_example_step(prompt: &str) -> &str {
+        &prompt[prompt.find('{').unwrap()..=prompt.rfind('}').unwrap()]
+    }
+
+    #[test]
+    fn test_plan_from_prompt_examples() {
+        use crate::orchestrator::prompt::SENTINEL_STEPS_PROMPT;
+
+        let step = prompt_example_step(SENTINEL_STEPS_PROMPT);
+        let plan = Plan::from_list_of_dicts_or_str(format!("[{}]", step)).unwrap();
+        assert_eq!(plan.task, None);
+        assert_eq!(plan.steps[0].title, "Watch the star count");
+        assert!(plan.steps[0].details.contains("\n Every 10 minutes"));
+        assert_eq!(plan.steps[0].kind, StepKind::Sentinel {
+            sleep_duration: 600,
+            condition: SentinelCondition::Text("The repository has at least 5 more stars than before".to_string()),
+        });
+
+        // 同一个步骤放在对象里、作为 Value 或已解析的步骤传入，结果相同
+        let wrapped = format!("{{\"task\": \"Watch stars\", \"steps\": [{}]}}", step);
+        let from_object = Plan::from_list_of_dicts_or_str(wrapped.as_str()).unwrap();
+        assert_eq!(from_object.task.as_deref(), Some("Watch stars"));
+        assert_eq!(from_object.steps[0].kind, plan.steps[0].kind);
+        let from_value = Plan::from_list_of_dicts_or_str(serde_json::from_str::<Value>(&wrapped).unwrap()).unwrap();
+        assert_eq!(from_value.steps[0].details, plan.steps[0].details);
+        let from_steps = Plan::from_list_of_dicts_or_str(plan.steps.clone()).unwrap();
+        assert_eq!(from_steps.steps, plan.steps);
+
+        let response: PlanResponse = serde_json::from_value(sentinel_example()).unwrap();
+        assert_eq!(Plan::from_list_of_dicts_or_str(response.steps).unwrap().steps.len(), 3);
+    }
+
+    #[test]
+    fn test_plan_input_normalization() {
+        // details 缺失时用标题代替，sleep_duration 可以是数字字符串，外面多包一层 plan 也能接受
+        let plan = Plan::from_list_of_dicts_or_str(json!({
+            "plan": {
+                "task": "Watch the inbox",
+                "steps": [
+                    { "title": "Open the inbox", "agent_name": "web_surfer", "details": null },
+                    {
+                        "title": "Watch",
+                        "details": "Refresh the inbox",
+                        "agent_name": "web_surfer",
+                        "step_type": "sentinelplanstep",
+                        "sleep_duration": " 600 ",
+                        "condition": 3
+                    }
+                ]
+            }
+        })).unwrap();
+        assert_eq!(plan.task.as_deref(), Some("Watch the inbox"));
+        assert_eq!(plan.steps[0].details, "Open the inbox");
+        assert_eq!(plan.steps[0].expected_outcome, None);
+        assert_eq!(plan.steps[1].kind, StepKind::Sentinel { sleep_duration: 600, condition: SentinelCondition::Count(3) });
+
+        // steps 被编码成字符串
+        let encoded = json!({ "steps": json!([{ "title": "Search", "agent_name": "web_surfer" }]).to_string() });
+        assert_eq!(Plan::from_list_of_dicts_or_str(encoded).unwrap().steps[0].title, "Search");
+    }
+
+    #[test]
+    fn test_adversarial_plan_inputs() {
+        let error = |input: PlanInput| Plan::from_list_of_dicts_or_str(input).unwrap_err();
+
+        assert!(matches!(error("Sounds good, go ahead".into()), PlanParseError::NotJson(_)));
+        assert!(matches!(error("[{\"title\": \"Search\",".into()), PlanParseError::NotJson(_)));
+        assert_eq!(error("[]".into()), PlanParseError::Empty);
+        assert_eq!(error("null".into()), PlanParseError::Empty);
+        assert_eq!(error(json!({ "task": "x", "steps": [] }).into()), PlanParseError::Empty);
+        assert_eq!(error(Vec::new().into()), PlanParseError::Empty);
+        assert_eq!(error(json!(42).into()).to_string(), "the plan must be a JSON object or an array of steps, got 42");
+        assert_eq!(error(json!({ "task": "x" }).into()).to_string(), "the plan object has no 'steps' key");
+        assert_eq!(error(json!({ "steps": { "title": "x" } }).into()).to_string(), "steps must be an array, got {\"title\":\"x\"}");
+        assert_eq!(error(json!({ "steps": "not steps" }).into()).to_string(), "steps must be an array, got 'not steps'");
+
+        let steps = |step: Value| PlanInput::from(json!([{ "title": "Search", "agent_name": "web_surfer" }, step]));
+        assert_eq!(error(steps(json!("Search"))).to_string(), "step 2: the step must be a JSON object, got 'Search'");
+        assert_eq!(error(steps(json!({ "agent_name": "web_surfer" }))).to_string(), "step 2: missing key 'title'");
+        assert_eq!(error(steps(json!({ "title": "Read", "agent_name": "  " }))).to_string(), "step 2: missing key 'agent_name'");
+        assert_eq!(
+            error(steps(json!({ "title": ["Read"], "agent_name": "web_surfer" }))).to_string(),
+            "step 2: title must be a string, got [\"Read\"]"
+        );
+        assert_eq!(
+            error(steps(json!({ "title": "Watch", "agent_name": "web_surfer", "step_type": "SentinelPlanStep", "sleep_duration": "hourly", "condition": 3 })))
+                .to_string(),
+            "step 2: sleep_duration must be a non-negative integer, got 'hourly'"
+        );
+        assert_eq!(
+            error(steps(json!({ "title": "Watch", "agent_name": "web_surfer", "step_type": "SentinelPlanStep", "sleep_duration": 60 }))).to_string(),
+            "step 2: condition is required for a SentinelPlanStep"
+        );
+
+        // 嵌套过深的输入不会无限展开
+        let mut nested = json!([{ "title": "Search", "agent_name": "web_surfer" }]);
+        for _ in 0..5 {
+            nested = json!({ "plan": nested });
+        }
+        assert!(matches!(error(nested.into()), PlanParseError::UnexpectedShape(_)));
+    }
+
+    #[test]
+    fn test_sentinel_step_round_trip() {
+        let response: PlanResponse = serde_json::from_value(sentinel_example()).unwrap();
+        let value = serde_json::to_value(&response.steps[2]).unwrap();
+        assert_eq!(value["step_type"], "SentinelPlanStep");
+        assert_eq!(value["sleep_duration"], 3600);
+        assert_eq!(value["condition"], 3);
+
+        let step: PlanStep = serde_json::from_value(value).unwrap();
+        assert_eq!(step.kind, response.steps[2].kind);
+    }
+
+    #[test]
+    fn test_step_ids() -> Result<()> {
+        // 模型输出没有 id，每个步骤分配不同的 id
+        let plan = Plan::from_list_of_dicts_or_str(sentinel_example()).unwrap();
+        let ids: HashSet<&str> = plan.steps.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(plan.steps[0].id.len(), 8);
+        assert_eq!(plan.steps[0].label(), format!("[{}] Record the current star count", &plan.steps[0].id[..4]));
+        assert_eq!(plan.step_index(&plan.steps[2].id), Some(2));
+
+        // 没有 id 的旧文件加载时分配 id，重复的 id 只保留第一个
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("old.json");
+        std::fs::write(&path, json!({
+            "version": 1,
+            "created_at": "2025-01-01T00:00:00Z",
+            "steps": [
+                { "title": "Search", "details": "Search", "agent_name": "web_surfer" },
+                { "id": "abcd1234", "title": "Read", "details": "Read", "agent_name": "web_surfer" },
+                { "id": "abcd1234", "title": "Summarize", "details": "Summarize", "agent_name": "web_surfer" }
+            ]
+        }).to_string())?;
+        let loaded = Plan::from_json_file(&path)?;
+        assert!(!loaded.steps[0].id.is_empty());
+        assert_eq!(loaded.steps[1].id, "abcd1234");
+        assert_ne!(loaded.steps[2].id, "abcd1234");
+        Ok(())
+    }
 }
@@ -0,0 +1,467 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+
+use crate::orchestrator::plan::{Plan, PlanStep};
+use crate::orchestrator::plan_history::{diff, PlanDiff, PlanSource, PlanVersion};
+use crate::orchestrator::validation::{format_validation_errors, validate_plan_steps, PlanValidationLimits};
+
+/// 修改步骤时要替换的字段，未设置的字段保持不变（包括哨兵字段）
+#[derive(Debug, Clone, Default)]
+pub struct StepEdit {
+    pub title: Option<String>,
+    pub details: Option<String>,
+    pub agent_name: Option<String>,
+}
+
+/// 待导入的计划，确认之前可以先展示与当前计划的差异
+#[derive(Debug, Clone)]
+pub struct PlanImport {
+    pub path: PathBuf,
+    pub plan: Plan,
+    pub diff: PlanDiff,
+}
+
+/* 带撤销历史的计划编辑：每次编辑都在当前计划的副本上进行，重新校验通过后才生成新版本，
+版本 1 为编辑前的原始计划（reason 为 "initial plan"），之后每个版本的 reason 是产生它的编辑，
+不合法的编辑（计划变空、未知代理等）直接返回错误，当前版本不变。
+undo/redo 在版本之间移动，在撤销之后做新的编辑会丢弃被撤销的版本 */
+#[derive(Debug, Clone)]
+pub struct PlanEditor {
+    versions: Vec<PlanVersion>,
+    // 当前版本在 versions 中的下标
+    current: usize,
+    team: Vec<String>,
+    sentinel_enabled: bool,
+    limits: PlanValidationLimits,
+}
+
+impl PlanEditor {
+    pub fn new(plan: Plan, team: Vec<String>, sentinel_enabled: bool, limits: PlanValidationLimits) -> Result<Self> {
+        let mut editor = Self {
+            versions: Vec::new(),
+            current: 0,
+            team,
+            sentinel_enabled,
+            limits,
+        };
+        editor.validate(&plan)?;
+        editor.versions.push(PlanVersion::new(1, plan, PlanSource::Generated, Some("initial plan".to_string())));
+        Ok(editor)
+    }
+
+    pub fn plan(&self) -> &Plan {
+        &self.versions[self.current].plan
+    }
+
+    // 到当前版本为止的历史，被撤销的版本不包括在内
+    pub fn plan_versions(&self) -> &[PlanVersion] {
+        &self.versions[..=self.current]
+    }
+
+    /// 两个版本之间的差异，版本号不存在（或已被撤销）时返回 None
+    pub fn diff_versions(&self, a: usize, b: usize) -> Option<PlanDiff> {
+        let find = |version: usize| self.plan_versions().iter().find(|v| v.version == version);
+        Some(diff(&find(a)?.plan, &find(b)?.plan))
+    }
+
+    /// 步骤可以分配给的代理
+    pub fn team(&self) -> &[String] {
+        &self.team
+    }
+
+    pub fn current_version(&self) -> usize {
+        self.versions[self.current].version
+    }
+
+    /// 例如 "v3 (2 edits)"
+    pub fn version_label(&self) -> String {
+        let edits = self.current;
+        format!("v{} ({} edit{})", self.current_version(), edits, if edits == 1 { "" } else { "s" })
+    }
+
+    pub fn modify_step(&mut self, index: usize, edit: StepEdit) -> Result<()> {
+        self.apply(format!("modify step {}", index + 1), |plan| {
+            let step = plan.steps.get_mut(index).ok_or_else(|| step_out_of_range(index))?;
+            if let Some(title) = edit.title {
+                step.title = title;
+            }
+            if let Some(details) = edit.details {
+                step.details = details;
+            }
+            if let Some(agent_name) = edit.agent_name {
+                step.agent_name = agent_name;
+            }
+            Ok(())
+        })
+    }
+
+    /// 在 index 处插入，index 等于步骤数时追加到末尾
+    pub fn add_step(&mut self, index: usize, step: PlanStep) -> Result<()> {
+        self.apply(format!("add step {}", index + 1), |plan| {
+            if index > plan.steps.len() {
+                return Err(step_out_of_range(index));
+            }
+            plan.steps.insert(index, step);
+            Ok(())
+        })
+    }
+
+    pub fn remove_step(&mut self, index: usize) -> Result<()> {
+        self.apply(format!("remove step {}", index + 1), |plan| {
+            if index >= plan.steps.len() {
+                return Err(step_out_of_range(index));
+            }
+            plan.steps.remove(index);
+            Ok(())
+        })
+    }
+
+    /// 一次删除多个步骤，只产生一个版本，撤销一次即可恢复全部
+    pub fn remove_steps(&mut self, indices: &[usize]) -> Result<()> {
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+        let label = indices.iter().map(|i| (i + 1).to_string()).collect::<Vec<_>>().join(", ");
+        self.apply(format!("remove steps {}", label), |plan| {
+            if let Some(&index) = indices.iter().find(|&&i| i >= plan.steps.len()) {
+                return Err(step_out_of_range(index));
+            }
+            for &index in indices.iter().rev() {
+                plan.steps.remove(index);
+            }
+            Ok(())
+        })
+    }
+
+    pub fn reorder_step(&mut self, from: usize, to: usize) -> Result<()> {
+        self.apply(format!("move step {} to {}", from + 1, to + 1), |plan| {
+            let len = plan.steps.len();
+            if from >= len {
+                return Err(step_out_of_range(from));
+            }
+            if to >= len {
+                return Err(step_out_of_range(to));
+            }
+            let step = plan.steps.remove(from);
+            plan.steps.insert(to, step);
+            Ok(())
+        })
+    }
+
+    pub fn replace_plan(&mut self, new_plan: Plan) -> Result<()> {
+        self.apply("replace plan".to_string(), |plan| {
+            *plan = new_plan;
+            Ok(())
+        })
+    }
+
+    /// 把当前计划写到文件，目录不存在时创建
+    pub fn export_to_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+        }
+        self.plan().to_json_file(path)
+    }
+
+    /// 读取并按当前团队校验计划文件，返回与当前计划的差异供确认，当前计划不变
+    pub fn preview_import(&self, path: impl AsRef<Path>) -> Result<PlanImport> {
+        let path = path.as_ref();
+        let mut plan = Plan::from_json_file(path)?;
+        if plan.task.is_none() {
+            plan.task = self.plan().task.clone();
+        }
+        self.validate(&plan)
+            .map_err(|e| anyhow!("Cannot import {}: {}", path.display(), e))?;
+        let diff = diff(self.plan(), &plan);
+        Ok(PlanImport { path: path.to_path_buf(), plan, diff })
+    }
+
+    /// 确认后用导入的计划替换当前计划，生成一个新版本
+    pub fn apply_import(&mut self, import: PlanImport) -> Result<()> {
+        let PlanImport { path, plan, .. } = import;
+        self.apply(format!("import {}", path.display()), |current| {
+            *current = plan;
+            Ok(())
+        })
+    }
+
+    /// 回到上一个版本，已经是原始计划时返回 false
+    pub fn undo(&mut self) -> bool {
+        if self.current == 0 {
+            return false;
+        }
+        self.current -= 1;
+        true
+    }
+
+    pub fn redo(&mut self) -> bool {
+        if self.current + 1 >= self.versions.len() {
+            return false;
+        }
+        self.current += 1;
+        true
+    }
+
+    fn validate(&self, plan: &Plan) -> Result<()> {
+        validate_plan_steps(plan, &self.team, self.sentinel_enabled, &self.limits)
+            .map_err(|errors| anyhow!("The edited plan is invalid: {}", format_validation_errors(&errors)))
+    }
+
+    fn apply(&mut self, edit: String, change: impl FnOnce(&mut Plan) -> Result<()>) -> Result<()> {
+        let mut plan = self.plan().clone();
+        change(&mut plan)?;
+        // 新加的步骤可能没有 id，或者复制了已有步骤的 id
+        plan.ensure_unique_step_ids();
+        self.validate(&plan)?;
+
+        self.versions.truncate(self.current + 1);
+        let version = PlanVersion::new(self.current_version() + 1, plan, PlanSource::UserEdited, Some(edit));
+        self.versions.push(version);
+        self.current += 1;
+        Ok(())
+    }
+}
+
+fn step_out_of_range(index: usize) -> anyhow::Error {
+    anyhow!("Step {} does not exist", index + 1)
+}
+
+/// 导出计划的默认路径：plans/<任务的 slug>-<时间>.json
+pub fn default_export_path(task: &str, now: DateTime<Utc>) -> PathBuf {
+    PathBuf::from("plans").join(format!("{}-{}.json", slugify(task), now.format("%Y%m%d-%H%M%S")))
+}
+
+// 小写字母数字之外的字符换成 -，连续的 - 合并，最多 50 个字符
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+        if slug.chars().count() >= 50 {
+            break;
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() { "plan".to_string() } else { slug.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::plan::{new_step_id, SentinelCondition, StepKind};
+
+    fn step(title: &str, agent_name: &str) -> PlanStep {
+        PlanStep {
+            id: new_step_id(),
+            title: title.to_string(),
+            details: format!("{} details", title),
+            agent_name: agent_name.to_string(),
+            expected_outcome: None,
+            kind: StepKind::Standard,
+        }
+    }
+
+    fn editor() -> PlanEditor {
+        let plan = Plan {
+            task: Some("Find the menu".to_string()),
+            steps: vec![step("Search", "web_surfer"), step("Read", "web_surfer")],
+        };
+        let team = vec!["web_surfer".to_string(), "coder_agent".to_string()];
+        PlanEditor::new(plan, team, true, PlanValidationLimits::default()).unwrap()
+    }
+
+    fn titles(editor: &PlanEditor) -> Vec<String> {
+        editor.plan().steps.iter().map(|s| s.title.clone()).collect()
+    }
+
+    #[test]
+    fn test_five_edits_undo_twice_redo_once() -> Result<()> {
+        let mut editor = editor();
+
+        editor.modify_step(0, StepEdit { title: Some("Search Bing".to_string()), ..StepEdit::default() })?;
+        editor.add_step(2, step("Summarize", "coder_agent"))?;
+        editor.reorder_step(2, 0)?;
+        editor.remove_step(2)?;
+        editor.add_step(1, step("Translate", "coder_agent"))?;
+        assert_eq!(titles(&editor), vec!["Summarize", "Translate", "Search Bing"]);
+        assert_eq!(editor.version_label(), "v6 (5 edits)");
+
+        assert!(editor.undo());
+        assert!(editor.undo());
+        assert_eq!(titles(&editor), vec!["Summarize", "Search Bing", "Read"]);
+        assert!(editor.redo());
+        assert_eq!(titles(&editor), vec!["Summarize", "Search Bing"]);
+        assert_eq!(editor.version_label(), "v5 (4 edits)");
+
+        assert_eq!(editor.diff_versions(4, 5).unwrap().summary(), "- 3. Read");
+        assert!(editor.diff_versions(5, 6).is_none());
+
+        let edits: Vec<&str> = editor.plan_versions().iter().map(|v| v.reason.as_deref().unwrap_or_default()).collect();
+        assert_eq!(edits, vec!["initial plan", "modify step 1", "add step 3", "move step 3 to 1", "remove step 3"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_step_ids_survive_edits() -> Result<()> {
+        let mut editor = editor();
+        let ids: Vec<String> = editor.plan().steps.iter().map(|s| s.id.clone()).collect();
+
+        editor.add_step(0, step("Open Bing", "web_surfer"))?;
+        editor.modify_step(2, StepEdit { title: Some("Read the menu".to_string()), ..StepEdit::default() })?;
+        editor.reorder_step(2, 1)?;
+        let plan = editor.plan();
+        assert_eq!(plan.step_index(&ids[0]), Some(2));
+        assert_eq!(plan.step_index(&ids[1]), Some(1));
+        assert_eq!(plan.steps[1].title, "Read the menu");
+
+        // 复制已有步骤（连同 id）时分配新的 id
+        let copy = editor.plan().steps[1].clone();
+        editor.add_step(3, copy)?;
+        assert_ne!(editor.plan().steps[3].id, ids[1]);
+        assert_eq!(editor.plan().step_index(&ids[1]), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_edit_after_undo_discards_redo() -> Result<()> {
+        let mut editor = editor();
+        editor.remove_step(1)?;
+        assert!(editor.undo());
+        editor.modify_step(1, StepEdit { details: Some("Open the menu page".to_string()), ..StepEdit::default() })?;
+
+        assert!(!editor.redo());
+        assert_eq!(editor.plan().steps.len(), 2);
+        assert_eq!(editor.current_version(), 2);
+        assert!(editor.undo());
+        assert!(!editor.undo());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_edits_are_rejected() {
+        let mut editor = editor();
+        editor.remove_step(0).unwrap();
+
+        // 计划不能变空
+        assert!(editor.remove_step(0).unwrap_err().to_string().contains("steps must not be empty"));
+        // 未知代理
+        let error = editor
+            .modify_step(0, StepEdit { agent_name: Some("file_surfer".to_string()), ..StepEdit::default() })
+            .unwrap_err();
+        assert!(error.to_string().contains("agent_name 'file_surfer'"));
+        assert!(editor.reorder_step(0, 3).is_err());
+        assert!(editor.replace_plan(Plan { task: None, steps: Vec::new() }).is_err());
+
+        // 失败的编辑不产生新版本
+        assert_eq!(editor.version_label(), "v2 (1 edit)");
+        assert_eq!(titles(&editor), vec!["Read"]);
+    }
+
+    #[test]
+    fn test_title_edit_keeps_sentinel_fields() -> Result<()> {
+        let mut editor = editor();
+        let sentinel = StepKind::Sentinel {
+            sleep_duration: 3600,
+            condition: SentinelCondition::Text("The menu changes".to_string()),
+        };
+        editor.add_step(2, PlanStep { kind: sentinel.clone(), ..step("Watch", "web_surfer") })?;
+
+        editor.modify_step(2, StepEdit { title: Some("Watch the menu".to_string()), ..StepEdit::default() })?;
+
+        let watched = &editor.plan().steps[2];
+        assert_eq!(watched.title, "Watch the menu");
+        assert_eq!(watched.kind, sentinel);
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_edit_import_round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("plans").join("menu.json");
+        let mut editor = editor();
+        editor.export_to_file(&path)?;
+
+        // 在磁盘上修改导出的文件：改第二步的标题，追加一步
+        let mut value: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        value["steps"][1]["title"] = serde_json::json!("Read the menu");
+        value["steps"].as_array_mut().unwrap().push(serde_json::json!({
+            "title": "Summarize",
+            "details": "Summarize the menu",
+            "agent_name": "coder_agent",
+        }));
+        std::fs::write(&path, serde_json::to_string_pretty(&value)?)?;
+
+        let import = editor.preview_import(&path)?;
+        assert_eq!(import.diff.summary(), "~ 2. title changed\n+ 3. Summarize");
+        // 确认之前当前计划不变
+        assert_eq!(titles(&editor), vec!["Search", "Read"]);
+
+        editor.apply_import(import)?;
+        assert_eq!(titles(&editor), vec!["Search", "Read the menu", "Summarize"]);
+        assert_eq!(editor.version_label(), "v2 (1 edit)");
+        let reason = editor.plan_versions()[1].reason.clone().unwrap();
+        assert!(reason.starts_with("import ") && reason.ends_with("menu.json"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_is_validated_against_the_team() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let editor = editor();
+        assert!(editor.preview_import(dir.path().join("missing.json")).is_err());
+
+        let path = dir.path().join("plan.json");
+        let plan = Plan { task: None, steps: vec![step("Open files", "file_surfer")] };
+        plan.to_json_file(&path)?;
+        let error = editor.preview_import(&path).unwrap_err();
+        assert!(error.to_string().contains("agent_name 'file_surfer'"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_export_path() {
+        let now = DateTime::parse_from_rfc3339("2024-05-01T08:30:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(
+            default_export_path("Find the menu of Café Roma!", now),
+            PathBuf::from("plans/find-the-menu-of-café-roma-20240501-083000.json")
+        );
+        assert_eq!(default_export_path("???", now), PathBuf::from("plans/plan-20240501-083000.json"));
+    }
+
+    #[test]
+    fn test_reorder_and_bulk_delete() -> Result<()> {
+        let mut editor = editor();
+        editor.add_step(2, step("Verify", "web_surfer"))?;
+        editor.add_step(3, step("Summarize", "coder_agent"))?;
+        let ids: Vec<String> = editor.plan().steps.iter().map(|s| s.id.clone()).collect();
+
+        // 验证步骤被放在了它要验证的动作之前
+        editor.reorder_step(2, 1)?;
+        assert_eq!(titles(&editor), vec!["Search", "Verify", "Read", "Summarize"]);
+        editor.reorder_step(1, 2)?;
+        assert_eq!(titles(&editor), vec!["Search", "Read", "Verify", "Summarize"]);
+        assert_eq!(editor.plan().steps.iter().map(|s| s.id.clone()).collect::<Vec<_>>(), ids);
+
+        editor.remove_steps(&[3, 0, 3])?;
+        assert_eq!(titles(&editor), vec!["Read", "Verify"]);
+        assert_eq!(editor.plan().steps[0].id, ids[1]);
+        assert_eq!(editor.plan_versions().last().unwrap().reason.as_deref(), Some("remove steps 1, 4"));
+
+        // 批量删除只占一个版本
+        assert!(editor.undo());
+        assert_eq!(titles(&editor), vec!["Search", "Read", "Verify", "Summarize"]);
+
+        // 越界或删空都不产生新版本
+        assert!(editor.remove_steps(&[1, 7]).is_err());
+        assert!(editor.remove_steps(&[0, 1, 2, 3]).is_err());
+        assert_eq!(titles(&editor), vec!["Search", "Read", "Verify", "Summarize"]);
+        Ok(())
+    }
+}
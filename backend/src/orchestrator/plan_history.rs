@@ -0,0 +1,232 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::common::text::normalize_text;
+use crate::orchestrator::plan::{Plan, PlanStep};
+
+/// 计划版本的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanSource {
+    Generated,
+    Replanned,
+    UserEdited,
+}
+
+/// 计划的一个版本，version 从 1 开始
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanVersion {
+    pub version: usize,
+    pub plan: Plan,
+    pub source: PlanSource,
+    pub created_at: DateTime<Utc>,
+    /// 产生这个版本的原因，例如重规划的原因或用户做的编辑
+    pub reason: Option<String>,
+}
+
+impl PlanVersion {
+    pub fn new(version: usize, plan: Plan, source: PlanSource, reason: Option<String>) -> Self {
+        Self { version, plan, source, created_at: Utc::now(), reason }
+    }
+}
+
+/// 一个字段在两个版本之间的变化
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// 步骤级别的变化，下标都是各自版本中的位置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StepChange {
+    Added { index: usize, step: PlanStep },
+    Removed { index: usize, step: PlanStep },
+    Modified { old_index: usize, new_index: usize, changes: Vec<FieldChange> },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlanDiff {
+    pub changes: Vec<StepChange>,
+}
+
+impl PlanDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// 紧凑的文字展示，每个变化一行，步骤编号从 1 开始
+    pub fn summary(&self) -> String {
+        if self.changes.is_empty() {
+            return "No changes".to_string();
+        }
+        self.changes
+            .iter()
+            .map(|change| match change {
+                StepChange::Added { index, step } => format!("+ {}. {}", index + 1, step.title),
+                StepChange::Removed { index, step } => format!("- {}. {}", index + 1, step.title),
+                StepChange::Modified { old_index, new_index, changes } => {
+                    let fields = changes.iter().map(|c| c.field.as_str()).collect::<Vec<_>>().join(", ");
+                    if old_index == new_index {
+                        format!("~ {}. {} changed", new_index + 1, fields)
+                    } else {
+                        format!("~ {} -> {}. {} changed", old_index + 1, new_index + 1, fields)
+                    }
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+fn field_changes(old: &PlanStep, new: &PlanStep) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    let mut compare = |field: &str, old: String, new: String| {
+        if old != new {
+            changes.push(FieldChange { field: field.to_string(), old, new });
+        }
+    };
+    compare("title", old.title.clone(), new.title.clone());
+    compare("details", old.details.clone(), new.details.clone());
+    compare("agent_name", old.agent_name.clone(), new.agent_name.clone());
+    compare(
+        "expected_outcome",
+        old.expected_outcome.clone().unwrap_or_default(),
+        new.expected_outcome.clone().unwrap_or_default(),
+    );
+    compare("kind", format!("{:?}", old.kind), format!("{:?}", new.kind));
+    changes
+}
+
+/* 步骤级别的差异：先按步骤 id 配对（编辑过标题的步骤 id 不变），
+再按标题（归一化后）配对重规划时重新生成、id 不同但内容相同的步骤，其余为新增或删除。
+只移动了位置、内容不变的步骤不算变化 */
+pub fn diff(a: &Plan, b: &Plan) -> PlanDiff {
+    let mut matched_old: Vec<Option<usize>> = vec![None; b.steps.len()];
+    let mut used = vec![false; a.steps.len()];
+
+    for (j, new_step) in b.steps.iter().enumerate() {
+        if let Some(i) = (0..a.steps.len()).find(|&i| !used[i] && a.steps[i].id == new_step.id) {
+            used[i] = true;
+            matched_old[j] = Some(i);
+        }
+    }
+    for (j, new_step) in b.steps.iter().enumerate() {
+        if matched_old[j].is_some() {
+            continue;
+        }
+        let title = normalize_text(&new_step.title);
+        if let Some(i) = (0..a.steps.len()).find(|&i| !used[i] && normalize_text(&a.steps[i].title) == title) {
+            used[i] = true;
+            matched_old[j] = Some(i);
+        }
+    }
+
+    let mut changes: Vec<StepChange> = a
+        .steps
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !used[*i])
+        .map(|(index, step)| StepChange::Removed { index, step: step.clone() })
+        .collect();
+    for (new_index, new_step) in b.steps.iter().enumerate() {
+        match matched_old[new_index] {
+            Some(old_index) => {
+                let fields = field_changes(&a.steps[old_index], new_step);
+                if !fields.is_empty() {
+                    changes.push(StepChange::Modified { old_index, new_index, changes: fields });
+                }
+            }
+            None => changes.push(StepChange::Added { index: new_index, step: new_step.clone() }),
+        }
+    }
+    PlanDiff { changes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::plan::{new_step_id, StepKind};
+
+    fn step(title: &str, details: &str) -> PlanStep {
+        PlanStep {
+            id: new_step_id(),
+            title: title.to_string(),
+            details: details.to_string(),
+            agent_name: "web_surfer".to_string(),
+            expected_outcome: None,
+            kind: StepKind::Standard,
+        }
+    }
+
+    fn plan(steps: Vec<PlanStep>) -> Plan {
+        Plan { task: None, steps }
+    }
+
+    fn base() -> Plan {
+        plan(vec![step("Search", "Search for the restaurant"), step("Read", "Open the menu page")])
+    }
+
+    #[test]
+    fn test_diff_added_and_removed() {
+        let base = base();
+        let mut added = base.clone();
+        let summarize = step("Summarize", "Summarize the menu");
+        added.steps.push(summarize.clone());
+        let diff = diff(&base, &added);
+        assert_eq!(diff.changes, vec![StepChange::Added { index: 2, step: summarize }]);
+        assert_eq!(diff.summary(), "+ 3. Summarize");
+
+        // 重新生成的步骤 id 不同，按标题配对
+        let removed = plan(vec![step("Read", "Open the menu page")]);
+        let diff = super::diff(&base, &removed);
+        assert_eq!(diff.changes, vec![StepChange::Removed { index: 0, step: base.steps[0].clone() }]);
+        assert_eq!(diff.summary(), "- 1. Search");
+    }
+
+    #[test]
+    fn test_diff_modified_fields() {
+        let base = base();
+        let mut edited = base.clone();
+        edited.steps[0].details = "Search Bing for the restaurant".to_string();
+        edited.steps[1].title = "Read the menu".to_string();
+        edited.steps[1].agent_name = "coder_agent".to_string();
+        edited.steps.swap(0, 1);
+
+        let diff = diff(&base, &edited);
+        assert_eq!(diff.changes.len(), 2);
+        assert_eq!(
+            diff.changes[1],
+            StepChange::Modified {
+                old_index: 0,
+                new_index: 1,
+                changes: vec![FieldChange {
+                    field: "details".to_string(),
+                    old: "Search for the restaurant".to_string(),
+                    new: "Search Bing for the restaurant".to_string(),
+                }],
+            }
+        );
+        assert_eq!(diff.summary(), "~ 2 -> 1. title, agent_name changed\n~ 1 -> 2. details changed");
+
+        // 标题变了、id 也不同的步骤不再配对
+        let mut replaced = base.clone();
+        replaced.steps[1] = step("Read the menu", "Open the menu page");
+        assert_eq!(super::diff(&base, &replaced).summary(), "- 2. Read\n+ 2. Read the menu");
+    }
+
+    #[test]
+    fn test_diff_ignores_moves_and_identical_plans() {
+        let original = base();
+        assert!(diff(&original, &original).is_empty());
+        // 重新生成（id 不同）但内容相同的计划
+        assert_eq!(diff(&original, &base()).summary(), "No changes");
+
+        let mut inserted = original.clone();
+        inserted.steps.insert(0, step("Open Bing", "Open bing.com"));
+        let diff = diff(&original, &inserted);
+        assert_eq!(diff.summary(), "+ 1. Open Bing");
+    }
+}
\ No newline at end of file
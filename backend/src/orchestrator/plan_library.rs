@@ -0,0 +1,285 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::common::text::text_similarity;
+use crate::orchestrator::message::estimate_text_tokens;
+use crate::orchestrator::plan::Plan;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanOutcome {
+    Success,
+    Failure,
+}
+
+/// 一次运行使用的计划及其结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanLibraryEntry {
+    pub task: String,
+    pub plan: Plan,
+    pub outcome: PlanOutcome,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl PlanLibraryEntry {
+    pub fn new(task: String, plan: Plan, outcome: PlanOutcome) -> Self {
+        Self { task, plan, outcome, timestamp: Utc::now() }
+    }
+}
+
+/// 检索结果，similarity 越大越相似
+#[derive(Debug, Clone)]
+pub struct ScoredPlan {
+    pub entry: PlanLibraryEntry,
+    pub similarity: f64,
+}
+
+/// few-shot 计划示例的配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanExamplesConfig {
+    /// 规划前最多注入几个相似的成功计划，0 表示不注入
+    pub top_k: usize,
+    /// 注入内容的 token 上限
+    pub max_tokens: usize,
+    /// 相似度低于这个值的计划不注入
+    pub min_similarity: f64,
+    /// 运行结束后自动把计划和结果写入计划库
+    pub record_runs: bool,
+}
+
+impl Default for PlanExamplesConfig {
+    fn default() -> Self {
+        Self {
+            top_k: 2,
+            max_tokens: 800,
+            min_similarity: 0.3,
+            record_runs: true,
+        }
+    }
+}
+
+/* 保存历史计划的库：CLI 使用内存或文件实现，后端可以换成带向量检索的实现。
+find_similar 只返回成功的计划，按相似度从高到低排列 */
+#[async_trait]
+pub trait PlanLibrary: Send + Sync {
+    async fn add(&self, entry: PlanLibraryEntry) -> Result<()>;
+
+    async fn find_similar(&self, task: &str, k: usize) -> Result<Vec<ScoredPlan>>;
+}
+
+// 本地实现共用的检索：字符串相似度排序，失败的计划不参与
+fn rank_entries(entries: Vec<PlanLibraryEntry>, task: &str, k: usize) -> Vec<ScoredPlan> {
+    let mut scored: Vec<ScoredPlan> = entries
+        .into_iter()
+        .filter(|entry| entry.outcome == PlanOutcome::Success)
+        .map(|entry| ScoredPlan { similarity: text_similarity(&entry.task, task), entry })
+        .collect();
+    scored.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    scored.truncate(k);
+    scored
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryPlanLibrary {
+    entries: Mutex<Vec<PlanLibraryEntry>>,
+}
+
+impl InMemoryPlanLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> Vec<PlanLibraryEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl PlanLibrary for InMemoryPlanLibrary {
+    async fn add(&self, entry: PlanLibraryEntry) -> Result<()> {
+        self.entries.lock().unwrap().push(entry);
+        Ok(())
+    }
+
+    async fn find_similar(&self, task: &str, k: usize) -> Result<Vec<ScoredPlan>> {
+        Ok(rank_entries(self.entries(), task, k))
+    }
+}
+
+/// 每行一条 JSON 的文件计划库，损坏的行跳过并打印警告
+#[derive(Debug)]
+pub struct FilePlanLibrary {
+    path: PathBuf,
+}
+
+impl FilePlanLibrary {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn load(&self) -> Result<Vec<PlanLibraryEntry>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read plan library {}", self.path.display()))?;
+        let mut entries = Vec::new();
+        for (i, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => tracing::warn!("Skipping line {} of plan library {}: {}", i + 1, self.path.display(), e),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[async_trait]
+impl PlanLibrary for FilePlanLibrary {
+    async fn add(&self, entry: PlanLibraryEntry) -> Result<()> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open plan library {}", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        Ok(())
+    }
+
+    async fn find_similar(&self, task: &str, k: usize) -> Result<Vec<ScoredPlan>> {
+        Ok(rank_entries(self.load()?, task, k))
+    }
+}
+
+fn format_example(entry: &PlanLibraryEntry) -> String {
+    let steps = entry
+        .plan
+        .steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| format!("{}. {} ({}): {}", i + 1, step.title, step.agent_name, step.details))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("Task: {}\nPlan:\n{}", entry.task, steps)
+}
+
+/* 拼成放进规划提示词的示例段落：按顺序加入，加入下一个示例会超过 token 上限时停止；
+失败的计划即使传进来也不会出现。没有可用示例时返回 None */
+pub fn format_plan_examples(plans: &[ScoredPlan], max_tokens: usize) -> Option<String> {
+    let header = "Here are plans that worked for similar tasks. Use them as a reference, but adapt the plan to the current task:";
+    let mut text = header.to_string();
+    let mut added = 0;
+    for plan in plans.iter().filter(|p| p.entry.outcome == PlanOutcome::Success) {
+        let candidate = format!("{}\n\nExample {}:\n{}", text, added + 1, format_example(&plan.entry));
+        if estimate_text_tokens(&candidate) > max_tokens {
+            break;
+        }
+        text = candidate;
+        added += 1;
+    }
+    (added > 0).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::plan::{new_step_id, PlanStep, StepKind};
+
+    fn plan(details: &str) -> Plan {
+        Plan {
+            task: None,
+            steps: vec![PlanStep {
+                id: new_step_id(),
+                title: "Search".to_string(),
+                details: details.to_string(),
+                agent_name: "web_surfer".to_string(),
+                expected_outcome: None,
+                kind: StepKind::Standard,
+            }],
+        }
+    }
+
+    fn entry(task: &str, outcome: PlanOutcome) -> PlanLibraryEntry {
+        PlanLibraryEntry::new(task.to_string(), plan(&format!("Search for {}", task)), outcome)
+    }
+
+    #[tokio::test]
+    async fn test_find_similar_skips_failures() -> Result<()> {
+        let library = InMemoryPlanLibrary::new();
+        library.add(entry("Find the price of a train ticket to Paris", PlanOutcome::Failure)).await?;
+        library.add(entry("Find the price of a train ticket to Berlin", PlanOutcome::Success)).await?;
+        library.add(entry("Write a poem about autumn", PlanOutcome::Success)).await?;
+
+        let found = library.find_similar("Find the price of a train ticket to Rome", 5).await?;
+
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].entry.task, "Find the price of a train ticket to Berlin");
+        assert!(found[0].similarity > found[1].similarity);
+        assert!(found.iter().all(|p| p.entry.outcome == PlanOutcome::Success));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_file_library_round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("plans").join("library.jsonl");
+        let library = FilePlanLibrary::new(&path);
+        library.add(entry("Find the menu of a restaurant", PlanOutcome::Success)).await?;
+        library.add(entry("Find the menu of a cafe", PlanOutcome::Success)).await?;
+        // 损坏的行被跳过
+        std::fs::OpenOptions::new().append(true).open(&path)?.write_all(b"not json\n")?;
+
+        let found = FilePlanLibrary::new(&path).find_similar("Find the menu of a cafe", 1).await?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].entry.task, "Find the menu of a cafe");
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_plan_examples() {
+        let plans = vec![ScoredPlan { entry: entry("Find the menu", PlanOutcome::Success), similarity: 0.9 }];
+
+        let text = format_plan_examples(&plans, 800).unwrap();
+        assert!(text.starts_with("Here are plans that worked for similar tasks."));
+        assert!(text.contains("Example 1:\nTask: Find the menu\nPlan:\n1. Search (web_surfer): Search for Find the menu"));
+    }
+
+    #[test]
+    fn test_format_plan_examples_respects_token_cap() {
+        let plans: Vec<ScoredPlan> = (0..5)
+            .map(|i| ScoredPlan { entry: entry(&format!("Task number {}", i), PlanOutcome::Success), similarity: 0.9 })
+            .collect();
+
+        let one = format_plan_examples(&plans[..1], 800).unwrap();
+        let cap = estimate_text_tokens(&one) + 5;
+        let text = format_plan_examples(&plans, cap).unwrap();
+        assert!(estimate_text_tokens(&text) <= cap);
+        assert!(text.contains("Example 1:"));
+        assert!(!text.contains("Example 2:"));
+
+        // 一个示例都放不下时不注入
+        assert!(format_plan_examples(&plans, 10).is_none());
+    }
+
+    #[test]
+    fn test_format_plan_examples_never_includes_failures() {
+        let plans = vec![
+            ScoredPlan { entry: entry("Failed task", PlanOutcome::Failure), similarity: 1.0 },
+            ScoredPlan { entry: entry("Good task", PlanOutcome::Success), similarity: 0.5 },
+        ];
+        let text = format_plan_examples(&plans, 800).unwrap();
+        assert!(!text.contains("Failed task"));
+        assert!(text.contains("Example 1:\nTask: Good task"));
+
+        assert!(format_plan_examples(&plans[..1], 800).is_none());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,193 @@
+use crate::orchestrator::plan::PlanStep;
+
+/* 从流式输出中提前取出计划步骤：跟踪字符串和括号深度，找到顶层的 "steps" 数组，
+数组中的对象一闭合就解析成 PlanStep 返回，这样前面的步骤可以在后面的步骤生成时就展示出来。
+这里只做单个步骤的解析，完整的计划仍然在输出结束后整体校验；
+遇到无法理解的输出（括号不匹配、步骤解析失败）后不再返回任何步骤，退回到结束后整体解析 */
+#[derive(Debug, Default)]
+pub struct IncrementalPlanParser {
+    buffer: String,
+    // 下一个要处理的字节位置
+    pos: usize,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    string_start: usize,
+    // 顶层对象中最近一个字符串，用来识别 "steps" 键
+    last_key: Option<String>,
+    expect_steps_array: bool,
+    // steps 数组内部的深度
+    steps_depth: Option<usize>,
+    steps_done: bool,
+    step_start: Option<usize>,
+    emitted: usize,
+    failed: bool,
+}
+
+impl IncrementalPlanParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 已经返回的步骤数，也是下一个步骤的下标
+    pub fn emitted(&self) -> usize {
+        self.emitted
+    }
+
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// 追加一段输出，返回这段输出中新闭合的步骤
+    pub fn push(&mut self, chunk: &str) -> Vec<PlanStep> {
+        self.buffer.push_str(chunk);
+        let mut steps = Vec::new();
+        if self.failed || self.steps_done {
+            return steps;
+        }
+
+        let start = self.pos;
+        let end = self.buffer.len();
+        for (offset, c) in self.buffer[start..end].char_indices().collect::<Vec<_>>() {
+            let i = start + offset;
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if c == '\\' {
+                    self.escaped = true;
+                } else if c == '"' {
+                    self.in_string = false;
+                    if self.depth == 1 {
+                        self.last_key = Some(self.buffer[self.string_start + 1..i].to_string());
+                    }
+                }
+                continue;
+            }
+
+            if self.expect_steps_array && !c.is_whitespace() && c != '[' {
+                self.expect_steps_array = false;
+            }
+            match c {
+                '"' => {
+                    self.in_string = true;
+                    self.string_start = i;
+                }
+                ':' if self.depth == 1 && self.last_key.as_deref() == Some("steps") => {
+                    self.expect_steps_array = true;
+                }
+                ',' => self.last_key = None,
+                '[' | '{' => {
+                    self.depth += 1;
+                    if c == '[' && self.expect_steps_array {
+                        self.expect_steps_array = false;
+                        self.steps_depth = Some(self.depth);
+                    } else if c == '{' && self.steps_depth == Some(self.depth - 1) {
+                        self.step_start = Some(i);
+                    }
+                }
+                '}' | ']' => {
+                    if self.depth == 0 {
+                        self.failed = true;
+                        return steps;
+                    }
+                    if c == ']' && self.steps_depth == Some(self.depth) {
+                        self.steps_done = true;
+                        self.pos = end;
+                        return steps;
+                    }
+                    self.depth -= 1;
+                    if c == '}' && self.steps_depth == Some(self.depth) {
+                        if let Some(step_start) = self.step_start.take() {
+                            match serde_json::from_str::<PlanStep>(&self.buffer[step_start..=i]) {
+                                Ok(step) => {
+                                    steps.push(step);
+                                    self.emitted += 1;
+                                }
+                                Err(e) => {
+                                    tracing::debug!("Stop streaming plan steps: {}", e);
+                                    self.failed = true;
+                                    return steps;
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.pos = end;
+        steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fixture() -> String {
+        let plan = json!({
+            "response": "",
+            "task": "Compare prices",
+            "plan_summary": "Check three shops",
+            "needs_plan": true,
+            "steps": [
+                { "title": "Shop A", "details": "Open shop A {the \"main\" one}", "agent_name": "web_surfer" },
+                { "title": "Shop B", "details": "Open shop B [steps: 2]", "agent_name": "web_surfer" },
+                {
+                    "title": "Watch",
+                    "details": "Check daily",
+                    "agent_name": "web_surfer",
+                    "step_type": "SentinelPlanStep",
+                    "sleep_duration": 86400,
+                    "condition": 3
+                }
+            ]
+        });
+        format!("```json\n{}\n```", serde_json::to_string_pretty(&plan).unwrap())
+    }
+
+    fn stream(text: &str, chunk_chars: usize) -> (Vec<PlanStep>, IncrementalPlanParser) {
+        let chars: Vec<char> = text.chars().collect();
+        let mut parser = IncrementalPlanParser::new();
+        let mut steps = Vec::new();
+        for chunk in chars.chunks(chunk_chars) {
+            steps.extend(parser.push(&chunk.iter().collect::<String>()));
+        }
+        (steps, parser)
+    }
+
+    #[test]
+    fn test_steps_are_emitted_as_they_close() {
+        for chunk_chars in [1, 7, 64, 10_000] {
+            let (steps, parser) = stream(&fixture(), chunk_chars);
+            let titles: Vec<&str> = steps.iter().map(|s| s.title.as_str()).collect();
+            assert_eq!(titles, vec!["Shop A", "Shop B", "Watch"], "chunk size {}", chunk_chars);
+            assert_eq!(steps[0].details, "Open shop A {the \"main\" one}");
+            assert!(steps[2].kind.is_sentinel());
+            assert!(!parser.failed());
+        }
+    }
+
+    #[test]
+    fn test_step_is_emitted_before_the_plan_ends() {
+        let text = fixture();
+        let cut = text.find("Shop B").unwrap();
+        let mut parser = IncrementalPlanParser::new();
+
+        assert_eq!(parser.push(&text[..cut]).len(), 1);
+        assert_eq!(parser.emitted(), 1);
+        assert_eq!(parser.push(&text[cut..]).len(), 2);
+    }
+
+    #[test]
+    fn test_malformed_output_stops_streaming() {
+        let (steps, parser) = stream(r#"{"steps": [{"title": "A", "details": "a"}, {"title": "B"}]}"#, 5);
+        assert!(steps.is_empty());
+        assert!(parser.failed());
+
+        let (steps, parser) = stream(r#"}} {"steps": [{"title": "A", "details": "a", "agent_name": "x"}]}"#, 5);
+        assert!(steps.is_empty());
+        assert!(parser.failed());
+    }
+}
\ No newline at end of file
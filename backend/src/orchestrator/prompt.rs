@@ -0,0 +1,233 @@
+use crate::common::template::render_template;
+use crate::orchestrator::plan::PlanStep;
+
+/// 最终答案提示词中可用的占位符
+pub const FINAL_ANSWER_PLACEHOLDERS: &[&str] = &["task", "progress_summary", "plan"];
+
+pub const FINAL_ANSWER_PROMPT: &str = r#"
+Progress summary: {progress_summary}
+
+We are working on the following task:
+{task}
+The above messages contain the steps that took place to complete the task.
+Based on the information gathered, provide a final response to the user in response to the task.
+Make sure the user can easily verify your answer, include links if there are any.
+Please refer to steps of the plan that was used to complete the task. Use the steps as a way to help the user verify your answer.
+Make sure to also say whether the answer was found using online search or from your own knowledge.
+There is no need to be verbose, but make sure it contains enough information for the user.
+"#;
+
+/// 开启哨兵任务时追加到规划提示词中的步骤说明
+pub const SENTINEL_STEPS_PROMPT: &str = r#"
+            There are two types of steps: PlanStep and SentinelPlanStep.
+            A PlanStep is executed once. A SentinelPlanStep is used when a task requires repeated monitoring or waiting, for example "check the repository every hour until it has 100 stars".
+            Every step must have a step_type field set to either "PlanStep" or "SentinelPlanStep".
+            A SentinelPlanStep must also have:
+            - sleep_duration: the number of seconds to wait between two checks, a non-negative integer
+            - condition: either an integer (the number of times to repeat the check) or a string describing when the step is complete
+
+            Example of a SentinelPlanStep:
+
+            {
+                "title": "Watch the star count",
+                "details": "Check whether the repository gained 5 more stars. \n Every 10 minutes open the repository page and compare the star count with the recorded one.",
+                "agent_name": "web_surfer",
+                "step_type": "SentinelPlanStep",
+                "sleep_duration": 600,
+                "condition": "The repository has at least 5 more stars than before"
+            }
+"#;
+
+// 规划提示词中的示例计划，每一项是一个示例的正文
+pub const PLANNING_EXAMPLES: &[&str] = &[
+    r#"            User request: "Report back the menus of three restaurants near the zipcode 98052"
+
+            Step 1:
+            - title: "Locate the menu of the first restaurant"
+            - details: "Locate the menu of the first restaurant. \n Search for highly-rated restaurants in the 98052 area using Bing, select one with good reviews and an accessible menu, then extract and format the menu information for reporting."
+            - agent_name: "web_surfer"
+            - expected_outcome: "The name of the first restaurant and its menu with prices are listed"
+
+            Step 2:
+            - title: "Locate the menu of the second restaurant"
+            - details: "Locate the menu of the second restaurant. \n After excluding the first restaurant, search for another well-reviewed establishment in 98052, ensuring it has a different cuisine type for variety, then collect and format its menu information."
+            - agent_name: "web_surfer"
+            - expected_outcome: "The name of a second restaurant with a different cuisine and its menu with prices are listed"
+
+            Step 3:
+            - title: "Locate the menu of the third restaurant"
+            - details: "Locate the menu of the third restaurant. \n Building on the previous searches but excluding the first two restaurants, find a third establishment with a distinct cuisine type, verify its menu is available online, and compile the menu details."
+            - agent_name: "web_surfer"
+            - expected_outcome: "The name of a third restaurant with a different cuisine and its menu with prices are listed""#,
+    r#"            User request: "Execute the starter code for the autogen repo"
+
+            Step 1:
+            - title: "Locate the starter code for the autogen repo"
+            - details: "Locate the starter code for the autogen repo. \n Search for the official AutoGen repository on GitHub, navigate to their examples or getting started section, and identify the recommended starter code for new users."
+            - agent_name: "web_surfer"
+            - expected_outcome: "The link to the recommended starter code and its file contents are available"
+
+            Step 2:
+            - title: "Execute the starter code for the autogen repo"
+            - details: "Execute the starter code for the autogen repo. \n Set up the Python environment with the correct dependencies, ensure all required packages are installed at their specified versions, and run the starter code while capturing any output or errors."
+            - agent_name: "coder_agent"
+            - expected_outcome: "The output of running the starter code, or the errors it raised, is reported""#,
+    r#"            User request: "On which social media platform does Autogen have the most followers?"
+
+            Step 1:
+            - title: "Find all social media platforms that Autogen is on"
+            - details: "Find all social media platforms that Autogen is on. \n Search for AutoGen's official presence across major platforms like GitHub, Twitter, LinkedIn, and others, then compile a comprehensive list of their verified accounts."
+            - agent_name: "web_surfer"
+            - expected_outcome: "A list of the platforms with a verified AutoGen account and a link to each account"
+
+            Step 2:
+            - title: "Find the number of followers for each social media platform"
+            - details: "Find the number of followers for each social media platform. \n For each platform identified, visit AutoGen's official profile and record their current follower count, ensuring to note the date of collection for accuracy."
+            - agent_name: "web_surfer"
+            - expected_outcome: "The follower count of every listed platform and the date it was collected"
+
+            Step 3:
+            - title: "Find the number of followers for the remaining social media platform that Autogen is on"
+            - details: "Find the number of followers for the remaining social media platforms. \n Visit the remaining platforms and record their follower counts."
+            - agent_name: "web_surfer"
+            - expected_outcome: "The follower count of every remaining platform""#,
+    r#"            User request: "Can you paraphrase the following sentence: 'The quick brown fox jumps over the lazy dog'"
+
+            You should not provide a plan for this request. Instead, just answer the question directly."#,
+];
+
+pub const PLANNING_TIPS: &str = r#"            Helpful tips:
+            - If the plan needs information from the user, try to get that information before creating the plan.
+            - When creating the plan you only need to add a step to the plan if it requires a different agent to be completed, or if the step is very complicated and can be split into two steps.
+            - Remember, there is no requirement to involve all team members -- a team member's particular expertise may not be needed for this task.
+            - Aim for a plan with the least number of steps possible.
+            - Use a search engine or platform to find the information you need. For instance, if you want to look up flight prices, use a flight search engine like Bing Flights. However, your final answer should not stop with a Bing search only.
+            - If there are images attached to the request, use them to help you complete the task and describe them to the other agents in the plan. Refer to each image by its filename in the step details.
+"#;
+
+/* 规划提示词的示例和提示部分。只保留用到的代理都已注册的示例并重新编号，
+避免模型照着示例把步骤分配给团队中不存在的代理 */
+pub fn planning_examples_section(registered: &[String]) -> String {
+    let examples = PLANNING_EXAMPLES
+        .iter()
+        .filter(|example| {
+            example
+                .lines()
+                .filter_map(|line| line.trim().strip_prefix("- agent_name: \"")?.strip_suffix('"'))
+                .all(|agent| registered.iter().any(|name| name == agent))
+        })
+        .enumerate()
+        .map(|(i, example)| format!("\n            Example {}:\n\n{}\n", i + 1, example))
+        .collect::<String>();
+    format!("{}\n\n{}", examples, PLANNING_TIPS)
+}
+
+pub fn build_final_answer_prompt(template: &str, task: &str, progress_summary: &str, plan: &str) -> String {
+    render_template(
+        template,
+        &[("task", task), ("progress_summary", progress_summary), ("plan", plan)],
+    )
+}
+
+/// 要求模型使用用户的语言回答，JSON 的键和代理名保持英文，否则计划无法通过校验
+pub fn language_instruction(language: &str) -> String {
+    format!(
+        "Always respond to the user in {language}. Write the response, the plan summary and the title and details of every step in {language}, \
+but keep JSON keys, step_type values and agent_name values in English exactly as given."
+    )
+}
+
+pub fn format_completed_steps(steps: &[PlanStep]) -> String {
+    steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| {
+            let mut line = format!(
+                "COMPLETED STEP {}: title=\"{}\", details=\"{}\", agent=\"{}\"",
+                i + 1,
+                step.title,
+                step.details,
+                step.agent_name
+            );
+            if let Some(outcome) = &step.expected_outcome {
+                line.push_str(&format!(", expected_outcome=\"{}\"", outcome));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/* 重新规划时放在重规划提示词前面的上下文：为什么要重新规划，以及哪些步骤已经完成并会被保留，
+避免模型重复失败的做法或重新规划已经完成的部分 */
+pub fn build_replan_context(reason: Option<&str>, completed_steps: &[PlanStep]) -> String {
+    let mut content = match reason.map(str::trim).filter(|r| !r.is_empty()) {
+        Some(reason) => format!(
+            "We need to replan because: {}\nDo not repeat the approach that led to this failure.",
+            reason
+        ),
+        None => "We need to replan.".to_string(),
+    };
+    if !completed_steps.is_empty() {
+        content.push_str(&format!(
+            "\n\nThe following steps are already completed and will be kept, only plan the remaining work:\n{}",
+            format_completed_steps(completed_steps)
+        ));
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::plan::{new_step_id, StepKind};
+
+    #[test]
+    fn test_default_final_answer_prompt() {
+        let prompt = build_final_answer_prompt(FINAL_ANSWER_PROMPT, "Find a pasta recipe", "Found two recipes", "[]");
+        assert!(prompt.contains("Progress summary: Found two recipes"));
+        assert!(prompt.contains("Find a pasta recipe"));
+        assert!(!prompt.contains('{'));
+    }
+
+    #[test]
+    fn test_replan_context() {
+        let step = PlanStep {
+            id: new_step_id(),
+            title: "Open site A".to_string(),
+            details: "Open the booking site A".to_string(),
+            agent_name: "web_surfer".to_string(),
+            expected_outcome: None,
+            kind: StepKind::Standard,
+        };
+
+        let context = build_replan_context(Some("Site A is down"), std::slice::from_ref(&step));
+        assert!(context.starts_with("We need to replan because: Site A is down"));
+        assert!(context.contains(
+            "COMPLETED STEP 1: title=\"Open site A\", details=\"Open the booking site A\", agent=\"web_surfer\""
+        ));
+
+        // 已完成步骤的预期结果随上下文保留
+        let step = PlanStep { expected_outcome: Some("Site A is open".to_string()), ..step };
+        assert!(build_replan_context(None, std::slice::from_ref(&step)).contains(", expected_outcome=\"Site A is open\""));
+
+        assert_eq!(build_replan_context(Some("  "), &[]), "We need to replan.");
+        assert_eq!(build_replan_context(None, &[]), "We need to replan.");
+    }
+
+    #[test]
+    fn test_planning_examples_follow_the_team() {
+        let team = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        let full = planning_examples_section(&team(&["web_surfer", "coder_agent", "no_action_agent"]));
+        assert!(full.contains("Example 4:"));
+        assert!(full.contains("coder_agent"));
+
+        // 只有 web_surfer 时去掉用到 coder_agent 的示例，其余示例重新编号
+        let web_only = planning_examples_section(&team(&["web_surfer", "no_action_agent"]));
+        assert!(!web_only.contains("coder_agent"));
+        assert!(web_only.contains("Example 3:"));
+        assert!(!web_only.contains("Example 4:"));
+        assert!(web_only.contains("Helpful tips:"));
+    }
+}
\ No newline at end of file
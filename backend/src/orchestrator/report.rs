@@ -0,0 +1,244 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+
+use crate::common::template::render_template;
+use crate::database::StepOutcome;
+use crate::orchestrator::metrics::OrchestratorMetrics;
+use crate::orchestrator::plan::Plan;
+use crate::orchestrator::types::RunOutcome;
+
+const REPORT_TEMPLATE: &str = include_str!("report.html");
+
+/// 运行目录中的报告文件
+pub const REPORT_FILE: &str = "report.html";
+/// 重新生成报告所需的数据
+pub const REPORT_DATA_FILE: &str = "run.json";
+
+/* 生成 HTML 报告所需的运行数据：写到运行目录的 run.json 中，
+之后可以只凭运行目录重新生成报告。截图路径相对运行目录保存 */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunReport {
+    pub task: String,
+    pub final_answer: String,
+    pub plan: Option<Plan>,
+    #[serde(default)]
+    pub step_outcomes: Vec<StepOutcome>,
+    pub metrics: OrchestratorMetrics,
+    #[serde(default)]
+    pub screenshots: Vec<PathBuf>,
+}
+
+impl RunReport {
+    pub fn from_outcome(task: &str, outcome: &RunOutcome) -> Self {
+        Self {
+            task: task.to_string(),
+            final_answer: outcome.final_answer.clone(),
+            plan: outcome.plan.clone(),
+            step_outcomes: outcome.step_outcomes.clone(),
+            metrics: outcome.metrics.clone(),
+            screenshots: outcome
+                .artifacts
+                .iter()
+                .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// 写出 run.json 和 report.html，返回报告路径
+    pub fn write(&self, run_dir: impl AsRef<Path>) -> Result<PathBuf> {
+        let run_dir = run_dir.as_ref();
+        std::fs::create_dir_all(run_dir)
+            .with_context(|| format!("Failed to create run directory {}", run_dir.display()))?;
+        let mut data = self.clone();
+        data.screenshots = data
+            .screenshots
+            .iter()
+            .map(|path| path.strip_prefix(run_dir).map(Path::to_path_buf).unwrap_or_else(|_| path.clone()))
+            .collect();
+        std::fs::write(run_dir.join(REPORT_DATA_FILE), serde_json::to_vec_pretty(&data)?)
+            .with_context(|| format!("Failed to write {}", run_dir.join(REPORT_DATA_FILE).display()))?;
+
+        let path = run_dir.join(REPORT_FILE);
+        std::fs::write(&path, data.render(run_dir))
+            .with_context(|| format!("Failed to write report {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// 从运行目录中的 run.json 重新生成 report.html
+    pub fn regenerate(run_dir: impl AsRef<Path>) -> Result<PathBuf> {
+        let run_dir = run_dir.as_ref();
+        let data_path = run_dir.join(REPORT_DATA_FILE);
+        let content = std::fs::read_to_string(&data_path)
+            .with_context(|| format!("No run data found at {}", data_path.display()))?;
+        let report: RunReport = serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Run data {} is invalid: {}", data_path.display(), e))?;
+        report.write(run_dir)
+    }
+
+    /// 渲染自包含的 HTML，缩略图内嵌为 base64 并链接到原图；读不到的截图只保留链接
+    pub fn render(&self, run_dir: &Path) -> String {
+        render_template(REPORT_TEMPLATE, &[
+            ("title", &escape_html(&self.task)),
+            ("final_answer", &escape_html(&self.final_answer)),
+            ("steps", &self.render_steps()),
+            ("screenshots", &self.render_screenshots(run_dir)),
+            ("metrics", &render_metrics(&self.metrics)),
+        ])
+    }
+
+    fn render_steps(&self) -> String {
+        let Some(plan) = &self.plan else {
+            return "<p>No plan was needed for this request.</p>".to_string();
+        };
+        plan.steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| {
+                let outcome = self.step_outcomes.iter().find(|o| o.step_index == i);
+                let (class, status) = match outcome {
+                    Some(outcome) => ("completed", format!("<p><b>Result:</b> {}</p>", escape_html(&outcome.summary))),
+                    None => ("pending", "<p>Not completed</p>".to_string()),
+                };
+                format!(
+                    "<details class=\"{}\"><summary>{}. {} ({})</summary><p>{}</p>{}</details>",
+                    class,
+                    i + 1,
+                    escape_html(&step.title),
+                    escape_html(&step.agent_name),
+                    escape_html(&step.details),
+                    status
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn render_screenshots(&self, run_dir: &Path) -> String {
+        if self.screenshots.is_empty() {
+            return "<p>No screenshots were saved.</p>".to_string();
+        }
+        self.screenshots
+            .iter()
+            .map(|path| {
+                let href = escape_html(&path.to_string_lossy());
+                let full_path = if path.is_absolute() { path.clone() } else { run_dir.join(path) };
+                match std::fs::read(&full_path) {
+                    Ok(bytes) => format!(
+                        "<a href=\"{}\"><img src=\"data:image/png;base64,{}\" alt=\"{}\"></a>",
+                        href,
+                        STANDARD.encode(bytes),
+                        href
+                    ),
+                    Err(_) => format!("<a href=\"{}\">{}</a>", href, href),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+// 把 metrics 的 markdown 表格转成 HTML 表格
+fn render_metrics(metrics: &OrchestratorMetrics) -> String {
+    let rows: Vec<String> = metrics
+        .to_table()
+        .lines()
+        .skip(2)
+        .map(|line| {
+            let cells: Vec<String> = line
+                .trim()
+                .trim_matches('|')
+                .split('|')
+                .map(|cell| format!("<td>{}</td>", escape_html(cell.trim())))
+                .collect();
+            format!("<tr>{}</tr>", cells.join(""))
+        })
+        .collect();
+    format!("<table>\n{}\n</table>", rows.join("\n"))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::plan::{new_step_id, PlanStep, StepKind};
+
+    fn step(title: &str) -> PlanStep {
+        PlanStep {
+            id: new_step_id(),
+            title: title.to_string(),
+            details: format!("{} details", title),
+            agent_name: "web_surfer".to_string(),
+            expected_outcome: None,
+            kind: StepKind::Standard,
+        }
+    }
+
+    fn outcome(run_dir: &Path) -> Result<RunOutcome> {
+        let screenshot = run_dir.join("screenshots/step1_1.png");
+        std::fs::create_dir_all(run_dir.join("screenshots"))?;
+        std::fs::write(&screenshot, include_bytes!("testdata/receipt.png"))?;
+        let mut metrics = OrchestratorMetrics::new();
+        metrics.rounds = 3;
+        metrics.cost = 0.0125;
+        Ok(RunOutcome {
+            final_answer: "The menu has <three> dishes.".to_string(),
+            plan: Some(Plan { task: Some("Find the menu".to_string()), steps: vec![step("Search"), step("Read")] }),
+            metrics,
+            artifacts: vec![screenshot],
+            step_outcomes: vec![StepOutcome {
+                step_index: 0,
+                step_id: String::new(),
+                title: "Search".to_string(),
+                summary: "Found the restaurant".to_string(),
+            }],
+            information_collected: String::new(),
+            cost_summary: None,
+        })
+    }
+
+    #[test]
+    fn test_report_sections() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let report = RunReport::from_outcome("Find the menu", &outcome(dir.path())?);
+        let path = report.write(dir.path())?;
+        assert_eq!(path, dir.path().join("report.html"));
+
+        let html = std::fs::read_to_string(&path)?;
+        assert!(html.contains("<title>Run report: Find the menu</title>"));
+        assert!(html.contains("The menu has &lt;three&gt; dishes."));
+        assert!(html.contains("<details class=\"completed\"><summary>1. Search (web_surfer)</summary>"));
+        assert!(html.contains("<b>Result:</b> Found the restaurant"));
+        assert!(html.contains("<details class=\"pending\"><summary>2. Read (web_surfer)</summary>"));
+        assert!(html.contains("<a href=\"screenshots/step1_1.png\"><img src=\"data:image/png;base64,"));
+        assert!(html.contains("<tr><td>Rounds</td><td>3</td></tr>"));
+        assert!(html.contains("<td>$0.0125</td>"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_regenerate_from_run_dir() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        RunReport::from_outcome("Find the menu", &outcome(dir.path())?).write(dir.path())?;
+        std::fs::remove_file(dir.path().join(REPORT_FILE))?;
+
+        let path = RunReport::regenerate(dir.path())?;
+        let html = std::fs::read_to_string(path)?;
+        assert!(html.contains("data:image/png;base64,"));
+
+        let empty = tempfile::tempdir()?;
+        assert!(RunReport::regenerate(empty.path()).unwrap_err().to_string().contains("No run data found"));
+        Ok(())
+    }
+}
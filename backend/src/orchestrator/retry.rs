@@ -0,0 +1,304 @@
+use futures::lock::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::agents::Agent;
+use crate::orchestrator::config::RetryPolicy;
+use crate::orchestrator::message::{ChatMessage, Message, MessageRole, MultiModalContent};
+
+/// 一次带重试的分发结果
+#[derive(Debug, Clone)]
+pub struct DispatchOutcome {
+    /// 最终交给 ledger 的响应；重试耗尽时是一条描述失败的观察
+    pub response: ChatMessage,
+    /// 每次重试前写入历史的注释，例如 "Retry 2/3 after error: ..."
+    pub retry_notes: Vec<ChatMessage>,
+    pub attempts: usize,
+    pub exhausted: bool,
+    /// 用户中途停止，代理的步骤被取消
+    pub cancelled: bool,
+}
+
+// on_message_stream 返回 Err，或者响应带有 error 标记，都算失败
+fn classify(result: anyhow::Result<ChatMessage>) -> Result<ChatMessage, (String, Option<Box<ChatMessage>>)> {
+    match result {
+        Ok(message) => match message.error() {
+            Some(error) => Err((error.to_string(), Some(Box::new(message.clone())))),
+            None => Ok(message),
+        },
+        Err(e) => Err((format!("{:#}", e), None)),
+    }
+}
+
+fn cancelled_outcome(message: &Message, retry_notes: Vec<ChatMessage>, attempts: usize) -> DispatchOutcome {
+    DispatchOutcome {
+        response: ChatMessage::new_text(
+            MessageRole::Assistant,
+            message.to.clone(),
+            format!("{} was stopped at the user's request.", message.to),
+        ),
+        retry_notes,
+        attempts,
+        exhausted: false,
+        cancelled: true,
+    }
+}
+
+/* 把同一条指令分发给同一个代理，失败时按策略退避重试。
+重试耗尽后不返回错误，而是转成一条普通的观察，让 ledger 自己决定是否重规划。
+cancel 被触发时立即放弃正在执行的步骤（丢弃代理的 future） */
+pub async fn dispatch_with_retry(
+    agent: &Mutex<Box<dyn Agent>>,
+    message: Message,
+    policy: &RetryPolicy,
+    orchestrator_name: &str,
+    cancel: &CancellationToken,
+) -> DispatchOutcome {
+    let max_attempts = policy.max_attempts.max(1);
+    let mut retry_notes = Vec::new();
+    let mut last_error = String::new();
+    let mut last_response = None;
+
+    for attempt in 1..=max_attempts {
+        let step = async {
+            let mut agent = agent.lock().await;
+            let run = agent.on_message_stream(message.clone());
+            match policy.step_timeout() {
+                // 超时后丢弃代理的 future，和其他失败一样进入重试
+                Some(timeout) => match tokio::time::timeout(timeout, run).await {
+                    Ok(result) => result,
+                    Err(_) => Err(anyhow::anyhow!("Step timed out after {}ms", timeout.as_millis())),
+                },
+                None => run.await,
+            }
+        };
+        let result = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return cancelled_outcome(&message, retry_notes, attempt),
+            result = step => result,
+        };
+
+        match classify(result) {
+            Ok(response) => {
+                return DispatchOutcome {
+                    response,
+                    retry_notes,
+                    attempts: attempt,
+                    exhausted: false,
+                    cancelled: false,
+                };
+            }
+            Err((error, response)) => {
+                last_error = error;
+                last_response = response.map(|response| *response);
+            }
+        }
+
+        if attempt < max_attempts {
+            retry_notes.push(ChatMessage::new_text(
+                MessageRole::User,
+                orchestrator_name.to_string(),
+                format!("Retry {}/{} after error: {}", attempt + 1, max_attempts, last_error),
+            ));
+            tokio::select! {
+                biased;
+                _ = cancel.cancelled() => return cancelled_outcome(&message, retry_notes, attempt),
+                _ = tokio::time::sleep(policy.backoff(attempt)) => {}
+            }
+        }
+    }
+
+    // 保留代理自己的失败总结（如果有），否则生成一条失败观察
+    let summary = format!(
+        "{} failed after {} attempt(s). Last error: {}",
+        message.to, max_attempts, last_error
+    );
+    let response = match last_response {
+        Some(ChatMessage::Text { role, source, content, .. }) => ChatMessage::new_text(
+            role,
+            source,
+            format!("{}\n\n{}", summary, content),
+        ),
+        Some(ChatMessage::MultiModal { role, source, mut content, .. }) => {
+            content.insert(0, MultiModalContent::Text(summary));
+            ChatMessage::new_multimodal(role, source, content)
+        }
+        None => ChatMessage::new_text(MessageRole::Assistant, message.to.clone(), summary),
+    };
+
+    DispatchOutcome {
+        response,
+        retry_notes,
+        attempts: max_attempts,
+        exhausted: true,
+        cancelled: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::message::MessageType;
+    use anyhow::{anyhow, Result};
+    use async_trait::async_trait;
+
+    // 前 n 次失败（一次返回 Err，一次返回带 error 标记的消息），之后成功
+    struct FlakyAgent {
+        failures_left: usize,
+        calls: usize,
+    }
+
+    #[async_trait]
+    impl Agent for FlakyAgent {
+        fn name(&self) -> &str {
+            "web_surfer"
+        }
+
+        async fn on_message_stream(&mut self, _message: Message) -> Result<ChatMessage> {
+            self.calls += 1;
+            if self.failures_left > 0 {
+                self.failures_left -= 1;
+                if self.calls % 2 == 1 {
+                    return Err(anyhow!("browser crashed"));
+                }
+                return Ok(ChatMessage::new_text(
+                    MessageRole::Assistant,
+                    "web_surfer".to_string(),
+                    "Could not click the button".to_string(),
+                ).with_error("click timed out"));
+            }
+            Ok(ChatMessage::new_text(
+                MessageRole::Assistant,
+                "web_surfer".to_string(),
+                "Clicked the button".to_string(),
+            ))
+        }
+    }
+
+    fn execute_message() -> Message {
+        Message {
+            from: "orchestrator".to_string(),
+            to: "web_surfer".to_string(),
+            chat_history: vec![],
+            msg_type: MessageType::Execute,
+        }
+    }
+
+    fn fast_policy(max_attempts: usize) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff_ms: 1,
+            backoff_multiplier: 1.0,
+            max_backoff_ms: 1,
+            step_timeout_ms: None,
+        }
+    }
+
+    fn text(message: &ChatMessage) -> String {
+        match message {
+            ChatMessage::Text { content, .. } => content.clone(),
+            _ => panic!("expected text message"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fails_twice_then_succeeds() {
+        let agent: Mutex<Box<dyn Agent>> = Mutex::new(Box::new(FlakyAgent { failures_left: 2, calls: 0 }));
+        let outcome = dispatch_with_retry(&agent, execute_message(), &fast_policy(3), "orchestrator", &CancellationToken::new()).await;
+
+        assert!(!outcome.exhausted);
+        assert_eq!(outcome.attempts, 3);
+        assert_eq!(text(&outcome.response), "Clicked the button");
+        assert_eq!(outcome.retry_notes.len(), 2);
+        assert_eq!(text(&outcome.retry_notes[0]), "Retry 2/3 after error: browser crashed");
+        assert_eq!(text(&outcome.retry_notes[1]), "Retry 3/3 after error: click timed out");
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_retries_become_observation() {
+        let agent: Mutex<Box<dyn Agent>> = Mutex::new(Box::new(FlakyAgent { failures_left: 2, calls: 0 }));
+        let outcome = dispatch_with_retry(&agent, execute_message(), &fast_policy(2), "orchestrator", &CancellationToken::new()).await;
+
+        assert!(outcome.exhausted);
+        assert_eq!(outcome.retry_notes.len(), 1);
+        let content = text(&outcome.response);
+        assert!(content.starts_with("web_surfer failed after 2 attempt(s). Last error: click timed out"));
+        assert!(content.contains("Could not click the button"));
+        // 转换后的观察不再带 error 标记
+        assert!(outcome.response.error().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_policy() {
+        let agent: Mutex<Box<dyn Agent>> = Mutex::new(Box::new(FlakyAgent { failures_left: 1, calls: 0 }));
+        let outcome = dispatch_with_retry(&agent, execute_message(), &RetryPolicy::no_retry(), "orchestrator", &CancellationToken::new()).await;
+
+        assert!(outcome.exhausted);
+        assert!(outcome.retry_notes.is_empty());
+        assert_eq!(text(&outcome.response), "web_surfer failed after 1 attempt(s). Last error: browser crashed");
+    }
+
+    // 执行很久的步骤，被丢弃时记录下来
+    struct SlowAgent {
+        dropped: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    struct DropFlag(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait]
+    impl Agent for SlowAgent {
+        fn name(&self) -> &str {
+            "web_surfer"
+        }
+
+        async fn on_message_stream(&mut self, _message: Message) -> Result<ChatMessage> {
+            let _flag = DropFlag(self.dropped.clone());
+            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+            Ok(ChatMessage::new_text(MessageRole::Assistant, "web_surfer".to_string(), "finished".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stop_cancels_long_step() {
+        let dropped = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let agent: Mutex<Box<dyn Agent>> = Mutex::new(Box::new(SlowAgent { dropped: dropped.clone() }));
+        let queue = crate::orchestrator::types::UserMessageQueue::new();
+        let token = queue.cancellation_token();
+
+        let handle = queue.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            handle.push(ChatMessage::new_text(MessageRole::User, "user".to_string(), "stop".to_string()));
+        });
+
+        let outcome = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            dispatch_with_retry(&agent, execute_message(), &fast_policy(3), "orchestrator", &token),
+        ).await.expect("step should be cancelled quickly");
+
+        assert!(outcome.cancelled);
+        assert!(dropped.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(text(&outcome.response), "web_surfer was stopped at the user's request.");
+        // 锁已经释放
+        assert!(agent.try_lock().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_step_timeout_counts_as_failure() {
+        let dropped = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let agent: Mutex<Box<dyn Agent>> = Mutex::new(Box::new(SlowAgent { dropped: dropped.clone() }));
+        let policy = RetryPolicy { step_timeout_ms: Some(20), ..fast_policy(2) };
+
+        let outcome = dispatch_with_retry(&agent, execute_message(), &policy, "orchestrator", &CancellationToken::new()).await;
+
+        assert!(outcome.exhausted);
+        assert!(dropped.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(text(&outcome.retry_notes[0]), "Retry 2/2 after error: Step timed out after 20ms");
+        assert_eq!(text(&outcome.response), "web_surfer failed after 2 attempt(s). Last error: Step timed out after 20ms");
+    }
+}
\ No newline at end of file
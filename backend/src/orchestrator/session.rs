@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::database::StepOutcome;
+use crate::orchestrator::message::{ChatMessage, MessageRole};
+use crate::orchestrator::metrics::OrchestratorMetrics;
+use crate::orchestrator::plan::Plan;
+use crate::orchestrator::plan_history::PlanVersion;
+use crate::orchestrator::types::OrchestratorState;
+
+/// 检查点文件格式的版本，字段不兼容地变化时加一
+pub const SESSION_FORMAT_VERSION: u32 = 1;
+
+/// 会话目录中的检查点文件名
+pub const CHECKPOINT_FILE: &str = "checkpoint.json";
+
+/// 写检查点的时机
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckpointReason {
+    PlanAccepted,
+    StepFinished,
+    /// 运行出错时在内存中保存，用于跳过出错的步骤后继续
+    StepFailed,
+}
+
+impl CheckpointReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CheckpointReason::PlanAccepted => "plan_accepted",
+            CheckpointReason::StepFinished => "step_finished",
+            CheckpointReason::StepFailed => "step_failed",
+        }
+    }
+}
+
+/* 会话检查点：恢复执行所需的 OrchestratorState 字段和计划版本历史。
+代理本身不保存，恢复时由调用方重新注册同名的代理，再把 agent_states 中保存的状态交还给它们 */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionCheckpoint {
+    pub version: u32,
+    pub saved_at: DateTime<Utc>,
+    pub reason: CheckpointReason,
+    pub task: String,
+    pub plan: Plan,
+    pub current_step_idx: usize,
+    pub n_rounds: usize,
+    pub n_replans: usize,
+    pub information_collected: String,
+    pub message_history: Vec<ChatMessage>,
+    pub last_approved_step: Option<usize>,
+    pub step_outcomes: Vec<StepOutcome>,
+    pub plan_history: Vec<PlanVersion>,
+    pub metrics: OrchestratorMetrics,
+    /// 下一轮是否需要重新向团队广播计划（计划刚被接受时还没有广播过）
+    pub announce_plan: bool,
+    /// 按代理名保存的代理状态（如浏览器的 cookies 和当前页面），旧的检查点中没有
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub agent_states: HashMap<String, serde_json::Value>,
+}
+
+impl SessionCheckpoint {
+    /// 没有计划（不需要计划或还在规划）时没有可以恢复的内容
+    pub fn capture(
+        state: &OrchestratorState,
+        metrics: &OrchestratorMetrics,
+        reason: CheckpointReason,
+        announce_plan: bool,
+    ) -> Option<Self> {
+        let plan = state.plan.clone()?;
+        Some(Self {
+            version: SESSION_FORMAT_VERSION,
+            saved_at: Utc::now(),
+            reason,
+            task: state.task.clone(),
+            plan,
+            current_step_idx: state.current_step_idx,
+            n_rounds: state.n_rounds,
+            n_replans: state.n_replans,
+            information_collected: state.information_collected.clone(),
+            message_history: state.message_history.clone(),
+            last_approved_step: state.last_approved_step,
+            step_outcomes: state.step_outcomes.clone(),
+            plan_history: state.plan_history.clone(),
+            metrics: metrics.clone(),
+            announce_plan,
+            agent_states: HashMap::new(),
+        })
+    }
+
+    // 在全新的状态上恢复，恢复之后处于执行阶段
+    pub fn restore(&self, state: &mut OrchestratorState) -> Result<()> {
+        state.reset();
+        state.task = self.task.clone();
+        state.plan_str = serde_json::to_string(&self.plan)?;
+        state.plan = Some(self.plan.clone());
+        state.current_step_idx = self.current_step_idx;
+        state.n_rounds = self.n_rounds;
+        state.n_replans = self.n_replans;
+        state.information_collected = self.information_collected.clone();
+        state.message_history = self.message_history.clone();
+        state.last_approved_step = self.last_approved_step;
+        state.step_outcomes = self.step_outcomes.clone();
+        state.plan_history = self.plan_history.clone();
+        state.in_planning_mode = false;
+        state.sync_current_step_id();
+        Ok(())
+    }
+
+    pub fn path(dir: impl AsRef<Path>) -> PathBuf {
+        dir.as_ref().join(CHECKPOINT_FILE)
+    }
+
+    // 先写临时文件再改名，中途退出不会留下写了一半的检查点
+    pub fn save(&self, dir: impl AsRef<Path>) -> Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create session directory {}", dir.display()))?;
+        let path = Self::path(dir);
+        let tmp = dir.join(format!("{}.tmp", CHECKPOINT_FILE));
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write session checkpoint {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path)
+            .with_context(|| format!("Failed to write session checkpoint {}", path.display()))?;
+        Ok(())
+    }
+
+    /// 读取会话目录中的检查点，文件缺失、损坏或版本不兼容时给出明确的错误
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self> {
+        let path = Self::path(dir);
+        let data = std::fs::read(&path)
+            .with_context(|| format!("No session checkpoint found at {}", path.display()))?;
+        let value: serde_json::Value = serde_json::from_slice(&data)
+            .map_err(|e| anyhow!("The session checkpoint {} is corrupt: {}", path.display(), e))?;
+        Self::from_value(value, &path.display().to_string())
+    }
+
+    /// 从 JSON 解析检查点（数据库中保存的也是同样的格式），origin 用于错误信息
+    pub fn from_value(value: serde_json::Value, origin: &str) -> Result<Self> {
+        let version = value
+            .get("version")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| anyhow!("The session checkpoint {} is corrupt: missing version", origin))?;
+        if version != SESSION_FORMAT_VERSION as u64 {
+            return Err(anyhow!(
+                "The session checkpoint {} has version {}, but this build only supports version {}",
+                origin,
+                version,
+                SESSION_FORMAT_VERSION
+            ));
+        }
+        serde_json::from_value(value)
+            .map_err(|e| anyhow!("The session checkpoint {} is corrupt: {}", origin, e))
+    }
+
+    /// 剩余步骤需要、但没有注册的代理
+    pub fn missing_agents(&self, registered: &[String]) -> Vec<String> {
+        let mut missing: Vec<String> = Vec::new();
+        for step in self.plan.steps.iter().skip(self.current_step_idx) {
+            if !registered.contains(&step.agent_name) && !missing.contains(&step.agent_name) {
+                missing.push(step.agent_name.clone());
+            }
+        }
+        missing
+    }
+
+    pub fn remaining_steps(&self) -> usize {
+        self.plan.steps.len().saturating_sub(self.current_step_idx)
+    }
+
+    /// 跳过当前步骤，恢复后从下一步继续；跳过记入历史，ledger 评估时能看到。没有剩余步骤时返回 false
+    pub fn skip_current_step(&mut self) -> bool {
+        let Some(step) = self.plan.steps.get(self.current_step_idx) else {
+            return false;
+        };
+        self.message_history.push(ChatMessage::new_text(
+            MessageRole::User,
+            "user".to_string(),
+            format!("Step {} ({}) failed and was skipped; continue with the next step.", self.current_step_idx + 1, step.title),
+        ));
+        self.current_step_idx += 1;
+        true
+    }
+
+    /// 恢复前给用户确认的摘要：任务、已完成的步骤和接下来的步骤
+    pub fn summary(&self) -> String {
+        let mut lines = vec![
+            format!("Task: {}", self.task),
+            format!("Saved at {} (plan v{}).", self.saved_at.format("%Y-%m-%d %H:%M:%S UTC"), self.plan_history.len()),
+        ];
+        if self.step_outcomes.is_empty() {
+            lines.push("No plan steps were completed.".to_string());
+        } else {
+            lines.push("Completed steps:".to_string());
+            for outcome in &self.step_outcomes {
+                lines.push(format!("- Step {} ({}): {}", outcome.step_index + 1, outcome.title, outcome.summary));
+            }
+        }
+        let remaining: Vec<String> = self.plan.steps
+            .iter()
+            .enumerate()
+            .skip(self.current_step_idx)
+            .map(|(i, step)| format!("{}. {} ({})", i + 1, step.label(), step.agent_name))
+            .collect();
+        if remaining.is_empty() {
+            lines.push("All plan steps are completed.".to_string());
+        } else {
+            lines.push("Remaining steps:".to_string());
+            lines.extend(remaining);
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::message::MessageRole;
+    use crate::orchestrator::plan::{new_step_id, PlanStep, StepKind};
+    use crate::orchestrator::plan_history::PlanSource;
+
+    fn state() -> OrchestratorState {
+        let step = |title: &str| PlanStep {
+            id: new_step_id(),
+            title: title.to_string(),
+            details: format!("{} details", title),
+            agent_name: "web_surfer".to_string(),
+            expected_outcome: None,
+            kind: StepKind::Standard,
+        };
+        let plan = Plan { task: Some("Find the menu".to_string()), steps: vec![step("Search"), step("Read")] };
+        let mut state = OrchestratorState { task: "Find the menu".to_string(), ..OrchestratorState::default() };
+        state.plan_history.push(PlanVersion::new(1, plan.clone(), PlanSource::Generated, None));
+        state.plan = Some(plan);
+        state.current_step_idx = 1;
+        state.step_outcomes.push(StepOutcome {
+            step_index: 0,
+            step_id: String::new(),
+            title: "Search".to_string(),
+            summary: "Found the restaurant".to_string(),
+        });
+        state.message_history.push(ChatMessage::new_text(MessageRole::User, "user".to_string(), "Find the menu".to_string()));
+        state
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let checkpoint = SessionCheckpoint::capture(&state(), &OrchestratorMetrics::new(), CheckpointReason::StepFinished, false).unwrap();
+        checkpoint.save(dir.path())?;
+
+        let loaded = SessionCheckpoint::load(dir.path())?;
+        assert_eq!(loaded, checkpoint);
+        assert_eq!(loaded.remaining_steps(), 1);
+
+        let mut restored = OrchestratorState::default();
+        loaded.restore(&mut restored)?;
+        assert_eq!(restored.current_step_idx, 1);
+        assert_eq!(restored.current_step_id.as_deref(), Some(checkpoint.plan.steps[1].id.as_str()));
+        assert!(!restored.in_planning_mode);
+        assert_eq!(restored.plan_history.len(), 1);
+
+        let summary = loaded.summary();
+        assert!(summary.contains("- Step 1 (Search): Found the restaurant"));
+        assert!(summary.contains("] Read (web_surfer)"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_and_incompatible_checkpoints() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let error = SessionCheckpoint::load(dir.path()).unwrap_err();
+        assert!(error.to_string().contains("No session checkpoint found"));
+
+        std::fs::write(SessionCheckpoint::path(dir.path()), "{\"version\": 1, \"task\": ")?;
+        let error = SessionCheckpoint::load(dir.path()).unwrap_err();
+        assert!(error.to_string().contains("is corrupt"));
+
+        let mut value = serde_json::to_value(
+            SessionCheckpoint::capture(&state(), &OrchestratorMetrics::new(), CheckpointReason::PlanAccepted, true).unwrap(),
+        )?;
+        value["version"] = serde_json::json!(SESSION_FORMAT_VERSION + 1);
+        std::fs::write(SessionCheckpoint::path(dir.path()), serde_json::to_vec(&value)?)?;
+        let error = SessionCheckpoint::load(dir.path()).unwrap_err();
+        assert!(error.to_string().contains("only supports version 1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_the_current_step() {
+        let mut checkpoint = SessionCheckpoint::capture(&state(), &OrchestratorMetrics::new(), CheckpointReason::StepFailed, false).unwrap();
+        assert!(checkpoint.skip_current_step());
+        assert_eq!((checkpoint.current_step_idx, checkpoint.remaining_steps()), (2, 0));
+        let note = checkpoint.message_history.last().unwrap();
+        assert!(matches!(note, ChatMessage::Text { content, .. } if content.starts_with("Step 2 (Read) failed and was skipped")));
+        // 最后一步之后没有可以跳过的步骤
+        assert!(!checkpoint.skip_current_step());
+        assert_eq!(checkpoint.message_history.len(), 2);
+    }
+
+    #[test]
+    fn test_no_checkpoint_without_plan() {
+        let state = OrchestratorState::default();
+        assert!(SessionCheckpoint::capture(&state, &OrchestratorMetrics::new(), CheckpointReason::PlanAccepted, true).is_none());
+    }
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+
+use crate::common::text::text_similarity;
+
+/// 停滞检测配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StallDetectionConfig {
+    /// 同一步骤连续多少轮给出几乎相同的指令视为停滞，0 表示不检测
+    pub max_rounds_without_progress: usize,
+    /// 两条指令归一化后的相似度达到这个值视为重复
+    pub similarity_threshold: f64,
+}
+
+impl Default for StallDetectionConfig {
+    fn default() -> Self {
+        Self {
+            max_rounds_without_progress: 3,
+            similarity_threshold: 0.9,
+        }
+    }
+}
+
+/* ledger 反复判定“步骤未完成”并给出同样的指令时，轮次会被白白消耗而不会触发重规划。
+每轮 ledger 评估后调用 observe：步骤下标没有前进、指令与上一轮几乎相同（或者代理报告页面没有变化）
+时累加计数，达到上限时返回 true */
+#[derive(Debug, Default)]
+pub struct StallDetector {
+    step_index: Option<usize>,
+    last_instruction: String,
+    rounds: usize,
+    // 上一轮没有进展：代理报告页面没有变化，或者指令没有分发出去
+    no_progress: bool,
+}
+
+impl StallDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 代理最近一次响应是否报告页面没有变化
+    pub fn record_page_unchanged(&mut self, unchanged: bool) {
+        self.no_progress = unchanged;
+    }
+
+    // ledger 指定的代理没有注册、指令没有分发时，下一轮同样视为没有进展
+    pub fn record_not_dispatched(&mut self) {
+        self.no_progress = true;
+    }
+
+    pub fn observe(&mut self, step_index: usize, instruction: &str, config: &StallDetectionConfig) -> bool {
+        let repeated = self.step_index == Some(step_index)
+            && (self.no_progress
+                || text_similarity(&self.last_instruction, instruction) >= config.similarity_threshold);
+
+        self.rounds = if repeated { self.rounds + 1 } else { 1 };
+        self.step_index = Some(step_index);
+        self.last_instruction = instruction.to_string();
+        self.no_progress = false;
+
+        config.max_rounds_without_progress > 0 && self.rounds >= config.max_rounds_without_progress
+    }
+
+    pub fn rounds(&self) -> usize {
+        self.rounds
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_repeated_instructions_at_threshold() {
+        let config = StallDetectionConfig::default();
+        let mut detector = StallDetector::new();
+
+        assert!(!detector.observe(0, "Click the search button", &config));
+        assert!(!detector.observe(0, "Click the search button.", &config));
+        assert!(detector.observe(0, "click the Search button", &config));
+        assert_eq!(detector.rounds(), 3);
+    }
+
+    #[test]
+    fn test_progress_or_new_instruction_resets() {
+        let config = StallDetectionConfig::default();
+        let mut detector = StallDetector::new();
+
+        detector.observe(0, "Click the search button", &config);
+        detector.observe(0, "Click the search button", &config);
+        // 步骤前进
+        assert!(!detector.observe(1, "Click the search button", &config));
+        assert_eq!(detector.rounds(), 1);
+        // 指令明显不同
+        detector.observe(1, "Click the search button", &config);
+        assert!(!detector.observe(1, "Open the settings page and enable dark mode", &config));
+        assert_eq!(detector.rounds(), 1);
+    }
+
+    #[test]
+    fn test_page_unchanged_counts_as_no_progress() {
+        let config = StallDetectionConfig::default();
+        let mut detector = StallDetector::new();
+
+        detector.observe(0, "Scroll down to find the price", &config);
+        detector.record_page_unchanged(true);
+        detector.observe(0, "Look for the price in the sidebar", &config);
+        detector.record_page_unchanged(true);
+        assert!(detector.observe(0, "Check the footer for the price", &config));
+    }
+
+    #[test]
+    fn test_undispatched_instruction_counts_as_no_progress() {
+        let config = StallDetectionConfig::default();
+        let mut detector = StallDetector::new();
+
+        detector.observe(0, "Ask the travel_agent for flights", &config);
+        detector.record_not_dispatched();
+        detector.observe(0, "Find flights to Tokyo", &config);
+        detector.record_not_dispatched();
+        assert!(detector.observe(0, "Book the cheapest flight", &config));
+    }
+
+    #[test]
+    fn test_disabled_when_zero() {
+        let config = StallDetectionConfig { max_rounds_without_progress: 0, ..StallDetectionConfig::default() };
+        let mut detector = StallDetector::new();
+        for _ in 0..10 {
+            assert!(!detector.observe(0, "same", &config));
+        }
+    }
+}
\ No newline at end of file
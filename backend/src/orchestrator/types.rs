@@ -1,6 +1,14 @@
-use crate::orchestrator::message::ChatMessage;
+use crate::orchestrator::message::{ChatMessage, MessageRole, MultiModalContent};
+use crate::orchestrator::metrics::{CostSummary, OrchestratorMetrics};
 use crate::orchestrator::plan::Plan;
+use crate::orchestrator::plan_history::PlanVersion;
+use crate::database::StepOutcome;
+use anyhow::{Context, Result};
 use serde::{Serialize, Deserialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
 
 // 维护群聊对话的状态
 /* OrchestratorState 存在的必要性：Orchestrator本身不足以管理复杂的多代理对话，
@@ -14,11 +22,17 @@ pub struct OrchestratorState {
     pub plan: Option<Plan>,                     // 执行的计划
     pub n_rounds: usize,                        // 执行的轮次
     pub current_step_idx: usize,                // 当前进行的步骤
+    pub current_step_id: Option<String>,        // 当前步骤的 id，计划变化后不受位置影响
     pub information_collected: String,          // 收集的信息
     pub in_planning_mode: bool,                 // 是否处于规划模式
     pub group_topic_type: String,               // 群聊的讨论主题
     pub message_history: Vec<ChatMessage>,      // 完整的对话历史
     pub n_replans: usize,                       // 重规划的次数
+    pub last_approved_step: Option<usize>,      // 最近一次被人工批准的步骤
+    pub is_terminated: bool,                    // 是否已经给出最终答案
+    pub step_outcomes: Vec<StepOutcome>,        // 每个已完成步骤的结论
+    pub plan_history: Vec<PlanVersion>,         // 本次任务的所有计划版本，最后一个是当前计划
+    pub sentinel_checks: Option<(String, u32)>, // 当前哨兵步骤的 id 和已经分发的检查次数
 }
 
 impl OrchestratorState {
@@ -29,10 +43,16 @@ impl OrchestratorState {
         self.plan = None;
         self.n_rounds = 0;
         self.current_step_idx = 0;
+        self.current_step_id = None;
         self.information_collected = String::new();
         self.in_planning_mode = true;
         self.message_history = vec![];
         self.n_replans = 0;
+        self.last_approved_step = None;
+        self.is_terminated = false;
+        self.step_outcomes = vec![];
+        self.plan_history = vec![];
+        self.sentinel_checks = None;
     }
 
     // 保留上下文的重制
@@ -42,8 +62,304 @@ impl OrchestratorState {
         self.plan = None;
         self.n_rounds = 0;
         self.current_step_idx = 0;
+        self.current_step_id = None;
         self.in_planning_mode = true;
         self.n_replans = 0;
+        self.last_approved_step = None;
+        self.is_terminated = false;
+        self.step_outcomes = vec![];
+        self.plan_history = vec![];
+        self.sentinel_checks = None;
+    }
+
+    // 计划或当前步骤下标变化后调用
+    pub fn sync_current_step_id(&mut self) {
+        self.current_step_id = self
+            .plan
+            .as_ref()
+            .and_then(|plan| plan.steps.get(self.current_step_idx))
+            .map(|step| step.id.clone());
+    }
+}
+
+/// Orchestrator::run_task 的选项
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// 写入运行记录的用户，未设置时沿用 set_run_store 传入的用户
+    pub user_id: Option<String>,
+    /// 执行前把计划交给 ActionGuard 审批
+    pub approve_plan: bool,
+    /// 用户随任务附带的图片，规划时一并交给模型
+    pub attachments: Vec<ImageAttachment>,
+    /// 演练运行：步骤通常交给 SimulatedAgent，不写运行记录、会话检查点、计划库和产物
+    pub dry_run: bool,
+    /// 调用方（后端执行器）预先创建的运行记录，设置时沿用它而不是新建
+    pub run_id: Option<String>,
+    /// 后端会话中用户上传的文件，已经下载到本地；规划时列出，代理用 session://<id> 引用
+    pub files: Option<SessionFiles>,
+    /// 同一会话中之前的任务和答案（终端交互模式的上下文），规划时放在任务前面，用来理解后续请求中的指代
+    pub context: Option<String>,
+}
+
+/// 会话中的一个文件，path 为运行期间的本地副本
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionFile {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+    pub path: PathBuf,
+}
+
+/* 一次运行可以使用的会话文件。后端的代理只能上传这些文件，
+模型给出的其他本地路径一律拒绝，避免把服务器上的文件发给网站 */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionFiles {
+    files: Vec<SessionFile>,
+}
+
+impl SessionFiles {
+    pub const SCHEME: &'static str = "session://";
+
+    pub fn new(files: Vec<SessionFile>) -> Self {
+        Self { files }
+    }
+
+    pub fn files(&self) -> &[SessionFile] {
+        &self.files
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// session://<id> 对应的本地文件，不是这种形式或没有这个文件时返回错误
+    pub fn resolve(&self, path: &str) -> Result<&SessionFile> {
+        let id = path
+            .trim()
+            .strip_prefix(Self::SCHEME)
+            .ok_or_else(|| anyhow::anyhow!("Only session files can be uploaded, refer to them as {}<file-id>", Self::SCHEME))?;
+        self.files
+            .iter()
+            .find(|file| file.id == id)
+            .ok_or_else(|| anyhow::anyhow!("Session file {} does not exist", id))
+    }
+
+    // 规划时附在任务后面的文件列表
+    pub fn describe(&self) -> String {
+        let lines: Vec<String> = self
+            .files
+            .iter()
+            .map(|file| format!("- {} ({} bytes): {}{}", file.name, file.size, Self::SCHEME, file.id))
+            .collect();
+        format!(
+            "Attached files (upload one with upload_file, using its {}<file-id> path as file_path):\n{}",
+            Self::SCHEME,
+            lines.join("\n")
+        )
+    }
+}
+
+/* 上传文件时使用的本地路径：有会话文件时只接受 session://<id>；否则（CLI）只接受 uploads_dir 中存在的文件，
+相对路径相对于 uploads_dir，返回规范化后的绝对路径。没有设置 uploads_dir 时不能上传本地文件 */
+pub fn resolve_upload_path(files: Option<&SessionFiles>, uploads_dir: Option<&Path>, path: &str) -> Result<PathBuf> {
+    let path = path.trim();
+    match files {
+        Some(files) => return Ok(files.resolve(path)?.path.clone()),
+        None if path.starts_with(SessionFiles::SCHEME) => {
+            return Err(anyhow::anyhow!("Session files are not available in this run"))
+        }
+        None => {}
+    }
+    let dir = uploads_dir.ok_or_else(|| anyhow::anyhow!("Uploading local files requires browser.uploads_dir to be set"))?;
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("Uploads directory {} does not exist", dir.display()))?;
+    let resolved = dir
+        .join(path)
+        .canonicalize()
+        .with_context(|| format!("File {} does not exist", path))?;
+    if !resolved.starts_with(&dir) {
+        return Err(anyhow::anyhow!("File {} is outside the uploads directory {}", path, dir.display()));
+    }
+    if !resolved.is_file() {
+        return Err(anyhow::anyhow!("{} is not a file", path));
+    }
+    Ok(resolved)
+}
+
+/// 用户附带的一张图片，计划步骤通过 filename 引用它
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageAttachment {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+impl ImageAttachment {
+    pub fn new(filename: impl Into<String>, data: Vec<u8>) -> Self {
+        Self { filename: filename.into(), data }
+    }
+
+    // 从本地文件读取，filename 取路径中的文件名
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).with_context(|| format!("Failed to read image {}", path.display()))?;
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        Ok(Self { filename, data })
+    }
+}
+
+/// 一次运行的结果
+#[derive(Debug, Clone, Serialize)]
+pub struct RunOutcome {
+    pub final_answer: String,
+    pub plan: Option<Plan>,
+    pub metrics: OrchestratorMetrics,
+    /// 本次运行保存的截图等产物
+    pub artifacts: Vec<PathBuf>,
+    /// 每个已完成步骤的结论
+    pub step_outcomes: Vec<StepOutcome>,
+    /// 运行结束时 ledger 汇总的已收集信息
+    pub information_collected: String,
+    /// 按代理汇总的用量和花费，cost_summary.enabled 关闭时为 None
+    pub cost_summary: Option<CostSummary>,
+}
+
+/// 默认的停止指令，可以通过配置追加
+pub const DEFAULT_STOP_COMMANDS: &[&str] = &["stop", "cancel", "停止"];
+
+// 去掉首尾空白和结尾的标点后比较，不区分大小写
+fn normalize_command(text: &str) -> String {
+    text.trim()
+        .trim_end_matches(['!', '.', '。', '！'])
+        .trim()
+        .to_lowercase()
+}
+
+/// 判断用户消息是否是明确的停止指令（只看文本消息，整条消息完全匹配）
+pub fn is_stop_command(message: &ChatMessage, extra_commands: &[String]) -> bool {
+    let content = match message {
+        ChatMessage::Text { content, .. } => normalize_command(content),
+        ChatMessage::MultiModal { .. } => return false,
+    };
+    DEFAULT_STOP_COMMANDS.iter().any(|c| normalize_command(c) == content)
+        || extra_commands.iter().any(|c| normalize_command(c) == content)
+}
+
+/* 运行过程中用户提交的消息队列：CLI 输入循环、后端接口可以在任意时刻（包括 orchestrator
+正在执行步骤时）放入消息，orchestrator 在下一次 ledger 评估之前统一取出。
+停止指令不进入队列，而是直接取消当前运行的 token */
+#[derive(Debug, Clone, Default)]
+pub struct UserMessageQueue {
+    inner: Arc<Mutex<VecDeque<ChatMessage>>>,
+    stop_commands: Arc<Vec<String>>,
+    cancel: Arc<Mutex<CancellationToken>>,
+}
+
+impl UserMessageQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_stop_commands(mut self, stop_commands: Vec<String>) -> Self {
+        self.stop_commands = Arc::new(stop_commands);
+        self
+    }
+
+    /// 放入一条消息，返回它是否是停止指令
+    pub fn push(&self, message: ChatMessage) -> bool {
+        if is_stop_command(&message, &self.stop_commands) {
+            self.cancel();
+            return true;
+        }
+        self.inner.lock().unwrap().push_back(message);
+        false
+    }
+
+    pub fn drain(&self) -> Vec<ChatMessage> {
+        self.inner.lock().unwrap().drain(..).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().unwrap().is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+
+    // 当前运行的取消 token，分发代理步骤时传入
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.lock().unwrap().clone()
+    }
+
+    /// 供后端取消接口直接调用
+    pub fn cancel(&self) {
+        self.cancel.lock().unwrap().cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.lock().unwrap().is_cancelled()
+    }
+
+    // 开始新一轮运行前换一个新的 token
+    pub fn reset_cancellation(&self) {
+        *self.cancel.lock().unwrap() = CancellationToken::new();
+    }
+
+    // 分发步骤时交给代理的句柄，只能看到此刻之后到达的消息
+    pub fn mailbox(&self) -> UserMailbox {
+        UserMailbox {
+            queue: self.clone(),
+            seen: self.len(),
+        }
+    }
+}
+
+/* 代理在步骤执行期间读取新用户消息的句柄：WebAgent 在每次工具调用之间轮询，
+把新消息放进自己的对话历史。这里只读取、不移出队列，消息仍然会在下一次 ledger 评估前
+由 orchestrator 统一放进历史。停止指令不进入队列，仍然走取消 token */
+#[derive(Debug, Clone)]
+pub struct UserMailbox {
+    queue: UserMessageQueue,
+    seen: usize,
+}
+
+impl UserMailbox {
+    /// 返回上次调用之后到达的消息
+    pub fn take_new(&mut self) -> Vec<ChatMessage> {
+        let inner = self.queue.inner.lock().unwrap();
+        // 队列在步骤之间会被取空，此时从头开始计数
+        if inner.len() < self.seen {
+            self.seen = 0;
+        }
+        let new: Vec<ChatMessage> = inner.iter().skip(self.seen).cloned().collect();
+        self.seen = inner.len();
+        new
+    }
+}
+
+// 给 ledger 看的新消息格式，文本前加上提示前缀，多模态消息保留图片
+pub fn as_new_user_message(message: ChatMessage) -> ChatMessage {
+    match message {
+        ChatMessage::Text { source, content, metadata, .. } => ChatMessage::Text {
+            role: MessageRole::User,
+            source,
+            content: format!("New user message received: {}", content),
+            metadata,
+        },
+        ChatMessage::MultiModal { source, content, metadata, .. } => {
+            let mut parts = vec![MultiModalContent::Text("New user message received:".to_string())];
+            parts.extend(content);
+            ChatMessage::MultiModal {
+                role: MessageRole::User,
+                source,
+                content: parts,
+                metadata,
+            }
+        }
     }
 }
 
@@ -67,3 +383,130 @@ pub struct InstructionOrQuestion {
     #[serde(rename = "agent_name")]
     pub agent_name: String,
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_message_queue_shared_between_clones() {
+        let queue = UserMessageQueue::new();
+        let handle = queue.clone();
+
+        handle.push(ChatMessage::new_text(MessageRole::User, "user".to_string(), "first".to_string()));
+        handle.push(ChatMessage::new_text(MessageRole::User, "user".to_string(), "second".to_string()));
+        assert_eq!(queue.len(), 2);
+
+        let drained = queue.drain();
+        assert_eq!(drained.len(), 2);
+        assert!(handle.is_empty());
+    }
+
+    #[test]
+    fn test_mailbox_sees_only_new_messages_without_draining() {
+        let queue = UserMessageQueue::new();
+        queue.push(ChatMessage::new_text(MessageRole::User, "user".to_string(), "before".to_string()));
+
+        let mut mailbox = queue.mailbox();
+        assert!(mailbox.take_new().is_empty());
+
+        queue.push(ChatMessage::new_text(MessageRole::User, "user".to_string(), "actually search for trains".to_string()));
+        let new = mailbox.take_new();
+        assert_eq!(new.len(), 1);
+        assert!(matches!(&new[0], ChatMessage::Text { content, .. } if content == "actually search for trains"));
+        assert!(mailbox.take_new().is_empty());
+
+        // 停止指令不会出现在 mailbox 中
+        queue.push(ChatMessage::new_text(MessageRole::User, "user".to_string(), "stop".to_string()));
+        assert!(mailbox.take_new().is_empty());
+        assert!(queue.is_cancelled());
+
+        // orchestrator 仍然能取出全部消息
+        assert_eq!(queue.drain().len(), 2);
+    }
+
+    #[test]
+    fn test_upload_paths_resolve_to_session_files() {
+        let files = SessionFiles::new(vec![SessionFile {
+            id: "f1".to_string(),
+            name: "prices.csv".to_string(),
+            size: 12,
+            path: PathBuf::from("/tmp/run/f1/prices.csv"),
+        }]);
+        assert_eq!(resolve_upload_path(Some(&files), None, "session://f1").unwrap(), PathBuf::from("/tmp/run/f1/prices.csv"));
+        assert!(resolve_upload_path(Some(&files), None, "session://f2").is_err());
+        // 后端的代理不能上传服务器上的其他文件
+        assert!(resolve_upload_path(Some(&files), None, "/etc/passwd").is_err());
+        assert!(resolve_upload_path(None, None, "session://f1").is_err());
+        assert!(files.describe().contains("- prices.csv (12 bytes): session://f1"));
+    }
+
+    #[test]
+    fn test_local_uploads_stay_in_the_uploads_dir() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let uploads = root.path().join("uploads");
+        std::fs::create_dir_all(uploads.join("docs"))?;
+        std::fs::write(uploads.join("docs/report.pdf"), b"%PDF")?;
+        std::fs::write(root.path().join("secret.txt"), b"hunter2")?;
+        let report = uploads.join("docs/report.pdf").canonicalize()?;
+
+        assert_eq!(resolve_upload_path(None, Some(&uploads), "docs/report.pdf")?, report);
+        assert_eq!(resolve_upload_path(None, Some(&uploads), &report.display().to_string())?, report);
+        for rejected in ["../secret.txt", "docs/missing.pdf", "docs", "/etc/passwd"] {
+            assert!(resolve_upload_path(None, Some(&uploads), rejected).is_err(), "{}", rejected);
+        }
+        assert!(resolve_upload_path(None, Some(&uploads), "../secret.txt").unwrap_err().to_string().contains("outside"));
+        // 没有设置目录时不能上传本地文件
+        assert!(resolve_upload_path(None, None, "docs/report.pdf").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_as_new_user_message() {
+        let msg = as_new_user_message(ChatMessage::new_text(
+            MessageRole::User,
+            "user".to_string(),
+            "search for trains instead".to_string(),
+        ));
+        match msg {
+            ChatMessage::Text { content, source, .. } => {
+                assert_eq!(content, "New user message received: search for trains instead");
+                assert_eq!(source, "user");
+            }
+            _ => panic!("Expected text message"),
+        }
+    }
+
+    #[test]
+    fn test_reset_clears_termination() {
+        let mut state = OrchestratorState { is_terminated: true, ..OrchestratorState::default() };
+        state.reset_with_context();
+        assert!(!state.is_terminated);
+    }
+
+    #[test]
+    fn test_is_stop_command() {
+        let text = |content: &str| ChatMessage::new_text(MessageRole::User, "user".to_string(), content.to_string());
+        assert!(is_stop_command(&text("stop"), &[]));
+        assert!(is_stop_command(&text("  Cancel! "), &[]));
+        assert!(is_stop_command(&text("停止。"), &[]));
+        assert!(!is_stop_command(&text("stop searching flights and look for trains"), &[]));
+        assert!(!is_stop_command(&text("abort"), &[]));
+        assert!(is_stop_command(&text("Abort"), &["abort".to_string()]));
+    }
+
+    #[test]
+    fn test_stop_command_cancels_instead_of_queueing() {
+        let queue = UserMessageQueue::new();
+        let token = queue.cancellation_token();
+
+        assert!(queue.push(ChatMessage::new_text(MessageRole::User, "user".to_string(), "STOP".to_string())));
+        assert!(queue.is_empty());
+        assert!(token.is_cancelled());
+
+        queue.reset_cancellation();
+        assert!(!queue.is_cancelled());
+        assert!(!queue.cancellation_token().is_cancelled());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,449 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::orchestrator::plan::{describe_value, Plan, StepKind};
+
+/// 计划校验的上限，0 表示不限制
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanValidationLimits {
+    pub max_steps: usize,
+    /// 单个步骤 details 的最大字符数
+    pub max_details_chars: usize,
+}
+
+impl Default for PlanValidationLimits {
+    fn default() -> Self {
+        Self {
+            max_steps: 20,
+            max_details_chars: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanValidationErrorKind {
+    NotAnObject,
+    MissingKey,
+    WrongType,
+    EmptySteps,
+    TooManySteps,
+    UnknownAgent,
+    SentinelField,
+    DetailsTooLong,
+    EmptyClarification,
+}
+
+/// 一条校验错误，带上步骤下标和字段，重试提示词可以直接引用
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanValidationError {
+    pub kind: PlanValidationErrorKind,
+    pub step_index: Option<usize>,
+    pub field: String,
+    pub message: String,
+}
+
+impl PlanValidationError {
+    fn plan(kind: PlanValidationErrorKind, field: &str, message: String) -> Self {
+        Self { kind, step_index: None, field: field.to_string(), message }
+    }
+
+    fn step(kind: PlanValidationErrorKind, step_index: usize, field: &str, message: String) -> Self {
+        Self { kind, step_index: Some(step_index), field: field.to_string(), message }
+    }
+}
+
+// 步骤编号从 1 开始，与计划展示给用户的编号一致
+impl fmt::Display for PlanValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.step_index {
+            Some(i) => write!(f, "step {}: {}", i + 1, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// 拼成一段文字放进重试提示词
+pub fn format_validation_errors(errors: &[PlanValidationError]) -> String {
+    errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; ")
+}
+
+pub fn validate_plan(plan: &Value, team: &[String], sentinel_enabled: bool) -> Result<(), Vec<PlanValidationError>> {
+    validate_plan_with_limits(plan, team, sentinel_enabled, &PlanValidationLimits::default())
+}
+
+/* 校验规划模型输出的计划 JSON，一次收集所有错误而不是遇到第一个就返回。
+team 为空时不检查代理名（orchestrator 会先做别名映射再单独处理未知代理） */
+pub fn validate_plan_with_limits(
+    plan: &Value,
+    team: &[String],
+    sentinel_enabled: bool,
+    limits: &PlanValidationLimits,
+) -> Result<(), Vec<PlanValidationError>> {
+    use PlanValidationErrorKind::*;
+
+    let obj = match plan.as_object() {
+        Some(obj) => obj,
+        None => {
+            return Err(vec![PlanValidationError::plan(
+                NotAnObject,
+                "plan",
+                format!("the plan must be a JSON object, got {}", describe_value(plan)),
+            )])
+        }
+    };
+
+    let mut errors = Vec::new();
+    for (key, expected) in [
+        ("task", "a string"),
+        ("response", "a string"),
+        ("plan_summary", "a string"),
+        ("needs_plan", "a boolean"),
+        ("steps", "an array"),
+    ] {
+        match obj.get(key) {
+            None => errors.push(PlanValidationError::plan(MissingKey, key, format!("missing key '{}'", key))),
+            Some(value) if !has_type(value, expected) => errors.push(PlanValidationError::plan(
+                WrongType,
+                key,
+                format!("{} must be {}, got {}", key, expected, describe_value(value)),
+            )),
+            Some(_) => {}
+        }
+    }
+    // 追问的两个字段可以省略
+    for (key, expected) in [("needs_clarification", "a boolean"), ("clarification_question", "a string")] {
+        if let Some(value) = obj.get(key).filter(|value| !has_type(value, expected)) {
+            errors.push(PlanValidationError::plan(
+                WrongType,
+                key,
+                format!("{} must be {}, got {}", key, expected, describe_value(value)),
+            ));
+        }
+    }
+    let needs_clarification = obj.get("needs_clarification").and_then(|v| v.as_bool()).unwrap_or(false);
+    let question = obj.get("clarification_question").and_then(|v| v.as_str()).unwrap_or("");
+    if needs_clarification && question.trim().is_empty() {
+        errors.push(PlanValidationError::plan(
+            EmptyClarification,
+            "clarification_question",
+            "clarification_question must not be empty when needs_clarification is true".to_string(),
+        ));
+    }
+
+    let steps = match obj.get("steps").and_then(|v| v.as_array()) {
+        Some(steps) => steps,
+        None => return Err(errors),
+    };
+    let needs_plan = obj.get("needs_plan").and_then(|v| v.as_bool()).unwrap_or(false);
+    if needs_plan && !needs_clarification && steps.is_empty() {
+        errors.push(PlanValidationError::plan(
+            EmptySteps,
+            "steps",
+            "steps must not be empty when needs_plan is true".to_string(),
+        ));
+    }
+    if limits.max_steps > 0 && steps.len() > limits.max_steps {
+        errors.push(PlanValidationError::plan(
+            TooManySteps,
+            "steps",
+            format!("the plan has {} steps, the limit is {}", steps.len(), limits.max_steps),
+        ));
+    }
+
+    for (i, step) in steps.iter().enumerate() {
+        match step.as_object() {
+            Some(step) => validate_step(i, step, team, sentinel_enabled, limits, &mut errors),
+            None => errors.push(PlanValidationError::step(
+                WrongType,
+                i,
+                "step",
+                format!("the step must be a JSON object, got {}", describe_value(step)),
+            )),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// 校验一个已经解析好的计划（导入的计划、编辑后的计划），计划为空视为错误
+pub fn validate_plan_steps(
+    plan: &Plan,
+    team: &[String],
+    sentinel_enabled: bool,
+    limits: &PlanValidationLimits,
+) -> Result<(), Vec<PlanValidationError>> {
+    let value = serde_json::json!({
+        "task": plan.task.clone().unwrap_or_default(),
+        "response": "",
+        "plan_summary": "",
+        "needs_plan": true,
+        "steps": plan.steps,
+    });
+    validate_plan_with_limits(&value, team, sentinel_enabled, limits)
+}
+
+fn validate_step(
+    i: usize,
+    step: &Map<String, Value>,
+    team: &[String],
+    sentinel_enabled: bool,
+    limits: &PlanValidationLimits,
+    errors: &mut Vec<PlanValidationError>,
+) {
+    use PlanValidationErrorKind::*;
+
+    for key in ["title", "details", "agent_name"] {
+        match step.get(key) {
+            None => errors.push(PlanValidationError::step(MissingKey, i, key, format!("missing key '{}'", key))),
+            Some(Value::String(_)) => {}
+            Some(value) => errors.push(PlanValidationError::step(
+                WrongType,
+                i,
+                key,
+                format!("{} must be a string, got {}", key, describe_value(value)),
+            )),
+        }
+    }
+
+    // expected_outcome 是可选的，给出时必须是字符串
+    if let Some(value) = step.get("expected_outcome").filter(|v| !v.is_null() && !v.is_string()) {
+        errors.push(PlanValidationError::step(
+            WrongType,
+            i,
+            "expected_outcome",
+            format!("expected_outcome must be a string, got {}", describe_value(value)),
+        ));
+    }
+
+    if let Some(agent_name) = step.get("agent_name").and_then(|v| v.as_str()) {
+        if !team.is_empty() && !team.iter().any(|name| name == agent_name) {
+            errors.push(PlanValidationError::step(
+                UnknownAgent,
+                i,
+                "agent_name",
+                format!("agent_name '{}' is not one of: {}", agent_name, team.join(", ")),
+            ));
+        }
+    }
+
+    if let Some(details) = step.get("details").and_then(|v| v.as_str()) {
+        let len = details.chars().count();
+        if limits.max_details_chars > 0 && len > limits.max_details_chars {
+            errors.push(PlanValidationError::step(
+                DetailsTooLong,
+                i,
+                "details",
+                format!("details is {} characters long, the limit is {}", len, limits.max_details_chars),
+            ));
+        }
+    }
+
+    if let Err(e) = StepKind::from_step_checked(step, sentinel_enabled) {
+        errors.push(PlanValidationError::step(SentinelField, i, e.field, e.message));
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "a string" => value.is_string(),
+        "a boolean" => value.is_boolean(),
+        "an array" => value.is_array(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use PlanValidationErrorKind::*;
+
+    fn team() -> Vec<String> {
+        vec!["web_surfer".to_string(), "coder_agent".to_string()]
+    }
+
+    fn sample_plan() -> Value {
+        json!({
+            "task": "Find the menu",
+            "response": "",
+            "plan_summary": "Search and read the menu",
+            "needs_plan": true,
+            "steps": [
+                { "title": "Search", "details": "Search for the restaurant", "agent_name": "web_surfer" },
+                { "title": "Read", "details": "Open the menu page", "agent_name": "web_surfer" },
+                {
+                    "title": "Watch",
+                    "details": "Check the menu every day",
+                    "agent_name": "web_surfer",
+                    "step_type": "SentinelPlanStep",
+                    "sleep_duration": 86400,
+                    "condition": 3
+                }
+            ]
+        })
+    }
+
+    fn validation_errors(plan: &Value, sentinel_enabled: bool) -> Vec<PlanValidationError> {
+        validate_plan(plan, &team(), sentinel_enabled).unwrap_err()
+    }
+
+    fn kinds(errors: &[PlanValidationError]) -> Vec<PlanValidationErrorKind> {
+        errors.iter().map(|e| e.kind).collect()
+    }
+
+    #[test]
+    fn test_valid_plan() {
+        assert!(validate_plan(&sample_plan(), &team(), true).is_ok());
+        // team 为空时不检查代理名
+        let mut unknown = sample_plan();
+        unknown["steps"][0]["agent_name"] = json!("browser");
+        assert!(validate_plan(&unknown, &[], true).is_ok());
+    }
+
+    #[test]
+    fn test_not_an_object() {
+        let errors = validation_errors(&json!(["a", "b"]), true);
+        assert_eq!(kinds(&errors), vec![NotAnObject]);
+    }
+
+    #[test]
+    fn test_missing_keys() {
+        let mut plan = sample_plan();
+        plan.as_object_mut().unwrap().remove("plan_summary");
+        plan["steps"][1].as_object_mut().unwrap().remove("details");
+
+        let errors = validation_errors(&plan, true);
+        assert_eq!(kinds(&errors), vec![MissingKey, MissingKey]);
+        assert_eq!(errors[0].to_string(), "missing key 'plan_summary'");
+        assert_eq!(errors[1].to_string(), "step 2: missing key 'details'");
+        assert_eq!((errors[1].step_index, errors[1].field.as_str()), (Some(1), "details"));
+    }
+
+    #[test]
+    fn test_wrong_types() {
+        let mut plan = sample_plan();
+        plan["needs_plan"] = json!("yes");
+        plan["steps"][0]["title"] = json!(42);
+        plan["steps"][1] = json!("Open the menu page");
+        plan["steps"][2]["expected_outcome"] = json!(["menu"]);
+
+        let errors = validation_errors(&plan, true);
+        assert_eq!(kinds(&errors), vec![WrongType, WrongType, WrongType, WrongType]);
+        assert_eq!(errors[0].to_string(), "needs_plan must be a boolean, got 'yes'");
+        assert_eq!(errors[1].to_string(), "step 1: title must be a string, got 42");
+        assert_eq!(errors[2].field, "step");
+        assert_eq!(errors[3].to_string(), "step 3: expected_outcome must be a string, got [\"menu\"]");
+    }
+
+    #[test]
+    fn test_empty_steps() {
+        let mut plan = sample_plan();
+        plan["steps"] = json!([]);
+        assert_eq!(kinds(&validation_errors(&plan, true)), vec![EmptySteps]);
+
+        // 不需要计划时允许没有步骤
+        plan["needs_plan"] = json!(false);
+        assert!(validate_plan(&plan, &team(), true).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_agent() {
+        let mut plan = sample_plan();
+        plan["steps"][1]["agent_name"] = json!("file_surfer");
+
+        let errors = validation_errors(&plan, true);
+        assert_eq!(kinds(&errors), vec![UnknownAgent]);
+        assert_eq!(errors[0].to_string(), "step 2: agent_name 'file_surfer' is not one of: web_surfer, coder_agent");
+    }
+
+    #[test]
+    fn test_sentinel_field_errors() {
+        let mut plan = sample_plan();
+        plan["steps"][2]["sleep_duration"] = json!("daily");
+        let errors = validation_errors(&plan, true);
+        assert_eq!(kinds(&errors), vec![SentinelField]);
+        assert_eq!(errors[0].to_string(), "step 3: sleep_duration must be a non-negative integer, got 'daily'");
+        assert_eq!(errors[0].field, "sleep_duration");
+
+        let mut plan = sample_plan();
+        plan["steps"][2]["sleep_duration"] = json!(-5);
+        assert_eq!(validation_errors(&plan, true)[0].field, "sleep_duration");
+
+        let mut plan = sample_plan();
+        plan["steps"][2]["condition"] = json!({ "times": 3 });
+        let errors = validation_errors(&plan, true);
+        assert_eq!(errors[0].field, "condition");
+        assert!(errors[0].message.contains("integer or a string"));
+
+        let mut plan = sample_plan();
+        plan["steps"][2].as_object_mut().unwrap().remove("condition");
+        assert_eq!(validation_errors(&plan, true)[0].to_string(), "step 3: condition is required for a SentinelPlanStep");
+
+        // 关闭哨兵任务时出现哨兵字段
+        let errors = validation_errors(&sample_plan(), false);
+        assert_eq!(kinds(&errors), vec![SentinelField]);
+        assert_eq!(errors[0].field, "step_type");
+    }
+
+    #[test]
+    fn test_details_too_long() {
+        let mut plan = sample_plan();
+        plan["steps"][0]["details"] = json!("x".repeat(30));
+        // 其余步骤的 details 都不超过 25 个字符
+        let limits = PlanValidationLimits { max_details_chars: 25, ..PlanValidationLimits::default() };
+
+        let errors = validate_plan_with_limits(&plan, &team(), true, &limits).unwrap_err();
+        assert_eq!(kinds(&errors), vec![DetailsTooLong]);
+        assert_eq!(errors[0].to_string(), "step 1: details is 30 characters long, the limit is 25");
+
+        let unlimited = PlanValidationLimits { max_details_chars: 0, ..PlanValidationLimits::default() };
+        assert!(validate_plan_with_limits(&plan, &team(), true, &unlimited).is_ok());
+    }
+
+    #[test]
+    fn test_too_many_steps() {
+        let limits = PlanValidationLimits { max_steps: 2, ..PlanValidationLimits::default() };
+        let errors = validate_plan_with_limits(&sample_plan(), &team(), true, &limits).unwrap_err();
+        assert_eq!(kinds(&errors), vec![TooManySteps]);
+        assert_eq!(errors[0].to_string(), "the plan has 3 steps, the limit is 2");
+    }
+
+    #[test]
+    fn test_collects_all_errors() {
+        let mut plan = sample_plan();
+        plan.as_object_mut().unwrap().remove("task");
+        plan["steps"][0]["agent_name"] = json!("ghost");
+        plan["steps"][2]["condition"] = json!(true);
+
+        let errors = validation_errors(&plan, true);
+        assert_eq!(kinds(&errors), vec![MissingKey, UnknownAgent, SentinelField]);
+        assert_eq!(
+            format_validation_errors(&errors),
+            "missing key 'task'; step 1: agent_name 'ghost' is not one of: web_surfer, coder_agent; step 3: condition must be a non-negative integer or a string, got true"
+        );
+    }
+
+    #[test]
+    fn test_clarification_fields() {
+        let mut plan = sample_plan();
+        plan["needs_clarification"] = json!(true);
+        plan["clarification_question"] = json!("Which restaurant?");
+        plan["steps"] = json!([]);
+        assert!(validate_plan(&plan, &team(), true).is_ok());
+
+        plan["clarification_question"] = json!(" ");
+        let errors = validation_errors(&plan, true);
+        assert_eq!(kinds(&errors), vec![EmptyClarification]);
+
+        plan["needs_clarification"] = json!("yes");
+        let errors = validation_errors(&plan, true);
+        assert_eq!(kinds(&errors), vec![WrongType, EmptySteps]);
+        assert_eq!(errors[0].to_string(), "needs_clarification must be a boolean, got 'yes'");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,90 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+
+use crate::storage::{is_valid_key, BlobStore};
+
+/// 本地目录中的存储，key 的前两位作为子目录，避免单个目录下文件过多
+#[derive(Debug, Clone)]
+pub struct LocalDirStore {
+    root: PathBuf,
+}
+
+impl LocalDirStore {
+    pub fn new(root: impl AsRef<Path>) -> Result<Self> {
+        std::fs::create_dir_all(root.as_ref())?;
+        Ok(Self { root: root.as_ref().to_path_buf() })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        if !is_valid_key(key) {
+            return Err(anyhow!("Invalid blob key '{}'", key));
+        }
+        Ok(self.root.join(&key[..2]).join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalDirStore {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    // 先写临时文件再改名，读到的文件总是完整的；内容相同的 key 已存在时不再写
+    async fn put_key(&self, key: &str, bytes: &[u8], _mime: &str) -> Result<()> {
+        let path = self.path(key)?;
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(());
+        }
+        let dir = path.parent().expect("blob path has a parent");
+        tokio::fs::create_dir_all(dir).await?;
+        let tmp = dir.join(format!(".{}.{}", key, uuid::Uuid::new_v4().simple()));
+        tokio::fs::write(&tmp, bytes).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_put_get_delete() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let store = LocalDirStore::new(dir.path())?;
+        let blob = store.put(b"%PDF-1.7 report", "application/pdf").await?;
+        assert_eq!(blob.store, "local");
+        assert_eq!(blob.size, 15);
+        assert!(blob.key.ends_with(".pdf"));
+        assert_eq!(store.put(b"%PDF-1.7 report", "application/pdf").await?, blob);
+        assert_eq!(store.get(&blob.key).await?.as_deref(), Some(&b"%PDF-1.7 report"[..]));
+
+        store.delete(&blob.key).await?;
+        store.delete(&blob.key).await?;
+        assert!(store.get(&blob.key).await?.is_none());
+        assert!(store.get("../secret").await.is_err());
+        Ok(())
+    }
+}
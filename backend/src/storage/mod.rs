@@ -0,0 +1,114 @@
+// 截图、PDF、下载文件等运行产物的外部存储，数据库中只保存引用
+pub mod local;
+pub mod s3;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub use local::LocalDirStore;
+pub use s3::{S3Settings, S3Store};
+
+/// 产物在存储中的引用，消息和运行事件里只保存它
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobRef {
+    pub store: String,
+    pub key: String,
+    pub mime: String,
+    pub size: u64,
+}
+
+/// 按 key 读写的对象存储，key 由内容决定，相同内容只保存一份
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// 写入 BlobRef.store 的名称
+    fn name(&self) -> &str;
+
+    async fn put_key(&self, key: &str, bytes: &[u8], mime: &str) -> Result<()>;
+
+    /// 不存在时返回 None
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// 不存在时也返回 Ok
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    async fn put(&self, bytes: &[u8], mime: &str) -> Result<BlobRef> {
+        let key = content_key(bytes, mime);
+        self.put_key(&key, bytes, mime).await?;
+        Ok(BlobRef {
+            store: self.name().to_string(),
+            key,
+            mime: mime.to_string(),
+            size: bytes.len() as u64,
+        })
+    }
+}
+
+/// 内容的 sha256 加上由类型决定的扩展名
+pub fn content_key(bytes: &[u8], mime: &str) -> String {
+    format!("{:x}.{}", Sha256::digest(bytes), extension(mime))
+}
+
+pub fn extension(mime: &str) -> &'static str {
+    match mime {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/webp" => "webp",
+        "image/gif" => "gif",
+        "application/pdf" => "pdf",
+        "text/plain" => "txt",
+        "text/html" => "html",
+        "text/csv" => "csv",
+        "application/json" => "json",
+        _ => "bin",
+    }
+}
+
+// 只接受 content_key 生成的形式，避免 key 被拼进路径或 URL 时越界
+pub fn is_valid_key(key: &str) -> bool {
+    match key.split_once('.') {
+        Some((hash, ext)) => {
+            hash.len() == 64
+                && hash.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+                && !ext.is_empty()
+                && ext.chars().all(|c| c.is_ascii_alphanumeric())
+        }
+        None => false,
+    }
+}
+
+/// 按文件头判断截图等二进制内容的类型，无法识别时为 application/octet-stream
+pub fn sniff_mime(bytes: &[u8]) -> &'static str {
+    if bytes.starts_with(&[0x89, b'P', b'N', b'G']) {
+        "image/png"
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "image/jpeg"
+    } else if bytes.starts_with(b"GIF8") {
+        "image/gif"
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        "image/webp"
+    } else if bytes.starts_with(b"%PDF") {
+        "application/pdf"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_key() {
+        let key = content_key(b"abc", "image/png");
+        assert_eq!(key, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad.png");
+        assert!(is_valid_key(&key));
+        assert!(!is_valid_key("../../etc/passwd"));
+        assert!(!is_valid_key("ba7816bf.png"));
+        assert!(!is_valid_key(&key.to_uppercase()));
+        assert_eq!(sniff_mime(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A]), "image/png");
+        assert_eq!(sniff_mime(b"%PDF-1.7"), "application/pdf");
+        assert_eq!(sniff_mime(b"hello"), "application/octet-stream");
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+
+use crate::agents::Agent;
+use crate::clients::{ChatCompletionClient, ModelInfo};
+use crate::orchestrator::config::{OrchestratorConfig, PlanApprovalConfig, RetryPolicy, StepApprovalPolicy};
+use crate::orchestrator::history::HistoryCompactionConfig;
+use crate::orchestrator::stall::StallDetectionConfig;
+use crate::orchestrator::validation::PlanValidationLimits;
+use crate::orchestrator::plan_library::PlanExamplesConfig;
+use crate::orchestrator::estimate::PlanEstimateConfig;
+use crate::orchestrator::message::{ChatMessage, MessageRole};
+use crate::orchestrator::orchestrator::{Orchestrator, TerminationConditionTrait};
+
+/// 测试用的配置：不需要用户参与、不重试、不写检查点和产物
+pub fn test_config() -> OrchestratorConfig {
+    OrchestratorConfig {
+        cooperative_planning: false,
+        autonomous_execution: true,
+        allow_follow_up_input: false,
+        max_replans: 3,
+        plan: None,
+        max_turns: Some(20),
+        allow_for_replans: true,
+        max_json_retries: 1,
+        saved_facts: None,
+        allowed_websites: None,
+        do_bing_search: false,
+        final_answer_prompt: None,
+        final_answer_prompt_file: None,
+        model_context_token_limit: None,
+        model_info: ModelInfo::default(),
+        pricing: None,
+        cost_summary: Default::default(),
+        retrieve_relevant_plans: None,
+        step_approval: StepApprovalPolicy::Never,
+        checkpoint_every_n_rounds: 0,
+        retry_policy: RetryPolicy::no_retry(),
+        step_retry_policies: HashMap::new(),
+        history_compaction: HistoryCompactionConfig::default(),
+        artifacts_dir: None,
+        session_dir: None,
+        stop_commands: Vec::new(),
+        agent_aliases: HashMap::new(),
+        stall_detection: StallDetectionConfig::default(),
+        notify_timeout_ms: 5000,
+        sentinel_tasks_enabled: false,
+        plan_validation: PlanValidationLimits::default(),
+        plan_examples: PlanExamplesConfig::default(),
+        plan_estimate: PlanEstimateConfig::default(),
+        force_language: None,
+        plan_approval: PlanApprovalConfig::default(),
+        stream_plan_text: false,
+    }
+}
+
+/// 在进程内用 mock 代理和 mock 模型组装 Orchestrator
+pub struct OrchestratorBuilder {
+    config: OrchestratorConfig,
+    provider: Option<Arc<dyn ChatCompletionClient>>,
+    agents: Vec<(String, Box<dyn Agent>)>,
+    termination_condition: Option<Box<dyn TerminationConditionTrait>>,
+}
+
+impl Default for OrchestratorBuilder {
+    fn default() -> Self {
+        Self {
+            config: test_config(),
+            provider: None,
+            agents: Vec::new(),
+            termination_condition: None,
+        }
+    }
+}
+
+impl OrchestratorBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn config(mut self, config: OrchestratorConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    // 在测试配置的基础上修改个别字段
+    pub fn configure(mut self, update: impl FnOnce(&mut OrchestratorConfig)) -> Self {
+        update(&mut self.config);
+        self
+    }
+
+    pub fn provider(mut self, provider: Arc<dyn ChatCompletionClient>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    pub fn agent(mut self, description: &str, agent: impl Agent + 'static) -> Self {
+        self.agents.push((description.to_string(), Box::new(agent)));
+        self
+    }
+
+    pub fn termination_condition(mut self, condition: impl TerminationConditionTrait + 'static) -> Self {
+        self.termination_condition = Some(Box::new(condition));
+        self
+    }
+
+    pub async fn build(self) -> Result<Orchestrator> {
+        let provider = self.provider.ok_or_else(|| anyhow!("OrchestratorBuilder requires a provider"))?;
+        let (participant_descriptions, participant_names): (Vec<String>, Vec<String>) = self
+            .agents
+            .iter()
+            .map(|(description, agent)| (description.clone(), agent.name().to_string()))
+            .unzip();
+
+        let mut orchestrator = Orchestrator::new(
+            "orchestrator".to_string(),
+            ChatMessage::new_text(MessageRole::User, "user".to_string(), String::new()),
+            participant_descriptions,
+            participant_names,
+            provider,
+            self.config,
+            self.termination_condition,
+            None,
+        ).await?;
+        for (_, agent) in self.agents {
+            orchestrator.register_agent(agent);
+        }
+        Ok(orchestrator)
+    }
+}
\ No newline at end of file
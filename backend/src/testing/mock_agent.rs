@@ -0,0 +1,232 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+
+use crate::agents::events::AgentEvent;
+use crate::agents::{Agent, AgentEventSink};
+use crate::clients::TokenUsage;
+use crate::orchestrator::message::{ChatMessage, Message, MessageRole, MessageType};
+use crate::orchestrator::types::{resolve_upload_path, SessionFiles};
+
+/// MockAgent 对每条 Execute 消息依次给出的响应
+#[derive(Debug, Clone)]
+pub enum MockReply {
+    Text(String),
+    Message(ChatMessage),
+    /// on_message_stream 直接返回 Err
+    Error(String),
+    /// 先等待一段时间再给出响应，用于测试超时和取消
+    Delayed(Duration, Box<MockReply>),
+    /// 模拟 web_surfer 的 upload_file：按 session:// 解析路径，把本地文件交给 uploads 记录
+    Upload(String),
+    /// 先通过事件出口发出代理事件（动作、结果），再给出响应
+    Events(Vec<AgentEvent>, Box<MockReply>),
+}
+
+/// MockAgent 上传的文件：解析后的本地路径和当时读到的内容（运行结束后临时文件已删除）
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockUpload {
+    pub path: PathBuf,
+    pub content: Vec<u8>,
+}
+
+/// 记录代理收到的所有消息，测试中用来断言分发的内容
+#[derive(Debug, Clone, Default)]
+pub struct MessageLog(Arc<Mutex<Vec<Message>>>);
+
+impl MessageLog {
+    fn push(&self, message: Message) {
+        self.0.lock().unwrap().push(message);
+    }
+
+    pub fn all(&self) -> Vec<Message> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn executes(&self) -> Vec<Message> {
+        self.all()
+            .into_iter()
+            .filter(|m| matches!(m.msg_type, MessageType::Execute))
+            .collect()
+    }
+
+    pub fn notifications(&self) -> Vec<Message> {
+        self.all()
+            .into_iter()
+            .filter(|m| matches!(m.msg_type, MessageType::Notify))
+            .collect()
+    }
+}
+
+/// 按脚本响应的代理，脚本用完之后回复一句固定的完成消息
+pub struct MockAgent {
+    name: String,
+    replies: VecDeque<MockReply>,
+    // 对 Notify 的响应，未设置时返回空消息
+    notify_reply: Option<MockReply>,
+    log: MessageLog,
+    event_sink: Option<AgentEventSink>,
+    // 每次执行 Execute 计入的模型用量
+    usage_per_execute: TokenUsage,
+    usage: TokenUsage,
+    // 写检查点时交出的状态，恢复时被替换为检查点中的状态
+    state: Arc<Mutex<Option<Value>>>,
+    session_files: Option<SessionFiles>,
+    uploads: Arc<Mutex<Vec<MockUpload>>>,
+}
+
+impl MockAgent {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            replies: VecDeque::new(),
+            notify_reply: None,
+            log: MessageLog::default(),
+            event_sink: None,
+            usage_per_execute: TokenUsage::default(),
+            usage: TokenUsage::default(),
+            state: Arc::new(Mutex::new(None)),
+            session_files: None,
+            uploads: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn reply(self, text: &str) -> Self {
+        self.then(MockReply::Text(text.to_string()))
+    }
+
+    pub fn reply_message(self, message: ChatMessage) -> Self {
+        self.then(MockReply::Message(message))
+    }
+
+    pub fn fail(self, error: &str) -> Self {
+        self.then(MockReply::Error(error.to_string()))
+    }
+
+    pub fn delayed_reply(self, delay: Duration, text: &str) -> Self {
+        self.then(MockReply::Delayed(delay, Box::new(MockReply::Text(text.to_string()))))
+    }
+
+    pub fn then(mut self, reply: MockReply) -> Self {
+        self.replies.push_back(reply);
+        self
+    }
+
+    // 每次执行 Execute 报告给 orchestrator 的用量
+    pub fn with_usage(mut self, usage: TokenUsage) -> Self {
+        self.usage_per_execute = usage;
+        self
+    }
+
+    // 每次收到 Notify 时的响应，例如 MockReply::Error 或 MockReply::Delayed
+    pub fn on_notify(mut self, reply: MockReply) -> Self {
+        self.notify_reply = Some(reply);
+        self
+    }
+
+    // 随检查点保存的状态，模拟浏览器的 cookies 等
+    pub fn with_state(self, state: Value) -> Self {
+        *self.state.lock().unwrap() = Some(state);
+        self
+    }
+
+    // 代理交给 orchestrator 之后查看它当前的状态（包括从检查点恢复的）
+    pub fn state(&self) -> Arc<Mutex<Option<Value>>> {
+        self.state.clone()
+    }
+
+    // 代理交给 orchestrator 之后仍然可以通过它查看收到的消息
+    pub fn log(&self) -> MessageLog {
+        self.log.clone()
+    }
+
+    // 代替浏览器控制器记录的上传
+    pub fn uploads(&self) -> Arc<Mutex<Vec<MockUpload>>> {
+        self.uploads.clone()
+    }
+
+    pub fn event_sink(&self) -> Option<&AgentEventSink> {
+        self.event_sink.as_ref()
+    }
+
+    fn text(&self, content: String) -> ChatMessage {
+        ChatMessage::new_text(MessageRole::Assistant, self.name.clone(), content)
+    }
+
+    async fn resolve(&self, mut reply: MockReply) -> Result<ChatMessage> {
+        loop {
+            match reply {
+                MockReply::Text(text) => return Ok(self.text(text)),
+                MockReply::Message(message) => return Ok(message),
+                MockReply::Error(error) => return Err(anyhow!(error)),
+                MockReply::Delayed(delay, next) => {
+                    tokio::time::sleep(delay).await;
+                    reply = *next;
+                }
+                MockReply::Events(events, next) => {
+                    if let Some(sink) = &self.event_sink {
+                        events.into_iter().for_each(|event| sink.emit(event));
+                    }
+                    reply = *next;
+                }
+                MockReply::Upload(path) => {
+                    let path = resolve_upload_path(self.session_files.as_ref(), None, &path)?;
+                    let content = tokio::fs::read(&path).await?;
+                    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                    self.uploads.lock().unwrap().push(MockUpload { path, content });
+                    return Ok(self.text(format!("Uploaded {}", name)));
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Agent for MockAgent {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_event_sink(&mut self, sink: Option<AgentEventSink>) {
+        self.event_sink = sink;
+    }
+
+    fn set_session_files(&mut self, files: Option<SessionFiles>) {
+        self.session_files = files;
+    }
+
+    fn take_usage(&mut self) -> TokenUsage {
+        std::mem::take(&mut self.usage)
+    }
+
+    async fn save_state(&mut self) -> Result<Option<Value>> {
+        Ok(self.state.lock().unwrap().clone())
+    }
+
+    async fn restore_state(&mut self, state: Value) -> Result<()> {
+        *self.state.lock().unwrap() = Some(state);
+        Ok(())
+    }
+
+    async fn on_message_stream(&mut self, message: Message) -> Result<ChatMessage> {
+        let is_execute = matches!(message.msg_type, MessageType::Execute);
+        self.log.push(message);
+        if !is_execute {
+            return match self.notify_reply.clone() {
+                Some(reply) => self.resolve(reply).await,
+                None => Ok(self.text(String::new())),
+            };
+        }
+
+        self.usage.add(&self.usage_per_execute);
+        match self.replies.pop_front() {
+            Some(reply) => self.resolve(reply).await,
+            None => Ok(self.text(format!("{} completed the instruction.", self.name))),
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,49 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::tools::chrome::{BrowserFactory, BrowserPool, BrowserPoolOptions, PooledBrowser};
+
+/// 放进 BrowserPool 的假浏览器，不启动 chromedriver；id 为创建的顺序
+#[derive(Debug, Default)]
+pub struct MockBrowser {
+    pub id: usize,
+}
+
+#[async_trait]
+impl PooledBrowser for MockBrowser {
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn reset(&self, _keep_session: bool) -> Result<()> {
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct MockBrowserFactory {
+    next_id: AtomicUsize,
+}
+
+#[async_trait]
+impl BrowserFactory<MockBrowser> for MockBrowserFactory {
+    async fn create(&self) -> Result<MockBrowser> {
+        Ok(MockBrowser { id: self.next_id.fetch_add(1, Ordering::SeqCst) })
+    }
+}
+
+/// 最多 size 个 MockBrowser 的池，借出和归还的次数见 stats
+pub fn mock_browser_pool(size: usize) -> BrowserPool<MockBrowser> {
+    BrowserPool::new(
+        Arc::new(MockBrowserFactory::default()),
+        BrowserPoolOptions { size, max_age: Duration::from_secs(3600) },
+    )
+}
@@ -0,0 +1,21 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::clients::{Embedding, TextEmbedder, EMBEDDING_DIMS};
+
+/// 按关键词落在固定维度上的嵌入，保证最近邻的顺序可预期
+pub struct KeywordEmbedder;
+
+#[async_trait]
+impl TextEmbedder for KeywordEmbedder {
+    async fn embed_text(&self, text: &str) -> Result<Embedding> {
+        let mut embedding = vec![0.0; EMBEDDING_DIMS as usize];
+        for (i, keyword) in ["train", "flight", "menu", "paris"].iter().enumerate() {
+            if text.to_lowercase().contains(keyword) {
+                embedding[i] = 1.0;
+            }
+        }
+        embedding[EMBEDDING_DIMS as usize - 1] = 0.1;
+        Ok(embedding)
+    }
+}
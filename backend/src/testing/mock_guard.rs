@@ -0,0 +1,100 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::orchestrator::message::ChatMessage;
+use crate::tools::approval_guard::{ActionGuard, StepApprovalDecision, StepApprovalRequest};
+
+/// 记录收到的审批请求，并按闭包对请求文字的判断批准或拒绝。
+/// 步骤审批优先按 with_step_decisions 给出的顺序回答，用完之后再退回到闭包
+pub struct MockGuard {
+    decide: Box<dyn Fn(&str) -> bool + Send + Sync>,
+    requests: Mutex<Vec<String>>,
+    step_decisions: Mutex<VecDeque<StepApprovalDecision>>,
+    rejection_reason: Option<String>,
+    answer: Option<String>,
+}
+
+impl MockGuard {
+    pub fn new(decide: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            decide: Box::new(decide),
+            requests: Mutex::new(Vec::new()),
+            step_decisions: Mutex::new(VecDeque::new()),
+            rejection_reason: None,
+            answer: None,
+        }
+    }
+
+    pub fn with_step_decisions(self, decisions: Vec<StepApprovalDecision>) -> Self {
+        Self { step_decisions: Mutex::new(decisions.into()), ..self }
+    }
+
+    /// 步骤被拒绝后给出的原因
+    pub fn with_rejection_reason(self, reason: &str) -> Self {
+        Self { rejection_reason: Some(reason.to_string()), ..self }
+    }
+
+    /// user_proxy 转交的提问得到的回答
+    pub fn with_answer(self, answer: &str) -> Self {
+        Self { answer: Some(answer.to_string()), ..self }
+    }
+
+    pub fn approve_all() -> Self {
+        Self::new(|_| true)
+    }
+
+    pub fn deny_all() -> Self {
+        Self::new(|_| false)
+    }
+
+    /// 目前为止收到的请求文字，按顺序
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn request_text(request: &ChatMessage) -> String {
+    match request {
+        ChatMessage::Text { content, .. } => content.clone(),
+        ChatMessage::MultiModal { .. } => String::new(),
+    }
+}
+
+impl fmt::Debug for MockGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockGuard").field("requests", &self.requests()).finish()
+    }
+}
+
+#[async_trait]
+impl ActionGuard for MockGuard {
+    async fn get_approval(&self, request: ChatMessage) -> bool {
+        let text = request_text(&request);
+        self.requests.lock().unwrap().push(text.clone());
+        (self.decide)(&text)
+    }
+
+    async fn get_step_approval(&self, request: &StepApprovalRequest) -> StepApprovalDecision {
+        let scripted = self.step_decisions.lock().unwrap().pop_front();
+        match scripted {
+            Some(decision) => {
+                self.requests.lock().unwrap().push(request_text(&request.to_chat_message()));
+                decision
+            }
+            None if self.get_approval(request.to_chat_message()).await => StepApprovalDecision::Approve,
+            None => StepApprovalDecision::Reject,
+        }
+    }
+
+    async fn get_rejection_reason(&self, _request: &StepApprovalRequest) -> Option<String> {
+        self.rejection_reason.clone()
+    }
+
+    async fn get_answer(&self, question: &ChatMessage) -> Option<String> {
+        self.requests.lock().unwrap().push(request_text(question));
+        self.answer.clone()
+    }
+}
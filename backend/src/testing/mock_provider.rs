@@ -0,0 +1,194 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use crate::clients::{ChatCompletionClient, CreateResult, FinishReason, ModelInfo, TokenUsage, ToolCall, ToolSpec};
+use crate::orchestrator::message::LLMMessage;
+
+// 一条脚本化回复，收到请求时才生成，可以根据请求内容决定回复
+type Responder = Box<dyn FnOnce(&[LLMMessage]) -> Result<CreateResult, String> + Send>;
+
+/// 按顺序返回脚本化回复的模型，记录每次收到的请求；脚本用完后返回错误
+pub struct MockProvider {
+    responses: Mutex<VecDeque<Responder>>,
+    requests: Mutex<Vec<Vec<LLMMessage>>>,
+    // 每次请求提供给模型的函数名
+    offered_tools: Mutex<Vec<Vec<String>>>,
+    model_info: ModelInfo,
+    usage: TokenUsage,
+    stream_chunk_chars: Option<usize>,
+}
+
+impl Default for MockProvider {
+    fn default() -> Self {
+        Self {
+            responses: Mutex::new(VecDeque::new()),
+            requests: Mutex::new(Vec::new()),
+            offered_tools: Mutex::new(Vec::new()),
+            model_info: ModelInfo::default(),
+            usage: TokenUsage::default(),
+            stream_chunk_chars: None,
+        }
+    }
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(self, responder: Responder) -> Self {
+        self.responses.lock().unwrap().push_back(responder);
+        self
+    }
+
+    pub fn respond(self, content: impl Into<String>) -> Self {
+        let content = content.into();
+        self.respond_with(move |_| content)
+    }
+
+    // 回复由 f 根据收到的请求生成，用来模拟按提示词内容作出不同判断的模型
+    pub fn respond_with(self, f: impl FnOnce(&[LLMMessage]) -> String + Send + 'static) -> Self {
+        self.push(Box::new(move |messages| Ok(CreateResult {
+            content: f(messages),
+            finish_reason: Some(FinishReason::Stop),
+            ..CreateResult::default()
+        })))
+    }
+
+    // 以函数调用的形式回复，arguments 为调用参数
+    pub fn respond_tool_call(self, name: &str, arguments: Value) -> Self {
+        let id = format!("call_{}", self.responses.lock().unwrap().len() + 1);
+        let tool_call = ToolCall { id, name: name.to_string(), arguments: arguments.to_string() };
+        self.push(Box::new(move |_| Ok(CreateResult {
+            tool_calls: vec![tool_call],
+            finish_reason: Some(FinishReason::ToolCalls),
+            ..CreateResult::default()
+        })))
+    }
+
+    pub fn respond_json(self, value: Value) -> Self {
+        self.respond(value.to_string())
+    }
+
+    pub fn fail(self, error: &str) -> Self {
+        let error = error.to_string();
+        self.push(Box::new(move |_| Err(error)))
+    }
+
+    pub fn with_model_info(mut self, model_info: ModelInfo) -> Self {
+        self.model_info = model_info;
+        self
+    }
+
+    // 每次调用报告的 token 用量
+    pub fn with_usage(mut self, usage: TokenUsage) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    // 流式调用时把回复切成若干字符一段依次交出
+    pub fn with_stream_chunks(mut self, chars: usize) -> Self {
+        self.stream_chunk_chars = Some(chars.max(1));
+        self
+    }
+
+    pub fn requests(&self) -> Vec<Vec<LLMMessage>> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn offered_tools(&self) -> Vec<Vec<String>> {
+        self.offered_tools.lock().unwrap().clone()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.responses.lock().unwrap().len()
+    }
+}
+
+#[async_trait]
+impl ChatCompletionClient for MockProvider {
+    fn model_info(&self) -> ModelInfo {
+        self.model_info
+    }
+
+    async fn create(&self, messages: &[LLMMessage]) -> Result<CreateResult> {
+        self.create_with_tools(messages, &[]).await
+    }
+
+    async fn create_with_tools(&self, messages: &[LLMMessage], tools: &[ToolSpec]) -> Result<CreateResult> {
+        self.requests.lock().unwrap().push(messages.to_vec());
+        self.offered_tools.lock().unwrap().push(tools.iter().map(|tool| tool.name.clone()).collect());
+        let responder = self
+            .responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| anyhow!("MockProvider has no scripted response left"))?;
+        match responder(messages) {
+            Ok(result) => Ok(CreateResult { usage: self.usage, ..result }),
+            Err(error) => Err(anyhow!(error)),
+        }
+    }
+
+    async fn create_stream(
+        &self,
+        messages: &[LLMMessage],
+        tools: &[ToolSpec],
+        on_chunk: &(dyn for<'a> Fn(&'a str) + Send + Sync),
+    ) -> Result<CreateResult> {
+        let result = self.create_with_tools(messages, tools).await?;
+        let texts = std::iter::once(&result.content).chain(result.tool_calls.iter().map(|call| &call.arguments));
+        for text in texts {
+            let chars: Vec<char> = text.chars().collect();
+            for chunk in chars.chunks(self.stream_chunk_chars.unwrap_or(chars.len().max(1))) {
+                on_chunk(&chunk.iter().collect::<String>());
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// 规划调用的回复，steps 为 (title, details, agent_name)
+pub fn plan_json(task: &str, steps: &[(&str, &str, &str)]) -> Value {
+    let steps: Vec<Value> = steps
+        .iter()
+        .map(|(title, details, agent_name)| json!({
+            "title": title,
+            "details": details,
+            "agent_name": agent_name,
+        }))
+        .collect();
+    let plan_summary = format!("A plan with {} step(s)", steps.len());
+    json!({
+        "task": task,
+        "steps": steps,
+        "needs_plan": true,
+        "response": "",
+        "plan_summary": plan_summary,
+    })
+}
+
+/// 不需要计划、直接回答的规划回复
+pub fn direct_answer_json(task: &str, response: &str) -> Value {
+    json!({
+        "task": task,
+        "steps": [],
+        "needs_plan": false,
+        "response": response,
+        "plan_summary": "",
+    })
+}
+
+/// ledger 评估的回复
+pub fn ledger_json(step_complete: bool, need_to_replan: bool, agent_name: &str, instruction: &str) -> Value {
+    json!({
+        "is_current_step_complete": { "reason": "scripted", "answer": step_complete },
+        "need_to_replan": { "reason": "scripted", "answer": need_to_replan },
+        "instruction_or_question": { "answer": instruction, "agent_name": agent_name },
+        "progress_summary": format!("Asked {} to: {}", agent_name, instruction),
+    })
+}
\ No newline at end of file
@@ -0,0 +1,14 @@
+// 编排器集成测试用的脚本化代理和模型，不访问网络也不启动浏览器
+pub mod mock_agent;
+pub mod mock_browser;
+pub mod mock_embedder;
+pub mod mock_guard;
+pub mod mock_provider;
+pub mod builder;
+
+pub use mock_agent::{MessageLog, MockAgent, MockReply, MockUpload};
+pub use mock_browser::{mock_browser_pool, MockBrowser};
+pub use mock_embedder::KeywordEmbedder;
+pub use mock_guard::MockGuard;
+pub use mock_provider::{direct_answer_json, ledger_json, plan_json, MockProvider};
+pub use builder::{test_config, OrchestratorBuilder};
\ No newline at end of file
@@ -0,0 +1,606 @@
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::orchestrator::message::{ChatMessage, MessageRole};
+use crate::orchestrator::plan::Plan;
+use crate::orchestrator::validation::PlanValidationLimits;
+use crate::tools::url_status_manager::{UrlStatus, UrlStatusManager};
+
+lazy_static::lazy_static! {
+    // 名称像提交、购买、删除的控件，点击之后通常无法撤销
+    static ref IRREVERSIBLE_TARGET: Regex =
+        Regex::new(r"(?i)\b(submit|buy|purchase|checkout|check out|place order|pay|delete|remove)\b").unwrap();
+}
+
+/// 人工审批的统一抽象：URL 审批（web_agent）和步骤审批（orchestrator）都经过这里，
+/// 具体由 CLI / 后端等前端实现如何向用户展示
+#[async_trait]
+pub trait ActionGuard: Send + Sync + Debug {
+    /// 对一条请求做是/否审批
+    async fn get_approval(&self, request: ChatMessage) -> bool;
+
+    /// 计划步骤分发之前的审批，默认退化为 get_approval 的是/否
+    async fn get_step_approval(&self, request: &StepApprovalRequest) -> StepApprovalDecision {
+        if self.get_approval(request.to_chat_message()).await {
+            StepApprovalDecision::Approve
+        } else {
+            StepApprovalDecision::Reject
+        }
+    }
+
+    /// 步骤被拒绝后询问一个可选的原因，默认不询问
+    async fn get_rejection_reason(&self, _request: &StepApprovalRequest) -> Option<String> {
+        None
+    }
+
+    /// 执行前的计划审批，默认退化为 get_approval 的是/否，不提供编辑
+    async fn get_plan_approval(&self, request: &PlanApprovalRequest) -> PlanApprovalDecision {
+        if self.get_approval(request.message.clone()).await {
+            PlanApprovalDecision::Approve
+        } else {
+            PlanApprovalDecision::Reject
+        }
+    }
+
+    /// user_proxy 转交的提问（如规划前的追问），返回用户的回答；默认不询问，交给模型自行假设
+    async fn get_answer(&self, _question: &ChatMessage) -> Option<String> {
+        None
+    }
+}
+
+/// 发送给 guard 的计划审批请求；编辑后的计划要按 team、sentinel_enabled 和 limits 重新校验
+#[derive(Debug, Clone)]
+pub struct PlanApprovalRequest {
+    /// 展示给用户的步骤列表和开销估算
+    pub message: ChatMessage,
+    pub plan: Plan,
+    pub team: Vec<String>,
+    pub sentinel_enabled: bool,
+    pub limits: PlanValidationLimits,
+    /// 重新生成之前的计划，用来展示新旧计划的差异；第一次审批时为 None
+    pub previous_plan: Option<Plan>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlanApprovalDecision {
+    Approve,
+    Reject,
+    /// 用户修改了计划并批准修改后的计划
+    Edited(Plan),
+    /// 按用户给出的原因（可以没有）重新生成计划，新计划再次审批
+    Regenerate(Option<String>),
+}
+
+/// 发送给 guard 的步骤审批请求
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StepApprovalRequest {
+    pub step_index: usize,
+    pub step_title: String,
+    pub step_details: String,
+    pub agent_name: String,
+    pub instruction: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum StepApprovalDecision {
+    Approve,
+    Reject,
+    /// 用户改写了要发送给代理的指令
+    EditInstruction(String),
+}
+
+/// 审批流程的最终结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepGateOutcome {
+    Approved { instruction: String, edited: bool },
+    Rejected { reason: Option<String> },
+}
+
+impl StepApprovalRequest {
+    pub fn to_chat_message(&self) -> ChatMessage {
+        ChatMessage::new_text(
+            MessageRole::User,
+            "orchestrator".to_string(),
+            format!(
+                "Step {}: {}\n\n{}\n\nInstruction for {}: {}\n\nDo you approve this step?",
+                self.step_index + 1,
+                self.step_title,
+                self.step_details,
+                self.agent_name,
+                self.instruction,
+            ),
+        )
+    }
+}
+
+/// 走一遍步骤审批：批准 / 编辑指令 / 拒绝（拒绝时再询问原因）
+pub async fn gate_step(guard: &dyn ActionGuard, request: &StepApprovalRequest) -> StepGateOutcome {
+    match guard.get_step_approval(request).await {
+        StepApprovalDecision::Approve => StepGateOutcome::Approved {
+            instruction: request.instruction.clone(),
+            edited: false,
+        },
+        StepApprovalDecision::EditInstruction(text) => {
+            // 编辑成空字符串视为没有修改
+            if text.trim().is_empty() {
+                StepGateOutcome::Approved {
+                    instruction: request.instruction.clone(),
+                    edited: false,
+                }
+            } else {
+                StepGateOutcome::Approved {
+                    instruction: text.trim().to_string(),
+                    edited: true,
+                }
+            }
+        }
+        StepApprovalDecision::Reject => {
+            let reason = guard
+                .get_rejection_reason(request)
+                .await
+                .map(|r| r.trim().to_string())
+                .filter(|r| !r.is_empty());
+            StepGateOutcome::Rejected { reason }
+        }
+    }
+}
+
+/* 访问不在允许列表中的网站之前询问是否允许整个域名。没有 guard 时拒绝；
+结果记录在 statuses 中，同一会话之后访问这个域名不再询问 */
+pub async fn gate_domain(
+    guard: Option<&dyn ActionGuard>,
+    statuses: &mut UrlStatusManager,
+    requester: &str,
+    url: &str,
+    domain: &str,
+) -> bool {
+    let approved = match guard {
+        Some(guard) => {
+            let request = ChatMessage::new_text(
+                MessageRole::User,
+                requester.to_string(),
+                format!(
+                    "The website {} is not allowed. Would you like to allow the domain {} for this session?",
+                    url, domain
+                ),
+            );
+            guard.get_approval(request).await
+        }
+        None => false,
+    };
+    let status = if approved { UrlStatus::Allowed } else { UrlStatus::Rejected };
+    statuses.set_url_status(domain, status);
+    approved
+}
+
+/* web_surfer 的工具调用是否无法撤销：按回车提交的输入，以及点击名称像提交、购买、删除的控件。
+target_name 是点击目标的无障碍名称 */
+pub fn is_irreversible_action(tool: &str, args: &Value, target_name: Option<&str>) -> bool {
+    match tool {
+        "input_text" => args.get("press_enter").and_then(Value::as_bool).unwrap_or(false),
+        "click" | "click_full" => target_name.is_some_and(|name| IRREVERSIBLE_TARGET.is_match(name)),
+        _ => false,
+    }
+}
+
+// 审批请求中输入文字的预览长度（字符）
+const TEXT_PREVIEW_CHARS: usize = 80;
+
+/* 审批无法撤销的动作时展示给用户的说明：工具、目标控件的名称、当前页面和要输入的文字（过长时截断）。
+target_name 为空时退回到参数中的控件编号 */
+pub fn describe_action(tool: &str, args: &Value, target_name: Option<&str>, page_url: Option<&str>) -> String {
+    let mut lines = vec![format!("Action: {}", tool)];
+    let target_id = ["target_id", "input_field_id"]
+        .iter()
+        .find_map(|key| args.get(*key))
+        .map(|id| id.as_str().map(str::to_string).unwrap_or_else(|| id.to_string()));
+    match (target_name, target_id) {
+        (Some(name), _) => lines.push(format!("Element: {}", name)),
+        (None, Some(id)) => lines.push(format!("Element: #{}", id)),
+        (None, None) => {}
+    }
+    if let Some(url) = page_url {
+        lines.push(format!("Page: {}", url));
+    }
+    if let Some(text) = args.get("text_value").and_then(Value::as_str) {
+        let preview: String = text.chars().take(TEXT_PREVIEW_CHARS).collect();
+        let ellipsis = if text.chars().count() > TEXT_PREVIEW_CHARS { "…" } else { "" };
+        let enter = if args.get("press_enter").and_then(Value::as_bool).unwrap_or(false) { " (then press Enter)" } else { "" };
+        lines.push(format!("Text: \"{}{}\"{}", preview, ellipsis, enter));
+    }
+    lines.join("\n")
+}
+
+/// 执行无法撤销的动作之前询问，没有 guard 时直接执行
+pub async fn gate_action(guard: Option<&dyn ActionGuard>, requester: &str, description: &str) -> bool {
+    let Some(guard) = guard else {
+        return true;
+    };
+    let request = ChatMessage::new_text(
+        MessageRole::User,
+        requester.to_string(),
+        format!("{} wants to perform an action that may not be reversible:\n\n{}\n\nDo you approve?", requester, description),
+    );
+    guard.get_approval(request).await
+}
+
+/// 审批策略：全部询问、只询问有风险的操作、从不询问
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApprovalPolicy {
+    #[default]
+    Always,
+    AutoConservative,
+    Never,
+}
+
+impl ApprovalPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ApprovalPolicy::Always => "always",
+            ApprovalPolicy::AutoConservative => "auto-conservative",
+            ApprovalPolicy::Never => "never",
+        }
+    }
+}
+
+impl FromStr for ApprovalPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().replace('_', "-").as_str() {
+            "always" => Ok(ApprovalPolicy::Always),
+            "auto-conservative" => Ok(ApprovalPolicy::AutoConservative),
+            "never" => Ok(ApprovalPolicy::Never),
+            other => Err(anyhow!(
+                "Unknown approval policy '{}', expected one of: always, auto-conservative, never",
+                other
+            )),
+        }
+    }
+}
+
+/* 按审批策略决定哪些请求交给用户：
+- always：所有请求（URL、计划、步骤）都交给 prompt
+- auto-conservative：计划中的步骤自动批准，URL、计划等其他请求仍然交给 prompt
+- never：不询问用户，需要审批的请求一律拒绝并记录日志，approve_all 时一律批准
+没有 prompt（非交互运行）时按 never 处理 */
+#[derive(Debug)]
+pub struct PolicyGuard {
+    policy: ApprovalPolicy,
+    prompt: Option<Arc<dyn ActionGuard>>,
+    approve_all: bool,
+}
+
+impl PolicyGuard {
+    pub fn new(policy: ApprovalPolicy, prompt: Option<Arc<dyn ActionGuard>>, approve_all: bool) -> Self {
+        Self { policy, prompt, approve_all }
+    }
+
+    pub fn policy(&self) -> ApprovalPolicy {
+        self.policy
+    }
+
+    // 需要询问用户时返回 prompt
+    fn prompt(&self) -> Option<&Arc<dyn ActionGuard>> {
+        self.prompt.as_ref().filter(|_| self.policy != ApprovalPolicy::Never)
+    }
+
+    fn decide_without_asking(&self, request: &str) -> bool {
+        let summary = request.lines().find(|line| !line.trim().is_empty()).unwrap_or_default();
+        if self.approve_all {
+            tracing::info!("Approved without asking (approve all): {}", summary);
+        } else {
+            tracing::warn!("Denied without asking (approval policy {}): {}", self.policy.name(), summary);
+        }
+        self.approve_all
+    }
+}
+
+#[async_trait]
+impl ActionGuard for PolicyGuard {
+    async fn get_approval(&self, request: ChatMessage) -> bool {
+        match self.prompt() {
+            Some(prompt) => prompt.get_approval(request).await,
+            None => {
+                let text = match &request {
+                    ChatMessage::Text { content, .. } => content.clone(),
+                    ChatMessage::MultiModal { .. } => String::new(),
+                };
+                self.decide_without_asking(&text)
+            }
+        }
+    }
+
+    async fn get_step_approval(&self, request: &StepApprovalRequest) -> StepApprovalDecision {
+        if self.policy == ApprovalPolicy::AutoConservative {
+            return StepApprovalDecision::Approve;
+        }
+        let approved = match self.prompt() {
+            Some(prompt) => return prompt.get_step_approval(request).await,
+            None => self.decide_without_asking(&format!("step {}: {}", request.step_index + 1, request.step_title)),
+        };
+        if approved {
+            StepApprovalDecision::Approve
+        } else {
+            StepApprovalDecision::Reject
+        }
+    }
+
+    async fn get_rejection_reason(&self, request: &StepApprovalRequest) -> Option<String> {
+        match self.prompt() {
+            Some(prompt) => prompt.get_rejection_reason(request).await,
+            None => Some(format!("denied by the {} approval policy", self.policy.name())),
+        }
+    }
+
+    async fn get_plan_approval(&self, request: &PlanApprovalRequest) -> PlanApprovalDecision {
+        match self.prompt() {
+            Some(prompt) => prompt.get_plan_approval(request).await,
+            None if self.get_approval(request.message.clone()).await => PlanApprovalDecision::Approve,
+            None => PlanApprovalDecision::Reject,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockGuard;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[derive(Debug)]
+    struct ScriptedGuard {
+        decision: StepApprovalDecision,
+        reason: Option<String>,
+        asked_reason: Mutex<bool>,
+    }
+
+    #[async_trait]
+    impl ActionGuard for ScriptedGuard {
+        async fn get_approval(&self, _request: ChatMessage) -> bool {
+            self.decision == StepApprovalDecision::Approve
+        }
+
+        async fn get_step_approval(&self, _request: &StepApprovalRequest) -> StepApprovalDecision {
+            self.decision.clone()
+        }
+
+        async fn get_rejection_reason(&self, _request: &StepApprovalRequest) -> Option<String> {
+            *self.asked_reason.lock().unwrap() = true;
+            self.reason.clone()
+        }
+    }
+
+    fn guard(decision: StepApprovalDecision, reason: Option<&str>) -> ScriptedGuard {
+        ScriptedGuard {
+            decision,
+            reason: reason.map(|r| r.to_string()),
+            asked_reason: Mutex::new(false),
+        }
+    }
+
+    fn request() -> StepApprovalRequest {
+        StepApprovalRequest {
+            step_index: 0,
+            step_title: "Search flights".to_string(),
+            step_details: "Search flights to Tokyo on Bing.".to_string(),
+            agent_name: "web_surfer".to_string(),
+            instruction: "Search Bing for flights to Tokyo".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gate_step_approve() {
+        let g = guard(StepApprovalDecision::Approve, None);
+        let outcome = gate_step(&g, &request()).await;
+        assert_eq!(
+            outcome,
+            StepGateOutcome::Approved {
+                instruction: "Search Bing for flights to Tokyo".to_string(),
+                edited: false,
+            }
+        );
+        assert!(!*g.asked_reason.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_gate_step_reject_with_reason() {
+        let g = guard(StepApprovalDecision::Reject, Some("  use Google Flights instead "));
+        let outcome = gate_step(&g, &request()).await;
+        assert_eq!(
+            outcome,
+            StepGateOutcome::Rejected {
+                reason: Some("use Google Flights instead".to_string()),
+            }
+        );
+        assert!(*g.asked_reason.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_gate_step_reject_without_reason() {
+        let g = guard(StepApprovalDecision::Reject, Some("   "));
+        let outcome = gate_step(&g, &request()).await;
+        assert_eq!(outcome, StepGateOutcome::Rejected { reason: None });
+    }
+
+    #[tokio::test]
+    async fn test_gate_step_edit_instruction() {
+        let g = guard(
+            StepApprovalDecision::EditInstruction("Search Google Flights for Tokyo".to_string()),
+            None,
+        );
+        let outcome = gate_step(&g, &request()).await;
+        assert_eq!(
+            outcome,
+            StepGateOutcome::Approved {
+                instruction: "Search Google Flights for Tokyo".to_string(),
+                edited: true,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_default_step_approval_falls_back_to_get_approval() {
+        #[derive(Debug)]
+        struct YesGuard;
+
+        #[async_trait]
+        impl ActionGuard for YesGuard {
+            async fn get_approval(&self, request: ChatMessage) -> bool {
+                match request {
+                    ChatMessage::Text { content, .. } => content.contains("Search flights"),
+                    _ => false,
+                }
+            }
+        }
+
+        let outcome = gate_step(&YesGuard, &request()).await;
+        assert!(matches!(outcome, StepGateOutcome::Approved { edited: false, .. }));
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingGuard {
+        approvals: Mutex<usize>,
+        steps: Mutex<usize>,
+    }
+
+    #[async_trait]
+    impl ActionGuard for CountingGuard {
+        async fn get_approval(&self, _request: ChatMessage) -> bool {
+            *self.approvals.lock().unwrap() += 1;
+            true
+        }
+
+        async fn get_step_approval(&self, _request: &StepApprovalRequest) -> StepApprovalDecision {
+            *self.steps.lock().unwrap() += 1;
+            StepApprovalDecision::Approve
+        }
+    }
+
+    fn url_request() -> ChatMessage {
+        ChatMessage::new_text(
+            MessageRole::Assistant,
+            "web_surfer".to_string(),
+            "Visit https://example.com/checkout?\nType 'my address' into the Address field".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_parse_approval_policy() {
+        assert_eq!("always".parse::<ApprovalPolicy>().unwrap(), ApprovalPolicy::Always);
+        assert_eq!("Auto-Conservative".parse::<ApprovalPolicy>().unwrap(), ApprovalPolicy::AutoConservative);
+        assert_eq!("auto_conservative".parse::<ApprovalPolicy>().unwrap(), ApprovalPolicy::AutoConservative);
+        assert_eq!(" never ".parse::<ApprovalPolicy>().unwrap(), ApprovalPolicy::Never);
+        let error = "sometimes".parse::<ApprovalPolicy>().unwrap_err();
+        assert!(error.to_string().contains("expected one of: always, auto-conservative, never"));
+    }
+
+    #[tokio::test]
+    async fn test_always_policy_asks_for_everything() {
+        let prompt = Arc::new(CountingGuard::default());
+        let guard = PolicyGuard::new(ApprovalPolicy::Always, Some(prompt.clone()), false);
+
+        assert!(guard.get_approval(url_request()).await);
+        assert!(matches!(gate_step(&guard, &request()).await, StepGateOutcome::Approved { .. }));
+        assert_eq!(*prompt.approvals.lock().unwrap(), 1);
+        assert_eq!(*prompt.steps.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_auto_conservative_policy_approves_steps_only() {
+        let prompt = Arc::new(CountingGuard::default());
+        let guard = PolicyGuard::new(ApprovalPolicy::AutoConservative, Some(prompt.clone()), false);
+
+        assert!(matches!(gate_step(&guard, &request()).await, StepGateOutcome::Approved { .. }));
+        assert!(guard.get_approval(url_request()).await);
+        assert_eq!(*prompt.steps.lock().unwrap(), 0);
+        assert_eq!(*prompt.approvals.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_never_policy_denies_unless_approve_all() {
+        let prompt = Arc::new(CountingGuard::default());
+        let guard = PolicyGuard::new(ApprovalPolicy::Never, Some(prompt.clone()), false);
+        assert!(!guard.get_approval(url_request()).await);
+        assert_eq!(
+            gate_step(&guard, &request()).await,
+            StepGateOutcome::Rejected { reason: Some("denied by the never approval policy".to_string()) }
+        );
+        assert_eq!(*prompt.approvals.lock().unwrap(), 0);
+
+        let guard = PolicyGuard::new(ApprovalPolicy::Never, None, true);
+        assert!(guard.get_approval(url_request()).await);
+        assert!(matches!(gate_step(&guard, &request()).await, StepGateOutcome::Approved { .. }));
+
+        // 非交互运行时 always 也不会询问
+        let guard = PolicyGuard::new(ApprovalPolicy::Always, None, false);
+        assert!(!guard.get_approval(url_request()).await);
+    }
+
+    #[tokio::test]
+    async fn test_gate_domain_records_the_decision() {
+        let mut statuses = UrlStatusManager::new(Some(HashMap::new()), None);
+        let guard = MockGuard::new(|request| request.contains("example.com"));
+
+        assert!(gate_domain(Some(&guard), &mut statuses, "web_surfer", "https://shop.example.com/cart", "example.com").await);
+        assert!(statuses.is_url_allowed("https://example.com/checkout"));
+
+        assert!(!gate_domain(Some(&guard), &mut statuses, "web_surfer", "https://evil.com/", "evil.com").await);
+        assert!(statuses.is_url_rejected("https://evil.com/login"));
+        assert!(!statuses.is_url_allowed("https://evil.com/login"));
+
+        let requests = guard.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].contains("allow the domain example.com for this session"));
+
+        // 没有 guard 时拒绝，也记录下来
+        assert!(!gate_domain(None, &mut statuses, "web_surfer", "https://other.org/", "other.org").await);
+        assert!(statuses.is_url_rejected("https://other.org/"));
+    }
+
+    #[tokio::test]
+    async fn test_irreversible_actions_need_approval() {
+        assert!(is_irreversible_action("input_text", &json!({ "press_enter": true }), None));
+        assert!(!is_irreversible_action("input_text", &json!({ "press_enter": false }), None));
+        assert!(is_irreversible_action("click", &json!({}), Some("Buy now")));
+        assert!(is_irreversible_action("click_full", &json!({}), Some("Delete account")));
+        assert!(!is_irreversible_action("click", &json!({}), Some("Next page")));
+        assert!(!is_irreversible_action("click", &json!({}), None));
+        assert!(!is_irreversible_action("scroll_down", &json!({}), Some("Submit")));
+
+        let guard = MockGuard::new(|request| !request.contains("Delete"));
+        assert!(gate_action(Some(&guard), "web_surfer", "Click 'Buy now'").await);
+        assert!(!gate_action(Some(&guard), "web_surfer", "Click 'Delete account'").await);
+        assert_eq!(guard.requests().len(), 2);
+        assert!(guard.requests()[1].starts_with("web_surfer wants to perform an action that may not be reversible"));
+
+        assert!(gate_action(None, "web_surfer", "Click 'Delete account'").await);
+    }
+
+    #[test]
+    fn test_describe_action_shows_the_element_page_and_text() {
+        let args = json!({ "input_field_id": 12, "text_value": "rust async book", "press_enter": true });
+        assert_eq!(
+            describe_action("input_text", &args, Some("Search"), Some("https://example.com/")),
+            "Action: input_text\nElement: Search\nPage: https://example.com/\nText: \"rust async book\" (then press Enter)"
+        );
+        // 没有名称时显示控件编号，过长的文字截断
+        let long = "a".repeat(100);
+        let description = describe_action("input_text", &json!({ "input_field_id": 7, "text_value": long }), None, None);
+        assert_eq!(description, format!("Action: input_text\nElement: #7\nText: \"{}…\"", "a".repeat(80)));
+        assert_eq!(
+            describe_action("click", &json!({ "target_id": "42" }), Some("Buy now"), Some("https://shop.example.com/cart")),
+            "Action: click\nElement: Buy now\nPage: https://shop.example.com/cart"
+        );
+    }
+}
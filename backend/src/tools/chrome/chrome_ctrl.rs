@@ -1,20 +1,29 @@
-use std::sync::Arc;
-use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use anyhow::{ Result, Context };
 use serde_json;
 use tokio::fs;
 use serde_json::Value;
-use thirtyfour::{DesiredCapabilities, WebDriver, WindowHandle};
+use thirtyfour::{ChromeCapabilities, DesiredCapabilities, WebDriver, WindowHandle};
+use thirtyfour::extensions::cdp::ChromeDevTools;
 use thirtyfour::prelude::*;
 use tokio::time::sleep;
 use std::collections::HashMap;
 
 
+use crate::config::BrowserSettings;
+use crate::tools::chrome::downloads::{self, DownloadedFile, VisitOutcome};
+use crate::tools::chrome::launcher::ChromedriverProcess;
 use crate::tools::utils::animation_utils::AnimationUtils;
 use crate::tools::utils::webpage_text_utils::{WebpageTextUtils};
 use crate::tools::chrome::types::{InteractiveRegion, VisualViewport, PageMetadata, TabInfo};
 
+// 导航之后等待下载开始的时间，没有出现新文件时视为普通页面
+const DOWNLOAD_START_TIMEOUT: Duration = Duration::from_secs(2);
+// 下载完成的最长等待时间
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
 /// Chrome 浏览器控制器
 #[derive(Debug)]
 pub struct Chrome {
@@ -22,34 +31,198 @@ pub struct Chrome {
     anim_utils: AnimationUtils,
     animate_actions: bool,
     single_tab_mode: bool,
+    // 下载保存的目录，没有设置时不识别下载
+    downloads_dir: Option<PathBuf>,
+    // 由这个 Chrome 启动的 chromedriver，quit 或 drop 时随之结束
+    chromedriver: Option<ChromedriverProcess>,
+    // 代理正在操作的标签页，用户手动打开的标签页不会成为被控制的标签页
+    controlled_handle: Mutex<Option<WindowHandle>>,
+}
+
+/// 创建 Chrome 的配置，Default 连接本地的 chromedriver 并停在空白页
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChromeConfig {
+    pub webdriver_url: String,
+    // 打开后访问的页面，None 时停在 about:blank
+    pub start_page: Option<String>,
+    pub headless: bool,
+    // 窗口大小（宽，高），None 时使用 Chrome 的默认大小
+    pub window_size: Option<(u32, u32)>,
+    pub animate_actions: bool,
+    pub single_tab_mode: bool,
+    // 浏览器的用户数据目录，None 时每个会话使用临时目录
+    pub user_data_dir: Option<PathBuf>,
+    // 下载保存的目录，设置时下载不再询问，直接保存到这个目录
+    pub downloads_dir: Option<PathBuf>,
+    // webdriver_url 不可访问时在空闲端口上启动 chromedriver（CHROMEDRIVER_PATH 或 PATH 中的）
+    pub auto_spawn_chromedriver: bool,
+}
+
+impl Default for ChromeConfig {
+    fn default() -> Self {
+        Self {
+            webdriver_url: "http://localhost:9515".to_string(),
+            start_page: None,
+            headless: false,
+            window_size: None,
+            animate_actions: true,
+            single_tab_mode: true,
+            user_data_dir: None,
+            downloads_dir: None,
+            auto_spawn_chromedriver: false,
+        }
+    }
+}
+
+impl ChromeConfig {
+    /// 按 [browser] 配置，viewport_width / viewport_height 都设置时作为窗口大小
+    pub fn from_settings(settings: &BrowserSettings) -> Self {
+        Self {
+            webdriver_url: settings.webdriver_url.clone(),
+            start_page: settings.start_page.clone(),
+            headless: settings.headless,
+            window_size: settings
+                .viewport_width
+                .zip(settings.viewport_height)
+                .map(|(width, height)| (width as u32, height as u32)),
+            downloads_dir: settings.downloads_folder.as_ref().map(PathBuf::from),
+            auto_spawn_chromedriver: settings.auto_spawn_chromedriver,
+            ..Self::default()
+        }
+    }
+
+    // 发送给 chromedriver 的 capabilities
+    pub fn capabilities(&self) -> Result<ChromeCapabilities> {
+        let mut caps = DesiredCapabilities::chrome();
+        if self.headless {
+            caps.add_arg("--headless=new")?;
+        }
+        if let Some((width, height)) = self.window_size {
+            caps.add_arg(&format!("--window-size={},{}", width, height))?;
+        }
+        if let Some(dir) = &self.user_data_dir {
+            caps.add_arg(&format!("--user-data-dir={}", dir.display()))?;
+        }
+        if let Some(dir) = &self.downloads_dir {
+            // PDF 也下载而不是在内置的查看器中打开
+            caps.add_experimental_option("prefs", serde_json::json!({
+                "download.default_directory": dir,
+                "download.prompt_for_download": false,
+                "download.directory_upgrade": true,
+                "plugins.always_open_pdf_externally": true,
+            }))?;
+        }
+        Ok(caps)
+    }
 }
 
 impl Chrome {
+    // 连接本地的 chromedriver 并打开 Google
     pub async fn new() -> Result<Self> {
-        let caps = DesiredCapabilities::chrome();
-        let driver = WebDriver::new("http://localhost:9515", caps).await?;
+        Self::with_config(ChromeConfig {
+            start_page: Some("https://www.google.com".to_string()),
+            ..ChromeConfig::default()
+        })
+        .await
+    }
 
-        driver.get("https://www.google.com").await?;
+    // 按配置连接 chromedriver 新建一个会话，打开 start_page（没有时停在空白页）
+    pub async fn with_config(mut config: ChromeConfig) -> Result<Self> {
+        if let Some(dir) = &config.downloads_dir {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create download folder {}", dir.display()))?;
+            config.downloads_dir = Some(dir.canonicalize()?);
+        }
+        let chromedriver = match config.auto_spawn_chromedriver {
+            true => ChromedriverProcess::ensure(&config.webdriver_url).await?,
+            false => None,
+        };
+        let webdriver_url = chromedriver.as_ref().map_or(config.webdriver_url.as_str(), |process| process.url());
+        let caps = config.capabilities()?;
+        let driver = WebDriver::new(webdriver_url, caps).await.with_context(|| {
+            format!(
+                "Failed to connect to chromedriver at {}. Start it with `chromedriver --port=9515` \
+                 or enable browser.auto_spawn_chromedriver",
+                webdriver_url
+            )
+        })?;
+        if let Some(page) = &config.start_page {
+            driver.goto(page).await?;
+        }
+        let controlled_handle = driver.window().await?;
 
         Ok(Self { 
             driver: Arc::new(driver),
             anim_utils: AnimationUtils::new(),
-            animate_actions: true,
-            single_tab_mode: true,
+            animate_actions: config.animate_actions,
+            single_tab_mode: config.single_tab_mode,
+            downloads_dir: config.downloads_dir,
+            chromedriver,
+            controlled_handle: Mutex::new(Some(controlled_handle)),
         })
     }
 
+    // 恢复到干净的状态：只保留第一个标签页并回到 about:blank，keep_session 为 false 时清除 cookie 和 storage
+    pub async fn reset_state(&self, keep_session: bool) -> Result<()> {
+        let handles = self.driver.windows().await?;
+        for handle in handles.iter().skip(1) {
+            self.driver.switch_to_window(handle.clone()).await?;
+            self.driver.close_window().await?;
+        }
+        if let Some(first) = handles.first() {
+            self.driver.switch_to_window(first.clone()).await?;
+        }
+        self.set_controlled(handles.first().cloned());
+        if !keep_session {
+            self.driver.delete_all_cookies().await?;
+            // about:blank 上不能访问 storage，先在当前页面清除
+            self.driver
+                .execute("try { localStorage.clear(); sessionStorage.clear(); } catch (e) {}", vec![])
+                .await?;
+        }
+        self.driver.goto("about:blank").await?;
+        Ok(())
+    }
+
+    // 结束 chromedriver 会话并关闭浏览器，chromedriver 是自己启动的时一并结束
+    pub async fn quit(&self) -> Result<()> {
+        let result = (*self.driver).clone().quit().await;
+        if let Some(process) = &self.chromedriver {
+            process.kill();
+        }
+        result?;
+        Ok(())
+    }
+
     pub async fn sleep(&self, duration: u64) -> Result<()> {
         self.wait_for_page_ready().await?;
         sleep(Duration::from_millis(duration)).await;
         Ok(())
     }
 
-    // 导航到指定的URL(而且智能处理下载文件，将下载的文件保存到指定的文件夹，并显示确认的页面) 暂不进行实现下载逻辑
-    pub async fn visit_page(&self, url: &str) -> Result<bool> {
-        let _ =  self.wait_for_page_ready();
-        self.driver.get(url).await?;
-        Ok(true)
+    /* 导航到指定的URL。设置了下载目录时识别导航触发的下载：地址没有变化而目录中出现了新文件，
+    等待下载完成（最多 DOWNLOAD_TIMEOUT）后返回本地文件的路径 */
+    pub async fn visit_page(&self, url: &str) -> Result<VisitOutcome> {
+        let _ = self.wait_for_page_ready().await;
+        let Some(dir) = &self.downloads_dir else {
+            self.driver.goto(url).await?;
+            return Ok(VisitOutcome::Page);
+        };
+        let before = downloads::snapshot(dir)?;
+        let url_before = self.get_url().await?;
+        self.driver.goto(url).await?;
+        // 打开了新页面
+        let url_after = self.get_url().await?;
+        if url_after != url_before && url_after.trim_end_matches('/') != "about:blank" {
+            return Ok(VisitOutcome::Page);
+        }
+        match downloads::wait_for_download(dir, &before, DOWNLOAD_START_TIMEOUT, DOWNLOAD_TIMEOUT).await? {
+            Some(path) => {
+                let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                Ok(VisitOutcome::Download(DownloadedFile { url: url.to_string(), file_name, path }))
+            }
+            None => Ok(VisitOutcome::Page),
+        }
     }
 
     pub async fn get_url(&self) -> Result<String> {
@@ -79,7 +252,9 @@ impl Chrome {
     }
 
     /// 标签页的管理
-    pub async fn new_tab(&self, url: &str) -> Result<WindowHandle> {
+    /* 打开新的标签页。focus 为 true 时切换过去，新的标签页成为被控制的标签页（代理的 visit_url 新标签页即如此）；
+    为 false 时和以前一样只在后台打开，当前标签页和被控制的标签页都不变 */
+    pub async fn new_tab(&self, url: &str, focus: bool) -> Result<WindowHandle> {
         let url = url.trim();
         self.driver
             .execute(&format!("window.open('{}', '_blank');", url), vec![])
@@ -89,9 +264,22 @@ impl Chrome {
         let handle = handles.last().ok_or_else(|| {
             anyhow::anyhow!("Failed to get last window handle")
         })?;
+        if focus {
+            self.driver.switch_to_window(handle.clone()).await?;
+            self.set_controlled(Some(handle.clone()));
+        }
         Ok(handle.clone())
     }
 
+    // 代理正在操作的标签页
+    pub fn controlled_handle(&self) -> Option<WindowHandle> {
+        self.controlled_handle.lock().unwrap().clone()
+    }
+
+    fn set_controlled(&self, handle: Option<WindowHandle>) {
+        *self.controlled_handle.lock().unwrap() = handle;
+    }
+
     // 获取标签页所有信息
     /* 
     返回一个包含所有标签页信息的列表，每个标签页信息包含：
@@ -99,53 +287,70 @@ impl Chrome {
     title: 标签页的标题
     url: 标签页的URL
     is_active: 标签页是否当前可见
-    is_controlled: 标签页是否被当前控制
+    is_controlled: 标签页是否是代理正在操作的标签页
      */
     pub async fn get_tabs_information(&self) -> Result<Vec<TabInfo>> {
         let handles = self.driver.windows().await?;
         let current_handle = self.driver.window().await?;
+        let controlled_handle = self.controlled_handle();
+        /* 不切换标签页：chromedriver 的窗口句柄就是 DevTools 的 target id，
+        用一次 Target.getTargets 取得所有标签页的标题和 URL */
+        let targets = self.page_targets().await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to query tab targets: {}", e);
+            HashMap::new()
+        });
+
         let mut tabs_info = Vec::new();
-        
         for (index, handle) in handles.iter().enumerate() {
-            // 切换到当前标签页以获取信息
-            self.driver.switch_to_window(handle.clone()).await?;
-            
-            let title = self.driver.title().await.unwrap_or_default();
-            let url = self.driver.current_url().await?.to_string();
-            
-            // 检查是否是当前活跃的标签页
             let is_active = handle == &current_handle;
-            
-            // 检查是否是当前控制的标签页（这里假设当前标签页就是被控制的）
-            let is_controlled = handle == &current_handle;
-            
-            let tab_info = TabInfo {
+            let (title, url) = match targets.get(&handle.to_string()) {
+                Some(target) => target.clone(),
+                // 查询失败时当前标签页直接读取，其他标签页留空
+                None if is_active => (
+                    self.driver.title().await.unwrap_or_default(),
+                    self.driver.current_url().await?.to_string(),
+                ),
+                None => (String::new(), String::new()),
+            };
+            tabs_info.push(TabInfo {
                 index,
                 title,
                 url,
                 is_active,
-                is_controlled,
-            };
-            
-            tabs_info.push(tab_info);
+                is_controlled: controlled_handle.as_ref() == Some(handle),
+            });
         }
-        
-        // 切换回原来的标签页
-        self.driver.switch_to_window(current_handle).await?;
-        
         Ok(tabs_info)
     }
 
+    // target id -> (标题, URL)，只包含 page 类型的 target
+    async fn page_targets(&self) -> Result<HashMap<String, (String, String)>> {
+        let devtools = ChromeDevTools::new(self.driver.handle.clone());
+        let result = devtools.execute_cdp("Target.getTargets").await?;
+        let targets = result["targetInfos"]
+            .as_array()
+            .ok_or_else(|| anyhow::anyhow!("Unexpected Target.getTargets result: {}", result))?
+            .iter()
+            .filter(|target| target["type"] == "page")
+            .filter_map(|target| {
+                let text = |key: &str| target[key].as_str().unwrap_or_default().to_string();
+                Some((target["targetId"].as_str()?.to_string(), (text("title"), text("url"))))
+            })
+            .collect();
+        Ok(targets)
+    }
+
     pub async fn switch_tab(&self, index: usize) -> Result<()> {
 
-        let _ = self.wait_for_page_ready().await?;
+        self.wait_for_page_ready().await?;
         let handles = self.driver.windows().await?;
         if index >= handles.len() {
             return Err(anyhow::anyhow!("Index out of bounds: 要切换到索引 {}, 但只有 {} 个标签页", index, handles.len()));
         }
         let handle = handles[index].clone();
 
-        self.driver.switch_to_window(handle).await?;
+        self.driver.switch_to_window(handle.clone()).await?;
+        self.set_controlled(Some(handle));
         Ok(())
     }
 
@@ -158,14 +363,17 @@ impl Chrome {
         self.driver.switch_to_window(handle).await?;
         self.driver.close_window().await?;
         
-        // 关闭后，自动切换到第一个可用的标签页（避免焦点处于无效窗口）
+        /* 关闭后回到被控制的标签页（避免焦点处于无效窗口）；
+        关闭的就是被控制的标签页时改为控制第一个标签页 */
         let remaining_handles = self.driver.windows().await?;
-        
-        if !remaining_handles.is_empty() {
-            // 切换到第一个标签页
-            self.driver.switch_to_window(remaining_handles[0].clone()).await?;
-            println!("已自动切换到索引 0");
+        let next = match self.controlled_handle() {
+            Some(controlled) if remaining_handles.contains(&controlled) => Some(controlled),
+            _ => remaining_handles.first().cloned(),
+        };
+        if let Some(next) = &next {
+            self.driver.switch_to_window(next.clone()).await?;
         }
+        self.set_controlled(next);
         
         Ok(())
     }
@@ -175,6 +383,7 @@ impl Chrome {
         Ok(())
     }
 
+    #[allow(dead_code)]
     async fn go_forward(&self) -> Result<()> {
         self.driver.forward().await?;
         Ok(())
@@ -226,7 +435,8 @@ impl Chrome {
     }
 
     /// 鼠标管理
-    async fn click_coords(&mut self, x: i32, y: i32, button: &str) -> Result<()> {
+    #[allow(dead_code)]
+    async fn click_coords(&self, x: i32, y: i32, button: &str) -> Result<()> {
         match button {
             "back" => {
                 self.go_back().await?;
@@ -235,7 +445,7 @@ impl Chrome {
                 self.go_forward().await?;
             }
             "wheel" => {
-                let (start_x, start_y) = self.anim_utils.last_cursor_position;
+                let (start_x, start_y) = self.anim_utils.last_position();
                 self.anim_utils.gradual_cursor_animation(&self.driver, start_x, start_y, x as f64, y as f64, 10, 50)
                     .await?;
                 self.driver.as_ref().execute(
@@ -245,7 +455,7 @@ impl Chrome {
                 self.anim_utils.cleanup_animations(&self.driver).await?;
             }
             "left" | "right" => {
-                let (start_x, start_y) = self.anim_utils.last_cursor_position;
+                let (start_x, start_y) = self.anim_utils.last_position();
                 self.anim_utils.gradual_cursor_animation(&self.driver, start_x, start_y, x as f64, y as f64, 10, 50)
                     .await?;
 
@@ -268,8 +478,9 @@ impl Chrome {
         Ok(())
     }
 
-    async fn double_coords(&mut self, x: i32, y: i32) -> Result<()> {
-        let (start_x, start_y) = self.anim_utils.last_cursor_position;
+    #[allow(dead_code)]
+    async fn double_coords(&self, x: i32, y: i32) -> Result<()> {
+        let (start_x, start_y) = self.anim_utils.last_position();
         self.anim_utils.gradual_cursor_animation(&self.driver, start_x, start_y, x as f64, y as f64, 10, 50)
             .await?;
         self.driver.as_ref().action_chain()
@@ -280,8 +491,9 @@ impl Chrome {
         Ok(())
     }
 
-    async fn hover_coords(&mut self, x: i32, y: i32) -> Result<()> {
-        let (start_x, start_y) = self.anim_utils.last_cursor_position;
+    #[allow(dead_code)]
+    async fn hover_coords(&self, x: i32, y: i32) -> Result<()> {
+        let (start_x, start_y) = self.anim_utils.last_position();
         self.anim_utils.gradual_cursor_animation(&self.driver, start_x, start_y, x as f64, y as f64, 10, 50)
             .await?;
         self.driver.as_ref().action_chain()
@@ -291,7 +503,8 @@ impl Chrome {
         Ok(())
     }
 
-    async fn drag_coords(&mut self, path: Vec<(i32, i32)>) -> Result<()> {
+    #[allow(dead_code)]
+    async fn drag_coords(&self, path: Vec<(i32, i32)>) -> Result<()> {
         if path.is_empty() {
             return Ok(());
         }
@@ -508,6 +721,7 @@ impl Chrome {
         Ok(page_metadata)
     }
 
+    #[allow(dead_code)]
     async fn get_all_webpage_text(&self,n_lines: Option<usize>) -> Result<String> {
         
         let text_util = WebpageTextUtils::new(self.driver.clone());
@@ -519,7 +733,8 @@ impl Chrome {
         Ok(page_text)
     }
 
-    pub async fn get_visible_text(&self) -> Result<String> {
+    /// 视口中可见的文字，按 clean_visible_text 整理，最多 max_chars 个字符（0 为不截断）
+    pub async fn get_visible_text(&self, max_chars: usize) -> Result<String> {
         let init_script = include_str!("page_script.js");
         self.driver
             .execute(init_script, Vec::new())
@@ -529,9 +744,10 @@ impl Chrome {
             .execute("return WebSurfer.getVisibleText();", Vec::new())
             .await?;
         
-        let text = result.json().to_string();
+        // 脚本返回的是字符串，to_string() 会得到带引号和转义的 JSON
+        let text = result.json().as_str().unwrap_or_default();
 
-        Ok(text)
+        Ok(clean_visible_text(text, max_chars))
     }
 
     // 网页内容转化为Markdown
@@ -569,7 +785,7 @@ impl Chrome {
         let viewport = self.get_visual_viewport().await?;
         
         // 获取可见文本
-        let viewport_text = self.get_visible_text().await?;
+        let viewport_text = self.get_visible_text(DEFAULT_MAX_VISIBLE_TEXT_CHARS).await?;
         
         // 计算百分比
         let percent_visible = if viewport.scroll_height > 0.0 {
@@ -616,13 +832,13 @@ impl Chrome {
 
     // 点击具有特定 __elementId 属性的元素。它能处理右键点击、按住点击（在单标签模式下阻止新窗口打开，以及检测点击后触发的下载或新页面） 括号内暂不进行实现
     pub async fn click_id(
-        &mut self,
+        &self,
         identifier: &str,   // 特定元素的标号
-        _hold: f64,         // 长按（暂未实现）
+        hold: f64,          // 长按的秒数，大于 0 时按下后等待再松开，最多 MAX_HOLD_SECS
         button: &str,       // "left" | "right"
     ) -> Result<bool> {
 
-        let _ = self.wait_for_page_ready().await?;
+        self.wait_for_page_ready().await?;
 
         // 首先检查元素是否存在，如果不存在则先扫描页面
         let element_exists = self.driver.execute(
@@ -715,7 +931,7 @@ impl Chrome {
                 .add_cursor_box(&self.driver, identifier)
                 .await?;
 
-            let (start_x, start_y) = self.anim_utils.last_cursor_position;
+            let (start_x, start_y) = self.anim_utils.last_position();
             self.anim_utils
                 .gradual_cursor_animation(
                     &self.driver,
@@ -731,7 +947,25 @@ impl Chrome {
         }
 
         // 5. 执行点击操作
+        let hold = clamp_hold(hold);
         match button {
+            // 长按：在元素中心按下，等待 hold 秒后在同一位置松开
+            "left" if hold > 0.0 => {
+                self.driver.as_ref().action_chain()
+                    .move_to(center_x as i64, center_y as i64)
+                    .click_and_hold()
+                    .perform()
+                    .await?;
+                sleep(Duration::from_secs_f64(hold)).await;
+                self.driver.as_ref().action_chain()
+                    .move_to(center_x as i64, center_y as i64)
+                    .release()
+                    .perform()
+                    .await?;
+            }
+            "right" if hold > 0.0 => {
+                return Err(anyhow::anyhow!("Long-press is only supported with the left mouse button"));
+            }
             "left" | "right" => {
                 let action_chain = self.driver.as_ref().action_chain()
                     .move_to(center_x as i64, center_y as i64);
@@ -783,7 +1017,7 @@ impl Chrome {
     -鼠标悬停 → playwright_controller.py 执行实际的鼠标悬停操作
     */
     pub async fn hover_id(
-        &mut self,
+        &self,
         identifier: &str,
     ) -> Result<()> {
         // 确保页面已加载完成
@@ -827,7 +1061,7 @@ impl Chrome {
             self.anim_utils.add_cursor_box(&self.driver, identifier).await?;
             
             // 移动光标到元素中心
-            let (start_x, start_y) = self.anim_utils.last_cursor_position;
+            let (start_x, start_y) = self.anim_utils.last_position();
             self.anim_utils.gradual_cursor_animation(
                 &self.driver,
                 start_x,
@@ -860,7 +1094,7 @@ impl Chrome {
     /// 向具有特定标识符的元素填充文本(键盘输入)
     /// 适用于文本输入框、文本区域和下拉框
     pub async fn fill_id(
-        &mut self,
+        &self,
         identifier: &str,
         value: &str,
         press_enter: bool,
@@ -922,7 +1156,7 @@ impl Chrome {
             self.anim_utils.add_cursor_box(&self.driver, identifier).await?;
             
             // 移动光标到元素中心
-            let (start_x, start_y) = self.anim_utils.last_cursor_position;
+            let (start_x, start_y) = self.anim_utils.last_position();
             self.anim_utils.gradual_cursor_animation(
                 &self.driver,
                 start_x,
@@ -960,7 +1194,7 @@ impl Chrome {
             // 逐字符输入以模拟打字效果
             for ch in value.chars() {
                 self.driver.action_chain()
-                    .send_keys(&ch.to_string())
+                    .send_keys(ch.to_string())
                     .perform().await?;
                 self.sleep(delay_ms).await?;
             }
@@ -987,6 +1221,41 @@ impl Chrome {
         Ok(())
     }
 
+    /* 把本地文件交给具有特定标识符的文件输入框，path 需要是 chromedriver 所在机器上的文件，
+    发送前转成绝对路径。目标不是 input[type=file] 时返回错误，不会把路径当作文字输入 */
+    pub async fn upload_file(&self, identifier: &str, path: &Path) -> Result<()> {
+        let _ = self.wait_for_page_ready().await;
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("File {} does not exist", path.display()))?;
+        let element = self
+            .driver
+            .find(By::Css(format!("[__elementId=\"{}\"]", identifier)))
+            .await
+            .with_context(|| format!("Element {} not found", identifier))?;
+
+        let tag = element.tag_name().await?.to_lowercase();
+        let input_type = element.attr("type").await?.unwrap_or_default().to_lowercase();
+        if tag != "input" || input_type != "file" {
+            let described = if tag == "input" { format!("an input of type '{}'", input_type) } else { format!("a <{}> element", tag) };
+            return Err(anyhow::anyhow!(
+                "Element {} is {}, not a file input; upload_file only works on input[type=file] targets",
+                identifier,
+                described
+            ));
+        }
+
+        // 文件输入框常常被样式隐藏，先滚动到可见位置，再直接把路径发给元素
+        self.driver
+            .execute(
+                &format!("document.querySelector('[__elementId=\"{}\"]').scrollIntoView({{ behavior: 'instant', block: 'center' }});", identifier),
+                vec![],
+            )
+            .await?;
+        element.send_keys(path.to_string_lossy().as_ref()).await?;
+        Ok(())
+    }
+
     pub async fn get_focused_rect_id(&self) -> Result<String> {
         let _ = self.wait_for_page_ready().await;
 
@@ -1014,15 +1283,36 @@ impl Chrome {
         Ok(focused_id)
     }
 
-    async fn quit(self) -> Result<()> {
-        let _ = Arc::try_unwrap(self.driver)
-            .map_err(|_| anyhow::anyhow!("Failed to unwrap driver"))?
-            .quit()
-            .await
-            .context("Failed to quit WebDriver");
-        Ok(())
+}
+
+/// describe_page 和 WebAgent 提示词中可见文字最多的字符数
+pub const DEFAULT_MAX_VISIBLE_TEXT_CHARS: usize = 10_000;
+const TRUNCATED_MARKER: &str = "…[truncated]";
+
+/* 每行中连续的空白合并为一个空格并去掉首尾空白，去掉空行；
+超过 max_chars 个字符时截断并加上 TRUNCATED_MARKER，max_chars 为 0 时不截断 */
+pub fn clean_visible_text(text: &str, max_chars: usize) -> String {
+    let text = text
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) if max_chars > 0 => format!("{}{}", text[..end].trim_end(), TRUNCATED_MARKER),
+        _ => text,
     }
+}
 
+/// click_id 长按的上限（秒）
+pub const MAX_HOLD_SECS: f64 = 5.0;
+
+/// 长按秒数限制在 0 到 MAX_HOLD_SECS 之间，负数和 NaN 视为不长按
+pub fn clamp_hold(hold: f64) -> f64 {
+    if hold.is_nan() {
+        return 0.0;
+    }
+    hold.clamp(0.0, MAX_HOLD_SECS)
 }
 
 #[cfg(test)]
@@ -1030,15 +1320,17 @@ mod test {
     use super::*;
     use anyhow::Result;
     // chromedriver --port=9515  
+    // 需要 chromedriver，运行方式：cargo test test_chrome -- --ignored
     #[tokio::test]
+    #[ignore]
     async fn test_chrome() -> Result<()> {
         let chrome = Chrome::new().await?;
 
-        let _ = chrome.new_tab("https://www.bilibili.com").await?;
-        let _ = chrome.new_tab("https://www.baidu.com").await?;
-        let _ = chrome.new_tab("https://www.qq.com").await?;
-        let _ = chrome.new_tab("https://www.taobao.com").await?;
-        let _ = chrome.new_tab("https://www.jd.com").await?;
+        let _ = chrome.new_tab("https://www.bilibili.com", false).await?;
+        let _ = chrome.new_tab("https://www.baidu.com", false).await?;
+        let _ = chrome.new_tab("https://www.qq.com", false).await?;
+        let _ = chrome.new_tab("https://www.taobao.com", false).await?;
+        let _ = chrome.new_tab("https://www.jd.com", false).await?;
 
         chrome.sleep(3000).await?;
 
@@ -1056,10 +1348,113 @@ mod test {
     }
 
 
+    #[test]
+    fn test_clean_visible_text() {
+        assert_eq!(clean_visible_text("  Opening   hours \n\n\n Monday\t9am \n", 0), "Opening hours\nMonday 9am");
+        assert_eq!(clean_visible_text("abcdef\nghi", 4), "abcd…[truncated]");
+        assert_eq!(clean_visible_text("营业时间", 2), "营业…[truncated]");
+        assert_eq!(clean_visible_text("short", 5), "short");
+    }
+
+    // 需要 chromedriver，运行方式：cargo test test_get_visible_text_is_plain_text -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_get_visible_text_is_plain_text() -> Result<()> {
+        let chrome = Chrome::new().await?;
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/visible_text.html").canonicalize()?;
+        chrome.visit_page(&format!("file://{}", fixture.display())).await?;
+
+        let text = chrome.get_visible_text(0).await?;
+        assert!(text.contains("Opening hours\nMonday to Friday: 9am - 6pm\n"), "{:?}", text);
+        assert!(text.contains("Pizza \"Margherita\"") && text.contains("Pasta \\ Salad"), "{:?}", text);
+        assert!(!text.contains("\\n") && !text.starts_with('"'), "{:?}", text);
+        assert!(!text.contains("Hidden text"));
+
+        let truncated = chrome.get_visible_text(10).await?;
+        assert_eq!(truncated, "Opening ho…[truncated]");
+        chrome.quit().await?;
+        Ok(())
+    }
+
+    // 需要 chromedriver，运行方式：cargo test test_get_tabs_information_keeps_the_current_tab -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_get_tabs_information_keeps_the_current_tab() -> Result<()> {
+        let chrome = Chrome::with_config(ChromeConfig::default()).await?;
+        for title in ["One", "Two", "Three"] {
+            chrome.new_tab(&format!("data:text/html,<title>{}</title>", title), true).await?;
+        }
+        let current = chrome.driver.window().await?;
+
+        let tabs = chrome.get_tabs_information().await?;
+        assert_eq!(chrome.driver.window().await?, current);
+        assert_eq!(tabs.len(), 4);
+        // new_tab 切换到了最后打开的标签页
+        assert!(tabs[3].is_active && tabs[3].is_controlled);
+        assert!(tabs[..3].iter().all(|tab| !tab.is_active && !tab.is_controlled));
+        assert_eq!(tabs[0].url, "about:blank");
+        let titles: Vec<&str> = tabs[1..].iter().map(|tab| tab.title.as_str()).collect();
+        assert_eq!(titles, ["One", "Two", "Three"]);
+
+        chrome.quit().await?;
+        Ok(())
+    }
+
+    // 需要 chromedriver，运行方式：cargo test test_new_tab_focus_is_explicit -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_new_tab_focus_is_explicit() -> Result<()> {
+        let chrome = Chrome::with_config(ChromeConfig::default()).await?;
+        let first = chrome.driver.window().await?;
+
+        // 后台打开：焦点和被控制的标签页都留在原来的标签页
+        let background = chrome.new_tab("data:text/html,<title>Background</title>", false).await?;
+        assert_eq!(chrome.driver.window().await?, first);
+        assert_eq!(chrome.controlled_handle(), Some(first.clone()));
+
+        let focused = chrome.new_tab("data:text/html,<title>Focused</title>", true).await?;
+        assert_ne!(focused, background);
+        assert_eq!(chrome.driver.window().await?, focused);
+        assert_eq!(chrome.controlled_handle(), Some(focused));
+
+        chrome.quit().await?;
+        Ok(())
+    }
+
+    // 需要 chromedriver，运行方式：cargo test test_closing_the_controlled_tab_falls_back_to_the_first -- --ignored
     #[tokio::test]
+    #[ignore]
+    async fn test_closing_the_controlled_tab_falls_back_to_the_first() -> Result<()> {
+        let chrome = Chrome::with_config(ChromeConfig::default()).await?;
+        for title in ["One", "Two", "Three"] {
+            chrome.new_tab(&format!("data:text/html,<title>{}</title>", title), true).await?;
+        }
+        let controlled = |tabs: &[TabInfo]| tabs.iter().filter(|tab| tab.is_controlled).map(|tab| tab.title.clone()).collect::<Vec<_>>();
+
+        // 关闭其他标签页时仍然控制原来的标签页
+        chrome.switch_tab(2).await?;
+        chrome.close_tab_by_index(1).await?;
+        let tabs = chrome.get_tabs_information().await?;
+        assert_eq!(controlled(&tabs), ["Two"]);
+        assert!(tabs[1].is_active);
+
+        chrome.close_tab_by_index(1).await?;
+        let tabs = chrome.get_tabs_information().await?;
+        assert_eq!(tabs.len(), 2);
+        assert!(tabs[0].is_controlled && tabs[0].is_active);
+        assert!(!tabs[1].is_controlled);
+        assert_eq!(chrome.controlled_handle(), Some(chrome.driver.window().await?));
+
+        chrome.quit().await?;
+        Ok(())
+    }
+
+    // 需要 chromedriver，运行方式：cargo test test_click_id -- --ignored
+    #[tokio::test]
+    #[ignore]
     async fn test_click_id() -> Result<()> {
-        let mut chrome = Chrome::new().await?;
-        let _ = chrome.new_tab("https://www.bilibili.com").await?;
+        let chrome = Chrome::new().await?;
+        let _ = chrome.new_tab("https://www.bilibili.com", false).await?;
         chrome.switch_tab(0).await?;
         chrome.sleep(2000).await?;
 
@@ -1071,11 +1466,13 @@ mod test {
         Ok(())
     }
 
+    // 需要 chromedriver，运行方式：cargo test test_fill_id -- --ignored
     #[tokio::test]
+    #[ignore]
     async fn test_fill_id() -> Result<()> {
-        let mut chrome = Chrome::new().await?;
+        let chrome = Chrome::new().await?;
         
-        let _ = chrome.new_tab("https://www.bilibili.com").await?;
+        let _ = chrome.new_tab("https://www.bilibili.com", false).await?;
         chrome.switch_tab(0).await?;
         chrome.sleep(2000).await?;
         
@@ -1109,4 +1506,138 @@ mod test {
         chrome.quit().await?;
         Ok(())
     }
+
+    // 本地服务器返回 Content-Disposition: attachment 的文件，导航之后保存在下载目录中，当前页面不变，运行方式：cargo test test_visit_page_downloads_attachments -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_visit_page_downloads_attachments() -> Result<()> {
+        use axum::http::header;
+        use axum::response::Html;
+        use axum::routing::get;
+
+        let app = axum::Router::new()
+            .route("/", get(|| async { Html("<title>Files</title><a href=\"/report.csv\">report</a>") }))
+            .route(
+                "/report.csv",
+                get(|| async {
+                    (
+                        [(header::CONTENT_TYPE, "text/csv"), (header::CONTENT_DISPOSITION, "attachment; filename=\"report.csv\"")],
+                        "item,price\nbread,3\n",
+                    )
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base = format!("http://{}", listener.local_addr()?);
+        let server = tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let dir = tempfile::tempdir()?;
+        let config = ChromeConfig { downloads_dir: Some(dir.path().to_path_buf()), ..ChromeConfig::default() };
+        let chrome = Chrome::with_config(config).await?;
+        assert_eq!(chrome.visit_page(&base).await?, VisitOutcome::Page);
+        match chrome.visit_page(&format!("{}/report.csv", base)).await? {
+            VisitOutcome::Download(file) => {
+                assert_eq!(file.file_name, "report.csv");
+                assert_eq!(std::fs::read_to_string(&file.path)?, "item,price\nbread,3\n");
+            }
+            other => panic!("Expected a download, got {:?}", other),
+        }
+        assert_eq!(chrome.get_url().await?, format!("{}/", base));
+
+        chrome.quit().await?;
+        server.abort();
+        Ok(())
+    }
+
+    // 本地的 file:// 页面，文件输入框在一屏之外，运行方式：cargo test test_upload_file -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_upload_file() -> Result<()> {
+        let chrome = Chrome::new().await?;
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/upload.html").canonicalize()?;
+        chrome.visit_page(&format!("file://{}", fixture.display())).await?;
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("prices.csv");
+        std::fs::write(&file, "item,price\nbread,3\n")?;
+
+        chrome.upload_file("upload", &file).await?;
+        let names = chrome
+            .driver
+            .execute("return Array.from(document.querySelector('[__elementId=\"upload\"]').files).map(f => f.name);", vec![])
+            .await?;
+        assert_eq!(names.json(), &serde_json::json!(["prices.csv"]));
+
+        // 不是文件输入框时返回错误，不会把路径当作文字输入
+        let error = chrome.upload_file("name", &file).await.unwrap_err();
+        assert!(error.to_string().contains("not a file input"), "{}", error);
+        let value = chrome.driver.execute("return document.querySelector('[__elementId=\"name\"]').value;", vec![]).await?;
+        assert_eq!(value.json(), &serde_json::json!(""));
+
+        chrome.quit().await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_capabilities_follow_the_config() -> Result<()> {
+        let caps = Value::Object(ChromeConfig::default().capabilities()?.into());
+        assert_eq!(caps["browserName"], "chrome");
+        let args = caps["goog:chromeOptions"]["args"].as_array().cloned().unwrap_or_default();
+        assert!(args.is_empty(), "{:?}", args);
+        assert!(caps["goog:chromeOptions"]["prefs"].is_null());
+
+        let config = ChromeConfig {
+            headless: true,
+            window_size: Some((1440, 900)),
+            user_data_dir: Some(PathBuf::from("/tmp/profile")),
+            downloads_dir: Some(PathBuf::from("/tmp/downloads")),
+            ..ChromeConfig::default()
+        };
+        let caps = Value::Object(config.capabilities()?.into());
+        assert_eq!(
+            caps["goog:chromeOptions"]["args"],
+            serde_json::json!(["--headless=new", "--window-size=1440,900", "--user-data-dir=/tmp/profile"])
+        );
+        assert_eq!(caps["goog:chromeOptions"]["prefs"]["download.default_directory"], "/tmp/downloads");
+        assert_eq!(caps["goog:chromeOptions"]["prefs"]["download.prompt_for_download"], false);
+
+        let mut settings = BrowserSettings { viewport_width: Some(1280), ..BrowserSettings::default() };
+        assert_eq!(ChromeConfig::from_settings(&settings).window_size, None);
+        settings.viewport_height = Some(720);
+        settings.start_page = Some("https://www.bing.com".to_string());
+        let config = ChromeConfig::from_settings(&settings);
+        assert_eq!(config.window_size, Some((1280, 720)));
+        assert_eq!(config.start_page.as_deref(), Some("https://www.bing.com"));
+        assert_eq!(config.webdriver_url, "http://localhost:9515");
+        Ok(())
+    }
+
+    #[test]
+    fn test_clamp_hold() {
+        assert_eq!(clamp_hold(0.0), 0.0);
+        assert_eq!(clamp_hold(1.5), 1.5);
+        assert_eq!(clamp_hold(30.0), MAX_HOLD_SECS);
+        assert_eq!(clamp_hold(-1.0), 0.0);
+        assert_eq!(clamp_hold(f64::NAN), 0.0);
+        assert_eq!(clamp_hold(f64::INFINITY), MAX_HOLD_SECS);
+    }
+
+    // 长按时 pointerdown 和 pointerup 之间间隔 hold 秒，运行方式：cargo test test_long_press -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_long_press() -> Result<()> {
+        let chrome = Chrome::new().await?;
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/long_press.html").canonicalize()?;
+        chrome.visit_page(&format!("file://{}", fixture.display())).await?;
+
+        chrome.click_id("hold", 1.0, "left").await?;
+        let events = chrome.driver.execute("return window.pointerEvents;", vec![]).await?;
+        let events = events.json().as_array().cloned().unwrap_or_default();
+        let types: Vec<&str> = events.iter().filter_map(|event| event["type"].as_str()).collect();
+        assert_eq!(types, vec!["pointerdown", "pointerup", "click"]);
+        let held = events[1]["t"].as_f64().unwrap() - events[0]["t"].as_f64().unwrap();
+        assert!(held >= 900.0, "held for {}ms", held);
+
+        assert!(chrome.click_id("hold", 1.0, "right").await.is_err());
+        chrome.quit().await?;
+        Ok(())
+    }
 }
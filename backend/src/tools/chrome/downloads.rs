@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use tokio::time::{sleep, Instant};
+
+// Chrome 下载过程中的临时文件后缀，下载完成后改名为最终的文件名
+const PARTIAL_SUFFIXES: &[&str] = &[".crdownload", ".tmp"];
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// 下载到本地的文件
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadedFile {
+    pub url: String,
+    pub file_name: String,
+    pub path: PathBuf,
+}
+
+/// visit_page 的结果：打开了页面，或者导航触发了下载
+#[derive(Debug, Clone, PartialEq)]
+pub enum VisitOutcome {
+    Page,
+    Download(DownloadedFile),
+}
+
+impl VisitOutcome {
+    /// 打开了新页面时需要重新获取页面的元数据，下载不改变当前页面
+    pub fn resets_metadata(&self) -> bool {
+        matches!(self, VisitOutcome::Page)
+    }
+}
+
+/// 目录中现有的文件名，导航之前记录下来，之后出现的文件视为这次导航的下载
+pub fn snapshot(dir: &Path) -> Result<HashSet<String>> {
+    let mut names = HashSet::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to read download folder {}", dir.display()))? {
+        names.insert(entry?.file_name().to_string_lossy().into_owned());
+    }
+    Ok(names)
+}
+
+fn is_partial(name: &str) -> bool {
+    PARTIAL_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
+
+/* 等待 before 之后出现的下载：start_timeout 内没有出现新文件（包括下载中的临时文件）时返回 None，
+出现之后等到临时文件消失、得到最终的文件，超过 timeout 时返回错误 */
+pub async fn wait_for_download(
+    dir: &Path,
+    before: &HashSet<String>,
+    start_timeout: Duration,
+    timeout: Duration,
+) -> Result<Option<PathBuf>> {
+    let started = Instant::now();
+    let mut seen_download = false;
+    loop {
+        let new_names: Vec<String> = snapshot(dir)?.into_iter().filter(|name| !before.contains(name)).collect();
+        if let Some(name) = new_names.iter().find(|name| !is_partial(name)) {
+            // 临时文件都没有了才算完成，同时下载多个文件时等全部结束
+            if !new_names.iter().any(|name| is_partial(name)) {
+                return Ok(Some(dir.join(name)));
+            }
+        }
+        seen_download |= !new_names.is_empty();
+        let elapsed = started.elapsed();
+        if !seen_download && elapsed >= start_timeout {
+            return Ok(None);
+        }
+        if elapsed >= timeout {
+            return Err(anyhow!("The download into {} did not finish within {}s", dir.display(), timeout.as_secs()));
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wait_for_download() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("old.pdf"), "old")?;
+        let before = snapshot(dir.path())?;
+
+        // 没有新文件时在 start_timeout 之后返回 None
+        let none = wait_for_download(dir.path(), &before, Duration::from_millis(300), Duration::from_secs(5)).await?;
+        assert_eq!(none, None);
+
+        // 先出现临时文件，之后改名为最终的文件
+        let path = dir.path().to_path_buf();
+        let writer = tokio::spawn(async move {
+            std::fs::write(path.join("report.zip.crdownload"), "partial").unwrap();
+            sleep(Duration::from_millis(600)).await;
+            std::fs::rename(path.join("report.zip.crdownload"), path.join("report.zip")).unwrap();
+        });
+        let found = wait_for_download(dir.path(), &before, Duration::from_millis(300), Duration::from_secs(5)).await?;
+        writer.await?;
+        assert_eq!(found, Some(dir.path().join("report.zip")));
+
+        // 一直没有完成时超时
+        let before = snapshot(dir.path())?;
+        std::fs::write(dir.path().join("stuck.iso.crdownload"), "partial")?;
+        let error = wait_for_download(dir.path(), &before, Duration::from_millis(100), Duration::from_millis(500))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("did not finish"));
+        Ok(())
+    }
+}
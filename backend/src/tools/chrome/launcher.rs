@@ -0,0 +1,199 @@
+use std::ffi::OsString;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use tokio::process::{Child, Command};
+use tokio::time::sleep;
+
+/// 指定 chromedriver 可执行文件的环境变量，没有设置时在 PATH 中查找
+pub const CHROMEDRIVER_PATH_ENV: &str = "CHROMEDRIVER_PATH";
+
+// 启动之后等待 /status 可用：最多 READY_ATTEMPTS 次，每次间隔 READY_INTERVAL
+const READY_ATTEMPTS: usize = 50;
+const READY_INTERVAL: Duration = Duration::from_millis(100);
+const STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// chromedriver 的 /status 是否可以访问
+pub async fn is_reachable(webdriver_url: &str) -> bool {
+    let url = format!("{}/status", webdriver_url.trim_end_matches('/'));
+    let Ok(client) = reqwest::Client::builder().timeout(STATUS_TIMEOUT).build() else {
+        return false;
+    };
+    matches!(client.get(&url).send().await, Ok(response) if response.status().is_success())
+}
+
+/// chromedriver 可执行文件：CHROMEDRIVER_PATH，否则在 PATH 中查找
+pub fn find_binary() -> Result<PathBuf> {
+    find_binary_in(std::env::var_os(CHROMEDRIVER_PATH_ENV), std::env::var_os("PATH"))
+}
+
+fn find_binary_in(configured: Option<OsString>, path_var: Option<OsString>) -> Result<PathBuf> {
+    if let Some(path) = configured.filter(|path| !path.is_empty()) {
+        let path = PathBuf::from(path);
+        return match path.is_file() {
+            true => Ok(path),
+            false => Err(anyhow!(
+                "{} points to {}, which is not a file. Set it to the chromedriver binary",
+                CHROMEDRIVER_PATH_ENV,
+                path.display()
+            )),
+        };
+    }
+    let name = if cfg!(windows) { "chromedriver.exe" } else { "chromedriver" };
+    path_var
+        .iter()
+        .flat_map(std::env::split_paths)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+        .ok_or_else(|| {
+            anyhow!(
+                "chromedriver was not found on PATH. Install the chromedriver that matches your Chrome version \
+                 and add it to PATH, set {} to the binary, or start it yourself with `chromedriver --port=9515`",
+                CHROMEDRIVER_PATH_ENV
+            )
+        })
+}
+
+/// 向系统要一个当前空闲的本地端口
+pub fn free_port() -> Result<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0").context("Failed to find a free port for chromedriver")?;
+    Ok(listener.local_addr()?.port())
+}
+
+/* 由我们启动的 chromedriver 子进程。Chrome::quit 时结束，
+没有调用 quit 时随着 drop 结束（kill_on_drop） */
+#[derive(Debug)]
+pub struct ChromedriverProcess {
+    child: Mutex<Option<Child>>,
+    url: String,
+}
+
+impl ChromedriverProcess {
+    /// 在 port 上启动 chromedriver，等待它可以接受连接
+    pub async fn spawn(binary: &Path, port: u16) -> Result<Self> {
+        let child = Command::new(binary)
+            .arg(format!("--port={}", port))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start chromedriver from {}", binary.display()))?;
+        let process = Self { child: Mutex::new(Some(child)), url: format!("http://127.0.0.1:{}", port) };
+
+        for _ in 0..READY_ATTEMPTS {
+            if let Some(status) = process.exit_status()? {
+                return Err(anyhow!(
+                    "chromedriver ({}) exited with {} before it was ready. Port {} may already be in use, \
+                     or the binary does not match the installed Chrome",
+                    binary.display(),
+                    status,
+                    port
+                ));
+            }
+            if is_reachable(&process.url).await {
+                return Ok(process);
+            }
+            sleep(READY_INTERVAL).await;
+        }
+        process.kill();
+        Err(anyhow!(
+            "chromedriver ({}) did not answer on port {} within {}s. Start it yourself with `chromedriver --port=9515` \
+             and check its output",
+            binary.display(),
+            port,
+            (READY_INTERVAL * READY_ATTEMPTS as u32).as_secs_f64()
+        ))
+    }
+
+    /// 配置的地址不可访问时在空闲端口上启动一个，可以访问时返回 None
+    pub async fn ensure(webdriver_url: &str) -> Result<Option<Self>> {
+        if is_reachable(webdriver_url).await {
+            return Ok(None);
+        }
+        tracing::info!("chromedriver is not reachable at {}, starting one", webdriver_url);
+        let process = Self::spawn(&find_binary()?, free_port()?).await?;
+        Ok(Some(process))
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn exit_status(&self) -> Result<Option<std::process::ExitStatus>> {
+        match self.child.lock().unwrap().as_mut() {
+            Some(child) => Ok(child.try_wait()?),
+            None => Ok(None),
+        }
+    }
+
+    /// 结束子进程，重复调用没有影响
+    pub fn kill(&self) {
+        if let Some(mut child) = self.child.lock().unwrap().take() {
+            if let Err(e) = child.start_kill() {
+                tracing::warn!("Failed to stop chromedriver: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    #[test]
+    fn test_find_binary() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let binary = dir.path().join(if cfg!(windows) { "chromedriver.exe" } else { "chromedriver" });
+        std::fs::write(&binary, "")?;
+
+        let path_var = std::env::join_paths([Path::new("/nonexistent"), dir.path()])?;
+        assert_eq!(find_binary_in(None, Some(path_var.clone()))?, binary);
+        // 环境变量优先于 PATH
+        let other = fixture("fake_chromedriver.py");
+        assert_eq!(find_binary_in(Some(other.clone().into()), Some(path_var))?, other);
+
+        let error = find_binary_in(None, Some("/nonexistent".into())).unwrap_err().to_string();
+        assert!(error.contains("not found on PATH") && error.contains(CHROMEDRIVER_PATH_ENV), "{}", error);
+        let error = find_binary_in(Some("/nonexistent/chromedriver".into()), None).unwrap_err().to_string();
+        assert!(error.contains("is not a file"), "{}", error);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_spawned_chromedriver_lives_with_the_process() -> Result<()> {
+        let process = ChromedriverProcess::spawn(&fixture("fake_chromedriver.py"), free_port()?).await?;
+        let url = process.url().to_string();
+        assert!(is_reachable(&url).await);
+        // 已经可以访问时不再启动
+        assert!(ChromedriverProcess::ensure(&url).await?.is_none());
+
+        drop(process);
+        let mut stopped = false;
+        for _ in 0..20 {
+            sleep(Duration::from_millis(100)).await;
+            if !is_reachable(&url).await {
+                stopped = true;
+                break;
+            }
+        }
+        assert!(stopped, "chromedriver is still running at {}", url);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_spawn_reports_early_exit() -> Result<()> {
+        // 端口被占用时 chromedriver 立即退出，这里用一个直接退出的程序代替
+        let error = ChromedriverProcess::spawn(Path::new("/bin/false"), free_port()?).await.unwrap_err();
+        assert!(error.to_string().contains("may already be in use"), "{}", error);
+        Ok(())
+    }
+}
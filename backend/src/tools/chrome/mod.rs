@@ -1,9 +1,13 @@
 // pub mod browser;
 pub mod chrome_ctrl;
+pub mod downloads;
+pub mod launcher;
 // pub mod chrome_state;
+pub mod pool;
 pub mod types;
 
 // pub use browser::{LocalChromiumBrowser, LocalBrowserConfig};
 // pub use chrome_ctrl::Chrome;
 // pub use chrome_state::{save_browser_state, load_browser_state, BrowserState, Tab, StorageState};
-// pub use types::{VisualViewport, InteractiveRegion};
\ No newline at end of file
+// pub use types::{VisualViewport, InteractiveRegion};
+pub use pool::{BrowserFactory, BrowserLease, BrowserPool, BrowserPoolOptions, BrowserPoolStats, ChromeFactory, Lease, PooledBrowser};
\ No newline at end of file
@@ -0,0 +1,501 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use tokio::time::Instant;
+
+use crate::config::BrowserSettings;
+use crate::tools::chrome::chrome_ctrl::{Chrome, ChromeConfig};
+
+/// 可以放进 BrowserPool 复用的浏览器控制器
+#[async_trait]
+pub trait PooledBrowser: Send + Sync + 'static {
+    /// 借出前的探测，失败的实例被销毁
+    async fn health_check(&self) -> Result<()>;
+    /* 归还时恢复到干净的状态：关闭多余的标签页，回到 about:blank；
+    keep_session 为 false 时同时清除 cookie 和 storage */
+    async fn reset(&self, keep_session: bool) -> Result<()>;
+    async fn close(&self) -> Result<()>;
+}
+
+/// 按配置创建新的浏览器实例
+#[async_trait]
+pub trait BrowserFactory<B>: Send + Sync {
+    async fn create(&self) -> Result<B>;
+}
+
+/// 池的大小和实例的最长使用时间，超过 max_age 的实例在归还或借出时销毁并补充新的
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrowserPoolOptions {
+    pub size: usize,
+    pub max_age: Duration,
+}
+
+impl BrowserPoolOptions {
+    pub fn from_settings(settings: &BrowserSettings) -> Self {
+        Self { size: settings.pool_size, max_age: Duration::from_secs(settings.pool_max_age_secs) }
+    }
+}
+
+/// 池的状态，供 metrics 接口使用
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct BrowserPoolStats {
+    /// 存活的实例数，包括借出的
+    pub size: u64,
+    pub in_use: u64,
+    pub created: u64,
+    pub destroyed: u64,
+    pub resets: u64,
+    /// 创建、探测或重置失败的次数
+    pub failures: u64,
+}
+
+struct Pooled<B> {
+    // 借出期间可以和代理共享，见 BrowserLease::share
+    browser: Arc<B>,
+    created: Instant,
+}
+
+struct PoolInner<B> {
+    factory: Arc<dyn BrowserFactory<B>>,
+    options: BrowserPoolOptions,
+    idle: Mutex<Vec<Pooled<B>>>,
+    permits: Arc<Semaphore>,
+    in_use: AtomicU64,
+    created: AtomicU64,
+    destroyed: AtomicU64,
+    resets: AtomicU64,
+    failures: AtomicU64,
+}
+
+/* 预热的浏览器池：最多 size 个实例，执行器按运行借出，借满时等待归还。
+归还的实例重置后放回池中，重置失败或超过 max_age 的实例销毁，并补充一个新的 */
+pub struct BrowserPool<B> {
+    inner: Arc<PoolInner<B>>,
+}
+
+impl<B> Clone for BrowserPool<B> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+impl<B: PooledBrowser> BrowserPool<B> {
+    pub fn new(factory: Arc<dyn BrowserFactory<B>>, options: BrowserPoolOptions) -> Self {
+        let size = options.size.max(1);
+        Self {
+            inner: Arc::new(PoolInner {
+                factory,
+                options: BrowserPoolOptions { size, ..options },
+                idle: Mutex::new(Vec::new()),
+                permits: Arc::new(Semaphore::new(size)),
+                in_use: AtomicU64::new(0),
+                created: AtomicU64::new(0),
+                destroyed: AtomicU64::new(0),
+                resets: AtomicU64::new(0),
+                failures: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// 把池填满，返回新建的实例数；启动后在后台调用
+    pub async fn warm_up(&self) -> Result<usize> {
+        let mut created = 0;
+        while self.inner.live() < self.inner.options.size as u64 {
+            let pooled = self.inner.create().await?;
+            self.inner.idle.lock().unwrap().push(pooled);
+            created += 1;
+        }
+        Ok(created)
+    }
+
+    /// 借出一个实例，池中没有空闲实例时新建，借满时等待其他运行归还
+    pub async fn acquire(&self) -> Result<BrowserLease<B>> {
+        let permit = self.inner.permits.clone().acquire_owned().await?;
+        let pooled = loop {
+            let Some(pooled) = self.inner.pop_idle() else {
+                break self.inner.create().await?;
+            };
+            if pooled.created.elapsed() >= self.inner.options.max_age {
+                self.inner.destroy(pooled).await;
+                continue;
+            }
+            match pooled.browser.health_check().await {
+                Ok(()) => break pooled,
+                Err(e) => {
+                    tracing::warn!("Pooled browser failed its health check: {:#}", e);
+                    self.inner.failures.fetch_add(1, Ordering::SeqCst);
+                    self.inner.destroy(pooled).await;
+                }
+            }
+        };
+        self.inner.in_use.fetch_add(1, Ordering::SeqCst);
+        Ok(BrowserLease { pooled: Some(pooled), permit: Some(permit), pool: self.inner.clone(), keep_session: false })
+    }
+
+    /* 就绪检查：探测空闲的实例，直到有一个通过；没有空闲实例时新建一个放进池中。
+    实例全部借出时说明它们借出时通过了探测、正在使用，不再打扰 */
+    pub async fn probe(&self) -> Result<()> {
+        let _permit = match self.inner.permits.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(TryAcquireError::NoPermits) => return Ok(()),
+            Err(TryAcquireError::Closed) => anyhow::bail!("The browser pool is closed"),
+        };
+        while let Some(pooled) = self.inner.pop_idle() {
+            match pooled.browser.health_check().await {
+                Ok(()) => {
+                    self.inner.idle.lock().unwrap().push(pooled);
+                    return Ok(());
+                }
+                Err(e) => {
+                    tracing::warn!("Pooled browser failed its health check: {:#}", e);
+                    self.inner.failures.fetch_add(1, Ordering::SeqCst);
+                    self.inner.destroy(pooled).await;
+                }
+            }
+        }
+        let pooled = self.inner.create().await?;
+        match pooled.browser.health_check().await {
+            Ok(()) => {
+                self.inner.idle.lock().unwrap().push(pooled);
+                Ok(())
+            }
+            Err(e) => {
+                self.inner.failures.fetch_add(1, Ordering::SeqCst);
+                self.inner.destroy(pooled).await;
+                Err(e.context("A new browser failed its health check"))
+            }
+        }
+    }
+
+    pub fn stats(&self) -> BrowserPoolStats {
+        let inner = &self.inner;
+        BrowserPoolStats {
+            size: inner.live(),
+            in_use: inner.in_use.load(Ordering::SeqCst),
+            created: inner.created.load(Ordering::SeqCst),
+            destroyed: inner.destroyed.load(Ordering::SeqCst),
+            resets: inner.resets.load(Ordering::SeqCst),
+            failures: inner.failures.load(Ordering::SeqCst),
+        }
+    }
+
+    /// 停止服务时关闭空闲的实例，借出的实例在归还后关闭
+    pub async fn close(&self) {
+        self.inner.permits.close();
+        let idle = std::mem::take(&mut *self.inner.idle.lock().unwrap());
+        for pooled in idle {
+            self.inner.destroy(pooled).await;
+        }
+    }
+}
+
+impl<B: PooledBrowser> PoolInner<B> {
+    fn live(&self) -> u64 {
+        self.created.load(Ordering::SeqCst) - self.destroyed.load(Ordering::SeqCst)
+    }
+
+    fn pop_idle(&self) -> Option<Pooled<B>> {
+        self.idle.lock().unwrap().pop()
+    }
+
+    async fn create(&self) -> Result<Pooled<B>> {
+        match self.factory.create().await {
+            Ok(browser) => {
+                self.created.fetch_add(1, Ordering::SeqCst);
+                Ok(Pooled { browser: Arc::new(browser), created: Instant::now() })
+            }
+            Err(e) => {
+                self.failures.fetch_add(1, Ordering::SeqCst);
+                Err(e.context("Failed to start a browser for the pool"))
+            }
+        }
+    }
+
+    async fn destroy(&self, pooled: Pooled<B>) {
+        if let Err(e) = pooled.browser.close().await {
+            tracing::warn!("Failed to close a pooled browser: {:#}", e);
+        }
+        self.destroyed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // 归还：重置后放回池中；过期、重置失败或池已关闭时销毁，池未关闭时补充一个新的实例
+    async fn give_back(&self, pooled: Pooled<B>, keep_session: bool) {
+        self.in_use.fetch_sub(1, Ordering::SeqCst);
+        let closed = self.permits.is_closed();
+        let expired = pooled.created.elapsed() >= self.options.max_age;
+        if !closed && !expired {
+            match pooled.browser.reset(keep_session).await {
+                Ok(()) => {
+                    self.resets.fetch_add(1, Ordering::SeqCst);
+                    self.idle.lock().unwrap().push(pooled);
+                    return;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to reset a pooled browser: {:#}", e);
+                    self.failures.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+        self.destroy(pooled).await;
+        if closed {
+            return;
+        }
+        match self.create().await {
+            Ok(replacement) => self.idle.lock().unwrap().push(replacement),
+            Err(e) => tracing::warn!("{:#}", e),
+        }
+    }
+}
+
+/* 借出的浏览器，离开作用域时自动归还；需要等归还完成时调用 release。
+归还之前池中的名额一直被占用 */
+pub struct BrowserLease<B: PooledBrowser> {
+    pooled: Option<Pooled<B>>,
+    permit: Option<OwnedSemaphorePermit>,
+    pool: Arc<PoolInner<B>>,
+    keep_session: bool,
+}
+
+impl<B: PooledBrowser> BrowserLease<B> {
+    /// 运行带有登录状态时调用，归还时保留 cookie 和 storage
+    pub fn keep_session(&mut self) {
+        self.keep_session = true;
+    }
+
+    /// 和代理共享借出的实例，例如交给 WebAgent；归还之前应先释放共享的引用
+    pub fn share(&self) -> Arc<B> {
+        self.pooled.as_ref().expect("lease is not released").browser.clone()
+    }
+
+    pub async fn release(mut self) {
+        if let Some(pooled) = self.pooled.take() {
+            self.pool.give_back(pooled, self.keep_session).await;
+        }
+        drop(self.permit.take());
+    }
+}
+
+/// 不区分浏览器类型的租约，执行器持有它并在运行结束时归还
+#[async_trait]
+pub trait Lease: Send + Sync {
+    async fn release(self: Box<Self>);
+}
+
+#[async_trait]
+impl<B: PooledBrowser> Lease for BrowserLease<B> {
+    async fn release(self: Box<Self>) {
+        BrowserLease::release(*self).await
+    }
+}
+
+impl<B: PooledBrowser> std::ops::Deref for BrowserLease<B> {
+    type Target = B;
+
+    fn deref(&self) -> &B {
+        &self.pooled.as_ref().expect("lease is not released").browser
+    }
+}
+
+impl<B: PooledBrowser> Drop for BrowserLease<B> {
+    fn drop(&mut self) {
+        let Some(pooled) = self.pooled.take() else {
+            return;
+        };
+        let pool = self.pool.clone();
+        let permit = self.permit.take();
+        let keep_session = self.keep_session;
+        // 没有运行时（进程退出时）直接丢弃，名额随之释放
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move {
+                pool.give_back(pooled, keep_session).await;
+                drop(permit);
+            });
+        } else {
+            pool.in_use.fetch_sub(1, Ordering::SeqCst);
+            pool.destroyed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[async_trait]
+impl PooledBrowser for Chrome {
+    async fn health_check(&self) -> Result<()> {
+        self.get_title().await.map(|_| ())
+    }
+
+    async fn reset(&self, keep_session: bool) -> Result<()> {
+        self.reset_state(keep_session).await
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.quit().await
+    }
+}
+
+/// 按 [browser] 配置连接 chromedriver 创建 Chrome
+pub struct ChromeFactory {
+    settings: BrowserSettings,
+}
+
+impl ChromeFactory {
+    pub fn new(settings: BrowserSettings) -> Self {
+        Self { settings }
+    }
+}
+
+#[async_trait]
+impl BrowserFactory<Chrome> for ChromeFactory {
+    async fn create(&self) -> Result<Chrome> {
+        // 每个浏览器下载到自己的子目录，同时运行的会话不会把对方的下载当作自己的
+        let mut config = ChromeConfig::from_settings(&self.settings);
+        config.downloads_dir = config.downloads_dir.map(|dir| dir.join(uuid::Uuid::new_v4().to_string()));
+        // 池中的浏览器借出前都会回到空白页
+        config.start_page = None;
+        Chrome::with_config(config).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::sync::atomic::AtomicBool;
+
+    // 记录重置调用的假浏览器，healthy 为 false 时探测失败
+    #[derive(Default)]
+    struct FakeBrowser {
+        id: u64,
+        healthy: Arc<AtomicBool>,
+        resets: Arc<Mutex<Vec<bool>>>,
+    }
+
+    #[async_trait]
+    impl PooledBrowser for FakeBrowser {
+        async fn health_check(&self) -> Result<()> {
+            match self.healthy.load(Ordering::SeqCst) {
+                true => Ok(()),
+                false => Err(anyhow!("The browser is not responding")),
+            }
+        }
+
+        async fn reset(&self, keep_session: bool) -> Result<()> {
+            self.resets.lock().unwrap().push(keep_session);
+            Ok(())
+        }
+
+        async fn close(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // 每个浏览器的健康标记和 reset 记录
+    type BrowserProbe = (Arc<AtomicBool>, Arc<Mutex<Vec<bool>>>);
+
+    #[derive(Default)]
+    struct FakeFactory {
+        next_id: AtomicU64,
+        browsers: Mutex<Vec<BrowserProbe>>,
+    }
+
+    #[async_trait]
+    impl BrowserFactory<FakeBrowser> for FakeFactory {
+        async fn create(&self) -> Result<FakeBrowser> {
+            let browser = FakeBrowser {
+                id: self.next_id.fetch_add(1, Ordering::SeqCst),
+                healthy: Arc::new(AtomicBool::new(true)),
+                ..FakeBrowser::default()
+            };
+            self.browsers.lock().unwrap().push((browser.healthy.clone(), browser.resets.clone()));
+            Ok(browser)
+        }
+    }
+
+    fn pool(factory: &Arc<FakeFactory>, size: usize, max_age: Duration) -> BrowserPool<FakeBrowser> {
+        BrowserPool::new(factory.clone(), BrowserPoolOptions { size, max_age })
+    }
+
+    #[tokio::test]
+    async fn test_leases_are_reset_and_reused() -> Result<()> {
+        let factory = Arc::new(FakeFactory::default());
+        let pool = pool(&factory, 1, Duration::from_secs(3600));
+        assert_eq!(pool.warm_up().await?, 1);
+
+        let lease = pool.acquire().await?;
+        assert_eq!(lease.share().id, 0);
+        assert_eq!(pool.stats().in_use, 1);
+        // 池已借满，下一个运行等待归还
+        assert!(tokio::time::timeout(Duration::from_millis(50), pool.acquire()).await.is_err());
+        lease.release().await;
+
+        let mut lease = pool.acquire().await?;
+        assert_eq!(lease.id, 0);
+        lease.keep_session();
+        drop(lease);
+        let lease = pool.acquire().await?;
+        assert_eq!(lease.id, 0);
+        lease.release().await;
+
+        let resets = factory.browsers.lock().unwrap()[0].1.lock().unwrap().clone();
+        assert_eq!(resets, vec![false, true, false]);
+        let stats = pool.stats();
+        assert_eq!((stats.size, stats.in_use, stats.created, stats.resets), (1, 0, 1, 3));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unhealthy_and_expired_browsers_are_replaced() -> Result<()> {
+        let factory = Arc::new(FakeFactory::default());
+        let pool = pool(&factory, 2, Duration::from_secs(3600));
+        pool.warm_up().await?;
+        for (healthy, _) in factory.browsers.lock().unwrap().iter() {
+            healthy.store(false, Ordering::SeqCst);
+        }
+        // 两个空闲实例都探测失败，借出的是新建的实例
+        let lease = pool.acquire().await?;
+        assert_eq!(lease.id, 2);
+        lease.release().await;
+        let stats = pool.stats();
+        assert_eq!((stats.size, stats.created, stats.destroyed, stats.failures), (1, 3, 2, 2));
+
+        // 超过最长使用时间的实例归还时销毁，并补充一个新的
+        let pool = self::pool(&factory, 1, Duration::ZERO);
+        let lease = pool.acquire().await?;
+        let id = lease.id;
+        lease.release().await;
+        let stats = pool.stats();
+        assert_eq!((stats.size, stats.created, stats.destroyed, stats.resets), (1, 2, 1, 0));
+        let resets = factory.browsers.lock().unwrap()[id as usize].1.lock().unwrap().clone();
+        assert!(resets.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_probe_checks_an_idle_browser() -> Result<()> {
+        let factory = Arc::new(FakeFactory::default());
+        let pool = pool(&factory, 1, Duration::from_secs(3600));
+        // 池是空的时新建一个实例留在池中
+        pool.probe().await?;
+        assert_eq!(pool.stats().size, 1);
+        pool.probe().await?;
+        assert_eq!(pool.stats().created, 1);
+
+        // 借满时不探测
+        let lease = pool.acquire().await?;
+        factory.browsers.lock().unwrap()[0].0.store(false, Ordering::SeqCst);
+        pool.probe().await?;
+        lease.release().await;
+
+        // 空闲的实例探测失败时销毁，换成新建的实例
+        pool.probe().await?;
+        let stats = pool.stats();
+        assert_eq!((stats.size, stats.created, stats.destroyed, stats.failures), (1, 2, 1, 1));
+        pool.close().await;
+        assert!(pool.probe().await.is_err());
+        Ok(())
+    }
+}
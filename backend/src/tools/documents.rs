@@ -2,7 +2,7 @@ use std::{collections::HashMap};
 
 use anyhow::Result;
 
-use crate::{clients::py_client::PyClient};
+use crate::{clients::py_client::PdfServiceClient};
 
 #[derive(Debug, Clone)]
 pub struct Document {
@@ -11,19 +11,19 @@ pub struct Document {
 }
 
 pub struct DocumentProcessor {
-    py_client: PyClient,
+    pdf_service: PdfServiceClient,
 }
 
 impl DocumentProcessor {
-    pub fn new(py_client: PyClient) -> Self {
-        Self { py_client }
+    pub fn new(pdf_service: PdfServiceClient) -> Self {
+        Self { pdf_service }
     }
 
     pub async fn process_document(
         &self,
         file_path: &str
     ) -> Result<()> {
-        let pages = self.py_client.load_pdf(file_path).await?;
+        let pages = self.pdf_service.load_pdf(file_path).await?;
         println!("pages: {:?}", pages);
 
         let mut documents = Vec::new();
@@ -38,7 +38,7 @@ impl DocumentProcessor {
         }
 
         // 分割文档
-        let chucks = self.split_document(&documents).await?;
+        let _chucks = self.split_document(&documents).await?;
 
         // 添加到向量数据库中
 
@@ -70,17 +70,17 @@ impl DocumentProcessor {
         }
     }
 
-    async fn get_split_points(&self, text: &str, split_points: &[&str]) -> Result<Vec<String>> {
+    async fn get_split_points(&self, _text: &str, _split_points: &[&str]) -> Result<Vec<String>> {
         
         unimplemented!();
     }
 
-    async fn split_by_points(&self, text: &str, split_points: &[&str]) -> Result<Vec<String>> {
+    async fn split_by_points(&self, text: &str, split_points: &[String]) -> Result<Vec<String>> {
         let mut chunks = Vec::new();
         let mut current_pos = 0;
 
-        for &point in split_points {
-            if let Some(pos) = text[current_pos..].find(point) {
+        for point in split_points {
+            if let Some(pos) = text[current_pos..].find(point.as_str()) {
                 let abs_pos = current_pos + pos;
 
                 if abs_pos > current_pos {
@@ -109,11 +109,12 @@ mod tests {
     use super::*;
 
     #[tokio::test]
+    #[ignore = "requires the PDF service on localhost:8001"]
     async fn test_py_client_load_pdf() {       
-        let py_client = PyClient::new("http://localhost:8001");
+        let pdf_service = PdfServiceClient::new("http://localhost:8001");
         let test_pdf_path = "/Users/xuenai/Downloads/djcftlqw.pdf";
         
-        let pages = py_client.load_pdf(test_pdf_path).await.unwrap(); // 如果出错，测试会 panic 并标记为 failed
+        let pages = pdf_service.load_pdf(test_pdf_path).await.unwrap(); // 如果出错，测试会 panic 并标记为 failed
         println!("✅ 成功加载 PDF，共 {} 页", pages.len());
         for (i, page) in pages.iter().enumerate() {
             println!("第 {} 页内容长度: {} 字符", i + 1, page.len());
@@ -2,4 +2,5 @@ pub mod chrome;
 pub mod utils;
 pub mod url_status_manager;
 pub mod tool_metadata;
-pub mod documents;
\ No newline at end of file
+pub mod documents;
+pub mod approval_guard;
\ No newline at end of file
@@ -2,6 +2,8 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use std::sync::RwLock;
 
+use crate::clients::ToolSpec;
+
 // 全局元数据注册表
 lazy_static::lazy_static! {
     static ref TOOL_METADATA_REGISTRY: RwLock<HashMap<String, ToolMetadata>> = 
@@ -86,6 +88,17 @@ pub fn load_tool(tooldef_json: &str) -> Result<ToolSchema, Box<dyn std::error::E
     })
 }
 
+// 交给模型客户端的函数定义，parameters 按 JSON Schema 序列化
+impl From<&ToolSchema> for ToolSpec {
+    fn from(tool: &ToolSchema) -> Self {
+        ToolSpec {
+            name: tool.name.clone(),
+            description: tool.description.clone(),
+            parameters: serde_json::to_value(&tool.parameters).unwrap_or_default(),
+        }
+    }
+}
+
 pub fn get_tool_metadata(tool_name: &str) -> Option<ToolMetadata> {
     TOOL_METADATA_REGISTRY
         .read()
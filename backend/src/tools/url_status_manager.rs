@@ -1,4 +1,7 @@
 use std::collections::HashMap;
+use std::path::Path;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
 use url::Url;
 use tldextract::{TldExtractor, TldOption};
 
@@ -44,7 +47,14 @@ impl UrlStatusManager {
         }
     }
 
+    /* 登记的网站可以是：
+    - 域名 wikipedia.org：匹配这个域名和它的所有子域名
+    - 主机名 en.wikipedia.org：只匹配这个主机
+    - 通配 *.wikipedia.org：匹配所有子域名
+    - URL 前缀 https://example.com/docs：匹配这个主机上以该路径开头的页面
+    http 和 https 视为相同，默认端口和末尾的 / 不影响匹配；登记时写了端口则端口必须相同 */
     fn is_url_match(&self, registered_url: &str, proposed_url: &str) -> bool {
+        let (wildcard, registered_url) = strip_wildcard(registered_url.trim());
         let reg_url = if registered_url.contains("://") {
             registered_url.to_string()
         } else {
@@ -73,6 +83,11 @@ impl UrlStatusManager {
             return false;
         }
 
+        // 登记时写了端口，比较时补上协议的默认端口（https 的 443 等同于不写）
+        if parsed_reg.port().is_some() && parsed_reg.port_or_known_default() != parsed_prop.port_or_known_default() {
+            return false;
+        }
+
         let host_reg = match parsed_reg.host_str() {
             Some(h) => h,
             None => return false,
@@ -82,6 +97,10 @@ impl UrlStatusManager {
             None => return false,
         };
 
+        if wildcard {
+            return host_prop.ends_with(&format!(".{}", host_reg)) && path_has_prefix(parsed_prop.path(), parsed_reg.path());
+        }
+
 
         fn extract_or_fallback(extractor: &TldExtractor, host: &str) -> (
             Option<String>, Option<String>, Option<String>,
@@ -116,33 +135,18 @@ impl UrlStatusManager {
             return false;
         }
 
-        let path_reg = parsed_reg.path();
-        let path_prop = parsed_prop.path();
-        
-        if !path_reg.is_empty() {
-            if !path_prop.starts_with(path_reg) {
-                return false;
-            }
-
-            if path_reg != "/" && !path_reg.ends_with('/') && path_prop.len() > path_reg.len() {
-                if !path_prop[path_reg.len()..].starts_with('/') {
-                    return false;
-                }
-            }
-        }
-
-        true
+        path_has_prefix(parsed_prop.path(), parsed_reg.path())
     }
 
     pub fn is_url_blocked(&self, url: &str) -> bool {
         self.url_block_list
             .as_ref()
-            .map_or(false, |list|list.iter().any(|site|self.is_url_match(site,url)))
+            .is_some_and(|list|list.iter().any(|site|self.is_url_match(site,url)))
     }
 
     pub fn is_url_rejected(&self, url: &str) -> bool {
 
-        self.url_statuses.as_ref().map_or(false, |statuses| {
+        self.url_statuses.as_ref().is_some_and(|statuses| {
             statuses
                 .iter()
                 .any(|(site, status)| self.is_url_match(site, url) && *status == UrlStatus::Rejected)
@@ -158,7 +162,7 @@ impl UrlStatusManager {
             return true;
         }
 
-        self.url_statuses.as_ref().map_or(false, |statuses| {
+        self.url_statuses.as_ref().is_some_and(|statuses| {
             statuses
                 .iter()
                 .any(|(site, status)| self.is_url_match(site, url) && *status == UrlStatus::Allowed)
@@ -188,4 +192,199 @@ impl UrlStatusManager {
     pub fn get_blocked_sites(&self) -> Option<&Vec<String>> {
         self.url_block_list.as_ref()
     }
-}
\ No newline at end of file
+}
+
+// "*.wikipedia.org" 或 "https://*.wikipedia.org/wiki" 去掉通配部分，返回是否有通配
+fn strip_wildcard(site: &str) -> (bool, String) {
+    match site.split_once("://") {
+        Some((scheme, rest)) => match rest.strip_prefix("*.") {
+            Some(rest) => (true, format!("{}://{}", scheme, rest)),
+            None => (false, site.to_string()),
+        },
+        None => match site.strip_prefix("*.") {
+            Some(rest) => (true, rest.to_string()),
+            None => (false, site.to_string()),
+        },
+    }
+}
+
+// 按路径段比较前缀，末尾的 / 不影响结果：/wiki 匹配 /wiki/Rust，不匹配 /wikis
+fn path_has_prefix(path: &str, prefix: &str) -> bool {
+    let path = path.trim_end_matches('/');
+    let prefix = prefix.trim_end_matches('/');
+    prefix.is_empty() || path == prefix || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/* 预先授权和屏蔽的网站，非交互运行无法回答审批时使用。
+可以从 JSON 文件读取：{"allowed_sites": [...], "blocked_sites": [...]} */
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SitePolicy {
+    #[serde(default)]
+    pub allowed_sites: Vec<String>,
+    #[serde(default)]
+    pub blocked_sites: Vec<String>,
+}
+
+impl SitePolicy {
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read sites file {}", path.display()))?;
+        serde_json::from_str(&content).map_err(|e| anyhow!("Sites file {} is invalid: {}", path.display(), e))
+    }
+
+    /// 在当前策略上合并优先级更高的策略（例如命令行给出的网站）：同一个网站在两边出现时以 other 为准
+    pub fn merge(mut self, other: SitePolicy) -> Self {
+        let normalize = |site: &String| site.trim().trim_end_matches('/').to_string();
+        for site in other.allowed_sites.iter().map(normalize) {
+            self.blocked_sites.retain(|s| normalize(s) != site);
+            if !self.allowed_sites.iter().any(|s| normalize(s) == site) {
+                self.allowed_sites.push(site);
+            }
+        }
+        for site in other.blocked_sites.iter().map(normalize) {
+            self.allowed_sites.retain(|s| normalize(s) != site);
+            if !self.blocked_sites.iter().any(|s| normalize(s) == site) {
+                self.blocked_sites.push(site);
+            }
+        }
+        self
+    }
+
+    /// 启动时校验，每一项都必须是带主机名的网站或 URL
+    pub fn validate(&self) -> Result<()> {
+        let invalid: Vec<&str> = self
+            .allowed_sites
+            .iter()
+            .chain(&self.blocked_sites)
+            .map(|site| site.as_str())
+            .filter(|site| !is_valid_site(site))
+            .collect();
+        if invalid.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid site pattern(s): {}", invalid.join(", ")))
+        }
+    }
+
+    /// 允许列表为空时不限制网站（只受屏蔽列表约束）
+    pub fn to_url_status_manager(&self) -> UrlStatusManager {
+        let url_statuses = (!self.allowed_sites.is_empty()).then(|| {
+            self.allowed_sites
+                .iter()
+                .map(|site| (site.clone(), UrlStatus::Allowed))
+                .collect()
+        });
+        let url_block_list = (!self.blocked_sites.is_empty()).then(|| self.blocked_sites.clone());
+        UrlStatusManager::new(url_statuses, url_block_list)
+    }
+
+    /// 展示用的策略表，每个网站一行
+    pub fn table(&self) -> String {
+        if self.allowed_sites.is_empty() && self.blocked_sites.is_empty() {
+            return "No site policy: every site is allowed".to_string();
+        }
+        self.allowed_sites
+            .iter()
+            .map(|site| format!("allow  {}", site))
+            .chain(self.blocked_sites.iter().map(|site| format!("block  {}", site)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+fn is_valid_site(site: &str) -> bool {
+    let site = site.trim();
+    if site.is_empty() || site.chars().any(char::is_whitespace) {
+        return false;
+    }
+    let (_, site) = strip_wildcard(site);
+    let url = if site.contains("://") { site } else { format!("http://{}", site) };
+    Url::parse(&url).is_ok_and(|url| url.host_str().is_some_and(|host| !host.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sites(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_site_policy_file_and_flags_merge() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sites.json");
+        std::fs::write(&path, r#"{"allowed_sites": ["wikipedia.org", "example.com/"], "blocked_sites": ["ads.example.com"]}"#)?;
+
+        let file = SitePolicy::from_json_file(&path)?;
+        let flags = SitePolicy {
+            allowed_sites: sites(&["ads.example.com", "bing.com"]),
+            blocked_sites: sites(&["example.com"]),
+        };
+        let policy = file.merge(flags);
+
+        // 命令行的设置覆盖文件中的同一个网站
+        assert_eq!(policy.allowed_sites, sites(&["wikipedia.org", "ads.example.com", "bing.com"]));
+        assert_eq!(policy.blocked_sites, sites(&["example.com"]));
+        assert_eq!(policy.table().lines().last(), Some("block  example.com"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_site_policy_builds_url_status_manager() {
+        let policy = SitePolicy { allowed_sites: sites(&["wikipedia.org"]), blocked_sites: sites(&["example.com"]) };
+        let manager = policy.to_url_status_manager();
+        assert!(manager.is_url_allowed("https://wikipedia.org/wiki/Rust"));
+        assert!(!manager.is_url_allowed("https://bing.com"));
+        assert!(manager.is_url_blocked("https://example.com/login"));
+
+        let manager = SitePolicy::default().to_url_status_manager();
+        assert!(manager.is_url_allowed("https://bing.com"));
+        assert!(manager.get_allowed_sites().is_none());
+    }
+
+    #[test]
+    fn test_site_policy_validation() {
+        let policy = SitePolicy { allowed_sites: sites(&["wikipedia.org", "https://example.com/docs"]), blocked_sites: Vec::new() };
+        assert!(policy.validate().is_ok());
+
+        let policy = SitePolicy { allowed_sites: sites(&["", "not a site"]), blocked_sites: sites(&["https://"]) };
+        let error = policy.validate().unwrap_err().to_string();
+        assert!(error.contains("not a site"));
+        assert!(error.contains("https://"));
+        assert!(SitePolicy::from_json_file("/nonexistent/sites.json").is_err());
+    }
+
+    #[test]
+    fn test_site_patterns_normalize_urls() {
+        let policy = SitePolicy {
+            allowed_sites: sites(&["*.wikipedia.org", "intranet.corp.example.com", "https://docs.rs/tokio/", "git.example.org:8443"]),
+            blocked_sites: sites(&["https://en.wikipedia.org/wiki/Special:Random"]),
+        };
+        assert!(policy.validate().is_ok());
+        let manager = policy.to_url_status_manager();
+
+        // 通配子域名，默认端口和协议不影响匹配
+        assert!(manager.is_url_allowed("https://en.wikipedia.org:443/wiki/Rust"));
+        assert!(manager.is_url_allowed("http://de.wikipedia.org/"));
+        assert!(!manager.is_url_allowed("https://wikipedia.org.evil.com/"));
+
+        // 主机名只匹配这个主机
+        assert!(manager.is_url_allowed("https://intranet.corp.example.com/wiki"));
+        assert!(!manager.is_url_allowed("https://mail.corp.example.com/"));
+
+        // URL 前缀按路径段匹配，末尾的 / 不影响
+        assert!(manager.is_url_allowed("http://docs.rs/tokio"));
+        assert!(manager.is_url_allowed("https://docs.rs/tokio/latest/tokio/"));
+        assert!(!manager.is_url_allowed("https://docs.rs/tokio-util/"));
+
+        // 登记时写了端口则端口必须相同
+        assert!(manager.is_url_allowed("https://git.example.org:8443/repo"));
+        assert!(!manager.is_url_allowed("https://git.example.org/repo"));
+
+        // 屏蔽优先于允许
+        assert!(manager.is_url_blocked("https://en.wikipedia.org/wiki/Special:Random/"));
+        assert!(!manager.is_url_allowed("https://en.wikipedia.org/wiki/Special:Random"));
+    }
+}
@@ -1,23 +1,30 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use anyhow::Result;
 use thirtyfour::prelude::*;
 
 #[derive(Debug)]
 pub struct AnimationUtils {
-    pub last_cursor_position: (f64, f64),
+    // 放在 Mutex 中，Chrome 的方法只需要 &self，可以在任务之间共享
+    last_cursor_position: Mutex<(f64, f64)>,
+}
+
+impl Default for AnimationUtils {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AnimationUtils {
     pub fn new() -> Self {
         Self {
-            last_cursor_position: (0.0, 0.0),
+            last_cursor_position: Mutex::new((0.0, 0.0)),
         }
     }
 
     /// 获取上次光标位置
     pub fn last_position(&self) -> (f64, f64) {
-        self.last_cursor_position
+        *self.last_cursor_position.lock().unwrap()
     }
 
     /// 高亮元素 + 创建自定义光标
@@ -53,8 +60,9 @@ impl AnimationUtils {
     }
 
     /// 从 (start_x, start_y) 平滑移动到 (end_x, end_y)
+    #[allow(clippy::too_many_arguments)]
     pub async fn gradual_cursor_animation(
-        &mut self,
+        &self,
         tab: &Arc<WebDriver>,
         start_x: f64,
         start_y: f64,
@@ -96,7 +104,7 @@ impl AnimationUtils {
             end_x, end_y
         );
         tab.as_ref().execute(&js_code, vec![]).await?;
-        self.last_cursor_position = (end_x, end_y);
+        *self.last_cursor_position.lock().unwrap() = (end_x, end_y);
         Ok(())
     }
 
@@ -121,7 +129,7 @@ impl AnimationUtils {
     }
 
     /// 清理所有动画效果
-    pub async fn cleanup_animations(&mut self, tab: &Arc<WebDriver>) -> Result<()> {
+    pub async fn cleanup_animations(&self, tab: &Arc<WebDriver>) -> Result<()> {
         let js_code = r#"
             const cursor = document.getElementById('red-cursor');
             if (cursor) {
@@ -134,7 +142,7 @@ impl AnimationUtils {
             });
             "#;
         tab.as_ref().execute(js_code, vec![]).await?;
-        self.last_cursor_position = (0.0, 0.0);
+        *self.last_cursor_position.lock().unwrap() = (0.0, 0.0);
         Ok(())
     }
 
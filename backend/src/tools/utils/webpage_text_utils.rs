@@ -14,16 +14,24 @@ use tempfile::NamedTempFile;
 use thirtyfour::prelude::*;
 use serde_json::Value;
 use tokio::time::Duration;
+use crate::clients::py_client::{PyClient, ReadableText};
 use crate::tools::utils::markitdown_bridge::convert_html_to_markdown_with_markitdown;
 
 #[derive(Debug,Clone)]
 pub struct WebpageTextUtils {
     driver: Arc<WebDriver>,
+    // markitdown 失败时用 Python 工作进程的 readability 提取正文
+    py_client: Option<PyClient>,
 }
 
 impl WebpageTextUtils {
     pub fn new(driver: Arc<WebDriver>) -> Self {
-        Self { driver }
+        Self { driver, py_client: None }
+    }
+
+    pub fn with_py_client(mut self, py_client: PyClient) -> Self {
+        self.py_client = Some(py_client);
+        self
     }
 
     pub async fn get_all_webpage_text(&self, n_lines: Option<usize>) -> Result<String> {
@@ -84,20 +92,26 @@ impl WebpageTextUtils {
             .await?;
 
         if self.is_pdf_page().await? {
-            return self.extract_pdf_content().await;
+            let content = self.extract_pdf_content().await?;
+            return limit_tokens(&content, max_tokens.max(0) as usize);
         }
 
         let html = self.get_clean_html().await?;
 
-        let markdown = convert_html_to_markdown_with_markitdown(&html)
-        .await
-        .map_err(|e| anyhow!("markitdown 转换失败: {}", e))?;
+        let markdown = match (convert_html_to_markdown_with_markitdown(&html).await, &self.py_client) {
+            (Ok(markdown), _) => markdown,
+            (Err(e), Some(py_client)) => {
+                tracing::warn!("markitdown 转换失败，改用 readability: {}", e);
+                let page = py_client
+                    .readability_extract(&html)
+                    .await
+                    .map_err(|fallback| anyhow!("markitdown 转换失败: {}; readability 也失败: {}", e, fallback))?;
+                readable_to_markdown(&page)
+            }
+            (Err(e), None) => return Err(anyhow!("markitdown 转换失败: {}", e)),
+        };
 
-        if max_tokens > 0 {
-            self.limit_token(&markdown, max_tokens as usize)
-        } else {
-            Ok(markdown)
-        }
+        limit_tokens(&markdown, max_tokens.max(0) as usize)
     }
 
     async fn get_clean_html(&self) -> Result<String> {
@@ -169,32 +183,6 @@ impl WebpageTextUtils {
         }
     }
 
-    // 限制tokn数量
-    fn limit_token(&self, content: &str, max_tokens: usize) -> Result<String>{
-        if content.is_empty() {
-            return Ok(String::new())
-        }
-        // 根据模型确定编码方案
-        let model = "gpt-4-0314";
-        let tokenizer_type = get_tokenizer(model).unwrap();
-
-        // Tokenizer 枚举转为真正的 CoreBPE 实例
-        let bpe = Self::tokenizer_to_core_bpe(tokenizer_type)?;
-
-        let tokens = bpe.encode_with_special_tokens(content);
-        let limited_tokens = if tokens.len() > max_tokens {
-            tokens.into_iter().take(max_tokens).collect::<Vec<usize>>()
-        } else {
-            tokens
-        };
-
-        // 步骤5：解码 Token 为文本（使用 CoreBPE 源码中的 decode 方法，自动验证 UTF-8）
-        let limited_content = bpe
-            .decode(limited_tokens)
-            .map_err(|e| anyhow!("Token解码失败：{}", e))?;
-
-        Ok(limited_content)
-    }
 
     // 从pdf 提取文本（高级实现，更好的错误处理）
     async fn extract_pdf_content(&self) -> Result<String> {
@@ -270,4 +258,68 @@ impl WebpageTextUtils {
         }
     }
 
-}
\ No newline at end of file
+}
+
+// readability 只给出标题和纯文本，标题作为一级标题
+fn readable_to_markdown(page: &ReadableText) -> String {
+    let title = page.title.trim();
+    let text = page.text.trim();
+    if title.is_empty() {
+        text.to_string()
+    } else {
+        format!("# {}\n\n{}", title, text)
+    }
+}
+
+/* 按 cl100k 编码截断到最多 max_tokens 个 token，max_tokens 为 0 时不限制。
+answer_question 等把页面正文放进提示词时用它控制长度 */
+pub fn limit_tokens(content: &str, max_tokens: usize) -> Result<String> {
+    if content.is_empty() || max_tokens == 0 {
+        return Ok(content.to_string())
+    }
+    // 根据模型确定编码方案
+    let model = "gpt-4-0314";
+    let tokenizer_type = get_tokenizer(model).ok_or_else(|| anyhow!("找不到模型 {} 的分词方案", model))?;
+
+    // Tokenizer 枚举转为真正的 CoreBPE 实例
+    let bpe = WebpageTextUtils::tokenizer_to_core_bpe(tokenizer_type)?;
+
+    let tokens = bpe.encode_with_special_tokens(content);
+    if tokens.len() <= max_tokens {
+        return Ok(content.to_string())
+    }
+
+    // 解码 Token 为文本（使用 CoreBPE 源码中的 decode 方法，自动验证 UTF-8）
+    bpe.decode(tokens.into_iter().take(max_tokens).collect())
+        .map_err(|e| anyhow!("Token解码失败：{}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_tokens(content: &str) -> usize {
+        let bpe = WebpageTextUtils::tokenizer_to_core_bpe(Tokenizer::Cl100kBase).unwrap();
+        bpe.encode_with_special_tokens(content).len()
+    }
+
+    #[test]
+    fn test_limit_tokens_truncates_long_pages() -> Result<()> {
+        // 合成的长页面：标题加上几千行表格
+        let mut page = String::from("# Quarterly prices\n\n| Item | Price |\n| --- | --- |\n");
+        for i in 0..3000 {
+            page.push_str(&format!("| Item {} | {}.99 USD |\n", i, i % 97));
+        }
+        assert!(count_tokens(&page) > 10_000);
+
+        let limited = limit_tokens(&page, 500)?;
+        assert!(count_tokens(&limited) <= 500);
+        assert!(page.starts_with(&limited));
+        assert!(limited.starts_with("# Quarterly prices"));
+
+        // 不超过上限或不限制时原样返回
+        assert_eq!(limit_tokens("# Short page", 500)?, "# Short page");
+        assert_eq!(limit_tokens(&page, 0)?, page);
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+//! 以子进程运行 server 二进制的一次性子命令，模型接口为本地的 mock，检查退出码和标准输出的格式
+use std::path::Path;
+use std::process::Output;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::Json;
+use serde_json::{json, Value};
+use tokio::process::Command;
+
+use mini_magentic_backend::orchestrator::plan::Plan;
+
+const TASK: &str = "Find the menu of Cafe Roma";
+
+// mock 模型接口的一个回答
+#[derive(Debug, Clone)]
+enum Reply {
+    /// 字符串按原文回答，其余按 JSON
+    Content(Value),
+    /// 返回 500
+    Fail,
+    /// 一直不回答，用来在运行中途发送信号
+    Hang,
+}
+
+// 按顺序给出回答的 chat/completions 接口，最后一个回答一直重复，流式请求按 SSE 返回
+async fn mock_llm(replies: Vec<Reply>) -> String {
+    let replies = Arc::new(Mutex::new(replies));
+    let app = axum::Router::new().route(
+        "/chat/completions",
+        post(move |Json(body): Json<Value>| {
+            let reply = {
+                let mut replies = replies.lock().unwrap();
+                if replies.len() > 1 { replies.remove(0) } else { replies[0].clone() }
+            };
+            async move {
+                let reply = match reply {
+                    Reply::Content(reply) => reply,
+                    Reply::Fail => return (StatusCode::INTERNAL_SERVER_ERROR, "the model is unavailable").into_response(),
+                    Reply::Hang => std::future::pending().await,
+                };
+                let content = reply.as_str().map(str::to_string).unwrap_or_else(|| reply.to_string());
+                if body["stream"] == true {
+                    let sse = format!(
+                        "data: {}\n\ndata: {}\n\ndata: [DONE]\n\n",
+                        json!({ "choices": [{ "index": 0, "delta": { "content": content } }] }),
+                        json!({ "choices": [{ "index": 0, "delta": {}, "finish_reason": "stop" }] }),
+                    );
+                    return ([(header::CONTENT_TYPE, "text/event-stream")], sse).into_response();
+                }
+                Json(json!({
+                    "choices": [{ "message": { "role": "assistant", "content": content }, "finish_reason": "stop" }],
+                    "usage": { "prompt_tokens": 3, "completion_tokens": 1 }
+                }))
+                .into_response()
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    base_url
+}
+
+// 在 dir 中运行 server，HOME 也指向 dir，不读取本机的配置文件
+async fn server(dir: &Path, base_url: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_server"))
+        .args(args)
+        .current_dir(dir)
+        .env_clear()
+        .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+        .env("HOME", dir)
+        .env("DASHSCOPE_BASE_URL", base_url)
+        .env("DASHSCOPE_API_KEY", "sk-test")
+        .output()
+        .await
+        .unwrap()
+}
+
+fn plan_reply() -> Value {
+    json!({
+        "task": TASK,
+        "steps": [{ "title": "Open the menu", "details": "Open the menu page of Cafe Roma", "agent_name": "web_surfer" }],
+        "needs_plan": true,
+        "response": "",
+        "plan_summary": "Open the menu page",
+    })
+}
+
+fn ledger_reply(done: bool) -> Value {
+    json!({
+        "is_current_step_complete": { "reason": "scripted", "answer": done },
+        "need_to_replan": { "reason": "scripted", "answer": false },
+        "instruction_or_question": { "answer": "Open the menu page of Cafe Roma", "agent_name": "web_surfer" },
+        "progress_summary": "Opened the menu page",
+    })
+}
+
+// 演练中 web_surfer 预测的动作
+fn prediction_reply() -> Value {
+    json!({ "actions": [{ "tool": "visit_url", "url": "https://roma.example.org/menu" }], "summary": "The menu page would be open" })
+}
+
+fn stdout_lines(output: &Output) -> Vec<Value> {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("not a JSON line ({}): {}", e, line)))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_run_json_output_is_ndjson() {
+    let base_url = mock_llm(vec![
+        Reply::Content(plan_reply()),
+        Reply::Content(ledger_reply(false)),
+        Reply::Content(prediction_reply()),
+        Reply::Content(ledger_reply(true)),
+        Reply::Content(json!("The menu has pizza and pasta.")),
+    ])
+    .await;
+    let dir = tempfile::tempdir().unwrap();
+    let output = server(dir.path(), &base_url, &["run", "--dry-run", "--output", "json", TASK]).await;
+
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    // 每行一个带 type 的对象，最后一行是运行结果
+    let lines = stdout_lines(&output);
+    assert!(lines.iter().all(|line| line["type"].is_string()), "{:?}", lines);
+    assert!(lines.iter().any(|line| line["type"] == "plan_ready"), "{:?}", lines);
+    let outcome = lines.last().unwrap();
+    assert_eq!(outcome["type"], "run_outcome");
+    assert!(outcome["final_answer"].as_str().unwrap().contains("The menu has pizza and pasta."), "{}", outcome);
+    assert!(outcome["metrics"].is_object());
+    assert!(outcome["artifacts"].is_array());
+}
+
+#[tokio::test]
+async fn test_run_failures_exit_non_zero() {
+    let base_url = mock_llm(vec![Reply::Fail]).await;
+    let dir = tempfile::tempdir().unwrap();
+    let output = server(dir.path(), &base_url, &["run", "--dry-run", "--output", "json", TASK]).await;
+
+    assert_eq!(output.status.code(), Some(1));
+    let lines = stdout_lines(&output);
+    let error = lines.last().unwrap();
+    assert_eq!(error["type"], "error");
+    assert!(!error["message"].as_str().unwrap().is_empty());
+
+    // 配置错误在调用模型之前就输出
+    let output = server(dir.path(), &base_url, &["run", "--approval-policy", "never", "--output", "json", TASK]).await;
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout_lines(&output).last().unwrap()["type"], "error");
+
+    // 参数错误
+    let output = server(dir.path(), &base_url, &["run"]).await;
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[tokio::test]
+async fn test_plan_writes_the_plan_file() {
+    let base_url = mock_llm(vec![Reply::Content(plan_reply())]).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("plans").join("roma.json");
+    let output = server(dir.path(), &base_url, &["plan", "-o", path.to_str().unwrap(), TASK]).await;
+
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Open the menu"), "{}", stdout);
+    assert!(stdout.contains(&format!("Wrote {}", path.display())), "{}", stdout);
+    // 写出的文件可以交给 run --plan-file
+    let plan = Plan::from_json_file(&path).unwrap();
+    assert_eq!(plan.task.as_deref(), Some(TASK));
+    assert_eq!(plan.steps[0].agent_name, "web_surfer");
+}
+
+// 在 dir 中启动 server，标准输出出现含 ready 的行（步骤已经开始执行）时发送 signals 次 SIGINT
+#[cfg(unix)]
+async fn interrupt_server(dir: &Path, base_url: &str, args: &[&str], ready: &str, signals: usize) -> (Option<i32>, String) {
+    use std::process::Stdio;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_server"))
+        .args(args)
+        .current_dir(dir)
+        .env_clear()
+        .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+        .env("HOME", dir)
+        .env("DASHSCOPE_BASE_URL", base_url)
+        .env("DASHSCOPE_API_KEY", "sk-test")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut seen = String::new();
+    while !seen.lines().any(|line| line.contains(ready)) {
+        assert!(stdout.read_line(&mut seen).await.unwrap() > 0, "the run ended early: {}", seen);
+    }
+    let pid = child.id().unwrap().to_string();
+    for _ in 0..signals {
+        let status = std::process::Command::new("kill").args(["-INT", &pid]).status().unwrap();
+        assert!(status.success());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    let status = tokio::time::timeout(Duration::from_secs(20), child.wait()).await.expect("the process exits").unwrap();
+    stdout.read_to_string(&mut seen).await.unwrap();
+    (status.code(), seen)
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_ctrl_c_cancels_the_run_and_a_second_one_exits() {
+    let dir = tempfile::tempdir().unwrap();
+    let args = ["run", "--dry-run", TASK];
+    let started = "step 1 -> web_surfer";
+
+    // 一次 Ctrl+C：web_surfer 的调用被取消，最终答案照常请求模型，输出部分进度的总结后正常退出
+    let base_url = mock_llm(vec![
+        Reply::Content(plan_reply()),
+        Reply::Content(ledger_reply(false)),
+        Reply::Hang,
+        Reply::Content(json!("Stopped before the menu was opened.")),
+    ])
+    .await;
+    let (code, out) = interrupt_server(dir.path(), &base_url, &args, started, 1).await;
+    assert_eq!(code, Some(0), "{}", out);
+    assert!(out.contains("cancelled at step 1: Stopped at the user's request"), "{}", out);
+    assert!(out.contains("Stopped before the menu was opened."), "{}", out);
+
+    // 总结也迟迟不返回时，3 秒内再按一次立即以 130 退出
+    let base_url = mock_llm(vec![Reply::Content(plan_reply()), Reply::Content(ledger_reply(false)), Reply::Hang]).await;
+    let (code, out) = interrupt_server(dir.path(), &base_url, &args, started, 2).await;
+    assert_eq!(code, Some(130), "{}", out);
+    assert!(out.contains("cancelled at step 1"), "{}", out);
+}
@@ -0,0 +1,23 @@
+#!/usr/bin/env python3
+# 测试用的 chromedriver 替身：按 --port= 监听，只响应 GET /status
+import sys
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+port = int(next(arg.split("=", 1)[1] for arg in sys.argv[1:] if arg.startswith("--port=")))
+
+
+class Handler(BaseHTTPRequestHandler):
+    def do_GET(self):
+        found = self.path == "/status"
+        body = b'{"value": {"ready": true, "message": "ready"}}' if found else b"{}"
+        self.send_response(200 if found else 404)
+        self.send_header("Content-Type", "application/json")
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+
+    def log_message(self, *args):
+        pass
+
+
+HTTPServer(("127.0.0.1", port), Handler).serve_forever()
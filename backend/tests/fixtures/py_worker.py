@@ -0,0 +1,112 @@
+"""PyClient 的参考工作进程：从 stdin 逐行读取 JSON-RPC 2.0 请求，响应逐行写到 stdout。
+
+每个请求在单独的线程中处理，慢的调用不会挡住后面的调用。只依赖标准库。
+"""
+
+import json
+import os
+import sys
+import threading
+import time
+from html.parser import HTMLParser
+
+_write_lock = threading.Lock()
+
+
+def _send(message):
+    line = json.dumps(message, ensure_ascii=False)
+    with _write_lock:
+        sys.stdout.write(line + "\n")
+        sys.stdout.flush()
+
+
+class _ReadableParser(HTMLParser):
+    """取 <title> 和正文文本，跳过脚本、样式等不可见内容"""
+
+    SKIPPED = {"script", "style", "noscript", "template", "head"}
+    BLOCKS = {"p", "div", "br", "li", "h1", "h2", "h3", "h4", "h5", "h6", "tr", "section", "article"}
+
+    def __init__(self):
+        super().__init__()
+        self.title = ""
+        self.parts = []
+        self._skip_depth = 0
+        self._in_title = False
+
+    def handle_starttag(self, tag, attrs):
+        if tag == "title":
+            self._in_title = True
+        elif tag in self.SKIPPED:
+            self._skip_depth += 1
+        elif tag in self.BLOCKS:
+            self.parts.append("\n")
+
+    def handle_endtag(self, tag):
+        if tag == "title":
+            self._in_title = False
+        elif tag in self.SKIPPED and self._skip_depth > 0:
+            self._skip_depth -= 1
+
+    def handle_data(self, data):
+        if self._in_title:
+            self.title += data
+        elif self._skip_depth == 0:
+            self.parts.append(data)
+
+
+def readability_extract(params):
+    parser = _ReadableParser()
+    parser.feed(params["html"])
+    lines = [" ".join(line.split()) for line in "".join(parser.parts).splitlines()]
+    return {"title": parser.title.strip(), "text": "\n".join(line for line in lines if line)}
+
+
+def sleep(params):
+    time.sleep(float(params.get("seconds", 0)))
+    return params.get("reply")
+
+
+def crash(_params):
+    os._exit(1)
+
+
+METHODS = {
+    "echo": lambda params: params,
+    "sleep": sleep,
+    "crash": crash,
+    "readability_extract": readability_extract,
+}
+
+
+def _handle(request):
+    request_id = request.get("id")
+    method = METHODS.get(request.get("method"))
+    if method is None:
+        _send({"jsonrpc": "2.0", "id": request_id,
+               "error": {"code": -32601, "message": "Method not found: %s" % request.get("method")}})
+        return
+    try:
+        result = method(request.get("params"))
+    except (KeyError, TypeError, ValueError) as e:
+        _send({"jsonrpc": "2.0", "id": request_id, "error": {"code": -32602, "message": str(e)}})
+        return
+    except Exception as e:  # noqa: BLE001 - 任何异常都要作为错误返回，而不是让工作进程退出
+        _send({"jsonrpc": "2.0", "id": request_id, "error": {"code": -32603, "message": str(e)}})
+        return
+    _send({"jsonrpc": "2.0", "id": request_id, "result": result})
+
+
+def main():
+    for line in sys.stdin:
+        if not line.strip():
+            continue
+        try:
+            request = json.loads(line)
+        except ValueError as e:
+            _send({"jsonrpc": "2.0", "id": None, "error": {"code": -32700, "message": "Parse error: %s" % e}})
+            continue
+        threading.Thread(target=_handle, args=(request,), daemon=True).start()
+
+
+if __name__ == "__main__":
+    main()